@@ -0,0 +1,63 @@
+// Compares the boxed AST with `ast::arena` on a large expression.
+// Run with `cargo bench -p ast --bench arena`.
+use ast::arena::{Arena, NodeKind};
+use ast::ast::{Expr, ExprKind, Span};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+fn tree(depth: u32, i: &mut i32) -> Expr {
+    let kind = if depth == 0 {
+        *i += 1;
+        ExprKind::I32Literal(*i)
+    } else {
+        let x = tree(depth - 1, i);
+        let y = tree(depth - 1, i);
+        ExprKind::Add(Box::new(x), Box::new(y))
+    };
+    Expr::new(kind, Span::default())
+}
+
+fn count(x: &Expr) -> usize {
+    match &x.kind {
+        ExprKind::Add(x, y) => 1 + count(x) + count(y),
+        _ => 1,
+    }
+}
+
+fn bench<T>(name: &str, mut f: impl FnMut() -> T) -> Duration {
+    let iters = 20;
+    let start = Instant::now();
+    for _ in 0..iters {
+        black_box(f());
+    }
+    let time = start.elapsed() / iters;
+    println!("{:<24}{:>12.3?}", name, time);
+    time
+}
+
+fn main() {
+    let expr = tree(18, &mut 0);
+    let mut arena = Arena::new();
+    let root = arena.alloc(expr.clone());
+    assert_eq!(count(&expr), arena.len());
+    println!("{} nodes", arena.len());
+
+    bench("boxed clone", || expr.clone());
+    bench("arena clone", || arena.clone());
+
+    let expr2 = expr.clone();
+    let arena2 = arena.clone();
+    bench("boxed eq", || expr == expr2);
+    bench("arena eq", || arena == arena2);
+
+    bench("boxed traverse", || count(&expr));
+    bench("arena traverse", || {
+        arena
+            .iter()
+            .filter(|(_, x)| matches!(x.kind, NodeKind::Binary(..)))
+            .count()
+    });
+
+    bench("alloc", || Arena::new().alloc(expr.clone()));
+    bench("to_expr", || arena.to_expr(root));
+}
@@ -0,0 +1,411 @@
+use crate::ast::{AssignOp, Expr, ExprKind, Ident, Pattern, Resolution, Span, Type};
+
+// Flat representation of expression trees: every node lives in one `Vec` and refers to its
+// children by index, so a big body is a single allocation and can be scanned linearly.
+// Children are always allocated before their parent.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(u32);
+
+impl ExprId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnOp {
+    Not,
+    Plus,
+    Minus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    Range,
+    RangeInclusive,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeKind {
+    StructLiteral(Ident, Vec<(Ident, ExprId)>),
+    I32Literal(i32),
+    I64Literal(i64),
+    F32Literal(f32),
+    F64Literal(f64),
+    StringLiteral(String),
+    ArrayLiteral(Type, ExprId),
+    BoolLiteral(bool),
+    CharLiteral(char),
+    Var(Ident),
+    Resolved(Ident, Resolution),
+    Unary(UnOp, ExprId),
+    Member(ExprId, Ident),
+    Cast(ExprId, Type),
+    Index(ExprId, ExprId),
+    Call(ExprId, Vec<ExprId>),
+    Binary(BinOp, ExprId, ExprId),
+    Block(Vec<ExprId>, Option<ExprId>),
+    Let(Ident, Span, Option<Type>, ExprId),
+    // `if`, then any `else if` branches.
+    If(Vec<(ExprId, ExprId)>, Option<ExprId>),
+    While(ExprId, ExprId),
+    Return(Option<ExprId>),
+    Set(ExprId, ExprId),
+    CompoundSet(AssignOp, ExprId, ExprId),
+    For(ExprId, ExprId, ExprId, ExprId),
+    Lambda(Vec<Ident>, Vec<(Ident, Type)>, Type, ExprId),
+    Match(ExprId, Vec<(Pattern, ExprId)>),
+    Break,
+    Continue,
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+fn unary(x: ExprKind) -> Result<(UnOp, Expr), ExprKind> {
+    match x {
+        ExprKind::Not(x) => Ok((UnOp::Not, *x)),
+        ExprKind::Plus(x) => Ok((UnOp::Plus, *x)),
+        ExprKind::Minus(x) => Ok((UnOp::Minus, *x)),
+        x => Err(x),
+    }
+}
+
+fn binary(x: ExprKind) -> Result<(BinOp, Expr, Expr), ExprKind> {
+    let (op, x, y) = match x {
+        ExprKind::Add(x, y) => (BinOp::Add, x, y),
+        ExprKind::Sub(x, y) => (BinOp::Sub, x, y),
+        ExprKind::Mul(x, y) => (BinOp::Mul, x, y),
+        ExprKind::Div(x, y) => (BinOp::Div, x, y),
+        ExprKind::Mod(x, y) => (BinOp::Mod, x, y),
+        ExprKind::And(x, y) => (BinOp::And, x, y),
+        ExprKind::Or(x, y) => (BinOp::Or, x, y),
+        ExprKind::BitAnd(x, y) => (BinOp::BitAnd, x, y),
+        ExprKind::BitOr(x, y) => (BinOp::BitOr, x, y),
+        ExprKind::BitXor(x, y) => (BinOp::BitXor, x, y),
+        ExprKind::Pow(x, y) => (BinOp::Pow, x, y),
+        ExprKind::Eq(x, y) => (BinOp::Eq, x, y),
+        ExprKind::Ne(x, y) => (BinOp::Ne, x, y),
+        ExprKind::Lt(x, y) => (BinOp::Lt, x, y),
+        ExprKind::Lte(x, y) => (BinOp::Lte, x, y),
+        ExprKind::Gt(x, y) => (BinOp::Gt, x, y),
+        ExprKind::Gte(x, y) => (BinOp::Gte, x, y),
+        ExprKind::Range(x, y) => (BinOp::Range, x, y),
+        ExprKind::RangeInclusive(x, y) => (BinOp::RangeInclusive, x, y),
+        x => return Err(x),
+    };
+    Ok((op, *x, *y))
+}
+
+impl UnOp {
+    fn build(self, x: Box<Expr>) -> ExprKind {
+        match self {
+            UnOp::Not => ExprKind::Not(x),
+            UnOp::Plus => ExprKind::Plus(x),
+            UnOp::Minus => ExprKind::Minus(x),
+        }
+    }
+}
+
+impl BinOp {
+    fn build(self, x: Box<Expr>, y: Box<Expr>) -> ExprKind {
+        let f = match self {
+            BinOp::Add => ExprKind::Add,
+            BinOp::Sub => ExprKind::Sub,
+            BinOp::Mul => ExprKind::Mul,
+            BinOp::Div => ExprKind::Div,
+            BinOp::Mod => ExprKind::Mod,
+            BinOp::And => ExprKind::And,
+            BinOp::Or => ExprKind::Or,
+            BinOp::BitAnd => ExprKind::BitAnd,
+            BinOp::BitOr => ExprKind::BitOr,
+            BinOp::BitXor => ExprKind::BitXor,
+            BinOp::Pow => ExprKind::Pow,
+            BinOp::Eq => ExprKind::Eq,
+            BinOp::Ne => ExprKind::Ne,
+            BinOp::Lt => ExprKind::Lt,
+            BinOp::Lte => ExprKind::Lte,
+            BinOp::Gt => ExprKind::Gt,
+            BinOp::Gte => ExprKind::Gte,
+            BinOp::Range => ExprKind::Range,
+            BinOp::RangeInclusive => ExprKind::RangeInclusive,
+        };
+        f(x, y)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Arena {
+    nodes: Vec<Node>,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn get(&self, id: ExprId) -> &Node {
+        &self.nodes[id.index()]
+    }
+
+    pub fn get_mut(&mut self, id: ExprId) -> &mut Node {
+        &mut self.nodes[id.index()]
+    }
+
+    // All nodes, children before parents.
+    pub fn iter(&self) -> impl Iterator<Item = (ExprId, &Node)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (ExprId(i as u32), x))
+    }
+
+    pub fn push(&mut self, node: Node) -> ExprId {
+        let id = ExprId(self.nodes.len() as u32);
+        self.nodes.push(node);
+        id
+    }
+
+    // Moves a boxed tree into the arena.
+    pub fn alloc(&mut self, x: Expr) -> ExprId {
+        let span = x.span;
+        let kind = match unary(x.kind) {
+            Ok((op, x)) => NodeKind::Unary(op, self.alloc(x)),
+            Err(kind) => match binary(kind) {
+                Ok((op, x, y)) => {
+                    let x = self.alloc(x);
+                    NodeKind::Binary(op, x, self.alloc(y))
+                }
+                Err(kind) => self.alloc_kind(kind),
+            },
+        };
+        self.push(Node { kind, span })
+    }
+
+    fn alloc_all(&mut self, xs: Vec<Expr>) -> Vec<ExprId> {
+        xs.into_iter().map(|x| self.alloc(x)).collect()
+    }
+
+    fn alloc_kind(&mut self, x: ExprKind) -> NodeKind {
+        match x {
+            ExprKind::StructLiteral(name, fields) => NodeKind::StructLiteral(
+                name,
+                fields
+                    .into_iter()
+                    .map(|(name, x)| (name, self.alloc(x)))
+                    .collect(),
+            ),
+            ExprKind::I32Literal(x) => NodeKind::I32Literal(x),
+            ExprKind::I64Literal(x) => NodeKind::I64Literal(x),
+            ExprKind::F32Literal(x) => NodeKind::F32Literal(x),
+            ExprKind::F64Literal(x) => NodeKind::F64Literal(x),
+            ExprKind::StringLiteral(x) => NodeKind::StringLiteral(x),
+            ExprKind::ArrayLiteral(t, len) => NodeKind::ArrayLiteral(t, self.alloc(*len)),
+            ExprKind::BoolLiteral(x) => NodeKind::BoolLiteral(x),
+            ExprKind::CharLiteral(x) => NodeKind::CharLiteral(x),
+            ExprKind::Var(x) => NodeKind::Var(x),
+            ExprKind::Resolved(x, res) => NodeKind::Resolved(x, res),
+            ExprKind::Member(x, name) => NodeKind::Member(self.alloc(*x), name),
+            ExprKind::Cast(x, t) => NodeKind::Cast(self.alloc(*x), t),
+            ExprKind::Index(x, y) => {
+                let x = self.alloc(*x);
+                NodeKind::Index(x, self.alloc(*y))
+            }
+            ExprKind::Call(f, args) => {
+                let f = self.alloc(*f);
+                NodeKind::Call(f, self.alloc_all(args))
+            }
+            ExprKind::Block(stmts, tail) => {
+                let stmts = self.alloc_all(stmts);
+                NodeKind::Block(stmts, tail.map(|x| self.alloc(x)))
+            }
+            ExprKind::Let(name, span, t, x) => NodeKind::Let(name, span, t, self.alloc(*x)),
+            ExprKind::If(first, elifs, els) => {
+                let branches = std::iter::once(*first)
+                    .chain(elifs)
+                    .map(|(cond, body)| {
+                        let cond = self.alloc(cond);
+                        (cond, self.alloc(body))
+                    })
+                    .collect();
+                NodeKind::If(branches, els.map(|x| self.alloc(x)))
+            }
+            ExprKind::While(cond, body) => {
+                let cond = self.alloc(*cond);
+                NodeKind::While(cond, self.alloc(*body))
+            }
+            ExprKind::Return(x) => NodeKind::Return(x.map(|x| self.alloc(x))),
+            ExprKind::Set(x, y) => {
+                let x = self.alloc(*x);
+                NodeKind::Set(x, self.alloc(*y))
+            }
+            ExprKind::CompoundSet(op, x, y) => {
+                let x = self.alloc(*x);
+                NodeKind::CompoundSet(op, x, self.alloc(*y))
+            }
+            ExprKind::For(init, cond, step, body) => NodeKind::For(
+                self.alloc(*init),
+                self.alloc(*cond),
+                self.alloc(*step),
+                self.alloc(*body),
+            ),
+            ExprKind::Lambda(captures, params, ret, body) => {
+                NodeKind::Lambda(captures, params, ret, self.alloc(*body))
+            }
+            ExprKind::Match(x, arms) => {
+                let x = self.alloc(*x);
+                let arms = arms.into_iter().map(|(p, x)| (p, self.alloc(x))).collect();
+                NodeKind::Match(x, arms)
+            }
+            ExprKind::Break => NodeKind::Break,
+            ExprKind::Continue => NodeKind::Continue,
+            ExprKind::Error => NodeKind::Error,
+            ExprKind::Not(_)
+            | ExprKind::Plus(_)
+            | ExprKind::Minus(_)
+            | ExprKind::Add(..)
+            | ExprKind::Sub(..)
+            | ExprKind::Mul(..)
+            | ExprKind::Div(..)
+            | ExprKind::Mod(..)
+            | ExprKind::And(..)
+            | ExprKind::Or(..)
+            | ExprKind::BitAnd(..)
+            | ExprKind::BitOr(..)
+            | ExprKind::BitXor(..)
+            | ExprKind::Pow(..)
+            | ExprKind::Eq(..)
+            | ExprKind::Ne(..)
+            | ExprKind::Lt(..)
+            | ExprKind::Lte(..)
+            | ExprKind::Gt(..)
+            | ExprKind::Gte(..)
+            | ExprKind::Range(..)
+            | ExprKind::RangeInclusive(..) => unreachable!("operators are handled by `alloc`"),
+        }
+    }
+
+    // Rebuilds the boxed tree rooted at `id`.
+    pub fn to_expr(&self, id: ExprId) -> Expr {
+        let node = self.get(id);
+        let e = |id: &ExprId| self.to_expr(*id);
+        let b = |id: &ExprId| Box::new(self.to_expr(*id));
+        let kind = match &node.kind {
+            NodeKind::StructLiteral(name, fields) => ExprKind::StructLiteral(
+                name.clone(),
+                fields
+                    .iter()
+                    .map(|(name, x)| (name.clone(), e(x)))
+                    .collect(),
+            ),
+            NodeKind::I32Literal(x) => ExprKind::I32Literal(*x),
+            NodeKind::I64Literal(x) => ExprKind::I64Literal(*x),
+            NodeKind::F32Literal(x) => ExprKind::F32Literal(*x),
+            NodeKind::F64Literal(x) => ExprKind::F64Literal(*x),
+            NodeKind::StringLiteral(x) => ExprKind::StringLiteral(x.clone()),
+            NodeKind::ArrayLiteral(t, len) => ExprKind::ArrayLiteral(t.clone(), b(len)),
+            NodeKind::BoolLiteral(x) => ExprKind::BoolLiteral(*x),
+            NodeKind::CharLiteral(x) => ExprKind::CharLiteral(*x),
+            NodeKind::Var(x) => ExprKind::Var(x.clone()),
+            NodeKind::Resolved(x, res) => ExprKind::Resolved(x.clone(), *res),
+            NodeKind::Unary(op, x) => op.build(b(x)),
+            NodeKind::Member(x, name) => ExprKind::Member(b(x), name.clone()),
+            NodeKind::Cast(x, t) => ExprKind::Cast(b(x), t.clone()),
+            NodeKind::Index(x, y) => ExprKind::Index(b(x), b(y)),
+            NodeKind::Call(f, args) => ExprKind::Call(b(f), args.iter().map(e).collect()),
+            NodeKind::Binary(op, x, y) => op.build(b(x), b(y)),
+            NodeKind::Block(stmts, tail) => ExprKind::Block(
+                stmts.iter().map(e).collect(),
+                Box::new(tail.as_ref().map(e)),
+            ),
+            NodeKind::Let(name, span, t, x) => ExprKind::Let(name.clone(), *span, t.clone(), b(x)),
+            NodeKind::If(branches, els) => {
+                let mut branches = branches.iter().map(|(cond, body)| (e(cond), e(body)));
+                let first = branches.next().expect("`if` without branches");
+                ExprKind::If(
+                    Box::new(first),
+                    branches.collect(),
+                    Box::new(els.as_ref().map(e)),
+                )
+            }
+            NodeKind::While(cond, body) => ExprKind::While(b(cond), b(body)),
+            NodeKind::Return(x) => ExprKind::Return(Box::new(x.as_ref().map(e))),
+            NodeKind::Set(x, y) => ExprKind::Set(b(x), b(y)),
+            NodeKind::CompoundSet(op, x, y) => ExprKind::CompoundSet(*op, b(x), b(y)),
+            NodeKind::For(init, cond, step, body) => {
+                ExprKind::For(b(init), b(cond), b(step), b(body))
+            }
+            NodeKind::Lambda(captures, params, ret, body) => {
+                ExprKind::Lambda(captures.clone(), params.clone(), ret.clone(), b(body))
+            }
+            NodeKind::Match(x, arms) => {
+                ExprKind::Match(b(x), arms.iter().map(|(p, x)| (p.clone(), e(x))).collect())
+            }
+            NodeKind::Break => ExprKind::Break,
+            NodeKind::Continue => ExprKind::Continue,
+            NodeKind::Error => ExprKind::Error,
+        };
+        Expr::new(kind, node.span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::MemberKind;
+    use crate::parser::parse_source;
+
+    #[test]
+    fn arena_test() {
+        let (module, _) = parse_source(
+            "fun f() {
+                let x = -a + b * c;
+                if x < 1 { 1 } else if x < 2 { 2 } else { S { y: [i32; 3] } };
+                match x { E.A(1) => g(x)[0].z, _ => |p: i32| -> i32 { return p } };
+                for i = 0; i < 10; i += 1 { break; }
+            }",
+        );
+        let body = match &module[0].kind {
+            MemberKind::Func(_, body) => body.clone(),
+            x => panic!("{:?}", x),
+        };
+        let mut arena = Arena::new();
+        let id = arena.alloc(body.clone());
+        assert_eq!(arena.to_expr(id), body);
+        assert_eq!(id.index(), arena.len() - 1);
+        assert!(arena.iter().all(|(id, node)| match &node.kind {
+            NodeKind::Binary(_, x, y) => x < &id && y < &id,
+            _ => true,
+        }));
+    }
+}
@@ -1,7 +1,60 @@
+pub use token::token::Span;
+
 pub type Ident = String;
 
 #[derive(Clone, Debug, PartialEq)]
-pub enum Expr {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Expr {
+        Expr { kind, span }
+    }
+
+    // Whether this can initialize a global: literals and operators over them, and other
+    // globals by name. Codegen folds these into a single wasm constant.
+    pub fn is_const(&self) -> bool {
+        match &self.kind {
+            ExprKind::I32Literal(_)
+            | ExprKind::I64Literal(_)
+            | ExprKind::F32Literal(_)
+            | ExprKind::F64Literal(_)
+            | ExprKind::BoolLiteral(_)
+            | ExprKind::CharLiteral(_)
+            | ExprKind::StringLiteral(_)
+            | ExprKind::Var(_)
+            | ExprKind::Resolved(..) => true,
+            ExprKind::Not(x) | ExprKind::Plus(x) | ExprKind::Minus(x) | ExprKind::Cast(x, _) => {
+                x.is_const()
+            }
+            ExprKind::Add(x, y)
+            | ExprKind::Sub(x, y)
+            | ExprKind::Mul(x, y)
+            | ExprKind::Div(x, y)
+            | ExprKind::Mod(x, y)
+            | ExprKind::And(x, y)
+            | ExprKind::Or(x, y)
+            | ExprKind::BitAnd(x, y)
+            | ExprKind::BitOr(x, y)
+            | ExprKind::BitXor(x, y)
+            | ExprKind::Pow(x, y)
+            | ExprKind::Eq(x, y)
+            | ExprKind::Ne(x, y)
+            | ExprKind::Lt(x, y)
+            | ExprKind::Lte(x, y)
+            | ExprKind::Gt(x, y)
+            | ExprKind::Gte(x, y) => x.is_const() && y.is_const(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExprKind {
     StructLiteral(Ident, Vec<(Ident, Expr)>),
     I32Literal(i32),
     I64Literal(i64),
@@ -12,9 +65,12 @@ pub enum Expr {
     BoolLiteral(bool),
     CharLiteral(char),
     Var(Ident),
+    // A `Var` after name resolution.
+    Resolved(Ident, Resolution),
     Not(Box<Expr>),
     Plus(Box<Expr>),
     Minus(Box<Expr>),
+    Cast(Box<Expr>, Type),
     Member(Box<Expr>, Ident),
     Index(Box<Expr>, Box<Expr>),
     Call(Box<Expr>, Vec<Expr>),
@@ -35,17 +91,89 @@ pub enum Expr {
     Lte(Box<Expr>, Box<Expr>),
     Gt(Box<Expr>, Box<Expr>),
     Gte(Box<Expr>, Box<Expr>),
+    Range(Box<Expr>, Box<Expr>),
+    RangeInclusive(Box<Expr>, Box<Expr>),
     Block(Vec<Expr>, Box<Option<Expr>>),
-    Let(Ident, Box<Expr>),
+    // The span is the one of the name.
+    Let(Ident, Span, Option<Type>, Box<Expr>),
     If(Box<(Expr, Expr)>, Vec<(Expr, Expr)>, Box<Option<Expr>>),
     While(Box<Expr>, Box<Expr>),
     Return(Box<Option<Expr>>),
     Set(Box<Expr>, Box<Expr>),
+    // `x += y` and the like, which evaluate the target only once.
+    CompoundSet(AssignOp, Box<Expr>, Box<Expr>),
     For(Box<Expr>, Box<Expr>, Box<Expr>, Box<Expr>),
     Lambda(Vec<Ident>, Vec<(Ident, Type)>, Type, Box<Expr>),
+    Match(Box<Expr>, Vec<(Pattern, Expr)>),
+    Break,
+    Continue,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AssignOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl AssignOp {
+    // The binary operator applied, as written.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AssignOp::Add => "+",
+            AssignOp::Sub => "-",
+            AssignOp::Mul => "*",
+            AssignOp::Div => "/",
+            AssignOp::Mod => "%",
+        }
+    }
+}
+
+// What a name refers to. Functions (including extern ones), globals and enums are numbered in
+// module order; locals per function or lambda, in the order they are declared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Resolution {
+    Local(usize),
+    Global(usize),
+    Func(usize),
+    // Only as the base of `Enum.Variant`.
+    Enum(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    Wildcard,
+    Binding(Ident),
+    Literal(Expr),
+    // `Enum.Variant(p, ...)`
+    Variant(Ident, Ident, Vec<Pattern>),
+}
+
+impl Pattern {
+    pub fn is_irrefutable(&self) -> bool {
+        matches!(self, Pattern::Wildcard | Pattern::Binding(_))
+    }
+
+    // The sub-patterns still to be matched once the scrutinee is known to be `Enum.Variant`
+    // with `arity` fields, or `None` if this pattern rejects it. Exhaustiveness checking
+    // specializes the rows of a match with this for every variant of the enum.
+    pub fn specialize(&self, enum_: &str, variant: &str, arity: usize) -> Option<Vec<Pattern>> {
+        match self {
+            Pattern::Wildcard | Pattern::Binding(_) => Some(vec![Pattern::Wildcard; arity]),
+            Pattern::Variant(e, v, xs) if e == enum_ && v == variant => Some(xs.clone()),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     I32,
     I64,
@@ -54,24 +182,157 @@ pub enum Type {
     Bool,
     Char,
     RefType(RefType),
+    Param(Ident),
+}
+
+impl Type {
+    // Replaces type parameters with concrete types, for monomorphizing generic members.
+    pub fn substitute(&self, params: &[TypeParam], args: &[Type]) -> Type {
+        let sub = |x: &Type| x.substitute(params, args);
+        match self {
+            Type::Param(x) => params
+                .iter()
+                .position(|p| &p.0 == x)
+                .and_then(|i| args.get(i))
+                .cloned()
+                .unwrap_or_else(|| self.clone()),
+            Type::RefType(RefType::Array(x)) => Type::RefType(RefType::Array(Box::new(sub(x)))),
+            Type::RefType(RefType::Struct(name, xs)) => {
+                Type::RefType(RefType::Struct(name.clone(), xs.iter().map(sub).collect()))
+            }
+            Type::RefType(RefType::Enum(name, xs)) => {
+                Type::RefType(RefType::Enum(name.clone(), xs.iter().map(sub).collect()))
+            }
+            Type::RefType(RefType::Func(xs, ret)) => Type::RefType(RefType::Func(
+                xs.iter().map(sub).collect(),
+                Box::new(ret.as_ref().as_ref().map(sub)),
+            )),
+            x => x.clone(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RefType {
     String,
     Array(Box<Type>),
-    Struct(Ident),
+    Struct(Ident, Vec<Type>),
+    // Named types are parsed as `Struct`; the ones naming an enum are told apart once the
+    // whole module is known.
+    Enum(Ident, Vec<Type>),
     Func(Vec<Type>, Box<Option<Type>>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub struct FuncDef(Ident, Vec<(Ident, Type)>, Option<Type>);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TypeParam(pub Ident);
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Variant(pub Ident, pub Vec<Type>);
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FuncDef(
+    pub Ident,
+    pub Vec<TypeParam>,
+    pub Vec<(Ident, Type)>,
+    pub Option<Type>,
+);
 
 #[derive(Clone, Debug, PartialEq)]
-pub enum Member {
-    Struct(Ident, Vec<(Ident, Type)>),
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Member {
+    pub kind: MemberKind,
+    pub span: Span,
+    pub visibility: Visibility,
+}
+
+impl Member {
+    pub fn new(kind: MemberKind, span: Span) -> Member {
+        Member {
+            kind,
+            span,
+            visibility: Visibility::Private,
+        }
+    }
+}
+
+// `const x: T = e;`, `let x: T = e;` and `let mut x: T = e;` at module level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mutability {
+    Const,
+    Immutable,
+    Mutable,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Visibility {
+    Private,
+    Public,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemberKind {
+    Struct(Ident, Vec<TypeParam>, Vec<(Ident, Type)>),
+    Enum(Ident, Vec<TypeParam>, Vec<Variant>),
     Func(FuncDef, Expr),
     ExternFun(FuncDef, String, String),
+    // `import a.b;`
+    Import(Vec<Ident>),
+    Global(Ident, Mutability, Type, Expr),
+    Error,
 }
 
 pub type Module = Vec<Member>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitute_test() {
+        let params = vec![TypeParam("T".to_string()), TypeParam("U".to_string())];
+        let t = Type::RefType(RefType::Func(
+            vec![Type::RefType(RefType::Array(Box::new(Type::Param(
+                "T".to_string(),
+            ))))],
+            Box::new(Some(Type::RefType(RefType::Struct(
+                "Box".to_string(),
+                vec![Type::Param("U".to_string())],
+            )))),
+        ));
+        assert_eq!(
+            t.substitute(&params, &[Type::I32, Type::Bool]),
+            Type::RefType(RefType::Func(
+                vec![Type::RefType(RefType::Array(Box::new(Type::I32)))],
+                Box::new(Some(Type::RefType(RefType::Struct(
+                    "Box".to_string(),
+                    vec![Type::Bool]
+                )))),
+            ))
+        );
+    }
+
+    #[test]
+    fn specialize_test() {
+        let some = Pattern::Variant(
+            "Option".to_string(),
+            "Some".to_string(),
+            vec![Pattern::Binding("x".to_string())],
+        );
+        assert_eq!(
+            some.specialize("Option", "Some", 1),
+            Some(vec![Pattern::Binding("x".to_string())])
+        );
+        assert_eq!(some.specialize("Option", "None", 0), None);
+        assert_eq!(
+            Pattern::Wildcard.specialize("Option", "Some", 1),
+            Some(vec![Pattern::Wildcard])
+        );
+    }
+}
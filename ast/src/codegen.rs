@@ -0,0 +1,3076 @@
+use crate::ast::{
+    AssignOp, Expr, ExprKind, FuncDef, Member, MemberKind, Module, Mutability, Pattern, Resolution,
+    Span, Type, Variant, Visibility,
+};
+use crate::desugar::desugar_expr;
+use crate::math;
+use crate::runtime::{self, Object, Runtime, ARRAY_DATA};
+use crate::sourcemap::CodeSpans;
+use crate::typeck::{diverges, plural, Ty, Types};
+use crate::wasi::{self, Wasi};
+use diagnostics::code::Code;
+use diagnostics::diagnostic::Diagnostic;
+use std::collections::{HashMap, HashSet};
+use wasm::ast::{
+    BlockType, CodeSection, DataSection, DataSegment, ElemSegment, ElemType, ElementSection,
+    ExportEntry, ExportSection, ExternalKind, ExternalKindImport, FuncType, FunctionBody,
+    FunctionSection, GlobalSection, GlobalType, GlobalVariable, ImportEntry, ImportSection,
+    InitExpr, LocalEntry, MemoryImmediate, MemorySection, MemoryType, NameSection, OperatorCode,
+    ResizableLimits, StartSection, TableSection, TableType, TypeSection, ValueType, WasmASTRoot,
+};
+
+// Lowers a resolved and type-checked module to wasm.
+//
+// Every value is a single wasm value: numbers as themselves, `bool` and `char` as `i32`, and
+// strings, arrays, structs and enums as `i32` pointers into linear memory. Functions are `i32`
+// indices into the function table, which only holds the functions used as values and is called
+// through with `call_indirect`. `()` and `!` have no value at all. Locals keep the numbering of
+// the resolver, parameters first.
+//
+// Address 0 is never allocated so that it can stand for a missing reference. Static data such
+// as string literals starts at `DATA_START`, each object aligned to 4 bytes. A string is its
+// length in bytes as an `i32` followed by its UTF-8 bytes. Objects created at run time, such as
+// structs, live on the reference counted heap of `runtime` that follows the static data.
+// An enum is the index of its variant as an `i32` followed by the fields of the variant, and an
+// array is its length followed by its elements, which `[T; n]` sets to zero.
+//
+// Code owns the references of counted types it handles: every expression evaluates to a
+// reference of its own, which is released when the value is dropped and handed over when it is
+// stored or passed as an argument. Locals, parameters included, release what they hold when
+// they are overwritten and when the function returns.
+
+const DATA_START: usize = 8;
+const PAGE_SIZE: usize = 65536;
+// `match`es whose first arms test at least this many integers, which fill at least half of the
+// range between the smallest and the largest, jump to the arm through a `br_table`.
+const TABLE_MIN_CASES: usize = 3;
+
+// Whether values of type `t` are references to the heap. Type parameters could stand for
+// anything, so their values are never counted.
+fn counted(t: &Ty) -> bool {
+    matches!(t, Ty::String | Ty::Array(_) | Ty::Struct(..) | Ty::Enum(..))
+}
+
+pub fn value_type(t: &Ty) -> Option<ValueType> {
+    match t {
+        Ty::I64 => Some(ValueType::I64),
+        Ty::F32 => Some(ValueType::F32),
+        Ty::F64 => Some(ValueType::F64),
+        Ty::Unit | Ty::Never => None,
+        _ => Some(ValueType::I32),
+    }
+}
+
+type Access = fn(MemoryImmediate) -> OperatorCode;
+
+// How a value of type `t` is kept in memory: its size, which is also its alignment, and the
+// instructions to load and store it. `()` takes no space at all.
+fn scalar(t: &Ty) -> Option<(u32, Access, Access)> {
+    Some(match value_type(t)? {
+        ValueType::I64 => (8, OperatorCode::I64Load, OperatorCode::I64Store),
+        ValueType::F64 => (8, OperatorCode::F64Load, OperatorCode::F64Store),
+        ValueType::F32 => (4, OperatorCode::F32Load, OperatorCode::F32Store),
+        _ if *t == Ty::Bool => (1, OperatorCode::I32Load8u, OperatorCode::I32Store8),
+        _ => (4, OperatorCode::I32Load, OperatorCode::I32Store),
+    })
+}
+
+// The instruction for `x op y` on numbers of type `t`.
+fn arith(op: AssignOp, t: &Ty) -> OperatorCode {
+    match (op, t) {
+        (AssignOp::Add, Ty::I64) => OperatorCode::I64Add,
+        (AssignOp::Add, Ty::F32) => OperatorCode::F32Add,
+        (AssignOp::Add, Ty::F64) => OperatorCode::F64Add,
+        (AssignOp::Add, _) => OperatorCode::I32Add,
+        (AssignOp::Sub, Ty::I64) => OperatorCode::I64Sub,
+        (AssignOp::Sub, Ty::F32) => OperatorCode::F32Sub,
+        (AssignOp::Sub, Ty::F64) => OperatorCode::F64Sub,
+        (AssignOp::Sub, _) => OperatorCode::I32Sub,
+        (AssignOp::Mul, Ty::I64) => OperatorCode::I64Mul,
+        (AssignOp::Mul, Ty::F32) => OperatorCode::F32Mul,
+        (AssignOp::Mul, Ty::F64) => OperatorCode::F64Mul,
+        (AssignOp::Mul, _) => OperatorCode::I32Mul,
+        (AssignOp::Div, Ty::I64) => OperatorCode::I64Divs,
+        (AssignOp::Div, Ty::F32) => OperatorCode::F32Div,
+        (AssignOp::Div, Ty::F64) => OperatorCode::F64Div,
+        (AssignOp::Div, _) => OperatorCode::I32Divs,
+        (AssignOp::Mod, Ty::I64) => OperatorCode::I64Rems,
+        (AssignOp::Mod, _) => OperatorCode::I32Rems,
+    }
+}
+
+fn memory_immediate(size: u32, offset: u32) -> MemoryImmediate {
+    MemoryImmediate {
+        flags: size.trailing_zeros(),
+        offset,
+    }
+}
+
+// The fields of a struct or an enum variant in declaration order, each at the next offset
+// aligned to its size. Type parameters are laid out as `i32`, like the references they stand for.
+struct Layout {
+    tag: usize,
+    size: u32,
+    fields: Vec<(String, Ty, u32)>,
+}
+
+impl Layout {
+    fn new(tag: usize, fields: &[(String, Type)]) -> Layout {
+        let mut size = 0u32;
+        let fields = fields
+            .iter()
+            .map(|(name, t)| {
+                let t = Ty::from_type(t);
+                let n = scalar(&t).map_or(0, |x| x.0);
+                let offset = size.next_multiple_of(n.max(1));
+                size = offset + n;
+                (name.clone(), t, offset)
+            })
+            .collect();
+        Layout { tag, size, fields }
+    }
+
+    fn field(&self, name: &str) -> Option<&(String, Ty, u32)> {
+        self.fields.iter().find(|x| x.0 == name)
+    }
+
+    fn object(&self) -> Object {
+        Object {
+            size: self.size,
+            refs: self
+                .fields
+                .iter()
+                .filter(|x| counted(&x.1))
+                .map(|x| x.2)
+                .collect(),
+            elems: None,
+        }
+    }
+}
+
+// A variant is laid out like a struct whose first field is the index of the variant, followed
+// by the fields of the variant named by their position.
+fn variant_layout(tag: usize, Variant(_, fields): &Variant) -> Layout {
+    let fields = std::iter::once((String::new(), Type::I32))
+        .chain(
+            fields
+                .iter()
+                .enumerate()
+                .map(|(i, t)| (i.to_string(), t.clone())),
+        )
+        .collect::<Vec<_>>();
+    Layout::new(tag, &fields)
+}
+
+// The enum `x` names, as the base of `Enum.Variant`.
+fn enum_name(x: &Expr) -> Option<&str> {
+    match &x.kind {
+        ExprKind::Resolved(name, Resolution::Enum(_)) => Some(name),
+        _ => None,
+    }
+}
+
+fn signature(FuncDef(_, _, params, ret): &FuncDef) -> (Vec<Ty>, Ty) {
+    (
+        params.iter().map(|x| Ty::from_type(&x.1)).collect(),
+        Ty::from_ret(ret.as_ref()),
+    )
+}
+
+// The function whose body is being generated.
+struct Func<'a> {
+    locals: &'a [Ty],
+    // The local the next `let` or pattern binding declares.
+    next: usize,
+    // Locals for intermediate values, numbered after those of the resolver.
+    temps: Vec<ValueType>,
+    // The temporaries holding references, which are released on return like locals.
+    refs: Vec<usize>,
+    // The names of the parameters and the locals declared so far, for the name section.
+    names: Vec<(usize, String)>,
+    // The expression each instruction from an index on comes from, for source maps, and the
+    // innermost expression being generated.
+    spans: Vec<(usize, Span)>,
+    span: Option<Span>,
+    // The number of enclosing wasm blocks, and the levels of the `block` that `break` leaves
+    // and the `loop` that `continue` restarts for each enclosing `while`.
+    depth: usize,
+    loops: Vec<(usize, usize)>,
+    codes: Vec<OperatorCode>,
+}
+
+impl<'a> Func<'a> {
+    fn new(locals: &'a [Ty], params: usize) -> Func<'a> {
+        Func {
+            locals,
+            next: params,
+            temps: Vec::new(),
+            refs: Vec::new(),
+            names: Vec::new(),
+            spans: Vec::new(),
+            span: None,
+            depth: 0,
+            loops: Vec::new(),
+            codes: Vec::new(),
+        }
+    }
+
+    fn temp(&mut self, t: ValueType) -> usize {
+        self.temps.push(t);
+        self.locals.len() + self.temps.len() - 1
+    }
+
+    fn ref_temp(&mut self) -> usize {
+        let i = self.temp(ValueType::I32);
+        self.refs.push(i);
+        i
+    }
+
+    fn mark(&mut self, span: Span) {
+        match self.spans.last_mut() {
+            Some(last) if last.0 == self.codes.len() => last.1 = span,
+            _ => self.spans.push((self.codes.len(), span)),
+        }
+    }
+
+    // Opens a `block`, `loop` or `if` and returns its level, for `br`.
+    fn open(&mut self, op: OperatorCode) -> usize {
+        self.codes.push(op);
+        self.depth += 1;
+        self.depth - 1
+    }
+
+    fn close(&mut self) {
+        self.codes.push(OperatorCode::End);
+        self.depth -= 1;
+    }
+
+    fn br(&self, level: usize) -> usize {
+        self.depth - 1 - level
+    }
+}
+
+// Compile-time values of global initializers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Const {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Const {
+    fn init_expr(self) -> InitExpr {
+        match self {
+            Const::I32(x) => InitExpr::I32(x),
+            Const::I64(x) => InitExpr::I64(x),
+            Const::F32(x) => InitExpr::F32(x),
+            Const::F64(x) => InitExpr::F64(x),
+        }
+    }
+
+    fn zero(t: &ValueType) -> Const {
+        match t {
+            ValueType::I32 => Const::I32(0),
+            ValueType::I64 => Const::I64(0),
+            ValueType::F32 => Const::F32(0.0),
+            ValueType::F64 => Const::F64(0.0),
+            // `value_type` gives nothing else.
+            _ => Const::I32(0),
+        }
+    }
+
+    // Integers wrap; the checker has made sure both sides have the same type.
+    fn arith(
+        self,
+        y: Const,
+        i32: fn(i32, i32) -> Option<i32>,
+        i64: fn(i64, i64) -> Option<i64>,
+        f32: fn(f32, f32) -> f32,
+        f64: fn(f64, f64) -> f64,
+    ) -> Option<Const> {
+        match (self, y) {
+            (Const::I32(x), Const::I32(y)) => i32(x, y).map(Const::I32),
+            (Const::I64(x), Const::I64(y)) => i64(x, y).map(Const::I64),
+            (Const::F32(x), Const::F32(y)) => Some(Const::F32(f32(x, y))),
+            (Const::F64(x), Const::F64(y)) => Some(Const::F64(f64(x, y))),
+            _ => None,
+        }
+    }
+
+    fn bits(self, y: Const, i32: fn(i32, i32) -> i32, i64: fn(i64, i64) -> i64) -> Option<Const> {
+        match (self, y) {
+            (Const::I32(x), Const::I32(y)) => Some(Const::I32(i32(x, y))),
+            (Const::I64(x), Const::I64(y)) => Some(Const::I64(i64(x, y))),
+            _ => None,
+        }
+    }
+
+    fn compare(self, y: Const, op: fn(std::cmp::Ordering) -> bool) -> Option<Const> {
+        let ordering = match (self, y) {
+            (Const::I32(x), Const::I32(y)) => x.partial_cmp(&y),
+            (Const::I64(x), Const::I64(y)) => x.partial_cmp(&y),
+            (Const::F32(x), Const::F32(y)) => x.partial_cmp(&y),
+            (Const::F64(x), Const::F64(y)) => x.partial_cmp(&y),
+            _ => None,
+        };
+        // Comparisons with NaN are all false.
+        Some(Const::I32(ordering.is_some_and(op) as i32))
+    }
+
+    // Like `as` in Rust, so floats saturate where wasm would trap.
+    fn cast(self, to: &ValueType) -> Const {
+        match (self, to) {
+            (Const::I32(x), ValueType::I64) => Const::I64(x.into()),
+            (Const::I32(x), ValueType::F32) => Const::F32(x as f32),
+            (Const::I32(x), ValueType::F64) => Const::F64(x.into()),
+            (Const::I64(x), ValueType::I32) => Const::I32(x as i32),
+            (Const::I64(x), ValueType::F32) => Const::F32(x as f32),
+            (Const::I64(x), ValueType::F64) => Const::F64(x as f64),
+            (Const::F32(x), ValueType::I32) => Const::I32(x as i32),
+            (Const::F32(x), ValueType::I64) => Const::I64(x as i64),
+            (Const::F32(x), ValueType::F64) => Const::F64(x.into()),
+            (Const::F64(x), ValueType::I32) => Const::I32(x as i32),
+            (Const::F64(x), ValueType::I64) => Const::I64(x as i64),
+            (Const::F64(x), ValueType::F32) => Const::F32(x as f32),
+            (x, _) => x,
+        }
+    }
+}
+
+struct Codegen<'a> {
+    // Functions and extern functions, by the id they resolve to.
+    funcs: Vec<&'a FuncDef>,
+    // The wasm index of each function in `funcs`: imports come first, so extern functions are
+    // numbered before the others.
+    indices: Vec<usize>,
+    imports: usize,
+    // The runtime follows the functions and the globals of the module.
+    runtime: Runtime,
+    // Helpers such as `math::pow` come last, after the builtins, and only when used.
+    helpers: usize,
+    pow: bool,
+    globals: Vec<(Mutability, &'a Type, &'a Expr)>,
+    structs: HashMap<&'a str, Layout>,
+    // The variants of every enum, tagged after the structs.
+    enums: HashMap<&'a str, Vec<(&'a str, Layout)>>,
+    // The element size and whether the elements are references of every kind of array in use,
+    // tagged after the variants.
+    arrays: Vec<(u32, bool)>,
+    types: Vec<FuncType>,
+    // The wasm index of the function in each slot of the table from slot 1 on. Slot 0 is left
+    // empty, like address 0, so that calling through it traps.
+    table: Vec<usize>,
+    // Static data, placed at `DATA_START`, and the address of every string literal in it.
+    data: Vec<u8>,
+    strings: HashMap<String, usize>,
+    // The names of the locals and the spans of the instructions of each function body.
+    names: Vec<Vec<(usize, String)>>,
+    spans: CodeSpans,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Codegen<'a> {
+    // The index of a function type, shared by all functions with the same signature.
+    fn func_type(&mut self, def: &FuncDef) -> usize {
+        let (params, ret) = signature(def);
+        self.signature_type(&params, &ret)
+    }
+
+    fn signature_type(&mut self, params: &[Ty], ret: &Ty) -> usize {
+        self.type_index(FuncType {
+            params: params.iter().filter_map(value_type).collect(),
+            results: value_type(ret).into_iter().collect(),
+        })
+    }
+
+    // The slot of the function with id `i` in the table, which is given one the first time it is
+    // used as a value.
+    fn table_slot(&mut self, i: usize) -> usize {
+        let index = self.indices[i];
+        match self.table.iter().position(|x| *x == index) {
+            Some(slot) => slot + 1,
+            None => {
+                self.table.push(index);
+                self.table.len()
+            }
+        }
+    }
+
+    fn type_index(&mut self, t: FuncType) -> usize {
+        match self.types.iter().position(|x| x == &t) {
+            Some(i) => i,
+            None => {
+                self.types.push(t);
+                self.types.len() - 1
+            }
+        }
+    }
+
+    // The type of `x`, recomputed from the types of locals and declarations. The checker has
+    // made sure that it is consistent; type arguments do not matter to the representation and
+    // are left out.
+    fn ty(&self, f: &Func, x: &Expr) -> Ty {
+        let first = |xs: &mut dyn Iterator<Item = &Expr>| {
+            xs.map(|x| self.ty(f, x))
+                .find(|t| *t != Ty::Never)
+                .unwrap_or(Ty::Never)
+        };
+        match &x.kind {
+            ExprKind::I32Literal(_) => Ty::I32,
+            ExprKind::I64Literal(_) => Ty::I64,
+            ExprKind::F32Literal(_) => Ty::F32,
+            ExprKind::F64Literal(_) => Ty::F64,
+            ExprKind::BoolLiteral(_) => Ty::Bool,
+            ExprKind::CharLiteral(_) => Ty::Char,
+            ExprKind::StringLiteral(_) => Ty::String,
+            ExprKind::ArrayLiteral(t, _) => Ty::Array(Box::new(Ty::from_type(t))),
+            ExprKind::StructLiteral(name, _) => Ty::Struct(name.clone(), Vec::new()),
+            ExprKind::Resolved(_, Resolution::Local(i)) => f.locals[*i].clone(),
+            ExprKind::Resolved(_, Resolution::Global(i)) => Ty::from_type(self.globals[*i].1),
+            ExprKind::Resolved(_, Resolution::Func(i)) => {
+                let (params, ret) = signature(self.funcs[*i]);
+                Ty::Func(params, Box::new(ret))
+            }
+            ExprKind::Not(x) | ExprKind::Plus(x) | ExprKind::Minus(x) => self.ty(f, x),
+            ExprKind::Cast(_, t) => Ty::from_type(t),
+            ExprKind::Index(x, _) => match self.ty(f, x) {
+                Ty::Array(t) => *t,
+                _ => Ty::Unknown,
+            },
+            ExprKind::Member(base, name) if enum_name(base).is_some() => {
+                let enum_ = enum_name(base).unwrap_or_default();
+                let t = Ty::Enum(enum_.to_string(), Vec::new());
+                match self.variant(enum_, name) {
+                    Some((_, layout)) if layout.fields.len() > 1 => Ty::Func(
+                        layout.fields[1..].iter().map(|x| x.1.clone()).collect(),
+                        Box::new(t),
+                    ),
+                    _ => t,
+                }
+            }
+            ExprKind::Member(x, name) => match self.member(f, x, name) {
+                Some((t, _)) => t,
+                None => Ty::Unknown,
+            },
+            ExprKind::Call(g, _) => match self.ty(f, g) {
+                Ty::Func(_, ret) => *ret,
+                _ => Ty::Unknown,
+            },
+            ExprKind::Add(x, y)
+            | ExprKind::Sub(x, y)
+            | ExprKind::Mul(x, y)
+            | ExprKind::Div(x, y)
+            | ExprKind::Mod(x, y)
+            | ExprKind::BitAnd(x, y)
+            | ExprKind::BitOr(x, y)
+            | ExprKind::BitXor(x, y)
+            | ExprKind::Pow(x, y) => first(&mut [&**x, &**y].iter().copied()),
+            ExprKind::And(..)
+            | ExprKind::Or(..)
+            | ExprKind::Eq(..)
+            | ExprKind::Ne(..)
+            | ExprKind::Lt(..)
+            | ExprKind::Lte(..)
+            | ExprKind::Gt(..)
+            | ExprKind::Gte(..) => Ty::Bool,
+            ExprKind::Block(_, tail) => match &**tail {
+                Some(tail) => self.ty(f, tail),
+                None if diverges(x) => Ty::Never,
+                None => Ty::Unit,
+            },
+            ExprKind::If(first_, elifs, els) => match &**els {
+                Some(els) => first(
+                    &mut std::iter::once(&**first_)
+                        .chain(elifs)
+                        .map(|x| &x.1)
+                        .chain(std::iter::once(els)),
+                ),
+                None => Ty::Unit,
+            },
+            ExprKind::Match(_, arms) => first(&mut arms.iter().map(|x| &x.1)),
+            ExprKind::While(..) if diverges(x) => Ty::Never,
+            ExprKind::Let(..)
+            | ExprKind::Set(..)
+            | ExprKind::CompoundSet(..)
+            | ExprKind::While(..)
+            | ExprKind::For(..) => Ty::Unit,
+            ExprKind::Return(_) | ExprKind::Break | ExprKind::Continue => Ty::Never,
+            ExprKind::Lambda(_, params, ret, _) => Ty::Func(
+                params.iter().map(|x| Ty::from_type(&x.1)).collect(),
+                Box::new(Ty::from_type(ret)),
+            ),
+            _ => Ty::Unknown,
+        }
+    }
+
+    // The type and offset of a field of the struct `x` evaluates to.
+    fn member(&self, f: &Func, x: &Expr, name: &str) -> Option<(Ty, u32)> {
+        match self.ty(f, x) {
+            Ty::Struct(s, _) => {
+                let (_, t, offset) = self.structs.get(s.as_str())?.field(name)?;
+                Some((t.clone(), *offset))
+            }
+            _ => None,
+        }
+    }
+
+    // The index and the layout of a variant.
+    fn variant(&self, enum_: &str, name: &str) -> Option<(usize, &Layout)> {
+        self.enums
+            .get(enum_)?
+            .iter()
+            .enumerate()
+            .find(|x| x.1 .0 == name)
+            .map(|(i, x)| (i, &x.1))
+    }
+
+    // The tag of arrays of `t` and the size of their elements. Arrays share a tag when their
+    // elements take the same space and are released the same way.
+    fn array_tag(&mut self, t: &Ty) -> (usize, u32) {
+        let elems = (scalar(t).map_or(0, |x| x.0), counted(t));
+        let i = match self.arrays.iter().position(|x| *x == elems) {
+            Some(i) => i,
+            None => {
+                self.arrays.push(elems);
+                self.arrays.len() - 1
+            }
+        };
+        let tags = self.structs.len() + self.enums.values().map(Vec::len).sum::<usize>();
+        (tags + i, elems.0)
+    }
+
+    // Allocates a struct and leaves its address on the stack.
+    fn alloc(&self, f: &mut Func, layout: &Layout) {
+        f.codes.push(OperatorCode::I32Const(layout.size as i32));
+        f.codes.push(OperatorCode::I32Const(layout.tag as i32));
+        f.codes.push(OperatorCode::Call(self.runtime.alloc));
+    }
+
+    // Traps if the `i32` on the stack is not zero.
+    fn trap_if(&self, f: &mut Func) {
+        f.open(OperatorCode::If(BlockType::Empty));
+        f.codes.push(OperatorCode::Unreachable);
+        f.close();
+    }
+
+    fn retain(&self, f: &mut Func) {
+        f.codes.push(OperatorCode::Call(self.runtime.retain()));
+    }
+
+    fn release(&self, f: &mut Func) {
+        f.codes.push(OperatorCode::Call(self.runtime.release()));
+    }
+
+    // Stores the reference on the stack in `local`, releasing the one it held.
+    fn replace(&self, f: &mut Func, local: usize) {
+        f.codes.push(OperatorCode::GetLocal(local));
+        self.release(f);
+        f.codes.push(OperatorCode::SetLocal(local));
+    }
+
+    // Releases the reference in the temporary `local` and clears it, so that it is not released
+    // again on return.
+    fn free(&self, f: &mut Func, local: usize) {
+        f.codes.push(OperatorCode::GetLocal(local));
+        self.release(f);
+        f.codes.push(OperatorCode::I32Const(0));
+        f.codes.push(OperatorCode::SetLocal(local));
+    }
+
+    // Releases the references of the locals before leaving the function with the value of type
+    // `t` on the stack.
+    fn exit(&self, f: &mut Func, t: Option<ValueType>) {
+        let refs = (0..f.locals.len())
+            .filter(|&i| counted(&f.locals[i]))
+            .chain(f.refs.iter().copied())
+            .collect::<Vec<_>>();
+        if refs.is_empty() {
+            return;
+        }
+        let result = t.map(|t| f.temp(t));
+        f.codes.extend(result.map(OperatorCode::SetLocal));
+        for i in refs {
+            f.codes.push(OperatorCode::GetLocal(i));
+            self.release(f);
+        }
+        f.codes.extend(result.map(OperatorCode::GetLocal));
+    }
+
+    // The address of a string literal, shared by all literals with the same contents.
+    fn string(&mut self, s: &str) -> usize {
+        if let Some(&addr) = self.strings.get(s) {
+            return addr;
+        }
+        self.data.resize((self.data.len() + 3) & !3, 0);
+        let addr = DATA_START + self.data.len();
+        self.data.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(s.as_bytes());
+        self.strings.insert(s.to_string(), addr);
+        addr
+    }
+
+    // Folds a constant expression as `Expr::is_const` describes them, or `None` for the ones
+    // that would trap such as a division by zero.
+    fn constant(&self, x: &Expr) -> Option<Const> {
+        use std::cmp::Ordering;
+        let c = |x: &Expr| self.constant(x);
+        Some(match &x.kind {
+            ExprKind::I32Literal(n) => Const::I32(*n),
+            ExprKind::I64Literal(n) => Const::I64(*n),
+            ExprKind::F32Literal(n) => Const::F32(*n),
+            ExprKind::F64Literal(n) => Const::F64(*n),
+            ExprKind::BoolLiteral(b) => Const::I32(*b as i32),
+            ExprKind::CharLiteral(c) => Const::I32(*c as i32),
+            ExprKind::StringLiteral(s) => Const::I32(*self.strings.get(s)? as i32),
+            ExprKind::Resolved(_, Resolution::Global(i)) => c(self.globals[*i].2)?,
+            ExprKind::Plus(x) => c(x)?,
+            ExprKind::Minus(x) => match c(x)? {
+                Const::I32(x) => Const::I32(x.wrapping_neg()),
+                Const::I64(x) => Const::I64(x.wrapping_neg()),
+                Const::F32(x) => Const::F32(-x),
+                Const::F64(x) => Const::F64(-x),
+            },
+            ExprKind::Not(x) => match (self.ty(&Func::new(&[], 0), x), c(x)?) {
+                (Ty::Bool, Const::I32(x)) => Const::I32(x ^ 1),
+                (_, Const::I32(x)) => Const::I32(!x),
+                (_, Const::I64(x)) => Const::I64(!x),
+                _ => return None,
+            },
+            ExprKind::Cast(x, t) => c(x)?.cast(&value_type(&Ty::from_type(t))?),
+            ExprKind::Add(x, y) => c(x)?.arith(
+                c(y)?,
+                |x, y| Some(x.wrapping_add(y)),
+                |x, y| Some(x.wrapping_add(y)),
+                |x, y| x + y,
+                |x, y| x + y,
+            )?,
+            ExprKind::Sub(x, y) => c(x)?.arith(
+                c(y)?,
+                |x, y| Some(x.wrapping_sub(y)),
+                |x, y| Some(x.wrapping_sub(y)),
+                |x, y| x - y,
+                |x, y| x - y,
+            )?,
+            ExprKind::Mul(x, y) => c(x)?.arith(
+                c(y)?,
+                |x, y| Some(x.wrapping_mul(y)),
+                |x, y| Some(x.wrapping_mul(y)),
+                |x, y| x * y,
+                |x, y| x * y,
+            )?,
+            ExprKind::Div(x, y) => c(x)?.arith(
+                c(y)?,
+                i32::checked_div,
+                i64::checked_div,
+                |x, y| x / y,
+                |x, y| x / y,
+            )?,
+            ExprKind::Mod(x, y) => c(x)?.arith(
+                c(y)?,
+                i32::checked_rem,
+                i64::checked_rem,
+                |x, y| x % y,
+                |x, y| x % y,
+            )?,
+            // Like the loop `Pow` lowers to, a negative exponent gives 1.
+            ExprKind::Pow(x, y) => c(x)?.arith(
+                c(y)?,
+                |x, y| Some(x.wrapping_pow(y.max(0) as u32)),
+                |x, y| Some(x.wrapping_pow(y.clamp(0, u32::MAX.into()) as u32)),
+                f32::powf,
+                f64::powf,
+            )?,
+            ExprKind::BitAnd(x, y) | ExprKind::And(x, y) => {
+                c(x)?.bits(c(y)?, |x, y| x & y, |x, y| x & y)?
+            }
+            ExprKind::BitOr(x, y) | ExprKind::Or(x, y) => {
+                c(x)?.bits(c(y)?, |x, y| x | y, |x, y| x | y)?
+            }
+            ExprKind::BitXor(x, y) => c(x)?.bits(c(y)?, |x, y| x ^ y, |x, y| x ^ y)?,
+            ExprKind::Eq(x, y) => c(x)?.compare(c(y)?, |o| o == Ordering::Equal)?,
+            ExprKind::Ne(x, y) => match c(x)?.compare(c(y)?, |o| o == Ordering::Equal)? {
+                Const::I32(b) => Const::I32(b ^ 1),
+                _ => return None,
+            },
+            ExprKind::Lt(x, y) => c(x)?.compare(c(y)?, |o| o == Ordering::Less)?,
+            ExprKind::Lte(x, y) => c(x)?.compare(c(y)?, |o| o != Ordering::Greater)?,
+            ExprKind::Gt(x, y) => c(x)?.compare(c(y)?, |o| o == Ordering::Greater)?,
+            ExprKind::Gte(x, y) => c(x)?.compare(c(y)?, |o| o != Ordering::Less)?,
+            _ => return None,
+        })
+    }
+
+    fn binary(&mut self, f: &mut Func, x: &Expr, y: &Expr, op: fn(&Ty) -> OperatorCode) {
+        let t = self.ty(f, x);
+        // References are compared by address, and released afterwards.
+        if counted(&t) {
+            let (a, b) = (f.ref_temp(), f.ref_temp());
+            self.expr(f, x);
+            self.replace(f, a);
+            self.expr(f, y);
+            self.replace(f, b);
+            f.codes.push(OperatorCode::GetLocal(a));
+            f.codes.push(OperatorCode::GetLocal(b));
+            f.codes.push(op(&t));
+            self.free(f, a);
+            self.free(f, b);
+            return;
+        }
+        self.expr(f, x);
+        self.expr(f, y);
+        f.codes.push(op(&t));
+    }
+
+    // Evaluates `x` for its side effects only.
+    fn stmt(&mut self, f: &mut Func, x: &Expr) {
+        self.expr(f, x);
+        let t = self.ty(f, x);
+        if counted(&t) {
+            self.release(f);
+        } else if value_type(&t).is_some() {
+            f.codes.push(OperatorCode::Drop);
+        }
+    }
+
+    // Evaluates `x` to a value of type `t`, which is nothing when `t` is `None`. Only values of
+    // branches that are not used are dropped, since the checker has made sure the rest agree.
+    fn value(&mut self, f: &mut Func, x: &Expr, t: Option<ValueType>) {
+        match t {
+            Some(_) => self.expr(f, x),
+            None => self.stmt(f, x),
+        }
+    }
+
+    // `if a { .. } else if b { .. } else { .. }` as nested `if`s.
+    fn branches(
+        &mut self,
+        f: &mut Func,
+        t: Option<ValueType>,
+        branches: &[&(Expr, Expr)],
+        els: Option<&Expr>,
+    ) {
+        let (cond, body) = branches[0];
+        self.expr(f, cond);
+        f.open(OperatorCode::If(BlockType::from(t.clone())));
+        self.value(f, body, t.clone());
+        if branches.len() > 1 || els.is_some() {
+            f.codes.push(OperatorCode::Else);
+            match els {
+                _ if branches.len() > 1 => self.branches(f, t, &branches[1..], els),
+                Some(els) => self.value(f, els, t),
+                None => {}
+            }
+        }
+        f.close();
+    }
+
+    // Integer powers by squaring, wrapping around like the other operators, with 1 for
+    // exponents below 1. Floats call `math::pow`, `f32`s by way of `f64`.
+    fn pow(&mut self, f: &mut Func, x: &Expr, y: &Expr) {
+        let (t, one, zero, mul, and, shr, le) = match self.ty(f, x) {
+            Ty::I64 => (
+                ValueType::I64,
+                OperatorCode::I64Const(1),
+                OperatorCode::I64Const(0),
+                OperatorCode::I64Mul,
+                OperatorCode::I64And,
+                OperatorCode::I64Shru,
+                OperatorCode::I64Les,
+            ),
+            Ty::I32 => (
+                ValueType::I32,
+                OperatorCode::I32Const(1),
+                OperatorCode::I32Const(0),
+                OperatorCode::I32Mul,
+                OperatorCode::I32And,
+                OperatorCode::I32Shru,
+                OperatorCode::I32Les,
+            ),
+            t => {
+                let promote = t == Ty::F32;
+                self.expr(f, x);
+                if promote {
+                    f.codes.push(OperatorCode::F64PromoteF32);
+                }
+                self.expr(f, y);
+                if promote {
+                    f.codes.push(OperatorCode::F64PromoteF32);
+                }
+                self.pow = true;
+                f.codes.push(OperatorCode::Call(self.helpers));
+                if promote {
+                    f.codes.push(OperatorCode::F32DemoteF64);
+                }
+                return;
+            }
+        };
+        let (base, exp, acc) = (f.temp(t.clone()), f.temp(t.clone()), f.temp(t));
+        self.expr(f, x);
+        f.codes.push(OperatorCode::SetLocal(base));
+        self.expr(f, y);
+        f.codes.push(OperatorCode::SetLocal(exp));
+        f.codes.push(one.clone());
+        f.codes.push(OperatorCode::SetLocal(acc));
+        let exit = f.open(OperatorCode::Block(BlockType::Empty));
+        let start = f.open(OperatorCode::Loop(BlockType::Empty));
+        f.codes.extend(vec![
+            OperatorCode::GetLocal(exp),
+            zero,
+            le,
+            OperatorCode::BrIf(f.br(exit)),
+            OperatorCode::GetLocal(exp),
+            one.clone(),
+            and,
+        ]);
+        if let OperatorCode::I64Const(_) = one {
+            f.codes.push(OperatorCode::I32WrapI64);
+        }
+        f.open(OperatorCode::If(BlockType::Empty));
+        f.codes.extend(vec![
+            OperatorCode::GetLocal(acc),
+            OperatorCode::GetLocal(base),
+            mul.clone(),
+            OperatorCode::SetLocal(acc),
+        ]);
+        f.close();
+        f.codes.extend(vec![
+            OperatorCode::GetLocal(exp),
+            one,
+            shr,
+            OperatorCode::SetLocal(exp),
+            OperatorCode::GetLocal(base),
+            OperatorCode::GetLocal(base),
+            mul,
+            OperatorCode::SetLocal(base),
+            OperatorCode::Br(f.br(start)),
+        ]);
+        f.close();
+        f.close();
+        f.codes.push(OperatorCode::GetLocal(acc));
+    }
+
+    // A `match` as a chain of tests, one block per arm that is left as soon as the pattern
+    // does not match:
+    //
+    //     block (result t)
+    //       block  <test arm 0, br_if 0 on failure> <body> br 1  end
+    //       block  <test arm 1, ...> end
+    //       unreachable
+    //     end
+    fn match_(&mut self, f: &mut Func, x: &Expr, scrutinee: &Expr, arms: &[(Pattern, Expr)]) {
+        let t = value_type(&self.ty(f, x));
+        let st = self.ty(f, scrutinee);
+        self.expr(f, scrutinee);
+        let local = value_type(&st).map(|vt| {
+            if counted(&st) {
+                let local = f.ref_temp();
+                self.replace(f, local);
+                local
+            } else {
+                let local = f.temp(vt);
+                f.codes.push(OperatorCode::SetLocal(local));
+                local
+            }
+        });
+        let exit = f.open(OperatorCode::Block(BlockType::from(t.clone())));
+        let mut rest = arms;
+        if let (Some(local), Some((cases, min, targets))) = (local, self.jump_table(&st, arms)) {
+            self.jump(
+                f,
+                &st,
+                local,
+                min,
+                &targets,
+                &arms[..cases],
+                exit,
+                t.clone(),
+            );
+            rest = &arms[cases..];
+        }
+        for (p, body) in rest {
+            let arm = f.open(OperatorCode::Block(BlockType::Empty));
+            self.pattern(f, p, local, arm);
+            self.value(f, body, t.clone());
+            f.codes.push(OperatorCode::Br(f.br(exit)));
+            f.close();
+        }
+        // Matches are exhaustive.
+        f.codes.push(OperatorCode::Unreachable);
+        f.close();
+        if let (Some(local), true) = (local, counted(&st)) {
+            self.free(f, local);
+        }
+    }
+
+    // The number of the first arms of a `match` that test integer literals, the smallest of them
+    // and the arm for each value from it on, `arms.len()` for the values they miss, if they are
+    // dense enough for a jump table.
+    fn jump_table(&self, st: &Ty, arms: &[(Pattern, Expr)]) -> Option<(usize, i64, Vec<usize>)> {
+        if !matches!(st, Ty::I32 | Ty::I64 | Ty::Char) {
+            return None;
+        }
+        let values = arms
+            .iter()
+            .map_while(|(p, _)| match p {
+                Pattern::Literal(x) => match self.constant(x)? {
+                    Const::I32(x) => Some(i64::from(x)),
+                    Const::I64(x) => Some(x),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let (min, max) = (*values.iter().min()?, *values.iter().max()?);
+        let span = i128::from(max) - i128::from(min) + 1;
+        let cases = values.iter().collect::<HashSet<_>>().len();
+        if cases < TABLE_MIN_CASES || span > 2 * cases as i128 {
+            return None;
+        }
+        let mut targets = vec![arms.len(); span as usize];
+        for (arm, x) in values.iter().enumerate().rev() {
+            targets[(x - min) as usize] = arm;
+        }
+        Some((values.len(), min, targets))
+    }
+
+    // The arms of a jump table, each body following the block the `br_table` leaves for it:
+    //
+    //     block            ;; the remaining arms
+    //       block          ;; arms[n - 1]
+    //         ...
+    //           block      ;; arms[0]
+    //             <value - min> br_table
+    //           end <arms[0]> br exit
+    //         ...
+    //       end <arms[n - 1]> br exit
+    //     end
+    #[allow(clippy::too_many_arguments)]
+    fn jump(
+        &mut self,
+        f: &mut Func,
+        st: &Ty,
+        local: usize,
+        min: i64,
+        targets: &[usize],
+        arms: &[(Pattern, Expr)],
+        exit: usize,
+        t: Option<ValueType>,
+    ) {
+        let rest = f.open(OperatorCode::Block(BlockType::Empty));
+        let mut blocks = arms
+            .iter()
+            .map(|_| f.open(OperatorCode::Block(BlockType::Empty)))
+            .collect::<Vec<_>>();
+        blocks.reverse();
+        f.codes.push(OperatorCode::GetLocal(local));
+        if *st == Ty::I64 {
+            // Values beyond the table go to the remaining arms before they are cut to `i32`.
+            let index = f.temp(ValueType::I64);
+            let span = targets.len() as i64;
+            f.codes.extend(vec![
+                OperatorCode::I64Const(min),
+                OperatorCode::I64Sub,
+                OperatorCode::TeeLocal(index),
+                OperatorCode::I32WrapI64,
+                OperatorCode::I32Const(span as i32),
+                OperatorCode::GetLocal(index),
+                OperatorCode::I64Const(span),
+                OperatorCode::I64Ltu,
+                OperatorCode::Select,
+            ]);
+        } else {
+            f.codes.push(OperatorCode::I32Const(min as i32));
+            f.codes.push(OperatorCode::I32Sub);
+        }
+        let depth = |f: &Func, arm: usize| f.br(*blocks.get(arm).unwrap_or(&rest));
+        f.codes.push(OperatorCode::BrTable {
+            index: depth(f, arms.len()),
+            params: targets.iter().map(|&arm| depth(f, arm)).collect(),
+        });
+        for (_, body) in arms {
+            f.close();
+            self.value(f, body, t.clone());
+            f.codes.push(OperatorCode::Br(f.br(exit)));
+        }
+        f.close();
+    }
+
+    // Tests the value in `local` against `p`, binding its variables, and leaves the block at
+    // `fail` if it does not match.
+    fn pattern(&mut self, f: &mut Func, p: &Pattern, local: Option<usize>, fail: usize) {
+        match p {
+            Pattern::Wildcard => {}
+            Pattern::Binding(name) => {
+                f.names.push((f.next, name.clone()));
+                if let Some(local) = local {
+                    f.codes.push(OperatorCode::GetLocal(local));
+                    if counted(&f.locals[f.next]) {
+                        self.retain(f);
+                    }
+                    self.set_local(f, f.next);
+                }
+                f.next += 1;
+            }
+            Pattern::Literal(x) => {
+                let eq = match self.ty(f, x) {
+                    Ty::I64 => OperatorCode::I64Eq,
+                    Ty::F32 => OperatorCode::F32Eq,
+                    Ty::F64 => OperatorCode::F64Eq,
+                    _ => OperatorCode::I32Eq,
+                };
+                f.codes.extend(local.map(OperatorCode::GetLocal));
+                self.expr(f, x);
+                f.codes.push(eq);
+                f.codes.push(OperatorCode::I32Eqz);
+                f.codes.push(OperatorCode::BrIf(f.br(fail)));
+            }
+            // The index of the variant first, then the fields, each loaded into a temporary
+            // and tested like a scrutinee of its own.
+            Pattern::Variant(enum_, variant, ps) => {
+                let (index, fields) = match self.variant(enum_, variant) {
+                    Some((index, layout)) => (index, layout.fields[1..].to_vec()),
+                    None => {
+                        f.codes.push(OperatorCode::Unreachable);
+                        return;
+                    }
+                };
+                let local = match local {
+                    Some(local) => local,
+                    None => return,
+                };
+                f.codes.extend(vec![
+                    OperatorCode::GetLocal(local),
+                    OperatorCode::I32Load(memory_immediate(4, 0)),
+                    OperatorCode::I32Const(index as i32),
+                    OperatorCode::I32Ne,
+                    OperatorCode::BrIf(f.br(fail)),
+                ]);
+                for (p, (_, t, offset)) in ps.iter().zip(fields) {
+                    let field = match (p, scalar(&t)) {
+                        (Pattern::Wildcard, _) | (_, None) => None,
+                        (_, Some((size, load, _))) => {
+                            let field = f.temp(value_type(&t).unwrap());
+                            f.codes.push(OperatorCode::GetLocal(local));
+                            f.codes.push(load(memory_immediate(size, offset)));
+                            f.codes.push(OperatorCode::SetLocal(field));
+                            Some(field)
+                        }
+                    };
+                    self.pattern(f, p, field, fail);
+                }
+            }
+        }
+    }
+
+    // Stores the value on the stack in `local`. Locals of type `()` are never read, so there is
+    // nothing to store.
+    fn set_local(&self, f: &mut Func, local: usize) {
+        let t = &f.locals[local];
+        if counted(t) {
+            self.replace(f, local);
+        } else if value_type(t).is_some() {
+            f.codes.push(OperatorCode::SetLocal(local));
+        }
+    }
+
+    // `base.name = value`, or `base.name op= value` with `op`. The old value of a reference field
+    // is released only once `value` has been evaluated, as `value` may still read it.
+    fn set_member(
+        &mut self,
+        f: &mut Func,
+        base: &Expr,
+        name: &str,
+        op: Option<AssignOp>,
+        value: &Expr,
+    ) {
+        let member = self.member(f, base, name);
+        let ptr = f.ref_temp();
+        self.expr(f, base);
+        self.replace(f, ptr);
+        match member.and_then(|(t, offset)| Some((scalar(&t)?, offset, t))) {
+            Some(((size, load, store), offset, t)) => {
+                f.codes.push(OperatorCode::GetLocal(ptr));
+                if op.is_some() {
+                    f.codes.push(OperatorCode::GetLocal(ptr));
+                    f.codes.push(load(memory_immediate(size, offset)));
+                }
+                self.expr(f, value);
+                if let Some(op) = op {
+                    f.codes.push(arith(op, &t));
+                }
+                if counted(&t) {
+                    f.codes.push(OperatorCode::GetLocal(ptr));
+                    f.codes.push(load(memory_immediate(size, offset)));
+                    self.release(f);
+                }
+                f.codes.push(store(memory_immediate(size, offset)));
+            }
+            None => self.stmt(f, value),
+        }
+        self.free(f, ptr);
+    }
+
+    // Evaluates `base[index]` up to the address of the element, which is left in a temporary,
+    // and traps if the index is out of bounds. With `borrow`, a variable is read in place;
+    // otherwise the array is held in a temporary until it is freed.
+    fn element(
+        &mut self,
+        f: &mut Func,
+        base: &Expr,
+        index: &Expr,
+        borrow: bool,
+    ) -> (usize, Option<usize>) {
+        let size = match self.ty(f, base) {
+            Ty::Array(t) => scalar(&t).map_or(0, |x| x.0),
+            _ => 0,
+        };
+        let (get, owned) = match &base.kind {
+            ExprKind::Resolved(_, Resolution::Local(i)) if borrow => {
+                (OperatorCode::GetLocal(*i), None)
+            }
+            ExprKind::Resolved(_, Resolution::Global(i)) if borrow => {
+                (OperatorCode::GetGlobal(*i), None)
+            }
+            _ => {
+                let ptr = f.ref_temp();
+                self.expr(f, base);
+                self.replace(f, ptr);
+                (OperatorCode::GetLocal(ptr), Some(ptr))
+            }
+        };
+        let addr = f.temp(ValueType::I32);
+        self.expr(f, index);
+        // Negative indices are out of bounds as unsigned numbers.
+        f.codes.extend(vec![
+            OperatorCode::SetLocal(addr),
+            OperatorCode::GetLocal(addr),
+            get.clone(),
+            OperatorCode::I32Load(memory_immediate(4, 0)),
+            OperatorCode::I32Geu,
+        ]);
+        self.trap_if(f);
+        f.codes.extend(vec![
+            get,
+            OperatorCode::GetLocal(addr),
+            OperatorCode::I32Const(size as i32),
+            OperatorCode::I32Mul,
+            OperatorCode::I32Add,
+            OperatorCode::SetLocal(addr),
+        ]);
+        (addr, owned)
+    }
+
+    // `base[index] = value`, or `op=` like `set_member`. The old element is released only once
+    // `value` has been evaluated.
+    fn set_index(
+        &mut self,
+        f: &mut Func,
+        base: &Expr,
+        index: &Expr,
+        op: Option<AssignOp>,
+        value: &Expr,
+    ) {
+        let t = match self.ty(f, base) {
+            Ty::Array(t) => *t,
+            _ => Ty::Unknown,
+        };
+        let (addr, owned) = self.element(f, base, index, false);
+        match scalar(&t) {
+            Some((size, load, store)) => {
+                f.codes.push(OperatorCode::GetLocal(addr));
+                if op.is_some() {
+                    f.codes.push(OperatorCode::GetLocal(addr));
+                    f.codes.push(load(memory_immediate(size, ARRAY_DATA)));
+                }
+                self.expr(f, value);
+                if let Some(op) = op {
+                    f.codes.push(arith(op, &t));
+                }
+                if counted(&t) {
+                    f.codes.push(OperatorCode::GetLocal(addr));
+                    f.codes.push(load(memory_immediate(size, ARRAY_DATA)));
+                    self.release(f);
+                }
+                f.codes.push(store(memory_immediate(size, ARRAY_DATA)));
+            }
+            None => self.stmt(f, value),
+        }
+        if let Some(ptr) = owned {
+            self.free(f, ptr);
+        }
+    }
+
+    // `[T; n]`, with the elements zeroed as freed blocks are reused. Lengths that are negative or
+    // too large for the memory trap.
+    fn array(&mut self, f: &mut Func, t: &Type, len: &Expr) {
+        let (tag, size) = self.array_tag(&Ty::from_type(t));
+        let max = (i32::MAX as u32 - 64 - ARRAY_DATA) / size.max(1);
+        let (n, ptr) = (f.temp(ValueType::I32), f.temp(ValueType::I32));
+        self.expr(f, len);
+        f.codes.extend(vec![
+            OperatorCode::TeeLocal(n),
+            OperatorCode::I32Const(max as i32),
+            OperatorCode::I32Gtu,
+        ]);
+        self.trap_if(f);
+        f.codes.extend(vec![
+            OperatorCode::GetLocal(n),
+            OperatorCode::I32Const(size as i32),
+            OperatorCode::I32Mul,
+            OperatorCode::I32Const(ARRAY_DATA as i32),
+            OperatorCode::I32Add,
+            OperatorCode::I32Const(tag as i32),
+            OperatorCode::Call(self.runtime.alloc),
+            OperatorCode::TeeLocal(ptr),
+            OperatorCode::GetLocal(n),
+            OperatorCode::I32Store(memory_immediate(4, 0)),
+            OperatorCode::GetLocal(ptr),
+            OperatorCode::I32Const(ARRAY_DATA as i32),
+            OperatorCode::I32Add,
+            OperatorCode::I32Const(0),
+            OperatorCode::GetLocal(n),
+            OperatorCode::I32Const(size as i32),
+            OperatorCode::I32Mul,
+            OperatorCode::MemoryFill,
+            OperatorCode::GetLocal(ptr),
+        ]);
+    }
+
+    // Stores the fields of the new object at `ptr`, evaluated in the order they are written.
+    fn init<'e>(
+        &mut self,
+        f: &mut Func,
+        ptr: usize,
+        fields: impl Iterator<Item = (&'e Expr, Option<(Ty, u32)>)>,
+    ) {
+        for (x, field) in fields {
+            match field.and_then(|(t, offset)| Some((scalar(&t)?, offset))) {
+                Some(((size, _, store), offset)) => {
+                    f.codes.push(OperatorCode::GetLocal(ptr));
+                    self.expr(f, x);
+                    f.codes.push(store(memory_immediate(size, offset)));
+                }
+                None => self.stmt(f, x),
+            }
+        }
+    }
+
+    // `Enum.Variant(args)`, or `Enum.Variant` alone for a variant without fields. The others are
+    // not functions, so they can only be called.
+    fn construct(
+        &mut self,
+        f: &mut Func,
+        x: &Expr,
+        base: &Expr,
+        name: &str,
+        args: Option<&[Expr]>,
+    ) {
+        let enum_ = enum_name(base).unwrap_or_default();
+        let (index, fields) = match self.variant(enum_, name) {
+            Some((index, layout)) => (index, layout.fields[1..].to_vec()),
+            None => {
+                f.codes.push(OperatorCode::Unreachable);
+                return;
+            }
+        };
+        let args = match args {
+            Some(args) => args,
+            None if fields.is_empty() => &[],
+            None => {
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        format!(
+                            "cannot use `{}.{}` as a value: it must be called with its fields",
+                            enum_, name
+                        ),
+                        x.span,
+                    )
+                    .with_code(Code::InvalidCall),
+                );
+                f.codes.push(OperatorCode::Unreachable);
+                return;
+            }
+        };
+        let ptr = f.temp(ValueType::I32);
+        let (_, layout) = self.variant(enum_, name).unwrap();
+        self.alloc(f, layout);
+        f.codes.extend(vec![
+            OperatorCode::TeeLocal(ptr),
+            OperatorCode::I32Const(index as i32),
+            OperatorCode::I32Store(memory_immediate(4, 0)),
+        ]);
+        let fields = fields.into_iter().map(|(_, t, offset)| Some((t, offset)));
+        self.init(f, ptr, args.iter().zip(fields));
+        f.codes.push(OperatorCode::GetLocal(ptr));
+    }
+
+    // Functions named directly are called with `call`, and any other function value through the
+    // table. The function is evaluated before the arguments.
+    fn call_indirect(&mut self, f: &mut Func, x: &Expr, g: &Expr, args: &[Expr]) {
+        let (params, ret) = match self.ty(f, g) {
+            Ty::Func(params, ret) if params.len() == args.len() => (params, ret),
+            _ => {
+                self.stmt(f, g);
+                f.codes.push(OperatorCode::Unreachable);
+                return;
+            }
+        };
+        let slot = f.temp(ValueType::I32);
+        self.expr(f, g);
+        f.codes.push(OperatorCode::SetLocal(slot));
+        for x in args {
+            self.expr(f, x);
+        }
+        f.codes.push(OperatorCode::GetLocal(slot));
+        let t = self.signature_type(&params, &ret);
+        f.codes.push(OperatorCode::CallIndirect(t));
+        if self.ty(f, x) == Ty::Never {
+            f.codes.push(OperatorCode::Unreachable);
+        }
+    }
+
+    fn call(&mut self, f: &mut Func, x: &Expr, g: &Expr, args: &[Expr]) {
+        let i = match &g.kind {
+            ExprKind::Resolved(_, Resolution::Func(i)) => *i,
+            ExprKind::Member(base, name) if enum_name(base).is_some() => {
+                return self.construct(f, x, base, name, Some(args))
+            }
+            _ => return self.call_indirect(f, x, g, args),
+        };
+        let FuncDef(name, _, params, _) = self.funcs[i];
+        if params.len() != args.len() {
+            self.diagnostics.push(
+                Diagnostic::new(
+                    format!(
+                        "cannot call `{}`: it takes {} but {} supplied",
+                        name,
+                        plural(params.len(), "argument"),
+                        plural(args.len(), "argument"),
+                    ),
+                    x.span,
+                )
+                .with_code(Code::InvalidCall),
+            );
+            f.codes.push(OperatorCode::Unreachable);
+            return;
+        }
+        // Functions of the module release their parameters themselves, but references passed to
+        // the host are only borrowed and released after the call.
+        let host = self.indices[i] < self.imports;
+        let mut borrowed = Vec::new();
+        for x in args {
+            self.expr(f, x);
+            if host && counted(&self.ty(f, x)) {
+                let local = f.ref_temp();
+                f.codes.push(OperatorCode::TeeLocal(local));
+                borrowed.push(local);
+            }
+        }
+        f.codes.push(OperatorCode::Call(self.indices[i]));
+        for local in borrowed {
+            self.free(f, local);
+        }
+        // A call that does not return leaves nothing on the stack for what follows.
+        if self.ty(f, x) == Ty::Never {
+            f.codes.push(OperatorCode::Unreachable);
+        }
+    }
+
+    // Instructions are attributed to the innermost expression they are generated for.
+    fn expr(&mut self, f: &mut Func, x: &Expr) {
+        let parent = f.span.replace(x.span);
+        f.mark(x.span);
+        self.expr_kind(f, x);
+        if let Some(span) = parent {
+            f.mark(span);
+        }
+        f.span = parent;
+    }
+
+    fn expr_kind(&mut self, f: &mut Func, x: &Expr) {
+        match &x.kind {
+            ExprKind::I32Literal(n) => f.codes.push(OperatorCode::I32Const(*n)),
+            ExprKind::I64Literal(n) => f.codes.push(OperatorCode::I64Const(*n)),
+            ExprKind::F32Literal(n) => f.codes.push(OperatorCode::F32Const(*n)),
+            ExprKind::F64Literal(n) => f.codes.push(OperatorCode::F64Const(*n)),
+            ExprKind::BoolLiteral(b) => f.codes.push(OperatorCode::I32Const(*b as i32)),
+            ExprKind::CharLiteral(c) => f.codes.push(OperatorCode::I32Const(*c as i32)),
+            ExprKind::StringLiteral(s) => {
+                let addr = self.string(s);
+                f.codes.push(OperatorCode::I32Const(addr as i32));
+            }
+            ExprKind::Resolved(_, Resolution::Local(i)) => {
+                f.codes.push(OperatorCode::GetLocal(*i));
+                if counted(&f.locals[*i]) {
+                    self.retain(f);
+                }
+            }
+            ExprKind::Resolved(_, Resolution::Global(i)) => {
+                f.codes.push(OperatorCode::GetGlobal(*i));
+                if counted(&Ty::from_type(self.globals[*i].1)) {
+                    self.retain(f);
+                }
+            }
+            // Calls through the table hand references over, which the host would not release.
+            ExprKind::Resolved(_, Resolution::Func(i))
+                if self.indices[*i] < self.imports
+                    && signature(self.funcs[*i]).0.iter().any(counted) =>
+            {
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        format!(
+                            "cannot use `{}` as a value: it takes references from the host",
+                            self.funcs[*i].0
+                        ),
+                        x.span,
+                    )
+                    .with_code(Code::InvalidCall),
+                );
+                f.codes.push(OperatorCode::Unreachable);
+            }
+            ExprKind::Resolved(_, Resolution::Func(i)) => {
+                let slot = self.table_slot(*i);
+                f.codes.push(OperatorCode::I32Const(slot as i32));
+            }
+            // Lambdas would need their captures stored along with the function.
+            ExprKind::Lambda(..) => {
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        "cannot compile a lambda: only named functions can be values".to_string(),
+                        x.span,
+                    )
+                    .with_code(Code::InvalidCall),
+                );
+                f.codes.push(OperatorCode::Unreachable);
+            }
+            ExprKind::Let(name, _, _, init) => {
+                self.expr(f, init);
+                f.names.push((f.next, name.clone()));
+                self.set_local(f, f.next);
+                f.next += 1;
+            }
+            ExprKind::Set(place, value) => match &place.kind {
+                ExprKind::Member(base, name) => self.set_member(f, base, name, None, value),
+                ExprKind::Index(base, index) => self.set_index(f, base, index, None, value),
+                ExprKind::Resolved(_, Resolution::Local(i)) => {
+                    self.expr(f, value);
+                    self.set_local(f, *i);
+                }
+                ExprKind::Resolved(_, Resolution::Global(i)) => {
+                    self.expr(f, value);
+                    let t = Ty::from_type(self.globals[*i].1);
+                    if counted(&t) {
+                        f.codes.push(OperatorCode::GetGlobal(*i));
+                        self.release(f);
+                    }
+                    if value_type(&t).is_some() {
+                        f.codes.push(OperatorCode::SetGlobal(*i));
+                    }
+                }
+                _ => {
+                    self.expr(f, value);
+                    f.codes.push(OperatorCode::Unreachable);
+                }
+            },
+            // Numbers only, so there is nothing to retain or release.
+            ExprKind::CompoundSet(op, place, value) => match &place.kind {
+                ExprKind::Member(base, name) => self.set_member(f, base, name, Some(*op), value),
+                ExprKind::Index(base, index) => self.set_index(f, base, index, Some(*op), value),
+                ExprKind::Resolved(_, Resolution::Local(i)) => {
+                    f.codes.push(OperatorCode::GetLocal(*i));
+                    self.expr(f, value);
+                    f.codes.push(arith(*op, &f.locals[*i]));
+                    f.codes.push(OperatorCode::SetLocal(*i));
+                }
+                ExprKind::Resolved(_, Resolution::Global(i)) => {
+                    f.codes.push(OperatorCode::GetGlobal(*i));
+                    self.expr(f, value);
+                    f.codes.push(arith(*op, &Ty::from_type(self.globals[*i].1)));
+                    f.codes.push(OperatorCode::SetGlobal(*i));
+                }
+                _ => {
+                    self.expr(f, value);
+                    f.codes.push(OperatorCode::Unreachable);
+                }
+            },
+            ExprKind::StructLiteral(name, fields) => {
+                let ptr = f.temp(ValueType::I32);
+                let layout = &self.structs[name.as_str()];
+                let offsets = fields
+                    .iter()
+                    .map(|(name, _)| layout.field(name).map(|x| (x.1.clone(), x.2)))
+                    .collect::<Vec<_>>();
+                self.alloc(f, layout);
+                f.codes.push(OperatorCode::SetLocal(ptr));
+                self.init(f, ptr, fields.iter().map(|x| &x.1).zip(offsets));
+                f.codes.push(OperatorCode::GetLocal(ptr));
+            }
+            ExprKind::ArrayLiteral(t, len) => self.array(f, t, len),
+            ExprKind::Index(base, index) => {
+                let t = self.ty(f, x);
+                let (addr, owned) = self.element(f, base, index, true);
+                if let Some((size, load, _)) = scalar(&t) {
+                    f.codes.push(OperatorCode::GetLocal(addr));
+                    f.codes.push(load(memory_immediate(size, ARRAY_DATA)));
+                    if counted(&t) {
+                        self.retain(f);
+                    }
+                }
+                if let Some(ptr) = owned {
+                    self.free(f, ptr);
+                }
+            }
+            ExprKind::Member(base, name) if enum_name(base).is_some() => {
+                self.construct(f, x, base, name, None)
+            }
+            ExprKind::Member(base, name) => {
+                let member = self.member(f, base, name);
+                // Fields of variables are read without retaining the struct.
+                let owned = match &base.kind {
+                    ExprKind::Resolved(_, Resolution::Local(i)) => {
+                        f.codes.push(OperatorCode::GetLocal(*i));
+                        None
+                    }
+                    ExprKind::Resolved(_, Resolution::Global(i)) => {
+                        f.codes.push(OperatorCode::GetGlobal(*i));
+                        None
+                    }
+                    _ => {
+                        let ptr = f.ref_temp();
+                        self.expr(f, base);
+                        self.replace(f, ptr);
+                        f.codes.push(OperatorCode::GetLocal(ptr));
+                        Some(ptr)
+                    }
+                };
+                match member.and_then(|(t, offset)| Some((scalar(&t)?, offset, counted(&t)))) {
+                    Some(((size, load, _), offset, counted)) => {
+                        f.codes.push(load(memory_immediate(size, offset)));
+                        if counted {
+                            self.retain(f);
+                        }
+                    }
+                    None => f.codes.push(OperatorCode::Drop),
+                }
+                if let Some(ptr) = owned {
+                    self.free(f, ptr);
+                }
+            }
+            ExprKind::If(first, elifs, els) => {
+                let t = value_type(&self.ty(f, x));
+                self.branches(
+                    f,
+                    t,
+                    &std::iter::once(&**first).chain(elifs).collect::<Vec<_>>(),
+                    els.as_ref().as_ref(),
+                );
+            }
+            ExprKind::While(cond, body) => {
+                let exit = f.open(OperatorCode::Block(BlockType::Empty));
+                let start = f.open(OperatorCode::Loop(BlockType::Empty));
+                self.expr(f, cond);
+                f.codes.push(OperatorCode::I32Eqz);
+                f.codes.push(OperatorCode::BrIf(f.br(exit)));
+                f.loops.push((exit, start));
+                self.stmt(f, body);
+                f.loops.pop();
+                f.codes.push(OperatorCode::Br(f.br(start)));
+                f.close();
+                f.close();
+            }
+            ExprKind::Break => {
+                let (exit, _) = *f.loops.last().unwrap();
+                f.codes.push(OperatorCode::Br(f.br(exit)));
+            }
+            ExprKind::Continue => {
+                let (_, start) = *f.loops.last().unwrap();
+                f.codes.push(OperatorCode::Br(f.br(start)));
+            }
+            // Modules that skipped `desugar_module` still compile.
+            ExprKind::For(..) => {
+                let x = desugar_expr(x.clone());
+                self.expr(f, &x);
+            }
+            // `x && y` is `if x { y } else { false }` and `x || y` is `if x { true } else { y }`.
+            ExprKind::And(x, y) => {
+                self.expr(f, x);
+                f.open(OperatorCode::If(BlockType::Value(ValueType::I32)));
+                self.expr(f, y);
+                f.codes.push(OperatorCode::Else);
+                f.codes.push(OperatorCode::I32Const(0));
+                f.close();
+            }
+            ExprKind::Or(x, y) => {
+                self.expr(f, x);
+                f.open(OperatorCode::If(BlockType::Value(ValueType::I32)));
+                f.codes.push(OperatorCode::I32Const(1));
+                f.codes.push(OperatorCode::Else);
+                self.expr(f, y);
+                f.close();
+            }
+            ExprKind::Cast(y, _) => {
+                let from = self.ty(f, y);
+                let to = self.ty(f, x);
+                self.expr(f, y);
+                let op = match (value_type(&from), value_type(&to)) {
+                    // `char` and `bool` are unsigned.
+                    (Some(ValueType::I32), Some(ValueType::I64))
+                        if matches!(from, Ty::Char | Ty::Bool) =>
+                    {
+                        Some(OperatorCode::I64ExtenduI32)
+                    }
+                    (Some(from), Some(to)) => OperatorCode::conversion(&from, &to),
+                    _ => None,
+                };
+                f.codes.extend(op);
+            }
+            ExprKind::Match(scrutinee, arms) => self.match_(f, x, scrutinee, arms),
+            ExprKind::Call(g, args) => self.call(f, x, g, args),
+            ExprKind::Block(stmts, tail) => {
+                for x in stmts {
+                    self.stmt(f, x);
+                }
+                if let Some(x) = &**tail {
+                    self.expr(f, x);
+                }
+            }
+            ExprKind::Return(x) => {
+                let t = match &**x {
+                    Some(x) => {
+                        self.expr(f, x);
+                        value_type(&self.ty(f, x))
+                    }
+                    None => None,
+                };
+                self.exit(f, t);
+                f.codes.push(OperatorCode::Return);
+            }
+            ExprKind::Plus(x) => self.expr(f, x),
+            ExprKind::Minus(x) => match self.ty(f, x) {
+                Ty::F32 => {
+                    self.expr(f, x);
+                    f.codes.push(OperatorCode::F32Neg);
+                }
+                Ty::F64 => {
+                    self.expr(f, x);
+                    f.codes.push(OperatorCode::F64Neg);
+                }
+                // Integers are subtracted from zero.
+                Ty::I64 => {
+                    f.codes.push(OperatorCode::I64Const(0));
+                    self.expr(f, x);
+                    f.codes.push(OperatorCode::I64Sub);
+                }
+                _ => {
+                    f.codes.push(OperatorCode::I32Const(0));
+                    self.expr(f, x);
+                    f.codes.push(OperatorCode::I32Sub);
+                }
+            },
+            ExprKind::Not(x) => {
+                let t = self.ty(f, x);
+                self.expr(f, x);
+                f.codes.extend(match t {
+                    Ty::Bool => vec![OperatorCode::I32Eqz],
+                    Ty::I64 => vec![OperatorCode::I64Const(-1), OperatorCode::I64Xor],
+                    _ => vec![OperatorCode::I32Const(-1), OperatorCode::I32Xor],
+                });
+            }
+            ExprKind::Add(x, y) => self.binary(f, x, y, |t| arith(AssignOp::Add, t)),
+            ExprKind::Sub(x, y) => self.binary(f, x, y, |t| arith(AssignOp::Sub, t)),
+            ExprKind::Mul(x, y) => self.binary(f, x, y, |t| arith(AssignOp::Mul, t)),
+            ExprKind::Div(x, y) => self.binary(f, x, y, |t| arith(AssignOp::Div, t)),
+            ExprKind::Mod(x, y) => self.binary(f, x, y, |t| arith(AssignOp::Mod, t)),
+            ExprKind::BitAnd(x, y) => self.binary(f, x, y, |t| match t {
+                Ty::I64 => OperatorCode::I64And,
+                _ => OperatorCode::I32And,
+            }),
+            ExprKind::BitOr(x, y) => self.binary(f, x, y, |t| match t {
+                Ty::I64 => OperatorCode::I64Or,
+                _ => OperatorCode::I32Or,
+            }),
+            ExprKind::BitXor(x, y) => self.binary(f, x, y, |t| match t {
+                Ty::I64 => OperatorCode::I64Xor,
+                _ => OperatorCode::I32Xor,
+            }),
+            ExprKind::Eq(x, y) => self.binary(f, x, y, |t| match t {
+                Ty::I64 => OperatorCode::I64Eq,
+                Ty::F32 => OperatorCode::F32Eq,
+                Ty::F64 => OperatorCode::F64Eq,
+                _ => OperatorCode::I32Eq,
+            }),
+            ExprKind::Ne(x, y) => self.binary(f, x, y, |t| match t {
+                Ty::I64 => OperatorCode::I64Ne,
+                Ty::F32 => OperatorCode::F32Ne,
+                Ty::F64 => OperatorCode::F64Ne,
+                _ => OperatorCode::I32Ne,
+            }),
+            // `char`s are compared as the unsigned code points they are.
+            ExprKind::Lt(x, y) => self.binary(f, x, y, |t| match t {
+                Ty::I64 => OperatorCode::I64Lts,
+                Ty::F32 => OperatorCode::F32Lt,
+                Ty::F64 => OperatorCode::F64Lt,
+                Ty::Char => OperatorCode::I32Ltu,
+                _ => OperatorCode::I32Lts,
+            }),
+            ExprKind::Lte(x, y) => self.binary(f, x, y, |t| match t {
+                Ty::I64 => OperatorCode::I64Les,
+                Ty::F32 => OperatorCode::F32Le,
+                Ty::F64 => OperatorCode::F64Le,
+                Ty::Char => OperatorCode::I32Leu,
+                _ => OperatorCode::I32Les,
+            }),
+            ExprKind::Gt(x, y) => self.binary(f, x, y, |t| match t {
+                Ty::I64 => OperatorCode::I64Gts,
+                Ty::F32 => OperatorCode::F32Gt,
+                Ty::F64 => OperatorCode::F64Gt,
+                Ty::Char => OperatorCode::I32Gtu,
+                _ => OperatorCode::I32Gts,
+            }),
+            ExprKind::Pow(x, y) => self.pow(f, x, y),
+            ExprKind::Gte(x, y) => self.binary(f, x, y, |t| match t {
+                Ty::I64 => OperatorCode::I64Ges,
+                Ty::F32 => OperatorCode::F32Ge,
+                Ty::F64 => OperatorCode::F64Ge,
+                Ty::Char => OperatorCode::I32Geu,
+                _ => OperatorCode::I32Ges,
+            }),
+            // Ranges and bare enums are rejected by the checker.
+            _ => f.codes.push(OperatorCode::Unreachable),
+        }
+    }
+
+    fn func(&mut self, def: &FuncDef, body: &Expr, locals: &[Ty]) -> FunctionBody {
+        let mut f = Func::new(locals, def.2.len());
+        f.names = def.2.iter().map(|x| x.0.clone()).enumerate().collect();
+        self.expr(&mut f, body);
+        let (_, ret) = signature(def);
+        let t = self.ty(&f, body);
+        match (value_type(&ret), value_type(&t)) {
+            // Every path ends in a `return`, but wasm still wants a value at the end.
+            (Some(_), None) => f.codes.push(OperatorCode::Unreachable),
+            (None, Some(_)) => {
+                if counted(&t) {
+                    self.release(&mut f);
+                } else {
+                    f.codes.push(OperatorCode::Drop);
+                }
+                self.exit(&mut f, None);
+            }
+            (ret, _) => self.exit(&mut f, ret),
+        }
+
+        let mut entries: Vec<LocalEntry> = Vec::new();
+        let declared = locals[def.2.len()..]
+            .iter()
+            .map(|t| value_type(t).unwrap_or(ValueType::I32));
+        for typ in declared.chain(f.temps) {
+            match entries.last_mut() {
+                Some(last) if last.typ == typ => last.count += 1,
+                _ => entries.push(LocalEntry { count: 1, typ }),
+            }
+        }
+        f.names.sort_by_key(|x| x.0);
+        self.names.push(f.names);
+        self.spans.push(f.spans);
+        FunctionBody {
+            locals: entries,
+            codes: f.codes,
+        }
+    }
+}
+
+// How the host enters the module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Entry {
+    // `main` is exported as `"main"` for the host to call.
+    #[default]
+    Export,
+    // `main` is the start function, which runs when the module is instantiated. It must take no
+    // parameters and return nothing.
+    Start,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub entry: Entry,
+    // Whether to emit the name section, so that debuggers and stack traces show the names of
+    // functions and locals.
+    pub names: bool,
+    // The URL of the source map of the module, made with `source_map`.
+    pub source_map: Option<String>,
+    // Whether to define the builtins `wasi::with_builtins` declares with WASI.
+    pub wasi: bool,
+}
+
+// Generates a module from a checked module, normally the output of `desugar_module`, and the
+// local types `check_module` computed for it. Public functions and the memory are exported, and
+// `main` is the entry. Also returns the spans the instructions of each function body come from,
+// for `source_map`.
+pub fn codegen_module(
+    module: &Module,
+    types: &Types,
+    options: &Options,
+) -> (WasmASTRoot, CodeSpans, Vec<Diagnostic>) {
+    let funcs = module
+        .iter()
+        .filter(|x| matches!(x.kind, MemberKind::Func(..) | MemberKind::ExternFun(..)))
+        .collect::<Vec<_>>();
+    // With WASI, builtins are defined after the runtime instead of imported, and `fd_write` is
+    // imported after the other imports.
+    let builtin = |x: &Member| match &x.kind {
+        MemberKind::ExternFun(def, module, field) => {
+            options.wasi && wasi::is_builtin(def, module, field)
+        }
+        _ => false,
+    };
+    let externs = funcs
+        .iter()
+        .filter(|x| matches!(x.kind, MemberKind::ExternFun(..)))
+        .count();
+    let builtins = funcs.iter().filter(|x| builtin(x)).count();
+    let imports = externs - builtins + (builtins > 0) as usize;
+    let defined = funcs.len() - externs;
+    let (mut next_import, mut next_func, mut next_builtin) =
+        (0, imports, imports + defined + runtime::FUNCS);
+    let globals = module
+        .iter()
+        .filter_map(|x| match &x.kind {
+            MemberKind::Global(_, mutability, t, init) => Some((*mutability, t, init)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut codegen = Codegen {
+        funcs: funcs
+            .iter()
+            .filter_map(|x| match &x.kind {
+                MemberKind::Func(def, _) | MemberKind::ExternFun(def, _, _) => Some(def),
+                _ => None,
+            })
+            .collect(),
+        indices: funcs
+            .iter()
+            .map(|x| {
+                let next = match x.kind {
+                    _ if builtin(x) => &mut next_builtin,
+                    MemberKind::ExternFun(..) => &mut next_import,
+                    _ => &mut next_func,
+                };
+                *next += 1;
+                *next - 1
+            })
+            .collect(),
+        imports,
+        // The start of the heap is only known once all static data is laid out.
+        runtime: Runtime {
+            start: 0,
+            heap: globals.len(),
+            alloc: imports + defined,
+        },
+        helpers: imports + defined + runtime::FUNCS + builtins,
+        pow: false,
+        globals,
+        structs: module
+            .iter()
+            .filter_map(|x| match &x.kind {
+                MemberKind::Struct(name, _, fields) => Some(name.as_str()).zip(Some(fields)),
+                _ => None,
+            })
+            .enumerate()
+            .map(|(tag, (name, fields))| (name, Layout::new(tag, fields)))
+            .collect(),
+        enums: HashMap::new(),
+        arrays: Vec::new(),
+        types: Vec::new(),
+        table: Vec::new(),
+        data: Vec::new(),
+        strings: HashMap::new(),
+        names: Vec::new(),
+        spans: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    let mut tag = codegen.structs.len();
+    for x in module {
+        if let MemberKind::Enum(name, _, variants) = &x.kind {
+            let variants = variants
+                .iter()
+                .map(|x| {
+                    tag += 1;
+                    (x.0.as_str(), variant_layout(tag - 1, x))
+                })
+                .collect();
+            codegen.enums.insert(name.as_str(), variants);
+        }
+    }
+    let mut imports = Vec::new();
+    let mut signatures = Vec::new();
+    let mut bodies = Vec::new();
+    let mut exports = vec![ExportEntry {
+        field: "memory".to_string(),
+        kind: ExternalKind::Memory,
+        index: 0,
+    }];
+    let mut start = None;
+    for (id, member) in funcs.iter().enumerate() {
+        let def = codegen.funcs[id];
+        let main = def.0 == "main" && matches!(member.kind, MemberKind::Func(..));
+        if main && options.entry == Entry::Start {
+            if def.2.is_empty() && def.3.is_none() {
+                start = Some(StartSection(codegen.indices[id]));
+            } else {
+                codegen.diagnostics.push(
+                    Diagnostic::new(
+                        "`main` cannot be the start function".to_string(),
+                        member.span,
+                    )
+                    .with_code(Code::InvalidEntry)
+                    .with_note(
+                        "the start function takes no parameters and returns nothing".to_string(),
+                    ),
+                );
+            }
+        }
+        if member.visibility == Visibility::Public || (main && options.entry == Entry::Export) {
+            exports.push(ExportEntry {
+                field: def.0.clone(),
+                kind: ExternalKind::Function,
+                index: codegen.indices[id],
+            });
+        }
+        match &member.kind {
+            _ if builtin(member) => {}
+            MemberKind::ExternFun(def, module, field) => imports.push(ImportEntry {
+                module: module.clone(),
+                field: field.clone(),
+                kind: ExternalKindImport::Function(codegen.func_type(def)),
+            }),
+            MemberKind::Func(def, body) => {
+                signatures.push(codegen.func_type(def));
+                bodies.push(codegen.func(def, body, &types.locals[id]));
+            }
+            // `funcs` holds nothing else.
+            _ => {}
+        }
+    }
+    // Initializers that do not fold, such as a division by zero, are left zero. String literals
+    // are laid out beforehand so that they fold to their address.
+    for (_, _, init) in &codegen.globals.clone() {
+        if let ExprKind::StringLiteral(s) = &init.kind {
+            codegen.string(s);
+        }
+    }
+    let wasi = if builtins > 0 {
+        let newline = codegen.string("\n") as u32;
+        codegen.data.resize((codegen.data.len() + 3) & !3, 0);
+        let scratch = (DATA_START + codegen.data.len()) as u32;
+        codegen.data.resize(codegen.data.len() + 12, 0);
+        let typ = codegen.type_index(FuncType {
+            params: vec![ValueType::I32; 4],
+            results: vec![ValueType::I32],
+        });
+        imports.push(wasi::fd_write(typ));
+        Some(Wasi {
+            fd_write: codegen.imports - 1,
+            release: codegen.runtime.release(),
+            scratch,
+            newline,
+        })
+    } else {
+        None
+    };
+    codegen.runtime.start = ((DATA_START + codegen.data.len() + 7) & !7) as u32;
+    let mut objects = codegen
+        .structs
+        .values()
+        .chain(codegen.enums.values().flatten().map(|x| &x.1))
+        .collect::<Vec<_>>();
+    objects.sort_by_key(|x| x.tag);
+    let arrays = codegen.arrays.iter().map(|&elems| Object {
+        size: ARRAY_DATA,
+        refs: Vec::new(),
+        elems: Some(elems),
+    });
+    let objects = objects
+        .iter()
+        .map(|x| x.object())
+        .chain(arrays)
+        .collect::<Vec<_>>();
+    let runtime = [
+        (
+            vec![ValueType::I32, ValueType::I32],
+            Some(ValueType::I32),
+            codegen.runtime.alloc_body(),
+        ),
+        (
+            vec![ValueType::I32],
+            Some(ValueType::I32),
+            codegen.runtime.retain_body(),
+        ),
+        (
+            vec![ValueType::I32],
+            None,
+            codegen.runtime.release_body(&objects),
+        ),
+    ];
+    for (params, result, body) in runtime {
+        signatures.push(codegen.type_index(FuncType {
+            params,
+            results: result.into_iter().collect(),
+        }));
+        bodies.push(body);
+        codegen.spans.push(Vec::new());
+    }
+    if let Some(wasi) = &wasi {
+        for member in funcs.iter().filter(|x| builtin(x)) {
+            if let MemberKind::ExternFun(def, _, field) = &member.kind {
+                signatures.push(codegen.func_type(def));
+                bodies.push(wasi.body(field));
+                codegen.spans.push(Vec::new());
+            }
+        }
+    }
+    if codegen.pow {
+        signatures.push(codegen.type_index(FuncType {
+            params: vec![ValueType::F64, ValueType::F64],
+            results: vec![ValueType::F64],
+        }));
+        bodies.push(math::pow_body());
+        codegen.spans.push(Vec::new());
+    }
+    let names = if options.names {
+        let mut functions = codegen
+            .funcs
+            .iter()
+            .zip(&codegen.indices)
+            .map(|(def, i)| (*i, def.0.clone()))
+            .chain(
+                ["alloc", "retain", "release"]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| (codegen.runtime.alloc + i, x.to_string())),
+            )
+            .chain(Some((codegen.helpers, "pow".to_string())).filter(|_| codegen.pow))
+            .collect::<Vec<_>>();
+        functions.sort_by_key(|x| x.0);
+        Some(NameSection {
+            module: None,
+            functions,
+            locals: (codegen.imports..)
+                .zip(std::mem::take(&mut codegen.names))
+                .filter(|x| !x.1.is_empty())
+                .collect(),
+        })
+    } else {
+        None
+    };
+    let heap_base = codegen.runtime.base();
+    let heap = GlobalVariable(
+        GlobalType {
+            content_type: ValueType::I32,
+            mutability: true,
+        },
+        InitExpr::I32(heap_base as i32),
+    );
+    let globals = codegen
+        .globals
+        .iter()
+        .map(|(mutability, t, init)| {
+            let t = value_type(&Ty::from_type(t)).unwrap_or(ValueType::I32);
+            let init = codegen.constant(init).unwrap_or_else(|| Const::zero(&t));
+            GlobalVariable(
+                GlobalType {
+                    content_type: t,
+                    mutability: *mutability == Mutability::Mutable,
+                },
+                init.init_expr(),
+            )
+        })
+        .chain(std::iter::once(heap))
+        .collect();
+    let (table_section, element_section) = if codegen.table.is_empty() {
+        (None, None)
+    } else {
+        let size = codegen.table.len() as i32 + 1;
+        (
+            Some(TableSection(vec![TableType {
+                element_type: ElemType::AnyFunc,
+                limits: ResizableLimits {
+                    initial: size,
+                    maximum: Some(size),
+                    shared: false,
+                },
+            }])),
+            Some(ElementSection(vec![ElemSegment {
+                offset: InitExpr::I32(1),
+                elems: codegen.table,
+            }])),
+        )
+    };
+    let root = WasmASTRoot {
+        type_section: Some(TypeSection(codegen.types)),
+        import_section: if imports.is_empty() {
+            None
+        } else {
+            Some(ImportSection(imports))
+        },
+        global_section: Some(GlobalSection(globals)),
+        function_section: Some(FunctionSection(signatures)),
+        memory_section: Some(MemorySection(vec![MemoryType(ResizableLimits {
+            initial: (heap_base as usize).div_ceil(PAGE_SIZE).max(1) as i32,
+            maximum: None,
+            shared: false,
+        })])),
+        table_section,
+        export_section: Some(ExportSection(exports)),
+        start_section: start,
+        element_section,
+        code_section: Some(CodeSection(bodies)),
+        data_section: if codegen.data.is_empty() {
+            None
+        } else {
+            Some(DataSection(vec![DataSegment {
+                offset: Some(InitExpr::I32(DATA_START as i32)),
+                data: codegen.data,
+            }]))
+        },
+        name_section: names,
+        source_mapping_url: options.source_map.clone(),
+        custom_sections: Vec::new(),
+    };
+    (root, codegen.spans, codegen.diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Span;
+    use crate::desugar::desugar_module;
+    use crate::parser::parse_source;
+    use crate::resolver::resolve_module;
+    use crate::typeck::check_module;
+    use std::cell::RefCell;
+    use std::convert::TryInto;
+    use std::rc::Rc;
+    use wasm::interp::{Imports, Instance, Trap, Value};
+    use wasm::wat::print_module;
+
+    // Leaves out the functions of `runtime`, which are the same in every module but for a few
+    // constants.
+    fn compile(src: &str) -> WasmASTRoot {
+        let mut root = compile_with_runtime(src);
+        for _ in 0..runtime::FUNCS {
+            root.function_section.as_mut().unwrap().0.pop();
+            root.code_section.as_mut().unwrap().0.pop();
+        }
+        root
+    }
+
+    fn compile_with_runtime(src: &str) -> WasmASTRoot {
+        let (root, diagnostics) = compile_with_options(src, &Options::default());
+        assert_eq!(diagnostics, vec![]);
+        root
+    }
+
+    fn compile_with_options(src: &str, options: &Options) -> (WasmASTRoot, Vec<Diagnostic>) {
+        let (module, diagnostics) = parse_source(src);
+        assert_eq!(diagnostics, vec![]);
+        let (module, _, diagnostics) = resolve_module(module);
+        assert!(diagnostics.iter().all(|x| !x.is_error()));
+        let (types, diagnostics) = check_module(&module);
+        assert_eq!(diagnostics, vec![]);
+        let (root, _, diagnostics) = codegen_module(&desugar_module(module), &types, options);
+        (root, diagnostics)
+    }
+
+    #[test]
+    fn codegen_test() {
+        let root = compile(
+            "fun main() -> i32 {
+                let x = 40;
+                let y: f64 = -1.5;
+                x = x + 2;
+                x * 1 == 42;
+                -x
+            }",
+        );
+        assert_eq!(
+            print_module(&root),
+            r#"(module
+  (type (;0;) (func (result i32)))
+  (type (;1;) (func (param i32 i32) (result i32)))
+  (type (;2;) (func (param i32) (result i32)))
+  (type (;3;) (func (param i32)))
+  (func (;0;) (type 0) (result i32)
+    (local i32 f64)
+    i32.const 40
+    local.set 0
+    f64.const 1.5
+    f64.neg
+    local.set 1
+    local.get 0
+    i32.const 2
+    i32.add
+    local.set 0
+    local.get 0
+    i32.const 1
+    i32.mul
+    i32.const 42
+    i32.eq
+    drop
+    i32.const 0
+    local.get 0
+    i32.sub)
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) (i32.const 144))
+  (export "memory" (memory 0))
+  (export "main" (func 0)))
+"#
+        );
+    }
+
+    #[test]
+    fn control_flow_test() {
+        let root = compile(
+            "const LIMIT: i32 = 2 * 5;
+            fun main() -> i32 {
+                let n = 0;
+                while true {
+                    n = n + 1;
+                    if n >= LIMIT || n == 3 { break; }
+                }
+                match n { 3 => 1, m => m }
+            }",
+        );
+        assert_eq!(
+            print_module(&root),
+            r#"(module
+  (type (;0;) (func (result i32)))
+  (type (;1;) (func (param i32 i32) (result i32)))
+  (type (;2;) (func (param i32) (result i32)))
+  (type (;3;) (func (param i32)))
+  (func (;0;) (type 0) (result i32)
+    (local i32 i32 i32)
+    i32.const 0
+    local.set 0
+    block
+      loop
+        i32.const 1
+        i32.eqz
+        br_if 1
+        local.get 0
+        i32.const 1
+        i32.add
+        local.set 0
+        local.get 0
+        global.get 0
+        i32.ge_s
+        if (result i32)
+          i32.const 1
+        else
+          local.get 0
+          i32.const 3
+          i32.eq
+        end
+        if
+          br 2
+        end
+        br 0
+      end
+    end
+    local.get 0
+    local.set 2
+    block (result i32)
+      block
+        local.get 2
+        i32.const 3
+        i32.eq
+        i32.eqz
+        br_if 0
+        i32.const 1
+        br 1
+      end
+      block
+        local.get 2
+        local.set 1
+        local.get 1
+        br 1
+      end
+      unreachable
+    end)
+  (memory (;0;) 1)
+  (global (;0;) i32 (i32.const 10))
+  (global (;1;) (mut i32) (i32.const 144))
+  (export "memory" (memory 0))
+  (export "main" (func 0)))
+"#
+        );
+    }
+
+    #[test]
+    fn call_test() {
+        let root = compile(
+            "pub fun fact(n: i64) -> i64 {
+                if n <= 1i64 { 1i64 } else { n * fact(n - 1i64) }
+            }
+            extern fun log(x: i64) = \"env\" \"log\";
+            fun main() -> i32 {
+                log(fact(10i64));
+                add(1, 2) as i32
+            }
+            pub fun add(a: i32, b: i32) -> i64 { (a + b) as i64 }",
+        );
+        assert_eq!(
+            print_module(&root),
+            r#"(module
+  (type (;0;) (func (param i64) (result i64)))
+  (type (;1;) (func (param i64)))
+  (type (;2;) (func (result i32)))
+  (type (;3;) (func (param i32 i32) (result i64)))
+  (type (;4;) (func (param i32 i32) (result i32)))
+  (type (;5;) (func (param i32) (result i32)))
+  (type (;6;) (func (param i32)))
+  (import "env" "log" (func (;0;) (type 1)))
+  (func (;1;) (type 0) (param i64) (result i64)
+    local.get 0
+    i64.const 1
+    i64.le_s
+    if (result i64)
+      i64.const 1
+    else
+      local.get 0
+      local.get 0
+      i64.const 1
+      i64.sub
+      call 1
+      i64.mul
+    end)
+  (func (;2;) (type 2) (result i32)
+    i64.const 10
+    call 1
+    call 0
+    i32.const 1
+    i32.const 2
+    call 3
+    i32.wrap_i64)
+  (func (;3;) (type 3) (param i32 i32) (result i64)
+    local.get 0
+    local.get 1
+    i32.add
+    i64.extend_i32_s)
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) (i32.const 144))
+  (export "memory" (memory 0))
+  (export "fact" (func 1))
+  (export "main" (func 2))
+  (export "add" (func 3)))
+"#
+        );
+
+        let (module, _) = parse_source("fun main() { let f = || -> i32 { 1 }; f(); }");
+        let (module, _, _) = resolve_module(module);
+        let (types, _) = check_module(&module);
+        let (_, _, diagnostics) = codegen_module(&module, &types, &Options::default());
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|x| (x.code, x.span))
+                .collect::<Vec<_>>(),
+            vec![(Some(Code::InvalidCall), Span::new(21, 15))]
+        );
+    }
+
+    #[test]
+    fn indirect_call_test() {
+        let root = compile_with_runtime(
+            "fun double(x: i32) -> i32 { x * 2 }
+            fun inc(x: i32) -> i32 { x + 1 }
+            fun apply(f: fun(i32) -> i32, x: i32) -> i32 { f(x) }
+            fun pick(b: bool) -> fun(i32) -> i32 { if b { double } else { inc } }
+            pub fun main() -> i32 { apply(double, 5) + pick(false)(10) + apply(pick(true), 1) }",
+        );
+        // Slot 0 is left empty.
+        assert_eq!(root.table_section.as_ref().unwrap().0[0].limits.initial, 3);
+        assert_eq!(
+            root.element_section.as_ref().unwrap().0,
+            vec![ElemSegment {
+                offset: InitExpr::I32(1),
+                elems: vec![0, 1],
+            }]
+        );
+        let mut instance = Instance::new(root, Imports::new()).unwrap();
+        assert_eq!(instance.invoke("main", &[]), Ok(vec![Value::I32(23)]));
+
+        let (_, diagnostics) = compile_with_options(
+            "extern fun print(s: string) = \"env\" \"print\";
+            fun main() { let f = print; f(\"a\"); }",
+            &Options::default(),
+        );
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|x| (x.code, x.span))
+                .collect::<Vec<_>>(),
+            vec![(Some(Code::InvalidCall), Span::new(78, 5))]
+        );
+    }
+
+    #[test]
+    fn string_test() {
+        let root = compile(
+            "const GREETING: string = \"héllo\";
+            extern fun print(s: string) = \"env\" \"print\";
+            fun main() {
+                print(\"abc\");
+                print(GREETING);
+                print(\"abc\");
+            }",
+        );
+        let wat = print_module(&root);
+        // The host only borrows strings.
+        assert!(wat.contains(
+            "    i32.const 8\n    local.tee 0\n    call 0\n    local.get 0\n    call 4\n"
+        ));
+        assert!(wat.contains("    global.get 0\n    call 3\n    local.tee 1\n    call 0\n"));
+        assert!(wat.contains("(global (;0;) i32 (i32.const 16))"));
+        assert!(wat
+            .contains(r#"(data (;0;) (i32.const 8) "\03\00\00\00abc\00\06\00\00\00h\c3\a9llo")"#));
+    }
+
+    #[test]
+    fn struct_test() {
+        let root = compile(
+            "struct Point { x: i64, ok: bool, y: f64, name: string, z: i32 }
+            fun make(n: i32) -> Point { Point { name: \"p\", z: n, x: 7i64, ok: true, y: 1.5 } }
+            fun main() -> i32 {
+                let p = make(3);
+                let q = make(4);
+                p.z = p.z + 10;
+                q.ok = false;
+                if q.ok { 0 } else { p.z + q.z + p.x as i32 + p.y as i32 }
+            }",
+        );
+        assert_eq!(
+            print_module(&root),
+            r#"(module
+  (type (;0;) (func (param i32) (result i32)))
+  (type (;1;) (func (result i32)))
+  (type (;2;) (func (param i32 i32) (result i32)))
+  (type (;3;) (func (param i32)))
+  (func (;0;) (type 0) (param i32) (result i32)
+    (local i32)
+    i32.const 32
+    i32.const 0
+    call 2
+    local.set 1
+    local.get 1
+    i32.const 8
+    i32.store offset=24
+    local.get 1
+    local.get 0
+    i32.store offset=28
+    local.get 1
+    i64.const 7
+    i64.store
+    local.get 1
+    i32.const 1
+    i32.store8 offset=8
+    local.get 1
+    f64.const 1.5
+    f64.store offset=16
+    local.get 1)
+  (func (;1;) (type 1) (result i32)
+    (local i32 i32 i32 i32 i32)
+    i32.const 3
+    call 0
+    local.get 0
+    call 4
+    local.set 0
+    i32.const 4
+    call 0
+    local.get 1
+    call 4
+    local.set 1
+    local.get 0
+    call 3
+    local.get 2
+    call 4
+    local.set 2
+    local.get 2
+    local.get 0
+    i32.load offset=28
+    i32.const 10
+    i32.add
+    i32.store offset=28
+    local.get 2
+    call 4
+    i32.const 0
+    local.set 2
+    local.get 1
+    call 3
+    local.get 3
+    call 4
+    local.set 3
+    local.get 3
+    i32.const 0
+    i32.store8 offset=8
+    local.get 3
+    call 4
+    i32.const 0
+    local.set 3
+    local.get 1
+    i32.load8_u offset=8
+    if (result i32)
+      i32.const 0
+    else
+      local.get 0
+      i32.load offset=28
+      local.get 1
+      i32.load offset=28
+      i32.add
+      local.get 0
+      i64.load
+      i32.wrap_i64
+      i32.add
+      local.get 0
+      f64.load offset=16
+      i32.trunc_f64_s
+      i32.add
+    end
+    local.set 4
+    local.get 0
+    call 4
+    local.get 1
+    call 4
+    local.get 2
+    call 4
+    local.get 3
+    call 4
+    local.get 4)
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) (i32.const 152))
+  (export "memory" (memory 0))
+  (export "main" (func 1))
+  (data (;0;) (i32.const 8) "\01\00\00\00p"))
+"#
+        );
+    }
+
+    #[test]
+    fn run_test() {
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let mut imports = Imports::new();
+        {
+            let printed = printed.clone();
+            imports.func("env", "print", move |memory, args| {
+                let ptr = match args[0] {
+                    Value::I32(ptr) => ptr as usize,
+                    _ => return Err(Trap::Host("bad argument to `print`".to_string())),
+                };
+                let len = u32::from_le_bytes(memory[ptr..ptr + 4].try_into().unwrap()) as usize;
+                let s = String::from_utf8(memory[ptr + 4..ptr + 4 + len].to_vec()).unwrap();
+                printed.borrow_mut().push(s);
+                Ok(Vec::new())
+            });
+        }
+        let root = compile_with_runtime(
+            "struct Point { x: i64, name: string }
+            extern fun print(s: string) = \"env\" \"print\";
+            fun make(n: i64) -> Point { Point { x: n, name: \"héllo\" } }
+            pub fun fact(n: i64) -> i64 { if n <= 1i64 { 1i64 } else { n * fact(n - 1i64) } }
+            fun main() -> i64 {
+                let p = make(3i64);
+                let q = make(4i64);
+                print(p.name);
+                print(\"abc\");
+                p.x + q.x * 10i64
+            }",
+        );
+        let mut instance = Instance::new(root, imports).unwrap();
+        assert_eq!(instance.invoke("main", &[]), Ok(vec![Value::I64(43)]));
+        assert_eq!(*printed.borrow(), vec!["héllo", "abc"]);
+        assert_eq!(
+            instance.invoke("fact", &[Value::I64(10)]),
+            Ok(vec![Value::I64(3628800)])
+        );
+    }
+
+    #[test]
+    fn array_enum_test() {
+        let root = compile_with_runtime(
+            "enum Shape { Circle(i32), Rect(i32, i64), Empty }
+            struct Named { name: string, shape: Shape }
+            fun area(s: Shape) -> i32 {
+                match s {
+                    Shape.Circle(r) => 3 * r * r,
+                    Shape.Rect(w, h) => w * h as i32,
+                    Shape.Empty => 0,
+                }
+            }
+            pub fun arrays() -> i32 {
+                let a = [i32; 3];
+                a[1] = 5;
+                a[2] = a[1] + 1;
+                let names = [string; 2];
+                names[0] = \"x\";
+                names[0] = \"y\";
+                let nested = [[bool]; 2];
+                nested[1] = [bool; 2];
+                nested[1][0] = true;
+                if nested[1][0] && !nested[1][1] { a[0] + a[1] + a[2] } else { 0 }
+            }
+            pub fun shapes() -> i32 {
+                let s = [Shape; 3];
+                s[0] = Shape.Circle(2);
+                s[1] = Shape.Rect(3, 4i64);
+                s[2] = Shape.Empty;
+                let n = Named { name: \"n\", shape: Shape.Rect(1, 1i64) };
+                area(s[0]) + area(s[1]) + area(s[2]) + area(n.shape)
+            }
+            pub fun get(i: i32) -> i64 { [i64; 2][i] }
+            pub fun make(n: i32) -> i32 { let a = [f64; n]; 1 }
+            fun main() {}",
+        );
+        let mut instance = Instance::new(root, Imports::new()).unwrap();
+        // Everything is freed, so the later calls only reuse blocks.
+        let mut heap = None;
+        for _ in 0..3 {
+            assert_eq!(instance.invoke("arrays", &[]), Ok(vec![Value::I32(11)]));
+            assert_eq!(instance.invoke("shapes", &[]), Ok(vec![Value::I32(25)]));
+            let top = instance.globals.last().cloned();
+            assert!(heap.is_none() || heap == top);
+            heap = top;
+        }
+        assert_eq!(
+            instance.invoke("get", &[Value::I32(1)]),
+            Ok(vec![Value::I64(0)])
+        );
+        assert_eq!(
+            instance.invoke("make", &[Value::I32(0)]),
+            Ok(vec![Value::I32(1)])
+        );
+        for i in [2, -1] {
+            assert!(matches!(
+                instance.invoke("get", &[Value::I32(i)]),
+                Err(Trap::Unreachable)
+            ));
+        }
+        assert!(matches!(
+            instance.invoke("make", &[Value::I32(-1)]),
+            Err(Trap::Unreachable)
+        ));
+
+        // A variant with fields is only a constructor.
+        let (_, diagnostics) = compile_with_options(
+            "enum E { A(i32) } fun main() { let f = E.A; }",
+            &Options::default(),
+        );
+        assert_eq!(
+            diagnostics.iter().map(|x| x.code).collect::<Vec<_>>(),
+            vec![Some(Code::InvalidCall)]
+        );
+    }
+
+    #[test]
+    fn undesugared_test() {
+        let (module, _) = parse_source(
+            "pub fun sum(n: i32) -> i32 {
+                let s = 0;
+                for let i = 0; i < n; i += 1 { if i == 2 { continue; } s += i; }
+                s
+            }
+            fun main() {}",
+        );
+        let (module, _, diagnostics) = resolve_module(module);
+        assert_eq!(diagnostics, vec![]);
+        let (types, diagnostics) = check_module(&module);
+        assert_eq!(diagnostics, vec![]);
+        let (root, _, diagnostics) = codegen_module(&module, &types, &Options::default());
+        assert_eq!(diagnostics, vec![]);
+        let mut instance = Instance::new(root, Imports::new()).unwrap();
+        assert_eq!(
+            instance.invoke("sum", &[Value::I32(5)]),
+            Ok(vec![Value::I32(8)])
+        );
+    }
+
+    #[test]
+    fn compound_set_test() {
+        let root = compile_with_runtime(
+            "struct P { x: f64 }
+            let mut calls: i32 = 0;
+            let mut total: i64 = 1i64;
+            fun next() -> i32 { calls += 1; calls - 1 }
+            pub fun run() -> i32 {
+                let a = [i32; 2];
+                a[next()] += 5;
+                a[next()] -= 2;
+                a[0] *= 3;
+                a[0] %= 4;
+                let p = P { x: 1.0 };
+                p.x /= 4.0;
+                total += 2i64;
+                let n = 7;
+                n -= a[0];
+                if p.x == 0.25 && total == 3i64 { n * 100 + a[1] * 10 + calls } else { 0 }
+            }
+            fun main() {}",
+        );
+        let mut instance = Instance::new(root, Imports::new()).unwrap();
+        // `a[next()]` calls `next` once per assignment.
+        assert_eq!(
+            instance.invoke("run", &[]),
+            Ok(vec![Value::I32(4 * 100 - 20 + 2)])
+        );
+    }
+
+    #[test]
+    fn numeric_test() {
+        let binary = [
+            ("add", "+"),
+            ("sub", "-"),
+            ("mul", "*"),
+            ("div", "/"),
+            ("rem", "%"),
+            ("pow", "**"),
+            ("and", "&"),
+            ("or", "|"),
+            ("xor", "^"),
+            ("eq", "=="),
+            ("ne", "!="),
+            ("lt", "<"),
+            ("le", "<="),
+            ("gt", ">"),
+            ("ge", ">="),
+        ];
+        let mut src = String::new();
+        for t in &["i32", "i64", "f32", "f64"] {
+            for (name, op) in &binary {
+                let float = t.starts_with('f');
+                if float && ["rem", "and", "or", "xor"].contains(name) {
+                    continue;
+                }
+                let ret = if name.len() == 2 && *name != "or" {
+                    "bool"
+                } else {
+                    t
+                };
+                src.push_str(&format!(
+                    "pub fun {}_{}(x: {}, y: {}) -> {} {{ x {} y }}\n",
+                    name, t, t, t, ret, op
+                ));
+            }
+            src.push_str(&format!("pub fun neg_{}(x: {}) -> {} {{ -x }}\n", t, t, t));
+        }
+        src.push_str("pub fun not_i32(x: i32) -> i32 { !x }\n");
+        src.push_str("pub fun not_i64(x: i64) -> i64 { !x }\n");
+        src.push_str("pub fun lt_char(x: char, y: char) -> bool { x < y }\n");
+        src.push_str("fun main() {}");
+        let mut instance = Instance::new(compile_with_runtime(&src), Imports::new()).unwrap();
+        let mut run = |name: &str, args: &[Value]| instance.invoke(name, args);
+
+        let ints = [
+            (7, 3),
+            (-7, 2),
+            (7, -2),
+            (3, 0),
+            (i32::MIN, -1),
+            (i32::MAX, 1),
+            (2, 31),
+            (-3, 3),
+            (5, -1),
+        ];
+        for &(x, y) in &ints {
+            let args = [Value::I32(x), Value::I32(y)];
+            let b = |b: bool| Ok(vec![Value::I32(b as i32)]);
+            let i = |x: i32| Ok(vec![Value::I32(x)]);
+            assert_eq!(run("add_i32", &args), i(x.wrapping_add(y)));
+            assert_eq!(run("sub_i32", &args), i(x.wrapping_sub(y)));
+            assert_eq!(run("mul_i32", &args), i(x.wrapping_mul(y)));
+            let div = match (x, y) {
+                (_, 0) => Err(Trap::DivisionByZero),
+                (i32::MIN, -1) => Err(Trap::IntegerOverflow),
+                _ => i(x / y),
+            };
+            assert_eq!(run("div_i32", &args), div);
+            let rem = match y {
+                0 => Err(Trap::DivisionByZero),
+                _ => i(x.wrapping_rem(y)),
+            };
+            assert_eq!(run("rem_i32", &args), rem);
+            assert_eq!(run("pow_i32", &args), i(x.wrapping_pow(y.max(0) as u32)));
+            assert_eq!(run("and_i32", &args), i(x & y));
+            assert_eq!(run("or_i32", &args), i(x | y));
+            assert_eq!(run("xor_i32", &args), i(x ^ y));
+            assert_eq!(run("eq_i32", &args), b(x == y));
+            assert_eq!(run("ne_i32", &args), b(x != y));
+            assert_eq!(run("lt_i32", &args), b(x < y));
+            assert_eq!(run("le_i32", &args), b(x <= y));
+            assert_eq!(run("gt_i32", &args), b(x > y));
+            assert_eq!(run("ge_i32", &args), b(x >= y));
+            assert_eq!(run("neg_i32", &args[..1]), i(x.wrapping_neg()));
+            assert_eq!(run("not_i32", &args[..1]), i(!x));
+
+            // The same, widened, except at the edges of `i32`.
+            let (x, y) = (i64::from(x) << 16, i64::from(y));
+            let args = [Value::I64(x), Value::I64(y)];
+            let i = |x: i64| Ok(vec![Value::I64(x)]);
+            assert_eq!(run("add_i64", &args), i(x.wrapping_add(y)));
+            assert_eq!(run("mul_i64", &args), i(x.wrapping_mul(y)));
+            let div = match y {
+                0 => Err(Trap::DivisionByZero),
+                _ => i(x / y),
+            };
+            assert_eq!(run("div_i64", &args), div);
+            let rem = match y {
+                0 => Err(Trap::DivisionByZero),
+                _ => i(x % y),
+            };
+            assert_eq!(run("rem_i64", &args), rem);
+            assert_eq!(run("pow_i64", &args), i(x.wrapping_pow(y.max(0) as u32)));
+            assert_eq!(run("xor_i64", &args), i(x ^ y));
+            assert_eq!(run("lt_i64", &args), b(x < y));
+            assert_eq!(run("ge_i64", &args), b(x >= y));
+            assert_eq!(run("neg_i64", &args[..1]), i(x.wrapping_neg()));
+            assert_eq!(run("not_i64", &args[..1]), i(!x));
+        }
+        assert_eq!(
+            run("div_i64", &[Value::I64(i64::MIN), Value::I64(-1)]),
+            Err(Trap::IntegerOverflow)
+        );
+        assert_eq!(
+            run("lt_char", &[Value::I32(0x10ffff), Value::I32(1)]),
+            Ok(vec![Value::I32(0)])
+        );
+
+        let floats = [
+            (1.5, 2.0),
+            (-7.25, 0.5),
+            (3.0, 0.0),
+            (0.0, 0.0),
+            (2.0, -3.0),
+            (-8.0, 1.0 / 3.0),
+            (f64::NAN, 1.0),
+            (f64::INFINITY, -0.5),
+        ];
+        for &(x, y) in &floats {
+            let args = [Value::F64(x), Value::F64(y)];
+            let b = |b: bool| Ok(vec![Value::I32(b as i32)]);
+            let same = |actual: Result<Vec<Value>, Trap>, expected: f64| match actual.as_deref() {
+                Ok(&[Value::F64(actual)]) => {
+                    assert!(
+                        actual == expected || (actual.is_nan() && expected.is_nan()),
+                        "{} is not {}",
+                        actual,
+                        expected
+                    );
+                }
+                actual => panic!("{:?}", actual),
+            };
+            same(run("add_f64", &args), x + y);
+            same(run("sub_f64", &args), x - y);
+            same(run("mul_f64", &args), x * y);
+            same(run("div_f64", &args), x / y);
+            same(run("pow_f64", &args), x.powf(y));
+            same(run("neg_f64", &args[..1]), -x);
+            assert_eq!(run("eq_f64", &args), b(x == y));
+            assert_eq!(run("ne_f64", &args), b(x != y));
+            assert_eq!(run("lt_f64", &args), b(x < y));
+            assert_eq!(run("le_f64", &args), b(x <= y));
+            assert_eq!(run("gt_f64", &args), b(x > y));
+            assert_eq!(run("ge_f64", &args), b(x >= y));
+
+            let (x, y) = (x as f32, y as f32);
+            let args = [Value::F32(x), Value::F32(y)];
+            let same = |actual: Result<Vec<Value>, Trap>, expected: f32| match actual.as_deref() {
+                Ok(&[Value::F32(actual)]) => {
+                    assert!(
+                        actual == expected || (actual.is_nan() && expected.is_nan()),
+                        "{} is not {}",
+                        actual,
+                        expected
+                    );
+                }
+                actual => panic!("{:?}", actual),
+            };
+            same(run("add_f32", &args), x + y);
+            same(run("div_f32", &args), x / y);
+            same(run("pow_f32", &args), x.powf(y));
+            same(run("neg_f32", &args[..1]), -x);
+            assert_eq!(run("lt_f32", &args), b(x < y));
+            assert_eq!(run("ne_f32", &args), b(x != y));
+        }
+    }
+
+    #[test]
+    fn match_test() {
+        let root = compile_with_runtime(
+            "pub fun dense(n: i32) -> i32 {
+                match n { 2 => 20, 3 => 30, 5 => 50, 2 => 0, 4 => 40, m => m }
+            }
+            pub fun wide(n: i64) -> i64 {
+                match n { 10i64 => 1i64, 11i64 => 2i64, 12i64 => 3i64, _ => 0i64 }
+            }
+            pub fun letter(c: char) -> i32 { match c { 'a' => 1, 'b' => 2, 'c' => 3, _ => 0 } }
+            pub fun sparse(n: i32) -> i32 { match n { 1 => 1, 100 => 2, 10000 => 3, _ => 0 } }
+            fun main() {}",
+        );
+        let tables = root.code_section.as_ref().unwrap().0[..4]
+            .iter()
+            .map(|x| {
+                x.codes
+                    .iter()
+                    .filter_map(|x| match x {
+                        OperatorCode::BrTable { index, params } => Some((*index, params.clone())),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // From 2 to 5, with the shadowed `2` left to the remaining arms like the binding.
+        assert_eq!(tables[0], vec![(5, vec![0, 1, 4, 2])]);
+        assert_eq!(tables[1], vec![(3, vec![0, 1, 2])]);
+        assert_eq!(tables[2], vec![(3, vec![0, 1, 2])]);
+        assert_eq!(tables[3], vec![]);
+
+        let mut instance = Instance::new(root, Imports::new()).unwrap();
+        for n in -2..15 {
+            let dense = match n {
+                2 => 20,
+                3 => 30,
+                5 => 50,
+                4 => 40,
+                m => m,
+            };
+            assert_eq!(
+                instance.invoke("dense", &[Value::I32(n)]),
+                Ok(vec![Value::I32(dense)])
+            );
+            let wide = match n {
+                10..=12 => n - 9,
+                _ => 0,
+            };
+            assert_eq!(
+                instance.invoke("wide", &[Value::I64(n.into())]),
+                Ok(vec![Value::I64(wide.into())])
+            );
+            let letter = ('a' as i32 - 1..)
+                .zip(0..4)
+                .find(|x| x.0 == n + 96)
+                .map_or(0, |x| x.1);
+            assert_eq!(
+                instance.invoke("letter", &[Value::I32(n + 96)]),
+                Ok(vec![Value::I32(letter)])
+            );
+        }
+        assert_eq!(
+            instance.invoke("wide", &[Value::I64(10 + (1 << 32))]),
+            Ok(vec![Value::I64(0)])
+        );
+        assert_eq!(
+            instance.invoke("sparse", &[Value::I32(10000)]),
+            Ok(vec![Value::I32(3)])
+        );
+    }
+
+    #[test]
+    fn alloc_test() {
+        let root = compile_with_runtime(&format!(
+            "const S: string = \"{}\"; fun main() {{}}",
+            "a".repeat(70000)
+        ));
+        let funcs = &root.function_section.as_ref().unwrap().0;
+        assert_eq!(funcs.len(), 4);
+        assert_eq!(
+            root.type_section.as_ref().unwrap().0[funcs[1]],
+            FuncType {
+                params: vec![ValueType::I32, ValueType::I32],
+                results: vec![ValueType::I32],
+            }
+        );
+        // The free lists come before the first block.
+        assert_eq!(
+            root.global_section.as_ref().unwrap().0[1].1,
+            InitExpr::I32(70016 + 136)
+        );
+        assert_eq!(
+            root.memory_section.as_ref().unwrap().0[0].0,
+            ResizableLimits {
+                initial: 2,
+                maximum: None,
+                shared: false,
+            }
+        );
+    }
+
+    #[test]
+    fn entry_test() {
+        let options = Options {
+            entry: Entry::Start,
+            ..Options::default()
+        };
+        let (root, diagnostics) = compile_with_options("pub fun run() {} fun main() {}", &options);
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(root.start_section, Some(StartSection(1)));
+        assert_eq!(
+            root.export_section
+                .unwrap()
+                .0
+                .iter()
+                .map(|x| x.field.as_str())
+                .collect::<Vec<_>>(),
+            vec!["memory", "run"]
+        );
+
+        let (root, diagnostics) = compile_with_options("fun main(x: i32) {}", &options);
+        assert_eq!(root.start_section, None);
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|x| (x.code, x.span))
+                .collect::<Vec<_>>(),
+            vec![(Some(Code::InvalidEntry), Span::new(0, 19))]
+        );
+    }
+
+    #[test]
+    fn names_test() {
+        let options = Options {
+            names: true,
+            ..Options::default()
+        };
+        let (root, _) = compile_with_options(
+            "extern fun log(x: i32) = \"env\" \"log\";
+            fun add(a: i32, b: i32) -> i32 { let c = a + b; c }
+            fun main() { log(add(1, 2)); }",
+            &options,
+        );
+        let names = root.name_section.unwrap();
+        assert_eq!(
+            names
+                .functions
+                .iter()
+                .map(|(i, x)| (*i, x.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, "log"),
+                (1, "add"),
+                (2, "main"),
+                (3, "alloc"),
+                (4, "retain"),
+                (5, "release")
+            ]
+        );
+        assert_eq!(
+            names.locals,
+            vec![(
+                1,
+                vec![
+                    (0, "a".to_string()),
+                    (1, "b".to_string()),
+                    (2, "c".to_string())
+                ]
+            )]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use crate::ast::{Expr, ExprKind, Module};
+use crate::fold::{self, Folder};
+
+// Lowers surface syntax to the smaller core the later passes handle:
+//
+// * `for init; cond; step { body }` becomes `{ init; while cond { body; step } }`, with every
+//   `continue` of the loop running `step` first.
+// * `if a {} else if b {} else {}` becomes `if a {} else { if b {} else {} }`.
+//
+// Compound assignments stay as they are, since lowering `a[f()] += 1` to `a[f()] = a[f()] + 1`
+// would evaluate the target twice. The language has no string interpolation.
+struct Desugar;
+
+impl Folder for Desugar {
+    fn fold_expr(&mut self, x: Expr) -> Expr {
+        let x = fold::walk_expr(self, x);
+        let span = x.span;
+        let kind = match x.kind {
+            ExprKind::For(init, cond, step, body) => {
+                let body = ContinueStep(&step).fold_expr(*body);
+                let body = Expr::new(ExprKind::Block(vec![body, *step], Box::new(None)), span);
+                let while_ = Expr::new(ExprKind::While(cond, Box::new(body)), span);
+                ExprKind::Block(vec![*init, while_], Box::new(None))
+            }
+            ExprKind::If(first, elifs, els) if !elifs.is_empty() => {
+                let els = elifs.into_iter().rev().fold(*els, |els, (cond, body)| {
+                    let span = cond.span.to(els.as_ref().unwrap_or(&body).span);
+                    let if_ = Expr::new(
+                        ExprKind::If(Box::new((cond, body)), Vec::new(), Box::new(els)),
+                        span,
+                    );
+                    Some(Expr::new(
+                        ExprKind::Block(Vec::new(), Box::new(Some(if_))),
+                        span,
+                    ))
+                });
+                ExprKind::If(first, Vec::new(), Box::new(els))
+            }
+            kind => kind,
+        };
+        Expr::new(kind, span)
+    }
+}
+
+// Rewrites `continue` into `{ step; continue }`, leaving nested loops and lambdas alone.
+struct ContinueStep<'a>(&'a Expr);
+
+impl Folder for ContinueStep<'_> {
+    fn fold_expr(&mut self, x: Expr) -> Expr {
+        match x.kind {
+            ExprKind::Continue => Expr::new(
+                ExprKind::Block(vec![self.0.clone(), x.clone()], Box::new(None)),
+                x.span,
+            ),
+            ExprKind::While(..) | ExprKind::For(..) | ExprKind::Lambda(..) => x,
+            _ => fold::walk_expr(self, x),
+        }
+    }
+}
+
+pub fn desugar_expr(x: Expr) -> Expr {
+    Desugar.fold_expr(x)
+}
+
+pub fn desugar_module(x: Module) -> Module {
+    Desugar.fold_module(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+    use crate::sexpr::module_to_sexpr;
+
+    fn helper(src: &str) -> String {
+        let (module, diagnostics) = parse_source(src);
+        assert_eq!(diagnostics, vec![]);
+        module_to_sexpr(&desugar_module(module))
+    }
+
+    #[test]
+    fn for_test() {
+        assert_eq!(
+            helper(
+                "fun f() { for i = 0; i < n; i += 1 { if p { continue; } while q { continue; } } }"
+            ),
+            "(fun f (type-params) (params) () (block \
+             (block (set (var i) (i32 0)) \
+             (while (lt (var i) (var n)) (block \
+             (block (if (branch (var p) (block \
+             (block (set-add (var i) (i32 1)) continue)))) \
+             (while (var q) (block continue))) \
+             (set-add (var i) (i32 1)))))))\n"
+        );
+    }
+
+    #[test]
+    fn if_test() {
+        assert_eq!(
+            helper("fun f() { if a { 1 } else if b { 2 } else if c { 3 } else { 4 } }"),
+            "(fun f (type-params) (params) () (block \
+             (if (branch (var a) (block (i32 1))) (else (block \
+             (if (branch (var b) (block (i32 2))) (else (block \
+             (if (branch (var c) (block (i32 3))) (else (block (i32 4))))))))))))\n"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use crate::ast::{
+    Expr, ExprKind, FuncDef, Member, MemberKind, Module, Pattern, RefType, Type, Variant,
+};
+
+// Owning counterpart of `visit::Visitor` for passes that rewrite the tree. Override the
+// `fold_*` methods of interest and call the matching `walk_*` to fold the children.
+pub trait Folder: Sized {
+    fn fold_module(&mut self, x: Module) -> Module {
+        walk_module(self, x)
+    }
+
+    fn fold_member(&mut self, x: Member) -> Member {
+        walk_member(self, x)
+    }
+
+    fn fold_expr(&mut self, x: Expr) -> Expr {
+        walk_expr(self, x)
+    }
+
+    fn fold_type(&mut self, x: Type) -> Type {
+        walk_type(self, x)
+    }
+
+    fn fold_pattern(&mut self, x: Pattern) -> Pattern {
+        walk_pattern(self, x)
+    }
+}
+
+pub fn walk_module<F: Folder>(f: &mut F, x: Module) -> Module {
+    x.into_iter().map(|x| f.fold_member(x)).collect()
+}
+
+fn walk_func_def<F: Folder>(f: &mut F, FuncDef(name, ty_params, params, ret): FuncDef) -> FuncDef {
+    FuncDef(
+        name,
+        ty_params,
+        params
+            .into_iter()
+            .map(|(name, t)| (name, f.fold_type(t)))
+            .collect(),
+        ret.map(|t| f.fold_type(t)),
+    )
+}
+
+pub fn walk_member<F: Folder>(f: &mut F, x: Member) -> Member {
+    let kind = match x.kind {
+        MemberKind::Struct(name, ty_params, fields) => MemberKind::Struct(
+            name,
+            ty_params,
+            fields
+                .into_iter()
+                .map(|(name, t)| (name, f.fold_type(t)))
+                .collect(),
+        ),
+        MemberKind::Enum(name, ty_params, variants) => MemberKind::Enum(
+            name,
+            ty_params,
+            variants
+                .into_iter()
+                .map(|Variant(name, ts)| {
+                    Variant(name, ts.into_iter().map(|t| f.fold_type(t)).collect())
+                })
+                .collect(),
+        ),
+        MemberKind::Func(def, body) => MemberKind::Func(walk_func_def(f, def), f.fold_expr(body)),
+        MemberKind::ExternFun(def, module, name) => {
+            MemberKind::ExternFun(walk_func_def(f, def), module, name)
+        }
+        MemberKind::Global(name, mutability, t, init) => {
+            MemberKind::Global(name, mutability, f.fold_type(t), f.fold_expr(init))
+        }
+        kind @ (MemberKind::Import(_) | MemberKind::Error) => kind,
+    };
+    Member { kind, ..x }
+}
+
+// Reuses the allocation.
+fn boxed<F: Folder>(f: &mut F, mut x: Box<Expr>) -> Box<Expr> {
+    *x = f.fold_expr(*x);
+    x
+}
+
+fn pair<F: Folder>(f: &mut F, (x, y): (Expr, Expr)) -> (Expr, Expr) {
+    (f.fold_expr(x), f.fold_expr(y))
+}
+
+pub fn walk_expr<F: Folder>(f: &mut F, x: Expr) -> Expr {
+    let kind = match x.kind {
+        ExprKind::StructLiteral(name, fields) => ExprKind::StructLiteral(
+            name,
+            fields
+                .into_iter()
+                .map(|(name, x)| (name, f.fold_expr(x)))
+                .collect(),
+        ),
+        ExprKind::ArrayLiteral(t, len) => ExprKind::ArrayLiteral(f.fold_type(t), boxed(f, len)),
+        ExprKind::Not(x) => ExprKind::Not(boxed(f, x)),
+        ExprKind::Plus(x) => ExprKind::Plus(boxed(f, x)),
+        ExprKind::Minus(x) => ExprKind::Minus(boxed(f, x)),
+        ExprKind::Cast(x, t) => ExprKind::Cast(boxed(f, x), f.fold_type(t)),
+        ExprKind::Member(x, name) => ExprKind::Member(boxed(f, x), name),
+        ExprKind::Index(x, y) => ExprKind::Index(boxed(f, x), boxed(f, y)),
+        ExprKind::Call(x, args) => ExprKind::Call(
+            boxed(f, x),
+            args.into_iter().map(|x| f.fold_expr(x)).collect(),
+        ),
+        ExprKind::Add(x, y) => ExprKind::Add(boxed(f, x), boxed(f, y)),
+        ExprKind::Sub(x, y) => ExprKind::Sub(boxed(f, x), boxed(f, y)),
+        ExprKind::Mul(x, y) => ExprKind::Mul(boxed(f, x), boxed(f, y)),
+        ExprKind::Div(x, y) => ExprKind::Div(boxed(f, x), boxed(f, y)),
+        ExprKind::Mod(x, y) => ExprKind::Mod(boxed(f, x), boxed(f, y)),
+        ExprKind::And(x, y) => ExprKind::And(boxed(f, x), boxed(f, y)),
+        ExprKind::Or(x, y) => ExprKind::Or(boxed(f, x), boxed(f, y)),
+        ExprKind::BitAnd(x, y) => ExprKind::BitAnd(boxed(f, x), boxed(f, y)),
+        ExprKind::BitOr(x, y) => ExprKind::BitOr(boxed(f, x), boxed(f, y)),
+        ExprKind::BitXor(x, y) => ExprKind::BitXor(boxed(f, x), boxed(f, y)),
+        ExprKind::Pow(x, y) => ExprKind::Pow(boxed(f, x), boxed(f, y)),
+        ExprKind::Eq(x, y) => ExprKind::Eq(boxed(f, x), boxed(f, y)),
+        ExprKind::Ne(x, y) => ExprKind::Ne(boxed(f, x), boxed(f, y)),
+        ExprKind::Lt(x, y) => ExprKind::Lt(boxed(f, x), boxed(f, y)),
+        ExprKind::Lte(x, y) => ExprKind::Lte(boxed(f, x), boxed(f, y)),
+        ExprKind::Gt(x, y) => ExprKind::Gt(boxed(f, x), boxed(f, y)),
+        ExprKind::Gte(x, y) => ExprKind::Gte(boxed(f, x), boxed(f, y)),
+        ExprKind::Range(x, y) => ExprKind::Range(boxed(f, x), boxed(f, y)),
+        ExprKind::RangeInclusive(x, y) => ExprKind::RangeInclusive(boxed(f, x), boxed(f, y)),
+        ExprKind::Block(stmts, tail) => ExprKind::Block(
+            stmts.into_iter().map(|x| f.fold_expr(x)).collect(),
+            Box::new(tail.map(|x| f.fold_expr(x))),
+        ),
+        ExprKind::Let(name, span, t, x) => {
+            ExprKind::Let(name, span, t.map(|t| f.fold_type(t)), boxed(f, x))
+        }
+        ExprKind::If(first, elifs, els) => ExprKind::If(
+            Box::new(pair(f, *first)),
+            elifs.into_iter().map(|x| pair(f, x)).collect(),
+            Box::new(els.map(|x| f.fold_expr(x))),
+        ),
+        ExprKind::While(x, y) => ExprKind::While(boxed(f, x), boxed(f, y)),
+        ExprKind::Return(x) => ExprKind::Return(Box::new(x.map(|x| f.fold_expr(x)))),
+        ExprKind::Set(x, y) => ExprKind::Set(boxed(f, x), boxed(f, y)),
+        ExprKind::CompoundSet(op, x, y) => ExprKind::CompoundSet(op, boxed(f, x), boxed(f, y)),
+        ExprKind::For(init, cond, step, body) => ExprKind::For(
+            boxed(f, init),
+            boxed(f, cond),
+            boxed(f, step),
+            boxed(f, body),
+        ),
+        ExprKind::Lambda(captures, params, ret, body) => ExprKind::Lambda(
+            captures,
+            params
+                .into_iter()
+                .map(|(name, t)| (name, f.fold_type(t)))
+                .collect(),
+            f.fold_type(ret),
+            boxed(f, body),
+        ),
+        ExprKind::Match(x, arms) => ExprKind::Match(
+            boxed(f, x),
+            arms.into_iter()
+                .map(|(p, x)| (f.fold_pattern(p), f.fold_expr(x)))
+                .collect(),
+        ),
+        kind @ (ExprKind::I32Literal(_)
+        | ExprKind::I64Literal(_)
+        | ExprKind::F32Literal(_)
+        | ExprKind::F64Literal(_)
+        | ExprKind::StringLiteral(_)
+        | ExprKind::BoolLiteral(_)
+        | ExprKind::CharLiteral(_)
+        | ExprKind::Var(_)
+        | ExprKind::Resolved(..)
+        | ExprKind::Break
+        | ExprKind::Continue
+        | ExprKind::Error) => kind,
+    };
+    Expr::new(kind, x.span)
+}
+
+pub fn walk_type<F: Folder>(f: &mut F, x: Type) -> Type {
+    match x {
+        Type::RefType(RefType::Array(x)) => {
+            Type::RefType(RefType::Array(Box::new(f.fold_type(*x))))
+        }
+        Type::RefType(RefType::Struct(name, xs)) => Type::RefType(RefType::Struct(
+            name,
+            xs.into_iter().map(|x| f.fold_type(x)).collect(),
+        )),
+        Type::RefType(RefType::Enum(name, xs)) => Type::RefType(RefType::Enum(
+            name,
+            xs.into_iter().map(|x| f.fold_type(x)).collect(),
+        )),
+        Type::RefType(RefType::Func(xs, ret)) => Type::RefType(RefType::Func(
+            xs.into_iter().map(|x| f.fold_type(x)).collect(),
+            Box::new(ret.map(|x| f.fold_type(x))),
+        )),
+        x => x,
+    }
+}
+
+pub fn walk_pattern<F: Folder>(f: &mut F, x: Pattern) -> Pattern {
+    match x {
+        Pattern::Literal(x) => Pattern::Literal(f.fold_expr(x)),
+        Pattern::Variant(enum_, variant, xs) => Pattern::Variant(
+            enum_,
+            variant,
+            xs.into_iter().map(|x| f.fold_pattern(x)).collect(),
+        ),
+        x => x,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Span;
+    use crate::parser::parse_source;
+
+    struct ConstFold;
+
+    impl Folder for ConstFold {
+        fn fold_expr(&mut self, x: Expr) -> Expr {
+            let x = walk_expr(self, x);
+            match &x.kind {
+                ExprKind::Add(a, b) => match (&a.kind, &b.kind) {
+                    (ExprKind::I32Literal(a), ExprKind::I32Literal(b)) => {
+                        Expr::new(ExprKind::I32Literal(a.wrapping_add(*b)), x.span)
+                    }
+                    _ => x,
+                },
+                _ => x,
+            }
+        }
+    }
+
+    #[test]
+    fn folder_test() {
+        let (module, _) = parse_source("fun f() -> i32 { 1 + 2 + 3 }");
+        let module = ConstFold.fold_module(module);
+        match &module[0].kind {
+            MemberKind::Func(_, body) => match &body.kind {
+                ExprKind::Block(stmts, tail) => {
+                    assert!(stmts.is_empty());
+                    assert_eq!(
+                        tail.as_ref().as_ref().unwrap(),
+                        &Expr::new(ExprKind::I32Literal(6), Span::new(17, 9))
+                    );
+                }
+                x => panic!("{:?}", x),
+            },
+            x => panic!("{:?}", x),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use crate::parser::parse_source;
+use crate::pretty::{print_module_with, Comment, Layout};
+use crate::structural::module_eq_ignore_spans;
+use diagnostics::diagnostic::Diagnostic;
+use diagnostics::span::Span;
+use parser::parser::Parser;
+use parser::stream::Stream;
+use token::parser::{lexer_diagnostic, lexer_with_trivia};
+use token::token::{KeywordTable, TriviaKind};
+
+// Reprints a source file in the style of `pretty`, keeping its comments and single blank lines.
+
+fn layout(src: &[char]) -> Result<Layout, Diagnostic> {
+    let (_, trivia) = lexer_with_trivia(&KeywordTable::default())
+        .parse(&mut Stream::new(src.to_vec()))
+        .map_err(|e| lexer_diagnostic(&e))?;
+    let comments = trivia
+        .into_iter()
+        .filter(|x| x.kind != TriviaKind::Whitespace)
+        .map(|x| {
+            let text = src[x.pos..x.pos + x.len].iter().collect::<String>();
+            let trailing = src[..x.pos]
+                .iter()
+                .rev()
+                .take_while(|&&c| c != '\n')
+                .any(|c| !c.is_whitespace());
+            let leading = src[x.pos + x.len..]
+                .iter()
+                .take_while(|&&c| c != '\n')
+                .any(|c| !c.is_whitespace());
+            Comment {
+                pos: x.pos,
+                len: x.len,
+                text: text.trim_end().to_string(),
+                trailing,
+                leading,
+            }
+        })
+        .collect();
+    let mut blank_lines = Vec::new();
+    let mut pos = 0;
+    for line in src.split(|&c| c == '\n') {
+        if line.iter().all(|c| c.is_whitespace()) {
+            blank_lines.push(pos);
+        }
+        pos += line.len() + 1;
+    }
+    Ok(Layout {
+        comments,
+        blank_lines,
+    })
+}
+
+pub fn format_source(src: &str) -> Result<String, Vec<Diagnostic>> {
+    let (module, diagnostics) = parse_source(src);
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(diagnostics);
+    }
+    let chars = src.chars().collect::<Vec<_>>();
+    let out = print_module_with(&module, layout(&chars).map_err(|x| vec![x])?);
+    // A guard against printer bugs: the output must mean the same as the input.
+    let (formatted, diagnostics) = parse_source(&out);
+    if diagnostics.iter().any(Diagnostic::is_error) || !module_eq_ignore_spans(&module, &formatted)
+    {
+        return Err(vec![Diagnostic::new(
+            "formatting would change the meaning of the file".to_string(),
+            Span::new(0, 0),
+        )]);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_source_test() {
+        let src = "// The entry point.
+import std.math; // for sqrt
+
+/* A point. */ struct P { x: i32, y: i32 }
+fun main() -> i32 {
+    // Nothing yet.
+    let p = P { x: 1, y: 2 };
+
+
+    let q = add(p.x, // the first
+        2);
+    if q > 2 { /* never */ }
+    q   }
+fun some_function_with_a_long_name(first_argument: i32, second_argument: i32, third_argument: i32) -> i32 {
+    some_function_with_a_long_name(first_argument + 1, second_argument * 100000, third_argument - 1000)
+}
+// The end.
+";
+        let expected = "// The entry point.
+import std.math; // for sqrt
+
+/* A point. */ struct P {
+    x: i32,
+    y: i32
+}
+
+fun main() -> i32 {
+    // Nothing yet.
+    let p = P { x: 1, y: 2 };
+
+    let q = add(
+        p.x, // the first
+        2
+    );
+    if q > 2 {
+        /* never */
+    }
+    q
+}
+
+fun some_function_with_a_long_name(
+    first_argument: i32,
+    second_argument: i32,
+    third_argument: i32
+) -> i32 {
+    some_function_with_a_long_name(
+        first_argument + 1,
+        second_argument * 100000,
+        third_argument - 1000
+    )
+}
+// The end.
+";
+        assert_eq!(format_source(src).as_deref(), Ok(expected));
+        assert_eq!(format_source(expected).as_deref(), Ok(expected));
+        assert!(format_source("fun main( {").is_err());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let src = "fun f(x: i32) -> i32 {
+    x += 2; // bump
+    let y = x *   /* twice */ 2 +
+        // and one more
+        1;
+    x = f(/* first */ y, // the rest
+        3);
+    let z = y
+        /* half */ / 2;
+    y
+}
+";
+        let expected = "fun f(x: i32) -> i32 {
+    x += 2; // bump
+    let y = x * /* twice */ 2 +
+        // and one more
+        1;
+    x = f(
+        /* first */ y, // the rest
+        3
+    );
+    let z = y / /* half */ 2;
+    y
+}
+";
+        let once = format_source(src).unwrap();
+        assert_eq!(once, expected);
+        assert_eq!(format_source(&once).as_deref(), Ok(expected));
+    }
+
+    #[test]
+    fn wrap_test() {
+        let src = "fun f() -> i32 {
+    let total = first_operand_value + second_operand_value * 2 - third_operand_value + fourth_operand;
+    g(first_operand_value + second_operand_value, third_operand_value + fourth_operand_value * 10000);
+    a && b
+}
+";
+        let expected = "fun f() -> i32 {
+    let total = first_operand_value
+        + second_operand_value * 2
+        - third_operand_value
+        + fourth_operand;
+    g(
+        first_operand_value + second_operand_value,
+        third_operand_value + fourth_operand_value * 10000
+    );
+    a && b
+}
+";
+        let once = format_source(src).unwrap();
+        assert_eq!(once, expected);
+        assert_eq!(format_source(&once).as_deref(), Ok(expected));
+    }
+}
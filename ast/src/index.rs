@@ -0,0 +1,202 @@
+use crate::ast::{Expr, Member, Module, Span};
+use crate::visit::{self, Visitor};
+use std::collections::HashMap;
+
+// Position and parent queries over a parsed module for editor tooling. Members and expressions
+// (including the literals inside patterns) are numbered in preorder. Positions are char offsets,
+// like `Span`.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Node<'a> {
+    Member(&'a Member),
+    Expr(&'a Expr),
+}
+
+impl<'a> Node<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Member(x) => x.span,
+            Node::Expr(x) => x.span,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry<'a> {
+    node: Node<'a>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+#[derive(Debug)]
+pub struct AstIndex<'a> {
+    entries: Vec<Entry<'a>>,
+    roots: Vec<NodeId>,
+    // Keyed by address, so that a node borrowed from the same module can be looked up.
+    members: HashMap<*const Member, NodeId>,
+    exprs: HashMap<*const Expr, NodeId>,
+}
+
+fn contains(span: Span, pos: usize) -> bool {
+    span.pos <= pos && pos < span.end()
+}
+
+impl<'a> AstIndex<'a> {
+    pub fn new(module: &'a Module) -> AstIndex<'a> {
+        let mut builder = Builder {
+            index: AstIndex {
+                entries: Vec::new(),
+                roots: Vec::new(),
+                members: HashMap::new(),
+                exprs: HashMap::new(),
+            },
+            parent: None,
+        };
+        builder.visit_module(module);
+        builder.index
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, id: NodeId) -> Node<'a> {
+        self.entries[id.0].node
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.entries[id.0].parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.entries[id.0].children
+    }
+
+    // From the parent of `id` up to its member.
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.parent(id), move |&id| self.parent(id))
+    }
+
+    pub fn member_id(&self, x: &Member) -> Option<NodeId> {
+        self.members.get(&(x as *const Member)).copied()
+    }
+
+    pub fn expr_id(&self, x: &Expr) -> Option<NodeId> {
+        self.exprs.get(&(x as *const Expr)).copied()
+    }
+
+    // The innermost node whose span contains `pos`.
+    pub fn node_at(&self, pos: usize) -> Option<NodeId> {
+        let mut found = None;
+        let mut candidates = &self.roots[..];
+        while let Some(&id) = candidates
+            .iter()
+            .find(|&&id| contains(self.get(id).span(), pos))
+        {
+            found = Some(id);
+            candidates = self.children(id);
+        }
+        found
+    }
+}
+
+struct Builder<'a> {
+    index: AstIndex<'a>,
+    parent: Option<NodeId>,
+}
+
+impl<'a> Builder<'a> {
+    fn push(&mut self, node: Node<'a>, walk: impl FnOnce(&mut Builder<'a>)) -> NodeId {
+        let id = NodeId(self.index.entries.len());
+        self.index.entries.push(Entry {
+            node,
+            parent: self.parent,
+            children: Vec::new(),
+        });
+        match self.parent {
+            Some(parent) => self.index.entries[parent.0].children.push(id),
+            None => self.index.roots.push(id),
+        }
+        let parent = self.parent.replace(id);
+        walk(self);
+        self.parent = parent;
+        id
+    }
+}
+
+impl<'a> Visitor<'a> for Builder<'a> {
+    fn visit_member(&mut self, x: &'a Member) {
+        let id = self.push(Node::Member(x), |b| visit::walk_member(b, x));
+        self.index.members.insert(x, id);
+    }
+
+    fn visit_expr(&mut self, x: &'a Expr) {
+        let id = self.push(Node::Expr(x), |b| visit::walk_expr(b, x));
+        self.index.exprs.insert(x, id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{ExprKind, MemberKind};
+    use crate::parser::parse_source;
+
+    #[test]
+    fn index_test() {
+        let src = "fun f(a: i32) -> i32 { a + g(a * 2) }\n\nfun g(x: i32) -> i32 { x }";
+        let (module, _) = parse_source(src);
+        let index = AstIndex::new(&module);
+        assert_eq!(index.len(), 12);
+
+        let two = index.node_at(src.find("* 2").unwrap() + 2).unwrap();
+        assert!(matches!(
+            index.get(two),
+            Node::Expr(Expr {
+                kind: ExprKind::I32Literal(2),
+                ..
+            })
+        ));
+        let path = index
+            .ancestors(two)
+            .map(|id| match index.get(id) {
+                Node::Expr(x) => x.to_sexpr(),
+                Node::Member(_) => "member".to_string(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            path,
+            vec![
+                "(mul (var a) (i32 2))",
+                "(call (var g) (mul (var a) (i32 2)))",
+                "(add (var a) (call (var g) (mul (var a) (i32 2))))",
+                "(block (add (var a) (call (var g) (mul (var a) (i32 2)))))",
+                "member",
+            ]
+        );
+
+        let body = match &module[1].kind {
+            MemberKind::Func(_, body) => body,
+            _ => panic!(),
+        };
+        let id = index.expr_id(body).unwrap();
+        assert_eq!(index.parent(id), index.member_id(&module[1]));
+        assert_eq!(index.children(id).len(), 1);
+
+        assert_eq!(index.node_at(src.find("\n\n").unwrap() + 1), None);
+        assert_eq!(index.node_at(1), index.member_id(&module[0]));
+    }
+}
@@ -1,2 +1,24 @@
+// Parser errors carry the offending `Token`, which makes `ParserResult<_, Token>` large.
+#![allow(clippy::result_large_err)]
+
+pub mod arena;
 pub mod ast;
-pub mod parser;
\ No newline at end of file
+pub mod codegen;
+pub mod desugar;
+pub mod fold;
+pub mod formatter;
+pub mod index;
+pub mod math;
+pub mod modules;
+pub mod parser;
+pub mod pretty;
+pub mod resolver;
+pub mod runtime;
+pub mod sexpr;
+pub mod sourcemap;
+pub mod structural;
+#[cfg(test)]
+pub mod testing;
+pub mod typeck;
+pub mod visit;
+pub mod wasi;
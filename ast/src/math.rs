@@ -0,0 +1,171 @@
+use wasm::ast::FunctionBody;
+use wasm::wat_parser::parse_module;
+
+// `pow(x: f64, y: f64) -> f64`, which `**` on floats calls since wasm has no instruction for it.
+// Integer exponents below 2^53 are multiplied out by squaring; the others go through
+// `exp(y * ln(x))` with series for both, which can be off from the `pow` of the C library in the
+// last few digits. Special cases such as NaN, zeros, infinities and negative bases follow
+// IEEE 754.
+const POW: &str = r#"
+(module
+  (func (export "pow") (param $x f64) (param $y f64) (result f64)
+    (local $r f64) (local $n i64) (local $e i64) (local $bits i64)
+    (local $m f64) (local $s f64) (local $z f64) (local $k f64) (local $i f64)
+    (if (f64.eq (local.get $y) (f64.const 0))
+      (then (return (f64.const 1))))
+    (if (f64.eq (local.get $x) (f64.const 1))
+      (then (return (f64.const 1))))
+    (if (i32.and
+          (f64.eq (f64.trunc (local.get $y)) (local.get $y))
+          (f64.lt (f64.abs (local.get $y)) (f64.const 9007199254740992)))
+      (then
+        (local.set $n (i64.trunc_f64_s (f64.abs (local.get $y))))
+        (local.set $r (f64.const 1))
+        (block $done
+          (loop $next
+            (if (i64.ne (i64.and (local.get $n) (i64.const 1)) (i64.const 0))
+              (then (local.set $r (f64.mul (local.get $r) (local.get $x)))))
+            (local.set $n (i64.shr_u (local.get $n) (i64.const 1)))
+            (br_if $done (i64.eqz (local.get $n)))
+            (local.set $x (f64.mul (local.get $x) (local.get $x)))
+            (br $next)))
+        (if (f64.lt (local.get $y) (f64.const 0))
+          (then (local.set $r (f64.div (f64.const 1) (local.get $r)))))
+        (return (local.get $r))))
+    (if (f64.ne (local.get $x) (local.get $x))
+      (then (return (local.get $x))))
+    (if (f64.ne (local.get $y) (local.get $y))
+      (then (return (local.get $y))))
+    ;; Negative bases other than -inf only have powers for integer exponents, which are even
+    ;; from 2^53 on.
+    (if (f64.lt (local.get $x) (f64.const 0))
+      (then
+        (if (i32.and
+              (f64.ne (f64.trunc (local.get $y)) (local.get $y))
+              (f64.ne (local.get $x) (f64.const -inf)))
+          (then (return (f64.const nan))))
+        (local.set $x (f64.neg (local.get $x)))
+        (if (f64.eq (local.get $x) (f64.const 1))
+          (then (return (f64.const 1))))))
+    (if (f64.eq (local.get $x) (f64.const 0))
+      (then (return (select (f64.const 0) (f64.const inf) (f64.gt (local.get $y) (f64.const 0))))))
+    (if (f64.eq (local.get $x) (f64.const inf))
+      (then (return (select (f64.const inf) (f64.const 0) (f64.gt (local.get $y) (f64.const 0))))))
+
+    ;; ln(x) = e * ln(2) + ln(m) for x = m * 2^e with m in [sqrt(2) / 2, sqrt(2)), where
+    ;; ln(m) = 2 * (s + s^3 / 3 + s^5 / 5 + ...) for s = (m - 1) / (m + 1).
+    (local.set $e (i64.const -1023))
+    (if (f64.lt (local.get $x) (f64.const 2.2250738585072014e-308))
+      (then
+        (local.set $x (f64.mul (local.get $x) (f64.const 18014398509481984)))
+        (local.set $e (i64.const -1077))))
+    (local.set $bits (i64.reinterpret_f64 (local.get $x)))
+    (local.set $e (i64.add (local.get $e) (i64.shr_u (local.get $bits) (i64.const 52))))
+    (local.set $m (f64.reinterpret_i64 (i64.or
+      (i64.and (local.get $bits) (i64.const 0xf_ffff_ffff_ffff))
+      (i64.const 0x3ff0_0000_0000_0000))))
+    (if (f64.gt (local.get $m) (f64.const 1.4142135623730951))
+      (then
+        (local.set $m (f64.mul (local.get $m) (f64.const 0.5)))
+        (local.set $e (i64.add (local.get $e) (i64.const 1)))))
+    (local.set $s (f64.div
+      (f64.sub (local.get $m) (f64.const 1))
+      (f64.add (local.get $m) (f64.const 1))))
+    (local.set $z (f64.mul (local.get $s) (local.get $s)))
+    (local.set $i (f64.const 25))
+    (loop $series
+      (local.set $k (f64.add
+        (f64.div (f64.const 1) (local.get $i))
+        (f64.mul (local.get $z) (local.get $k))))
+      (local.set $i (f64.sub (local.get $i) (f64.const 2)))
+      (br_if $series (f64.gt (local.get $i) (f64.const 0))))
+    (local.set $z (f64.mul (local.get $y) (f64.add
+      (f64.mul (f64.convert_i64_s (local.get $e)) (f64.const 0.6931471805599453))
+      (f64.mul (f64.mul (f64.const 2) (local.get $s)) (local.get $k)))))
+
+    ;; exp(z) = 2^k * exp(s) for s = z - k * ln(2), with the Taylor series of exp(s).
+    (if (f64.gt (local.get $z) (f64.const 709.782712893384))
+      (then (return (f64.const inf))))
+    (if (f64.lt (local.get $z) (f64.const -745.1332191019412))
+      (then (return (f64.const 0))))
+    (local.set $k (f64.nearest (f64.mul (local.get $z) (f64.const 1.4426950408889634))))
+    (local.set $s (f64.sub
+      (f64.sub (local.get $z) (f64.mul (local.get $k) (f64.const 0.6931471803691238)))
+      (f64.mul (local.get $k) (f64.const 1.9082149292705877e-10))))
+    (local.set $m (f64.const 1))
+    (local.set $i (f64.const 14))
+    (loop $series
+      (local.set $m (f64.add
+        (f64.const 1)
+        (f64.div (f64.mul (local.get $s) (local.get $m)) (local.get $i))))
+      (local.set $i (f64.sub (local.get $i) (f64.const 1)))
+      (br_if $series (f64.gt (local.get $i) (f64.const 0))))
+    ;; 2^k in two halves, each the exponent of a normal number.
+    (local.set $n (i64.trunc_f64_s (local.get $k)))
+    (local.set $e (i64.div_s (local.get $n) (i64.const 2)))
+    (f64.mul
+      (f64.mul
+        (local.get $m)
+        (f64.reinterpret_i64 (i64.shl
+          (i64.add (local.get $e) (i64.const 1023))
+          (i64.const 52))))
+      (f64.reinterpret_i64 (i64.shl
+        (i64.add (i64.sub (local.get $n) (local.get $e)) (i64.const 1023))
+        (i64.const 52))))))
+"#;
+
+pub fn pow_body() -> FunctionBody {
+    parse_module(POW).unwrap().code_section.unwrap().0.remove(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::interp::{Imports, Instance, Value};
+
+    #[test]
+    fn pow_test() {
+        let mut instance = Instance::new(parse_module(POW).unwrap(), Imports::new()).unwrap();
+        let mut pow = |x: f64, y: f64| match instance
+            .invoke("pow", &[Value::F64(x), Value::F64(y)])
+            .as_deref()
+        {
+            Ok(&[Value::F64(z)]) => z,
+            result => panic!("{:?}", result),
+        };
+        let xs = [
+            0.0, -0.0, 0.5, 1.0, -1.0, 2.0, -2.0, 3.7, 10.0, 1e-310, 1e300, -8.0,
+        ];
+        let ys = [
+            0.0,
+            1.0,
+            2.0,
+            3.0,
+            -1.0,
+            -3.0,
+            0.5,
+            -0.5,
+            1.0 / 3.0,
+            2.5,
+            64.0,
+            1e20,
+        ];
+        let specials = [f64::NAN, f64::INFINITY, f64::NEG_INFINITY];
+        for &x in xs.iter().chain(&specials) {
+            for &y in ys.iter().chain(&specials).chain(&[-1e20, 1023.5]) {
+                let (expected, actual) = (x.powf(y), pow(x, y));
+                let ok = if expected.is_nan() {
+                    actual.is_nan()
+                } else if expected == 0.0 || expected.is_infinite() {
+                    expected == actual && expected.is_sign_negative() == actual.is_sign_negative()
+                } else {
+                    ((actual - expected) / expected).abs() < 1e-13
+                };
+                assert!(ok, "{} ** {} is {}, not {}", x, y, expected, actual);
+            }
+        }
+        assert_eq!(pow(2.0, 10.0), 1024.0);
+        assert_eq!(pow(-2.0, 3.0), -8.0);
+        assert_eq!(pow(4.0, 0.5), 2.0);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::ast::{Ident, MemberKind, Module};
+use crate::parser::parse_source;
+use diagnostics::diagnostic::Diagnostic;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const EXTENSION: &str = "tl";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadedModule {
+    pub path: PathBuf,
+    pub module: Module,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(PathBuf, io::Error),
+    // The import chain that leads back to its first module.
+    Cycle(Vec<PathBuf>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            LoadError::Cycle(paths) => write!(
+                f,
+                "import cycle: {}",
+                paths
+                    .iter()
+                    .map(|x| x.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            ),
+        }
+    }
+}
+
+impl error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LoadError::Io(_, e) => Some(e),
+            LoadError::Cycle(_) => None,
+        }
+    }
+}
+
+// `import a.b;` refers to `<root>/a/b.tl`.
+pub fn import_path(root: &Path, import: &[Ident]) -> PathBuf {
+    let mut path = root.to_path_buf();
+    path.extend(import);
+    path.set_extension(EXTENSION);
+    path
+}
+
+pub fn imports(module: &Module) -> impl Iterator<Item = &Vec<Ident>> {
+    module.iter().filter_map(|x| match &x.kind {
+        MemberKind::Import(x) => Some(x),
+        _ => None,
+    })
+}
+
+struct Loader<F> {
+    root: PathBuf,
+    read: F,
+    loaded: Vec<LoadedModule>,
+    stack: Vec<PathBuf>,
+}
+
+impl<F: Fn(&Path) -> io::Result<String>> Loader<F> {
+    fn load(&mut self, path: PathBuf) -> Result<(), LoadError> {
+        if let Some(i) = self.stack.iter().position(|x| x == &path) {
+            let mut cycle = self.stack[i..].to_vec();
+            cycle.push(path);
+            return Err(LoadError::Cycle(cycle));
+        }
+        if self.loaded.iter().any(|x| x.path == path) {
+            return Ok(());
+        }
+        let src = (self.read)(&path).map_err(|e| LoadError::Io(path.clone(), e))?;
+        let (module, diagnostics) = parse_source(&src);
+        self.stack.push(path.clone());
+        for import in imports(&module) {
+            self.load(import_path(&self.root, import))?;
+        }
+        self.stack.pop();
+        self.loaded.push(LoadedModule {
+            path,
+            module,
+            diagnostics,
+        });
+        Ok(())
+    }
+}
+
+// Loads `entry` and everything it imports, transitively. Imports are resolved relative to the
+// directory of `entry`, and the modules are returned dependencies first.
+pub fn load_with<F: Fn(&Path) -> io::Result<String>>(
+    entry: &Path,
+    read: F,
+) -> Result<Vec<LoadedModule>, LoadError> {
+    let mut loader = Loader {
+        root: entry.parent().map(Path::to_path_buf).unwrap_or_default(),
+        read,
+        loaded: Vec::new(),
+        stack: Vec::new(),
+    };
+    loader.load(entry.to_path_buf())?;
+    Ok(loader.loaded)
+}
+
+pub fn load(entry: &Path) -> Result<Vec<LoadedModule>, LoadError> {
+    load_with(entry, |x| fs::read_to_string(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn helper(files: &[(&str, &str)]) -> Result<Vec<PathBuf>, LoadError> {
+        let files = files
+            .iter()
+            .map(|&(path, src)| (PathBuf::from(path), src.to_string()))
+            .collect::<HashMap<_, _>>();
+        load_with(Path::new("src/main.tl"), |path| {
+            files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        })
+        .map(|xs| xs.into_iter().map(|x| x.path).collect())
+    }
+
+    #[test]
+    fn load_test() {
+        assert_eq!(
+            helper(&[
+                ("src/main.tl", "import std.math; import util; fun main() {}"),
+                ("src/std/math.tl", "pub fun abs(x: i32) -> i32 { x }"),
+                ("src/util.tl", "import std.math;"),
+            ])
+            .unwrap(),
+            vec![
+                PathBuf::from("src/std/math.tl"),
+                PathBuf::from("src/util.tl"),
+                PathBuf::from("src/main.tl"),
+            ]
+        );
+        match helper(&[
+            ("src/main.tl", "import a;"),
+            ("src/a.tl", "import b;"),
+            ("src/b.tl", "import a;"),
+        ]) {
+            Err(LoadError::Cycle(xs)) => assert_eq!(
+                xs,
+                vec![
+                    PathBuf::from("src/a.tl"),
+                    PathBuf::from("src/b.tl"),
+                    PathBuf::from("src/a.tl"),
+                ]
+            ),
+            x => panic!("{:?}", x),
+        }
+        assert!(matches!(
+            helper(&[("src/main.tl", "import missing;")]),
+            Err(LoadError::Io(..))
+        ));
+    }
+}
@@ -1,19 +1,1800 @@
+use crate::ast::{
+    AssignOp, Expr, ExprKind, FuncDef, Member, MemberKind, Module, Mutability, Pattern, RefType,
+    Span, Type, TypeParam, Variant, Visibility,
+};
+use diagnostics::code::Code;
+use diagnostics::diagnostic::Diagnostic;
+use parser::or;
+use parser::parser::{
+    eof, parser_func, pratt_by, Assoc, ErrorExpect, Operator, Parser, ParserError,
+};
+use parser::stream::Stream;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use token::parser::{lexer, lexer_diagnostic};
+use token::token::{Keyword, KeywordTable, Kind, Literal, NumLiteral, Symbol, Token};
 
-use crate::ast::Expr;
-use parser::parser::{token, val, Parser};
-use token::token::{Kind, Symbol};
+type BinaryFn = fn(Box<Expr>, Box<Expr>) -> ExprKind;
 
-pub fn expr() -> impl Parser<Input = Kind, Output = Expr> {
-        unimplemented!();
-        val(Expr::I32Literal(1))
+fn peak_kind(st: &Stream<Token>) -> Option<Kind> {
+    st.peak().map(|x| x.kind)
 }
 
-pub fn block() -> impl Parser<Input = Kind, Output = Expr> {
-        token(Kind::Symbol(Symbol::OpenBrace))
-                .with(expr()
-                        .skip(token(Kind::Symbol(Symbol::Semicolon)))
-                        .attempt()
-                        .many())
-                .and(expr().optional())
-                .map(|(a, b)| Expr::Block(a, Box::new(b)))
+// Span of the tokens consumed since `start`.
+fn span_from(st: &Stream<Token>, start: usize) -> Span {
+    match (
+        st.get(start),
+        st.pos().checked_sub(1).and_then(|i| st.get(i)),
+    ) {
+        (Some(first), Some(last)) if st.pos() > start => first.span().to(last.span()),
+        (_, Some(last)) => Span::new(last.span().end(), 0),
+        (Some(first), None) => Span::new(first.pos, 0),
+        (None, None) => Span::default(),
+    }
+}
+
+fn spanned<P: Parser<Input = Token>>(
+    p: P,
+) -> impl Parser<Input = Token, Output = (P::Output, Span)> {
+    parser_func(move |st| {
+        let start = st.pos();
+        let x = p.parse(st)?;
+        Ok((x, span_from(st, start)))
+    })
+}
+
+fn node<P: Parser<Input = Token, Output = ExprKind>>(
+    p: P,
+) -> impl Parser<Input = Token, Output = Expr> {
+    spanned(p).map(|(kind, span)| Expr::new(kind, span))
+}
+
+fn unexpected(st: &Stream<Token>, x: Option<Token>, expecting: &str) -> ParserError<Token> {
+    let found = match x {
+        Some(x) => format!("`{}`", x.kind),
+        None => "end of file".to_string(),
+    };
+    ParserError::with_message(
+        st.pos(),
+        1,
+        format!("expected {}, found {}", expecting, found),
+    )
+}
+
+pub fn kind(k: Kind) -> impl Parser<Input = Token, Output = Token> {
+    parser_func(move |st: &mut Stream<Token>| match st.peak() {
+        Some(x) if x.kind == k => {
+            st.next();
+            Ok(x)
+        }
+        x => Err(unexpected(st, x, &format!("`{}`", k))),
+    })
+}
+
+fn symbol(s: Symbol) -> impl Parser<Input = Token, Output = Token> {
+    kind(Kind::Symbol(s))
+}
+
+fn keyword(k: Keyword) -> impl Parser<Input = Token, Output = Token> {
+    kind(Kind::Keyword(k))
+}
+
+fn binary(a: Expr, b: Expr, f: BinaryFn) -> Expr {
+    let span = a.span.to(b.span);
+    Expr::new(f(Box::new(a), Box::new(b)), span)
+}
+
+fn op(s: Symbol, prec: usize, assoc: Assoc, build: fn(Expr, Expr) -> Expr) -> Operator<Kind, Expr> {
+    Operator::new(Kind::Symbol(s), prec, assoc, build)
+}
+
+pub fn operators() -> Vec<Operator<Kind, Expr>> {
+    vec![
+        op(Symbol::DotDot, 1, Assoc::Non, |a, b| {
+            binary(a, b, ExprKind::Range)
+        }),
+        op(Symbol::DotDotEq, 1, Assoc::Non, |a, b| {
+            binary(a, b, ExprKind::RangeInclusive)
+        }),
+        op(Symbol::Or, 2, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Or)
+        }),
+        op(Symbol::And, 3, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::And)
+        }),
+        op(Symbol::Eq, 4, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Eq)
+        }),
+        op(Symbol::Ne, 4, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Ne)
+        }),
+        op(Symbol::Lt, 5, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Lt)
+        }),
+        op(Symbol::Lte, 5, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Lte)
+        }),
+        op(Symbol::Gt, 5, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Gt)
+        }),
+        op(Symbol::Gte, 5, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Gte)
+        }),
+        op(Symbol::BitOr, 6, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::BitOr)
+        }),
+        op(Symbol::BitXor, 7, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::BitXor)
+        }),
+        op(Symbol::BitAnd, 8, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::BitAnd)
+        }),
+        op(Symbol::Add, 9, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Add)
+        }),
+        op(Symbol::Sub, 9, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Sub)
+        }),
+        op(Symbol::Mul, 10, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Mul)
+        }),
+        op(Symbol::Div, 10, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Div)
+        }),
+        op(Symbol::Mod, 10, Assoc::Left, |a, b| {
+            binary(a, b, ExprKind::Mod)
+        }),
+    ]
+}
+
+// The binary operators the expression parser knows, keyed by token. Embedders can register
+// extra operators, e.g. a reserved keyword `in` for `a in b` => `contains(a, b)`.
+#[derive(Clone, Debug)]
+pub struct OperatorTable(Vec<Operator<Kind, Expr>>);
+
+impl OperatorTable {
+    pub fn new() -> OperatorTable {
+        OperatorTable(Vec::new())
+    }
+
+    pub fn get(&self, kind: &Kind) -> Option<&Operator<Kind, Expr>> {
+        self.0.iter().find(|x| &x.token == kind)
+    }
+
+    pub fn operators(&self) -> &[Operator<Kind, Expr>] {
+        &self.0
+    }
+
+    pub fn insert(&mut self, op: Operator<Kind, Expr>) -> Option<Operator<Kind, Expr>> {
+        let old = self.remove(&op.token);
+        self.0.push(op);
+        old
+    }
+
+    pub fn remove(&mut self, kind: &Kind) -> Option<Operator<Kind, Expr>> {
+        let i = self.0.iter().position(|x| &x.token == kind)?;
+        Some(self.0.remove(i))
+    }
+
+    // `a op b` parses as the call `func(a, b)`.
+    pub fn register(
+        &mut self,
+        kind: Kind,
+        prec: usize,
+        assoc: Assoc,
+        func: &str,
+    ) -> Option<Operator<Kind, Expr>> {
+        let func = func.to_string();
+        self.insert(Operator::new(kind, prec, assoc, move |a: Expr, b: Expr| {
+            let span = a.span.to(b.span);
+            let f = Expr::new(ExprKind::Var(func.clone()), span);
+            Expr::new(ExprKind::Call(Box::new(f), vec![a, b]), span)
+        }))
+    }
+}
+
+impl Default for OperatorTable {
+    fn default() -> Self {
+        OperatorTable(operators())
+    }
+}
+
+// The operators of the current parse, shared with every `pratt` parser built while it runs.
+thread_local! {
+    static OPERATORS: RefCell<Rc<[Operator<Kind, Expr>]>> = RefCell::new(operators().into());
+}
+
+// Puts the previous operators back when dropped, so a panicking parse does not leak its table
+// into the next one.
+struct RestoreOperators(Rc<[Operator<Kind, Expr>]>);
+
+impl Drop for RestoreOperators {
+    fn drop(&mut self) {
+        OPERATORS.with(|x| x.replace(self.0.clone()));
+    }
+}
+
+fn with_operators<T>(table: &OperatorTable, f: impl FnOnce() -> T) -> T {
+    let _restore = RestoreOperators(OPERATORS.with(|x| x.replace(table.operators().into())));
+    f()
+}
+
+pub fn expr() -> impl Parser<Input = Token, Output = Expr> {
+    assign_expr(true)
+}
+
+// `while x { ... }` would otherwise parse as a struct literal `x { ... }`, so struct literals
+// are not allowed in the header of `while`/`for` unless they are parenthesized.
+pub fn cond() -> impl Parser<Input = Token, Output = Expr> {
+    assign_expr(false)
+}
+
+fn is_place(x: &Expr) -> bool {
+    matches!(
+        x.kind,
+        ExprKind::Var(_) | ExprKind::Member(..) | ExprKind::Index(..)
+    )
+}
+
+fn assign_expr(allow_struct: bool) -> impl Parser<Input = Token, Output = Expr> {
+    parser_func(move |st| {
+        let pos = st.pos();
+        let ops = OPERATORS.with(|x| x.borrow().clone());
+        let lhs = pratt_by(cast_expr(allow_struct), ops, |x: &Token| x.kind.clone()).parse(st)?;
+        let op = match peak_kind(st) {
+            Some(Kind::Symbol(Symbol::Assign)) => None,
+            Some(Kind::Symbol(Symbol::AddAssign)) => Some(AssignOp::Add),
+            Some(Kind::Symbol(Symbol::SubAssign)) => Some(AssignOp::Sub),
+            Some(Kind::Symbol(Symbol::MulAssign)) => Some(AssignOp::Mul),
+            Some(Kind::Symbol(Symbol::DivAssign)) => Some(AssignOp::Div),
+            Some(Kind::Symbol(Symbol::ModAssign)) => Some(AssignOp::Mod),
+            _ => return Ok(lhs),
+        };
+        if !is_place(&lhs) {
+            return Err(ParserError::with_message(
+                pos,
+                st.pos() - pos,
+                "invalid left-hand side of assignment".to_string(),
+            ));
+        }
+        st.next();
+        let rhs = assign_expr(allow_struct).parse(st)?;
+        Ok(match op {
+            Some(op) => {
+                let span = lhs.span.to(rhs.span);
+                Expr::new(
+                    ExprKind::CompoundSet(op, Box::new(lhs), Box::new(rhs)),
+                    span,
+                )
+            }
+            None => binary(lhs, rhs, ExprKind::Set),
+        })
+    })
+}
+
+// `as` binds tighter than every binary operator but looser than the prefix ones, so
+// `-x as i64` casts `-x` and `a * b as i64` only casts `b`.
+fn cast_expr(allow_struct: bool) -> impl Parser<Input = Token, Output = Expr> {
+    parser_func(move |st| {
+        let start = st.pos();
+        let mut x = unary_expr(allow_struct).parse(st)?;
+        while peak_kind(st) == Some(Kind::Keyword(Keyword::As)) {
+            st.next();
+            let t = type_().parse(st)?;
+            x = Expr::new(ExprKind::Cast(Box::new(x), t), span_from(st, start));
+        }
+        Ok(x)
+    })
+}
+
+fn unary_expr(allow_struct: bool) -> impl Parser<Input = Token, Output = Expr> {
+    parser_func(move |st| {
+        let start = st.pos();
+        let f: fn(Box<Expr>) -> ExprKind = match peak_kind(st) {
+            Some(Kind::Symbol(Symbol::Not)) => ExprKind::Not,
+            Some(Kind::Symbol(Symbol::Add)) => ExprKind::Plus,
+            Some(Kind::Symbol(Symbol::Sub)) => ExprKind::Minus,
+            _ => return pow_expr(allow_struct).parse(st),
+        };
+        st.next();
+        let x = unary_expr(allow_struct).parse(st)?;
+        Ok(Expr::new(f(Box::new(x)), span_from(st, start)))
+    })
+}
+
+fn pow_expr(allow_struct: bool) -> impl Parser<Input = Token, Output = Expr> {
+    parser_func(move |st| {
+        let x = postfix_expr(allow_struct).parse(st)?;
+        if peak_kind(st) == Some(Kind::Symbol(Symbol::Pow)) {
+            st.next();
+            let y = unary_expr(allow_struct).parse(st)?;
+            Ok(binary(x, y, ExprKind::Pow))
+        } else {
+            Ok(x)
+        }
+    })
+}
+
+fn postfix_expr(allow_struct: bool) -> impl Parser<Input = Token, Output = Expr> {
+    parser_func(move |st| {
+        let start = st.pos();
+        let mut x = primary(allow_struct).parse(st)?;
+        loop {
+            let kind = match peak_kind(st) {
+                Some(Kind::Symbol(Symbol::OpenBracket)) => {
+                    st.next();
+                    let i = expr().skip(symbol(Symbol::CloseBracket)).parse(st)?;
+                    ExprKind::Index(Box::new(x), Box::new(i))
+                }
+                Some(Kind::Symbol(Symbol::Dot)) => {
+                    st.next();
+                    ExprKind::Member(Box::new(x), ident().parse(st)?)
+                }
+                Some(Kind::Symbol(Symbol::OpenParent)) => {
+                    st.next();
+                    let args = expr()
+                        .sep_by(symbol(Symbol::Comma))
+                        .skip(symbol(Symbol::CloseParent))
+                        .parse(st)?;
+                    ExprKind::Call(Box::new(x), args)
+                }
+                _ => return Ok(x),
+            };
+            x = Expr::new(kind, span_from(st, start));
+        }
+    })
+}
+
+fn primary(allow_struct: bool) -> impl Parser<Input = Token, Output = Expr> {
+    or!(
+        node(let_expr()),
+        node(return_expr()),
+        node(if_expr()),
+        node(while_expr()),
+        node(for_expr()),
+        node(match_expr()),
+        node(jump_expr(Keyword::Break, ExprKind::Break)),
+        node(jump_expr(Keyword::Continue, ExprKind::Continue)),
+        literal(),
+        node(array_literal()),
+        node(lambda()),
+        node(var_or_struct_literal(allow_struct)),
+        paren_expr(),
+        block(),
+        parser_func(|st| Err(unexpected(st, st.peak(), "expression")))
+    )
+}
+
+fn paren_expr() -> impl Parser<Input = Token, Output = Expr> {
+    spanned(
+        symbol(Symbol::OpenParent)
+            .with(expr())
+            .skip(symbol(Symbol::CloseParent)),
+    )
+    .map(|(x, span)| Expr { span, ..x })
+}
+
+fn array_literal() -> impl Parser<Input = Token, Output = ExprKind> {
+    symbol(Symbol::OpenBracket)
+        .with(type_())
+        .skip(symbol(Symbol::Semicolon))
+        .and(expr())
+        .skip(symbol(Symbol::CloseBracket))
+        .map(|(t, len)| ExprKind::ArrayLiteral(t, Box::new(len)))
+}
+
+fn lambda() -> impl Parser<Input = Token, Output = ExprKind> {
+    or!(
+        symbol(Symbol::Or).map(|_| Vec::new()),
+        symbol(Symbol::BitOr)
+            .with(param().sep_by(symbol(Symbol::Comma)))
+            .skip(symbol(Symbol::BitOr))
+    )
+    .and(
+        symbol(Symbol::OpenBracket)
+            .with(ident().sep_by(symbol(Symbol::Comma)))
+            .skip(symbol(Symbol::CloseBracket))
+            .optional(),
+    )
+    .skip(symbol(Symbol::Arrow))
+    .and(type_())
+    .and(in_loop(false, block()))
+    .map(|(((params, captures), ret), body)| {
+        ExprKind::Lambda(captures.unwrap_or_default(), params, ret, Box::new(body))
+    })
+}
+
+fn var_or_struct_literal(allow_struct: bool) -> impl Parser<Input = Token, Output = ExprKind> {
+    parser_func(move |st| {
+        let name = ident().parse(st)?;
+        if allow_struct && peak_kind(st) == Some(Kind::Symbol(Symbol::OpenBrace)) {
+            let fields = symbol(Symbol::OpenBrace)
+                .with(
+                    ident()
+                        .skip(symbol(Symbol::Colon))
+                        .and(expr())
+                        .sep_by(symbol(Symbol::Comma)),
+                )
+                .skip(symbol(Symbol::CloseBrace))
+                .parse(st)?;
+            Ok(ExprKind::StructLiteral(name, fields))
+        } else {
+            Ok(ExprKind::Var(name))
+        }
+    })
+}
+
+fn let_expr() -> impl Parser<Input = Token, Output = ExprKind> {
+    keyword(Keyword::Let)
+        .with(spanned(ident()))
+        .and(symbol(Symbol::Colon).with(type_()).optional())
+        .skip(symbol(Symbol::Assign))
+        .and(expr())
+        .map(|(((name, span), t), x)| ExprKind::Let(name, span, t, Box::new(x)))
+}
+
+fn return_expr() -> impl Parser<Input = Token, Output = ExprKind> {
+    keyword(Keyword::Return)
+        .with(expr().optional())
+        .map(|x| ExprKind::Return(Box::new(x)))
+}
+
+fn if_expr() -> impl Parser<Input = Token, Output = ExprKind> {
+    parser_func(|st| {
+        let branch = || keyword(Keyword::If).with(cond()).and(block());
+        let first = branch().parse(st)?;
+        let mut elifs = Vec::new();
+        let mut els = None;
+        while keyword(Keyword::Else).optional().parse(st)?.is_some() {
+            if peak_kind(st) == Some(Kind::Keyword(Keyword::If)) {
+                elifs.push(branch().parse(st)?);
+            } else {
+                els = Some(block().parse(st)?);
+                break;
+            }
+        }
+        Ok(ExprKind::If(Box::new(first), elifs, Box::new(els)))
+    })
+}
+
+fn while_expr() -> impl Parser<Input = Token, Output = ExprKind> {
+    keyword(Keyword::While)
+        .with(cond())
+        .and(in_loop(true, block()))
+        .map(|(cond, body)| ExprKind::While(Box::new(cond), Box::new(body)))
+}
+
+fn for_expr() -> impl Parser<Input = Token, Output = ExprKind> {
+    keyword(Keyword::For)
+        .with(cond())
+        .skip(symbol(Symbol::Semicolon))
+        .and(cond())
+        .skip(symbol(Symbol::Semicolon))
+        .and(cond())
+        .and(in_loop(true, block()))
+        .map(|(((init, cond), step), body)| {
+            ExprKind::For(
+                Box::new(init),
+                Box::new(cond),
+                Box::new(step),
+                Box::new(body),
+            )
+        })
+}
+
+fn jump_expr(k: Keyword, x: ExprKind) -> impl Parser<Input = Token, Output = ExprKind> {
+    parser_func(move |st| {
+        let pos = st.pos();
+        let token = keyword(k.clone()).parse(st)?;
+        if !IN_LOOP.with(|x| x.get()) {
+            record(ParserError::with_message(
+                pos,
+                1,
+                format!("`{}` outside of a loop", token.kind),
+            ));
+        }
+        Ok(x.clone())
+    })
+}
+
+fn match_expr() -> impl Parser<Input = Token, Output = ExprKind> {
+    keyword(Keyword::Match)
+        .with(cond())
+        .skip(symbol(Symbol::OpenBrace))
+        .and(
+            pattern()
+                .skip(symbol(Symbol::FatArrow))
+                .and(expr())
+                .sep_by(symbol(Symbol::Comma)),
+        )
+        .skip(symbol(Symbol::CloseBrace))
+        .map(|(x, arms)| ExprKind::Match(Box::new(x), arms))
+}
+
+pub fn pattern() -> impl Parser<Input = Token, Output = Pattern> {
+    parser_func(|st| match peak_kind(st) {
+        Some(Kind::Ident(x)) => {
+            st.next();
+            if x == "_" {
+                return Ok(Pattern::Wildcard);
+            }
+            if symbol(Symbol::Dot).optional().parse(st)?.is_none() {
+                return Ok(Pattern::Binding(x));
+            }
+            let variant = ident().parse(st)?;
+            let fields = symbol(Symbol::OpenParent)
+                .with(pattern().sep_by(symbol(Symbol::Comma)))
+                .skip(symbol(Symbol::CloseParent))
+                .optional()
+                .parse(st)?;
+            Ok(Pattern::Variant(x, variant, fields.unwrap_or_default()))
+        }
+        _ => literal().map(Pattern::Literal).parse(st),
+    })
+}
+
+pub fn ident() -> impl Parser<Input = Token, Output = String> {
+    parser_func(|st| match peak_kind(st) {
+        Some(Kind::Ident(x)) => {
+            st.next();
+            Ok(x)
+        }
+        _ => Err(unexpected(st, st.peak(), "identifier")),
+    })
+}
+
+pub fn literal() -> impl Parser<Input = Token, Output = Expr> {
+    parser_func(|st| {
+        let token = st.peak();
+        let kind = match peak_kind(st) {
+            Some(Kind::Literal(Literal::Num(NumLiteral::I32(x)))) => ExprKind::I32Literal(x),
+            Some(Kind::Literal(Literal::Num(NumLiteral::I64(x)))) => ExprKind::I64Literal(x),
+            Some(Kind::Literal(Literal::Num(NumLiteral::F32(x)))) => ExprKind::F32Literal(x),
+            Some(Kind::Literal(Literal::Num(NumLiteral::F64(x)))) => ExprKind::F64Literal(x),
+            Some(Kind::Literal(Literal::Byte(x))) => ExprKind::I32Literal(x as i32),
+            // There is no value of bytes to lower them to yet.
+            Some(Kind::Literal(Literal::ByteString(_))) => {
+                record(ParserError::with_message(
+                    st.pos(),
+                    1,
+                    "byte strings are not supported here".to_string(),
+                ));
+                ExprKind::Error
+            }
+            Some(Kind::Literal(Literal::Char(x))) => ExprKind::CharLiteral(x),
+            Some(Kind::Literal(Literal::String(x))) => ExprKind::StringLiteral(x),
+            Some(Kind::Keyword(Keyword::True)) => ExprKind::BoolLiteral(true),
+            Some(Kind::Keyword(Keyword::False)) => ExprKind::BoolLiteral(false),
+            _ => return Err(unexpected(st, token, "expression")),
+        };
+        st.next();
+        Ok(Expr::new(kind, token.unwrap().span()))
+    })
+}
+
+// Returns the statement and whether it is the tail expression of the block.
+fn stmt() -> impl Parser<Input = Token, Output = (Expr, bool)> {
+    parser_func(|st| {
+        let x = expr().parse(st)?;
+        if symbol(Symbol::Semicolon).optional().parse(st)?.is_some() {
+            return Ok((x, false));
+        }
+        match peak_kind(st) {
+            Some(Kind::Symbol(Symbol::CloseBrace)) => Ok((x, true)),
+            _ if is_block_like(&x) => Ok((x, false)),
+            _ => Err(unexpected(st, st.peak(), "`;` or `}`")),
+        }
+    })
+}
+
+pub fn block() -> impl Parser<Input = Token, Output = Expr> {
+    parser_func(|st| {
+        let start = st.pos();
+        symbol(Symbol::OpenBrace).parse(st)?;
+        let mut stmts = Vec::new();
+        loop {
+            if symbol(Symbol::CloseBrace).optional().parse(st)?.is_some() {
+                let kind = ExprKind::Block(stmts, Box::new(None));
+                return Ok(Expr::new(kind, span_from(st, start)));
+            }
+            // A missing `}` would otherwise swallow the following members as statements.
+            match peak_kind(st) {
+                Some(x) if !is_member_start(&x) => {}
+                _ => return Err(unexpected(st, st.peak(), "`}`")),
+            }
+            let stmt_start = st.pos();
+            match stmt().parse(st) {
+                Ok((x, false)) => stmts.push(x),
+                Ok((x, true)) => {
+                    symbol(Symbol::CloseBrace).parse(st)?;
+                    let kind = ExprKind::Block(stmts, Box::new(Some(x)));
+                    return Ok(Expr::new(kind, span_from(st, start)));
+                }
+                Err(e) => {
+                    record(e);
+                    skip_stmt(st);
+                    stmts.push(Expr::new(ExprKind::Error, span_from(st, stmt_start)));
+                }
+            }
+        }
+    })
+}
+
+pub(crate) fn is_block_like(x: &Expr) -> bool {
+    matches!(
+        x.kind,
+        ExprKind::Block(..)
+            | ExprKind::If(..)
+            | ExprKind::While(..)
+            | ExprKind::For(..)
+            | ExprKind::Match(..)
+    )
+}
+
+pub fn type_() -> impl Parser<Input = Token, Output = Type> {
+    parser_func(|st| {
+        let res = match peak_kind(st) {
+            Some(Kind::Keyword(Keyword::I32)) => Type::I32,
+            Some(Kind::Keyword(Keyword::I64)) => Type::I64,
+            Some(Kind::Keyword(Keyword::F32)) => Type::F32,
+            Some(Kind::Keyword(Keyword::F64)) => Type::F64,
+            Some(Kind::Keyword(Keyword::Bool)) => Type::Bool,
+            Some(Kind::Keyword(Keyword::Char)) => Type::Char,
+            Some(Kind::Keyword(Keyword::String)) => Type::RefType(RefType::String),
+            Some(Kind::Ident(x)) => {
+                st.next();
+                if in_scope(&x) {
+                    return Ok(Type::Param(x));
+                }
+                let args = symbol(Symbol::Lt)
+                    .with(type_().sep_by(symbol(Symbol::Comma)))
+                    .skip(symbol(Symbol::Gt))
+                    .optional()
+                    .parse(st)?;
+                return Ok(Type::RefType(RefType::Struct(x, args.unwrap_or_default())));
+            }
+            Some(Kind::Symbol(Symbol::OpenBracket)) => {
+                st.next();
+                let t = type_().skip(symbol(Symbol::CloseBracket)).parse(st)?;
+                return Ok(Type::RefType(RefType::Array(Box::new(t))));
+            }
+            Some(Kind::Keyword(Keyword::Fun)) => {
+                st.next();
+                let (params, ret) = symbol(Symbol::OpenParent)
+                    .with(type_().sep_by(symbol(Symbol::Comma)))
+                    .skip(symbol(Symbol::CloseParent))
+                    .and(symbol(Symbol::Arrow).with(type_()).optional())
+                    .parse(st)?;
+                return Ok(Type::RefType(RefType::Func(params, Box::new(ret))));
+            }
+            _ => return Err(unexpected(st, st.peak(), "type")),
+        };
+        st.next();
+        Ok(res)
+    })
+}
+
+fn param() -> impl Parser<Input = Token, Output = (String, Type)> {
+    ident().skip(symbol(Symbol::Colon)).and(type_())
+}
+
+fn type_params() -> impl Parser<Input = Token, Output = Vec<TypeParam>> {
+    symbol(Symbol::Lt)
+        .with(ident().map(TypeParam).sep_by(symbol(Symbol::Comma)))
+        .skip(symbol(Symbol::Gt))
+        .optional()
+        .map(|x| x.unwrap_or_default())
+}
+
+pub fn func_def() -> impl Parser<Input = Token, Output = FuncDef> {
+    keyword(Keyword::Fun)
+        .with(ident())
+        .and(type_params())
+        .then(|(name, type_params)| {
+            with_type_params(
+                type_params.clone(),
+                symbol(Symbol::OpenParent)
+                    .with(param().sep_by(symbol(Symbol::Comma)))
+                    .skip(symbol(Symbol::CloseParent))
+                    .and(symbol(Symbol::Arrow).with(type_()).optional()),
+            )
+            .map(move |(params, ret)| FuncDef(name.clone(), type_params.clone(), params, ret))
+        })
+}
+
+fn member_node<P: Parser<Input = Token, Output = MemberKind>>(
+    p: P,
+) -> impl Parser<Input = Token, Output = Member> {
+    spanned(p).map(|(kind, span)| Member::new(kind, span))
+}
+
+pub fn struct_def() -> impl Parser<Input = Token, Output = Member> {
+    member_node(
+        keyword(Keyword::Struct)
+            .with(ident())
+            .and(type_params())
+            .then(|(name, type_params)| {
+                with_type_params(
+                    type_params.clone(),
+                    symbol(Symbol::OpenBrace)
+                        .with(param().sep_by(symbol(Symbol::Comma)))
+                        .skip(symbol(Symbol::CloseBrace)),
+                )
+                .map(move |fields| MemberKind::Struct(name.clone(), type_params.clone(), fields))
+            }),
+    )
+}
+
+fn variant() -> impl Parser<Input = Token, Output = Variant> {
+    ident()
+        .and(
+            symbol(Symbol::OpenParent)
+                .with(type_().sep_by(symbol(Symbol::Comma)))
+                .skip(symbol(Symbol::CloseParent))
+                .optional(),
+        )
+        .map(|(name, fields)| Variant(name, fields.unwrap_or_default()))
+}
+
+pub fn enum_def() -> impl Parser<Input = Token, Output = Member> {
+    member_node(
+        keyword(Keyword::Enum)
+            .with(ident())
+            .and(type_params())
+            .then(|(name, type_params)| {
+                with_type_params(
+                    type_params.clone(),
+                    symbol(Symbol::OpenBrace)
+                        .with(variant().sep_by(symbol(Symbol::Comma)))
+                        .skip(symbol(Symbol::CloseBrace)),
+                )
+                .map(move |variants| MemberKind::Enum(name.clone(), type_params.clone(), variants))
+            }),
+    )
+}
+
+fn string_literal() -> impl Parser<Input = Token, Output = String> {
+    parser_func(|st| match peak_kind(st) {
+        Some(Kind::Literal(Literal::String(x))) => {
+            st.next();
+            Ok(x)
+        }
+        _ => Err(unexpected(st, st.peak(), "string literal")),
+    })
+}
+
+pub fn extern_fun() -> impl Parser<Input = Token, Output = Member> {
+    member_node(
+        keyword(Keyword::Extern)
+            .with(func_def())
+            .skip(symbol(Symbol::Assign))
+            .and(string_literal())
+            .and(string_literal())
+            .skip(symbol(Symbol::Semicolon))
+            .map(|((def, module), field)| MemberKind::ExternFun(def, module, field)),
+    )
+}
+
+pub fn import() -> impl Parser<Input = Token, Output = Member> {
+    member_node(
+        keyword(Keyword::Import)
+            .with(ident())
+            .and(symbol(Symbol::Dot).with(ident()).many())
+            .skip(symbol(Symbol::Semicolon))
+            .map(|(x, mut xs)| {
+                xs.insert(0, x);
+                MemberKind::Import(xs)
+            }),
+    )
+}
+
+fn mutability() -> impl Parser<Input = Token, Output = Mutability> {
+    or!(
+        keyword(Keyword::Const).map(|_| Mutability::Const),
+        keyword(Keyword::Let)
+            .with(keyword(Keyword::Mut).optional())
+            .map(|x| match x {
+                Some(_) => Mutability::Mutable,
+                None => Mutability::Immutable,
+            })
+    )
+}
+
+pub fn global() -> impl Parser<Input = Token, Output = Member> {
+    member_node(parser_func(|st| {
+        let ((mutability, name), t) = mutability()
+            .and(ident())
+            .skip(symbol(Symbol::Colon))
+            .and(type_())
+            .skip(symbol(Symbol::Assign))
+            .parse(st)?;
+        let start = st.pos();
+        let init = expr().parse(st)?;
+        if !init.is_const() {
+            return Err(ParserError::with_message(
+                start,
+                st.pos() - start,
+                "global initializer must be a constant expression".to_string(),
+            ));
+        }
+        symbol(Symbol::Semicolon).parse(st)?;
+        Ok(MemberKind::Global(name, mutability, t, init))
+    }))
+}
+
+pub fn member() -> impl Parser<Input = Token, Output = Member> {
+    spanned(keyword(Keyword::Pub).optional())
+        .and(or!(
+            member_node(func_def().then(|def| {
+                with_type_params(def.1.clone(), block())
+                    .map(move |body| MemberKind::Func(def.clone(), body))
+            })),
+            struct_def(),
+            enum_def(),
+            extern_fun(),
+            import(),
+            global()
+        ))
+        .map(|((vis, span), x)| match vis {
+            Some(_) => Member {
+                span: span.to(x.span),
+                visibility: Visibility::Public,
+                ..x
+            },
+            None => x,
+        })
+}
+
+pub fn module() -> impl Parser<Input = Token, Output = Module> {
+    parser_func(|st| {
+        let mut members = Vec::new();
+        while !st.eof() {
+            let start = st.pos();
+            match member().parse(st) {
+                Ok(x) => members.push(x),
+                Err(e) => {
+                    record(e);
+                    skip_member(st, start);
+                    members.push(Member::new(MemberKind::Error, span_from(st, start)));
+                }
+            }
+        }
+        Ok(members)
+    })
+}
+
+// Errors recovered from while parsing. Recovery happens deep inside `block` and `module`, so
+// they are collected here rather than threaded through every parser.
+thread_local! {
+    static RECOVERED: RefCell<Vec<ParserError<Token>>> = const { RefCell::new(Vec::new()) };
+}
+
+// Type parameters of the member being parsed, which `type_()` reads as `Type::Param`.
+thread_local! {
+    static TYPE_PARAMS: RefCell<Vec<TypeParam>> = const { RefCell::new(Vec::new()) };
+}
+
+fn in_scope(x: &str) -> bool {
+    TYPE_PARAMS.with(|params| params.borrow().iter().any(|p| p.0 == x))
+}
+
+fn with_type_params<P: Parser<Input = Token>>(
+    params: Vec<TypeParam>,
+    p: P,
+) -> impl Parser<Input = Token, Output = P::Output> {
+    parser_func(move |st| {
+        let saved = TYPE_PARAMS.with(|x| x.replace(params.clone()));
+        let res = p.parse(st);
+        TYPE_PARAMS.with(|x| x.replace(saved));
+        res
+    })
+}
+
+// Whether `break`/`continue` would be inside a loop. Lambda bodies start outside of one.
+thread_local! {
+    static IN_LOOP: Cell<bool> = const { Cell::new(false) };
+}
+
+fn in_loop<P: Parser<Input = Token>>(
+    value: bool,
+    p: P,
+) -> impl Parser<Input = Token, Output = P::Output> {
+    parser_func(move |st| {
+        let saved = IN_LOOP.with(|x| x.replace(value));
+        let res = p.parse(st);
+        IN_LOOP.with(|x| x.set(saved));
+        res
+    })
+}
+
+fn record(e: ParserError<Token>) {
+    RECOVERED.with(|x| x.borrow_mut().push(e));
+}
+
+fn take_recovered() -> Vec<ParserError<Token>> {
+    RECOVERED.with(|x| x.replace(Vec::new()))
+}
+
+fn is_member_start(x: &Kind) -> bool {
+    matches!(
+        x,
+        Kind::Keyword(Keyword::Fun)
+            | Kind::Keyword(Keyword::Struct)
+            | Kind::Keyword(Keyword::Enum)
+            | Kind::Keyword(Keyword::Extern)
+            | Kind::Keyword(Keyword::Import)
+            | Kind::Keyword(Keyword::Pub)
+            | Kind::Keyword(Keyword::Const)
+    )
+}
+
+// Skips to the end of the broken statement: past the next `;`, or up to a `}` or a keyword
+// that starts a member.
+fn skip_stmt(st: &mut Stream<Token>) {
+    while let Some(x) = peak_kind(st) {
+        match x {
+            Kind::Symbol(Symbol::Semicolon) => {
+                st.next();
+                return;
+            }
+            Kind::Symbol(Symbol::CloseBrace) => return,
+            x if is_member_start(&x) => return,
+            _ => {
+                st.next();
+            }
+        }
+    }
+}
+
+fn skip_member(st: &mut Stream<Token>, start: usize) {
+    if st.pos() == start {
+        st.next();
+    }
+    // `let` only starts a member at the top level, so it is not in `is_member_start`.
+    while let Some(x) = peak_kind(st) {
+        if is_member_start(&x) || x == Kind::Keyword(Keyword::Let) {
+            return;
+        }
+        st.next();
+    }
+}
+
+fn with_recovered<T>(
+    st: &mut Stream<Token>,
+    p: impl Parser<Input = Token, Output = T>,
+) -> (Option<T>, Vec<Diagnostic>) {
+    take_recovered();
+    let res = p.parse(st);
+    let mut errors = take_recovered();
+    let res = match res {
+        Ok(x) => Some(x),
+        Err(e) => {
+            errors.push(e);
+            None
+        }
+    };
+    (res, errors.iter().map(|e| diagnostic(st, e)).collect())
+}
+
+fn diagnostic(st: &Stream<Token>, e: &ParserError<Token>) -> Diagnostic {
+    let (pos, len) = e.span();
+    let span_at = |i: usize| match (st.get(i), i.checked_sub(1).and_then(|i| st.get(i))) {
+        (Some(x), _) => x.span(),
+        (None, Some(last)) => Span::new(last.span().end(), 0),
+        (None, None) => Span::default(),
+    };
+    let span = span_at(pos).to(span_at(pos + len.max(1) - 1));
+    let found = match e.unexpected() {
+        Some(x) => format!("`{}`", x.kind),
+        None => "end of file".to_string(),
+    };
+    let message = match (e.message(), e.expecting()) {
+        (Some(message), _) => message.to_string(),
+        (None, ErrorExpect::Eof) => format!("expected end of file, found {}", found),
+        (None, _) => format!("unexpected {}", found),
+    };
+    Diagnostic::new(message, span).with_code(Code::Syntax)
+}
+
+// Broken statements and members are replaced with `Error` nodes, so a module is returned
+// together with every syntax error in it.
+pub fn parse_module(tokens: Vec<Token>) -> (Module, Vec<Diagnostic>) {
+    parse_module_with(tokens, &OperatorTable::default())
+}
+
+pub fn parse_module_with(tokens: Vec<Token>, ops: &OperatorTable) -> (Module, Vec<Diagnostic>) {
+    let (module, diagnostics) =
+        with_operators(ops, || with_recovered(&mut Stream::new(tokens), module()));
+    (module.unwrap_or_default(), diagnostics)
+}
+
+pub fn parse_source(src: &str) -> (Module, Vec<Diagnostic>) {
+    match lexer(&KeywordTable::default()).parse(&mut Stream::new(src.chars().collect())) {
+        Ok(tokens) => parse_module(tokens),
+        Err(e) => (Vec::new(), vec![lexer_diagnostic(&e)]),
+    }
+}
+
+pub fn parse_expr(tokens: Vec<Token>) -> Result<Expr, Vec<Diagnostic>> {
+    parse_expr_with(tokens, &OperatorTable::default())
+}
+
+pub fn parse_expr_with(tokens: Vec<Token>, ops: &OperatorTable) -> Result<Expr, Vec<Diagnostic>> {
+    let res = with_operators(ops, || {
+        with_recovered(&mut Stream::new(tokens), expr().skip(eof()))
+    });
+    match res {
+        (Some(x), diagnostics) if diagnostics.is_empty() => Ok(x),
+        (_, diagnostics) => Err(diagnostics),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr;
+    use crate::structural::erase_module_spans;
+    use crate::testing::{b, e, m};
+
+    fn lex(input: &str) -> Vec<Token> {
+        lexer(&KeywordTable::default())
+            .parse(&mut Stream::new(input.chars().collect()))
+            .unwrap()
+    }
+
+    fn var(x: &str) -> Box<Expr> {
+        b(ExprKind::Var(x.to_string()))
+    }
+
+    // Spans are checked separately in `span_test`, so the other tests compare shapes only.
+    fn clear(x: &mut Expr) {
+        *x = x.clone().erase_spans();
+    }
+
+    fn clear_module(module: &mut Module) {
+        *module = erase_module_spans(std::mem::take(module));
+    }
+
+    fn parse(input: &str) -> Result<Module, Vec<Diagnostic>> {
+        match parse_source(input) {
+            (mut module, diagnostics) if diagnostics.is_empty() => {
+                clear_module(&mut module);
+                Ok(module)
+            }
+            (_, diagnostics) => Err(diagnostics),
+        }
+    }
+
+    fn helper(cases: Vec<(&str, Expr)>) {
+        for (input, expected) in cases {
+            let res = parse_expr(lex(input)).map(|mut x| {
+                clear(&mut x);
+                x
+            });
+            assert_eq!(Ok(expected), res, "{}", input);
+        }
+    }
+
+    #[test]
+    fn span_test() {
+        let x = parse_expr(lex("(a +\n b)[0]")).unwrap();
+        assert_eq!(x.span, Span::new(0, 11));
+        match x.kind {
+            ExprKind::Index(x, i) => {
+                assert_eq!(x.span, Span::new(0, 8));
+                assert_eq!(i.span, Span::new(9, 1));
+                match x.kind {
+                    ExprKind::Add(a, b) => {
+                        assert_eq!(a.span, Span::new(1, 1));
+                        assert_eq!(b.span, Span::new(6, 1));
+                    }
+                    _ => panic!(),
+                }
+            }
+            _ => panic!(),
+        }
+        assert_eq!(
+            parse_source("fun f() { 1 }\nstruct A {}")
+                .0
+                .into_iter()
+                .map(|x| x.span)
+                .collect::<Vec<_>>(),
+            vec![Span::new(0, 13), Span::new(14, 11)]
+        );
+        assert_eq!(
+            parse_expr(lex("f(a b)")),
+            Err(vec![Diagnostic::new(
+                "expected `)`, found `b`".to_string(),
+                Span::new(4, 1)
+            )
+            .with_code(Code::Syntax)])
+        );
+        assert_eq!(
+            parse_expr(lex("let")),
+            Err(vec![Diagnostic::new(
+                "expected identifier, found end of file".to_string(),
+                Span::new(3, 0)
+            )
+            .with_code(Code::Syntax)])
+        );
+        assert_eq!(
+            parse_source("fun f() { 'a }"),
+            (
+                vec![],
+                vec![
+                    Diagnostic::new("unexpected character ` `".to_string(), Span::new(12, 0))
+                        .with_code(Code::UnexpectedCharacter)
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn expr_test() {
+        helper(vec![
+            ("a + b * c", expr!((add a (mul b c)))),
+            ("a - b - c", expr!((sub (sub a b) c))),
+            ("a ** b ** c", expr!((pow a (pow b c)))),
+            ("-a ** b", expr!((minus (pow a b)))),
+            ("!a && b || c", expr!((or (and (not a) b) c))),
+            (
+                "a == b < c | d ^ e & f",
+                expr!((eq a (lt b (bit-or c (bit-xor d (bit-and e f)))))),
+            ),
+            ("(1 + 2i64) % 3.5", expr!((mod (add 1 2i64) 3.5))),
+            ("0..n + 1", expr!((range 0 (add n 1)))),
+            ("{ a; true }", expr!((block [a] true))),
+            ("a * b as i64", expr!((mul a (cast b i64)))),
+            ("-a as f64 as i32", expr!((cast (cast (minus a) f64) i32))),
+            ("a as i64 < b", expr!((lt (cast a i64) b))),
+        ]);
+    }
+
+    #[test]
+    fn module_test() {
+        assert_eq!(
+            parse(
+                "fun add(a: i32, b: i32) -> i32 {
+                    let c = a + b;
+                    while c > 0 { c; }
+                    return c;
+                }
+                fun main() {}"
+            ),
+            Ok(vec![
+                m(MemberKind::Func(
+                    FuncDef(
+                        "add".to_string(),
+                        vec![],
+                        vec![("a".to_string(), Type::I32), ("b".to_string(), Type::I32)],
+                        Some(Type::I32)
+                    ),
+                    e(ExprKind::Block(
+                        vec![
+                            e(ExprKind::Let(
+                                "c".to_string(),
+                                Span::default(),
+                                None,
+                                b(ExprKind::Add(var("a"), var("b")))
+                            )),
+                            e(ExprKind::While(
+                                b(ExprKind::Gt(var("c"), b(ExprKind::I32Literal(0)))),
+                                b(ExprKind::Block(
+                                    vec![e(ExprKind::Var("c".to_string()))],
+                                    Box::new(None)
+                                ))
+                            )),
+                            e(ExprKind::Return(Box::new(Some(e(ExprKind::Var(
+                                "c".to_string()
+                            )))))),
+                        ],
+                        Box::new(None)
+                    ))
+                )),
+                m(MemberKind::Func(
+                    FuncDef("main".to_string(), vec![], vec![], None),
+                    e(ExprKind::Block(vec![], Box::new(None)))
+                )),
+            ])
+        );
+    }
+
+    #[test]
+    fn struct_test() {
+        assert_eq!(
+            parse(
+                "struct Point { x: i32, y: i32, }
+                fun main() {
+                    while p { Point { x: 1, y: 2 } }
+                }"
+            ),
+            Ok(vec![
+                m(MemberKind::Struct(
+                    "Point".to_string(),
+                    vec![],
+                    vec![("x".to_string(), Type::I32), ("y".to_string(), Type::I32)]
+                )),
+                m(MemberKind::Func(
+                    FuncDef("main".to_string(), vec![], vec![], None),
+                    e(ExprKind::Block(
+                        vec![],
+                        Box::new(Some(e(ExprKind::While(
+                            var("p"),
+                            b(ExprKind::Block(
+                                vec![],
+                                Box::new(Some(e(ExprKind::StructLiteral(
+                                    "Point".to_string(),
+                                    vec![
+                                        ("x".to_string(), e(ExprKind::I32Literal(1))),
+                                        ("y".to_string(), e(ExprKind::I32Literal(2)))
+                                    ]
+                                ))))
+                            ))
+                        ))))
+                    ))
+                )),
+            ])
+        );
+    }
+
+    #[test]
+    fn extern_fun_test() {
+        assert_eq!(
+            parse("extern fun print(x: i32) -> i32 = \"env\" \"print\";"),
+            Ok(vec![m(MemberKind::ExternFun(
+                FuncDef(
+                    "print".to_string(),
+                    vec![],
+                    vec![("x".to_string(), Type::I32)],
+                    Some(Type::I32)
+                ),
+                "env".to_string(),
+                "print".to_string()
+            ))])
+        );
+    }
+
+    fn block_of(x: Expr) -> Expr {
+        e(ExprKind::Block(vec![], Box::new(Some(x))))
+    }
+
+    #[test]
+    fn if_test() {
+        helper(vec![
+            (
+                "if a { x } else if b { y } else if c { z } else { w }",
+                e(ExprKind::If(
+                    Box::new((
+                        e(ExprKind::Var("a".to_string())),
+                        block_of(e(ExprKind::Var("x".to_string()))),
+                    )),
+                    vec![
+                        (
+                            e(ExprKind::Var("b".to_string())),
+                            block_of(e(ExprKind::Var("y".to_string()))),
+                        ),
+                        (
+                            e(ExprKind::Var("c".to_string())),
+                            block_of(e(ExprKind::Var("z".to_string()))),
+                        ),
+                    ],
+                    Box::new(Some(block_of(e(ExprKind::Var("w".to_string()))))),
+                )),
+            ),
+            (
+                "if a { if b { x } else { y } }",
+                e(ExprKind::If(
+                    Box::new((
+                        e(ExprKind::Var("a".to_string())),
+                        block_of(e(ExprKind::If(
+                            Box::new((
+                                e(ExprKind::Var("b".to_string())),
+                                block_of(e(ExprKind::Var("x".to_string()))),
+                            )),
+                            vec![],
+                            Box::new(Some(block_of(e(ExprKind::Var("y".to_string()))))),
+                        ))),
+                    )),
+                    vec![],
+                    Box::new(None),
+                )),
+            ),
+            (
+                "if a { if b { x } } else { y }",
+                e(ExprKind::If(
+                    Box::new((
+                        e(ExprKind::Var("a".to_string())),
+                        block_of(e(ExprKind::If(
+                            Box::new((
+                                e(ExprKind::Var("b".to_string())),
+                                block_of(e(ExprKind::Var("x".to_string()))),
+                            )),
+                            vec![],
+                            Box::new(None),
+                        ))),
+                    )),
+                    vec![],
+                    Box::new(Some(block_of(e(ExprKind::Var("y".to_string()))))),
+                )),
+            ),
+        ]);
+        assert!(parse_expr(lex("if a x")).is_err());
+    }
+
+    #[test]
+    fn lambda_test() {
+        helper(vec![
+            (
+                "|a: i32, b: i32| [c] -> i32 { a + b + c }",
+                e(ExprKind::Lambda(
+                    vec!["c".to_string()],
+                    vec![("a".to_string(), Type::I32), ("b".to_string(), Type::I32)],
+                    Type::I32,
+                    Box::new(block_of(e(ExprKind::Add(
+                        b(ExprKind::Add(var("a"), var("b"))),
+                        var("c"),
+                    )))),
+                )),
+            ),
+            (
+                "|| -> bool { || -> bool { true } }",
+                e(ExprKind::Lambda(
+                    vec![],
+                    vec![],
+                    Type::Bool,
+                    Box::new(block_of(e(ExprKind::Lambda(
+                        vec![],
+                        vec![],
+                        Type::Bool,
+                        Box::new(block_of(e(ExprKind::BoolLiteral(true)))),
+                    )))),
+                )),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn array_test() {
+        helper(vec![
+            (
+                "[[i32]; n + 1][0][1]",
+                e(ExprKind::Index(
+                    b(ExprKind::Index(
+                        b(ExprKind::ArrayLiteral(
+                            Type::RefType(RefType::Array(Box::new(Type::I32))),
+                            b(ExprKind::Add(var("n"), b(ExprKind::I32Literal(1)))),
+                        )),
+                        b(ExprKind::I32Literal(0)),
+                    )),
+                    b(ExprKind::I32Literal(1)),
+                )),
+            ),
+            (
+                "-a[i] ** 2",
+                e(ExprKind::Minus(b(ExprKind::Pow(
+                    b(ExprKind::Index(var("a"), var("i"))),
+                    b(ExprKind::I32Literal(2)),
+                )))),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn postfix_test() {
+        helper(vec![
+            (
+                "a.b.c(x)(y)[2]",
+                e(ExprKind::Index(
+                    b(ExprKind::Call(
+                        b(ExprKind::Call(
+                            b(ExprKind::Member(
+                                b(ExprKind::Member(var("a"), "b".to_string())),
+                                "c".to_string(),
+                            )),
+                            vec![e(ExprKind::Var("x".to_string()))],
+                        )),
+                        vec![e(ExprKind::Var("y".to_string()))],
+                    )),
+                    b(ExprKind::I32Literal(2)),
+                )),
+            ),
+            (
+                "f(|x: i32| -> i32 { x }, 1,)",
+                e(ExprKind::Call(
+                    var("f"),
+                    vec![
+                        e(ExprKind::Lambda(
+                            vec![],
+                            vec![("x".to_string(), Type::I32)],
+                            Type::I32,
+                            Box::new(block_of(e(ExprKind::Var("x".to_string())))),
+                        )),
+                        e(ExprKind::I32Literal(1)),
+                    ],
+                )),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn assign_test() {
+        helper(vec![
+            (
+                "a.b = c = 1",
+                e(ExprKind::Set(
+                    b(ExprKind::Member(var("a"), "b".to_string())),
+                    b(ExprKind::Set(var("c"), b(ExprKind::I32Literal(1)))),
+                )),
+            ),
+            (
+                "a[i] += 2",
+                e(ExprKind::CompoundSet(
+                    AssignOp::Add,
+                    b(ExprKind::Index(var("a"), var("i"))),
+                    b(ExprKind::I32Literal(2)),
+                )),
+            ),
+        ]);
+        assert_eq!(
+            parse_expr(lex("a + b = c")),
+            Err(vec![Diagnostic::new(
+                "invalid left-hand side of assignment".to_string(),
+                Span::new(0, 5)
+            )
+            .with_code(Code::Syntax)])
+        );
+    }
+
+    #[test]
+    fn recovery_test() {
+        let (module, errors) = parse_module(lex("fun f() {
+                let a = ;
+                a b;
+                1
+            }
+            struct A { x }
+            fun g() { if x { 1 + }
+            fun h() {}"));
+        let mut module = module;
+        clear_module(&mut module);
+        assert_eq!(
+            module,
+            vec![
+                m(MemberKind::Func(
+                    FuncDef("f".to_string(), vec![], vec![], None),
+                    e(ExprKind::Block(
+                        vec![e(ExprKind::Error), e(ExprKind::Error)],
+                        Box::new(Some(e(ExprKind::I32Literal(1))))
+                    ))
+                )),
+                m(MemberKind::Error),
+                m(MemberKind::Error),
+                m(MemberKind::Func(
+                    FuncDef("h".to_string(), vec![], vec![], None),
+                    e(ExprKind::Block(vec![], Box::new(None)))
+                )),
+            ]
+        );
+        assert_eq!(
+            errors
+                .iter()
+                .map(|x| x.message.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "expected expression, found `;`",
+                "expected `;` or `}`, found `b`",
+                "expected `:`, found `}`",
+                "expected expression, found `}`",
+                "expected `}`, found `fun`",
+            ]
+        );
+        assert_eq!(
+            parse_source("fun f() { a b; }").1,
+            vec![Diagnostic::new(
+                "expected `;` or `}`, found `b`".to_string(),
+                Span::new(12, 1)
+            )
+            .with_code(Code::Syntax)]
+        );
+    }
+
+    #[test]
+    fn type_test() {
+        let func = |params, ret: Option<Type>| Type::RefType(RefType::Func(params, Box::new(ret)));
+        helper(vec![(
+            "let f: fun(i32, [string]) -> fun() = g",
+            e(ExprKind::Let(
+                "f".to_string(),
+                Span::default(),
+                Some(func(
+                    vec![
+                        Type::I32,
+                        Type::RefType(RefType::Array(Box::new(Type::RefType(RefType::String)))),
+                    ],
+                    Some(func(vec![], None)),
+                )),
+                var("g"),
+            )),
+        )]);
+        assert_eq!(
+            parse("struct A { f: fun(A) -> bool }"),
+            Ok(vec![m(MemberKind::Struct(
+                "A".to_string(),
+                vec![],
+                vec![(
+                    "f".to_string(),
+                    func(
+                        vec![Type::RefType(RefType::Struct("A".to_string(), vec![]))],
+                        Some(Type::Bool)
+                    )
+                )]
+            ))])
+        );
+    }
+
+    #[test]
+    fn generics_test() {
+        let param = |x: &str| Type::Param(x.to_string());
+        let boxed = |x| Type::RefType(RefType::Struct("Box".to_string(), vec![x]));
+        assert_eq!(
+            parse(
+                "struct Box<T> { value: T }
+                fun unbox<T>(x: Box<T>) -> T { let y: T = x.value; y }
+                fun main() { let b: Box<Box<T>> = b; }"
+            ),
+            Ok(vec![
+                m(MemberKind::Struct(
+                    "Box".to_string(),
+                    vec![TypeParam("T".to_string())],
+                    vec![("value".to_string(), param("T"))]
+                )),
+                m(MemberKind::Func(
+                    FuncDef(
+                        "unbox".to_string(),
+                        vec![TypeParam("T".to_string())],
+                        vec![("x".to_string(), boxed(param("T")))],
+                        Some(param("T"))
+                    ),
+                    e(ExprKind::Block(
+                        vec![e(ExprKind::Let(
+                            "y".to_string(),
+                            Span::default(),
+                            Some(param("T")),
+                            b(ExprKind::Member(var("x"), "value".to_string()))
+                        ))],
+                        Box::new(Some(e(ExprKind::Var("y".to_string()))))
+                    ))
+                )),
+                m(MemberKind::Func(
+                    FuncDef("main".to_string(), vec![], vec![], None),
+                    e(ExprKind::Block(
+                        vec![e(ExprKind::Let(
+                            "b".to_string(),
+                            Span::default(),
+                            Some(boxed(boxed(Type::RefType(RefType::Struct(
+                                "T".to_string(),
+                                vec![]
+                            ))))),
+                            var("b")
+                        ))],
+                        Box::new(None)
+                    ))
+                )),
+            ])
+        );
+    }
+
+    #[test]
+    fn enum_test() {
+        assert_eq!(
+            parse(
+                "enum Color { Red, Green, Rgb(i32, i32, i32), } enum Option<T> { Some(T), None }"
+            ),
+            Ok(vec![
+                m(MemberKind::Enum(
+                    "Color".to_string(),
+                    vec![],
+                    vec![
+                        Variant("Red".to_string(), vec![]),
+                        Variant("Green".to_string(), vec![]),
+                        Variant("Rgb".to_string(), vec![Type::I32, Type::I32, Type::I32]),
+                    ]
+                )),
+                m(MemberKind::Enum(
+                    "Option".to_string(),
+                    vec![TypeParam("T".to_string())],
+                    vec![
+                        Variant("Some".to_string(), vec![Type::Param("T".to_string())]),
+                        Variant("None".to_string(), vec![]),
+                    ]
+                )),
+            ])
+        );
+    }
+
+    #[test]
+    fn match_test() {
+        let variant = |v: &str, xs| Pattern::Variant("Shape".to_string(), v.to_string(), xs);
+        helper(vec![(
+            "match s { Shape.Circle(r) => r, Shape.Rect(0, _) => { 0 }, Shape.Empty => 1, x => 2, }",
+            e(ExprKind::Match(
+                var("s"),
+                vec![
+                    (
+                        variant("Circle", vec![Pattern::Binding("r".to_string())]),
+                        e(ExprKind::Var("r".to_string())),
+                    ),
+                    (
+                        variant(
+                            "Rect",
+                            vec![
+                                Pattern::Literal(e(ExprKind::I32Literal(0))),
+                                Pattern::Wildcard,
+                            ],
+                        ),
+                        e(ExprKind::Block(
+                            vec![],
+                            Box::new(Some(e(ExprKind::I32Literal(0)))),
+                        )),
+                    ),
+                    (variant("Empty", vec![]), e(ExprKind::I32Literal(1))),
+                    (
+                        Pattern::Binding("x".to_string()),
+                        e(ExprKind::I32Literal(2)),
+                    ),
+                ],
+            )),
+        )]);
+    }
+
+    #[test]
+    fn import_test() {
+        let (module, diagnostics) = parse_source("import std.math; pub fun f() {} struct A {}");
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(
+            module
+                .iter()
+                .map(|x| (x.kind.clone(), x.span, x.visibility))
+                .filter(|x| matches!(x.0, MemberKind::Import(_)))
+                .collect::<Vec<_>>(),
+            vec![(
+                MemberKind::Import(vec!["std".to_string(), "math".to_string()]),
+                Span::new(0, 16),
+                Visibility::Private
+            )]
+        );
+        assert_eq!(
+            module[1..]
+                .iter()
+                .map(|x| (x.span, x.visibility))
+                .collect::<Vec<_>>(),
+            vec![
+                (Span::new(17, 14), Visibility::Public),
+                (Span::new(32, 11), Visibility::Private)
+            ]
+        );
+    }
+
+    #[test]
+    fn global_test() {
+        assert_eq!(
+            parse(
+                "const MAX: i32 = -(1 + 2); pub let mut counter: i64 = MAX; let s: string = \"a\";"
+            ),
+            Ok(vec![
+                m(MemberKind::Global(
+                    "MAX".to_string(),
+                    Mutability::Const,
+                    Type::I32,
+                    e(ExprKind::Minus(b(ExprKind::Add(
+                        b(ExprKind::I32Literal(1)),
+                        b(ExprKind::I32Literal(2))
+                    ))))
+                )),
+                Member {
+                    visibility: Visibility::Public,
+                    ..m(MemberKind::Global(
+                        "counter".to_string(),
+                        Mutability::Mutable,
+                        Type::I64,
+                        e(ExprKind::Var("MAX".to_string()))
+                    ))
+                },
+                m(MemberKind::Global(
+                    "s".to_string(),
+                    Mutability::Immutable,
+                    Type::RefType(RefType::String),
+                    e(ExprKind::StringLiteral("a".to_string()))
+                )),
+            ])
+        );
+        assert_eq!(
+            parse("const X: i32 = f(1); fun f(x: i32) -> i32 { x }")
+                .unwrap_err()
+                .into_iter()
+                .map(|x| (x.message, x.span))
+                .collect::<Vec<_>>(),
+            vec![(
+                "global initializer must be a constant expression".to_string(),
+                Span::new(15, 4)
+            )]
+        );
+    }
+
+    #[test]
+    fn operator_table_test() {
+        let mut keywords = KeywordTable::default();
+        keywords.reserve("in");
+        let mut ops = OperatorTable::default();
+        ops.register(
+            Kind::Keyword(Keyword::Reserved("in".to_string())),
+            4,
+            Assoc::Non,
+            "contains",
+        );
+        ops.remove(&Kind::Symbol(Symbol::BitXor));
+        let parse = |s: &str| {
+            let tokens = lexer(&keywords)
+                .parse(&mut Stream::new(s.chars().collect()))
+                .unwrap();
+            parse_expr_with(tokens, &ops).map(|mut x| {
+                clear(&mut x);
+                x
+            })
+        };
+        assert_eq!(
+            parse("a in b + c && d"),
+            Ok(e(ExprKind::And(
+                b(ExprKind::Call(
+                    var("contains"),
+                    vec![
+                        e(ExprKind::Var("a".to_string())),
+                        e(ExprKind::Add(var("b"), var("c"))),
+                    ]
+                )),
+                var("d")
+            )))
+        );
+        assert!(parse("a ^ b").is_err());
+        assert!(parse_expr(lex("a ^ b")).is_ok());
+
+        // A panic halfway through a parse still puts the default operators back.
+        let res = std::panic::catch_unwind(|| with_operators(&OperatorTable::new(), || panic!()));
+        assert!(res.is_err());
+        assert!(parse_expr(lex("a ^ b")).is_ok());
+    }
+
+    #[test]
+    fn break_test() {
+        assert_eq!(
+            parse("fun f() { while a { if b { break; } continue } }").map(|x| x.len()),
+            Ok(1)
+        );
+        assert_eq!(
+            parse("fun f() { break; while a { || -> i32 { continue; 1 }; } }")
+                .unwrap_err()
+                .into_iter()
+                .map(|x| (x.message, x.span))
+                .collect::<Vec<_>>(),
+            vec![
+                ("`break` outside of a loop".to_string(), Span::new(10, 5)),
+                ("`continue` outside of a loop".to_string(), Span::new(39, 8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn byte_string_test() {
+        assert_eq!(
+            parse("fun f() { let x = b\"ab\" + b'c'; }")
+                .unwrap_err()
+                .into_iter()
+                .map(|x| (x.message, x.span))
+                .collect::<Vec<_>>(),
+            vec![(
+                "byte strings are not supported here".to_string(),
+                Span::new(18, 5)
+            )]
+        );
+    }
 }
@@ -0,0 +1,905 @@
+use crate::ast::{
+    Expr, ExprKind, FuncDef, Member, MemberKind, Module, Mutability, Pattern, RefType, Span, Type,
+    TypeParam, Variant, Visibility,
+};
+use crate::parser::is_block_like;
+use std::collections::VecDeque;
+use token::token::{Literal, NumLiteral};
+
+const INDENT: &str = "    ";
+// Argument and parameter lists that would make their line longer are put one per line.
+const MAX_WIDTH: usize = 100;
+
+// Binding strength of each expression form, mirroring the parser. Operands that bind weaker
+// than their position requires are parenthesized.
+const OPEN: u8 = 0;
+const ASSIGN: u8 = 1;
+const CAST: u8 = 12;
+const UNARY: u8 = 13;
+const POW: u8 = 14;
+const POSTFIX: u8 = 15;
+const ATOM: u8 = 16;
+
+// Symbol, precedence (above `ASSIGN`, as in `parser::operators`) and whether the operator is
+// non-associative.
+fn binary_op(x: &ExprKind) -> Option<(&'static str, u8, bool, &Expr, &Expr)> {
+    let (s, prec, non, x, y) = match x {
+        ExprKind::Range(x, y) => ("..", 2, true, x, y),
+        ExprKind::RangeInclusive(x, y) => ("..=", 2, true, x, y),
+        ExprKind::Or(x, y) => ("||", 3, false, x, y),
+        ExprKind::And(x, y) => ("&&", 4, false, x, y),
+        ExprKind::Eq(x, y) => ("==", 5, false, x, y),
+        ExprKind::Ne(x, y) => ("!=", 5, false, x, y),
+        ExprKind::Lt(x, y) => ("<", 6, false, x, y),
+        ExprKind::Lte(x, y) => ("<=", 6, false, x, y),
+        ExprKind::Gt(x, y) => (">", 6, false, x, y),
+        ExprKind::Gte(x, y) => (">=", 6, false, x, y),
+        ExprKind::BitOr(x, y) => ("|", 7, false, x, y),
+        ExprKind::BitXor(x, y) => ("^", 8, false, x, y),
+        ExprKind::BitAnd(x, y) => ("&", 9, false, x, y),
+        ExprKind::Add(x, y) => ("+", 10, false, x, y),
+        ExprKind::Sub(x, y) => ("-", 10, false, x, y),
+        ExprKind::Mul(x, y) => ("*", 11, false, x, y),
+        ExprKind::Div(x, y) => ("/", 11, false, x, y),
+        ExprKind::Mod(x, y) => ("%", 11, false, x, y),
+        _ => return None,
+    };
+    Some((s, prec, non, x, y))
+}
+
+fn is_negative(x: &ExprKind) -> bool {
+    match x {
+        ExprKind::I32Literal(x) => *x < 0,
+        ExprKind::I64Literal(x) => *x < 0,
+        ExprKind::F32Literal(x) => x.is_sign_negative(),
+        ExprKind::F64Literal(x) => x.is_sign_negative(),
+        _ => false,
+    }
+}
+
+fn prec(x: &Expr) -> u8 {
+    match &x.kind {
+        ExprKind::Let(..) | ExprKind::Return(_) => OPEN,
+        ExprKind::Set(..) | ExprKind::CompoundSet(..) => ASSIGN,
+        ExprKind::Cast(..) => CAST,
+        ExprKind::Not(_) | ExprKind::Plus(_) | ExprKind::Minus(_) => UNARY,
+        ExprKind::Pow(..) => POW,
+        ExprKind::Call(..) | ExprKind::Index(..) | ExprKind::Member(..) => POSTFIX,
+        x if is_negative(x) => UNARY,
+        x => binary_op(x).map_or(ATOM, |x| x.1),
+    }
+}
+
+fn literal(x: Literal) -> String {
+    x.to_string()
+}
+
+fn comma_sep<T>(xs: &[T], f: impl FnMut(&T) -> String) -> String {
+    xs.iter().map(f).collect::<Vec<_>>().join(", ")
+}
+
+pub fn print_type(x: &Type) -> String {
+    match x {
+        Type::I32 => "i32".to_string(),
+        Type::I64 => "i64".to_string(),
+        Type::F32 => "f32".to_string(),
+        Type::F64 => "f64".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Char => "char".to_string(),
+        Type::Param(x) => x.clone(),
+        Type::RefType(RefType::String) => "string".to_string(),
+        Type::RefType(RefType::Array(x)) => format!("[{}]", print_type(x)),
+        Type::RefType(RefType::Struct(name, args)) | Type::RefType(RefType::Enum(name, args)) => {
+            if args.is_empty() {
+                name.clone()
+            } else {
+                format!("{}<{}>", name, comma_sep(args, print_type))
+            }
+        }
+        Type::RefType(RefType::Func(params, ret)) => match &**ret {
+            Some(ret) => format!(
+                "fun({}) -> {}",
+                comma_sep(params, print_type),
+                print_type(ret)
+            ),
+            None => format!("fun({})", comma_sep(params, print_type)),
+        },
+    }
+}
+
+fn type_params(xs: &[TypeParam]) -> String {
+    if xs.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", comma_sep(xs, |x| x.0.clone()))
+    }
+}
+
+fn params(xs: &[(String, Type)]) -> String {
+    comma_sep(xs, |(name, t)| format!("{}: {}", name, print_type(t)))
+}
+
+pub fn print_pattern(x: &Pattern) -> String {
+    match x {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Binding(x) => x.clone(),
+        Pattern::Literal(x) => print_expr(x),
+        Pattern::Variant(enum_, variant, xs) if xs.is_empty() => format!("{}.{}", enum_, variant),
+        Pattern::Variant(enum_, variant, xs) => {
+            format!("{}.{}({})", enum_, variant, comma_sep(xs, print_pattern))
+        }
+    }
+}
+
+// A comment of the source, with `trailing` set when code comes before it on its line and
+// `leading` when code comes after it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub pos: usize,
+    pub len: usize,
+    pub text: String,
+    pub trailing: bool,
+    pub leading: bool,
+}
+
+impl Comment {
+    fn is_line(&self) -> bool {
+        self.text.starts_with("//")
+    }
+
+    // A block comment in front of code on its line, which stays there.
+    fn is_inline(&self) -> bool {
+        self.leading && !self.is_line()
+    }
+}
+
+// What the syntax tree loses of the source and `print_module_with` puts back: the comments, and
+// the char offsets of the blank lines. A single blank line is kept between statements and
+// members where the source had any. Comments are written in front of the statement, member or
+// operand that follows them, or at the end of the line of what precedes them if they were there
+// in the source.
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    pub comments: Vec<Comment>,
+    pub blank_lines: Vec<usize>,
+}
+
+struct Printer {
+    out: String,
+    indent: usize,
+    // Cleared in the header of `if`/`while`/`for`/`match`, where a struct literal would be
+    // taken for the body.
+    allow_struct: bool,
+    comments: VecDeque<Comment>,
+    blank_lines: Vec<usize>,
+    // The end of what was last printed, in the source.
+    last: usize,
+    // Set after an inline comment that started a line, which the next item continues.
+    glued: bool,
+    // Set while trying a list or operator chain on one line, where operator chains inside do
+    // not wrap on their own.
+    flat: bool,
+}
+
+impl Printer {
+    fn new(indent: usize) -> Printer {
+        Printer {
+            out: String::new(),
+            indent,
+            allow_struct: true,
+            comments: VecDeque::new(),
+            blank_lines: Vec::new(),
+            last: 0,
+            glued: false,
+            flat: false,
+        }
+    }
+
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+    }
+
+    fn newline(&mut self) {
+        self.glued = false;
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn current_line(&self) -> &str {
+        self.out.rsplit('\n').next().unwrap()
+    }
+
+    fn blank_between(&self, from: usize, to: usize) -> bool {
+        let i = self.blank_lines.partition_point(|&x| x <= from);
+        self.blank_lines.get(i).is_some_and(|&x| x < to)
+    }
+
+    // Starts a line for the next item, after a blank one if `blank`, except at the start of the
+    // output or of a block.
+    fn line(&mut self, blank: bool) {
+        if self.out.is_empty() {
+            return;
+        }
+        if self.glued {
+            self.glued = false;
+            self.push(" ");
+            return;
+        }
+        if blank && !self.out.ends_with('{') {
+            self.out.push('\n');
+        }
+        self.newline();
+    }
+
+    // The next comment if it comes before `pos`.
+    fn comment_before(&mut self, pos: usize) -> Option<Comment> {
+        if self.comments.front()?.pos < pos {
+            self.comments.pop_front()
+        } else {
+            None
+        }
+    }
+
+    // Writes a comment at the end of the current line if it was there in the source, or on a
+    // line of its own otherwise or at the start of a block, and returns whether it took a line
+    // of its own. Something on a new line must follow, as a line comment runs to the end of the
+    // line.
+    fn comment(&mut self, x: Comment, blank: bool) -> bool {
+        let own_line =
+            !x.trailing || self.out.ends_with('{') || self.current_line().trim().is_empty();
+        if own_line {
+            let blank = blank || self.blank_between(self.last, x.pos);
+            self.line(blank);
+        } else {
+            self.push(" ");
+        }
+        self.push(&x.text);
+        self.last = x.pos + x.len;
+        self.glued = own_line && x.is_inline();
+        own_line
+    }
+
+    fn comments_before(&mut self, pos: usize) {
+        while let Some(x) = self.comment_before(pos) {
+            self.comment(x, false);
+        }
+    }
+
+    // Writes the comments before `pos` in the middle of an expression. A comment that had a line
+    // of its own gets one, and code after it or after a line comment continues on the next line,
+    // indented once more unless the line started with the comment. Inline comments stay inline.
+    fn inline_comments(&mut self, pos: usize) {
+        while let Some(x) = self.comment_before(pos) {
+            let continued = !self.current_line().trim().is_empty();
+            let extra = continued as usize;
+            let own_line = !x.trailing && !x.is_inline();
+            if continued && own_line {
+                self.out.truncate(self.out.trim_end_matches(' ').len());
+                self.indent += 1;
+                self.newline();
+                self.indent -= 1;
+            } else if continued && !self.out.ends_with([' ', '(', '[']) {
+                self.push(" ");
+            }
+            self.push(&x.text);
+            self.last = x.pos + x.len;
+            if x.is_line() || continued && own_line {
+                self.indent += extra;
+                self.newline();
+                self.indent -= extra;
+            } else {
+                self.push(" ");
+            }
+        }
+    }
+
+    // Starts the line of a statement, member or arm at `pos` in the source.
+    fn item(&mut self, pos: usize) {
+        self.comments_before(pos);
+        let blank = self.blank_between(self.last, pos);
+        self.line(blank);
+    }
+
+    // Prints `open`, the `n` items printed by `f` separated by commas, and `close`, all on one
+    // line if it fits with `reserve` more chars after it, and with each item on its own line
+    // otherwise. With `bounds`, the source positions where each item starts and where the list
+    // ends, a line comment among the items also puts them on their own lines, where comments
+    // after an item stay at the end of its line unless they are inline with the next one.
+    fn list(
+        &mut self,
+        (open, close): (&str, &str),
+        reserve: usize,
+        n: usize,
+        bounds: Option<&[usize]>,
+        f: impl Fn(&mut Printer, usize),
+    ) {
+        let start = self.out.len();
+        let saved = (self.comments.clone(), self.last);
+        let end = bounds.map_or(0, |x| x[n]);
+        let broken = self
+            .comments
+            .iter()
+            .take_while(|x| x.pos < end)
+            .any(|x| x.is_line() || !x.trailing && !x.is_inline());
+        let flat = std::mem::replace(&mut self.flat, true);
+        self.push(open);
+        for i in 0..n {
+            if i != 0 {
+                self.push(", ");
+            }
+            f(self, i);
+        }
+        self.push(close);
+        self.flat = flat;
+        if n == 0 || !broken && self.fits(start, reserve) {
+            return;
+        }
+        self.out.truncate(start);
+        (self.comments, self.last) = saved;
+        self.push(open);
+        self.indent += 1;
+        for i in 0..n {
+            self.newline();
+            f(self, i);
+            if i + 1 != n {
+                self.push(",");
+            }
+            if let Some(bounds) = bounds {
+                while self
+                    .comments
+                    .front()
+                    .is_some_and(|x| x.pos < bounds[i + 1] && (i + 1 == n || !x.is_inline()))
+                {
+                    let x = self.comments.pop_front().unwrap();
+                    self.comment(x, false);
+                }
+            }
+        }
+        self.indent -= 1;
+        self.newline();
+        self.push(close);
+    }
+
+    // Whether the line of what was printed from `start` on has room for `reserve` more chars.
+    fn fits(&self, start: usize, reserve: usize) -> bool {
+        let line_start = self.out[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.out[start..]
+            .find('\n')
+            .map_or(self.out.len(), |i| start + i);
+        self.out[line_start..line_end].chars().count() + reserve <= MAX_WIDTH
+    }
+
+    // Prints a chain of binary operators of the same precedence on one line if it fits with room
+    // for a `;` or `,` after it, and otherwise breaks it before each operator, indented once
+    // more.
+    fn binary(&mut self, s: &'static str, prec: u8, x: &Expr, y: &Expr) {
+        let start = self.out.len();
+        let saved = (self.comments.clone(), self.last);
+        let flat = std::mem::replace(&mut self.flat, true);
+        self.expr(x, prec);
+        self.push(&format!(" {} ", s));
+        self.expr(y, prec + 1);
+        self.flat = flat;
+        if flat || self.fits(start, 1) {
+            return;
+        }
+        self.out.truncate(start);
+        (self.comments, self.last) = saved;
+        let mut first = x;
+        let mut rest = vec![(s, y)];
+        while let Some((s, p, _, x, y)) = binary_op(&first.kind) {
+            if p != prec {
+                break;
+            }
+            first = x;
+            rest.push((s, y));
+        }
+        self.expr(first, prec);
+        self.indent += 1;
+        for (s, y) in rest.into_iter().rev() {
+            self.newline();
+            self.push(&format!("{} ", s));
+            self.expr(y, prec + 1);
+        }
+        self.indent -= 1;
+    }
+
+    fn render(&self, x: &Expr) -> String {
+        let mut p = Printer::new(self.indent);
+        p.expr(x, OPEN);
+        p.out
+    }
+
+    fn nested(&mut self, allow_struct: bool, f: impl FnOnce(&mut Printer)) {
+        let saved = std::mem::replace(&mut self.allow_struct, allow_struct);
+        f(self);
+        self.allow_struct = saved;
+    }
+
+    fn expr(&mut self, x: &Expr, min: u8) {
+        self.inline_comments(x.span.pos);
+        let paren =
+            prec(x) < min || (!self.allow_struct && matches!(x.kind, ExprKind::StructLiteral(..)));
+        if paren {
+            self.push("(");
+            self.nested(true, |p| p.spanned(x));
+            self.push(")");
+        } else {
+            self.spanned(x);
+        }
+    }
+
+    // Blocks and calls need their span to place the comments in them.
+    fn spanned(&mut self, x: &Expr) {
+        match &x.kind {
+            ExprKind::Block(stmts, tail) => self.block(stmts, tail.as_ref().as_ref(), x.span),
+            ExprKind::Call(f, args) => {
+                self.expr(f, POSTFIX);
+                let bounds = args
+                    .iter()
+                    .map(|x| x.span.pos)
+                    .chain(std::iter::once(x.span.end()))
+                    .collect::<Vec<_>>();
+                // Room for a `;` or `,` after the call.
+                self.list(("(", ")"), 1, args.len(), Some(&bounds), |p, i| {
+                    p.open(&args[i])
+                });
+            }
+            x => self.kind(x),
+        }
+    }
+
+    fn open(&mut self, x: &Expr) {
+        self.nested(true, |p| p.expr(x, OPEN));
+    }
+
+    fn cond(&mut self, x: &Expr) {
+        self.nested(false, |p| p.expr(x, ASSIGN));
+    }
+
+    fn kind(&mut self, x: &ExprKind) {
+        if let Some((s, prec, non, x, y)) = binary_op(x) {
+            if prec != 2 {
+                self.binary(s, prec, x, y);
+                return;
+            }
+            self.expr(x, if non { prec + 1 } else { prec });
+            self.push(s);
+            self.expr(y, prec + 1);
+            return;
+        }
+        match x {
+            ExprKind::StructLiteral(name, fields) => {
+                self.push(name);
+                if fields.is_empty() {
+                    self.push(" {}");
+                    return;
+                }
+                self.push(" { ");
+                for (i, (name, x)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        self.push(", ");
+                    }
+                    self.inline_comments(x.span.pos);
+                    self.push(&format!("{}: ", name));
+                    self.open(x);
+                }
+                self.push(" }");
+            }
+            ExprKind::I32Literal(x) => self.push(&literal(Literal::Num(NumLiteral::I32(*x)))),
+            ExprKind::I64Literal(x) => self.push(&literal(Literal::Num(NumLiteral::I64(*x)))),
+            ExprKind::F32Literal(x) => self.push(&literal(Literal::Num(NumLiteral::F32(*x)))),
+            ExprKind::F64Literal(x) => self.push(&literal(Literal::Num(NumLiteral::F64(*x)))),
+            ExprKind::StringLiteral(x) => self.push(&literal(Literal::String(x.clone()))),
+            ExprKind::CharLiteral(x) => self.push(&literal(Literal::Char(*x))),
+            ExprKind::BoolLiteral(x) => self.push(&x.to_string()),
+            ExprKind::ArrayLiteral(t, len) => {
+                self.push(&format!("[{}; ", print_type(t)));
+                self.open(len);
+                self.push("]");
+            }
+            ExprKind::Var(x) | ExprKind::Resolved(x, _) => self.push(x),
+            ExprKind::Not(x) => self.unary("!", x),
+            ExprKind::Plus(x) => self.unary("+", x),
+            ExprKind::Minus(x) => self.unary("-", x),
+            ExprKind::Cast(x, t) => {
+                self.expr(x, CAST);
+                self.push(&format!(" as {}", print_type(t)));
+            }
+            ExprKind::Member(x, name) => {
+                // `1.x` would lex as a float.
+                if matches!(
+                    x.kind,
+                    ExprKind::I32Literal(_)
+                        | ExprKind::I64Literal(_)
+                        | ExprKind::F32Literal(_)
+                        | ExprKind::F64Literal(_)
+                ) {
+                    self.push("(");
+                    self.kind(&x.kind);
+                    self.push(")");
+                } else {
+                    self.expr(x, POSTFIX);
+                }
+                self.push(&format!(".{}", name));
+            }
+            ExprKind::Index(x, i) => {
+                self.expr(x, POSTFIX);
+                self.push("[");
+                self.open(i);
+                self.push("]");
+            }
+            ExprKind::Pow(x, y) => {
+                self.expr(x, POSTFIX);
+                self.push(" ** ");
+                self.expr(y, UNARY);
+            }
+            ExprKind::Set(x, y) => {
+                self.expr(x, POSTFIX);
+                self.push(" = ");
+                self.expr(y, ASSIGN);
+            }
+            ExprKind::CompoundSet(op, x, y) => {
+                self.expr(x, POSTFIX);
+                self.push(&format!(" {}= ", op.as_str()));
+                self.expr(y, ASSIGN);
+            }
+            ExprKind::Block(..) | ExprKind::Call(..) => {
+                unreachable!("blocks and calls are handled in `spanned`")
+            }
+            ExprKind::Let(name, _, t, x) => {
+                self.push(&format!("let {}", name));
+                if let Some(t) = t {
+                    self.push(&format!(": {}", print_type(t)));
+                }
+                self.push(" = ");
+                self.open(x);
+            }
+            ExprKind::If(first, elifs, els) => {
+                for (i, (cond, body)) in std::iter::once(&**first).chain(elifs).enumerate() {
+                    if i != 0 {
+                        self.push(" else ");
+                    }
+                    self.push("if ");
+                    self.cond(cond);
+                    self.push(" ");
+                    self.body(body);
+                }
+                if let Some(x) = &**els {
+                    self.push(" else ");
+                    self.body(x);
+                }
+            }
+            ExprKind::While(cond, body) => {
+                self.push("while ");
+                self.cond(cond);
+                self.push(" ");
+                self.body(body);
+            }
+            ExprKind::For(init, cond, step, body) => {
+                self.push("for ");
+                self.cond(init);
+                self.push("; ");
+                self.cond(cond);
+                self.push("; ");
+                self.cond(step);
+                self.push(" ");
+                self.body(body);
+            }
+            ExprKind::Return(x) => {
+                self.push("return");
+                if let Some(x) = &**x {
+                    self.push(" ");
+                    self.open(x);
+                }
+            }
+            ExprKind::Lambda(captures, ps, ret, body) => {
+                self.push(&format!("|{}| ", params(ps)));
+                if !captures.is_empty() {
+                    self.push(&format!("[{}] ", captures.join(", ")));
+                }
+                self.push(&format!("-> {} ", print_type(ret)));
+                self.body(body);
+            }
+            ExprKind::Match(x, arms) => {
+                self.push("match ");
+                self.cond(x);
+                if arms.is_empty() {
+                    self.push(" {}");
+                    return;
+                }
+                self.push(" {");
+                self.indent += 1;
+                for (i, (pat, x)) in arms.iter().enumerate() {
+                    if i != 0 {
+                        self.push(",");
+                    }
+                    self.item(x.span.pos);
+                    self.push(&format!("{} => ", print_pattern(pat)));
+                    self.open(x);
+                    self.last = x.span.end();
+                }
+                self.indent -= 1;
+                self.newline();
+                self.push("}");
+            }
+            ExprKind::Break => self.push("break"),
+            ExprKind::Continue => self.push("continue"),
+            ExprKind::Error => self.push("/* error */"),
+            _ => unreachable!("binary operators are handled above"),
+        }
+    }
+
+    fn unary(&mut self, op: &str, x: &Expr) {
+        self.push(op);
+        self.expr(x, UNARY);
+    }
+
+    // Bodies of `if`, loops and lambdas must be blocks.
+    fn body(&mut self, x: &Expr) {
+        match &x.kind {
+            ExprKind::Block(stmts, tail) => self.block(stmts, tail.as_ref().as_ref(), x.span),
+            _ => self.block(&[], Some(x), x.span),
+        }
+    }
+
+    fn block(&mut self, stmts: &[Expr], tail: Option<&Expr>, span: Span) {
+        let commented = self.comments.front().is_some_and(|x| x.pos < span.end());
+        if stmts.is_empty() && tail.is_none() && !commented {
+            self.push("{}");
+            return;
+        }
+        self.push("{");
+        self.indent += 1;
+        self.last = span.pos;
+        let flat = std::mem::replace(&mut self.flat, false);
+        for (i, x) in stmts.iter().enumerate() {
+            self.item(x.span.pos);
+            self.open(x);
+            // A block-like statement needs no `;`, unless the next statement would continue it
+            // as an operand, or it would otherwise be taken as the tail expression.
+            let semi = match stmts.iter().chain(tail).nth(i + 1) {
+                _ if !is_block_like(x) => true,
+                Some(next) => self.render(next).starts_with(|c| "-+!|([".contains(c)),
+                None => true,
+            };
+            if semi {
+                self.push(";");
+            }
+            self.last = x.span.end();
+        }
+        if let Some(x) = tail {
+            self.item(x.span.pos);
+            self.open(x);
+            self.last = x.span.end();
+        }
+        self.comments_before(span.end());
+        self.flat = flat;
+        self.indent -= 1;
+        self.newline();
+        self.push("}");
+        self.last = span.end();
+    }
+
+    fn func_def(&mut self, FuncDef(name, ty_params, ps, ret): &FuncDef) {
+        self.push(&format!("fun {}{}", name, type_params(ty_params)));
+        let ret = ret
+            .as_ref()
+            .map_or(String::new(), |x| format!(" -> {}", print_type(x)));
+        // Room for the return type and the ` {` of the body.
+        self.list(("(", ")"), ret.len() + 2, ps.len(), None, |p, i| {
+            p.push(&params(&ps[i..i + 1]))
+        });
+        self.push(&ret);
+    }
+
+    fn member(&mut self, x: &Member) {
+        if x.visibility == Visibility::Public {
+            self.push("pub ");
+        }
+        match &x.kind {
+            MemberKind::Struct(name, ty_params, fields) => {
+                self.push(&format!("struct {}{} ", name, type_params(ty_params)));
+                self.fields(fields, |(name, t)| format!("{}: {}", name, print_type(t)));
+            }
+            MemberKind::Enum(name, ty_params, variants) => {
+                self.push(&format!("enum {}{} ", name, type_params(ty_params)));
+                self.fields(variants, |Variant(name, ts)| {
+                    if ts.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}({})", name, comma_sep(ts, print_type))
+                    }
+                });
+            }
+            MemberKind::Func(def, body) => {
+                self.func_def(def);
+                self.push(" ");
+                self.body(body);
+            }
+            MemberKind::ExternFun(def, module, name) => {
+                self.push("extern ");
+                self.func_def(def);
+                self.push(&format!(
+                    " = {} {};",
+                    literal(Literal::String(module.clone())),
+                    literal(Literal::String(name.clone()))
+                ));
+            }
+            MemberKind::Import(path) => self.push(&format!("import {};", path.join("."))),
+            MemberKind::Global(name, mutability, t, init) => {
+                self.push(match mutability {
+                    Mutability::Const => "const ",
+                    Mutability::Immutable => "let ",
+                    Mutability::Mutable => "let mut ",
+                });
+                self.push(&format!("{}: {} = ", name, print_type(t)));
+                self.open(init);
+                self.push(";");
+            }
+            MemberKind::Error => self.push("/* error */"),
+        }
+    }
+
+    fn fields<T>(&mut self, xs: &[T], f: impl Fn(&T) -> String) {
+        if xs.is_empty() {
+            self.push("{}");
+            return;
+        }
+        self.push("{");
+        self.indent += 1;
+        for (i, x) in xs.iter().enumerate() {
+            if i != 0 {
+                self.push(",");
+            }
+            self.newline();
+            self.push(&f(x));
+        }
+        self.indent -= 1;
+        self.newline();
+        self.push("}");
+    }
+}
+
+pub fn print_expr(x: &Expr) -> String {
+    let mut p = Printer::new(0);
+    p.expr(x, OPEN);
+    p.out
+}
+
+pub fn print_module(x: &Module) -> String {
+    print_module_with(x, Layout::default())
+}
+
+// Members are separated by a blank line, except for runs of imports and of globals. The comments
+// in front of a member stay with it.
+pub fn print_module_with(x: &Module, layout: Layout) -> String {
+    let mut p = Printer::new(0);
+    p.comments = layout.comments.into();
+    p.blank_lines = layout.blank_lines;
+    for (i, member) in x.iter().enumerate() {
+        let mut blank = i != 0
+            && !matches!(
+                (&x[i - 1].kind, &member.kind),
+                (MemberKind::Import(_), MemberKind::Import(_))
+                    | (MemberKind::Global(..), MemberKind::Global(..))
+            );
+        while let Some(comment) = p.comment_before(member.span.pos) {
+            if p.comment(comment, blank) {
+                blank = false;
+            }
+        }
+        let blank = blank || p.blank_between(p.last, member.span.pos);
+        p.line(blank);
+        p.member(member);
+        p.last = member.span.end();
+    }
+    while let Some(comment) = p.comment_before(usize::MAX) {
+        p.comment(comment, false);
+    }
+    if !p.out.is_empty() {
+        p.push("\n");
+    }
+    p.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    fn round_trip(src: &str) -> String {
+        let (module, diagnostics) = parse_source(src);
+        assert_eq!(diagnostics, vec![]);
+        let printed = print_module(&module);
+        let (reparsed, diagnostics) = parse_source(&printed);
+        assert_eq!(diagnostics, vec![], "{}", printed);
+        assert_eq!(print_module(&reparsed), printed);
+        printed
+    }
+
+    #[test]
+    fn print_module_test() {
+        assert_eq!(
+            round_trip(
+                "import std.math; import util;
+                pub struct P<T> { x: T, y: [i32] }
+                enum E { A, B(i32, string) }
+                const N: i32 = -1; let mut g: f32 = 1f32;
+                extern fun log(x: i32) = \"console\" \"log\";
+                fun f<T>(p: P<T>, k: fun(T) -> bool) -> i32 {
+                    let x: i64 = (1i64 + 2) * 3 - -4 ** 2;
+                    if (P { x: 1 }).x < 2 { x += 1; } else if !a { } else { 'c' };
+                    while i < 10 { i = i + 1; if i == 5 { break } }
+                    for i = 0; i < (1..=3).len; i = i + 1 { continue; }
+                    match e { E.B(1, s) => s, _ => \"a\\n\", };
+                    |a: i32| [x] -> i32 { a }(1)[0].y;
+                    { 1 };
+                    -2
+                }"
+            ),
+            "import std.math;
+import util;
+
+pub struct P<T> {
+    x: T,
+    y: [i32]
+}
+
+enum E {
+    A,
+    B(i32, string)
+}
+
+const N: i32 = -1;
+let mut g: f32 = 1.0f32;
+
+extern fun log(x: i32) = \"console\" \"log\";
+
+fun f<T>(p: P<T>, k: fun(T) -> bool) -> i32 {
+    let x: i64 = (1i64 + 2) * 3 - -4 ** 2;
+    if (P { x: 1 }).x < 2 {
+        x += 1;
+    } else if !a {} else {
+        'c'
+    }
+    while i < 10 {
+        i = i + 1;
+        if i == 5 {
+            break
+        }
+    }
+    for i = 0; i < (1..=3).len; i = i + 1 {
+        continue;
+    }
+    match e {
+        E.B(1, s) => s,
+        _ => \"a\\n\"
+    };
+    |a: i32| [x] -> i32 {
+        a
+    }(1)[0].y;
+    {
+        1
+    };
+    -2
+}
+"
+        );
+    }
+
+    #[test]
+    fn print_expr_test() {
+        let (module, _) =
+            parse_source("fun f() { (a = b) + (return 1) * -(1 + 2) as f64 * (x + 1) as f64 }");
+        match &module[0].kind {
+            MemberKind::Func(_, body) => assert_eq!(
+                print_expr(body),
+                "{\n    (a = b) + (return 1) * -(1 + 2) as f64 * (x + 1) as f64\n}"
+            ),
+            x => panic!("{:?}", x),
+        }
+    }
+}
@@ -0,0 +1,777 @@
+use crate::ast::{
+    Expr, ExprKind, FuncDef, Ident, Member, MemberKind, Module, Pattern, RefType, Resolution, Span,
+    Type, Variant, Visibility,
+};
+use crate::fold::{self, Folder};
+use crate::visit::{self, Visitor};
+use diagnostics::code::Code;
+use diagnostics::diagnostic::Diagnostic;
+use std::collections::HashMap;
+
+// Names declared by a module, indexed by the numbers used in `Resolution`.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Symbols {
+    pub funcs: Vec<Ident>,
+    pub globals: Vec<Ident>,
+    pub structs: Vec<Ident>,
+    pub enums: Vec<Ident>,
+    // Locals of each function by function id, parameters first.
+    pub locals: Vec<Vec<Ident>>,
+    // Locals of each lambda in the order the lambdas appear, captures and then parameters first.
+    pub lambdas: Vec<Vec<Ident>>,
+}
+
+// A `let` or a pattern binding somewhere in a body, to explain lookups that fail because of
+// scoping. Bindings are attributed to the body of their match arm.
+struct Declaration {
+    name: Ident,
+    span: Span,
+    is_let: bool,
+}
+
+struct Declarations(Vec<Declaration>);
+
+impl Declarations {
+    fn pattern(&mut self, p: &Pattern, span: Span) {
+        match p {
+            Pattern::Binding(name) => self.0.push(Declaration {
+                name: name.clone(),
+                span,
+                is_let: false,
+            }),
+            Pattern::Variant(_, _, xs) => xs.iter().for_each(|p| self.pattern(p, span)),
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+        }
+    }
+}
+
+impl Visitor<'_> for Declarations {
+    fn visit_expr(&mut self, x: &Expr) {
+        match &x.kind {
+            ExprKind::Let(name, ..) => self.0.push(Declaration {
+                name: name.clone(),
+                span: x.span,
+                is_let: true,
+            }),
+            ExprKind::Match(_, arms) => {
+                for (p, body) in arms {
+                    self.pattern(p, body.span);
+                }
+            }
+            // Lambdas have frames of their own.
+            ExprKind::Lambda(..) => return,
+            _ => {}
+        }
+        visit::walk_expr(self, x)
+    }
+}
+
+// The locals of a function or lambda body, with the block scopes currently open. Each local
+// remembers where it was declared and whether it has been referred to.
+struct Frame {
+    locals: Vec<Ident>,
+    spans: Vec<Span>,
+    used: Vec<bool>,
+    scopes: Vec<Vec<(Ident, usize)>>,
+    declarations: Vec<Declaration>,
+}
+
+impl Frame {
+    fn new(locals: Vec<Ident>, span: Span, body: &Expr) -> Frame {
+        let scope = locals.iter().cloned().zip(0..).collect();
+        let mut declarations = Declarations(Vec::new());
+        declarations.visit_expr(body);
+        Frame {
+            spans: vec![span; locals.len()],
+            used: vec![false; locals.len()],
+            locals,
+            scopes: vec![scope],
+            declarations: declarations.0,
+        }
+    }
+
+    fn declare(&mut self, name: Ident, span: Span) {
+        let i = self.locals.len();
+        self.locals.push(name.clone());
+        self.spans.push(span);
+        self.used.push(false);
+        self.scopes.last_mut().unwrap().push((name, i));
+    }
+
+    fn get(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|x| x.iter().rev())
+            .find(|x| x.0 == name)
+            .map(|x| x.1)
+    }
+}
+
+struct Resolver {
+    funcs: HashMap<Ident, usize>,
+    globals: HashMap<Ident, usize>,
+    structs: HashMap<Ident, usize>,
+    enums: HashMap<Ident, (usize, Vec<Variant>)>,
+    frames: Vec<Frame>,
+    symbols: Symbols,
+    // Id of the next function member.
+    func: usize,
+    // Id of the function whose body is being resolved, and which functions are referred to from
+    // elsewhere.
+    current: Option<usize>,
+    used: Vec<bool>,
+    // Span of the innermost node being resolved, for errors in types which carry none.
+    span: Span,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    fn error(&mut self, code: Code, message: String, span: Span) {
+        self.diagnostics
+            .push(Diagnostic::new(message, span).with_code(code));
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn scoped<T>(&mut self, f: impl FnOnce(&mut Resolver) -> T) -> T {
+        self.frame().scopes.push(Vec::new());
+        let res = f(self);
+        self.frame().scopes.pop();
+        res
+    }
+
+    // Warns about the locals of the frame that were never referred to, except for those
+    // named with a leading `_`.
+    fn in_frame<T>(&mut self, frame: Frame, f: impl FnOnce(&mut Resolver) -> T) -> (T, Vec<Ident>) {
+        self.frames.push(frame);
+        let res = f(self);
+        let frame = self.frames.pop().unwrap();
+        for ((name, span), used) in frame.locals.iter().zip(frame.spans).zip(frame.used) {
+            if !used && !name.starts_with('_') {
+                self.diagnostics.push(
+                    Diagnostic::warning(format!("unused variable `{}`", name), span)
+                        .with_code(Code::UnusedVariable)
+                        .with_note(format!(
+                            "if this is intentional, prefix it with an underscore: `_{}`",
+                            name
+                        )),
+                );
+            }
+        }
+        (res, frame.locals)
+    }
+
+    fn local(&mut self, name: &str) -> Option<usize> {
+        let frame = self.frames.last_mut()?;
+        let i = frame.get(name)?;
+        frame.used[i] = true;
+        Some(i)
+    }
+
+    fn resolve(&mut self, name: &str, span: Span) -> Option<Resolution> {
+        if let Some(i) = self.local(name) {
+            Some(Resolution::Local(i))
+        } else if let Some(&i) = self.globals.get(name) {
+            Some(Resolution::Global(i))
+        } else if let Some(&i) = self.funcs.get(name) {
+            if self.current != Some(i) {
+                self.used[i] = true;
+            }
+            Some(Resolution::Func(i))
+        } else if self.frames.iter().any(|x| x.get(name).is_some()) {
+            self.error(
+                Code::MissingCapture,
+                format!("`{}` must be listed in the captures of the lambda", name),
+                span,
+            );
+            None
+        } else {
+            self.not_found(name, span);
+            None
+        }
+    }
+
+    // Points out a declaration of the name that is not visible at `span`: one that comes later,
+    // including the `let` whose initializer `span` is in, or one whose scope has ended.
+    fn not_found(&mut self, name: &str, span: Span) {
+        let declarations = self.frames.last().map_or(&[][..], |x| &x.declarations);
+        let later = declarations
+            .iter()
+            .find(|x| x.name == name && x.is_let && span.pos < x.span.end());
+        let ended = declarations
+            .iter()
+            .rev()
+            .find(|x| x.name == name && x.span.end() <= span.pos);
+        let diagnostic = match (later, ended) {
+            (Some(x), _) => Diagnostic::new(
+                format!("cannot use `{}` before its declaration", name),
+                span,
+            )
+            .with_code(Code::UseBeforeDeclaration)
+            .with_label(format!("`{}` is declared here", name), x.span),
+            (None, Some(x)) => Diagnostic::new(format!("cannot find value `{}`", name), span)
+                .with_code(Code::UnresolvedValue)
+                .with_label(
+                    format!("`{}` is declared here, but its scope has ended", name),
+                    x.span,
+                ),
+            (None, None) => Diagnostic::new(format!("cannot find value `{}`", name), span)
+                .with_code(Code::UnresolvedValue),
+        };
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn variant(&mut self, enum_: &str, variant: &str, span: Span) {
+        match self.enums.get(enum_) {
+            Some((_, variants)) if variants.iter().any(|x| x.0 == variant) => {}
+            Some(_) => self.error(
+                Code::UnresolvedVariant,
+                format!("enum `{}` has no variant `{}`", enum_, variant),
+                span,
+            ),
+            None => self.error(
+                Code::UnresolvedEnum,
+                format!("cannot find enum `{}`", enum_),
+                span,
+            ),
+        }
+    }
+
+    fn func_def(&mut self, FuncDef(name, ty_params, params, ret): FuncDef) -> FuncDef {
+        FuncDef(
+            name,
+            ty_params,
+            params
+                .into_iter()
+                .map(|(name, t)| (name, self.fold_type(t)))
+                .collect(),
+            ret.map(|t| self.fold_type(t)),
+        )
+    }
+}
+
+impl Folder for Resolver {
+    fn fold_member(&mut self, x: Member) -> Member {
+        self.span = x.span;
+        match x.kind {
+            MemberKind::Func(def, body) => {
+                let def = self.func_def(def);
+                let params = def.2.iter().map(|x| x.0.clone()).collect();
+                self.current = Some(self.func);
+                let (body, locals) =
+                    self.in_frame(Frame::new(params, x.span, &body), |r| r.fold_expr(body));
+                self.current = None;
+                self.symbols.locals[self.func] = locals;
+                self.func += 1;
+                Member {
+                    kind: MemberKind::Func(def, body),
+                    ..x
+                }
+            }
+            MemberKind::ExternFun(..) => {
+                self.func += 1;
+                fold::walk_member(self, x)
+            }
+            MemberKind::Global(name, mutability, t, init) => {
+                let t = self.fold_type(t);
+                let (init, _) =
+                    self.in_frame(Frame::new(Vec::new(), x.span, &init), |r| r.fold_expr(init));
+                Member {
+                    kind: MemberKind::Global(name, mutability, t, init),
+                    ..x
+                }
+            }
+            _ => fold::walk_member(self, x),
+        }
+    }
+
+    fn fold_expr(&mut self, x: Expr) -> Expr {
+        self.span = x.span;
+        let span = x.span;
+        let kind = match x.kind {
+            ExprKind::Var(name) => match self.resolve(&name, span) {
+                Some(res) => ExprKind::Resolved(name, res),
+                None => ExprKind::Var(name),
+            },
+            ExprKind::Member(base, variant) => {
+                let is_enum = match &base.kind {
+                    ExprKind::Var(name) => {
+                        self.enums.contains_key(name)
+                            && self.frames.last().and_then(|x| x.get(name)).is_none()
+                    }
+                    _ => false,
+                };
+                if is_enum {
+                    let name = match base.kind {
+                        ExprKind::Var(name) => name,
+                        _ => unreachable!(),
+                    };
+                    self.variant(&name, &variant, span);
+                    let res = Resolution::Enum(self.enums[&name].0);
+                    let base = Expr::new(ExprKind::Resolved(name, res), base.span);
+                    ExprKind::Member(Box::new(base), variant)
+                } else {
+                    ExprKind::Member(Box::new(self.fold_expr(*base)), variant)
+                }
+            }
+            ExprKind::StructLiteral(ref name, _) if !self.structs.contains_key(name) => {
+                self.error(
+                    Code::UnresolvedStruct,
+                    format!("cannot find struct `{}`", name),
+                    span,
+                );
+                return fold::walk_expr(self, x);
+            }
+            ExprKind::Let(name, name_span, t, init) => {
+                let t = t.map(|t| self.fold_type(t));
+                let init = self.fold_expr(*init);
+                self.frame().declare(name.clone(), name_span);
+                ExprKind::Let(name, name_span, t, Box::new(init))
+            }
+            ExprKind::Block(..) | ExprKind::For(..) => {
+                return self.scoped(|r| fold::walk_expr(r, Expr::new(x.kind, span)));
+            }
+            ExprKind::Match(x, arms) => {
+                let x = self.fold_expr(*x);
+                let arms = arms
+                    .into_iter()
+                    .map(|(p, body)| {
+                        self.scoped(|r| {
+                            r.span = body.span;
+                            let p = r.fold_pattern(p);
+                            (p, r.fold_expr(body))
+                        })
+                    })
+                    .collect();
+                ExprKind::Match(Box::new(x), arms)
+            }
+            ExprKind::Lambda(captures, params, ret, body) => {
+                for name in &captures {
+                    if self.local(name).is_none() {
+                        self.error(
+                            Code::UnresolvedCapture,
+                            format!("cannot find local `{}` to capture", name),
+                            span,
+                        );
+                    }
+                }
+                let params = params
+                    .into_iter()
+                    .map(|(name, t)| (name, self.fold_type(t)))
+                    .collect::<Vec<_>>();
+                let ret = self.fold_type(ret);
+                let locals = captures
+                    .iter()
+                    .chain(params.iter().map(|x| &x.0))
+                    .cloned()
+                    .collect();
+                let i = self.symbols.lambdas.len();
+                self.symbols.lambdas.push(Vec::new());
+                let mut frame = Frame::new(locals, span, &body);
+                // A capture is a use of the outer local, not a declaration of its own.
+                frame.used[..captures.len()].fill(true);
+                let (body, locals) = self.in_frame(frame, |r| r.fold_expr(*body));
+                self.symbols.lambdas[i] = locals;
+                ExprKind::Lambda(captures, params, ret, Box::new(body))
+            }
+            kind => return fold::walk_expr(self, Expr::new(kind, span)),
+        };
+        Expr::new(kind, span)
+    }
+
+    fn fold_type(&mut self, x: Type) -> Type {
+        match fold::walk_type(self, x) {
+            Type::RefType(RefType::Struct(name, args)) if self.enums.contains_key(&name) => {
+                Type::RefType(RefType::Enum(name, args))
+            }
+            Type::RefType(RefType::Struct(name, args)) => {
+                if !self.structs.contains_key(&name) {
+                    self.error(
+                        Code::UnresolvedType,
+                        format!("cannot find type `{}`", name),
+                        self.span,
+                    );
+                }
+                Type::RefType(RefType::Struct(name, args))
+            }
+            x => x,
+        }
+    }
+
+    fn fold_pattern(&mut self, x: Pattern) -> Pattern {
+        match &x {
+            Pattern::Binding(name) => {
+                let span = self.span;
+                self.frame().declare(name.clone(), span)
+            }
+            Pattern::Variant(enum_, variant, _) => self.variant(enum_, variant, self.span),
+            _ => {}
+        }
+        fold::walk_pattern(self, x)
+    }
+}
+
+fn index<'a>(names: impl Iterator<Item = &'a Ident>) -> HashMap<Ident, usize> {
+    let mut map = HashMap::new();
+    for (i, name) in names.enumerate() {
+        map.entry(name.clone()).or_insert(i);
+    }
+    map
+}
+
+// Names listed more than once in `names`, in the order of their second occurrence.
+fn repeated<'a>(names: impl Iterator<Item = &'a Ident>) -> Vec<&'a Ident> {
+    let mut seen = Vec::new();
+    let mut res = Vec::new();
+    for name in names {
+        if seen.contains(&name) {
+            res.push(name);
+        } else {
+            seen.push(name);
+        }
+    }
+    res
+}
+
+// Functions and globals share one namespace, structs and enums another.
+fn duplicates(module: &Module) -> Vec<Diagnostic> {
+    let mut values = HashMap::new();
+    let mut types = HashMap::new();
+    let mut diagnostics = Vec::new();
+    for member in module {
+        let (names, name) = match &member.kind {
+            MemberKind::Func(def, _) | MemberKind::ExternFun(def, _, _) => {
+                for name in repeated(def.2.iter().map(|x| &x.0)) {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            format!(
+                                "identifier `{}` is bound more than once in this parameter list",
+                                name
+                            ),
+                            member.span,
+                        )
+                        .with_code(Code::DuplicateParameter),
+                    );
+                }
+                (&mut values, &def.0)
+            }
+            MemberKind::Global(name, ..) => (&mut values, name),
+            MemberKind::Struct(name, _, fields) => {
+                for field in repeated(fields.iter().map(|x| &x.0)) {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            format!("field `{}` is already declared", field),
+                            member.span,
+                        )
+                        .with_code(Code::DuplicateField),
+                    );
+                }
+                (&mut types, name)
+            }
+            MemberKind::Enum(name, ..) => (&mut types, name),
+            MemberKind::Import(_) | MemberKind::Error => continue,
+        };
+        match names.get(name) {
+            Some(&span) => diagnostics.push(
+                Diagnostic::new(
+                    format!("the name `{}` is defined multiple times", name),
+                    member.span,
+                )
+                .with_code(Code::DuplicateDefinition)
+                .with_label(format!("previous definition of `{}` here", name), span),
+            ),
+            None => {
+                names.insert(name, member.span);
+            }
+        }
+    }
+    diagnostics
+}
+
+// Replaces every `Var` that names a local, global or function with `Resolved`, and tells apart
+// named types that refer to enums. Names the module does not declare or declares twice are
+// reported, and unused locals and functions warned about; imported modules are not consulted
+// yet.
+pub fn resolve_module(module: Module) -> (Module, Symbols, Vec<Diagnostic>) {
+    let mut symbols = Symbols::default();
+    let mut enums = Vec::new();
+    for member in &module {
+        match &member.kind {
+            MemberKind::Func(def, _) | MemberKind::ExternFun(def, _, _) => {
+                symbols.funcs.push(def.0.clone());
+                symbols
+                    .locals
+                    .push(def.2.iter().map(|x| x.0.clone()).collect());
+            }
+            MemberKind::Global(name, ..) => symbols.globals.push(name.clone()),
+            MemberKind::Struct(name, ..) => symbols.structs.push(name.clone()),
+            MemberKind::Enum(name, _, variants) => {
+                symbols.enums.push(name.clone());
+                enums.push(variants.clone());
+            }
+            MemberKind::Import(_) | MemberKind::Error => {}
+        }
+    }
+    let mut resolver = Resolver {
+        funcs: index(symbols.funcs.iter()),
+        globals: index(symbols.globals.iter()),
+        structs: index(symbols.structs.iter()),
+        enums: index(symbols.enums.iter())
+            .into_iter()
+            .map(|(name, i)| (name, (i, enums[i].clone())))
+            .collect(),
+        frames: Vec::new(),
+        used: vec![false; symbols.funcs.len()],
+        symbols,
+        func: 0,
+        current: None,
+        span: Span::default(),
+        diagnostics: duplicates(&module),
+    };
+    let module = resolver.fold_module(module);
+    // Private functions that no other function refers to. `main` is the entry point.
+    let funcs = module
+        .iter()
+        .filter(|x| matches!(x.kind, MemberKind::Func(..) | MemberKind::ExternFun(..)));
+    for (member, used) in funcs.zip(&resolver.used) {
+        match &member.kind {
+            MemberKind::Func(FuncDef(name, ..), _)
+                if !used
+                    && member.visibility == Visibility::Private
+                    && name != "main"
+                    && !name.starts_with('_') =>
+            {
+                resolver.diagnostics.push(
+                    Diagnostic::warning(format!("function `{}` is never used", name), member.span)
+                        .with_code(Code::UnusedFunction),
+                );
+            }
+            _ => {}
+        }
+    }
+    (module, resolver.symbols, resolver.diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+    use crate::sexpr::module_to_sexpr;
+
+    fn helper(src: &str) -> (String, Symbols, Vec<String>) {
+        let (module, diagnostics) = parse_source(src);
+        assert_eq!(diagnostics, vec![]);
+        let (module, symbols, diagnostics) = resolve_module(module);
+        (
+            module_to_sexpr(&module),
+            symbols,
+            diagnostics.into_iter().map(|x| x.message).collect(),
+        )
+    }
+
+    #[test]
+    fn resolve_test() {
+        let (module, symbols, diagnostics) = helper(
+            "enum E { A, B(i32) }
+            const N: i32 = 1;
+            extern fun log(x: i32) = \"m\" \"log\";
+            fun f(x: i32, e: E) -> i32 {
+                let y = x + N;
+                { let x = y; log(x); }
+                let g = |a: i32| [y] -> i32 { a + y };
+                match e { E.B(z) => z, _ => E.A }
+            }",
+        );
+        assert_eq!(
+            diagnostics,
+            vec!["unused variable `g`", "function `f` is never used"]
+        );
+        assert!(module.contains(
+            "(params (x i32) (e (enum E))) i32 (block \
+             (let y (add (local x 0) (global N 0))) \
+             (block (let x (local y 2)) (call (func log 0) (local x 3))) \
+             (let g (lambda (captures y) (params (a i32)) i32 (block (add (local a 1) (local y 0))))) \
+             (match (local e 1) (arm (variant E B (bind z)) (local z 5)) (arm _ (member (enum E 0) A)))))"
+        ), "{}", module);
+        assert_eq!(symbols.funcs, vec!["log", "f"]);
+        assert_eq!(symbols.locals[1], vec!["x", "e", "y", "x", "g", "z"]);
+        assert_eq!(symbols.lambdas, vec![vec!["y", "a"]]);
+    }
+
+    #[test]
+    fn duplicate_test() {
+        let src = "fun f(a: i32, b: i32, a: i32) {}
+            struct S { x: i32, x: i32 }
+            fun f() {}
+            enum S { A }
+            const N: i32 = 1;
+            fun N() {}";
+        let (module, _) = parse_source(src);
+        let (_, _, diagnostics) = resolve_module(module);
+        let text = |span: Span| &src[span.pos..span.end()];
+        assert_eq!(
+            diagnostics
+                .iter()
+                .filter(|x| x.is_error())
+                .map(|x| (
+                    x.message.as_str(),
+                    text(x.span),
+                    x.labels.iter().map(|x| text(x.span)).collect::<Vec<_>>()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "identifier `a` is bound more than once in this parameter list",
+                    "fun f(a: i32, b: i32, a: i32) {}",
+                    vec![]
+                ),
+                (
+                    "field `x` is already declared",
+                    "struct S { x: i32, x: i32 }",
+                    vec![]
+                ),
+                (
+                    "the name `f` is defined multiple times",
+                    "fun f() {}",
+                    vec!["fun f(a: i32, b: i32, a: i32) {}"]
+                ),
+                (
+                    "the name `S` is defined multiple times",
+                    "enum S { A }",
+                    vec!["struct S { x: i32, x: i32 }"]
+                ),
+                (
+                    "the name `N` is defined multiple times",
+                    "fun N() {}",
+                    vec!["const N: i32 = 1;"]
+                ),
+            ]
+        );
+        assert_eq!(
+            diagnostics[2].labels[0].message,
+            "previous definition of `f` here"
+        );
+    }
+
+    #[test]
+    fn unused_test() {
+        let src = "fun main() { let a = 1; let _b = 2; let c = 3; g(|| [c] -> i32 { 1 }); }
+            fun g(f: fun() -> i32) { g(f); }
+            fun h(_x: i32, y: i32) { match 1 { z => h(z, 0) }; }
+            pub fun i() {}";
+        let (module, _) = parse_source(src);
+        let (_, _, diagnostics) = resolve_module(module);
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|x| (x.message.as_str(), &src[x.span.pos..x.span.end()]))
+                .collect::<Vec<_>>(),
+            vec![
+                ("unused variable `a`", "a"),
+                (
+                    "unused variable `y`",
+                    "fun h(_x: i32, y: i32) { match 1 { z => h(z, 0) }; }"
+                ),
+                (
+                    "function `h` is never used",
+                    "fun h(_x: i32, y: i32) { match 1 { z => h(z, 0) }; }"
+                ),
+            ]
+        );
+        assert_eq!(diagnostics[0].span, Span::new(17, 1));
+        assert!(diagnostics.iter().all(|x| !x.is_error()));
+    }
+
+    #[test]
+    fn scope_test() {
+        let src = "fun f() {
+                let x = 1;
+                { let x = true; x; }
+                x;
+                y;
+                let y = y;
+                { let z = 1; z; }
+                z;
+                if true { let w = 1; w; } else { w; }
+                match 1 { v => v, _ => v };
+            }";
+        let (module, _) = parse_source(src);
+        let (module, _, diagnostics) = resolve_module(module);
+        assert!(module_to_sexpr(&module).contains(
+            "(block (let x (bool true)) (local x 1)) (local x 0) (var y) (let y (var y))"
+        ));
+        let text = |span: Span| &src[span.pos..span.end()];
+        assert_eq!(
+            diagnostics
+                .iter()
+                .filter(|x| x.is_error())
+                .map(|x| (
+                    x.message.as_str(),
+                    text(x.span),
+                    x.labels
+                        .iter()
+                        .map(|x| (x.message.as_str(), text(x.span)))
+                        .collect::<Vec<_>>()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "cannot use `y` before its declaration",
+                    "y",
+                    vec![("`y` is declared here", "let y = y")]
+                ),
+                (
+                    "cannot use `y` before its declaration",
+                    "y",
+                    vec![("`y` is declared here", "let y = y")]
+                ),
+                (
+                    "cannot find value `z`",
+                    "z",
+                    vec![("`z` is declared here, but its scope has ended", "let z = 1")]
+                ),
+                (
+                    "cannot find value `w`",
+                    "w",
+                    vec![("`w` is declared here, but its scope has ended", "let w = 1")]
+                ),
+                (
+                    "cannot find value `v`",
+                    "v",
+                    vec![("`v` is declared here, but its scope has ended", "v")]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unresolved_test() {
+        let (_, _, diagnostics) = helper(
+            "fun f(x: T) {
+                y;
+                S { a: 1 };
+                E.A;
+                let z = 1;
+                || -> i32 { z };
+                match x { F.A => 1 };
+            }
+            enum E { B }",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "cannot find type `T`",
+                "cannot find value `y`",
+                "cannot find struct `S`",
+                "enum `E` has no variant `A`",
+                "`z` must be listed in the captures of the lambda",
+                "cannot find enum `F`",
+                "unused variable `z`",
+                "function `f` is never used",
+            ]
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use wasm::ast::{BlockType, FunctionBody, LocalEntry, MemoryImmediate, OperatorCode, ValueType};
+
+// The functions every generated module contains to manage the heap:
+//
+// * `alloc(size: i32, tag: i32) -> i32` allocates an object with a reference count of 1.
+// * `retain(ptr: i32) -> i32` increments the count of an object and returns it.
+// * `release(ptr: i32)` decrements it, releasing the references the object holds and freeing it
+//   once it drops to 0.
+//
+// The heap starts with the heads of the free lists, one for each block size up to `MAX_BLOCK`
+// in steps of 8 bytes, followed by the blocks. A block is an 8-byte header, the reference count
+// and the tag of the object, followed by the object itself; references point past the header.
+// Freed blocks go to the free list of their size, which `alloc` takes from before it moves the
+// heap pointer. Larger blocks are not reused. Everything below the first block, such as static
+// data and null, is never counted.
+//
+// Arrays are the only objects whose size is not known from their tag: their length is an `i32`
+// at offset 0 and the elements follow from `ARRAY_DATA` on.
+
+// `alloc`, `retain` and `release`.
+pub const FUNCS: usize = 3;
+
+const FREE_LISTS: u32 = 33;
+const MAX_BLOCK: u32 = (FREE_LISTS - 1) * 8;
+const HEADER: i32 = 8;
+pub const ARRAY_DATA: u32 = 8;
+
+// The size and the offsets of the references of the objects of a tag. Arrays have the size of
+// an element and whether the elements are references instead.
+pub struct Object {
+    pub size: u32,
+    pub refs: Vec<u32>,
+    pub elems: Option<(u32, bool)>,
+}
+
+// Where the heap is and the indices it is managed with.
+pub struct Runtime {
+    // The start of the heap, 8-byte aligned.
+    pub start: u32,
+    // The global holding the heap pointer.
+    pub heap: usize,
+    // The index of `alloc`, which `retain` and `release` follow.
+    pub alloc: usize,
+}
+
+pub(crate) fn i32_immediate(offset: u32) -> MemoryImmediate {
+    MemoryImmediate { flags: 2, offset }
+}
+
+fn block_size(size: u32) -> u32 {
+    (size + HEADER as u32 + 7) & !7
+}
+
+impl Runtime {
+    // The address of the first block, which the heap pointer starts at.
+    pub fn base(&self) -> u32 {
+        (self.start + FREE_LISTS * 4 + 7) & !7
+    }
+
+    pub fn retain(&self) -> usize {
+        self.alloc + 1
+    }
+
+    pub fn release(&self) -> usize {
+        self.alloc + 2
+    }
+
+    pub fn alloc_body(&self) -> FunctionBody {
+        let (size, tag, total, slot, ptr) = (0, 1, 2, 3, 4);
+        let heap = self.heap;
+        FunctionBody {
+            locals: vec![LocalEntry {
+                count: 3,
+                typ: ValueType::I32,
+            }],
+            codes: vec![
+                OperatorCode::GetLocal(size),
+                OperatorCode::I32Const(HEADER + 7),
+                OperatorCode::I32Add,
+                OperatorCode::I32Const(!7),
+                OperatorCode::I32And,
+                OperatorCode::SetLocal(total),
+                OperatorCode::Block(BlockType::Empty),
+                // Take the block from its free list if there is one.
+                OperatorCode::Block(BlockType::Empty),
+                OperatorCode::GetLocal(total),
+                OperatorCode::I32Const(MAX_BLOCK as i32),
+                OperatorCode::I32Gtu,
+                OperatorCode::BrIf(0),
+                OperatorCode::GetLocal(total),
+                OperatorCode::I32Const(1),
+                OperatorCode::I32Shru,
+                OperatorCode::I32Const(self.start as i32),
+                OperatorCode::I32Add,
+                OperatorCode::TeeLocal(slot),
+                OperatorCode::I32Load(i32_immediate(0)),
+                OperatorCode::TeeLocal(ptr),
+                OperatorCode::I32Eqz,
+                OperatorCode::BrIf(0),
+                OperatorCode::GetLocal(slot),
+                OperatorCode::GetLocal(ptr),
+                OperatorCode::I32Load(i32_immediate(0)),
+                OperatorCode::I32Store(i32_immediate(0)),
+                OperatorCode::Br(1),
+                OperatorCode::End,
+                // Otherwise move the heap pointer, growing the memory when it no longer fits.
+                OperatorCode::GetGlobal(heap),
+                OperatorCode::SetLocal(ptr),
+                OperatorCode::GetGlobal(heap),
+                OperatorCode::GetLocal(total),
+                OperatorCode::I32Add,
+                OperatorCode::SetGlobal(heap),
+                OperatorCode::GetGlobal(heap),
+                OperatorCode::CurrentMemory,
+                OperatorCode::I32Const(16),
+                OperatorCode::I32Shl,
+                OperatorCode::I32Leu,
+                OperatorCode::BrIf(0),
+                OperatorCode::GetGlobal(heap),
+                OperatorCode::I32Const(65535),
+                OperatorCode::I32Add,
+                OperatorCode::I32Const(16),
+                OperatorCode::I32Shru,
+                OperatorCode::CurrentMemory,
+                OperatorCode::I32Sub,
+                OperatorCode::GrowMemory,
+                OperatorCode::I32Const(-1),
+                OperatorCode::I32Ne,
+                OperatorCode::BrIf(0),
+                OperatorCode::Unreachable,
+                OperatorCode::End,
+                OperatorCode::GetLocal(ptr),
+                OperatorCode::I32Const(1),
+                OperatorCode::I32Store(i32_immediate(0)),
+                OperatorCode::GetLocal(ptr),
+                OperatorCode::GetLocal(tag),
+                OperatorCode::I32Store(i32_immediate(4)),
+                OperatorCode::GetLocal(ptr),
+                OperatorCode::I32Const(HEADER),
+                OperatorCode::I32Add,
+            ],
+        }
+    }
+
+    pub fn retain_body(&self) -> FunctionBody {
+        let ptr = 0;
+        FunctionBody {
+            locals: Vec::new(),
+            codes: vec![
+                OperatorCode::GetLocal(ptr),
+                OperatorCode::I32Const(self.base() as i32),
+                OperatorCode::I32Geu,
+                OperatorCode::If(BlockType::Empty),
+                OperatorCode::GetLocal(ptr),
+                OperatorCode::I32Const(HEADER),
+                OperatorCode::I32Sub,
+                OperatorCode::GetLocal(ptr),
+                OperatorCode::I32Const(HEADER),
+                OperatorCode::I32Sub,
+                OperatorCode::I32Load(i32_immediate(0)),
+                OperatorCode::I32Const(1),
+                OperatorCode::I32Add,
+                OperatorCode::I32Store(i32_immediate(0)),
+                OperatorCode::End,
+                OperatorCode::GetLocal(ptr),
+            ],
+        }
+    }
+
+    // Objects are told apart by their tag, the index of their `Object`.
+    pub fn release_body(&self, objects: &[Object]) -> FunctionBody {
+        let (ptr, header, slot, i, end) = (0, 1, 2, 3, 4);
+        let mut codes = vec![
+            OperatorCode::Block(BlockType::Empty),
+            OperatorCode::GetLocal(ptr),
+            OperatorCode::I32Const(self.base() as i32),
+            OperatorCode::I32Ltu,
+            OperatorCode::BrIf(0),
+            OperatorCode::GetLocal(ptr),
+            OperatorCode::I32Const(HEADER),
+            OperatorCode::I32Sub,
+            OperatorCode::TeeLocal(header),
+            OperatorCode::GetLocal(header),
+            OperatorCode::I32Load(i32_immediate(0)),
+            OperatorCode::I32Const(1),
+            OperatorCode::I32Sub,
+            OperatorCode::TeeLocal(slot),
+            OperatorCode::I32Store(i32_immediate(0)),
+            OperatorCode::GetLocal(slot),
+            OperatorCode::BrIf(0),
+            OperatorCode::Block(BlockType::Empty),
+        ];
+        // One block per tag, innermost first, so that `br_table` jumps to the code after the
+        // block of the tag, which then leaves the outer block with the free list in `slot`.
+        codes.extend(
+            objects
+                .iter()
+                .map(|_| OperatorCode::Block(BlockType::Empty)),
+        );
+        if !objects.is_empty() {
+            codes.extend(vec![
+                OperatorCode::GetLocal(header),
+                OperatorCode::I32Load(i32_immediate(4)),
+                OperatorCode::BrTable {
+                    index: objects.len() - 1,
+                    params: (0..objects.len()).collect(),
+                },
+            ]);
+        }
+        for (n, object) in objects.iter().enumerate() {
+            codes.push(OperatorCode::End);
+            for &offset in &object.refs {
+                codes.push(OperatorCode::GetLocal(ptr));
+                codes.push(OperatorCode::I32Load(i32_immediate(offset)));
+                codes.push(OperatorCode::Call(self.release()));
+            }
+            match object.elems {
+                Some((size, counted)) => {
+                    if counted {
+                        codes.extend(self.release_elems(ptr, i, end));
+                    }
+                    // The free list is picked at run time from the length.
+                    codes.extend(vec![
+                        OperatorCode::GetLocal(ptr),
+                        OperatorCode::I32Load(i32_immediate(0)),
+                        OperatorCode::I32Const(size as i32),
+                        OperatorCode::I32Mul,
+                        OperatorCode::I32Const(ARRAY_DATA as i32 + HEADER + 7),
+                        OperatorCode::I32Add,
+                        OperatorCode::I32Const(!7),
+                        OperatorCode::I32And,
+                        OperatorCode::TeeLocal(end),
+                        OperatorCode::I32Const(1),
+                        OperatorCode::I32Shru,
+                        OperatorCode::I32Const(self.start as i32),
+                        OperatorCode::I32Add,
+                        OperatorCode::I32Const(0),
+                        OperatorCode::GetLocal(end),
+                        OperatorCode::I32Const(MAX_BLOCK as i32),
+                        OperatorCode::I32Leu,
+                        OperatorCode::Select,
+                        OperatorCode::SetLocal(slot),
+                    ]);
+                }
+                None => {
+                    let total = block_size(object.size);
+                    let list = if total <= MAX_BLOCK {
+                        self.start + total / 2
+                    } else {
+                        0
+                    };
+                    codes.push(OperatorCode::I32Const(list as i32));
+                    codes.push(OperatorCode::SetLocal(slot));
+                }
+            }
+            if n + 1 < objects.len() {
+                codes.push(OperatorCode::Br(objects.len() - 1 - n));
+            }
+        }
+        if objects.is_empty() {
+            codes.push(OperatorCode::I32Const(0));
+            codes.push(OperatorCode::SetLocal(slot));
+        }
+        codes.extend(vec![
+            OperatorCode::End,
+            OperatorCode::GetLocal(slot),
+            OperatorCode::I32Eqz,
+            OperatorCode::BrIf(0),
+            OperatorCode::GetLocal(header),
+            OperatorCode::GetLocal(slot),
+            OperatorCode::I32Load(i32_immediate(0)),
+            OperatorCode::I32Store(i32_immediate(0)),
+            OperatorCode::GetLocal(slot),
+            OperatorCode::GetLocal(header),
+            OperatorCode::I32Store(i32_immediate(0)),
+            OperatorCode::End,
+        ]);
+        FunctionBody {
+            locals: vec![LocalEntry {
+                count: 4,
+                typ: ValueType::I32,
+            }],
+            codes,
+        }
+    }
+
+    // Releases the references an array at `ptr` holds, with `i` going from the first element to
+    // `end`.
+    fn release_elems(&self, ptr: usize, i: usize, end: usize) -> Vec<OperatorCode> {
+        vec![
+            OperatorCode::GetLocal(ptr),
+            OperatorCode::I32Const(ARRAY_DATA as i32),
+            OperatorCode::I32Add,
+            OperatorCode::TeeLocal(i),
+            OperatorCode::GetLocal(ptr),
+            OperatorCode::I32Load(i32_immediate(0)),
+            OperatorCode::I32Const(2),
+            OperatorCode::I32Shl,
+            OperatorCode::I32Add,
+            OperatorCode::SetLocal(end),
+            OperatorCode::Block(BlockType::Empty),
+            OperatorCode::Loop(BlockType::Empty),
+            OperatorCode::GetLocal(i),
+            OperatorCode::GetLocal(end),
+            OperatorCode::I32Geu,
+            OperatorCode::BrIf(1),
+            OperatorCode::GetLocal(i),
+            OperatorCode::I32Load(i32_immediate(0)),
+            OperatorCode::Call(self.release()),
+            OperatorCode::GetLocal(i),
+            OperatorCode::I32Const(4),
+            OperatorCode::I32Add,
+            OperatorCode::SetLocal(i),
+            OperatorCode::Br(0),
+            OperatorCode::End,
+            OperatorCode::End,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_test() {
+        let runtime = Runtime {
+            start: 16,
+            heap: 0,
+            alloc: 5,
+        };
+        assert_eq!(runtime.base(), 152);
+        let body = runtime.release_body(&[
+            Object {
+                size: 8,
+                refs: vec![4],
+                elems: None,
+            },
+            Object {
+                size: 300,
+                refs: Vec::new(),
+                elems: None,
+            },
+            Object {
+                size: ARRAY_DATA,
+                refs: Vec::new(),
+                elems: Some((4, true)),
+            },
+        ]);
+        let codes = &body.codes;
+        let opened = codes
+            .iter()
+            .filter(|x| matches!(x, OperatorCode::Block(_) | OperatorCode::Loop(_)))
+            .count();
+        assert_eq!(
+            opened,
+            codes.iter().filter(|x| **x == OperatorCode::End).count()
+        );
+        assert!(codes.contains(&OperatorCode::BrTable {
+            index: 2,
+            params: vec![0, 1, 2],
+        }));
+        // One for the field of the first object and one for the elements of the array.
+        assert_eq!(
+            codes
+                .iter()
+                .filter(|x| **x == OperatorCode::Call(runtime.release()))
+                .count(),
+            2
+        );
+        // A 16-byte block goes to the free list at `start + 16 / 8 * 4`; a 312-byte one is not
+        // reused. The list of an array is only known at run time.
+        let lists = codes
+            .windows(2)
+            .filter_map(|x| match x {
+                [OperatorCode::I32Const(list), OperatorCode::SetLocal(2)] => Some(*list),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lists, vec![24, 0]);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::ast::{
+    AssignOp, Expr, ExprKind, FuncDef, Member, MemberKind, Module, Mutability, Pattern, RefType,
+    Resolution, Type, TypeParam, Variant, Visibility,
+};
+use token::token::Literal;
+
+// Compact structural dumps such as `(add (var x) (i32 1))`, mainly for test expectations.
+// Spans are left out.
+
+fn list(head: &str, xs: impl IntoIterator<Item = String>) -> String {
+    let mut s = format!("({}", head);
+    for x in xs {
+        s.push(' ');
+        s.push_str(&x);
+    }
+    s.push(')');
+    s
+}
+
+fn string(x: &str) -> String {
+    Literal::String(x.to_string()).to_string()
+}
+
+fn params(xs: &[(String, Type)]) -> String {
+    list(
+        "params",
+        xs.iter().map(|(name, t)| list(name, vec![t.to_sexpr()])),
+    )
+}
+
+fn type_params(xs: &[TypeParam]) -> String {
+    list("type-params", xs.iter().map(|x| x.0.clone()))
+}
+
+fn func_def(FuncDef(name, ty_params, ps, ret): &FuncDef) -> Vec<String> {
+    vec![
+        name.clone(),
+        type_params(ty_params),
+        params(ps),
+        ret.as_ref().map_or("()".to_string(), Type::to_sexpr),
+    ]
+}
+
+impl Type {
+    pub fn to_sexpr(&self) -> String {
+        match self {
+            Type::I32 => "i32".to_string(),
+            Type::I64 => "i64".to_string(),
+            Type::F32 => "f32".to_string(),
+            Type::F64 => "f64".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Char => "char".to_string(),
+            Type::Param(x) => list("param", vec![x.clone()]),
+            Type::RefType(RefType::String) => "string".to_string(),
+            Type::RefType(RefType::Array(x)) => list("array", vec![x.to_sexpr()]),
+            Type::RefType(RefType::Struct(name, xs)) => list(
+                "struct",
+                std::iter::once(name.clone()).chain(xs.iter().map(Type::to_sexpr)),
+            ),
+            Type::RefType(RefType::Enum(name, xs)) => list(
+                "enum",
+                std::iter::once(name.clone()).chain(xs.iter().map(Type::to_sexpr)),
+            ),
+            Type::RefType(RefType::Func(xs, ret)) => list(
+                "fun",
+                vec![
+                    list("params", xs.iter().map(Type::to_sexpr)),
+                    ret.as_ref()
+                        .as_ref()
+                        .map_or("()".to_string(), Type::to_sexpr),
+                ],
+            ),
+        }
+    }
+}
+
+impl Pattern {
+    pub fn to_sexpr(&self) -> String {
+        match self {
+            Pattern::Wildcard => "_".to_string(),
+            Pattern::Binding(x) => list("bind", vec![x.clone()]),
+            Pattern::Literal(x) => x.to_sexpr(),
+            Pattern::Variant(enum_, variant, xs) => list(
+                "variant",
+                vec![enum_.clone(), variant.clone()]
+                    .into_iter()
+                    .chain(xs.iter().map(Pattern::to_sexpr)),
+            ),
+        }
+    }
+}
+
+impl Expr {
+    pub fn to_sexpr(&self) -> String {
+        let un = |head: &str, x: &Expr| list(head, vec![x.to_sexpr()]);
+        let bin = |head: &str, x: &Expr, y: &Expr| list(head, vec![x.to_sexpr(), y.to_sexpr()]);
+        match &self.kind {
+            ExprKind::StructLiteral(name, fields) => list(
+                "struct",
+                std::iter::once(name.clone()).chain(
+                    fields
+                        .iter()
+                        .map(|(name, x)| list(name, vec![x.to_sexpr()])),
+                ),
+            ),
+            ExprKind::I32Literal(x) => list("i32", vec![x.to_string()]),
+            ExprKind::I64Literal(x) => list("i64", vec![x.to_string()]),
+            ExprKind::F32Literal(x) => list("f32", vec![x.to_string()]),
+            ExprKind::F64Literal(x) => list("f64", vec![x.to_string()]),
+            ExprKind::StringLiteral(x) => list("string", vec![string(x)]),
+            ExprKind::ArrayLiteral(t, len) => list("array", vec![t.to_sexpr(), len.to_sexpr()]),
+            ExprKind::BoolLiteral(x) => list("bool", vec![x.to_string()]),
+            ExprKind::CharLiteral(x) => list("char", vec![Literal::Char(*x).to_string()]),
+            ExprKind::Var(x) => list("var", vec![x.clone()]),
+            ExprKind::Resolved(x, res) => {
+                let (head, i) = match res {
+                    Resolution::Local(i) => ("local", i),
+                    Resolution::Global(i) => ("global", i),
+                    Resolution::Func(i) => ("func", i),
+                    Resolution::Enum(i) => ("enum", i),
+                };
+                list(head, vec![x.clone(), i.to_string()])
+            }
+            ExprKind::Not(x) => un("not", x),
+            ExprKind::Plus(x) => un("plus", x),
+            ExprKind::Minus(x) => un("minus", x),
+            ExprKind::Cast(x, t) => list("cast", vec![x.to_sexpr(), t.to_sexpr()]),
+            ExprKind::Member(x, name) => list("member", vec![x.to_sexpr(), name.clone()]),
+            ExprKind::Index(x, y) => bin("index", x, y),
+            ExprKind::Call(f, args) => list(
+                "call",
+                std::iter::once(f.to_sexpr()).chain(args.iter().map(Expr::to_sexpr)),
+            ),
+            ExprKind::Add(x, y) => bin("add", x, y),
+            ExprKind::Sub(x, y) => bin("sub", x, y),
+            ExprKind::Mul(x, y) => bin("mul", x, y),
+            ExprKind::Div(x, y) => bin("div", x, y),
+            ExprKind::Mod(x, y) => bin("mod", x, y),
+            ExprKind::And(x, y) => bin("and", x, y),
+            ExprKind::Or(x, y) => bin("or", x, y),
+            ExprKind::BitAnd(x, y) => bin("bit-and", x, y),
+            ExprKind::BitOr(x, y) => bin("bit-or", x, y),
+            ExprKind::BitXor(x, y) => bin("bit-xor", x, y),
+            ExprKind::Pow(x, y) => bin("pow", x, y),
+            ExprKind::Eq(x, y) => bin("eq", x, y),
+            ExprKind::Ne(x, y) => bin("ne", x, y),
+            ExprKind::Lt(x, y) => bin("lt", x, y),
+            ExprKind::Lte(x, y) => bin("lte", x, y),
+            ExprKind::Gt(x, y) => bin("gt", x, y),
+            ExprKind::Gte(x, y) => bin("gte", x, y),
+            ExprKind::Range(x, y) => bin("range", x, y),
+            ExprKind::RangeInclusive(x, y) => bin("range-inclusive", x, y),
+            ExprKind::Block(stmts, tail) => list(
+                "block",
+                stmts
+                    .iter()
+                    .map(Expr::to_sexpr)
+                    .chain(tail.as_ref().as_ref().map(Expr::to_sexpr)),
+            ),
+            ExprKind::Let(name, _, t, x) => list(
+                "let",
+                std::iter::once(name.clone())
+                    .chain(t.as_ref().map(Type::to_sexpr))
+                    .chain(std::iter::once(x.to_sexpr())),
+            ),
+            ExprKind::If(first, elifs, els) => list(
+                "if",
+                std::iter::once(&**first)
+                    .chain(elifs)
+                    .map(|(cond, body)| list("branch", vec![cond.to_sexpr(), body.to_sexpr()]))
+                    .chain(els.as_ref().as_ref().map(|x| un("else", x))),
+            ),
+            ExprKind::While(x, y) => bin("while", x, y),
+            ExprKind::Return(x) => list("return", x.as_ref().as_ref().map(Expr::to_sexpr)),
+            ExprKind::Set(x, y) => bin("set", x, y),
+            ExprKind::CompoundSet(op, x, y) => {
+                let op = match op {
+                    AssignOp::Add => "set-add",
+                    AssignOp::Sub => "set-sub",
+                    AssignOp::Mul => "set-mul",
+                    AssignOp::Div => "set-div",
+                    AssignOp::Mod => "set-mod",
+                };
+                bin(op, x, y)
+            }
+            ExprKind::For(init, cond, step, body) => list(
+                "for",
+                vec![
+                    init.to_sexpr(),
+                    cond.to_sexpr(),
+                    step.to_sexpr(),
+                    body.to_sexpr(),
+                ],
+            ),
+            ExprKind::Lambda(captures, ps, ret, body) => list(
+                "lambda",
+                vec![
+                    list("captures", captures.iter().cloned()),
+                    params(ps),
+                    ret.to_sexpr(),
+                    body.to_sexpr(),
+                ],
+            ),
+            ExprKind::Match(x, arms) => list(
+                "match",
+                std::iter::once(x.to_sexpr()).chain(
+                    arms.iter()
+                        .map(|(p, x)| list("arm", vec![p.to_sexpr(), x.to_sexpr()])),
+                ),
+            ),
+            ExprKind::Break => "break".to_string(),
+            ExprKind::Continue => "continue".to_string(),
+            ExprKind::Error => "error".to_string(),
+        }
+    }
+}
+
+impl Member {
+    pub fn to_sexpr(&self) -> String {
+        let x = match &self.kind {
+            MemberKind::Struct(name, ty_params, fields) => list(
+                "struct",
+                vec![name.clone(), type_params(ty_params), params(fields)],
+            ),
+            MemberKind::Enum(name, ty_params, variants) => list(
+                "enum",
+                vec![name.clone(), type_params(ty_params)]
+                    .into_iter()
+                    .chain(
+                        variants
+                            .iter()
+                            .map(|Variant(name, ts)| list(name, ts.iter().map(Type::to_sexpr))),
+                    ),
+            ),
+            MemberKind::Func(def, body) => list(
+                "fun",
+                func_def(def)
+                    .into_iter()
+                    .chain(std::iter::once(body.to_sexpr())),
+            ),
+            MemberKind::ExternFun(def, module, name) => list(
+                "extern",
+                func_def(def)
+                    .into_iter()
+                    .chain(vec![string(module), string(name)]),
+            ),
+            MemberKind::Import(path) => list("import", vec![path.join(".")]),
+            MemberKind::Global(name, mutability, t, init) => list(
+                match mutability {
+                    Mutability::Const => "const",
+                    Mutability::Immutable => "let",
+                    Mutability::Mutable => "let-mut",
+                },
+                vec![name.clone(), t.to_sexpr(), init.to_sexpr()],
+            ),
+            MemberKind::Error => "error".to_string(),
+        };
+        match self.visibility {
+            Visibility::Public => list("pub", vec![x]),
+            Visibility::Private => x,
+        }
+    }
+}
+
+// One member per line.
+pub fn module_to_sexpr(x: &Module) -> String {
+    x.iter().map(|x| x.to_sexpr() + "\n").collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    #[test]
+    fn sexpr_test() {
+        let (module, _) = parse_source(
+            "pub fun f<T>(x: [T]) -> i32 { let y = x[0] + 1; match y { E.A(_, z) => -z, _ => \"s\" } }
+            const N: i64 = 1i64;",
+        );
+        assert_eq!(
+            module_to_sexpr(&module),
+            "(pub (fun f (type-params T) (params (x (array (param T)))) i32 \
+             (block (let y (add (index (var x) (i32 0)) (i32 1))) \
+             (match (var y) (arm (variant E A _ (bind z)) (minus (var z))) \
+             (arm _ (string \"s\"))))))\n\
+             (const N i64 (i64 1))\n"
+        );
+    }
+}
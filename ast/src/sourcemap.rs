@@ -0,0 +1,103 @@
+use crate::ast::Span;
+use diagnostics::json::string;
+use diagnostics::render::SourceFile;
+use wasm::ast::WasmASTRoot;
+
+// Source maps (revision 3) of generated modules, which browser devtools use to step through the
+// tlang source. For wasm, every mapping is on line 0 with the byte offset of the instruction in
+// the module as its column. The module points to the map with `Options::source_map`.
+
+// For each function body, the span each run of instructions comes from, by the index of the
+// first instruction of the run.
+pub type CodeSpans = Vec<Vec<(usize, Span)>>;
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// A base64 VLQ: the sign in the lowest bit, then 5 bits per digit, least significant first.
+fn vlq(x: i64, out: &mut String) {
+    let mut x = if x < 0 {
+        (-x as u64) << 1 | 1
+    } else {
+        (x as u64) << 1
+    };
+    loop {
+        let digit = (x & 31) as usize;
+        x >>= 5;
+        out.push(BASE64[digit | if x > 0 { 32 } else { 0 }] as char);
+        if x == 0 {
+            break;
+        }
+    }
+}
+
+// `spans` are those `codegen_module` returns for `root`.
+pub fn source_map(root: &WasmASTRoot, spans: &CodeSpans, file: &SourceFile) -> String {
+    let mut mappings = String::new();
+    let (mut last_offset, mut last_line, mut last_col) = (0, 0, 0);
+    for (offsets, spans) in root.code_offsets().iter().zip(spans) {
+        for &(i, span) in spans {
+            let offset = match offsets.get(i) {
+                Some(&x) => x as i64,
+                None => continue,
+            };
+            let (line, col) = file.line_col(span.pos);
+            let (line, col) = (line as i64, col as i64);
+            if !mappings.is_empty() {
+                mappings.push(',');
+            }
+            vlq(offset - last_offset, &mut mappings);
+            vlq(0, &mut mappings);
+            vlq(line - last_line, &mut mappings);
+            vlq(col - last_col, &mut mappings);
+            last_offset = offset;
+            last_line = line;
+            last_col = col;
+        }
+    }
+    format!(
+        "{{\"version\":3,\"sources\":[{}],\"names\":[],\"mappings\":{}}}",
+        string(file.name()),
+        string(&mappings)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::ast::{CodeSection, FunctionBody, OperatorCode};
+
+    #[test]
+    fn source_map_test() {
+        let mut out = String::new();
+        for x in &[0, 1, -1, 15, 16, -100] {
+            vlq(*x, &mut out);
+            out.push(' ');
+        }
+        assert_eq!(out, "A C D e gB pG ");
+
+        let root = WasmASTRoot {
+            code_section: Some(CodeSection(vec![FunctionBody {
+                locals: Vec::new(),
+                codes: vec![
+                    OperatorCode::I32Const(1),
+                    OperatorCode::I32Const(2),
+                    OperatorCode::I32Add,
+                    OperatorCode::Drop,
+                ],
+            }])),
+            ..WasmASTRoot::default()
+        };
+        let file = SourceFile::new("main.tl", "fun main() {\n  1 + 2;\n}");
+        // The literals, then `+` and the statement, the last of which has no instructions.
+        let spans = vec![vec![
+            (0, Span::new(15, 1)),
+            (1, Span::new(19, 1)),
+            (2, Span::new(15, 5)),
+            (4, Span::new(11, 11)),
+        ]];
+        assert_eq!(
+            source_map(&root, &spans, &file),
+            "{\"version\":3,\"sources\":[\"main.tl\"],\"names\":[],\"mappings\":\"aACE,EAAI,EAAJ\"}"
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use crate::ast::{Expr, ExprKind, Member, Module};
+use crate::fold::{self, Folder};
+use crate::sexpr::module_to_sexpr;
+use diagnostics::span::Span;
+
+// Span-insensitive comparison and hashing, so that trees that only differ in layout (whitespace,
+// comments, reordered lines above them) are treated as the same by the incremental cache.
+
+struct EraseSpans;
+
+impl Folder for EraseSpans {
+    fn fold_member(&mut self, x: Member) -> Member {
+        Member {
+            span: Span::default(),
+            ..fold::walk_member(self, x)
+        }
+    }
+
+    fn fold_expr(&mut self, x: Expr) -> Expr {
+        let kind = match fold::walk_expr(self, x).kind {
+            ExprKind::Let(name, _, t, init) => ExprKind::Let(name, Span::default(), t, init),
+            kind => kind,
+        };
+        Expr::new(kind, Span::default())
+    }
+}
+
+pub fn erase_module_spans(x: Module) -> Module {
+    EraseSpans.fold_module(x)
+}
+
+pub fn module_eq_ignore_spans(x: &Module, y: &Module) -> bool {
+    x.len() == y.len() && x.iter().zip(y).all(|(x, y)| x.eq_ignore_spans(y))
+}
+
+// The standard library hashers are not guaranteed to be stable across releases, and the hash
+// ends up in the on-disk cache, so this is FNV-1a over the span-free S-expression dump.
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn module_structural_hash(x: &Module) -> u64 {
+    fnv1a(&module_to_sexpr(x))
+}
+
+impl Expr {
+    pub fn erase_spans(self) -> Expr {
+        EraseSpans.fold_expr(self)
+    }
+
+    pub fn eq_ignore_spans(&self, other: &Expr) -> bool {
+        self.clone().erase_spans() == other.clone().erase_spans()
+    }
+
+    pub fn structural_hash(&self) -> u64 {
+        fnv1a(&self.to_sexpr())
+    }
+}
+
+impl Member {
+    pub fn erase_spans(self) -> Member {
+        EraseSpans.fold_member(self)
+    }
+
+    pub fn eq_ignore_spans(&self, other: &Member) -> bool {
+        self.clone().erase_spans() == other.clone().erase_spans()
+    }
+
+    pub fn structural_hash(&self) -> u64 {
+        fnv1a(&self.to_sexpr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    fn parse(input: &str) -> Module {
+        let (module, diagnostics) = parse_source(input);
+        assert!(diagnostics.is_empty(), "{}", input);
+        module
+    }
+
+    #[test]
+    fn structural_test() {
+        let x = parse("fun f(x: i32) -> i32 { let y = x + 1; match y { 1 => 2, _ => y } }");
+        let y = parse(
+            "// comment
+            fun f(x:i32)->i32{
+                let y = x+1;
+                match y {
+                    1 => 2,
+                    _ => y
+                }
+            }",
+        );
+        let z = parse("fun f(x: i32) -> i32 { let y = x + 2; match y { 1 => 2, _ => y } }");
+
+        assert_ne!(x, y);
+        assert!(module_eq_ignore_spans(&x, &y));
+        assert_eq!(erase_module_spans(x.clone()), erase_module_spans(y.clone()));
+        assert_eq!(module_structural_hash(&x), module_structural_hash(&y));
+
+        assert!(!module_eq_ignore_spans(&x, &z));
+        assert_ne!(module_structural_hash(&x), module_structural_hash(&z));
+        assert_ne!(x[0].structural_hash(), z[0].structural_hash());
+    }
+}
@@ -0,0 +1,444 @@
+use crate::ast::{AssignOp, Expr, ExprKind, Member, MemberKind, Span};
+
+// Terse construction of expected trees for tests. All spans are `Span::default()`, so compare
+// against trees that went through `erase_spans`.
+//
+// The `expr!`, `ty!` and `pat!` macros take the same notation that `to_sexpr` prints, so a dump
+// can be pasted into a test as is. Bare identifiers are variables and bare literals pick the
+// literal kind from their Rust type (`1` is `i32`, `2i64` is `i64`, `3.5` is `f64`):
+//
+//     expr!((add a (mul b 2)))
+//     expr!((block [(let x i32 1) (set x (add x 1))] x))
+//
+// The one difference is `block`, whose statements go in brackets to tell them from the tail.
+
+pub fn e(kind: ExprKind) -> Expr {
+    Expr::new(kind, Span::default())
+}
+
+pub fn b(kind: ExprKind) -> Box<Expr> {
+    Box::new(e(kind))
+}
+
+pub fn m(kind: MemberKind) -> Member {
+    Member::new(kind, Span::default())
+}
+
+pub trait Lit {
+    fn lit(self) -> ExprKind;
+}
+
+impl Lit for i32 {
+    fn lit(self) -> ExprKind {
+        ExprKind::I32Literal(self)
+    }
+}
+
+impl Lit for i64 {
+    fn lit(self) -> ExprKind {
+        ExprKind::I64Literal(self)
+    }
+}
+
+impl Lit for f32 {
+    fn lit(self) -> ExprKind {
+        ExprKind::F32Literal(self)
+    }
+}
+
+impl Lit for f64 {
+    fn lit(self) -> ExprKind {
+        ExprKind::F64Literal(self)
+    }
+}
+
+impl Lit for bool {
+    fn lit(self) -> ExprKind {
+        ExprKind::BoolLiteral(self)
+    }
+}
+
+impl Lit for char {
+    fn lit(self) -> ExprKind {
+        ExprKind::CharLiteral(self)
+    }
+}
+
+impl Lit for &str {
+    fn lit(self) -> ExprKind {
+        ExprKind::StringLiteral(self.to_string())
+    }
+}
+
+pub fn unary(op: &str, x: Expr) -> Expr {
+    let x = Box::new(x);
+    e(match op {
+        "not" => ExprKind::Not(x),
+        "plus" => ExprKind::Plus(x),
+        "minus" => ExprKind::Minus(x),
+        _ => panic!("unknown unary operator `{}`", op),
+    })
+}
+
+pub fn binary(op: &str, x: Expr, y: Expr) -> Expr {
+    let (x, y) = (Box::new(x), Box::new(y));
+    e(match op {
+        "index" => ExprKind::Index(x, y),
+        "add" => ExprKind::Add(x, y),
+        "sub" => ExprKind::Sub(x, y),
+        "mul" => ExprKind::Mul(x, y),
+        "div" => ExprKind::Div(x, y),
+        "mod" => ExprKind::Mod(x, y),
+        "and" => ExprKind::And(x, y),
+        "or" => ExprKind::Or(x, y),
+        "bit-and" => ExprKind::BitAnd(x, y),
+        "bit-or" => ExprKind::BitOr(x, y),
+        "bit-xor" => ExprKind::BitXor(x, y),
+        "pow" => ExprKind::Pow(x, y),
+        "eq" => ExprKind::Eq(x, y),
+        "ne" => ExprKind::Ne(x, y),
+        "lt" => ExprKind::Lt(x, y),
+        "lte" => ExprKind::Lte(x, y),
+        "gt" => ExprKind::Gt(x, y),
+        "gte" => ExprKind::Gte(x, y),
+        "range" => ExprKind::Range(x, y),
+        "range-inclusive" => ExprKind::RangeInclusive(x, y),
+        "while" => ExprKind::While(x, y),
+        "set" => ExprKind::Set(x, y),
+        "set-add" => ExprKind::CompoundSet(AssignOp::Add, x, y),
+        "set-sub" => ExprKind::CompoundSet(AssignOp::Sub, x, y),
+        "set-mul" => ExprKind::CompoundSet(AssignOp::Mul, x, y),
+        "set-div" => ExprKind::CompoundSet(AssignOp::Div, x, y),
+        "set-mod" => ExprKind::CompoundSet(AssignOp::Mod, x, y),
+        _ => panic!("unknown binary operator `{}`", op),
+    })
+}
+
+#[macro_export]
+macro_rules! ty {
+    (i32) => {
+        $crate::ast::Type::I32
+    };
+    (i64) => {
+        $crate::ast::Type::I64
+    };
+    (f32) => {
+        $crate::ast::Type::F32
+    };
+    (f64) => {
+        $crate::ast::Type::F64
+    };
+    (bool) => {
+        $crate::ast::Type::Bool
+    };
+    (char) => {
+        $crate::ast::Type::Char
+    };
+    (string) => {
+        $crate::ast::Type::RefType($crate::ast::RefType::String)
+    };
+    ((param $x:ident)) => {
+        $crate::ast::Type::Param(stringify!($x).to_string())
+    };
+    ((array $t:tt)) => {
+        $crate::ast::Type::RefType($crate::ast::RefType::Array(Box::new($crate::ty!($t))))
+    };
+    ((struct $x:ident $($t:tt)*)) => {
+        $crate::ast::Type::RefType($crate::ast::RefType::Struct(
+            stringify!($x).to_string(),
+            vec![$($crate::ty!($t)),*],
+        ))
+    };
+    ((enum $x:ident $($t:tt)*)) => {
+        $crate::ast::Type::RefType($crate::ast::RefType::Enum(
+            stringify!($x).to_string(),
+            vec![$($crate::ty!($t)),*],
+        ))
+    };
+}
+
+#[macro_export]
+macro_rules! pat {
+    (_) => {
+        $crate::ast::Pattern::Wildcard
+    };
+    ((bind $x:ident)) => {
+        $crate::ast::Pattern::Binding(stringify!($x).to_string())
+    };
+    ((variant $x:ident $y:ident $($p:tt)*)) => {
+        $crate::ast::Pattern::Variant(
+            stringify!($x).to_string(),
+            stringify!($y).to_string(),
+            vec![$($crate::pat!($p)),*],
+        )
+    };
+    ($x:tt) => {
+        $crate::ast::Pattern::Literal($crate::expr!($x))
+    };
+}
+
+#[macro_export]
+macro_rules! expr {
+    ((var $x:ident)) => {
+        $crate::testing::e($crate::ast::ExprKind::Var(stringify!($x).to_string()))
+    };
+    ((i32 $x:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::I32Literal($x))
+    };
+    ((i64 $x:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::I64Literal($x))
+    };
+    ((f32 $x:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::F32Literal($x))
+    };
+    ((f64 $x:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::F64Literal($x))
+    };
+    ((bool $x:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::BoolLiteral($x))
+    };
+    ((char $x:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::CharLiteral($x))
+    };
+    ((string $x:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::StringLiteral($x.to_string()))
+    };
+    ((local $x:ident $i:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::Resolved(
+            stringify!($x).to_string(),
+            $crate::ast::Resolution::Local($i),
+        ))
+    };
+    ((global $x:ident $i:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::Resolved(
+            stringify!($x).to_string(),
+            $crate::ast::Resolution::Global($i),
+        ))
+    };
+    ((func $x:ident $i:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::Resolved(
+            stringify!($x).to_string(),
+            $crate::ast::Resolution::Func($i),
+        ))
+    };
+    ((enum $x:ident $i:literal)) => {
+        $crate::testing::e($crate::ast::ExprKind::Resolved(
+            stringify!($x).to_string(),
+            $crate::ast::Resolution::Enum($i),
+        ))
+    };
+    ((struct $x:ident $(($f:ident $y:tt))*)) => {
+        $crate::testing::e($crate::ast::ExprKind::StructLiteral(
+            stringify!($x).to_string(),
+            vec![$((stringify!($f).to_string(), $crate::expr!($y))),*],
+        ))
+    };
+    ((array $t:tt $x:tt)) => {
+        $crate::testing::e($crate::ast::ExprKind::ArrayLiteral(
+            $crate::ty!($t),
+            Box::new($crate::expr!($x)),
+        ))
+    };
+    ((member $x:tt $y:ident)) => {
+        $crate::testing::e($crate::ast::ExprKind::Member(
+            Box::new($crate::expr!($x)),
+            stringify!($y).to_string(),
+        ))
+    };
+    ((cast $x:tt $t:tt)) => {
+        $crate::testing::e($crate::ast::ExprKind::Cast(
+            Box::new($crate::expr!($x)),
+            $crate::ty!($t),
+        ))
+    };
+    ((call $f:tt $($x:tt)*)) => {
+        $crate::testing::e($crate::ast::ExprKind::Call(
+            Box::new($crate::expr!($f)),
+            vec![$($crate::expr!($x)),*],
+        ))
+    };
+    ((block [$($x:tt)*] $($y:tt)?)) => {
+        $crate::testing::e($crate::ast::ExprKind::Block(
+            vec![$($crate::expr!($x)),*],
+            Box::new(None$(.or(Some($crate::expr!($y))))?),
+        ))
+    };
+    ((let $x:ident $t:tt $y:tt)) => {
+        $crate::testing::e($crate::ast::ExprKind::Let(
+            stringify!($x).to_string(),
+            $crate::ast::Span::default(),
+            Some($crate::ty!($t)),
+            Box::new($crate::expr!($y)),
+        ))
+    };
+    ((let $x:ident $y:tt)) => {
+        $crate::testing::e($crate::ast::ExprKind::Let(
+            stringify!($x).to_string(),
+            $crate::ast::Span::default(),
+            None,
+            Box::new($crate::expr!($y)),
+        ))
+    };
+    ((if (branch $c:tt $x:tt) $((branch $cs:tt $xs:tt))* $((else $y:tt))?)) => {
+        $crate::testing::e($crate::ast::ExprKind::If(
+            Box::new(($crate::expr!($c), $crate::expr!($x))),
+            vec![$(($crate::expr!($cs), $crate::expr!($xs))),*],
+            Box::new(None$(.or(Some($crate::expr!($y))))?),
+        ))
+    };
+    ((return $($x:tt)?)) => {
+        $crate::testing::e($crate::ast::ExprKind::Return(Box::new(
+            None$(.or(Some($crate::expr!($x))))?,
+        )))
+    };
+    ((match $x:tt $((arm $p:tt $y:tt))*)) => {
+        $crate::testing::e($crate::ast::ExprKind::Match(
+            Box::new($crate::expr!($x)),
+            vec![$(($crate::pat!($p), $crate::expr!($y))),*],
+        ))
+    };
+    ((for $a:tt $b:tt $c:tt $d:tt)) => {
+        $crate::testing::e($crate::ast::ExprKind::For(
+            Box::new($crate::expr!($a)),
+            Box::new($crate::expr!($b)),
+            Box::new($crate::expr!($c)),
+            Box::new($crate::expr!($d)),
+        ))
+    };
+    ((lambda (captures $($c:ident)*) (params $(($x:ident $t:tt))*) $r:tt $y:tt)) => {
+        $crate::testing::e($crate::ast::ExprKind::Lambda(
+            vec![$(stringify!($c).to_string()),*],
+            vec![$((stringify!($x).to_string(), $crate::ty!($t))),*],
+            $crate::ty!($r),
+            Box::new($crate::expr!($y)),
+        ))
+    };
+    ((bit-and $x:tt $y:tt)) => {
+        $crate::testing::binary("bit-and", $crate::expr!($x), $crate::expr!($y))
+    };
+    ((bit-or $x:tt $y:tt)) => {
+        $crate::testing::binary("bit-or", $crate::expr!($x), $crate::expr!($y))
+    };
+    ((bit-xor $x:tt $y:tt)) => {
+        $crate::testing::binary("bit-xor", $crate::expr!($x), $crate::expr!($y))
+    };
+    ((range-inclusive $x:tt $y:tt)) => {
+        $crate::testing::binary("range-inclusive", $crate::expr!($x), $crate::expr!($y))
+    };
+    ((set-$op:ident $x:tt $y:tt)) => {
+        $crate::testing::binary(
+            concat!("set-", stringify!($op)),
+            $crate::expr!($x),
+            $crate::expr!($y),
+        )
+    };
+    ((while $x:tt $y:tt)) => {
+        $crate::testing::binary("while", $crate::expr!($x), $crate::expr!($y))
+    };
+    (($op:ident $x:tt $y:tt)) => {
+        $crate::testing::binary(stringify!($op), $crate::expr!($x), $crate::expr!($y))
+    };
+    (($op:ident $x:tt)) => {
+        $crate::testing::unary(stringify!($op), $crate::expr!($x))
+    };
+    (break) => {
+        $crate::testing::e($crate::ast::ExprKind::Break)
+    };
+    (continue) => {
+        $crate::testing::e($crate::ast::ExprKind::Continue)
+    };
+    ($x:literal) => {
+        $crate::testing::e($crate::testing::Lit::lit($x))
+    };
+    ($x:ident) => {
+        $crate::testing::e($crate::ast::ExprKind::Var(stringify!($x).to_string()))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Pattern, RefType, Type};
+
+    #[test]
+    fn expr_macro_test() {
+        assert_eq!(
+            expr!((add a (mul (var b) 2))),
+            e(ExprKind::Add(
+                b(ExprKind::Var("a".to_string())),
+                b(ExprKind::Mul(
+                    b(ExprKind::Var("b".to_string())),
+                    b(ExprKind::I32Literal(2)),
+                )),
+            ))
+        );
+        assert_eq!(
+            expr!((block [(let x (array i32) (array i32 3)) (bit-or 2i64 3.5)] (minus x))),
+            e(ExprKind::Block(
+                vec![
+                    e(ExprKind::Let(
+                        "x".to_string(),
+                        Span::default(),
+                        Some(Type::RefType(RefType::Array(Box::new(Type::I32)))),
+                        b(ExprKind::ArrayLiteral(
+                            Type::I32,
+                            b(ExprKind::I32Literal(3))
+                        )),
+                    )),
+                    e(ExprKind::BitOr(
+                        b(ExprKind::I64Literal(2)),
+                        b(ExprKind::F64Literal(3.5)),
+                    )),
+                ],
+                Box::new(Some(e(ExprKind::Minus(b(ExprKind::Var("x".to_string())))))),
+            ))
+        );
+        assert_eq!(
+            expr!((if (branch c (block [] "s")) (branch true (block [break])) (else (return)))),
+            e(ExprKind::If(
+                Box::new((
+                    e(ExprKind::Var("c".to_string())),
+                    e(ExprKind::Block(
+                        vec![],
+                        Box::new(Some(e(ExprKind::StringLiteral("s".to_string())))),
+                    )),
+                )),
+                vec![(
+                    e(ExprKind::BoolLiteral(true)),
+                    e(ExprKind::Block(vec![e(ExprKind::Break)], Box::new(None))),
+                )],
+                Box::new(Some(e(ExprKind::Return(Box::new(None))))),
+            ))
+        );
+        assert_eq!(
+            expr!((match x (arm (variant E A _ (bind y)) y) (arm 'c' 1))),
+            e(ExprKind::Match(
+                b(ExprKind::Var("x".to_string())),
+                vec![
+                    (
+                        Pattern::Variant(
+                            "E".to_string(),
+                            "A".to_string(),
+                            vec![Pattern::Wildcard, Pattern::Binding("y".to_string())],
+                        ),
+                        e(ExprKind::Var("y".to_string())),
+                    ),
+                    (
+                        Pattern::Literal(e(ExprKind::CharLiteral('c'))),
+                        e(ExprKind::I32Literal(1)),
+                    ),
+                ],
+            ))
+        );
+    }
+
+    #[test]
+    fn sexpr_round_trip_test() {
+        let x = expr!((call (member (struct P (x 1) (y (not false))) f) (index xs 0) (lte a b)));
+        assert_eq!(
+            x.to_sexpr(),
+            "(call (member (struct P (x (i32 1)) (y (not (bool false)))) f) \
+             (index (var xs) (i32 0)) (lte (var a) (var b)))"
+        );
+    }
+}
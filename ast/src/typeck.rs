@@ -0,0 +1,1340 @@
+use crate::ast::{
+    AssignOp, Expr, ExprKind, FuncDef, Ident, Member, MemberKind, Module, Mutability, Pattern,
+    RefType, Resolution, Span, Type, TypeParam, Variant,
+};
+use crate::visit::{self, Visitor};
+use diagnostics::code::Code;
+use diagnostics::diagnostic::Diagnostic;
+use std::collections::HashMap;
+use std::fmt;
+
+// Types as the checker sees them. `Var` is an inference variable standing for a type argument
+// of a generic function, struct literal or enum variant that has not been pinned down yet.
+// `Unknown` is the type of erroneous expressions and matches anything, so that one mistake is
+// reported once.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ty {
+    I32,
+    I64,
+    F32,
+    F64,
+    Bool,
+    Char,
+    String,
+    Array(Box<Ty>),
+    Struct(Ident, Vec<Ty>),
+    Enum(Ident, Vec<Ty>),
+    Func(Vec<Ty>, Box<Ty>),
+    Param(Ident),
+    Unit,
+    // `return`, `break` and `continue`.
+    Never,
+    Var(usize),
+    Unknown,
+}
+
+impl Ty {
+    pub fn from_type(x: &Type) -> Ty {
+        match x {
+            Type::I32 => Ty::I32,
+            Type::I64 => Ty::I64,
+            Type::F32 => Ty::F32,
+            Type::F64 => Ty::F64,
+            Type::Bool => Ty::Bool,
+            Type::Char => Ty::Char,
+            Type::Param(x) => Ty::Param(x.clone()),
+            Type::RefType(RefType::String) => Ty::String,
+            Type::RefType(RefType::Array(x)) => Ty::Array(Box::new(Ty::from_type(x))),
+            Type::RefType(RefType::Struct(name, xs)) => {
+                Ty::Struct(name.clone(), xs.iter().map(Ty::from_type).collect())
+            }
+            Type::RefType(RefType::Enum(name, xs)) => {
+                Ty::Enum(name.clone(), xs.iter().map(Ty::from_type).collect())
+            }
+            Type::RefType(RefType::Func(xs, ret)) => Ty::Func(
+                xs.iter().map(Ty::from_type).collect(),
+                Box::new(Ty::from_ret(ret.as_ref().as_ref())),
+            ),
+        }
+    }
+
+    pub fn from_ret(x: Option<&Type>) -> Ty {
+        x.map_or(Ty::Unit, Ty::from_type)
+    }
+
+    fn substitute(&self, params: &[TypeParam], args: &[Ty]) -> Ty {
+        let sub = |x: &Ty| x.substitute(params, args);
+        match self {
+            Ty::Param(x) => params
+                .iter()
+                .position(|p| &p.0 == x)
+                .and_then(|i| args.get(i))
+                .cloned()
+                .unwrap_or_else(|| self.clone()),
+            Ty::Array(x) => Ty::Array(Box::new(sub(x))),
+            Ty::Struct(name, xs) => Ty::Struct(name.clone(), xs.iter().map(sub).collect()),
+            Ty::Enum(name, xs) => Ty::Enum(name.clone(), xs.iter().map(sub).collect()),
+            Ty::Func(xs, ret) => Ty::Func(xs.iter().map(sub).collect(), Box::new(sub(ret))),
+            x => x.clone(),
+        }
+    }
+
+    pub(crate) fn is_integer(&self) -> bool {
+        matches!(self, Ty::I32 | Ty::I64)
+    }
+
+    pub(crate) fn is_numeric(&self) -> bool {
+        matches!(self, Ty::I32 | Ty::I64 | Ty::F32 | Ty::F64)
+    }
+
+    fn is_scalar(&self) -> bool {
+        self.is_numeric() || matches!(self, Ty::Bool | Ty::Char)
+    }
+
+    // Numbers convert freely between each other; `char` and `bool` only to integers, and
+    // only integers back to `char`.
+    fn can_cast(&self, to: &Ty) -> bool {
+        self == to
+            || match self {
+                t if t.is_numeric() => to.is_numeric() || (t.is_integer() && *to == Ty::Char),
+                Ty::Char | Ty::Bool => to.is_integer(),
+                _ => false,
+            }
+    }
+}
+
+fn args(f: &mut fmt::Formatter, xs: &[Ty]) -> fmt::Result {
+    for (i, x) in xs.iter().enumerate() {
+        if i != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", x)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ty::I32 => write!(f, "i32"),
+            Ty::I64 => write!(f, "i64"),
+            Ty::F32 => write!(f, "f32"),
+            Ty::F64 => write!(f, "f64"),
+            Ty::Bool => write!(f, "bool"),
+            Ty::Char => write!(f, "char"),
+            Ty::String => write!(f, "string"),
+            Ty::Array(x) => write!(f, "[{}]", x),
+            Ty::Struct(name, xs) | Ty::Enum(name, xs) if xs.is_empty() => write!(f, "{}", name),
+            Ty::Struct(name, xs) | Ty::Enum(name, xs) => {
+                write!(f, "{}<", name)?;
+                args(f, xs)?;
+                write!(f, ">")
+            }
+            Ty::Func(xs, ret) => {
+                write!(f, "fun(")?;
+                args(f, xs)?;
+                match &**ret {
+                    Ty::Unit => write!(f, ")"),
+                    ret => write!(f, ") -> {}", ret),
+                }
+            }
+            Ty::Param(x) => write!(f, "{}", x),
+            Ty::Unit => write!(f, "()"),
+            Ty::Never => write!(f, "!"),
+            Ty::Var(_) | Ty::Unknown => write!(f, "_"),
+        }
+    }
+}
+
+// Types of the locals of every function and lambda, indexed like `resolver::Symbols`.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Types {
+    pub locals: Vec<Vec<Ty>>,
+    pub lambdas: Vec<Vec<Ty>>,
+}
+
+// Mirrors the scopes of the resolver so that captures can be looked up by name.
+struct Frame {
+    locals: Vec<Ty>,
+    scopes: Vec<Vec<(Ident, usize)>>,
+    ret: Ty,
+}
+
+impl Frame {
+    fn new(locals: Vec<(Ident, Ty)>, ret: Ty) -> Frame {
+        let scope = locals.iter().map(|x| x.0.clone()).zip(0..).collect();
+        Frame {
+            locals: locals.into_iter().map(|x| x.1).collect(),
+            scopes: vec![scope],
+            ret,
+        }
+    }
+}
+
+type StructDef<'a> = (&'a [TypeParam], &'a [(Ident, Type)]);
+type EnumDef<'a> = (&'a [TypeParam], &'a [Variant]);
+
+struct Checker<'a> {
+    structs: HashMap<&'a str, StructDef<'a>>,
+    enums: HashMap<&'a str, EnumDef<'a>>,
+    funcs: Vec<&'a FuncDef>,
+    globals: Vec<(&'a Ident, Mutability, Ty)>,
+    // Bindings of the inference variables.
+    vars: Vec<Option<Ty>>,
+    frames: Vec<Frame>,
+    types: Types,
+    diagnostics: Vec<Diagnostic>,
+}
+
+pub(crate) fn plural(n: usize, word: &str) -> String {
+    format!("{} {}{}", n, word, if n == 1 { "" } else { "s" })
+}
+
+fn supplied(n: usize) -> String {
+    format!("{} {}", n, if n == 1 { "was" } else { "were" })
+}
+
+// The span to blame when a block evaluates to the wrong type.
+fn tail_span(x: &Expr) -> Span {
+    match &x.kind {
+        ExprKind::Block(_, tail) => tail.as_ref().as_ref().map_or(x.span, |x| x.span),
+        _ => x.span,
+    }
+}
+
+// Whether a `break` leaves the loop with body `x`.
+struct Breaks(bool);
+
+impl Visitor<'_> for Breaks {
+    fn visit_expr(&mut self, x: &Expr) {
+        match x.kind {
+            ExprKind::Break => self.0 = true,
+            ExprKind::While(..) | ExprKind::For(..) | ExprKind::Lambda(..) => {}
+            _ => visit::walk_expr(self, x),
+        }
+    }
+}
+
+// Whether control never reaches the end of `x`. This agrees with the checker giving `x` the
+// type `!`.
+pub(crate) fn diverges(x: &Expr) -> bool {
+    match &x.kind {
+        ExprKind::Return(_) | ExprKind::Break | ExprKind::Continue => true,
+        ExprKind::Block(stmts, tail) => stmts.iter().chain(tail.as_ref()).any(diverges),
+        ExprKind::If(first, elifs, els) => match &**els {
+            Some(els) => std::iter::once(&**first)
+                .chain(elifs)
+                .map(|x| &x.1)
+                .chain(std::iter::once(els))
+                .all(diverges),
+            None => false,
+        },
+        ExprKind::Match(_, arms) => arms.iter().all(|x| diverges(&x.1)),
+        ExprKind::While(cond, body) => {
+            let mut breaks = Breaks(false);
+            breaks.visit_expr(body);
+            cond.kind == ExprKind::BoolLiteral(true) && !breaks.0
+        }
+        _ => false,
+    }
+}
+
+// Where control can reach the end of a body without producing a value: the closing brace of a
+// block that ends in a statement, an `if` without `else` or a loop, looking into the branches
+// of a trailing `if` or `match`.
+fn fallthrough(x: &Expr) -> Option<Span> {
+    let branches = |x: &Expr| match &x.kind {
+        ExprKind::If(first, elifs, els) => Some(
+            std::iter::once(&**first)
+                .chain(elifs)
+                .find_map(|x| fallthrough(&x.1))
+                .or_else(|| match &**els {
+                    Some(els) => fallthrough(els),
+                    None => Some(x.span),
+                }),
+        ),
+        ExprKind::Match(_, arms) => Some(arms.iter().find_map(|x| fallthrough(&x.1))),
+        ExprKind::Block(..) => Some(fallthrough(x)),
+        _ => None,
+    };
+    match &x.kind {
+        ExprKind::Block(stmts, tail) => match &**tail {
+            Some(tail) => fallthrough(tail),
+            None if diverges(x) => None,
+            None => {
+                let end = Some(Span::new(x.span.end() - 1, 1));
+                stmts.last().and_then(branches).flatten().or(end)
+            }
+        },
+        _ if diverges(x) => None,
+        ExprKind::If(..) | ExprKind::Match(..) => branches(x).flatten(),
+        ExprKind::While(..) | ExprKind::For(..) => Some(x.span),
+        _ => None,
+    }
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, code: Code, message: String, span: Span) {
+        self.diagnostics
+            .push(Diagnostic::new(message, span).with_code(code));
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn scoped<T>(&mut self, f: impl FnOnce(&mut Checker<'a>) -> T) -> T {
+        self.frame().scopes.push(Vec::new());
+        let res = f(self);
+        self.frame().scopes.pop();
+        res
+    }
+
+    fn declare(&mut self, name: &str, t: Ty) {
+        let frame = self.frame();
+        let i = frame.locals.len();
+        frame.locals.push(t);
+        frame.scopes.last_mut().unwrap().push((name.to_string(), i));
+    }
+
+    fn in_frame(&mut self, frame: Frame, f: impl FnOnce(&mut Checker<'a>)) -> Vec<Ty> {
+        self.frames.push(frame);
+        f(self);
+        self.frames.pop().unwrap().locals
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.vars.push(None);
+        Ty::Var(self.vars.len() - 1)
+    }
+
+    fn fresh_args(&mut self, params: &[TypeParam]) -> Vec<Ty> {
+        params.iter().map(|_| self.fresh()).collect()
+    }
+
+    fn shallow(&self, x: &Ty) -> Ty {
+        match x {
+            Ty::Var(i) => match &self.vars[*i] {
+                Some(x) => self.shallow(x),
+                None => x.clone(),
+            },
+            x => x.clone(),
+        }
+    }
+
+    // Substitutes the bound variables, and `Unknown` for the rest.
+    fn zonk(&self, x: &Ty) -> Ty {
+        match self.shallow(x) {
+            Ty::Var(_) => Ty::Unknown,
+            Ty::Array(x) => Ty::Array(Box::new(self.zonk(&x))),
+            Ty::Struct(name, xs) => Ty::Struct(name, xs.iter().map(|x| self.zonk(x)).collect()),
+            Ty::Enum(name, xs) => Ty::Enum(name, xs.iter().map(|x| self.zonk(x)).collect()),
+            Ty::Func(xs, ret) => Ty::Func(
+                xs.iter().map(|x| self.zonk(x)).collect(),
+                Box::new(self.zonk(&ret)),
+            ),
+            x => x,
+        }
+    }
+
+    fn unify(&mut self, x: &Ty, y: &Ty) -> bool {
+        match (self.shallow(x), self.shallow(y)) {
+            (Ty::Unknown, _) | (_, Ty::Unknown) | (Ty::Never, _) | (_, Ty::Never) => true,
+            (Ty::Var(i), Ty::Var(j)) if i == j => true,
+            (Ty::Var(i), t) | (t, Ty::Var(i)) => {
+                self.vars[i] = Some(t);
+                true
+            }
+            (Ty::Array(x), Ty::Array(y)) => self.unify(&x, &y),
+            (Ty::Struct(a, xs), Ty::Struct(b, ys)) | (Ty::Enum(a, xs), Ty::Enum(b, ys)) => {
+                a == b && self.unify_all(&xs, &ys)
+            }
+            (Ty::Func(xs, x), Ty::Func(ys, y)) => self.unify_all(&xs, &ys) && self.unify(&x, &y),
+            (x, y) => x == y,
+        }
+    }
+
+    fn unify_all(&mut self, xs: &[Ty], ys: &[Ty]) -> bool {
+        xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| self.unify(x, y))
+    }
+
+    fn expect(&mut self, expected: &Ty, found: &Ty, span: Span) {
+        if !self.unify(expected, found) {
+            let message = format!(
+                "mismatched types: expected `{}`, found `{}`",
+                self.zonk(expected),
+                self.zonk(found)
+            );
+            self.error(Code::Mismatch, message, span);
+        }
+    }
+
+    // The type of an `if` or `match` whose branches evaluate to `x` and `y`.
+    fn join(&mut self, x: Ty, y: Ty, span: Span) -> Ty {
+        match self.shallow(&x) {
+            Ty::Never => y,
+            _ => {
+                self.expect(&x, &y, span);
+                x
+            }
+        }
+    }
+
+    // Reports named types applied to the wrong number of arguments.
+    // Whether a value of type `t` contains `start` by value, pushing the structs in between on
+    // `path`. Arrays, functions and enums are references, so recursion through them is fine.
+    fn contains(&self, start: &str, t: &Type, path: &mut Vec<&'a str>) -> bool {
+        let (name, args) = match t {
+            Type::RefType(RefType::Struct(name, args)) => (name, args),
+            _ => return false,
+        };
+        if name == start {
+            return true;
+        }
+        let (&name, &(params, fields)) = match self.structs.get_key_value(name.as_str()) {
+            Some(x) => x,
+            None => return false,
+        };
+        if path.contains(&name) {
+            return false;
+        }
+        path.push(name);
+        let found = fields
+            .iter()
+            .any(|(_, t)| self.contains(start, &t.substitute(params, args), path));
+        if !found {
+            path.pop();
+        }
+        found
+    }
+
+    // A struct that contains itself by value has no finite layout. Each cycle is reported
+    // once, at the struct of it that comes first.
+    fn cycles(&mut self, module: &'a Module) {
+        let spans = module
+            .iter()
+            .filter_map(|x| match &x.kind {
+                MemberKind::Struct(name, _, _) => Some((name.as_str(), x.span)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+        let mut reported = Vec::new();
+        for member in module {
+            let (name, fields) = match &member.kind {
+                MemberKind::Struct(name, _, fields) if !reported.contains(&name.as_str()) => {
+                    (name.as_str(), fields)
+                }
+                _ => continue,
+            };
+            let mut path = vec![name];
+            if !fields
+                .iter()
+                .any(|(_, t)| self.contains(name, t, &mut path))
+            {
+                continue;
+            }
+            let mut diagnostic = Diagnostic::new(
+                format!("recursive type `{}` has infinite size", name),
+                member.span,
+            )
+            .with_code(Code::RecursiveType);
+            for (i, x) in path.iter().enumerate() {
+                let next = path[(i + 1) % path.len()];
+                diagnostic = diagnostic
+                    .with_label(format!("`{}` contains `{}` by value", x, next), spans[x]);
+            }
+            self.diagnostics.push(diagnostic.with_note(
+                "wrap one of the fields in an array or an enum to break the cycle".to_string(),
+            ));
+            reported.extend(path);
+        }
+    }
+
+    fn check_type(&mut self, x: &Type, span: Span) {
+        let (kind, name, xs, expected) = match x {
+            Type::RefType(RefType::Array(x)) => return self.check_type(x, span),
+            Type::RefType(RefType::Func(xs, ret)) => {
+                for x in xs.iter().chain(ret.as_ref()) {
+                    self.check_type(x, span);
+                }
+                return;
+            }
+            Type::RefType(RefType::Struct(name, xs)) => match self.structs.get(name.as_str()) {
+                Some((params, _)) => ("struct", name, xs, params.len()),
+                None => return,
+            },
+            Type::RefType(RefType::Enum(name, xs)) => match self.enums.get(name.as_str()) {
+                Some((params, _)) => ("enum", name, xs, params.len()),
+                None => return,
+            },
+            _ => return,
+        };
+        if xs.len() != expected {
+            self.error(
+                Code::TypeArgumentCount,
+                format!(
+                    "{} `{}` takes {} but {} supplied",
+                    kind,
+                    name,
+                    plural(expected, "type argument"),
+                    supplied(xs.len())
+                ),
+                span,
+            );
+        }
+        for x in xs {
+            self.check_type(x, span);
+        }
+    }
+
+    fn func_def(&mut self, FuncDef(_, _, params, ret): &FuncDef, span: Span) -> Vec<(Ident, Ty)> {
+        for t in params.iter().map(|x| &x.1).chain(ret) {
+            self.check_type(t, span);
+        }
+        params
+            .iter()
+            .map(|(name, t)| (name.clone(), Ty::from_type(t)))
+            .collect()
+    }
+
+    fn member(&mut self, x: &Member) {
+        match &x.kind {
+            MemberKind::Struct(_, _, fields) => {
+                for (_, t) in fields {
+                    self.check_type(t, x.span);
+                }
+            }
+            MemberKind::Enum(_, _, variants) => {
+                for t in variants.iter().flat_map(|x| &x.1) {
+                    self.check_type(t, x.span);
+                }
+            }
+            MemberKind::Func(def, body) => {
+                let params = self.func_def(def, x.span);
+                let ret = Ty::from_ret(def.3.as_ref());
+                let what = format!("function `{}`", def.0);
+                let locals = self.in_frame(Frame::new(params, ret.clone()), |c| {
+                    c.body(&what, &ret, body)
+                });
+                self.types.locals.push(locals);
+            }
+            MemberKind::ExternFun(def, _, _) => {
+                let params = self.func_def(def, x.span);
+                self.types
+                    .locals
+                    .push(params.into_iter().map(|x| x.1).collect());
+            }
+            MemberKind::Global(_, _, t, init) => {
+                self.check_type(t, x.span);
+                let t = Ty::from_type(t);
+                self.in_frame(Frame::new(Vec::new(), Ty::Unknown), |c| {
+                    let u = c.expr(init);
+                    c.expect(&t, &u, init.span);
+                });
+            }
+            MemberKind::Import(_) | MemberKind::Error => {}
+        }
+    }
+
+    // Checks the body of a function or lambda against its return type. Branches of a trailing
+    // `if` or `match` that disagree with the others have been reported by `join` already.
+    fn body(&mut self, what: &str, ret: &Ty, body: &Expr) {
+        let t = self.expr(body);
+        let missing = t == Ty::Unit && !matches!(self.shallow(ret), Ty::Unit | Ty::Unknown);
+        match fallthrough(body) {
+            Some(span) if missing => self.error(
+                Code::MissingReturn,
+                format!("{} does not return a value on all paths", what),
+                span,
+            ),
+            _ => self.expect(ret, &t, tail_span(body)),
+        }
+    }
+
+    fn unary(&mut self, op: &str, x: &Expr, ok: fn(&Ty) -> bool) -> Ty {
+        let t = self.expr(x);
+        match self.shallow(&t) {
+            Ty::Unknown | Ty::Never | Ty::Var(_) => t,
+            t if ok(&t) => t,
+            t => {
+                self.error(
+                    Code::UnaryOperator,
+                    format!("cannot apply unary operator `{}` to type `{}`", op, t),
+                    x.span,
+                );
+                Ty::Unknown
+            }
+        }
+    }
+
+    fn arith(&mut self, op: &str, x: &Expr, y: &Expr, span: Span, ok: fn(&Ty) -> bool) -> Ty {
+        let a = self.expr(x);
+        let b = self.expr(y);
+        self.operands(op, &a, &b, y, span, ok)
+    }
+
+    // Both operands must have the same type, accepted by `ok`. Returns that type.
+    fn operands(
+        &mut self,
+        op: &str,
+        a: &Ty,
+        b: &Ty,
+        y: &Expr,
+        span: Span,
+        ok: fn(&Ty) -> bool,
+    ) -> Ty {
+        self.expect(a, b, y.span);
+        let t = match self.shallow(a) {
+            Ty::Unknown | Ty::Never => self.shallow(b),
+            t => t,
+        };
+        match t {
+            Ty::Unknown | Ty::Never | Ty::Var(_) => Ty::Unknown,
+            t if ok(&t) => t,
+            t => {
+                self.error(
+                    Code::BinaryOperator,
+                    format!(
+                        "binary operation `{}` cannot be applied to type `{}`",
+                        op, t
+                    ),
+                    span,
+                );
+                Ty::Unknown
+            }
+        }
+    }
+
+    fn compare(&mut self, op: &str, x: &Expr, y: &Expr, span: Span, ok: fn(&Ty) -> bool) -> Ty {
+        self.arith(op, x, y, span, ok);
+        Ty::Bool
+    }
+
+    // Every declared field must be initialized exactly once.
+    fn struct_literal(&mut self, name: &str, fields: &[(Ident, Expr)], span: Span) -> Ty {
+        let decl = self.structs.get(name).copied();
+        let args = decl.map_or(Vec::new(), |(params, _)| self.fresh_args(params));
+        let mut seen: Vec<(&str, Span)> = Vec::new();
+        for (field, x) in fields {
+            let t = self.expr(x);
+            let (params, decls) = match decl {
+                Some(decl) => decl,
+                None => continue,
+            };
+            if let Some(&(_, first)) = seen.iter().find(|(f, _)| f == field) {
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        format!("field `{}` specified more than once", field),
+                        x.span,
+                    )
+                    .with_code(Code::RepeatedStructField)
+                    .with_label(format!("first use of `{}`", field), first),
+                );
+                continue;
+            }
+            seen.push((field, x.span));
+            match decls.iter().find(|x| &x.0 == field) {
+                Some((_, u)) => {
+                    self.expect(&Ty::from_type(u).substitute(params, &args), &t, x.span)
+                }
+                None => self.error(
+                    Code::UnknownStructField,
+                    format!("struct `{}` has no field named `{}`", name, field),
+                    x.span,
+                ),
+            }
+        }
+        match decl {
+            Some((_, decls)) => {
+                for (field, _) in decls {
+                    if !seen.iter().any(|(f, _)| f == field) {
+                        self.error(
+                            Code::MissingStructField,
+                            format!("missing field `{}` in initializer of `{}`", field, name),
+                            span,
+                        );
+                    }
+                }
+                Ty::Struct(name.to_string(), args)
+            }
+            None => Ty::Unknown,
+        }
+    }
+
+    fn func(&mut self, i: usize) -> Ty {
+        let FuncDef(_, ty_params, params, ret) = self.funcs[i];
+        let args = self.fresh_args(ty_params);
+        Ty::Func(
+            params
+                .iter()
+                .map(|(_, t)| Ty::from_type(t).substitute(ty_params, &args))
+                .collect(),
+            Box::new(Ty::from_ret(ret.as_ref()).substitute(ty_params, &args)),
+        )
+    }
+
+    // `Enum.Variant`: the enum itself, or a constructor function if the variant has fields.
+    fn variant(&mut self, enum_: &str, variant: &str) -> Ty {
+        let (params, variants) = match self.enums.get(enum_) {
+            Some(x) => *x,
+            None => return Ty::Unknown,
+        };
+        let fields = match variants.iter().find(|x| x.0 == variant) {
+            Some(x) => &x.1,
+            None => return Ty::Unknown,
+        };
+        let args = self.fresh_args(params);
+        let t = Ty::Enum(enum_.to_string(), args.clone());
+        if fields.is_empty() {
+            t
+        } else {
+            Ty::Func(
+                fields
+                    .iter()
+                    .map(|x| Ty::from_type(x).substitute(params, &args))
+                    .collect(),
+                Box::new(t),
+            )
+        }
+    }
+
+    fn field(&mut self, t: &Ty, name: &str, span: Span) -> Ty {
+        let fields = match self.shallow(t) {
+            Ty::Unknown | Ty::Never | Ty::Var(_) => return Ty::Unknown,
+            Ty::Struct(s, args) => match self.structs.get(s.as_str()) {
+                Some((params, fields)) => fields
+                    .iter()
+                    .find(|x| x.0 == name)
+                    .map(|x| Ty::from_type(&x.1).substitute(params, &args)),
+                None => return Ty::Unknown,
+            },
+            _ => None,
+        };
+        fields.unwrap_or_else(|| {
+            let message = format!("no field `{}` on type `{}`", name, self.zonk(t));
+            self.error(Code::NoField, message, span);
+            Ty::Unknown
+        })
+    }
+
+    // The type of an assignment target.
+    fn place(&mut self, x: &Expr) -> Ty {
+        match &x.kind {
+            ExprKind::Resolved(name, Resolution::Global(i)) => {
+                if self.globals[*i].1 != Mutability::Mutable {
+                    self.error(
+                        Code::ImmutableAssign,
+                        format!("cannot assign to immutable global `{}`", name),
+                        x.span,
+                    );
+                }
+                self.expr(x)
+            }
+            ExprKind::Resolved(_, Resolution::Local(_))
+            | ExprKind::Index(..)
+            | ExprKind::Var(_)
+            | ExprKind::Error => self.expr(x),
+            ExprKind::Member(base, _)
+                if !matches!(base.kind, ExprKind::Resolved(_, Resolution::Enum(_))) =>
+            {
+                self.expr(x)
+            }
+            _ => {
+                self.expr(x);
+                self.error(
+                    Code::InvalidAssign,
+                    "invalid left-hand side of assignment".to_string(),
+                    x.span,
+                );
+                Ty::Unknown
+            }
+        }
+    }
+
+    fn pattern(&mut self, p: &Pattern, t: &Ty, span: Span) {
+        match p {
+            Pattern::Wildcard => {}
+            Pattern::Binding(name) => self.declare(name, t.clone()),
+            Pattern::Literal(x) => {
+                let u = self.expr(x);
+                self.expect(t, &u, x.span);
+            }
+            Pattern::Variant(enum_, variant, ps) => {
+                let fields = match self.enums.get(enum_.as_str()) {
+                    Some((params, variants)) => {
+                        let (params, variants) = (*params, *variants);
+                        let args = self.fresh_args(params);
+                        self.expect(t, &Ty::Enum(enum_.clone(), args.clone()), span);
+                        variants.iter().find(|x| &x.0 == variant).map(|x| {
+                            x.1.iter()
+                                .map(|x| Ty::from_type(x).substitute(params, &args))
+                                .collect::<Vec<_>>()
+                        })
+                    }
+                    None => None,
+                };
+                if let Some(fields) = &fields {
+                    if fields.len() != ps.len() {
+                        self.error(
+                            Code::PatternArity,
+                            format!(
+                                "this pattern has {}, but the variant `{}.{}` has {}",
+                                plural(ps.len(), "field"),
+                                enum_,
+                                variant,
+                                plural(fields.len(), "field")
+                            ),
+                            span,
+                        );
+                    }
+                }
+                // Sub-patterns past the end still bind locals, which must be counted.
+                for (i, p) in ps.iter().enumerate() {
+                    let t = fields
+                        .as_ref()
+                        .and_then(|x| x.get(i).cloned())
+                        .unwrap_or(Ty::Unknown);
+                    self.pattern(p, &t, span);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, x: &Expr) -> Ty {
+        let span = x.span;
+        match &x.kind {
+            ExprKind::I32Literal(_) => Ty::I32,
+            ExprKind::I64Literal(_) => Ty::I64,
+            ExprKind::F32Literal(_) => Ty::F32,
+            ExprKind::F64Literal(_) => Ty::F64,
+            ExprKind::BoolLiteral(_) => Ty::Bool,
+            ExprKind::CharLiteral(_) => Ty::Char,
+            ExprKind::StringLiteral(_) => Ty::String,
+            ExprKind::ArrayLiteral(t, len) => {
+                self.check_type(t, span);
+                let u = self.expr(len);
+                self.expect(&Ty::I32, &u, len.span);
+                Ty::Array(Box::new(Ty::from_type(t)))
+            }
+            ExprKind::StructLiteral(name, fields) => self.struct_literal(name, fields, span),
+            ExprKind::Var(_) | ExprKind::Error => Ty::Unknown,
+            ExprKind::Resolved(_, Resolution::Local(i)) => self.frame().locals[*i].clone(),
+            ExprKind::Resolved(_, Resolution::Global(i)) => self.globals[*i].2.clone(),
+            ExprKind::Resolved(_, Resolution::Func(i)) => self.func(*i),
+            ExprKind::Resolved(name, Resolution::Enum(_)) => {
+                self.error(
+                    Code::EnumAsValue,
+                    format!("expected value, found enum `{}`", name),
+                    span,
+                );
+                Ty::Unknown
+            }
+            ExprKind::Not(x) => self.unary("!", x, |t| t.is_integer() || *t == Ty::Bool),
+            ExprKind::Plus(x) => self.unary("+", x, Ty::is_numeric),
+            ExprKind::Minus(x) => self.unary("-", x, Ty::is_numeric),
+            ExprKind::Cast(x, t) => {
+                self.check_type(t, span);
+                let to = Ty::from_type(t);
+                let from = self.expr(x);
+                match self.shallow(&from) {
+                    Ty::Unknown | Ty::Never | Ty::Var(_) => {}
+                    from if from.can_cast(&to) => {}
+                    // Casting a compound value to its own type is allowed, so unify.
+                    from if !from.is_scalar() && !to.is_scalar() => self.expect(&to, &from, x.span),
+                    from => self.error(
+                        Code::InvalidCast,
+                        format!("cannot cast `{}` as `{}`", from, to),
+                        span,
+                    ),
+                }
+                to
+            }
+            ExprKind::Member(base, name) => match &base.kind {
+                ExprKind::Resolved(enum_, Resolution::Enum(_)) => self.variant(enum_, name),
+                _ => {
+                    let t = self.expr(base);
+                    self.field(&t, name, span)
+                }
+            },
+            ExprKind::Index(x, i) => {
+                let t = self.expr(x);
+                let u = self.expr(i);
+                self.expect(&Ty::I32, &u, i.span);
+                match self.shallow(&t) {
+                    Ty::Array(t) => *t,
+                    Ty::Unknown | Ty::Never | Ty::Var(_) => Ty::Unknown,
+                    t => {
+                        self.error(
+                            Code::NotIndexable,
+                            format!("cannot index into a value of type `{}`", t),
+                            x.span,
+                        );
+                        Ty::Unknown
+                    }
+                }
+            }
+            ExprKind::Call(f, xs) => {
+                let t = self.expr(f);
+                let args = xs.iter().map(|x| self.expr(x)).collect::<Vec<_>>();
+                match self.shallow(&t) {
+                    Ty::Func(params, ret) => {
+                        if params.len() != args.len() {
+                            self.error(
+                                Code::ArgumentCount,
+                                format!(
+                                    "this function takes {} but {} supplied",
+                                    plural(params.len(), "argument"),
+                                    supplied(args.len())
+                                ),
+                                span,
+                            );
+                        }
+                        for ((p, t), x) in params.iter().zip(&args).zip(xs) {
+                            self.expect(p, t, x.span);
+                        }
+                        *ret
+                    }
+                    Ty::Unknown | Ty::Never | Ty::Var(_) => Ty::Unknown,
+                    t => {
+                        self.error(
+                            Code::NotAFunction,
+                            format!("expected function, found `{}`", t),
+                            f.span,
+                        );
+                        Ty::Unknown
+                    }
+                }
+            }
+            ExprKind::Add(x, y) => self.arith("+", x, y, span, Ty::is_numeric),
+            ExprKind::Sub(x, y) => self.arith("-", x, y, span, Ty::is_numeric),
+            ExprKind::Mul(x, y) => self.arith("*", x, y, span, Ty::is_numeric),
+            ExprKind::Div(x, y) => self.arith("/", x, y, span, Ty::is_numeric),
+            ExprKind::Mod(x, y) => self.arith("%", x, y, span, Ty::is_integer),
+            ExprKind::Pow(x, y) => self.arith("**", x, y, span, Ty::is_numeric),
+            ExprKind::And(x, y) => self.arith("&&", x, y, span, |t| *t == Ty::Bool),
+            ExprKind::Or(x, y) => self.arith("||", x, y, span, |t| *t == Ty::Bool),
+            ExprKind::BitAnd(x, y) => {
+                self.arith("&", x, y, span, |t| t.is_integer() || *t == Ty::Bool)
+            }
+            ExprKind::BitOr(x, y) => {
+                self.arith("|", x, y, span, |t| t.is_integer() || *t == Ty::Bool)
+            }
+            ExprKind::BitXor(x, y) => {
+                self.arith("^", x, y, span, |t| t.is_integer() || *t == Ty::Bool)
+            }
+            ExprKind::Eq(x, y) => self.compare("==", x, y, span, |t| {
+                t.is_numeric() || matches!(t, Ty::Bool | Ty::Char | Ty::String)
+            }),
+            ExprKind::Ne(x, y) => self.compare("!=", x, y, span, |t| {
+                t.is_numeric() || matches!(t, Ty::Bool | Ty::Char | Ty::String)
+            }),
+            ExprKind::Lt(x, y) => {
+                self.compare("<", x, y, span, |t| t.is_numeric() || *t == Ty::Char)
+            }
+            ExprKind::Lte(x, y) => {
+                self.compare("<=", x, y, span, |t| t.is_numeric() || *t == Ty::Char)
+            }
+            ExprKind::Gt(x, y) => {
+                self.compare(">", x, y, span, |t| t.is_numeric() || *t == Ty::Char)
+            }
+            ExprKind::Gte(x, y) => {
+                self.compare(">=", x, y, span, |t| t.is_numeric() || *t == Ty::Char)
+            }
+            ExprKind::Range(x, y) | ExprKind::RangeInclusive(x, y) => {
+                self.arith("..", x, y, span, Ty::is_integer);
+                self.error(
+                    Code::RangeAsValue,
+                    "ranges cannot be used as values".to_string(),
+                    span,
+                );
+                Ty::Unknown
+            }
+            ExprKind::Block(stmts, tail) => self.scoped(|c| {
+                // Statements after one that diverges are unreachable, but still checked.
+                let mut t = Ty::Unit;
+                for x in stmts {
+                    if c.expr(x) == Ty::Never {
+                        t = Ty::Never;
+                    }
+                }
+                match &**tail {
+                    Some(x) => c.expr(x),
+                    None => t,
+                }
+            }),
+            ExprKind::Let(name, _, t, init) => {
+                if let Some(t) = t {
+                    self.check_type(t, span);
+                }
+                let u = self.expr(init);
+                let t = match t {
+                    Some(t) => {
+                        let t = Ty::from_type(t);
+                        self.expect(&t, &u, init.span);
+                        t
+                    }
+                    None => u,
+                };
+                self.declare(name, t);
+                Ty::Unit
+            }
+            ExprKind::If(first, elifs, els) => {
+                let mut res = Ty::Never;
+                for (cond, body) in std::iter::once(&**first).chain(elifs) {
+                    let t = self.expr(cond);
+                    self.expect(&Ty::Bool, &t, cond.span);
+                    let t = self.expr(body);
+                    res = self.join(res, t, tail_span(body));
+                }
+                match &**els {
+                    Some(x) => {
+                        let t = self.expr(x);
+                        self.join(res, t, tail_span(x))
+                    }
+                    None => Ty::Unit,
+                }
+            }
+            ExprKind::While(cond, body) => {
+                let t = self.expr(cond);
+                self.expect(&Ty::Bool, &t, cond.span);
+                self.expr(body);
+                if diverges(x) {
+                    Ty::Never
+                } else {
+                    Ty::Unit
+                }
+            }
+            ExprKind::Return(x) => {
+                let t = match &**x {
+                    Some(x) => self.expr(x),
+                    None => Ty::Unit,
+                };
+                let ret = self.frame().ret.clone();
+                let span = x.as_ref().as_ref().map_or(span, |x| x.span);
+                self.expect(&ret, &t, span);
+                Ty::Never
+            }
+            ExprKind::Set(x, y) => {
+                let t = self.place(x);
+                let u = self.expr(y);
+                self.expect(&t, &u, y.span);
+                Ty::Unit
+            }
+            ExprKind::CompoundSet(op, x, y) => {
+                let t = self.place(x);
+                let u = self.expr(y);
+                let ok = match op {
+                    AssignOp::Mod => Ty::is_integer,
+                    _ => Ty::is_numeric,
+                };
+                self.operands(op.as_str(), &t, &u, y, span, ok);
+                Ty::Unit
+            }
+            ExprKind::For(init, cond, step, body) => self.scoped(|c| {
+                c.expr(init);
+                let t = c.expr(cond);
+                c.expect(&Ty::Bool, &t, cond.span);
+                c.expr(step);
+                c.expr(body);
+                Ty::Unit
+            }),
+            ExprKind::Lambda(captures, params, ret, body) => {
+                for (_, t) in params {
+                    self.check_type(t, span);
+                }
+                self.check_type(ret, span);
+                let frame = self.frames.last().unwrap();
+                let mut locals = captures
+                    .iter()
+                    .map(|name| {
+                        let t = frame
+                            .scopes
+                            .iter()
+                            .rev()
+                            .flat_map(|x| x.iter().rev())
+                            .find(|x| &x.0 == name)
+                            .map_or(Ty::Unknown, |x| frame.locals[x.1].clone());
+                        (name.clone(), t)
+                    })
+                    .collect::<Vec<_>>();
+                let params = params
+                    .iter()
+                    .map(|(name, t)| (name.clone(), Ty::from_type(t)))
+                    .collect::<Vec<_>>();
+                locals.extend(params.iter().cloned());
+                let ret = Ty::from_type(ret);
+                let i = self.types.lambdas.len();
+                self.types.lambdas.push(Vec::new());
+                self.types.lambdas[i] = self.in_frame(Frame::new(locals, ret.clone()), |c| {
+                    c.body("lambda", &ret, body)
+                });
+                Ty::Func(params.into_iter().map(|x| x.1).collect(), Box::new(ret))
+            }
+            ExprKind::Match(x, arms) => {
+                let t = self.expr(x);
+                let mut res = Ty::Never;
+                for (p, body) in arms {
+                    let u = self.scoped(|c| {
+                        c.pattern(p, &t, body.span);
+                        c.expr(body)
+                    });
+                    res = self.join(res, u, tail_span(body));
+                }
+                res
+            }
+            ExprKind::Break | ExprKind::Continue => Ty::Never,
+        }
+    }
+}
+
+// Type checks a module after `resolve_module`. Names the resolver could not resolve are not
+// reported again.
+pub fn check_module(module: &Module) -> (Types, Vec<Diagnostic>) {
+    let mut checker = Checker {
+        structs: HashMap::new(),
+        enums: HashMap::new(),
+        funcs: Vec::new(),
+        globals: Vec::new(),
+        vars: Vec::new(),
+        frames: Vec::new(),
+        types: Types::default(),
+        diagnostics: Vec::new(),
+    };
+    for member in module {
+        match &member.kind {
+            MemberKind::Struct(name, params, fields) => {
+                checker.structs.entry(name).or_insert((params, fields));
+            }
+            MemberKind::Enum(name, params, variants) => {
+                checker.enums.entry(name).or_insert((params, variants));
+            }
+            MemberKind::Func(def, _) | MemberKind::ExternFun(def, _, _) => checker.funcs.push(def),
+            MemberKind::Global(name, mutability, t, _) => {
+                checker.globals.push((name, *mutability, Ty::from_type(t)))
+            }
+            MemberKind::Import(_) | MemberKind::Error => {}
+        }
+    }
+    checker.cycles(module);
+    for member in module {
+        checker.member(member);
+    }
+    let zonk = |xs: &[Vec<Ty>]| {
+        xs.iter()
+            .map(|xs| xs.iter().map(|x| checker.zonk(x)).collect())
+            .collect()
+    };
+    let types = Types {
+        locals: zonk(&checker.types.locals),
+        lambdas: zonk(&checker.types.lambdas),
+    };
+    (types, checker.diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+    use crate::resolver::resolve_module;
+
+    fn check(src: &str) -> (Types, Vec<String>) {
+        let (module, diagnostics) = parse_source(src);
+        assert_eq!(diagnostics, vec![]);
+        let (module, _, diagnostics) = resolve_module(module);
+        assert!(diagnostics.iter().all(|x| !x.is_error()));
+        let (types, diagnostics) = check_module(&module);
+        (types, diagnostics.into_iter().map(|x| x.message).collect())
+    }
+
+    #[test]
+    fn typeck_test() {
+        let (types, diagnostics) = check(
+            "struct P<T> { x: T, y: T }
+            enum Option<T> { None, Some(T) }
+            let mut count: i64 = 0i64;
+            fun id<T>(x: T) -> T { x }
+            fun f(p: P<f64>, xs: [i32]) -> i32 {
+                let q = P { x: 1, y: id(2) };
+                let o = Option.None;
+                o = Option.Some(\"s\");
+                count = count + 1i64;
+                let g = |a: i32| [q] -> i32 { a * q.x };
+                if p.x < 1.5 && !false {
+                    return g(xs[0]);
+                }
+                let c = match o { Option.Some(s) => s == \"t\", _ => true };
+                for let i = 0; i < 10; i += 1 { xs[i] = -i; }
+                q.y % 2
+            }",
+        );
+        assert_eq!(diagnostics, Vec::<String>::new());
+        let p = |x| Ty::Struct("P".to_string(), vec![x]);
+        assert_eq!(
+            types.locals[1],
+            vec![
+                p(Ty::F64),
+                Ty::Array(Box::new(Ty::I32)),
+                p(Ty::I32),
+                Ty::Enum("Option".to_string(), vec![Ty::String]),
+                Ty::Func(vec![Ty::I32], Box::new(Ty::I32)),
+                Ty::String,
+                Ty::Bool,
+                Ty::I32,
+            ]
+        );
+        assert_eq!(types.lambdas, vec![vec![p(Ty::I32), Ty::I32]]);
+    }
+
+    #[test]
+    fn return_path_test() {
+        let src = "fun a(c: bool) -> i32 { if c { return 1; } }
+            fun b(c: bool) -> i32 { if c { 1 } else { let x = 2; } }
+            fun c() -> i32 { while true { } }
+            fun d(c: bool) -> i32 { while c { return 1; } }
+            fun e(c: bool) -> i32 { match c { true => 1, _ => { } } }
+            fun f(c: bool) -> i32 { if c { return 1; } else { return 2; } }
+            fun g(c: bool) -> i32 { return c; }
+            fun h() { let l = || -> i32 { 1; }; }";
+        let (module, _) = parse_source(src);
+        let (module, _, _) = resolve_module(module);
+        let (_, diagnostics) = check_module(&module);
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|x| (x.message.as_str(), &src[x.span.pos..x.span.end()]))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "function `a` does not return a value on all paths",
+                    "if c { return 1; }"
+                ),
+                (
+                    "mismatched types: expected `i32`, found `()`",
+                    "{ let x = 2; }"
+                ),
+                (
+                    "function `d` does not return a value on all paths",
+                    "while c { return 1; }"
+                ),
+                ("mismatched types: expected `i32`, found `()`", "{ }"),
+                ("mismatched types: expected `i32`, found `bool`", "c"),
+                ("lambda does not return a value on all paths", "}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn struct_literal_test() {
+        let (_, diagnostics) = check(
+            "struct P<T> { x: T, y: i32, z: bool }
+            fun f() {
+                let p = P { x: 1, y: 2, z: true };
+                let q: P<i64> = P { x: 1i64, y: 2, y: 3, w: 4, z: 5 };
+                P { y: 1 };
+            }",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "field `y` specified more than once",
+                "struct `P` has no field named `w`",
+                "mismatched types: expected `bool`, found `i32`",
+                "missing field `x` in initializer of `P`",
+                "missing field `z` in initializer of `P`",
+            ]
+        );
+    }
+
+    #[test]
+    fn recursive_struct_test() {
+        let src = "struct A { b: B, n: i32 }
+            struct B { a: W<A> }
+            struct W<T> { x: T }
+            struct C { c: C }
+            struct D { a: A, ds: [D], e: E }
+            enum E { Nil, Cons(D, E) }";
+        let (module, _) = parse_source(src);
+        let (module, _, _) = resolve_module(module);
+        let (_, diagnostics) = check_module(&module);
+        let labels = |i: usize| {
+            diagnostics[i]
+                .labels
+                .iter()
+                .map(|x| x.message.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].message,
+            "recursive type `A` has infinite size"
+        );
+        assert_eq!(
+            labels(0),
+            vec![
+                "`A` contains `B` by value",
+                "`B` contains `W` by value",
+                "`W` contains `A` by value",
+            ]
+        );
+        assert_eq!(
+            diagnostics[1].message,
+            "recursive type `C` has infinite size"
+        );
+        assert_eq!(labels(1), vec!["`C` contains `C` by value"]);
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "recursive type `C` has infinite size\n\
+             note: wrap one of the fields in an array or an enum to break the cycle"
+        );
+    }
+
+    #[test]
+    fn type_error_test() {
+        let (_, diagnostics) = check(
+            "struct P { x: i32 }
+            enum E { A(i32, bool) }
+            const N: i32 = true;
+            fun f(p: P<i32>, e: E) -> bool {
+                let x: i64 = 1;
+                if 1 { }
+                N = 2;
+                p.z;
+                p.x + 1.0;
+                \"a\" - \"b\";
+                let s = \"a\";
+                s %= \"b\";
+                x as f32 as char;
+                'a' as i64 + true as i64;
+                p as i32;
+                f(p);
+                1(2);
+                match e { E.A(a) => a, _ => 1 };
+                let y = if true { 1 } else { false };
+                return;
+                1
+            }",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "mismatched types: expected `i32`, found `bool`",
+                "struct `P` takes 0 type arguments but 1 was supplied",
+                "mismatched types: expected `i64`, found `i32`",
+                "mismatched types: expected `bool`, found `i32`",
+                "cannot assign to immutable global `N`",
+                "no field `z` on type `P<i32>`",
+                "mismatched types: expected `i32`, found `f64`",
+                "binary operation `-` cannot be applied to type `string`",
+                "binary operation `%` cannot be applied to type `string`",
+                "cannot cast `f32` as `char`",
+                "cannot cast `P<i32>` as `i32`",
+                "this function takes 2 arguments but 1 was supplied",
+                "expected function, found `i32`",
+                "this pattern has 1 field, but the variant `E.A` has 2 fields",
+                "mismatched types: expected `i32`, found `bool`",
+                "mismatched types: expected `bool`, found `()`",
+                "mismatched types: expected `bool`, found `i32`",
+            ]
+        );
+    }
+}
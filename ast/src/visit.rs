@@ -0,0 +1,257 @@
+use crate::ast::{Expr, ExprKind, FuncDef, Member, MemberKind, Module, Pattern, RefType, Type};
+
+// Read-only traversal. Override the `visit_*` methods of interest and call the matching
+// `walk_*` from them to keep descending into children. Nodes are borrowed for `'ast`, so a
+// visitor can hold on to them.
+pub trait Visitor<'ast>: Sized {
+    fn visit_module(&mut self, x: &'ast Module) {
+        walk_module(self, x)
+    }
+
+    fn visit_member(&mut self, x: &'ast Member) {
+        walk_member(self, x)
+    }
+
+    fn visit_expr(&mut self, x: &'ast Expr) {
+        walk_expr(self, x)
+    }
+
+    fn visit_type(&mut self, x: &'ast Type) {
+        walk_type(self, x)
+    }
+
+    fn visit_pattern(&mut self, x: &'ast Pattern) {
+        walk_pattern(self, x)
+    }
+}
+
+pub fn walk_module<'ast, V: Visitor<'ast>>(v: &mut V, x: &'ast Module) {
+    for member in x {
+        v.visit_member(member);
+    }
+}
+
+fn walk_func_def<'ast, V: Visitor<'ast>>(v: &mut V, FuncDef(_, _, params, ret): &'ast FuncDef) {
+    for (_, t) in params {
+        v.visit_type(t);
+    }
+    if let Some(t) = ret {
+        v.visit_type(t);
+    }
+}
+
+pub fn walk_member<'ast, V: Visitor<'ast>>(v: &mut V, x: &'ast Member) {
+    match &x.kind {
+        MemberKind::Struct(_, _, fields) => {
+            for (_, t) in fields {
+                v.visit_type(t);
+            }
+        }
+        MemberKind::Enum(_, _, variants) => {
+            for t in variants.iter().flat_map(|x| &x.1) {
+                v.visit_type(t);
+            }
+        }
+        MemberKind::Func(def, body) => {
+            walk_func_def(v, def);
+            v.visit_expr(body);
+        }
+        MemberKind::ExternFun(def, _, _) => walk_func_def(v, def),
+        MemberKind::Global(_, _, t, init) => {
+            v.visit_type(t);
+            v.visit_expr(init);
+        }
+        MemberKind::Import(_) | MemberKind::Error => {}
+    }
+}
+
+pub fn walk_expr<'ast, V: Visitor<'ast>>(v: &mut V, x: &'ast Expr) {
+    match &x.kind {
+        ExprKind::I32Literal(_)
+        | ExprKind::I64Literal(_)
+        | ExprKind::F32Literal(_)
+        | ExprKind::F64Literal(_)
+        | ExprKind::StringLiteral(_)
+        | ExprKind::BoolLiteral(_)
+        | ExprKind::CharLiteral(_)
+        | ExprKind::Var(_)
+        | ExprKind::Resolved(..)
+        | ExprKind::Break
+        | ExprKind::Continue
+        | ExprKind::Error => {}
+        ExprKind::StructLiteral(_, fields) => {
+            for (_, x) in fields {
+                v.visit_expr(x);
+            }
+        }
+        ExprKind::ArrayLiteral(t, len) => {
+            v.visit_type(t);
+            v.visit_expr(len);
+        }
+        ExprKind::Not(x) | ExprKind::Plus(x) | ExprKind::Minus(x) | ExprKind::Member(x, _) => {
+            v.visit_expr(x)
+        }
+        ExprKind::Cast(x, t) => {
+            v.visit_expr(x);
+            v.visit_type(t);
+        }
+        ExprKind::Call(f, args) => {
+            v.visit_expr(f);
+            for x in args {
+                v.visit_expr(x);
+            }
+        }
+        ExprKind::Index(x, y)
+        | ExprKind::Add(x, y)
+        | ExprKind::Sub(x, y)
+        | ExprKind::Mul(x, y)
+        | ExprKind::Div(x, y)
+        | ExprKind::Mod(x, y)
+        | ExprKind::And(x, y)
+        | ExprKind::Or(x, y)
+        | ExprKind::BitAnd(x, y)
+        | ExprKind::BitOr(x, y)
+        | ExprKind::BitXor(x, y)
+        | ExprKind::Pow(x, y)
+        | ExprKind::Eq(x, y)
+        | ExprKind::Ne(x, y)
+        | ExprKind::Lt(x, y)
+        | ExprKind::Lte(x, y)
+        | ExprKind::Gt(x, y)
+        | ExprKind::Gte(x, y)
+        | ExprKind::Range(x, y)
+        | ExprKind::RangeInclusive(x, y)
+        | ExprKind::While(x, y)
+        | ExprKind::Set(x, y)
+        | ExprKind::CompoundSet(_, x, y) => {
+            v.visit_expr(x);
+            v.visit_expr(y);
+        }
+        ExprKind::Block(stmts, tail) => {
+            for x in stmts {
+                v.visit_expr(x);
+            }
+            if let Some(x) = &**tail {
+                v.visit_expr(x);
+            }
+        }
+        ExprKind::Let(_, _, t, x) => {
+            if let Some(t) = t {
+                v.visit_type(t);
+            }
+            v.visit_expr(x);
+        }
+        ExprKind::If(first, elifs, els) => {
+            for (cond, body) in std::iter::once(&**first).chain(elifs) {
+                v.visit_expr(cond);
+                v.visit_expr(body);
+            }
+            if let Some(x) = &**els {
+                v.visit_expr(x);
+            }
+        }
+        ExprKind::Return(x) => {
+            if let Some(x) = &**x {
+                v.visit_expr(x);
+            }
+        }
+        ExprKind::For(init, cond, step, body) => {
+            v.visit_expr(init);
+            v.visit_expr(cond);
+            v.visit_expr(step);
+            v.visit_expr(body);
+        }
+        ExprKind::Lambda(_, params, ret, body) => {
+            for (_, t) in params {
+                v.visit_type(t);
+            }
+            v.visit_type(ret);
+            v.visit_expr(body);
+        }
+        ExprKind::Match(x, arms) => {
+            v.visit_expr(x);
+            for (p, x) in arms {
+                v.visit_pattern(p);
+                v.visit_expr(x);
+            }
+        }
+    }
+}
+
+pub fn walk_type<'ast, V: Visitor<'ast>>(v: &mut V, x: &'ast Type) {
+    match x {
+        Type::RefType(RefType::Array(x)) => v.visit_type(x),
+        Type::RefType(RefType::Struct(_, xs)) | Type::RefType(RefType::Enum(_, xs)) => {
+            for x in xs {
+                v.visit_type(x);
+            }
+        }
+        Type::RefType(RefType::Func(xs, ret)) => {
+            for x in xs.iter().chain(&**ret) {
+                v.visit_type(x);
+            }
+        }
+        Type::I32
+        | Type::I64
+        | Type::F32
+        | Type::F64
+        | Type::Bool
+        | Type::Char
+        | Type::Param(_)
+        | Type::RefType(RefType::String) => {}
+    }
+}
+
+pub fn walk_pattern<'ast, V: Visitor<'ast>>(v: &mut V, x: &'ast Pattern) {
+    match x {
+        Pattern::Literal(x) => v.visit_expr(x),
+        Pattern::Variant(_, _, xs) => {
+            for x in xs {
+                v.visit_pattern(x);
+            }
+        }
+        Pattern::Wildcard | Pattern::Binding(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    #[derive(Default)]
+    struct Names(Vec<String>);
+
+    impl<'ast> Visitor<'ast> for Names {
+        fn visit_expr(&mut self, x: &'ast Expr) {
+            if let ExprKind::Var(name) = &x.kind {
+                self.0.push(name.clone());
+            }
+            walk_expr(self, x)
+        }
+
+        fn visit_type(&mut self, x: &'ast Type) {
+            if let Type::RefType(RefType::Struct(name, _)) = x {
+                self.0.push(name.clone());
+            }
+            walk_type(self, x)
+        }
+    }
+
+    #[test]
+    fn visitor_test() {
+        let (module, _) = parse_source(
+            "struct A { b: B }
+            fun f(x: [C]) -> fun(D) {
+                let y: E = x[i];
+                match y { Z.V(1) => g(|a: F| -> G { h }), _ => y }
+            }",
+        );
+        let mut names = Names::default();
+        names.visit_module(&module);
+        assert_eq!(
+            names.0,
+            vec!["B", "C", "D", "E", "x", "i", "y", "g", "F", "G", "h", "y"]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::ast::{FuncDef, MemberKind, Module};
+use crate::parser::parse_source;
+use crate::runtime::i32_immediate;
+use crate::typeck::Ty;
+use wasm::ast::{ExternalKindImport, FunctionBody, ImportEntry, OperatorCode};
+
+// The builtins `print` and `println`, which write a string to standard output. They are
+// declared as extern functions of `MODULE`, so that hosts can provide them, unless codegen is
+// asked for WASI, which defines them on top of `fd_write` for wasmtime, wasmer and the like.
+
+pub const MODULE: &str = "tlang";
+
+const PRELUDE: &str = r#"
+extern fun print(s: string) = "tlang" "print";
+extern fun println(s: string) = "tlang" "println";
+"#;
+
+const STDOUT: i32 = 1;
+
+fn name(kind: &MemberKind) -> Option<&str> {
+    match kind {
+        MemberKind::Func(def, _) | MemberKind::ExternFun(def, _, _) => Some(&def.0),
+        _ => None,
+    }
+}
+
+// Adds the builtins the module does not define itself, after its own members.
+pub fn with_builtins(mut module: Module) -> Module {
+    let (prelude, _) = parse_source(PRELUDE);
+    for member in prelude {
+        if !module.iter().any(|x| name(&x.kind) == name(&member.kind)) {
+            module.push(member);
+        }
+    }
+    module
+}
+
+pub fn is_builtin(def: &FuncDef, module: &str, field: &str) -> bool {
+    module == MODULE
+        && (field == "print" || field == "println")
+        && def.2.len() == 1
+        && Ty::from_type(&def.2[0].1) == Ty::String
+        && Ty::from_ret(def.3.as_ref()) == Ty::Unit
+}
+
+pub fn fd_write(typ: usize) -> ImportEntry {
+    ImportEntry {
+        module: "wasi_snapshot_preview1".to_string(),
+        field: "fd_write".to_string(),
+        kind: ExternalKindImport::Function(typ),
+    }
+}
+
+// What the builtins are made of.
+pub struct Wasi {
+    // The index of `fd_write`, of type `(i32, i32, i32, i32) -> i32`.
+    pub fd_write: usize,
+    // The index of `release`, as the builtins own their argument.
+    pub release: usize,
+    // 12 bytes of static data for the `iovec` and the number of bytes written, and the address
+    // of the string "\n".
+    pub scratch: u32,
+    pub newline: u32,
+}
+
+impl Wasi {
+    // Writes the string whose address `ptr` pushes. Errors are ignored.
+    fn write(&self, ptr: OperatorCode, codes: &mut Vec<OperatorCode>) {
+        let scratch = self.scratch as i32;
+        codes.extend(vec![
+            OperatorCode::I32Const(scratch),
+            ptr.clone(),
+            OperatorCode::I32Const(4),
+            OperatorCode::I32Add,
+            OperatorCode::I32Store(i32_immediate(0)),
+            OperatorCode::I32Const(scratch),
+            ptr,
+            OperatorCode::I32Load(i32_immediate(0)),
+            OperatorCode::I32Store(i32_immediate(4)),
+            OperatorCode::I32Const(STDOUT),
+            OperatorCode::I32Const(scratch),
+            OperatorCode::I32Const(1),
+            OperatorCode::I32Const(scratch + 8),
+            OperatorCode::Call(self.fd_write),
+            OperatorCode::Drop,
+        ]);
+    }
+
+    // The body of the builtin imported as `field`.
+    pub fn body(&self, field: &str) -> FunctionBody {
+        let mut codes = Vec::new();
+        self.write(OperatorCode::GetLocal(0), &mut codes);
+        if field == "println" {
+            self.write(OperatorCode::I32Const(self.newline as i32), &mut codes);
+        }
+        codes.push(OperatorCode::GetLocal(0));
+        codes.push(OperatorCode::Call(self.release));
+        FunctionBody {
+            locals: Vec::new(),
+            codes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::{codegen_module, Options};
+    use crate::desugar::desugar_module;
+    use crate::resolver::resolve_module;
+    use crate::typeck::check_module;
+    use wasm::ast::WasmASTRoot;
+
+    fn compile(src: &str, options: &Options) -> WasmASTRoot {
+        let (module, _) = parse_source(src);
+        let module = with_builtins(module);
+        let (module, _, _) = resolve_module(module);
+        let (types, diagnostics) = check_module(&module);
+        assert_eq!(diagnostics, vec![]);
+        let (root, _, diagnostics) = codegen_module(&desugar_module(module), &types, options);
+        assert_eq!(diagnostics, vec![]);
+        root
+    }
+
+    #[test]
+    fn builtins_test() {
+        let src = "fun print(x: string) {} fun main() { print(\"a\"); println(\"b\"); }";
+        let root = compile(src, &Options::default());
+        let imports = root.import_section.unwrap().0;
+        assert_eq!(
+            imports
+                .iter()
+                .map(|x| (x.module.as_str(), x.field.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("tlang", "println")]
+        );
+
+        let options = Options {
+            wasi: true,
+            ..Options::default()
+        };
+        let root = compile(src, &options);
+        let imports = root.import_section.unwrap().0;
+        assert_eq!(
+            imports
+                .iter()
+                .map(|x| (x.module.as_str(), x.field.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("wasi_snapshot_preview1", "fd_write")]
+        );
+        // `print`, `main`, the runtime and then `println`, which writes twice.
+        let bodies = root.code_section.unwrap().0;
+        assert_eq!(bodies.len(), 6);
+        assert_eq!(
+            bodies[5]
+                .codes
+                .iter()
+                .filter(|x| **x == OperatorCode::Call(0))
+                .count(),
+            2
+        );
+        assert!(bodies[1].codes.contains(&OperatorCode::Call(6)));
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+
+// Stable identifiers for every kind of diagnostic, so that tools can match on them instead of
+// on the wording of messages. `E` codes are errors and `W` codes warnings; the hundreds digit
+// is the phase that reports them (0 syntax, 1 name resolution, 2 type checking, 3 code
+// generation). A code is never reused for something else once it has been published.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Code {
+    Io,
+    UnexpectedCharacter,
+    Syntax,
+
+    UnresolvedValue,
+    UseBeforeDeclaration,
+    UnresolvedStruct,
+    UnresolvedType,
+    UnresolvedEnum,
+    UnresolvedVariant,
+    UnresolvedCapture,
+    MissingCapture,
+    DuplicateDefinition,
+    DuplicateField,
+    DuplicateParameter,
+
+    Mismatch,
+    TypeArgumentCount,
+    NoField,
+    ImmutableAssign,
+    InvalidAssign,
+    BinaryOperator,
+    UnaryOperator,
+    ArgumentCount,
+    NotAFunction,
+    NotIndexable,
+    PatternArity,
+    EnumAsValue,
+    RangeAsValue,
+    MissingReturn,
+    InvalidCast,
+    RecursiveType,
+    RepeatedStructField,
+    UnknownStructField,
+    MissingStructField,
+
+    InvalidCall,
+    InvalidEntry,
+
+    UnusedVariable,
+    UnusedFunction,
+}
+
+impl Code {
+    pub const ALL: &'static [Code] = &[
+        Code::Io,
+        Code::UnexpectedCharacter,
+        Code::Syntax,
+        Code::UnresolvedValue,
+        Code::UseBeforeDeclaration,
+        Code::UnresolvedStruct,
+        Code::UnresolvedType,
+        Code::UnresolvedEnum,
+        Code::UnresolvedVariant,
+        Code::UnresolvedCapture,
+        Code::MissingCapture,
+        Code::DuplicateDefinition,
+        Code::DuplicateField,
+        Code::DuplicateParameter,
+        Code::Mismatch,
+        Code::TypeArgumentCount,
+        Code::NoField,
+        Code::ImmutableAssign,
+        Code::InvalidAssign,
+        Code::BinaryOperator,
+        Code::UnaryOperator,
+        Code::ArgumentCount,
+        Code::NotAFunction,
+        Code::NotIndexable,
+        Code::PatternArity,
+        Code::EnumAsValue,
+        Code::RangeAsValue,
+        Code::MissingReturn,
+        Code::InvalidCast,
+        Code::RecursiveType,
+        Code::RepeatedStructField,
+        Code::UnknownStructField,
+        Code::MissingStructField,
+        Code::InvalidCall,
+        Code::InvalidEntry,
+        Code::UnusedVariable,
+        Code::UnusedFunction,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::Io => "E0001",
+            Code::UnexpectedCharacter => "E0002",
+            Code::Syntax => "E0003",
+
+            Code::UnresolvedValue => "E0101",
+            Code::UseBeforeDeclaration => "E0102",
+            Code::UnresolvedStruct => "E0103",
+            Code::UnresolvedType => "E0104",
+            Code::UnresolvedEnum => "E0105",
+            Code::UnresolvedVariant => "E0106",
+            Code::UnresolvedCapture => "E0107",
+            Code::MissingCapture => "E0108",
+            Code::DuplicateDefinition => "E0109",
+            Code::DuplicateField => "E0110",
+            Code::DuplicateParameter => "E0111",
+
+            Code::Mismatch => "E0201",
+            Code::TypeArgumentCount => "E0202",
+            Code::NoField => "E0203",
+            Code::ImmutableAssign => "E0204",
+            Code::InvalidAssign => "E0205",
+            Code::BinaryOperator => "E0206",
+            Code::UnaryOperator => "E0207",
+            Code::ArgumentCount => "E0208",
+            Code::NotAFunction => "E0209",
+            Code::NotIndexable => "E0210",
+            Code::PatternArity => "E0211",
+            Code::EnumAsValue => "E0212",
+            Code::RangeAsValue => "E0213",
+            Code::MissingReturn => "E0214",
+            Code::InvalidCast => "E0215",
+            Code::RecursiveType => "E0216",
+            Code::RepeatedStructField => "E0217",
+            Code::UnknownStructField => "E0218",
+            Code::MissingStructField => "E0219",
+
+            Code::InvalidCall => "E0301",
+            Code::InvalidEntry => "E0302",
+
+            Code::UnusedVariable => "W0101",
+            Code::UnusedFunction => "W0102",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Code> {
+        Code::ALL.iter().copied().find(|x| x.as_str() == s)
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_test() {
+        for (i, x) in Code::ALL.iter().enumerate() {
+            assert_eq!(Code::parse(x.as_str()), Some(*x));
+            assert!(Code::ALL[..i].iter().all(|y| y.as_str() != x.as_str()));
+        }
+        assert_eq!(Code::parse("E9999"), None);
+    }
+}
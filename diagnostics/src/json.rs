@@ -0,0 +1,111 @@
+use crate::diagnostic::Diagnostic;
+use crate::render::SourceFile;
+use crate::span::Span;
+
+// Machine-readable diagnostics for editors and CI: one JSON object per line, e.g.
+//
+// {"code":"E0201","severity":"error","message":"mismatched types: ...","spans":[{"file":
+// "main.tl","start":12,"end":13,"line_start":2,"column_start":18,"line_end":2,"column_end":19,
+// "primary":true,"label":null}],"notes":[]}
+//
+// Offsets are in chars; lines and columns are 1-based.
+
+// A JSON string literal.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn span(file: &SourceFile, span: Span, primary: bool, label: Option<&str>) -> String {
+    let (line_start, column_start) = file.line_col(span.pos);
+    let (line_end, column_end) = file.line_col(span.end());
+    format!(
+        "{{\"file\":{},\"start\":{},\"end\":{},\"line_start\":{},\"column_start\":{},\
+         \"line_end\":{},\"column_end\":{},\"primary\":{},\"label\":{}}}",
+        string(file.name()),
+        span.pos,
+        span.end(),
+        line_start + 1,
+        column_start + 1,
+        line_end + 1,
+        column_end + 1,
+        primary,
+        label.map_or("null".to_string(), string),
+    )
+}
+
+pub fn to_json(diagnostic: &Diagnostic, file: &SourceFile) -> String {
+    let spans = std::iter::once(span(file, diagnostic.span, true, None))
+        .chain(
+            diagnostic
+                .labels
+                .iter()
+                .map(|x| span(file, x.span, false, Some(&x.message))),
+        )
+        .collect::<Vec<_>>();
+    let notes = diagnostic
+        .notes
+        .iter()
+        .map(|x| string(x))
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"code\":{},\"severity\":{},\"message\":{},\"spans\":[{}],\"notes\":[{}]}}",
+        diagnostic
+            .code
+            .map_or("null".to_string(), |x| string(x.as_str())),
+        string(&diagnostic.severity.to_string()),
+        string(&diagnostic.message),
+        spans.join(","),
+        notes.join(","),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::Code;
+    use crate::render::{emit, ErrorFormat};
+
+    #[test]
+    fn to_json_test() {
+        let file = SourceFile::new("main.tl", "let a = 1;\nlet b = \"\\\";");
+        let diagnostic = Diagnostic::warning("unused variable `b`".to_string(), Span::new(11, 11))
+            .with_code(Code::UnusedVariable)
+            .with_label("\"here\"".to_string(), Span::new(15, 1))
+            .with_note("prefix it with `_`\n".to_string());
+        assert_eq!(
+            to_json(&diagnostic, &file),
+            "{\"code\":\"W0101\",\"severity\":\"warning\",\"message\":\"unused variable `b`\",\
+             \"spans\":[{\"file\":\"main.tl\",\"start\":11,\"end\":22,\"line_start\":2,\
+             \"column_start\":1,\"line_end\":2,\"column_end\":12,\"primary\":true,\"label\":null},\
+             {\"file\":\"main.tl\",\"start\":15,\"end\":16,\"line_start\":2,\"column_start\":5,\
+             \"line_end\":2,\"column_end\":6,\"primary\":false,\"label\":\"\\\"here\\\"\"}],\
+             \"notes\":[\"prefix it with `_`\\n\"]}"
+        );
+        assert_eq!(
+            emit(
+                &[diagnostic.clone(), diagnostic],
+                &file,
+                "json".parse().unwrap(),
+                true
+            )
+            .lines()
+            .count(),
+            2
+        );
+        assert!("xml".parse::<ErrorFormat>().is_err());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod code;
+pub mod diagnostic;
+pub mod json;
+pub mod render;
+pub mod span;
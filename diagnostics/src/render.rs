@@ -0,0 +1,308 @@
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::json::to_json;
+use crate::span::Span;
+use std::fmt::Write;
+use std::str::FromStr;
+
+// Renders diagnostics for a terminal in the style of rustc:
+//
+// error: mismatched types
+//  --> main.tl:2:18
+//   |
+// 2 |     let x: i64 = 1;
+//   |                  ^
+//   = note: ...
+//
+// With colors the severity and the primary underline are red for errors and yellow for warnings,
+// and the gutter and the other underlines blue, as ANSI escapes.
+
+pub struct SourceFile<'a> {
+    name: &'a str,
+    lines: Vec<&'a str>,
+    // Char offset of the start of each line.
+    starts: Vec<usize>,
+}
+
+impl<'a> SourceFile<'a> {
+    pub fn new(name: &'a str, src: &'a str) -> SourceFile<'a> {
+        let mut lines = Vec::new();
+        let mut starts = Vec::new();
+        let mut pos = 0;
+        for line in src.split('\n') {
+            starts.push(pos);
+            pos += line.chars().count() + 1;
+            lines.push(line.strip_suffix('\r').unwrap_or(line));
+        }
+        SourceFile {
+            name,
+            lines,
+            starts,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    // 0-based line and column (in chars) of a char offset. Offsets past the end are clamped
+    // to the end of the last line.
+    pub fn line_col(&self, pos: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&x| x <= pos) - 1;
+        let col = (pos - self.starts[line]).min(self.lines[line].chars().count());
+        (line, col)
+    }
+}
+
+struct Mark<'a> {
+    span: Span,
+    primary: bool,
+    message: Option<&'a str>,
+}
+
+const BOLD: &str = "1";
+const RED: &str = "1;31";
+const YELLOW: &str = "1;33";
+const BLUE: &str = "1;34";
+
+// `s` in the ANSI style `style`, or as it is without colors.
+fn paint(s: &str, style: &str, color: bool) -> String {
+    if color && !s.is_empty() {
+        format!("\x1b[{}m{}\x1b[0m", style, s)
+    } else {
+        s.to_string()
+    }
+}
+
+fn severity_style(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => RED,
+        Severity::Warning => YELLOW,
+    }
+}
+
+pub fn render(diagnostic: &Diagnostic, file: &SourceFile) -> String {
+    render_with(diagnostic, file, false)
+}
+
+pub fn render_colored(diagnostic: &Diagnostic, file: &SourceFile) -> String {
+    render_with(diagnostic, file, true)
+}
+
+fn render_with(diagnostic: &Diagnostic, file: &SourceFile, color: bool) -> String {
+    let mut out = String::new();
+    let (line, col) = file.line_col(diagnostic.span.pos);
+    let style = severity_style(diagnostic.severity);
+    let severity = match diagnostic.code {
+        Some(code) => format!("{}[{}]", diagnostic.severity, code),
+        None => diagnostic.severity.to_string(),
+    };
+    writeln!(
+        out,
+        "{}{}",
+        paint(&severity, style, color),
+        paint(&format!(": {}", diagnostic.message), BOLD, color)
+    )
+    .unwrap();
+
+    let mut marks = vec![Mark {
+        span: diagnostic.span,
+        primary: true,
+        message: None,
+    }];
+    marks.extend(diagnostic.labels.iter().map(|x| Mark {
+        span: x.span,
+        primary: false,
+        message: Some(&x.message),
+    }));
+    marks.sort_by_key(|x| x.span.pos);
+    let last = marks
+        .iter()
+        .map(|x| file.line_col(x.span.pos).0)
+        .max()
+        .unwrap_or(line);
+    let width = (last + 1).to_string().len();
+    let gutter = " ".repeat(width);
+    let bar = paint("|", BLUE, color);
+
+    writeln!(
+        out,
+        "{}{} {}:{}:{}",
+        gutter,
+        paint("-->", BLUE, color),
+        file.name,
+        line + 1,
+        col + 1
+    )
+    .unwrap();
+    writeln!(out, "{} {}", gutter, bar).unwrap();
+    let mut prev: Option<usize> = None;
+    for mark in &marks {
+        let (line, col) = file.line_col(mark.span.pos);
+        if prev != Some(line) {
+            if prev.is_some_and(|prev| line > prev + 1) {
+                writeln!(out, "{}", paint("...", BLUE, color)).unwrap();
+            }
+            let number = format!("{:>width$}", line + 1, width = width);
+            writeln!(
+                out,
+                "{} {} {}",
+                paint(&number, BLUE, color),
+                bar,
+                file.lines[line]
+            )
+            .unwrap();
+            prev = Some(line);
+        }
+        // Spans over several lines are underlined up to the end of their first line.
+        let (end_line, end_col) = file.line_col(mark.span.end());
+        let end = if end_line == line {
+            end_col
+        } else {
+            file.lines[line].chars().count()
+        };
+        let (underline, mark_style) = if mark.primary {
+            ("^", style)
+        } else {
+            ("-", BLUE)
+        };
+        let underline = underline.repeat((end - col).max(1));
+        let underline = match mark.message {
+            Some(message) => format!("{} {}", underline, message),
+            None => underline,
+        };
+        writeln!(
+            out,
+            "{} {} {}{}",
+            gutter,
+            bar,
+            " ".repeat(col),
+            paint(&underline, mark_style, color)
+        )
+        .unwrap();
+    }
+    for note in &diagnostic.notes {
+        writeln!(out, "{} {} note: {}", gutter, paint("=", BLUE, color), note).unwrap();
+    }
+    out
+}
+
+pub fn render_all(diagnostics: &[Diagnostic], file: &SourceFile, color: bool) -> String {
+    diagnostics
+        .iter()
+        .map(|x| render_with(x, file, color))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn count(n: usize, what: &str) -> String {
+    format!("{} {}{}", n, what, if n == 1 { "" } else { "s" })
+}
+
+// A line like `2 errors, 1 warning emitted`, or `None` when there is nothing to count.
+pub fn summary(diagnostics: &[Diagnostic], color: bool) -> Option<String> {
+    let errors = diagnostics.iter().filter(|x| x.is_error()).count();
+    let warnings = diagnostics.len() - errors;
+    let mut counts = Vec::new();
+    if errors > 0 {
+        counts.push(paint(&count(errors, "error"), RED, color));
+    }
+    if warnings > 0 {
+        counts.push(paint(&count(warnings, "warning"), YELLOW, color));
+    }
+    if counts.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{}{}",
+        counts.join(", "),
+        paint(" emitted", BOLD, color)
+    ))
+}
+
+// The value of `--error-format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ErrorFormat, String> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("unknown error format `{}`", s)),
+        }
+    }
+}
+
+// Colors are only used by the human format.
+pub fn emit(
+    diagnostics: &[Diagnostic],
+    file: &SourceFile,
+    format: ErrorFormat,
+    color: bool,
+) -> String {
+    match format {
+        ErrorFormat::Human => render_all(diagnostics, file, color),
+        ErrorFormat::Json => diagnostics
+            .iter()
+            .map(|x| to_json(x, file) + "\n")
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::Code;
+
+    #[test]
+    fn render_test() {
+        let src = "fun f() {\n    let x: i64 = 1;\n\n\n    x + \"あ\"\n}";
+        let file = SourceFile::new("main.tl", src);
+        let pos = src.chars().position(|c| c == '"').unwrap();
+        let diagnostic = Diagnostic::new("mismatched types".to_string(), Span::new(pos, 3))
+            .with_code(Code::Mismatch)
+            .with_label("`x` is declared here".to_string(), Span::new(18, 1))
+            .with_note("expected `i64`, found `string`".to_string());
+        assert_eq!(
+            render(&diagnostic, &file),
+            "error[E0201]: mismatched types
+ --> main.tl:5:9
+  |
+2 |     let x: i64 = 1;
+  |         - `x` is declared here
+...
+5 |     x + \"あ\"
+  |         ^^^
+  = note: expected `i64`, found `string`
+"
+        );
+
+        let warning = Diagnostic::warning("unused".to_string(), Span::new(src.chars().count(), 0));
+        assert_eq!(
+            render(&warning, &file),
+            "warning: unused\n --> main.tl:6:2\n  |\n6 | }\n  |  ^\n"
+        );
+        assert_eq!(
+            render_colored(&warning, &file),
+            "\x1b[1;33mwarning\x1b[0m\x1b[1m: unused\x1b[0m\n \x1b[1;34m-->\x1b[0m main.tl:6:2\n  \
+             \x1b[1;34m|\x1b[0m\n\x1b[1;34m6\x1b[0m \x1b[1;34m|\x1b[0m }\n  \x1b[1;34m|\x1b[0m  \
+             \x1b[1;33m^\x1b[0m\n"
+        );
+
+        let diagnostics = [diagnostic.clone(), diagnostic, warning];
+        assert_eq!(
+            summary(&diagnostics, false).as_deref(),
+            Some("2 errors, 1 warning emitted")
+        );
+        assert_eq!(
+            summary(&diagnostics[2..], true).as_deref(),
+            Some("\x1b[1;33m1 warning\x1b[0m\x1b[1m emitted\x1b[0m")
+        );
+        assert_eq!(summary(&[], false), None);
+    }
+}
@@ -0,0 +1,21 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub pos: usize,
+    pub len: usize,
+}
+
+impl Span {
+    pub fn new(pos: usize, len: usize) -> Span {
+        Span { pos, len }
+    }
+
+    pub fn end(&self) -> usize {
+        self.pos + self.len
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        let pos = self.pos.min(other.pos);
+        Span::new(pos, self.end().max(other.end()) - pos)
+    }
+}
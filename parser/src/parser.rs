@@ -3,6 +3,7 @@ use std::error;
 use std::fmt;
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::rc::Rc;
 
 #[macro_export]
 macro_rules! or {
@@ -25,22 +26,59 @@ pub enum ErrorExpect<T> {
 #[derive(Clone, Debug, PartialEq)]
 pub struct ParserError<T> {
     pos: usize,
+    len: usize,
     unexpected: Option<T>,
     expecting: ErrorExpect<T>,
+    message: Option<String>,
 }
 
 impl<T> ParserError<T> {
     pub fn new(pos: usize, unexpected: Option<T>, expecting: ErrorExpect<T>) -> ParserError<T> {
         ParserError {
             pos,
+            len: 0,
             unexpected,
             expecting,
+            message: None,
         }
     }
+
+    pub fn with_message(pos: usize, len: usize, message: String) -> ParserError<T> {
+        ParserError {
+            pos,
+            len,
+            unexpected: None,
+            expecting: ErrorExpect::Unknown,
+            message: Some(message),
+        }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn span(&self) -> (usize, usize) {
+        (self.pos, self.len)
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn unexpected(&self) -> Option<&T> {
+        self.unexpected.as_ref()
+    }
+
+    pub fn expecting(&self) -> &ErrorExpect<T> {
+        &self.expecting
+    }
 }
 
 impl<T: Debug> fmt::Display for ParserError<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(message) = &self.message {
+            return write!(f, "{}", message);
+        }
         write!(
             f,
             "unexpected {:?} expecting {:?}",
@@ -146,6 +184,13 @@ pub trait Parser {
         Msg::new(self, msg)
     }
 
+    fn sep_by<T: Parser<Input = Self::Input>>(self, sep: T) -> SepBy<Self, T>
+    where
+        Self: Sized,
+    {
+        SepBy::new(self, sep)
+    }
+
     fn then<F: Fn(Self::Output) -> B, B: Parser<Input = Self::Input>>(
         self,
         f: F,
@@ -220,6 +265,24 @@ pub fn fail<A: Clone, B>() -> Fail<A, B> {
     Fail::new()
 }
 
+pub fn pratt<A: Parser>(
+    term: A,
+    ops: impl Into<Rc<[Operator<A::Input, A::Output>]>>,
+) -> Pratt<A, A::Input>
+where
+    A::Input: Clone,
+{
+    Pratt::new(term, ops.into(), |x| x.clone())
+}
+
+pub fn pratt_by<A: Parser, K>(
+    term: A,
+    ops: impl Into<Rc<[Operator<K, A::Output>]>>,
+    key: fn(&A::Input) -> K,
+) -> Pratt<A, K> {
+    Pratt::new(term, ops.into(), key)
+}
+
 #[derive(Clone, Debug)]
 pub struct AnyOne<T: Clone>(PhantomData<T>);
 
@@ -229,6 +292,12 @@ impl<T: Clone> AnyOne<T> {
     }
 }
 
+impl<T: Clone> Default for AnyOne<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Clone> Parser for AnyOne<T> {
     type Input = T;
     type Output = T;
@@ -256,7 +325,7 @@ impl<T: Parser> Parser for Attempt<T> {
     fn parse(&self, st: &mut Stream<T::Input>) -> ParserResult<T::Output, T::Input> {
         let pos = st.pos();
         let res = self.0.parse(st);
-        if let Err(_) = res {
+        if res.is_err() {
             st.set_pos(pos);
         }
         res
@@ -456,6 +525,12 @@ impl<T: Clone> Eof<T> {
     }
 }
 
+impl<T: Clone> Default for Eof<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Clone> Parser for Eof<T> {
     type Input = T;
     type Output = ();
@@ -587,6 +662,31 @@ where
     }
 }
 
+// A trailing separator is allowed.
+#[derive(Clone, Debug)]
+pub struct SepBy<A: Parser, B: Parser<Input = A::Input>>(A, B);
+
+impl<A: Parser, B: Parser<Input = A::Input>> SepBy<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        SepBy(a, b)
+    }
+}
+
+impl<A: Parser, B: Parser<Input = A::Input>> Parser for SepBy<A, B> {
+    type Input = A::Input;
+    type Output = Vec<A::Output>;
+    fn parse(&self, st: &mut Stream<Self::Input>) -> ParserResult<Self::Output, Self::Input> {
+        let mut res = Vec::new();
+        while let Some(x) = (&self.0).optional().parse(st)? {
+            res.push(x);
+            if (&self.1).optional().parse(st)?.is_none() {
+                break;
+            }
+        }
+        Ok(res)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Then<A: Parser, F: Fn(A::Output) -> B, B: Parser<Input = A::Input>>(
     A,
@@ -637,6 +737,12 @@ impl<A: Clone, B> Fail<A, B> {
     }
 }
 
+impl<A: Clone, B> Default for Fail<A, B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<A: Clone, B> Parser for Fail<A, B> {
     type Input = A;
     type Output = B;
@@ -666,14 +772,112 @@ impl<A: Parser, B: Parser<Input = A::Input, Output = A::Output>> Parser for Eith
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Assoc {
+    Left,
+    Right,
+    Non,
+}
+
+#[derive(Clone)]
+pub struct Operator<T, O> {
+    pub token: T,
+    pub prec: usize,
+    pub assoc: Assoc,
+    pub build: Rc<dyn Fn(O, O) -> O>,
+}
+
+impl<T, O> Operator<T, O> {
+    pub fn new<F: Fn(O, O) -> O + 'static>(token: T, prec: usize, assoc: Assoc, build: F) -> Self {
+        Operator {
+            token,
+            prec,
+            assoc,
+            build: Rc::new(build),
+        }
+    }
+}
+
+impl<T: Debug, O> Debug for Operator<T, O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Operator")
+            .field("token", &self.token)
+            .field("prec", &self.prec)
+            .field("assoc", &self.assoc)
+            .finish()
+    }
+}
+
+// Operators are looked up by `key(token)`, so inputs that carry more than the operator itself
+// (e.g. positions) can still share one table.
+#[derive(Clone, Debug)]
+pub struct Pratt<A: Parser, K>(A, Rc<[Operator<K, A::Output>]>, fn(&A::Input) -> K);
+
+impl<A: Parser, K> Pratt<A, K> {
+    pub fn new(term: A, ops: Rc<[Operator<K, A::Output>]>, key: fn(&A::Input) -> K) -> Self {
+        Pratt(term, ops, key)
+    }
+}
+
+impl<A: Parser, K: PartialEq> Pratt<A, K>
+where
+    A::Input: Clone,
+{
+    fn parse_prec(
+        &self,
+        st: &mut Stream<A::Input>,
+        min: usize,
+    ) -> ParserResult<A::Output, A::Input> {
+        let mut lhs = self.0.parse(st)?;
+        let mut non_assoc = None;
+        while let Some(op) = st.peak().and_then(|x| {
+            let key = (self.2)(&x);
+            self.1
+                .iter()
+                .find(|op| op.token == key && op.prec >= min && Some(op.prec) != non_assoc)
+        }) {
+            st.next();
+            let rhs = self.parse_prec(
+                st,
+                match op.assoc {
+                    Assoc::Right => op.prec,
+                    Assoc::Left | Assoc::Non => op.prec + 1,
+                },
+            )?;
+            lhs = (op.build)(lhs, rhs);
+            non_assoc = if op.assoc == Assoc::Non {
+                Some(op.prec)
+            } else {
+                None
+            };
+        }
+        Ok(lhs)
+    }
+}
+
+impl<A: Parser, K: PartialEq> Parser for Pratt<A, K>
+where
+    A::Input: Clone,
+{
+    type Input = A::Input;
+    type Output = A::Output;
+    fn parse(&self, st: &mut Stream<Self::Input>) -> ParserResult<Self::Output, Self::Input> {
+        self.parse_prec(st, 0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn helper<A: Parser>(
-        analyzer: A,
-        cases: Vec<(Vec<A::Input>, ParserResult<A::Output, A::Input>, usize)>,
-    ) where
+    type Case<A> = (
+        Vec<<A as Parser>::Input>,
+        ParserResult<<A as Parser>::Output, <A as Parser>::Input>,
+        usize,
+    );
+
+    fn helper<A: Parser>(analyzer: A, cases: Vec<Case<A>>)
+    where
         A::Input: PartialEq + Debug,
         A::Output: PartialEq + Debug,
     {
@@ -761,4 +965,44 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn pratt_test() {
+        let num = expect(|x: &char| x.is_ascii_digit()).map(|x| x.to_string());
+        let ops = vec![
+            Operator::new('=', 0, Assoc::Non, |a, b| format!("({}={})", a, b)),
+            Operator::new('+', 1, Assoc::Left, |a, b| format!("({}+{})", a, b)),
+            Operator::new('*', 2, Assoc::Left, |a, b| format!("({}*{})", a, b)),
+            Operator::new('^', 3, Assoc::Right, |a, b| format!("({}^{})", a, b)),
+        ];
+        helper(
+            pratt(num, ops),
+            vec![
+                (
+                    "1+2*3^4^5+6".chars().collect(),
+                    Ok("((1+(2*(3^(4^5))))+6)".to_string()),
+                    11,
+                ),
+                ("1=2=3".chars().collect(), Ok("(1=2)".to_string()), 3),
+                (
+                    "1+".chars().collect(),
+                    Err(ParserError::new(2, None, ErrorExpect::Unknown)),
+                    2,
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn sep_by_test() {
+        helper(
+            token(1).sep_by(token(0)),
+            vec![
+                (vec![], Ok(vec![]), 0),
+                (vec![1, 0, 1], Ok(vec![1, 1]), 3),
+                (vec![1, 0, 1, 0, 2], Ok(vec![1, 1]), 4),
+                (vec![1, 1], Ok(vec![1]), 1),
+            ],
+        );
+    }
 }
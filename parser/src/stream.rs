@@ -1,5 +1,8 @@
+use std::cell::OnceCell;
+
+// The third field caches where each line starts, for `line_col` on char streams.
 #[derive(Clone, Debug)]
-pub struct Stream<T>(Vec<T>, usize);
+pub struct Stream<T>(Vec<T>, usize, OnceCell<Vec<usize>>);
 
 impl<T: Clone> Stream<T> {
     pub fn peak(&self) -> Option<T> {
@@ -9,11 +12,15 @@ impl<T: Clone> Stream<T> {
     pub fn peak_index(&self, i: usize) -> Option<T> {
         self.0.get(self.1 + i).cloned()
     }
+
+    pub fn get(&self, i: usize) -> Option<T> {
+        self.0.get(i).cloned()
+    }
 }
 
 impl<T> Stream<T> {
     pub fn new(data: Vec<T>) -> Self {
-        Stream(data, 0)
+        Stream(data, 0, OnceCell::new())
     }
 
     pub fn pos(&self) -> usize {
@@ -33,6 +40,7 @@ impl<T> Stream<T> {
         self.set_pos(self.pos() + x)
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<()> {
         self.add_pos(1)
     }
@@ -40,4 +48,79 @@ impl<T> Stream<T> {
     pub fn eof(&self) -> bool {
         self.0.len() <= self.1
     }
+
+    pub fn remaining(&self) -> usize {
+        self.0.len().saturating_sub(self.1)
+    }
+
+    // Drops what comes before the position and appends `xs`, for input that arrives in chunks.
+    // Positions and lines then count from the old position.
+    pub fn refill(&mut self, xs: impl IntoIterator<Item = T>) {
+        self.0.drain(..self.1);
+        self.0.extend(xs);
+        self.1 = 0;
+        self.2 = OnceCell::new();
+    }
+}
+
+impl Stream<char> {
+    // Lines end at `\n`, `\r\n` or a lone `\r`.
+    pub fn line_col(&self) -> (usize, usize) {
+        let starts = self.2.get_or_init(|| {
+            std::iter::once(0)
+                .chain(
+                    self.0
+                        .iter()
+                        .enumerate()
+                        .filter(|&(i, &c)| {
+                            c == '\n' || c == '\r' && self.0.get(i + 1) != Some(&'\n')
+                        })
+                        .map(|x| x.0 + 1),
+                )
+                .collect()
+        });
+        let pos = self.1.min(self.0.len());
+        let line = starts.partition_point(|&x| x <= pos);
+        (line, pos - starts[line - 1] + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_col_test() {
+        let mut st = Stream::new("ab\n\ncd\n".chars().collect());
+        let mut got = Vec::new();
+        while !st.eof() {
+            got.push(st.line_col());
+            st.next();
+        }
+        got.push(st.line_col());
+        assert_eq!(
+            got,
+            vec![
+                (1, 1),
+                (1, 2),
+                (1, 3),
+                (2, 1),
+                (3, 1),
+                (3, 2),
+                (3, 3),
+                (4, 1)
+            ]
+        );
+        // Backtracking reuses the same line starts.
+        st.set_pos(4);
+        assert_eq!(st.line_col(), (3, 1));
+
+        let mut st = Stream::new("a\r\nb\rc".chars().collect());
+        let mut got = Vec::new();
+        while !st.eof() {
+            got.push(st.line_col());
+            st.next();
+        }
+        assert_eq!(got, vec![(1, 1), (1, 2), (1, 3), (2, 1), (2, 2), (3, 1)]);
+    }
 }
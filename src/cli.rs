@@ -0,0 +1,463 @@
+use ast::codegen::{Entry, Options};
+use diagnostics::render::ErrorFormat;
+
+use std::path::Path;
+use std::str::FromStr;
+
+// The command line, `tlang <command> [options] <file>...`, parsed by hand so that the compiler
+// does not depend on an argument parsing library. A file of `-` is the standard input.
+
+pub const NO_JSON: &str = "tlang was built without the `json` feature";
+
+pub const USAGE: &str = "usage: tlang <command> [options] <file>...
+
+commands:
+    lex      print the tokens of each file
+    parse    print the syntax tree of each file
+    check    report the errors in each file
+    build    compile each file to a wasm module
+    run      compile each file and call its `main`
+    fmt      rewrite each file in the standard style, or print it for -
+    eval     run the statements given with -e or --stdin as the body of `main`, and print the
+             value they end in; it takes no files
+    lsp      serve the language server protocol over stdio, and take no files
+
+options:
+    -o, --output <path>          where `build` writes the output of its only file
+                                 [default: the file with a .wasm extension, or out.wasm for -,
+                                 and the standard output for the other kinds of --emit]
+    --format <text|json>         how `lex` and `parse` print the tokens or the syntax tree
+                                 [default: text]
+    --emit <kind>                what `build` makes, one of tokens, ast, hir (the syntax tree
+                                 after type checking and desugaring), wat or wasm [default: wasm]
+    --names                      make `build` emit the name section, so that debuggers and stack
+                                 traces show the names of functions and locals
+    --source-map <path>          make `build` also write the source map of its only file, which
+                                 the module points to
+    --entry <export|start>       whether `build` exports `main` for the host to call, or makes
+                                 it the start function that runs on instantiation
+                                 [default: export]
+    --wasi                       make `build` define `print` and `println` on top of WASI's
+                                 `fd_write`, for wasmtime, wasmer and the like, instead of
+                                 importing them from the host
+    --error-format <human|json>  how diagnostics are printed [default: human]
+    --color <auto|always|never>  whether human diagnostics are colored, where auto colors them
+                                 when they go to a terminal [default: auto]
+    --check                      make `fmt` list the files it would change instead, and fail
+                                 if there are any
+    -e, --expr <source>          what `eval` runs
+    --stdin                      make `eval` run the standard input instead
+    -h, --help                   print this message
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Lex,
+    Parse,
+    Check,
+    Build,
+    Run,
+    Fmt,
+    Eval,
+    Lsp,
+}
+
+// The stage `build` stops at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    Hir,
+    Wat,
+    Wasm,
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Emit, String> {
+        match s {
+            "tokens" => Ok(Emit::Tokens),
+            "ast" => Ok(Emit::Ast),
+            "hir" => Ok(Emit::Hir),
+            "wat" => Ok(Emit::Wat),
+            "wasm" => Ok(Emit::Wasm),
+            _ => Err(format!("unknown kind of output `{}`", s)),
+        }
+    }
+}
+
+// How `lex` and `parse` print what they made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" if cfg!(feature = "json") => Ok(Format::Json),
+            "json" => Err(NO_JSON.to_string()),
+            _ => Err(format!("unknown format `{}`", s)),
+        }
+    }
+}
+
+// `Entry` belongs to codegen, so it cannot implement `FromStr` here.
+fn entry(s: &str) -> Result<Entry, String> {
+    match s {
+        "export" => Ok(Entry::Export),
+        "start" => Ok(Entry::Start),
+        _ => Err(format!("unknown entry `{}`", s)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Color, String> {
+        match s {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err(format!("unknown color choice `{}`", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Args {
+    pub command: Command,
+    pub inputs: Vec<String>,
+    pub output: Option<String>,
+    pub emit: Emit,
+    pub format: Format,
+    pub error_format: ErrorFormat,
+    pub color: Color,
+    // What `build` asks codegen for.
+    pub options: Options,
+    // Where `build` writes the source map.
+    pub source_map: Option<String>,
+    pub check: bool,
+    // The source `eval` runs, or `None` for the standard input.
+    pub expr: Option<String>,
+}
+
+pub fn is_help(args: &[String]) -> bool {
+    args.is_empty() || args.iter().any(|x| x == "-h" || x == "--help")
+}
+
+pub fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut args = args.iter();
+    let command = match args.next().map(String::as_str) {
+        Some("lex") => Command::Lex,
+        Some("parse") => Command::Parse,
+        Some("check") => Command::Check,
+        Some("build") => Command::Build,
+        Some("run") => Command::Run,
+        Some("fmt") => Command::Fmt,
+        Some("eval") => Command::Eval,
+        Some("lsp") if cfg!(feature = "json") => Command::Lsp,
+        Some("lsp") => return Err(NO_JSON.to_string()),
+        Some(x) => return Err(format!("unknown command `{}`", x)),
+        None => return Err("no command given".to_string()),
+    };
+    let mut inputs = Vec::<String>::new();
+    let mut output = None;
+    let mut emit = Emit::Wasm;
+    let mut format = Format::Text;
+    let mut error_format = ErrorFormat::Human;
+    let mut color = Color::Auto;
+    let mut options = Options::default();
+    let mut source_map = None;
+    let mut check = false;
+    let mut expr = None;
+    let mut stdin = false;
+    while let Some(arg) = args.next() {
+        // Options take their value either after `=` or as the next argument.
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with('-') => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| format!("`{}` needs a value", name))
+        };
+        match name {
+            "-o" | "--output" if command == Command::Build => output = Some(value()?),
+            "--emit" if command == Command::Build => emit = value()?.parse()?,
+            "--names" if command == Command::Build && inline.is_none() => options.names = true,
+            "--source-map" if command == Command::Build => source_map = Some(value()?),
+            "--entry" if command == Command::Build => options.entry = entry(&value()?)?,
+            "--wasi" if command == Command::Build && inline.is_none() => options.wasi = true,
+            "--format" if matches!(command, Command::Lex | Command::Parse) => {
+                format = value()?.parse()?
+            }
+            "--error-format" => error_format = value()?.parse()?,
+            "--color" => color = value()?.parse()?,
+            "--check" if command == Command::Fmt && inline.is_none() => check = true,
+            "-e" | "--expr" if command == Command::Eval => expr = Some(value()?),
+            "--stdin" if command == Command::Eval && inline.is_none() => stdin = true,
+            _ if name.starts_with('-') && name.len() > 1 => {
+                return Err(format!("unknown option `{}`", name))
+            }
+            _ if arg == "-" && inputs.iter().any(|x| x == "-") => {
+                return Err("`-` can only be given once".to_string())
+            }
+            _ => inputs.push(arg.clone()),
+        }
+    }
+    if matches!(command, Command::Lsp | Command::Eval) {
+        if let Some(x) = inputs.first() {
+            return Err(format!("unexpected argument `{}`", x));
+        }
+    } else if inputs.is_empty() {
+        return Err("no input file given".to_string());
+    }
+    if command == Command::Eval && expr.is_some() == stdin {
+        return Err("`eval` needs either `-e` or `--stdin`".to_string());
+    }
+    if output.is_some() && inputs.len() > 1 {
+        return Err("`-o` needs a single input file".to_string());
+    }
+    if source_map.is_some() && emit != Emit::Wasm {
+        return Err("`--source-map` needs `--emit=wasm`".to_string());
+    }
+    if source_map.is_some() && inputs.len() > 1 {
+        return Err("`--source-map` needs a single input file".to_string());
+    }
+    Ok(Args {
+        command,
+        inputs,
+        output,
+        emit,
+        format,
+        error_format,
+        color,
+        options,
+        source_map,
+        check,
+        expr,
+    })
+}
+
+// The URL the module of `input` refers to its source map by: the name of the map if it is next
+// to the module, and its path otherwise.
+pub fn source_map_url(args: &Args, input: &str) -> Option<String> {
+    let map = Path::new(args.source_map.as_ref()?);
+    let module = output_path(args, input)?;
+    let dir = |x: &Path| x.parent().map(Path::to_path_buf).unwrap_or_default();
+    match map.file_name() {
+        Some(name) if dir(map) == dir(Path::new(&module)) => {
+            Some(name.to_string_lossy().into_owned())
+        }
+        _ => Some(map.to_string_lossy().into_owned()),
+    }
+}
+
+// Where `build` writes the output of `input`, or `None` for the standard output.
+pub fn output_path(args: &Args, input: &str) -> Option<String> {
+    match &args.output {
+        Some(output) => Some(output.clone()),
+        None if args.emit != Emit::Wasm => None,
+        None if input == "-" => Some("out.wasm".to_string()),
+        None => Some(
+            Path::new(input)
+                .with_extension("wasm")
+                .to_string_lossy()
+                .into_owned(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        parse_args(
+            &args
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn parse_args_test() {
+        assert_eq!(
+            parse("build main.tl -o a.wasm --error-format=json"),
+            Ok(Args {
+                command: Command::Build,
+                inputs: vec!["main.tl".to_string()],
+                output: Some("a.wasm".to_string()),
+                emit: Emit::Wasm,
+                format: Format::Text,
+                error_format: ErrorFormat::Json,
+                color: Color::Auto,
+                options: Options::default(),
+                source_map: None,
+                check: false,
+                expr: None,
+            })
+        );
+        let args = parse("build --error-format human src/main.tl - lib.tl").unwrap();
+        assert_eq!(
+            args,
+            Args {
+                command: Command::Build,
+                inputs: vec![
+                    "src/main.tl".to_string(),
+                    "-".to_string(),
+                    "lib.tl".to_string()
+                ],
+                output: None,
+                emit: Emit::Wasm,
+                format: Format::Text,
+                error_format: ErrorFormat::Human,
+                color: Color::Auto,
+                options: Options::default(),
+                source_map: None,
+                check: false,
+                expr: None,
+            }
+        );
+        assert_eq!(
+            output_path(&args, "src/main.tl").as_deref(),
+            Some("src/main.wasm")
+        );
+        assert_eq!(output_path(&args, "-").as_deref(), Some("out.wasm"));
+        assert_eq!(output_path(&args, "lib").as_deref(), Some("lib.wasm"));
+        let args = parse("build --emit hir a.tl").unwrap();
+        assert_eq!(args.emit, Emit::Hir);
+        assert_eq!(output_path(&args, "a.tl"), None);
+        assert_eq!(
+            parse("build --emit=wat a.tl -o a.wat").map(|x| output_path(&x, "a.tl")),
+            Ok(Some("a.wat".to_string()))
+        );
+        assert!(parse("build --wasi a.tl").unwrap().options.wasi);
+        assert!(parse("build --names a.tl").unwrap().options.names);
+        let args = parse("build --source-map out/a.wasm.map a.tl -o out/a.wasm").unwrap();
+        assert_eq!(source_map_url(&args, "a.tl").as_deref(), Some("a.wasm.map"));
+        let args = parse("build --source-map maps/a.map src/a.tl").unwrap();
+        assert_eq!(
+            source_map_url(&args, "src/a.tl").as_deref(),
+            Some("maps/a.map")
+        );
+        assert_eq!(source_map_url(&parse("build a.tl").unwrap(), "a.tl"), None);
+        assert_eq!(
+            parse("build --emit=wat --source-map a.map a.tl").unwrap_err(),
+            "`--source-map` needs `--emit=wasm`"
+        );
+        assert_eq!(
+            parse("build --source-map a.map a.tl b.tl").unwrap_err(),
+            "`--source-map` needs a single input file"
+        );
+        assert_eq!(
+            parse("build --entry=start a.tl").map(|x| x.options.entry),
+            Ok(Entry::Start)
+        );
+        assert_eq!(
+            parse("build --entry main a.tl").unwrap_err(),
+            "unknown entry `main`"
+        );
+        assert_eq!(
+            parse("run --wasi a.tl").unwrap_err(),
+            "unknown option `--wasi`"
+        );
+        assert_eq!(
+            parse("build --emit=mir a.tl").unwrap_err(),
+            "unknown kind of output `mir`"
+        );
+        if cfg!(feature = "json") {
+            assert_eq!(
+                parse("parse --format=json a.tl").map(|x| x.format),
+                Ok(Format::Json)
+            );
+        }
+        assert_eq!(
+            parse("lex --format=yaml a.tl").unwrap_err(),
+            "unknown format `yaml`"
+        );
+        assert_eq!(
+            parse("check --color never a.tl").map(|x| x.color),
+            Ok(Color::Never)
+        );
+        assert_eq!(
+            parse("check --color=sometimes a.tl").unwrap_err(),
+            "unknown color choice `sometimes`"
+        );
+        assert_eq!(
+            parse("check --format=json a.tl").unwrap_err(),
+            "unknown option `--format`"
+        );
+        if cfg!(feature = "json") {
+            assert_eq!(parse("lsp").map(|x| x.command), Ok(Command::Lsp));
+            assert_eq!(parse("lsp a.tl").unwrap_err(), "unexpected argument `a.tl`");
+        }
+        assert_eq!(parse("fmt --check a.tl b.tl").map(|x| x.check), Ok(true));
+        assert_eq!(
+            parse("build --check a.tl").unwrap_err(),
+            "unknown option `--check`"
+        );
+        assert_eq!(
+            parse("run --emit=wat a.tl").unwrap_err(),
+            "unknown option `--emit`"
+        );
+        assert_eq!(
+            parse("eval -e 1+2").map(|x| x.expr),
+            Ok(Some("1+2".to_string()))
+        );
+        assert_eq!(
+            parse("eval --stdin").map(|x| (x.expr, x.inputs)),
+            Ok((None, vec![]))
+        );
+        assert_eq!(
+            parse("eval -e 1 --stdin").unwrap_err(),
+            "`eval` needs either `-e` or `--stdin`"
+        );
+        assert_eq!(
+            parse("eval").unwrap_err(),
+            "`eval` needs either `-e` or `--stdin`"
+        );
+        assert_eq!(
+            parse("eval --stdin a.tl").unwrap_err(),
+            "unexpected argument `a.tl`"
+        );
+        assert_eq!(parse("run -e 1").unwrap_err(), "unknown option `-e`");
+        assert_eq!(parse("lex").unwrap_err(), "no input file given");
+        assert_eq!(
+            parse("compile a.tl").unwrap_err(),
+            "unknown command `compile`"
+        );
+        assert_eq!(parse("check a.tl -o x").unwrap_err(), "unknown option `-o`");
+        assert_eq!(parse("build a.tl -o").unwrap_err(), "`-o` needs a value");
+        assert_eq!(
+            parse("parse - a.tl -").unwrap_err(),
+            "`-` can only be given once"
+        );
+        assert_eq!(
+            parse("build a.tl b.tl -o x.wasm").unwrap_err(),
+            "`-o` needs a single input file"
+        );
+        assert_eq!(
+            parse("parse a.tl --error-format=xml").unwrap_err(),
+            "unknown error format `xml`"
+        );
+        assert!(is_help(&["run".to_string(), "--help".to_string()]));
+        assert!(is_help(&[]));
+    }
+}
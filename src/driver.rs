@@ -0,0 +1,315 @@
+use ast::ast::{MemberKind, Module};
+use ast::codegen::{codegen_module, Options};
+use ast::desugar::desugar_module;
+use ast::formatter::format_source;
+use ast::parser::{parse_module, parse_source};
+use ast::resolver::resolve_module;
+use ast::sourcemap::{source_map, CodeSpans};
+use ast::typeck::{check_module, Ty, Types};
+use ast::wasi::{self, with_builtins};
+use diagnostics::diagnostic::Diagnostic;
+use diagnostics::render::SourceFile;
+use diagnostics::span::Span;
+use parser::parser::Parser;
+use parser::stream::Stream;
+use std::cell::RefCell;
+use std::convert::TryInto;
+use std::io::Write;
+use std::rc::Rc;
+use token::parser::{lexer, lexer_diagnostic};
+use token::token::{KeywordTable, Literal, NumLiteral, Token};
+use wasm::ast::WasmASTRoot;
+use wasm::interp::{Imports, Instance, Trap, Value};
+
+// The stages of the compiler, from source text to a module. Each stage returns the diagnostics
+// of the stages before it too, and stops before making anything once one of them has an error.
+
+fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(Diagnostic::is_error)
+}
+
+pub fn lex(src: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+    let chars = src.chars().collect();
+    match lexer(&KeywordTable::default()).parse(&mut Stream::new(chars)) {
+        Ok(tokens) => (tokens, Vec::new()),
+        Err(e) => (Vec::new(), vec![lexer_diagnostic(&e)]),
+    }
+}
+
+pub fn parse(src: &str) -> (Module, Vec<Diagnostic>) {
+    parse_source(src)
+}
+
+pub fn format(src: &str) -> Result<String, Vec<Diagnostic>> {
+    format_source(src)
+}
+
+// The module with the builtins added and names resolved, and the types of its locals.
+pub fn check(src: &str) -> (Option<(Module, Types)>, Vec<Diagnostic>) {
+    let (module, mut diagnostics) = parse(src);
+    if has_errors(&diagnostics) {
+        return (None, diagnostics);
+    }
+    let (module, _, resolve) = resolve_module(with_builtins(module));
+    diagnostics.extend(resolve);
+    if has_errors(&diagnostics) {
+        return (None, diagnostics);
+    }
+    let (types, check) = check_module(&module);
+    diagnostics.extend(check);
+    if has_errors(&diagnostics) {
+        return (None, diagnostics);
+    }
+    (Some((module, types)), diagnostics)
+}
+
+// The checked module desugared into the forms that codegen takes.
+pub fn hir(src: &str) -> (Option<(Module, Types)>, Vec<Diagnostic>) {
+    let (module, diagnostics) = check(src);
+    (
+        module.map(|(module, types)| (desugar_module(module), types)),
+        diagnostics,
+    )
+}
+
+pub fn build(src: &str, options: &Options) -> (Option<WasmASTRoot>, Vec<Diagnostic>) {
+    let (built, diagnostics) = compile(src, options);
+    (built.map(|x| x.0), diagnostics)
+}
+
+// Builds the module like `build`, along with its source map, which names the source `name`.
+pub fn build_with_map(
+    src: &str,
+    name: &str,
+    options: &Options,
+) -> (Option<(WasmASTRoot, String)>, Vec<Diagnostic>) {
+    let (built, diagnostics) = compile(src, options);
+    let file = SourceFile::new(name, src);
+    let built = built.map(|(root, spans, _)| {
+        let map = source_map(&root, &spans, &file);
+        (root, map)
+    });
+    (built, diagnostics)
+}
+
+// The module, the spans of its code and the return type of its `main`.
+#[allow(clippy::type_complexity)]
+fn compile(
+    src: &str,
+    options: &Options,
+) -> (Option<(WasmASTRoot, CodeSpans, Ty)>, Vec<Diagnostic>) {
+    let ((module, types), mut diagnostics) = match hir(src) {
+        (Some(x), diagnostics) => (x, diagnostics),
+        (None, diagnostics) => return (None, diagnostics),
+    };
+    let (root, spans, codegen) = codegen_module(&module, &types, options);
+    diagnostics.extend(codegen);
+    if has_errors(&diagnostics) {
+        return (None, diagnostics);
+    }
+    let ret = module.iter().find_map(|x| match &x.kind {
+        MemberKind::Func(def, _) if def.0 == "main" => Some(Ty::from_ret(def.3.as_ref())),
+        _ => None,
+    });
+    (Some((root, spans, ret.unwrap_or(Ty::Unit))), diagnostics)
+}
+
+// The diagnostics of every stage, each run on what the one before it recovered, so that editors
+// get the errors of a buffer that is still being written. Only the language server, which needs
+// the `json` feature, uses it.
+#[cfg_attr(not(feature = "json"), allow(dead_code))]
+pub fn diagnose(tokens: Vec<Token>) -> Vec<Diagnostic> {
+    let (module, mut diagnostics) = parse_module(tokens);
+    let (module, _, resolve) = resolve_module(with_builtins(module));
+    diagnostics.extend(resolve);
+    diagnostics.extend(check_module(&module).1);
+    diagnostics
+}
+
+// Builds `src` and calls its `main` with the builtins writing to `out`, giving what it returns
+// like `eval` does.
+pub fn run<W: Write + 'static>(
+    src: &str,
+    out: Rc<RefCell<W>>,
+) -> (Option<Result<Option<String>, Trap>>, Vec<Diagnostic>) {
+    let (built, diagnostics) = compile(src, &Options::default());
+    (built.map(|(root, _, t)| call(root, &t, out)), diagnostics)
+}
+
+// Runs `src`, statements that may end in an expression, as the body of a `main` returning its
+// value, and gives the value as a literal, or `None` for `()`. Diagnostics point into `src`.
+pub fn eval<W: Write + 'static>(
+    src: &str,
+    out: Rc<RefCell<W>>,
+) -> (Option<Result<Option<String>, Trap>>, Vec<Diagnostic>) {
+    // The type of the value is that of the last local, as a `let` is declared after its
+    // initializer.
+    let probe = ("fun main() { let _value = {\n", "\n}; }");
+    let t = match check(&format!("{}{}{}", probe.0, src, probe.1)) {
+        (Some((_, types)), _) => types.locals[0].last().cloned().unwrap_or(Ty::Unit),
+        (None, diagnostics) => return (None, unwrap(diagnostics, probe.0, src)),
+    };
+    let ret = match t {
+        Ty::Unit | Ty::Never => String::new(),
+        Ty::I32 | Ty::I64 | Ty::F32 | Ty::F64 | Ty::Bool | Ty::Char | Ty::String => {
+            format!(" -> {}", t)
+        }
+        t => {
+            let message = format!("cannot print a value of type `{}`", t);
+            let span = Span::new(0, src.chars().count());
+            return (None, vec![Diagnostic::new(message, span)]);
+        }
+    };
+    let prefix = format!("fun main(){} {{\n", ret);
+    let (root, diagnostics) = build(&format!("{}{}\n}}", prefix, src), &Options::default());
+    let diagnostics = unwrap(diagnostics, &prefix, src);
+    let root = match root {
+        Some(root) => root,
+        None => return (None, diagnostics),
+    };
+    (Some(call(root, &t, out)), diagnostics)
+}
+
+// Calls `main`, which returns a `t`, and gives its value as a literal, or `None` for `()`.
+fn call<W: Write + 'static>(
+    root: WasmASTRoot,
+    t: &Ty,
+    out: Rc<RefCell<W>>,
+) -> Result<Option<String>, Trap> {
+    let mut instance = instantiate(root, out)?;
+    let values = instance.invoke("main", &[])?;
+    Ok(show(t, &values, &instance.memory))
+}
+
+// Moves the spans of diagnostics in `src` wrapped after `prefix` back into `src`.
+fn unwrap(mut diagnostics: Vec<Diagnostic>, prefix: &str, src: &str) -> Vec<Diagnostic> {
+    let (start, len) = (prefix.chars().count(), src.chars().count());
+    let shift = |x: &mut Span| {
+        x.pos = x.pos.saturating_sub(start).min(len);
+        x.len = x.len.min(len - x.pos);
+    };
+    for x in &mut diagnostics {
+        shift(&mut x.span);
+        for label in &mut x.labels {
+            shift(&mut label.span);
+        }
+    }
+    diagnostics
+}
+
+fn show(t: &Ty, values: &[Value], memory: &[u8]) -> Option<String> {
+    let literal = match (t, values) {
+        (Ty::I32, [Value::I32(x)]) => Literal::Num(NumLiteral::I32(*x)),
+        (Ty::I64, [Value::I64(x)]) => Literal::Num(NumLiteral::I64(*x)),
+        (Ty::F32, [Value::F32(x)]) => Literal::Num(NumLiteral::F32(*x)),
+        (Ty::F64, [Value::F64(x)]) => Literal::Num(NumLiteral::F64(*x)),
+        (Ty::Bool, [Value::I32(x)]) => return Some((*x != 0).to_string()),
+        (Ty::Char, [Value::I32(x)]) => Literal::Char(char::from_u32(*x as u32)?),
+        (Ty::String, [Value::I32(ptr)]) => Literal::String(string(memory, *ptr as u32 as usize)?),
+        _ => return None,
+    };
+    Some(literal.to_string())
+}
+
+// The module instantiated with the builtins writing to `out`.
+fn instantiate<W: Write + 'static>(
+    root: WasmASTRoot,
+    out: Rc<RefCell<W>>,
+) -> Result<Instance, Trap> {
+    let mut imports = Imports::new();
+    for field in ["print", "println"] {
+        let out = out.clone();
+        imports.func(wasi::MODULE, field, move |memory, args| {
+            let s = match args {
+                [Value::I32(ptr)] => string(memory, *ptr as u32 as usize),
+                _ => None,
+            }
+            .ok_or_else(|| Trap::Host(format!("bad argument to `{}`", field)))?;
+            let mut out = out.borrow_mut();
+            let newline = if field == "println" { "\n" } else { "" };
+            write!(out, "{}{}", s, newline).map_err(|e| Trap::Host(e.to_string()))?;
+            Ok(Vec::new())
+        });
+    }
+    Instance::new(root, imports)
+}
+
+// A string is its length in bytes followed by its UTF-8 bytes.
+fn string(memory: &[u8], ptr: usize) -> Option<String> {
+    let len = u32::from_le_bytes(memory.get(ptr..ptr + 4)?.try_into().unwrap()) as usize;
+    let bytes = memory.get(ptr + 4..ptr + 4 + len)?;
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diagnostics::code::Code;
+
+    #[test]
+    fn driver_test() {
+        let (tokens, diagnostics) = lex("let x = 1;");
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(lex("let x = 99999999999;").1.len(), 1);
+
+        let (module, diagnostics) = check("fun main() -> i32 { true }");
+        assert!(module.is_none());
+        assert!(has_errors(&diagnostics));
+        let (tokens, _) = lex("fun f() -> i32 { 1 + } fun main() -> i32 { true }");
+        // The parse error does not hide the type error after it.
+        let diagnostics = diagnose(tokens);
+        assert_eq!(diagnostics[0].message, "expected expression, found `}`");
+        assert!(diagnostics.iter().any(|x| x.code == Some(Code::Mismatch)));
+
+        let src = "fun main() -> i32 { println(\"hi\"); print(\"!\"); 6 * 7 }";
+        let out = Rc::new(RefCell::new(Vec::new()));
+        assert_eq!(
+            run(src, out.clone()),
+            (Some(Ok(Some("42".to_string()))), vec![])
+        );
+        assert_eq!(*out.borrow(), b"hi\n!");
+        // With WASI, the builtins of the prelude are defined on top of `fd_write`, which is the
+        // only import.
+        let options = Options {
+            wasi: true,
+            ..Options::default()
+        };
+        let root = build(src, &options).0.unwrap();
+        let imports = root.import_section.unwrap().0;
+        assert_eq!(
+            imports
+                .iter()
+                .map(|x| (x.module.as_str(), x.field.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("wasi_snapshot_preview1", "fd_write")]
+        );
+
+        let out = Rc::new(RefCell::new(Vec::new()));
+        for (src, value) in [
+            ("let x = 20i64; x * 2i64 + 2i64", Some("42i64")),
+            ("\"a\\n\"", Some("\"a\\n\"")),
+            ("'c' == 'c'", Some("true")),
+            ("println(\"hi\")", None),
+        ] {
+            let (result, diagnostics) = eval(src, out.clone());
+            assert_eq!(diagnostics, vec![]);
+            assert_eq!(result, Some(Ok(value.map(String::from))));
+        }
+        assert_eq!(*out.borrow(), b"hi\n");
+        assert_eq!(
+            eval("1 / 0", out.clone()).0,
+            Some(Err(Trap::DivisionByZero))
+        );
+        // Spans point into the source as given.
+        let (result, diagnostics) = eval("1 +\ntrue", out.clone());
+        assert_eq!(result, None);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span, Span::new(4, 4));
+        let (_, diagnostics) = eval("[i32; 1]", out);
+        assert_eq!(
+            diagnostics[0].message,
+            "cannot print a value of type `[i32]`"
+        );
+    }
+}
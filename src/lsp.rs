@@ -0,0 +1,380 @@
+use crate::driver;
+use diagnostics::diagnostic::{Diagnostic, Severity};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use token::incremental::{relex, Edit};
+use token::token::{KeywordTable, Token};
+
+// A language server over stdio that publishes the diagnostics of the open documents whenever they
+// change. Documents are synced incrementally: each edit relexes only the tokens around it, and the
+// parser recovers from errors so that a broken buffer still gets its type errors reported.
+//
+// Positions in the protocol are lines and UTF-16 code units; everywhere else they are chars.
+
+struct Document {
+    text: String,
+    version: i64,
+    // `None` after a lexer error, until the next edit lexes the whole text again.
+    tokens: Option<Vec<Token>>,
+}
+
+// The next message, or `None` at the end of the input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let len = len.ok_or_else(|| invalid("a message without a Content-Length"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid(&e.to_string()))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// The char offset of a protocol position, clamped to the end of its line.
+fn offset(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let mut pos = 0;
+    for (i, l) in text.split('\n').enumerate() {
+        if i == line {
+            let mut units = 0;
+            for c in l.chars() {
+                if units >= character || c == '\r' {
+                    break;
+                }
+                units += c.len_utf16();
+                pos += 1;
+            }
+            return pos;
+        }
+        pos += l.chars().count() + 1;
+    }
+    text.chars().count()
+}
+
+fn position(text: &str, offset: usize) -> Value {
+    let (mut line, mut character) = (0, 0);
+    for c in text.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16();
+        }
+    }
+    json!({ "line": line, "character": character })
+}
+
+fn range(text: &str, pos: usize, end: usize) -> Value {
+    json!({ "start": position(text, pos), "end": position(text, end) })
+}
+
+fn lex(text: &str) -> (Option<Vec<Token>>, Vec<Diagnostic>) {
+    let (tokens, diagnostics) = driver::lex(text);
+    (Some(tokens).filter(|_| diagnostics.is_empty()), diagnostics)
+}
+
+impl Document {
+    fn new(text: String, version: i64) -> Document {
+        Document {
+            tokens: lex(&text).0,
+            text,
+            version,
+        }
+    }
+
+    // Applies a change from `didChange`, which replaces the whole text when it has no range.
+    fn change(&mut self, change: &Value) {
+        let new_text = change["text"].as_str().unwrap_or("").to_string();
+        let range = &change["range"];
+        if range.is_null() {
+            *self = Document::new(new_text, self.version);
+            return;
+        }
+        let start = offset(&self.text, &range["start"]);
+        let end = offset(&self.text, &range["end"]).max(start);
+        let mut chars = self.text.chars().collect::<Vec<_>>();
+        chars.splice(start..end, new_text.chars());
+        self.text = chars.into_iter().collect();
+        let edit = Edit {
+            range: start..end,
+            new_text,
+        };
+        self.tokens = match &self.tokens {
+            Some(tokens) => relex(&KeywordTable::default(), &self.text, tokens, &edit).ok(),
+            None => lex(&self.text).0,
+        };
+    }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        match &self.tokens {
+            Some(tokens) => driver::diagnose(tokens.clone()),
+            None => lex(&self.text).1,
+        }
+    }
+}
+
+fn to_lsp(uri: &str, text: &str, diagnostic: &Diagnostic) -> Value {
+    let span = diagnostic.span;
+    let message = std::iter::once(diagnostic.message.as_str())
+        .chain(diagnostic.notes.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let related = diagnostic
+        .labels
+        .iter()
+        .map(|x| {
+            json!({
+                "location": { "uri": uri, "range": range(text, x.span.pos, x.span.end()) },
+                "message": x.message,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "range": range(text, span.pos, span.end()),
+        "severity": match diagnostic.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        },
+        "code": diagnostic.code.map(|x| x.as_str()),
+        "source": "tlang",
+        "message": message,
+        "relatedInformation": related,
+    })
+}
+
+fn response(id: &Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+    // The exit code, once `exit` has been received.
+    pub exit: Option<i32>,
+}
+
+impl Server {
+    // The messages to send in reply to `message`.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let version = params["textDocument"]["version"].as_i64().unwrap_or(0);
+        match method {
+            "initialize" => vec![response(
+                &message["id"],
+                json!({
+                    "capabilities": {
+                        "textDocumentSync": { "openClose": true, "change": 2 },
+                    },
+                    "serverInfo": { "name": "tlang", "version": env!("CARGO_PKG_VERSION") },
+                }),
+            )],
+            "shutdown" => {
+                self.shut_down = true;
+                vec![response(&message["id"], Value::Null)]
+            }
+            "exit" => {
+                self.exit = Some(if self.shut_down { 0 } else { 1 });
+                Vec::new()
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                let document = Document::new(text.to_string(), version);
+                self.documents.insert(uri.to_string(), document);
+                vec![self.publish(uri)]
+            }
+            "textDocument/didChange" => match self.documents.get_mut(uri) {
+                Some(document) => {
+                    for change in params["contentChanges"].as_array().into_iter().flatten() {
+                        document.change(change);
+                    }
+                    document.version = version;
+                    vec![self.publish(uri)]
+                }
+                None => Vec::new(),
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                vec![self.publish(uri)]
+            }
+            // Requests need an answer even when they are not supported; other notifications are
+            // ignored.
+            _ if !message["id"].is_null() => vec![json!({
+                "jsonrpc": "2.0",
+                "id": message["id"],
+                "error": { "code": -32601, "message": format!("unknown method `{}`", method) },
+            })],
+            _ => Vec::new(),
+        }
+    }
+
+    fn publish(&self, uri: &str) -> Value {
+        let (diagnostics, version) = match self.documents.get(uri) {
+            Some(x) => {
+                let diagnostics = x.diagnostics();
+                let diagnostics = diagnostics
+                    .iter()
+                    .map(|d| to_lsp(uri, &x.text, d))
+                    .collect::<Vec<_>>();
+                (diagnostics, Some(x.version))
+            }
+            None => (Vec::new(), None),
+        };
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "version": version, "diagnostics": diagnostics },
+        })
+    }
+}
+
+// Serves until `exit` or the end of the input, and returns the exit code.
+pub fn serve(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<i32> {
+    let mut server = Server::default();
+    while let Some(message) = read_message(input)? {
+        for x in server.handle(&message) {
+            write_message(output, &x)?;
+        }
+        if let Some(code) = server.exit {
+            return Ok(code);
+        }
+    }
+    Ok(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    fn diagnostics(messages: Vec<Value>) -> Vec<(String, Value)> {
+        messages[0]["params"]["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| {
+                (
+                    x["message"].as_str().unwrap().to_string(),
+                    x["range"].clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lsp_test() {
+        let mut input = Vec::new();
+        for x in [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+            notification("exit", Value::Null),
+        ] {
+            write_message(&mut input, &x).unwrap();
+        }
+        let mut output = Vec::new();
+        assert_eq!(serve(&mut &input[..], &mut output).unwrap(), 0);
+        let mut output = &output[..];
+        let initialize = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(
+            initialize["result"]["capabilities"]["textDocumentSync"]["change"],
+            2
+        );
+        assert_eq!(read_message(&mut output).unwrap().unwrap()["id"], 2);
+        assert_eq!(read_message(&mut output).unwrap(), None);
+
+        let mut server = Server::default();
+        let uri = "file:///main.tl";
+        let text = "fun main() -> i32 {\n    let _s = \"あ😀\"; 1 + ;\n    true\n}";
+        let open = server.handle(&notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": uri, "version": 1, "text": text } }),
+        ));
+        let range = |line, start, end| {
+            json!({
+                "start": { "line": line, "character": start },
+                "end": { "line": line, "character": end },
+            })
+        };
+        // The parse error does not hide the type error after it. The emoji is two UTF-16 units.
+        assert_eq!(
+            diagnostics(open),
+            vec![
+                (
+                    "expected expression, found `;`".to_string(),
+                    range(1, 24, 25)
+                ),
+                (
+                    "mismatched types: expected `i32`, found `bool`".to_string(),
+                    range(2, 4, 8)
+                ),
+            ]
+        );
+
+        let change = |version, range, text| {
+            notification(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": { "uri": uri, "version": version },
+                    "contentChanges": [{ "range": range, "text": text }],
+                }),
+            )
+        };
+        let fixed = server.handle(&change(2, range(2, 4, 8), "1"));
+        assert_eq!(fixed[0]["params"]["version"], 2);
+        assert_eq!(
+            diagnostics(fixed),
+            vec![(
+                "expected expression, found `;`".to_string(),
+                range(1, 24, 25)
+            )]
+        );
+        let fixed = server.handle(&change(3, range(1, 24, 24), "2 "));
+        assert_eq!(diagnostics(fixed), vec![]);
+        assert_eq!(
+            server.documents[uri].text,
+            "fun main() -> i32 {\n    let _s = \"あ😀\"; 1 + 2 ;\n    1\n}"
+        );
+        let unlexable = server.handle(&change(4, range(1, 4, 4), "'"));
+        assert_eq!(diagnostics(unlexable).len(), 1);
+        assert!(server.documents[uri].tokens.is_none());
+
+        let closed = server.handle(&notification(
+            "textDocument/didClose",
+            json!({ "textDocument": { "uri": uri } }),
+        ));
+        assert_eq!(diagnostics(closed), vec![]);
+        let unknown = server.handle(&json!({ "jsonrpc": "2.0", "id": 3, "method": "x" }));
+        assert_eq!(unknown[0]["error"]["code"], -32601);
+    }
+}
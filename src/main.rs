@@ -0,0 +1,249 @@
+mod cli;
+mod driver;
+#[cfg(feature = "json")]
+mod lsp;
+
+use ast::codegen::Options;
+use ast::sexpr::module_to_sexpr;
+use cli::{Args, Color, Command, Emit, Format};
+use diagnostics::diagnostic::Diagnostic;
+use diagnostics::render::{emit, summary, ErrorFormat, SourceFile};
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::process;
+use std::rc::Rc;
+use token::token::Token;
+use wasm::wat;
+
+// Exit codes: 1 when the program has errors or traps, 2 when the command line is wrong.
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if cli::is_help(&args) {
+        print!("{}", cli::USAGE);
+        return;
+    }
+    let code = match cli::parse_args(&args) {
+        Ok(args) if args.command == Command::Lsp => serve(),
+        Ok(args) if args.command == Command::Eval => run(&args, "-"),
+        Ok(args) => args.inputs.iter().map(|x| run(&args, x)).max().unwrap_or(0),
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            2
+        }
+    };
+    process::exit(code);
+}
+
+#[cfg(feature = "json")]
+fn serve() -> i32 {
+    match lsp::serve(&mut io::stdin().lock(), &mut io::stdout().lock()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(not(feature = "json"))]
+fn serve() -> i32 {
+    eprintln!("error: {}", cli::NO_JSON);
+    2
+}
+
+fn read(input: &str) -> io::Result<String> {
+    if input == "-" {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src)?;
+        Ok(src)
+    } else {
+        fs::read_to_string(input)
+    }
+}
+
+fn colored(args: &Args) -> bool {
+    match args.color {
+        Color::Auto => io::stderr().is_terminal(),
+        Color::Always => true,
+        Color::Never => false,
+    }
+}
+
+fn run(args: &Args, input: &str) -> i32 {
+    let name = match &args.expr {
+        Some(_) => "<expr>",
+        None if input == "-" => "<stdin>",
+        None => input,
+    };
+    let src = match args.expr.clone().map_or_else(|| read(input), Ok) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("error: {}: {}", name, e);
+            return 1;
+        }
+    };
+    let report = |diagnostics: &[Diagnostic]| {
+        if !diagnostics.is_empty() {
+            let file = SourceFile::new(name, &src);
+            let color = colored(args);
+            eprintln!(
+                "{}",
+                emit(diagnostics, &file, args.error_format, color).trim_end()
+            );
+            if args.error_format == ErrorFormat::Human {
+                if let Some(summary) = summary(diagnostics, color) {
+                    eprintln!("\n{}", summary);
+                }
+            }
+        }
+        diagnostics.iter().any(Diagnostic::is_error)
+    };
+    match args.command {
+        Command::Lex => {
+            let (tokens, diagnostics) = driver::lex(&src);
+            let printed = match args.format {
+                Format::Text => {
+                    print!("{}", print_tokens(&tokens));
+                    0
+                }
+                Format::Json => print_json(&tokens),
+            };
+            printed.max(report(&diagnostics) as i32)
+        }
+        Command::Parse => {
+            let (module, diagnostics) = driver::parse(&src);
+            let printed = match args.format {
+                Format::Text => {
+                    print!("{}", module_to_sexpr(&module));
+                    0
+                }
+                Format::Json => print_json(&module),
+            };
+            printed.max(report(&diagnostics) as i32)
+        }
+        Command::Check => report(&driver::check(&src).1) as i32,
+        Command::Lsp => unreachable!("`lsp` takes no files"),
+        Command::Eval | Command::Run => {
+            let out = Rc::new(RefCell::new(io::stdout()));
+            let (value, diagnostics) = match args.command {
+                Command::Eval => driver::eval(&src, out),
+                _ => driver::run(&src, out),
+            };
+            report(&diagnostics);
+            match value {
+                Some(Ok(value)) => {
+                    if let Some(x) = value {
+                        println!("{}", x);
+                    }
+                    0
+                }
+                Some(Err(e)) => {
+                    eprintln!("error: {}", e);
+                    1
+                }
+                None => 1,
+            }
+        }
+        Command::Fmt => {
+            let out = match driver::format(&src) {
+                Ok(out) => out,
+                Err(diagnostics) => {
+                    report(&diagnostics);
+                    return 1;
+                }
+            };
+            if args.check {
+                if out == src {
+                    return 0;
+                }
+                println!("{}", name);
+                return 1;
+            }
+            if input == "-" {
+                print!("{}", out);
+            } else if out != src {
+                if let Err(e) = fs::write(input, out) {
+                    eprintln!("error: {}: {}", input, e);
+                    return 1;
+                }
+            }
+            0
+        }
+        Command::Build => {
+            let mut map = None;
+            let (out, diagnostics) = match args.emit {
+                Emit::Tokens => {
+                    let (tokens, diagnostics) = driver::lex(&src);
+                    (Some(print_tokens(&tokens).into_bytes()), diagnostics)
+                }
+                Emit::Ast => {
+                    let (module, diagnostics) = driver::parse(&src);
+                    (Some(module_to_sexpr(&module).into_bytes()), diagnostics)
+                }
+                Emit::Hir => {
+                    let (module, diagnostics) = driver::hir(&src);
+                    let out = module.map(|(module, _)| module_to_sexpr(&module).into_bytes());
+                    (out, diagnostics)
+                }
+                Emit::Wat | Emit::Wasm => {
+                    let options = Options {
+                        source_map: cli::source_map_url(args, input),
+                        ..args.options.clone()
+                    };
+                    let (built, diagnostics) = driver::build_with_map(&src, name, &options);
+                    let out = built.map(|(root, source_map)| {
+                        map = Some(source_map);
+                        match args.emit {
+                            Emit::Wat => wat::print_module(&root).into_bytes(),
+                            _ => root.to_bytes(),
+                        }
+                    });
+                    (out, diagnostics)
+                }
+            };
+            if report(&diagnostics) {
+                return 1;
+            }
+            let out = match out {
+                Some(out) => out,
+                None => return 1,
+            };
+            let written = match cli::output_path(args, input) {
+                Some(output) => fs::write(&output, out).map_err(|e| (output, e)),
+                None => io::stdout()
+                    .write_all(&out)
+                    .map_err(|e| ("<stdout>".to_string(), e)),
+            }
+            .and_then(|_| match (&args.source_map, map) {
+                (Some(path), Some(map)) => fs::write(path, map).map_err(|e| (path.clone(), e)),
+                _ => Ok(()),
+            });
+            if let Err((output, e)) = written {
+                eprintln!("error: {}: {}", output, e);
+                return 1;
+            }
+            0
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+fn print_json<T: serde::Serialize>(x: &T) -> i32 {
+    println!("{}", serde_json::to_string(x).unwrap());
+    0
+}
+
+#[cfg(not(feature = "json"))]
+fn print_json<T>(_: &T) -> i32 {
+    eprintln!("error: {}", cli::NO_JSON);
+    2
+}
+
+fn print_tokens(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|x| format!("{}:{} {}\n", x.line, x.col, x))
+        .collect()
+}
@@ -0,0 +1,113 @@
+use crate::parser::{one_token, skip};
+use crate::token::{KeywordTable, Token};
+use parser::parser::{Parser, ParserResult};
+use parser::stream::Stream;
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub new_text: String,
+}
+
+// `source` is the buffer after the edit has been applied and `edit.range` is in the
+// coordinates of the old buffer that produced `old_tokens`.
+pub fn relex(
+    table: &KeywordTable,
+    source: &str,
+    old_tokens: &[Token],
+    edit: &Edit,
+) -> ParserResult<Vec<Token>, char> {
+    let new_len = edit.new_text.chars().count();
+    let edit_end = edit.range.start + new_len;
+    let delta = new_len as isize - edit.range.len() as isize;
+
+    // Restart one token before the edit so that tokens merging across the edit are relexed too.
+    let start = old_tokens
+        .iter()
+        .position(|x| x.pos + x.len >= edit.range.start)
+        .unwrap_or(old_tokens.len())
+        .saturating_sub(1);
+    let restart = if start == 0 { 0 } else { old_tokens[start].pos };
+
+    let mut st = Stream::new(source.chars().collect());
+    st.set_pos(restart);
+    let mut res = old_tokens[..start].to_vec();
+    let mut old = start;
+    loop {
+        skip().many().parse(&mut st)?;
+        if st.eof() {
+            return Ok(res);
+        }
+        let token = one_token(table).parse(&mut st)?;
+        if token.pos >= edit_end {
+            let old_pos = (token.pos as isize - delta) as usize;
+            while old < old_tokens.len() && old_tokens[old].pos < old_pos {
+                old += 1;
+            }
+            if let Some(x) = old_tokens.get(old) {
+                if x.pos == old_pos && x.kind == token.kind && x.len == token.len {
+                    res.extend(shift(&old_tokens[old..], &token, delta));
+                    return Ok(res);
+                }
+            }
+        }
+        res.push(token);
+    }
+}
+
+fn shift<'a>(
+    tokens: &'a [Token],
+    anchor: &'a Token,
+    delta: isize,
+) -> impl Iterator<Item = Token> + 'a {
+    let base = &tokens[0];
+    let line_delta = anchor.line as isize - base.line as isize;
+    let col_delta = anchor.col as isize - base.col as isize;
+    tokens.iter().map(move |x| Token {
+        pos: (x.pos as isize + delta) as usize,
+        line: (x.line as isize + line_delta) as usize,
+        col: if x.line == base.line {
+            (x.col as isize + col_delta) as usize
+        } else {
+            x.col
+        },
+        ..x.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer;
+
+    fn lex(s: &str) -> Vec<Token> {
+        lexer(&KeywordTable::default())
+            .parse(&mut Stream::new(s.chars().collect()))
+            .unwrap()
+    }
+
+    fn helper(old: &str, range: Range<usize>, new_text: &str) {
+        let mut new = old.chars().collect::<Vec<_>>();
+        new.splice(range.clone(), new_text.chars());
+        let new = new.into_iter().collect::<String>();
+        let edit = Edit {
+            range,
+            new_text: new_text.to_string(),
+        };
+        assert_eq!(
+            Ok(lex(&new)),
+            relex(&KeywordTable::default(), &new, &lex(old), &edit)
+        );
+    }
+
+    #[test]
+    fn relex_test() {
+        helper("let a = 1;\nlet b = 2;", 4..5, "abc");
+        helper("let a = 1;\nlet b = 2;", 9..9, "\n  ");
+        helper("let a = 1;\nlet b = 2;", 0..4, "/* */ x");
+        helper("let ab = 1; b", 5..6, "");
+        helper("a /* x */ b", 2..9, "+");
+        helper("", 0..0, "x + y");
+    }
+}
@@ -1,2 +1,4 @@
-pub mod token;
+pub mod incremental;
 pub mod parser;
+pub mod reader;
+pub mod token;
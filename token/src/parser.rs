@@ -1,11 +1,12 @@
-
-use crate::token::{Keyword, Kind, Literal, NumLiteral, Symbol, Token};
-use parser::stream::Stream;
+use crate::token::{
+    KeywordTable, Kind, Literal, NumLiteral, Span, Symbol, Token, Trivia, TriviaKind,
+};
+use diagnostics::code::Code;
+use diagnostics::diagnostic::Diagnostic;
 use parser::{
     or,
     parser::{
-        any_one, eof, expect, fail, parser_func, token, tokens, val, Either, Fail, Parser,
-        ParserError, ParserResult, Val,
+        any_one, eof, expect, fail, parser_func, token, tokens, val, Either, Parser, ParserError,
     },
 };
 pub fn string(s: &str) -> impl Parser<Input = char, Output = String> {
@@ -13,19 +14,24 @@ pub fn string(s: &str) -> impl Parser<Input = char, Output = String> {
 }
 
 pub fn space() -> impl Parser<Input = char, Output = ()> {
-    or!(token(' '), token('\n'), token('\t')).with(val(()))
+    expect(|&x: &char| x.is_whitespace()).with(val(()))
+}
+
+pub fn newline() -> impl Parser<Input = char, Output = ()> {
+    or!(string("\r\n").attempt(), string("\n"), string("\r")).with(val(()))
 }
 
 pub fn line_comment() -> impl Parser<Input = char, Output = ()> {
     string("//")
-        .with(expect(|&x| x != '\n').many())
-        .with(token('\n').optional())
+        .with(expect(|&x| x != '\n' && x != '\r').many())
+        .with(newline().optional())
         .with(val(()))
 }
 
 pub fn block_comment() -> impl Parser<Input = char, Output = ()> {
     parser_func(|st| {
         string("/*")
+            .attempt()
             .with(
                 parser_func(|st| match (st.peak(), st.peak_index(1)) {
                     (Some('/'), Some('*')) => block_comment().parse(st),
@@ -48,8 +54,22 @@ pub fn skip() -> impl Parser<Input = char, Output = ()> {
     space().or(comment())
 }
 
+pub fn trivia() -> impl Parser<Input = char, Output = Trivia> {
+    parser_func(|st| {
+        let pos = st.pos();
+        let kind = or!(
+            space().many1().val(TriviaKind::Whitespace),
+            line_comment().val(TriviaKind::LineComment).attempt(),
+            block_comment().val(TriviaKind::BlockComment)
+        )
+        .parse(st)?;
+        let len = st.pos() - pos;
+        Ok(Trivia { kind, pos, len })
+    })
+}
+
 pub fn ident_str() -> impl Parser<Input = char, Output = String> {
-    expect::<char, _>(|&c| c.is_ascii_alphabetic())
+    expect::<char, _>(|&c| c.is_ascii_alphabetic() || c == '_')
         .and(expect::<char, _>(|&c| c.is_ascii_alphanumeric() || c == '_').many())
         .map(|(x, mut xs)| {
             xs.insert(0, x);
@@ -61,46 +81,54 @@ pub fn num_literal() -> impl Parser<Input = char, Output = NumLiteral> {
     fn parse<T: std::str::FromStr, F: Fn(T) -> NumLiteral>(
         s: String,
         f: F,
-    ) -> Either<Val<NumLiteral, char>, Fail<char, NumLiteral>> {
-        s.parse::<T>()
-            .map(|x| Either::Right(val(f(x))))
-            .unwrap_or(Either::Left(fail()))
+        msg: &str,
+    ) -> Result<NumLiteral, String> {
+        s.parse::<T>().map(f).map_err(|_| msg.to_string())
     }
 
-    let num = expect::<char, _>(|&c| c.is_ascii_digit())
-        .many1()
-        .map(|x| x.into_iter().collect::<String>());
-    num.clone()
-        .and(token('.').and(num).optional())
-        .and(ident_str().optional())
-        .then(|((s1, dot_num), suffix)| {
-            let suffix = suffix.as_ref().map(|x| x.as_str());
-            if let Some((_, s2)) = dot_num {
-                let s = format!("{}.{}", s1, s2);
-                match suffix {
-                    None | Some("f64") => parse::<_, _>(s, NumLiteral::F64),
-                    Some("f32") => parse::<_, _>(s, NumLiteral::F32),
-                    _ => Either::Left(fail()),
-                }
-            } else {
-                match suffix {
-                    None | Some("i32") => parse::<_, _>(s1, NumLiteral::I32),
-                    Some("i64") => parse::<_, _>(s1, NumLiteral::I64),
-                    Some("f32") => parse::<_, _>(s1, NumLiteral::F32),
-                    Some("f64") => parse::<_, _>(s1, NumLiteral::F64),
-                    _ => Either::Left(fail()),
+    parser_func(|st| {
+        let pos = st.pos();
+        let num = expect::<char, _>(|&c| c.is_ascii_digit())
+            .many1()
+            .map(|x| x.into_iter().collect::<String>());
+        let ((s1, dot_num), suffix) = num
+            .clone()
+            .and(token('.').and(num).attempt().optional())
+            .and(ident_str().optional())
+            .parse(st)?;
+        let res = if let Some((_, s2)) = dot_num {
+            let s = format!("{}.{}", s1, s2);
+            match suffix.as_deref() {
+                None | Some("f64") => parse::<_, _>(s, NumLiteral::F64, "invalid f64 literal"),
+                Some("f32") => parse::<_, _>(s, NumLiteral::F32, "invalid f32 literal"),
+                Some(x) => Err(format!("invalid suffix `{}` for float literal", x)),
+            }
+        } else {
+            match suffix.as_deref() {
+                None | Some("i32") => parse::<_, _>(
+                    s1,
+                    NumLiteral::I32,
+                    "integer literal out of range for i32, consider i64 suffix",
+                ),
+                Some("i64") => {
+                    parse::<_, _>(s1, NumLiteral::I64, "integer literal out of range for i64")
                 }
+                Some("f32") => parse::<_, _>(s1, NumLiteral::F32, "invalid f32 literal"),
+                Some("f64") => parse::<_, _>(s1, NumLiteral::F64, "invalid f64 literal"),
+                Some(x) => Err(format!("invalid suffix `{}` for number literal", x)),
             }
-        })
+        };
+        res.map_err(|msg| ParserError::with_message(pos, st.pos() - pos, msg))
+    })
 }
 
 pub fn hex_char(len: usize) -> impl Parser<Input = char, Output = char> {
-    expect::<char, _>(|&x| x.is_ascii_digit() || ('a' <= x && x <= 'f') || ('A' <= x && x <= 'F'))
+    expect::<char, _>(|&x| x.is_ascii_hexdigit())
         .map(|x| x.to_ascii_lowercase())
         .many_n(len)
         .map(|x| {
             u32::from_str_radix(&x.into_iter().collect::<String>(), 16)
-                .map(|x| std::char::from_u32(x))
+                .map(std::char::from_u32)
                 .unwrap()
         })
         .then(|x| match x {
@@ -109,27 +137,62 @@ pub fn hex_char(len: usize) -> impl Parser<Input = char, Output = char> {
         })
 }
 
-pub fn lexer() -> impl Parser<Input = char, Output = Vec<Token>> {
+pub fn lexer_diagnostic(e: &ParserError<char>) -> Diagnostic {
+    let (pos, len) = e.span();
+    let message = match (e.message(), e.unexpected()) {
+        (Some(message), _) => message.to_string(),
+        (None, Some(c)) => format!("unexpected character `{}`", c.escape_debug()),
+        (None, None) => "unexpected end of file".to_string(),
+    };
+    Diagnostic::new(message, Span::new(pos, len)).with_code(Code::UnexpectedCharacter)
+}
+
+pub fn lexer(table: &KeywordTable) -> impl Parser<Input = char, Output = Vec<Token>> + '_ {
     skip()
         .map(|_| None)
-        .or(one_token().map(Some))
+        .or(one_token(table).map(Some))
         .many()
-        .map(|x| x.into_iter().filter_map(|x| x).collect::<Vec<_>>())
+        .map(|x| x.into_iter().flatten().collect::<Vec<_>>())
         .skip(eof())
 }
 
-pub fn one_token() -> impl Parser<Input = char, Output = Token> {
-    parser_func(|st| {
+pub fn lexer_with_trivia(
+    table: &KeywordTable,
+) -> impl Parser<Input = char, Output = (Vec<Token>, Vec<Trivia>)> + '_ {
+    trivia()
+        .map(|x| (None, Some(x)))
+        .or(one_token(table).map(|x| (Some(x), None)))
+        .many()
+        .map(|x| {
+            let (tokens, trivia): (Vec<_>, Vec<_>) = x.into_iter().unzip();
+            (
+                tokens.into_iter().flatten().collect(),
+                trivia.into_iter().flatten().collect(),
+            )
+        })
+        .skip(eof())
+}
+
+pub fn one_token(table: &KeywordTable) -> impl Parser<Input = char, Output = Token> + '_ {
+    parser_func(move |st| {
         let pos = st.pos();
-        let kind = kind().parse(st)?;
+        let (line, col) = st.line_col();
+        let kind = kind(table).parse(st)?;
         let len = st.pos() - pos;
-        Ok(Token { pos, kind, len })
+        Ok(Token {
+            pos,
+            kind,
+            len,
+            line,
+            col,
+        })
     })
 }
 
-pub fn kind() -> impl Parser<Input = char, Output = Kind> {
+pub fn kind(table: &KeywordTable) -> impl Parser<Input = char, Output = Kind> + '_ {
     or!(
-        ident_or_keyword(),
+        byte_literal().map(Kind::Literal),
+        ident_or_keyword(table),
         symbol().map(Kind::Symbol),
         literal().map(Kind::Literal)
     )
@@ -159,6 +222,36 @@ pub fn literal_char(lit: char) -> impl Parser<Input = char, Output = char> {
     )
 }
 
+// Other chars than ASCII only as `\x` escapes.
+pub fn byte_char(lit: char) -> impl Parser<Input = char, Output = u8> {
+    or!(
+        token('\\').with(or!(
+            token('t').val(b'\t'),
+            token('n').val(b'\n'),
+            token('r').val(b'\r'),
+            token('\\').val(b'\\'),
+            token(lit).val(lit as u8),
+            token('x').with(hex_char(2)).map(|x| x as u8)
+        )),
+        expect(move |&x: &char| x != lit && x.is_ascii()).map(|x| x as u8)
+    )
+}
+
+pub fn byte_literal() -> impl Parser<Input = char, Output = Literal> {
+    or!(
+        string("b'")
+            .attempt()
+            .with(byte_char('\''))
+            .skip(token('\''))
+            .map(Literal::Byte),
+        string("b\"")
+            .attempt()
+            .with(byte_char('\"').many())
+            .skip(token('\"'))
+            .map(Literal::ByteString)
+    )
+}
+
 pub fn char_literal() -> impl Parser<Input = char, Output = char> {
     token('\'').with(literal_char('\'')).skip(token('\''))
 }
@@ -173,34 +266,20 @@ pub fn string_literal() -> impl Parser<Input = char, Output = String> {
         .skip(token('\"'))
 }
 
-pub fn ident_or_keyword() -> impl Parser<Input = char, Output = Kind> {
-    parser_func(|st| {
+pub fn ident_or_keyword(table: &KeywordTable) -> impl Parser<Input = char, Output = Kind> + '_ {
+    parser_func(move |st| {
         let s = ident_str().parse(st)?;
-        Ok(match s.as_ref() {
-            "i32" => Kind::Keyword(Keyword::I32),
-            "i64" => Kind::Keyword(Keyword::I64),
-            "F32" => Kind::Keyword(Keyword::F32),
-            "F64" => Kind::Keyword(Keyword::F64),
-            "string" => Kind::Keyword(Keyword::String),
-            "bool" => Kind::Keyword(Keyword::Bool),
-            "char" => Kind::Keyword(Keyword::Char),
-            "true" => Kind::Keyword(Keyword::True),
-            "false" => Kind::Keyword(Keyword::False),
-            "let" => Kind::Keyword(Keyword::Let),
-            "if" => Kind::Keyword(Keyword::If),
-            "while" => Kind::Keyword(Keyword::While),
-            "return" => Kind::Keyword(Keyword::Return),
-            "struct" => Kind::Keyword(Keyword::Struct),
-            "fun" => Kind::Keyword(Keyword::Fun),
-            "extern" => Kind::Keyword(Keyword::Extern),
-            "for" => Kind::Keyword(Keyword::For),
-            s => Kind::Ident(s.to_string()),
+        Ok(match table.get(&s) {
+            Some(keyword) => Kind::Keyword(keyword.clone()),
+            None => Kind::Ident(s),
         })
     })
 }
 
 pub fn symbol() -> impl Parser<Input = char, Output = Symbol> {
     or!(
+        string("..=").with(val(Symbol::DotDotEq)).attempt(),
+        string("..").with(val(Symbol::DotDot)).attempt(),
         token('.').with(val(Symbol::Dot)),
         token(',').with(val(Symbol::Comma)),
         token(':').with(val(Symbol::Colon)),
@@ -213,11 +292,17 @@ pub fn symbol() -> impl Parser<Input = char, Output = Symbol> {
         token('}').with(val(Symbol::CloseBrace)),
         string("!=").with(val(Symbol::Ne)).attempt(),
         token('!').with(val(Symbol::Not)),
+        string("+=").with(val(Symbol::AddAssign)).attempt(),
         token('+').with(val(Symbol::Add)),
+        string("->").with(val(Symbol::Arrow)).attempt(),
+        string("-=").with(val(Symbol::SubAssign)).attempt(),
         token('-').with(val(Symbol::Sub)),
         string("**").with(val(Symbol::Pow)).attempt(),
+        string("*=").with(val(Symbol::MulAssign)).attempt(),
         token('*').with(val(Symbol::Mul)),
+        string("/=").with(val(Symbol::DivAssign)).attempt(),
         token('/').with(val(Symbol::Div)),
+        string("%=").with(val(Symbol::ModAssign)).attempt(),
         token('%').with(val(Symbol::Mod)),
         string("&&").with(val(Symbol::And)).attempt(),
         token('&').with(val(Symbol::BitAnd)),
@@ -229,6 +314,230 @@ pub fn symbol() -> impl Parser<Input = char, Output = Symbol> {
         string(">=").with(val(Symbol::Gte)).attempt(),
         token('>').with(val(Symbol::Gt)),
         string("==").with(val(Symbol::Eq)).attempt(),
+        string("=>").with(val(Symbol::FatArrow)).attempt(),
         token('=').with(val(Symbol::Assign))
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Keyword;
+    use parser::stream::Stream;
+
+    fn kinds(s: &str) -> Vec<Kind> {
+        lexer(&KeywordTable::default())
+            .parse(&mut Stream::new(s.chars().collect()))
+            .unwrap()
+            .into_iter()
+            .map(|x| x.kind)
+            .collect()
+    }
+
+    #[test]
+    fn line_col_test() {
+        let tokens = lexer(&KeywordTable::default())
+            .parse(&mut Stream::new("a\n  b c\n\nd".chars().collect()))
+            .unwrap();
+        assert_eq!(
+            tokens.iter().map(|x| (x.line, x.col)).collect::<Vec<_>>(),
+            vec![(1, 1), (2, 3), (2, 5), (4, 1)]
+        );
+    }
+
+    #[test]
+    fn trivia_test() {
+        let (tokens, trivia) = lexer_with_trivia(&KeywordTable::default())
+            .parse(&mut Stream::new("a // x\n/* y */b".chars().collect()))
+            .unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(
+            trivia,
+            vec![
+                Trivia {
+                    kind: TriviaKind::Whitespace,
+                    pos: 1,
+                    len: 1
+                },
+                Trivia {
+                    kind: TriviaKind::LineComment,
+                    pos: 2,
+                    len: 5
+                },
+                Trivia {
+                    kind: TriviaKind::BlockComment,
+                    pos: 7,
+                    len: 7
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn byte_literal_test() {
+        assert_eq!(
+            kinds("b'a' b\"\\x00b\" b"),
+            vec![
+                Kind::Literal(Literal::Byte(b'a')),
+                Kind::Literal(Literal::ByteString(vec![0, b'b'])),
+                Kind::Ident("b".to_string()),
+            ]
+        );
+        assert_eq!(kinds("b'\\xff'"), vec![Kind::Literal(Literal::Byte(0xff))]);
+        for (s, pos, c) in [
+            ("b'é'", 2, 'é'),
+            ("b\"aé\"", 3, 'é'),
+            ("b'\\u00e9'", 3, 'u'),
+        ] {
+            let e = lexer(&KeywordTable::default())
+                .parse(&mut Stream::new(s.chars().collect()))
+                .unwrap_err();
+            let diagnostic = lexer_diagnostic(&e);
+            assert_eq!(diagnostic.span.pos, pos, "{}", s);
+            assert_eq!(diagnostic.message, format!("unexpected character `{}`", c));
+        }
+    }
+
+    #[test]
+    fn skip_test() {
+        assert_eq!(
+            kinds("a // x\r\n/ b\r\u{3000}/* y */"),
+            vec![
+                Kind::Ident("a".to_string()),
+                Kind::Symbol(Symbol::Div),
+                Kind::Ident("b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn display_test() {
+        let s = "let x = 'a' + b'\\n' \"\\\"\\t\" 1.0 2i64 0.5f32 -> ..=";
+        let tokens = lexer(&KeywordTable::default())
+            .parse(&mut Stream::new(s.chars().collect()))
+            .unwrap();
+        assert_eq!(
+            s,
+            tokens
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    }
+
+    #[test]
+    fn num_literal_test() {
+        assert_eq!(
+            lexer(&KeywordTable::default())
+                .parse(&mut Stream::new("x = 99999999999;".chars().collect())),
+            Err(ParserError::with_message(
+                4,
+                11,
+                "integer literal out of range for i32, consider i64 suffix".to_string()
+            ))
+        );
+        assert_eq!(
+            kinds("99999999999i64 1f32"),
+            vec![
+                Kind::Literal(Literal::Num(NumLiteral::I64(99_999_999_999))),
+                Kind::Literal(Literal::Num(NumLiteral::F32(1.0))),
+            ]
+        );
+
+        // Floats are equal when their bits are.
+        let nan = NumLiteral::F64(f64::NAN);
+        assert_eq!(nan, nan.clone());
+        assert_ne!(NumLiteral::F64(0.0), NumLiteral::F64(-0.0));
+        assert_ne!(NumLiteral::I32(1), NumLiteral::I64(1));
+        let kinds = kinds("1.5 1.5 1.5f32 x x")
+            .into_iter()
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(kinds.len(), 3);
+    }
+
+    #[test]
+    fn range_test() {
+        assert_eq!(
+            kinds("0..10 a..=b c.d"),
+            vec![
+                Kind::Literal(Literal::Num(NumLiteral::I32(0))),
+                Kind::Symbol(Symbol::DotDot),
+                Kind::Literal(Literal::Num(NumLiteral::I32(10))),
+                Kind::Ident("a".to_string()),
+                Kind::Symbol(Symbol::DotDotEq),
+                Kind::Ident("b".to_string()),
+                Kind::Ident("c".to_string()),
+                Kind::Symbol(Symbol::Dot),
+                Kind::Ident("d".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn ident_test() {
+        assert_eq!(
+            kinds("_ _a a_1"),
+            vec![
+                Kind::Ident("_".to_string()),
+                Kind::Ident("_a".to_string()),
+                Kind::Ident("a_1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn compound_assign_test() {
+        assert_eq!(
+            kinds("a += 1 -= *= /= %= + = - ** *"),
+            vec![
+                Kind::Ident("a".to_string()),
+                Kind::Symbol(Symbol::AddAssign),
+                Kind::Literal(Literal::Num(NumLiteral::I32(1))),
+                Kind::Symbol(Symbol::SubAssign),
+                Kind::Symbol(Symbol::MulAssign),
+                Kind::Symbol(Symbol::DivAssign),
+                Kind::Symbol(Symbol::ModAssign),
+                Kind::Symbol(Symbol::Add),
+                Kind::Symbol(Symbol::Assign),
+                Kind::Symbol(Symbol::Sub),
+                Kind::Symbol(Symbol::Pow),
+                Kind::Symbol(Symbol::Mul),
+            ]
+        );
+    }
+
+    #[test]
+    fn arrow_test() {
+        assert_eq!(
+            kinds("-> => - > = == >= -=>"),
+            vec![
+                Kind::Symbol(Symbol::Arrow),
+                Kind::Symbol(Symbol::FatArrow),
+                Kind::Symbol(Symbol::Sub),
+                Kind::Symbol(Symbol::Gt),
+                Kind::Symbol(Symbol::Assign),
+                Kind::Symbol(Symbol::Eq),
+                Kind::Symbol(Symbol::Gte),
+                Kind::Symbol(Symbol::SubAssign),
+                Kind::Symbol(Symbol::Gt),
+            ]
+        );
+    }
+
+    #[test]
+    fn keyword_test() {
+        assert_eq!(
+            kinds("else break continue elsewhere breaks continue_ _else"),
+            vec![
+                Kind::Keyword(Keyword::Else),
+                Kind::Keyword(Keyword::Break),
+                Kind::Keyword(Keyword::Continue),
+                Kind::Ident("elsewhere".to_string()),
+                Kind::Ident("breaks".to_string()),
+                Kind::Ident("continue_".to_string()),
+                Kind::Ident("_else".to_string()),
+            ]
+        );
+    }
+}
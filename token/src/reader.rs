@@ -0,0 +1,246 @@
+use crate::parser::{lexer_diagnostic, one_token, skip};
+use crate::token::{KeywordTable, Span, Token};
+use diagnostics::code::Code;
+use diagnostics::diagnostic::Diagnostic;
+use parser::parser::{Parser, ParserError};
+use parser::stream::Stream;
+use std::error;
+use std::fmt;
+use std::io::{self, Read};
+
+// Tokens and errors are only reported once this many chars follow them in the buffer (or the
+// input has ended), which covers the longest lookahead of the lexer (e.g. `..=` and `1.5`).
+const LOOKAHEAD: usize = 4;
+const CHUNK_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    Parser(ParserError<char>),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "{}", e),
+            ReadError::Parser(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl ReadError {
+    // I/O errors have no location in the source.
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            ReadError::Io(e) => Diagnostic::new(e.to_string(), Span::default()).with_code(Code::Io),
+            ReadError::Parser(e) => lexer_diagnostic(e),
+        }
+    }
+}
+
+impl error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ReadError::Io(e) => Some(e),
+            ReadError::Parser(e) => Some(e),
+        }
+    }
+}
+
+pub struct ReaderLexer<'a, R: Read> {
+    table: &'a KeywordTable,
+    reader: R,
+    bytes: Vec<u8>,
+    // The chars read so far from the start of the token being lexed, which is where the
+    // source position `offset`, line `line` and column `col` are.
+    stream: Stream<char>,
+    offset: usize,
+    line: usize,
+    col: usize,
+    eof: bool,
+    done: bool,
+}
+
+pub fn lex_reader<R: Read>(table: &KeywordTable, reader: R) -> ReaderLexer<'_, R> {
+    ReaderLexer {
+        table,
+        reader,
+        bytes: Vec::new(),
+        stream: Stream::new(Vec::new()),
+        offset: 0,
+        line: 1,
+        col: 1,
+        eof: false,
+        done: false,
+    }
+}
+
+impl<'a, R: Read> ReaderLexer<'a, R> {
+    fn fill(&mut self) -> io::Result<()> {
+        let mut buf = [0; CHUNK_SIZE];
+        let n = self.reader.read(&mut buf)?;
+        if n == 0 {
+            self.eof = true;
+            if !self.bytes.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream did not contain valid UTF-8",
+                ));
+            }
+            return Ok(());
+        }
+        self.bytes.extend_from_slice(&buf[..n]);
+        let valid = match std::str::from_utf8(&self.bytes) {
+            Ok(s) => s.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let (line, col) = self.stream.line_col();
+        self.offset += self.stream.pos();
+        if line == 1 {
+            self.col += col - 1;
+        } else {
+            self.line += line - 1;
+            self.col = col;
+        }
+        let s = std::str::from_utf8(&self.bytes[..valid]).unwrap();
+        self.stream.refill(s.chars());
+        self.bytes.drain(..valid);
+        Ok(())
+    }
+
+    // Whether more input could not change how the lexer got to `pos` of the stream.
+    fn complete(&self, pos: usize) -> bool {
+        self.eof || pos + LOOKAHEAD < self.stream.pos() + self.stream.remaining()
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, ReadError> {
+        loop {
+            let start = self.stream.pos();
+            let (table, st) = (self.table, &mut self.stream);
+            let res = skip().many().parse(st).and_then(|_| {
+                if st.eof() {
+                    Ok(None)
+                } else {
+                    one_token(table).parse(st).map(Some)
+                }
+            });
+            match res {
+                Ok(token) if self.complete(self.stream.pos()) => {
+                    return Ok(token.map(|x| self.absolute(x)));
+                }
+                Err(e) if self.complete(e.span().0 + e.span().1) => {
+                    let pos = e.pos() + self.offset;
+                    return Err(ReadError::Parser(match e.message() {
+                        Some(message) => {
+                            ParserError::with_message(pos, e.span().1, message.to_string())
+                        }
+                        None => {
+                            ParserError::new(pos, e.unexpected().cloned(), e.expecting().clone())
+                        }
+                    }));
+                }
+                _ => {
+                    self.stream.set_pos(start);
+                    self.fill().map_err(ReadError::Io)?;
+                }
+            }
+        }
+    }
+
+    fn absolute(&self, token: Token) -> Token {
+        Token {
+            pos: token.pos + self.offset,
+            line: token.line + self.line - 1,
+            col: if token.line == 1 {
+                token.col + self.col - 1
+            } else {
+                token.col
+            },
+            ..token
+        }
+    }
+}
+
+impl<'a, R: Read> Iterator for ReaderLexer<'a, R> {
+    type Item = Result<Token, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_token() {
+            Ok(Some(token)) => Some(Ok(token)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer;
+
+    struct OneByte<'a>(&'a [u8]);
+
+    impl<'a> Read for OneByte<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((x, xs)) => {
+                    buf[0] = *x;
+                    self.0 = xs;
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+    }
+
+    // Fails on any read after the first.
+    struct OneChunk<'a>(Option<&'a [u8]>);
+
+    impl<'a> Read for OneChunk<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let x = self
+                .0
+                .take()
+                .ok_or_else(|| io::Error::other("read past the first chunk"))?;
+            buf[..x.len()].copy_from_slice(x);
+            Ok(x.len())
+        }
+    }
+
+    #[test]
+    fn lex_reader_test() {
+        let table = KeywordTable::default();
+        let s = "let x = 1.5;\n/* ｺﾒﾝﾄ */ x ..= \"あいう\" // end\n  0..10";
+        let expected = lexer(&table)
+            .parse(&mut Stream::new(s.chars().collect()))
+            .unwrap();
+        let tokens = lex_reader(&table, OneByte(s.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(expected, tokens);
+
+        let e = lex_reader(&table, OneByte("let x = #;".as_bytes()))
+            .find_map(Result::err)
+            .unwrap();
+        let diagnostic = e.to_diagnostic();
+        assert_eq!(diagnostic.message, "unexpected character `#`");
+        assert_eq!(diagnostic.span.pos, 8);
+
+        // The error is known without reading past the first chunk.
+        let mut tokens = lex_reader(&table, OneChunk(Some("x\n  # let y = 1;".as_bytes())));
+        assert_eq!(tokens.next().unwrap().unwrap().pos, 0);
+        let diagnostic = tokens.next().unwrap().unwrap_err().to_diagnostic();
+        assert_eq!(diagnostic.message, "unexpected character `#`");
+        assert_eq!(diagnostic.span.pos, 4);
+    }
+}
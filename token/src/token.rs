@@ -1,11 +1,42 @@
-#[derive(Clone, Debug, PartialEq)]
+pub use diagnostics::span::Span;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     pub kind: Kind,
     pub pos: usize,
     pub len: usize,
+    pub line: usize,
+    pub col: usize,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl Token {
+    pub fn span(&self) -> Span {
+        Span::new(self.pos, self.len)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub pos: usize,
+    pub len: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TriviaKind {
+    Whitespace,
+    LineComment,
+    BlockComment,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Kind {
     Keyword(Keyword),
     Ident(String),
@@ -13,14 +44,20 @@ pub enum Kind {
     Symbol(Symbol),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Literal {
     Char(char),
     String(String),
+    Byte(u8),
+    ByteString(Vec<u8>),
     Num(NumLiteral),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// Floats compare and hash by their bits, so that a literal equals itself even when it is NaN,
+// and `0.0` and `-0.0` are different literals.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NumLiteral {
     I32(i32),
     I64(i64),
@@ -28,7 +65,8 @@ pub enum NumLiteral {
     F64(f64),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Keyword {
     I32,
     I64,
@@ -41,15 +79,90 @@ pub enum Keyword {
     False,
     Let,
     If,
+    Else,
     While,
     Return,
     Struct,
     Fun,
     Extern,
     For,
+    Break,
+    Continue,
+    Enum,
+    Match,
+    Import,
+    Pub,
+    Const,
+    Mut,
+    As,
+    Reserved(String),
 }
 
 #[derive(Clone, Debug, PartialEq)]
+pub struct KeywordTable(HashMap<String, Keyword>);
+
+impl KeywordTable {
+    pub fn new() -> Self {
+        KeywordTable(HashMap::new())
+    }
+
+    pub fn get(&self, ident: &str) -> Option<&Keyword> {
+        self.0.get(ident)
+    }
+
+    pub fn insert(&mut self, ident: &str, keyword: Keyword) -> Option<Keyword> {
+        self.0.insert(ident.to_string(), keyword)
+    }
+
+    pub fn remove(&mut self, ident: &str) -> Option<Keyword> {
+        self.0.remove(ident)
+    }
+
+    pub fn reserve(&mut self, ident: &str) -> Option<Keyword> {
+        self.insert(ident, Keyword::Reserved(ident.to_string()))
+    }
+}
+
+impl Default for KeywordTable {
+    fn default() -> Self {
+        let mut table = KeywordTable::new();
+        for (ident, keyword) in vec![
+            ("i32", Keyword::I32),
+            ("i64", Keyword::I64),
+            ("f32", Keyword::F32),
+            ("f64", Keyword::F64),
+            ("string", Keyword::String),
+            ("bool", Keyword::Bool),
+            ("char", Keyword::Char),
+            ("true", Keyword::True),
+            ("false", Keyword::False),
+            ("let", Keyword::Let),
+            ("if", Keyword::If),
+            ("else", Keyword::Else),
+            ("while", Keyword::While),
+            ("return", Keyword::Return),
+            ("struct", Keyword::Struct),
+            ("fun", Keyword::Fun),
+            ("extern", Keyword::Extern),
+            ("for", Keyword::For),
+            ("break", Keyword::Break),
+            ("continue", Keyword::Continue),
+            ("enum", Keyword::Enum),
+            ("match", Keyword::Match),
+            ("import", Keyword::Import),
+            ("pub", Keyword::Pub),
+            ("const", Keyword::Const),
+            ("mut", Keyword::Mut),
+            ("as", Keyword::As),
+        ] {
+            table.insert(ident, keyword);
+        }
+        table
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Symbol {
     Dot,
     Comma,
@@ -80,4 +193,197 @@ pub enum Symbol {
     Gt,
     Gte,
     Assign,
+    AddAssign,
+    SubAssign,
+    MulAssign,
+    DivAssign,
+    ModAssign,
+    Arrow,
+    FatArrow,
+    DotDot,
+    DotDotEq,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Keyword(x) => write!(f, "{}", x),
+            Kind::Ident(x) => write!(f, "{}", x),
+            Kind::Literal(x) => write!(f, "{}", x),
+            Kind::Symbol(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+fn escape(c: char, quote: char) -> String {
+    match c {
+        '\t' => "\\t".to_string(),
+        '\n' => "\\n".to_string(),
+        '\r' => "\\r".to_string(),
+        '\\' => "\\\\".to_string(),
+        c if c == quote => format!("\\{}", c),
+        c if c.is_control() => format!("\\u{:04x}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+fn escape_byte(b: u8, quote: char) -> String {
+    if b.is_ascii() {
+        escape(b as char, quote)
+    } else {
+        format!("\\x{:02x}", b)
+    }
+}
+
+fn float(s: String) -> String {
+    if s.contains('.') {
+        s
+    } else {
+        format!("{}.0", s)
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Literal::Char(x) => write!(f, "'{}'", escape(*x, '\'')),
+            Literal::String(x) => write!(
+                f,
+                "\"{}\"",
+                x.chars().map(|c| escape(c, '"')).collect::<String>()
+            ),
+            Literal::Byte(x) => write!(f, "b'{}'", escape_byte(*x, '\'')),
+            Literal::ByteString(x) => write!(
+                f,
+                "b\"{}\"",
+                x.iter().map(|&b| escape_byte(b, '"')).collect::<String>()
+            ),
+            Literal::Num(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl NumLiteral {
+    // The variant and the bits of the value.
+    fn key(&self) -> (u8, u64) {
+        match self {
+            NumLiteral::I32(x) => (0, *x as u32 as u64),
+            NumLiteral::I64(x) => (1, *x as u64),
+            NumLiteral::F32(x) => (2, x.to_bits().into()),
+            NumLiteral::F64(x) => (3, x.to_bits()),
+        }
+    }
+}
+
+impl PartialEq for NumLiteral {
+    fn eq(&self, other: &NumLiteral) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for NumLiteral {}
+
+impl Hash for NumLiteral {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl fmt::Display for NumLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NumLiteral::I32(x) => write!(f, "{}", x),
+            NumLiteral::I64(x) => write!(f, "{}i64", x),
+            NumLiteral::F32(x) => write!(f, "{}f32", float(x.to_string())),
+            NumLiteral::F64(x) => write!(f, "{}", float(x.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Keyword::I32 => "i32",
+            Keyword::I64 => "i64",
+            Keyword::F32 => "f32",
+            Keyword::F64 => "f64",
+            Keyword::String => "string",
+            Keyword::Bool => "bool",
+            Keyword::Char => "char",
+            Keyword::True => "true",
+            Keyword::False => "false",
+            Keyword::Let => "let",
+            Keyword::If => "if",
+            Keyword::Else => "else",
+            Keyword::While => "while",
+            Keyword::Return => "return",
+            Keyword::Struct => "struct",
+            Keyword::Fun => "fun",
+            Keyword::Extern => "extern",
+            Keyword::For => "for",
+            Keyword::Break => "break",
+            Keyword::Continue => "continue",
+            Keyword::Enum => "enum",
+            Keyword::Match => "match",
+            Keyword::Import => "import",
+            Keyword::Pub => "pub",
+            Keyword::Const => "const",
+            Keyword::Mut => "mut",
+            Keyword::As => "as",
+            Keyword::Reserved(x) => x,
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Symbol::Dot => ".",
+            Symbol::Comma => ",",
+            Symbol::Colon => ":",
+            Symbol::Semicolon => ";",
+            Symbol::OpenParent => "(",
+            Symbol::CloseParent => ")",
+            Symbol::OpenBracket => "[",
+            Symbol::CloseBracket => "]",
+            Symbol::OpenBrace => "{",
+            Symbol::CloseBrace => "}",
+            Symbol::Not => "!",
+            Symbol::Add => "+",
+            Symbol::Sub => "-",
+            Symbol::Mul => "*",
+            Symbol::Div => "/",
+            Symbol::Mod => "%",
+            Symbol::And => "&&",
+            Symbol::Or => "||",
+            Symbol::BitAnd => "&",
+            Symbol::BitOr => "|",
+            Symbol::BitXor => "^",
+            Symbol::Pow => "**",
+            Symbol::Eq => "==",
+            Symbol::Ne => "!=",
+            Symbol::Lt => "<",
+            Symbol::Lte => "<=",
+            Symbol::Gt => ">",
+            Symbol::Gte => ">=",
+            Symbol::Assign => "=",
+            Symbol::AddAssign => "+=",
+            Symbol::SubAssign => "-=",
+            Symbol::MulAssign => "*=",
+            Symbol::DivAssign => "/=",
+            Symbol::ModAssign => "%=",
+            Symbol::Arrow => "->",
+            Symbol::FatArrow => "=>",
+            Symbol::DotDot => "..",
+            Symbol::DotDotEq => "..=",
+        };
+        write!(f, "{}", s)
+    }
 }
@@ -1,27 +1,43 @@
 #[derive(Clone, Debug, PartialEq)]
-enum ValueType {
+pub enum ValueType {
     I32,
-    I63,
+    I64,
     F32,
     F64,
+    V128,
+    FuncRef,
+    ExternRef,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct BlockType(Option<ValueType>);
+pub enum BlockType {
+    Empty,
+    Value(ValueType),
+    // The index of a function type, for blocks with parameters or several results.
+    Func(usize),
+}
+
+impl From<Option<ValueType>> for BlockType {
+    fn from(x: Option<ValueType>) -> BlockType {
+        x.map_or(BlockType::Empty, BlockType::Value)
+    }
+}
 
 #[derive(Clone, Debug, PartialEq)]
-enum ElemType {
+pub enum ElemType {
     AnyFunc,
+    ExternRef,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct FuncType {
-    params: Vec<ValueType>,
-    result: Option<ValueType>,
+pub struct FuncType {
+    pub params: Vec<ValueType>,
+    pub results: Vec<ValueType>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum LanguageType {
+#[allow(clippy::enum_variant_names)]
+pub enum LanguageType {
     ValueType(ValueType),
     ElemType(ElemType),
     FuncType(FuncType),
@@ -29,22 +45,22 @@ enum LanguageType {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct GlobalType {
-    content_type: ValueType,
-    mutability: bool,
+pub struct GlobalType {
+    pub content_type: ValueType,
+    pub mutability: bool,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct TableType {
-    element_type: ElemType,
-    limits: ResizableLimits,
+pub struct TableType {
+    pub element_type: ElemType,
+    pub limits: ResizableLimits,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct MemoryType(ResizableLimits);
+pub struct MemoryType(pub ResizableLimits);
 
 #[derive(Clone, Debug, PartialEq)]
-enum ExternalKind {
+pub enum ExternalKind {
     Function,
     Table,
     Memory,
@@ -52,7 +68,7 @@ enum ExternalKind {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum ExternalKindImport {
+pub enum ExternalKindImport {
     Function(usize),
     Table(TableType),
     Memory(MemoryType),
@@ -60,13 +76,15 @@ enum ExternalKindImport {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct ResizableLimits {
-    initial: i32,
-    maximum: Option<i32>,
+pub struct ResizableLimits {
+    pub initial: i32,
+    pub maximum: Option<i32>,
+    // Whether the memory is shared between threads, which needs a maximum.
+    pub shared: bool,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum InitExpr {
+pub enum InitExpr {
     I32(i32),
     I64(i64),
     F32(f32),
@@ -75,87 +93,107 @@ enum InitExpr {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct TypeSection(Vec<FuncType>);
+pub struct TypeSection(pub Vec<FuncType>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct ImportEntry {
-    module: String,
-    field: String,
-    kind: ExternalKindImport,
+pub struct ImportEntry {
+    pub module: String,
+    pub field: String,
+    pub kind: ExternalKindImport,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct ImportSection(Vec<ImportEntry>);
+pub struct ImportSection(pub Vec<ImportEntry>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct FunctionSection(Vec<usize>);
+pub struct FunctionSection(pub Vec<usize>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct TableSection(Vec<TableType>);
+pub struct TableSection(pub Vec<TableType>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct MemorySection(Vec<MemoryType>);
+pub struct MemorySection(pub Vec<MemoryType>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct GlobalSection(Vec<GlobalVariable>);
+pub struct GlobalSection(pub Vec<GlobalVariable>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct GlobalVariable(GlobalType, InitExpr);
+pub struct GlobalVariable(pub GlobalType, pub InitExpr);
 
 #[derive(Clone, Debug, PartialEq)]
-struct ExportSection(Vec<ExportEntry>);
+pub struct ExportSection(pub Vec<ExportEntry>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct ExportEntry {
-    field: String,
-    kind: ExternalKind,
-    index: usize,
+pub struct ExportEntry {
+    pub field: String,
+    pub kind: ExternalKind,
+    pub index: usize,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct StartSection(usize);
+pub struct StartSection(pub usize);
 
 #[derive(Clone, Debug, PartialEq)]
-struct ElementSection(Vec<ElemSegment>);
+pub struct ElementSection(pub Vec<ElemSegment>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct ElemSegment {
-    offset: InitExpr,
-    elems: usize,
+pub struct ElemSegment {
+    pub offset: InitExpr,
+    // Function indices placed in the table from `offset` on.
+    pub elems: Vec<usize>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct CodeSection(Vec<FunctionBody>);
+pub struct CodeSection(pub Vec<FunctionBody>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct FunctionBody {
-    locals: Vec<LocalEntry>,
-    codes: Vec<OperatorCode>,
+pub struct FunctionBody {
+    pub locals: Vec<LocalEntry>,
+    // Without the `End` that closes the body, which the encoder adds.
+    pub codes: Vec<OperatorCode>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct LocalEntry {
-    count: usize,
-    typ: ValueType,
+pub struct LocalEntry {
+    pub count: usize,
+    pub typ: ValueType,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct DataSection(Vec<DataSegment>);
+pub struct DataSection(pub Vec<DataSegment>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct DataSegment {
-    offset: InitExpr,
-    data: Vec<u8>,
+pub struct DataSegment {
+    // Where the data is copied on instantiation, or `None` for a passive segment, which only
+    // `memory.init` copies.
+    pub offset: Option<InitExpr>,
+    pub data: Vec<u8>,
+}
+
+// The "name" custom section, which tells debuggers the names of the module, the functions and
+// their locals. Name maps are by index, in increasing order.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct NameSection {
+    pub module: Option<String>,
+    pub functions: Vec<(usize, String)>,
+    pub locals: Vec<(usize, Vec<(usize, String)>)>,
 }
 
+// Any other custom section, such as build info or hashes of the sources, which engines ignore.
 #[derive(Clone, Debug, PartialEq)]
-struct MemoryImmediate {
-    flags: u32,
-    offset: u32,
+pub struct CustomSection {
+    pub name: String,
+    pub payload: Vec<u8>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum OperatorCode {
+pub struct MemoryImmediate {
+    pub flags: u32,
+    pub offset: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OperatorCode {
     Unreachable,
     Nop,
     Block(BlockType),
@@ -176,6 +214,8 @@ enum OperatorCode {
     TeeLocal(usize),
     GetGlobal(usize),
     SetGlobal(usize),
+    TableGet(usize),
+    TableSet(usize),
     I32Load(MemoryImmediate),
     I64Load(MemoryImmediate),
     F32Load(MemoryImmediate),
@@ -328,19 +368,147 @@ enum OperatorCode {
     I64ReinterpretF64,
     F32ReinterpretI32,
     F64ReinterpretI64,
+    RefNull(ElemType),
+    RefIsNull,
+    RefFunc(usize),
+    MemoryInit(usize),
+    DataDrop(usize),
+    MemoryCopy,
+    MemoryFill,
+    // SIMD. Lanes are numbered from the lowest bytes of vectors, which are stored little-endian.
+    V128Load(MemoryImmediate),
+    V128Store(MemoryImmediate),
+    V128Const(u128),
+    I8x16Splat,
+    I16x8Splat,
+    I32x4Splat,
+    I64x2Splat,
+    F32x4Splat,
+    F64x2Splat,
+    I8x16ExtractLanes(u8),
+    I8x16ExtractLaneu(u8),
+    I8x16ReplaceLane(u8),
+    I16x8ExtractLanes(u8),
+    I16x8ExtractLaneu(u8),
+    I16x8ReplaceLane(u8),
+    I32x4ExtractLane(u8),
+    I32x4ReplaceLane(u8),
+    I64x2ExtractLane(u8),
+    I64x2ReplaceLane(u8),
+    F32x4ExtractLane(u8),
+    F32x4ReplaceLane(u8),
+    F64x2ExtractLane(u8),
+    F64x2ReplaceLane(u8),
+    I8x16Eq,
+    I8x16Ne,
+    I16x8Eq,
+    I16x8Ne,
+    I32x4Eq,
+    I32x4Ne,
+    F32x4Eq,
+    F32x4Ne,
+    F64x2Eq,
+    F64x2Ne,
+    V128Not,
+    V128And,
+    V128AndNot,
+    V128Or,
+    V128Xor,
+    V128Bitselect,
+    V128AnyTrue,
+    I8x16AllTrue,
+    I8x16Bitmask,
+    I8x16Add,
+    I8x16Sub,
+    I16x8AllTrue,
+    I16x8Bitmask,
+    I16x8Add,
+    I16x8Sub,
+    I16x8Mul,
+    I32x4AllTrue,
+    I32x4Bitmask,
+    I32x4Add,
+    I32x4Sub,
+    I32x4Mul,
+    I64x2AllTrue,
+    I64x2Bitmask,
+    I64x2Add,
+    I64x2Sub,
+    I64x2Mul,
+    F32x4Add,
+    F32x4Sub,
+    F32x4Mul,
+    F32x4Div,
+    F64x2Add,
+    F64x2Sub,
+    F64x2Mul,
+    F64x2Div,
+    // Threads. Atomic accesses must be aligned to their size, and read-modify-write instructions
+    // return the old value.
+    MemoryAtomicNotify(MemoryImmediate),
+    MemoryAtomicWait32(MemoryImmediate),
+    MemoryAtomicWait64(MemoryImmediate),
+    AtomicFence,
+    I32AtomicLoad(MemoryImmediate),
+    I64AtomicLoad(MemoryImmediate),
+    I32AtomicStore(MemoryImmediate),
+    I64AtomicStore(MemoryImmediate),
+    I32AtomicRmwAdd(MemoryImmediate),
+    I64AtomicRmwAdd(MemoryImmediate),
+    I32AtomicRmwSub(MemoryImmediate),
+    I64AtomicRmwSub(MemoryImmediate),
+    I32AtomicRmwAnd(MemoryImmediate),
+    I64AtomicRmwAnd(MemoryImmediate),
+    I32AtomicRmwOr(MemoryImmediate),
+    I64AtomicRmwOr(MemoryImmediate),
+    I32AtomicRmwXor(MemoryImmediate),
+    I64AtomicRmwXor(MemoryImmediate),
+    I32AtomicRmwXchg(MemoryImmediate),
+    I64AtomicRmwXchg(MemoryImmediate),
+    I32AtomicRmwCmpxchg(MemoryImmediate),
+    I64AtomicRmwCmpxchg(MemoryImmediate),
+}
+
+impl OperatorCode {
+    // The instruction behind a numeric `as` cast, or `None` when both sides already have the
+    // same representation. Integers are treated as signed and floats truncate towards zero.
+    pub fn conversion(from: &ValueType, to: &ValueType) -> Option<OperatorCode> {
+        use ValueType::*;
+        let op = match (from, to) {
+            (I64, I32) => OperatorCode::I32WrapI64,
+            (F32, I32) => OperatorCode::I32TruncsF32,
+            (F64, I32) => OperatorCode::I32TrancsF64,
+            (I32, I64) => OperatorCode::I64ExtendsI32,
+            (F32, I64) => OperatorCode::I64TruncsF32,
+            (F64, I64) => OperatorCode::I64TrancsF64,
+            (I32, F32) => OperatorCode::F32ConvertsI32,
+            (I64, F32) => OperatorCode::F32ConvertsI64,
+            (F64, F32) => OperatorCode::F32DemoteF64,
+            (I32, F64) => OperatorCode::F64ConvertsI32,
+            (I64, F64) => OperatorCode::F64ConvertsI64,
+            (F32, F64) => OperatorCode::F64PromoteF32,
+            _ => return None,
+        };
+        Some(op)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Default)]
-struct WasmASTRoot {
-    type_section: Option<TypeSection>,
-    import_section: Option<ImportSection>,
-    function_section: Option<FunctionSection>,
-    table_section: Option<TableSection>,
-    memory_section: Option<MemorySection>,
-    global_section: Option<GlobalSection>,
-    export_section: Option<ExportSection>,
-    start_section: Option<StartSection>,
-    element_section: Option<ElementSection>,
-    code_section: Option<CodeSection>,
-    data_section: Option<DataSection>,
+pub struct WasmASTRoot {
+    pub type_section: Option<TypeSection>,
+    pub import_section: Option<ImportSection>,
+    pub function_section: Option<FunctionSection>,
+    pub table_section: Option<TableSection>,
+    pub memory_section: Option<MemorySection>,
+    pub global_section: Option<GlobalSection>,
+    pub export_section: Option<ExportSection>,
+    pub start_section: Option<StartSection>,
+    pub element_section: Option<ElementSection>,
+    pub code_section: Option<CodeSection>,
+    pub data_section: Option<DataSection>,
+    pub name_section: Option<NameSection>,
+    // The URL of the source map, in the "sourceMappingURL" custom section.
+    pub source_mapping_url: Option<String>,
+    // Written after all the other sections, in this order.
+    pub custom_sections: Vec<CustomSection>,
 }
@@ -0,0 +1,286 @@
+use crate::ast::*;
+
+// Builds a `WasmASTRoot` one item at a time, numbering functions, globals, memories and tables
+// as they are added. Imports are numbered before the items the module defines, so they have to
+// be added first: importing a function once one is defined would change the index of every
+// defined function, which panics instead. Sections with nothing in them are left out.
+
+pub type FuncIdx = usize;
+pub type GlobalIdx = usize;
+
+#[derive(Clone, Debug, Default)]
+pub struct WasmBuilder {
+    types: Vec<FuncType>,
+    imports: Vec<ImportEntry>,
+    imported_funcs: usize,
+    imported_globals: usize,
+    imported_memories: usize,
+    imported_tables: usize,
+    funcs: Vec<usize>,
+    bodies: Vec<FunctionBody>,
+    tables: Vec<TableType>,
+    memories: Vec<MemoryType>,
+    globals: Vec<GlobalVariable>,
+    exports: Vec<ExportEntry>,
+    start: Option<FuncIdx>,
+    elements: Vec<ElemSegment>,
+    data: Vec<DataSegment>,
+    names: Vec<(FuncIdx, String)>,
+    custom_sections: Vec<CustomSection>,
+}
+
+impl WasmBuilder {
+    pub fn new() -> WasmBuilder {
+        WasmBuilder::default()
+    }
+
+    // The index of a function type, shared by all functions with the same signature.
+    pub fn add_type(&mut self, sig: FuncType) -> usize {
+        match self.types.iter().position(|x| *x == sig) {
+            Some(i) => i,
+            None => {
+                self.types.push(sig);
+                self.types.len() - 1
+            }
+        }
+    }
+
+    fn import(&mut self, module: &str, field: &str, kind: ExternalKindImport) {
+        self.imports.push(ImportEntry {
+            module: module.to_string(),
+            field: field.to_string(),
+            kind,
+        });
+    }
+
+    pub fn add_import(&mut self, module: &str, field: &str, sig: FuncType) -> FuncIdx {
+        assert!(
+            self.bodies.is_empty(),
+            "functions must be imported before any is defined"
+        );
+        let t = self.add_type(sig);
+        self.import(module, field, ExternalKindImport::Function(t));
+        self.imported_funcs += 1;
+        self.imported_funcs - 1
+    }
+
+    pub fn import_global(&mut self, module: &str, field: &str, t: GlobalType) -> GlobalIdx {
+        assert!(
+            self.globals.is_empty(),
+            "globals must be imported before any is defined"
+        );
+        self.import(module, field, ExternalKindImport::Global(t));
+        self.imported_globals += 1;
+        self.imported_globals - 1
+    }
+
+    pub fn import_memory(&mut self, module: &str, field: &str, limits: ResizableLimits) -> usize {
+        assert!(
+            self.memories.is_empty(),
+            "memories must be imported before any is defined"
+        );
+        self.import(
+            module,
+            field,
+            ExternalKindImport::Memory(MemoryType(limits)),
+        );
+        self.imported_memories += 1;
+        self.imported_memories - 1
+    }
+
+    pub fn import_table(&mut self, module: &str, field: &str, t: TableType) -> usize {
+        assert!(
+            self.tables.is_empty(),
+            "tables must be imported before any is defined"
+        );
+        self.import(module, field, ExternalKindImport::Table(t));
+        self.imported_tables += 1;
+        self.imported_tables - 1
+    }
+
+    // `body` leaves out the `End` that closes it, like `FunctionBody::codes`.
+    pub fn add_function(
+        &mut self,
+        sig: FuncType,
+        locals: Vec<LocalEntry>,
+        body: Vec<OperatorCode>,
+    ) -> FuncIdx {
+        let t = self.add_type(sig);
+        self.funcs.push(t);
+        self.bodies.push(FunctionBody {
+            locals,
+            codes: body,
+        });
+        self.imported_funcs + self.bodies.len() - 1
+    }
+
+    pub fn add_global(&mut self, t: GlobalType, init: InitExpr) -> GlobalIdx {
+        self.globals.push(GlobalVariable(t, init));
+        self.imported_globals + self.globals.len() - 1
+    }
+
+    pub fn add_memory(&mut self, limits: ResizableLimits) -> usize {
+        self.memories.push(MemoryType(limits));
+        self.imported_memories + self.memories.len() - 1
+    }
+
+    pub fn add_table(&mut self, t: TableType) -> usize {
+        self.tables.push(t);
+        self.imported_tables + self.tables.len() - 1
+    }
+
+    pub fn add_elements(&mut self, offset: InitExpr, funcs: Vec<FuncIdx>) -> &mut WasmBuilder {
+        self.elements.push(ElemSegment {
+            offset,
+            elems: funcs,
+        });
+        self
+    }
+
+    pub fn add_data(&mut self, offset: InitExpr, data: Vec<u8>) -> &mut WasmBuilder {
+        self.data.push(DataSegment {
+            offset: Some(offset),
+            data,
+        });
+        self
+    }
+
+    // A segment only `memory.init` copies, by the index this returns.
+    pub fn add_passive_data(&mut self, data: Vec<u8>) -> usize {
+        self.data.push(DataSegment { offset: None, data });
+        self.data.len() - 1
+    }
+
+    pub fn export(&mut self, field: &str, kind: ExternalKind, index: usize) -> &mut WasmBuilder {
+        self.exports.push(ExportEntry {
+            field: field.to_string(),
+            kind,
+            index,
+        });
+        self
+    }
+
+    pub fn start(&mut self, func: FuncIdx) -> &mut WasmBuilder {
+        self.start = Some(func);
+        self
+    }
+
+    // Names the function in the name section, which is only emitted once a function has a name.
+    pub fn name(&mut self, func: FuncIdx, name: &str) -> &mut WasmBuilder {
+        self.names.retain(|x| x.0 != func);
+        self.names.push((func, name.to_string()));
+        self
+    }
+
+    pub fn add_custom_section(&mut self, name: &str, payload: Vec<u8>) -> &mut WasmBuilder {
+        self.custom_sections.push(CustomSection {
+            name: name.to_string(),
+            payload,
+        });
+        self
+    }
+
+    pub fn build(self) -> WasmASTRoot {
+        fn section<T, S>(xs: Vec<T>, f: impl FnOnce(Vec<T>) -> S) -> Option<S> {
+            if xs.is_empty() {
+                None
+            } else {
+                Some(f(xs))
+            }
+        }
+        let mut names = self.names;
+        names.sort_by_key(|x| x.0);
+        WasmASTRoot {
+            type_section: section(self.types, TypeSection),
+            import_section: section(self.imports, ImportSection),
+            function_section: section(self.funcs, FunctionSection),
+            table_section: section(self.tables, TableSection),
+            memory_section: section(self.memories, MemorySection),
+            global_section: section(self.globals, GlobalSection),
+            export_section: section(self.exports, ExportSection),
+            start_section: self.start.map(StartSection),
+            element_section: section(self.elements, ElementSection),
+            code_section: section(self.bodies, CodeSection),
+            data_section: section(self.data, DataSection),
+            name_section: section(names, |functions| NameSection {
+                functions,
+                ..NameSection::default()
+            }),
+            source_mapping_url: None,
+            custom_sections: self.custom_sections,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wat::print_module;
+
+    #[test]
+    fn builder_test() {
+        let unary = FuncType {
+            params: vec![ValueType::I32],
+            results: vec![ValueType::I32],
+        };
+        let mut builder = WasmBuilder::new();
+        let double = builder.add_import("env", "double", unary.clone());
+        let base = builder.import_global(
+            "env",
+            "base",
+            GlobalType {
+                content_type: ValueType::I32,
+                mutability: false,
+            },
+        );
+        let memory = builder.add_memory(ResizableLimits {
+            initial: 1,
+            maximum: None,
+            shared: false,
+        });
+        let count = builder.add_global(
+            GlobalType {
+                content_type: ValueType::I32,
+                mutability: true,
+            },
+            InitExpr::Global(base),
+        );
+        let next = builder.add_function(
+            unary.clone(),
+            Vec::new(),
+            vec![
+                OperatorCode::GetLocal(0),
+                OperatorCode::Call(double),
+                OperatorCode::GetGlobal(count),
+                OperatorCode::I32Add,
+            ],
+        );
+        builder
+            .export("memory", ExternalKind::Memory, memory)
+            .export("next", ExternalKind::Function, next)
+            .add_data(InitExpr::I32(0), b"hi".to_vec())
+            .name(next, "next");
+        assert_eq!((double, base, count, next), (0, 0, 1, 1));
+        let root = builder.build();
+        assert_eq!(root.type_section, Some(TypeSection(vec![unary])));
+        assert_eq!(root.table_section, None);
+        assert_eq!(
+            print_module(&root),
+            r#"(module
+  (type (;0;) (func (param i32) (result i32)))
+  (import "env" "double" (func (;0;) (type 0)))
+  (import "env" "base" (global (;0;) i32))
+  (func (;1;) (type 0) (param i32) (result i32)
+    local.get 0
+    call 0
+    global.get 1
+    i32.add)
+  (memory (;0;) 1)
+  (global (;1;) (mut i32) (global.get 0))
+  (export "memory" (memory 0))
+  (export "next" (func 1))
+  (data (;0;) (i32.const 0) "hi"))
+"#
+        );
+    }
+}
@@ -1,18 +1,1440 @@
+use crate::ast::*;
 use byteorder::{LittleEndian, WriteBytesExt};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::io::{self, Write};
 
-
-trait BinaryEncode {
+pub trait BinaryEncode {
     fn encode(&self, bytes: &mut Vec<u8>);
 }
 
-fn encodeUint8(x: u8, bytes: &mut Vec<u8>) {
+pub fn encode_uint8(x: u8, bytes: &mut Vec<u8>) {
     bytes.write_u8(x).unwrap();
 }
 
-fn encodeUint16(x: u16, bytes: &mut Vec<u8>) {
+pub fn encode_uint16(x: u16, bytes: &mut Vec<u8>) {
     bytes.write_u16::<LittleEndian>(x).unwrap();
 }
 
-fn encodeUint32(x: u32, bytes: &mut Vec<u8>) {
+pub fn encode_uint32(x: u32, bytes: &mut Vec<u8>) {
     bytes.write_u32::<LittleEndian>(x).unwrap();
-}
\ No newline at end of file
+}
+
+// Integers in the binary format are LEB128 encoded, with the shortest encoding.
+
+pub fn encode_varuint32(x: u32, bytes: &mut Vec<u8>) {
+    leb128::write::unsigned(bytes, u64::from(x)).unwrap();
+}
+
+pub fn encode_varint32(x: i32, bytes: &mut Vec<u8>) {
+    leb128::write::signed(bytes, i64::from(x)).unwrap();
+}
+
+pub fn encode_varint64(x: i64, bytes: &mut Vec<u8>) {
+    leb128::write::signed(bytes, x).unwrap();
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEof,
+    // The value does not fit in the integer type being read.
+    Overflow,
+    // The input does not start with the magic number and version of a `.wasm` file.
+    InvalidHeader,
+    InvalidUtf8,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof => write!(f, "unexpected end of input"),
+            DecodeError::Overflow => write!(f, "integer too large"),
+            DecodeError::InvalidHeader => write!(f, "not a wasm module"),
+            DecodeError::InvalidUtf8 => write!(f, "invalid UTF-8 in a name"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<leb128::read::Error> for DecodeError {
+    fn from(e: leb128::read::Error) -> DecodeError {
+        match e {
+            leb128::read::Error::IoError(_) => DecodeError::UnexpectedEof,
+            leb128::read::Error::Overflow => DecodeError::Overflow,
+        }
+    }
+}
+
+// The decoders advance `bytes` past the value they read.
+
+pub fn decode_varuint32(bytes: &mut &[u8]) -> Result<u32, DecodeError> {
+    let x = leb128::read::unsigned(bytes)?;
+    u32::try_from(x).map_err(|_| DecodeError::Overflow)
+}
+
+pub fn decode_varint32(bytes: &mut &[u8]) -> Result<i32, DecodeError> {
+    let x = leb128::read::signed(bytes)?;
+    i32::try_from(x).map_err(|_| DecodeError::Overflow)
+}
+
+pub fn decode_varint64(bytes: &mut &[u8]) -> Result<i64, DecodeError> {
+    Ok(leb128::read::signed(bytes)?)
+}
+
+fn decode_array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], DecodeError> {
+    if bytes.len() < N {
+        return Err(DecodeError::UnexpectedEof);
+    }
+    let (x, rest) = bytes.split_at(N);
+    *bytes = rest;
+    Ok(x.try_into().unwrap())
+}
+
+// Floats are their bits in little endian, so NaN payloads survive decoding and encoding again.
+pub fn decode_f32(bytes: &mut &[u8]) -> Result<f32, DecodeError> {
+    Ok(f32::from_le_bytes(decode_array(bytes)?))
+}
+
+pub fn decode_f64(bytes: &mut &[u8]) -> Result<f64, DecodeError> {
+    Ok(f64::from_le_bytes(decode_array(bytes)?))
+}
+
+fn decode_bytes<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], DecodeError> {
+    let n = decode_varuint32(bytes)? as usize;
+    if n > bytes.len() {
+        return Err(DecodeError::UnexpectedEof);
+    }
+    let (x, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(x)
+}
+
+// The custom sections of a `.wasm` file, wherever they are, skipping the other sections. The
+// "name" and "sourceMappingURL" sections are left out, since `WasmASTRoot` has fields for them.
+pub fn decode_custom_sections(mut bytes: &[u8]) -> Result<Vec<CustomSection>, DecodeError> {
+    if !bytes.starts_with(b"\0asm\x01\0\0\0") {
+        return Err(DecodeError::InvalidHeader);
+    }
+    bytes = &bytes[8..];
+    let mut sections = Vec::new();
+    while let Some((&id, rest)) = bytes.split_first() {
+        bytes = rest;
+        let mut payload = decode_bytes(&mut bytes)?;
+        if id != 0 {
+            continue;
+        }
+        let name = std::str::from_utf8(decode_bytes(&mut payload)?)
+            .map_err(|_| DecodeError::InvalidUtf8)?;
+        if name != "name" && name != "sourceMappingURL" {
+            sections.push(CustomSection {
+                name: name.to_string(),
+                payload: payload.to_vec(),
+            });
+        }
+    }
+    Ok(sections)
+}
+
+pub fn encode_index(x: usize, bytes: &mut Vec<u8>) {
+    encode_varuint32(u32::try_from(x).expect("index out of range"), bytes);
+}
+
+pub fn encode_bytes(x: &[u8], bytes: &mut Vec<u8>) {
+    encode_index(x.len(), bytes);
+    bytes.extend_from_slice(x);
+}
+
+pub fn encode_string(x: &str, bytes: &mut Vec<u8>) {
+    encode_bytes(x.as_bytes(), bytes);
+}
+
+pub fn encode_vec<T: BinaryEncode>(xs: &[T], bytes: &mut Vec<u8>) {
+    encode_index(xs.len(), bytes);
+    for x in xs {
+        x.encode(bytes);
+    }
+}
+
+// Sections and function bodies are prefixed with the size of their payload.
+fn encode_sized(bytes: &mut Vec<u8>, f: impl FnOnce(&mut Vec<u8>)) {
+    let mut payload = Vec::new();
+    f(&mut payload);
+    encode_bytes(&payload, bytes);
+}
+
+fn encode_section(id: u8, bytes: &mut Vec<u8>, f: impl FnOnce(&mut Vec<u8>)) {
+    encode_uint8(id, bytes);
+    encode_sized(bytes, f);
+}
+
+impl BinaryEncode for ValueType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_uint8(
+            match self {
+                ValueType::I32 => 0x7f,
+                ValueType::I64 => 0x7e,
+                ValueType::F32 => 0x7d,
+                ValueType::F64 => 0x7c,
+                ValueType::V128 => 0x7b,
+                ValueType::FuncRef => 0x70,
+                ValueType::ExternRef => 0x6f,
+            },
+            bytes,
+        );
+    }
+}
+
+impl BinaryEncode for BlockType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            BlockType::Empty => encode_uint8(0x40, bytes),
+            BlockType::Value(x) => x.encode(bytes),
+            // A positive signed 33-bit integer, which cannot be mistaken for the other two.
+            BlockType::Func(x) => encode_varint64(*x as i64, bytes),
+        }
+    }
+}
+
+impl BinaryEncode for ElemType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            ElemType::AnyFunc => encode_uint8(0x70, bytes),
+            ElemType::ExternRef => encode_uint8(0x6f, bytes),
+        }
+    }
+}
+
+impl BinaryEncode for FuncType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_uint8(0x60, bytes);
+        encode_vec(&self.params, bytes);
+        encode_vec(&self.results, bytes);
+    }
+}
+
+impl BinaryEncode for LanguageType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            LanguageType::ValueType(x) => x.encode(bytes),
+            LanguageType::ElemType(x) => x.encode(bytes),
+            LanguageType::FuncType(x) => x.encode(bytes),
+            LanguageType::BlockType(x) => x.encode(bytes),
+        }
+    }
+}
+
+impl BinaryEncode for GlobalType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.content_type.encode(bytes);
+        encode_uint8(self.mutability as u8, bytes);
+    }
+}
+
+impl BinaryEncode for ResizableLimits {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        // Bit 0 for the maximum and bit 1 for shared memories.
+        encode_uint8(
+            self.maximum.is_some() as u8 | (self.shared as u8) << 1,
+            bytes,
+        );
+        encode_varuint32(self.initial as u32, bytes);
+        if let Some(maximum) = self.maximum {
+            encode_varuint32(maximum as u32, bytes);
+        }
+    }
+}
+
+impl BinaryEncode for TableType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.element_type.encode(bytes);
+        self.limits.encode(bytes);
+    }
+}
+
+impl BinaryEncode for MemoryType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.0.encode(bytes);
+    }
+}
+
+impl BinaryEncode for ExternalKind {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_uint8(
+            match self {
+                ExternalKind::Function => 0,
+                ExternalKind::Table => 1,
+                ExternalKind::Memory => 2,
+                ExternalKind::Global => 3,
+            },
+            bytes,
+        );
+    }
+}
+
+impl BinaryEncode for ExternalKindImport {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            ExternalKindImport::Function(x) => {
+                ExternalKind::Function.encode(bytes);
+                encode_index(*x, bytes);
+            }
+            ExternalKindImport::Table(x) => {
+                ExternalKind::Table.encode(bytes);
+                x.encode(bytes);
+            }
+            ExternalKindImport::Memory(x) => {
+                ExternalKind::Memory.encode(bytes);
+                x.encode(bytes);
+            }
+            ExternalKindImport::Global(x) => {
+                ExternalKind::Global.encode(bytes);
+                x.encode(bytes);
+            }
+        }
+    }
+}
+
+impl BinaryEncode for InitExpr {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            InitExpr::I32(x) => OperatorCode::I32Const(*x).encode(bytes),
+            InitExpr::I64(x) => OperatorCode::I64Const(*x).encode(bytes),
+            InitExpr::F32(x) => OperatorCode::F32Const(*x).encode(bytes),
+            InitExpr::F64(x) => OperatorCode::F64Const(*x).encode(bytes),
+            InitExpr::Global(x) => OperatorCode::GetGlobal(*x).encode(bytes),
+        }
+        OperatorCode::End.encode(bytes);
+    }
+}
+
+impl BinaryEncode for ImportEntry {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_string(&self.module, bytes);
+        encode_string(&self.field, bytes);
+        self.kind.encode(bytes);
+    }
+}
+
+impl BinaryEncode for GlobalVariable {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.0.encode(bytes);
+        self.1.encode(bytes);
+    }
+}
+
+impl BinaryEncode for ExportEntry {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_string(&self.field, bytes);
+        self.kind.encode(bytes);
+        encode_index(self.index, bytes);
+    }
+}
+
+impl BinaryEncode for ElemSegment {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        // Table index, always 0 in the MVP.
+        encode_index(0, bytes);
+        self.offset.encode(bytes);
+        encode_index(self.elems.len(), bytes);
+        for x in &self.elems {
+            encode_index(*x, bytes);
+        }
+    }
+}
+
+impl BinaryEncode for LocalEntry {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_index(self.count, bytes);
+        self.typ.encode(bytes);
+    }
+}
+
+impl BinaryEncode for FunctionBody {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_sized(bytes, |bytes| {
+            encode_vec(&self.locals, bytes);
+            for x in &self.codes {
+                x.encode(bytes);
+            }
+            OperatorCode::End.encode(bytes);
+        });
+    }
+}
+
+impl BinaryEncode for DataSegment {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match &self.offset {
+            // Memory index 0, which is the only one.
+            Some(x) => {
+                encode_index(0, bytes);
+                x.encode(bytes);
+            }
+            None => encode_uint8(0x01, bytes),
+        }
+        encode_bytes(&self.data, bytes);
+    }
+}
+
+impl BinaryEncode for MemoryImmediate {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_varuint32(self.flags, bytes);
+        encode_varuint32(self.offset, bytes);
+    }
+}
+
+impl OperatorCode {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            OperatorCode::Unreachable => 0x00,
+            OperatorCode::Nop => 0x01,
+            OperatorCode::Block(_) => 0x02,
+            OperatorCode::Loop(_) => 0x03,
+            OperatorCode::If(_) => 0x04,
+            OperatorCode::Else => 0x05,
+            OperatorCode::End => 0x0b,
+            OperatorCode::Br(_) => 0x0c,
+            OperatorCode::BrIf(_) => 0x0d,
+            OperatorCode::BrTable { .. } => 0x0e,
+            OperatorCode::Return => 0x0f,
+            OperatorCode::Call(_) => 0x10,
+            OperatorCode::CallIndirect(_) => 0x11,
+            OperatorCode::Drop => 0x1a,
+            OperatorCode::Select => 0x1b,
+            OperatorCode::GetLocal(_) => 0x20,
+            OperatorCode::SetLocal(_) => 0x21,
+            OperatorCode::TeeLocal(_) => 0x22,
+            OperatorCode::GetGlobal(_) => 0x23,
+            OperatorCode::SetGlobal(_) => 0x24,
+            OperatorCode::TableGet(_) => 0x25,
+            OperatorCode::TableSet(_) => 0x26,
+            OperatorCode::I32Load(_) => 0x28,
+            OperatorCode::I64Load(_) => 0x29,
+            OperatorCode::F32Load(_) => 0x2a,
+            OperatorCode::F64Load(_) => 0x2b,
+            OperatorCode::I32Load8s(_) => 0x2c,
+            OperatorCode::I32Load8u(_) => 0x2d,
+            OperatorCode::I32Load16s(_) => 0x2e,
+            OperatorCode::I32Load16u(_) => 0x2f,
+            OperatorCode::I64Load8s(_) => 0x30,
+            OperatorCode::I64Load8u(_) => 0x31,
+            OperatorCode::I64Load16s(_) => 0x32,
+            OperatorCode::I64Load16u(_) => 0x33,
+            OperatorCode::I64Load32s(_) => 0x34,
+            OperatorCode::I64Load32u(_) => 0x35,
+            OperatorCode::I32Store(_) => 0x36,
+            OperatorCode::I64Store(_) => 0x37,
+            OperatorCode::F32Store(_) => 0x38,
+            OperatorCode::F64Store(_) => 0x39,
+            OperatorCode::I32Store8(_) => 0x3a,
+            OperatorCode::I32Store16(_) => 0x3b,
+            OperatorCode::I64Store8(_) => 0x3c,
+            OperatorCode::I64Store16(_) => 0x3d,
+            OperatorCode::I64Store32(_) => 0x3e,
+            OperatorCode::CurrentMemory => 0x3f,
+            OperatorCode::GrowMemory => 0x40,
+            OperatorCode::I32Const(_) => 0x41,
+            OperatorCode::I64Const(_) => 0x42,
+            OperatorCode::F32Const(_) => 0x43,
+            OperatorCode::F64Const(_) => 0x44,
+            OperatorCode::I32Eqz => 0x45,
+            OperatorCode::I32Eq => 0x46,
+            OperatorCode::I32Ne => 0x47,
+            OperatorCode::I32Lts => 0x48,
+            OperatorCode::I32Ltu => 0x49,
+            OperatorCode::I32Gts => 0x4a,
+            OperatorCode::I32Gtu => 0x4b,
+            OperatorCode::I32Les => 0x4c,
+            OperatorCode::I32Leu => 0x4d,
+            OperatorCode::I32Ges => 0x4e,
+            OperatorCode::I32Geu => 0x4f,
+            OperatorCode::I64Eqz => 0x50,
+            OperatorCode::I64Eq => 0x51,
+            OperatorCode::I64Ne => 0x52,
+            OperatorCode::I64Lts => 0x53,
+            OperatorCode::I64Ltu => 0x54,
+            OperatorCode::I64Gts => 0x55,
+            OperatorCode::I64Gtu => 0x56,
+            OperatorCode::I64Les => 0x57,
+            OperatorCode::I64Leu => 0x58,
+            OperatorCode::I64Ges => 0x59,
+            OperatorCode::I64Geu => 0x5a,
+            OperatorCode::F32Eq => 0x5b,
+            OperatorCode::F32Ne => 0x5c,
+            OperatorCode::F32Lt => 0x5d,
+            OperatorCode::F32Gt => 0x5e,
+            OperatorCode::F32Le => 0x5f,
+            OperatorCode::F32Ge => 0x60,
+            OperatorCode::F64Eq => 0x61,
+            OperatorCode::F64Ne => 0x62,
+            OperatorCode::F64Lt => 0x63,
+            OperatorCode::F64Gt => 0x64,
+            OperatorCode::F64Le => 0x65,
+            OperatorCode::F64Ge => 0x66,
+            OperatorCode::I32Clz => 0x67,
+            OperatorCode::I32Ctz => 0x68,
+            OperatorCode::I32Popcnt => 0x69,
+            OperatorCode::I32Add => 0x6a,
+            OperatorCode::I32Sub => 0x6b,
+            OperatorCode::I32Mul => 0x6c,
+            OperatorCode::I32Divs => 0x6d,
+            OperatorCode::I32Divu => 0x6e,
+            OperatorCode::I32Rems => 0x6f,
+            OperatorCode::I32Remu => 0x70,
+            OperatorCode::I32And => 0x71,
+            OperatorCode::I32Or => 0x72,
+            OperatorCode::I32Xor => 0x73,
+            OperatorCode::I32Shl => 0x74,
+            OperatorCode::I32Shrs => 0x75,
+            OperatorCode::I32Shru => 0x76,
+            OperatorCode::I32Rotl => 0x77,
+            OperatorCode::I32Rotr => 0x78,
+            OperatorCode::I64Clz => 0x79,
+            OperatorCode::I64Ctz => 0x7a,
+            OperatorCode::I64Popcnt => 0x7b,
+            OperatorCode::I64Add => 0x7c,
+            OperatorCode::I64Sub => 0x7d,
+            OperatorCode::I64Mul => 0x7e,
+            OperatorCode::I64Divs => 0x7f,
+            OperatorCode::I64Divu => 0x80,
+            OperatorCode::I64Rems => 0x81,
+            OperatorCode::I64Remu => 0x82,
+            OperatorCode::I64And => 0x83,
+            OperatorCode::I64Or => 0x84,
+            OperatorCode::I64Xor => 0x85,
+            OperatorCode::I64Shl => 0x86,
+            OperatorCode::I64Shrs => 0x87,
+            OperatorCode::I64Shru => 0x88,
+            OperatorCode::I64Rotl => 0x89,
+            OperatorCode::I64Rotr => 0x8a,
+            OperatorCode::F32Abs => 0x8b,
+            OperatorCode::F32Neg => 0x8c,
+            OperatorCode::F32Ceil => 0x8d,
+            OperatorCode::F32Floor => 0x8e,
+            OperatorCode::F32Trunc => 0x8f,
+            OperatorCode::F32Nearest => 0x90,
+            OperatorCode::F32Sqrt => 0x91,
+            OperatorCode::F32Add => 0x92,
+            OperatorCode::F32Sub => 0x93,
+            OperatorCode::F32Mul => 0x94,
+            OperatorCode::F32Div => 0x95,
+            OperatorCode::F32Min => 0x96,
+            OperatorCode::F32Max => 0x97,
+            OperatorCode::F32Copysign => 0x98,
+            OperatorCode::F64Abs => 0x99,
+            OperatorCode::F64Neg => 0x9a,
+            OperatorCode::F64Ceil => 0x9b,
+            OperatorCode::F64Floor => 0x9c,
+            OperatorCode::F64Trunc => 0x9d,
+            OperatorCode::F64Nearest => 0x9e,
+            OperatorCode::F64Sqrt => 0x9f,
+            OperatorCode::F64Add => 0xa0,
+            OperatorCode::F64Sub => 0xa1,
+            OperatorCode::F64Mul => 0xa2,
+            OperatorCode::F64Div => 0xa3,
+            OperatorCode::F64Min => 0xa4,
+            OperatorCode::F64Max => 0xa5,
+            OperatorCode::F64Copysign => 0xa6,
+            OperatorCode::I32WrapI64 => 0xa7,
+            OperatorCode::I32TruncsF32 => 0xa8,
+            OperatorCode::I32TrancuF32 => 0xa9,
+            OperatorCode::I32TrancsF64 => 0xaa,
+            OperatorCode::I32TrancuF64 => 0xab,
+            OperatorCode::I64ExtendsI32 => 0xac,
+            OperatorCode::I64ExtenduI32 => 0xad,
+            OperatorCode::I64TruncsF32 => 0xae,
+            OperatorCode::I64TrancuF32 => 0xaf,
+            OperatorCode::I64TrancsF64 => 0xb0,
+            OperatorCode::I64TrancuF64 => 0xb1,
+            OperatorCode::F32ConvertsI32 => 0xb2,
+            OperatorCode::F32ConvertuI32 => 0xb3,
+            OperatorCode::F32ConvertsI64 => 0xb4,
+            OperatorCode::F32ConvertuI64 => 0xb5,
+            OperatorCode::F32DemoteF64 => 0xb6,
+            OperatorCode::F64ConvertsI32 => 0xb7,
+            OperatorCode::F64ConvertuI32 => 0xb8,
+            OperatorCode::F64ConvertsI64 => 0xb9,
+            OperatorCode::F64ConvertuI64 => 0xba,
+            OperatorCode::F64PromoteF32 => 0xbb,
+            OperatorCode::I32ReinterpretF32 => 0xbc,
+            OperatorCode::I64ReinterpretF64 => 0xbd,
+            OperatorCode::F32ReinterpretI32 => 0xbe,
+            OperatorCode::F64ReinterpretI64 => 0xbf,
+            OperatorCode::RefNull(_) => 0xd0,
+            OperatorCode::RefIsNull => 0xd1,
+            OperatorCode::RefFunc(_) => 0xd2,
+            OperatorCode::MemoryInit(_)
+            | OperatorCode::DataDrop(_)
+            | OperatorCode::MemoryCopy
+            | OperatorCode::MemoryFill => 0xfc,
+            // The SIMD instructions and the atomic ones, which `simd_opcode` and `atomic_opcode`
+            // tell apart.
+            _ if self.atomic_opcode().is_some() => 0xfe,
+            _ => 0xfd,
+        }
+    }
+
+    // The opcode of an atomic instruction after the prefix 0xfe.
+    pub fn atomic_opcode(&self) -> Option<u32> {
+        Some(match self {
+            OperatorCode::MemoryAtomicNotify(_) => 0x00,
+            OperatorCode::MemoryAtomicWait32(_) => 0x01,
+            OperatorCode::MemoryAtomicWait64(_) => 0x02,
+            OperatorCode::AtomicFence => 0x03,
+            OperatorCode::I32AtomicLoad(_) => 0x10,
+            OperatorCode::I64AtomicLoad(_) => 0x11,
+            OperatorCode::I32AtomicStore(_) => 0x17,
+            OperatorCode::I64AtomicStore(_) => 0x18,
+            OperatorCode::I32AtomicRmwAdd(_) => 0x1e,
+            OperatorCode::I64AtomicRmwAdd(_) => 0x1f,
+            OperatorCode::I32AtomicRmwSub(_) => 0x25,
+            OperatorCode::I64AtomicRmwSub(_) => 0x26,
+            OperatorCode::I32AtomicRmwAnd(_) => 0x2c,
+            OperatorCode::I64AtomicRmwAnd(_) => 0x2d,
+            OperatorCode::I32AtomicRmwOr(_) => 0x33,
+            OperatorCode::I64AtomicRmwOr(_) => 0x34,
+            OperatorCode::I32AtomicRmwXor(_) => 0x3a,
+            OperatorCode::I64AtomicRmwXor(_) => 0x3b,
+            OperatorCode::I32AtomicRmwXchg(_) => 0x41,
+            OperatorCode::I64AtomicRmwXchg(_) => 0x42,
+            OperatorCode::I32AtomicRmwCmpxchg(_) => 0x48,
+            OperatorCode::I64AtomicRmwCmpxchg(_) => 0x49,
+            _ => return None,
+        })
+    }
+
+    // The opcode of a SIMD instruction after the prefix 0xfd.
+    pub fn simd_opcode(&self) -> Option<u32> {
+        Some(match self {
+            OperatorCode::V128Load(_) => 0x00,
+            OperatorCode::V128Store(_) => 0x0b,
+            OperatorCode::V128Const(_) => 0x0c,
+            OperatorCode::I8x16Splat => 0x0f,
+            OperatorCode::I16x8Splat => 0x10,
+            OperatorCode::I32x4Splat => 0x11,
+            OperatorCode::I64x2Splat => 0x12,
+            OperatorCode::F32x4Splat => 0x13,
+            OperatorCode::F64x2Splat => 0x14,
+            OperatorCode::I8x16ExtractLanes(_) => 0x15,
+            OperatorCode::I8x16ExtractLaneu(_) => 0x16,
+            OperatorCode::I8x16ReplaceLane(_) => 0x17,
+            OperatorCode::I16x8ExtractLanes(_) => 0x18,
+            OperatorCode::I16x8ExtractLaneu(_) => 0x19,
+            OperatorCode::I16x8ReplaceLane(_) => 0x1a,
+            OperatorCode::I32x4ExtractLane(_) => 0x1b,
+            OperatorCode::I32x4ReplaceLane(_) => 0x1c,
+            OperatorCode::I64x2ExtractLane(_) => 0x1d,
+            OperatorCode::I64x2ReplaceLane(_) => 0x1e,
+            OperatorCode::F32x4ExtractLane(_) => 0x1f,
+            OperatorCode::F32x4ReplaceLane(_) => 0x20,
+            OperatorCode::F64x2ExtractLane(_) => 0x21,
+            OperatorCode::F64x2ReplaceLane(_) => 0x22,
+            OperatorCode::I8x16Eq => 0x23,
+            OperatorCode::I8x16Ne => 0x24,
+            OperatorCode::I16x8Eq => 0x2d,
+            OperatorCode::I16x8Ne => 0x2e,
+            OperatorCode::I32x4Eq => 0x37,
+            OperatorCode::I32x4Ne => 0x38,
+            OperatorCode::F32x4Eq => 0x41,
+            OperatorCode::F32x4Ne => 0x42,
+            OperatorCode::F64x2Eq => 0x47,
+            OperatorCode::F64x2Ne => 0x48,
+            OperatorCode::V128Not => 0x4d,
+            OperatorCode::V128And => 0x4e,
+            OperatorCode::V128AndNot => 0x4f,
+            OperatorCode::V128Or => 0x50,
+            OperatorCode::V128Xor => 0x51,
+            OperatorCode::V128Bitselect => 0x52,
+            OperatorCode::V128AnyTrue => 0x53,
+            OperatorCode::I8x16AllTrue => 0x63,
+            OperatorCode::I8x16Bitmask => 0x64,
+            OperatorCode::I8x16Add => 0x6e,
+            OperatorCode::I8x16Sub => 0x71,
+            OperatorCode::I16x8AllTrue => 0x83,
+            OperatorCode::I16x8Bitmask => 0x84,
+            OperatorCode::I16x8Add => 0x8e,
+            OperatorCode::I16x8Sub => 0x91,
+            OperatorCode::I16x8Mul => 0x95,
+            OperatorCode::I32x4AllTrue => 0xa3,
+            OperatorCode::I32x4Bitmask => 0xa4,
+            OperatorCode::I32x4Add => 0xae,
+            OperatorCode::I32x4Sub => 0xb1,
+            OperatorCode::I32x4Mul => 0xb5,
+            OperatorCode::I64x2AllTrue => 0xc3,
+            OperatorCode::I64x2Bitmask => 0xc4,
+            OperatorCode::I64x2Add => 0xce,
+            OperatorCode::I64x2Sub => 0xd1,
+            OperatorCode::I64x2Mul => 0xd5,
+            OperatorCode::F32x4Add => 0xe4,
+            OperatorCode::F32x4Sub => 0xe5,
+            OperatorCode::F32x4Mul => 0xe6,
+            OperatorCode::F32x4Div => 0xe7,
+            OperatorCode::F64x2Add => 0xf0,
+            OperatorCode::F64x2Sub => 0xf1,
+            OperatorCode::F64x2Mul => 0xf2,
+            OperatorCode::F64x2Div => 0xf3,
+            _ => return None,
+        })
+    }
+}
+
+impl BinaryEncode for OperatorCode {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_uint8(self.opcode(), bytes);
+        if let Some(x) = self.simd_opcode().or_else(|| self.atomic_opcode()) {
+            encode_varuint32(x, bytes);
+        }
+        match self {
+            OperatorCode::Block(x) | OperatorCode::Loop(x) | OperatorCode::If(x) => x.encode(bytes),
+            OperatorCode::Br(x)
+            | OperatorCode::BrIf(x)
+            | OperatorCode::Call(x)
+            | OperatorCode::GetLocal(x)
+            | OperatorCode::SetLocal(x)
+            | OperatorCode::TeeLocal(x)
+            | OperatorCode::GetGlobal(x)
+            | OperatorCode::SetGlobal(x)
+            | OperatorCode::TableGet(x)
+            | OperatorCode::TableSet(x)
+            | OperatorCode::RefFunc(x) => encode_index(*x, bytes),
+            OperatorCode::RefNull(x) => x.encode(bytes),
+            // The instructions behind the prefix 0xfc, and the reserved memory indices.
+            OperatorCode::MemoryInit(x) => {
+                encode_varuint32(8, bytes);
+                encode_index(*x, bytes);
+                encode_uint8(0, bytes);
+            }
+            OperatorCode::DataDrop(x) => {
+                encode_varuint32(9, bytes);
+                encode_index(*x, bytes);
+            }
+            OperatorCode::MemoryCopy => {
+                encode_varuint32(10, bytes);
+                encode_uint8(0, bytes);
+                encode_uint8(0, bytes);
+            }
+            OperatorCode::MemoryFill => {
+                encode_varuint32(11, bytes);
+                encode_uint8(0, bytes);
+            }
+            // `params` are the targets and `index` the default.
+            OperatorCode::BrTable { index, params } => {
+                encode_index(params.len(), bytes);
+                for x in params {
+                    encode_index(*x, bytes);
+                }
+                encode_index(*index, bytes);
+            }
+            OperatorCode::CallIndirect(x) => {
+                encode_index(*x, bytes);
+                // Reserved table index.
+                encode_uint8(0, bytes);
+            }
+            OperatorCode::I32Load(x)
+            | OperatorCode::I64Load(x)
+            | OperatorCode::F32Load(x)
+            | OperatorCode::F64Load(x)
+            | OperatorCode::I32Load8s(x)
+            | OperatorCode::I32Load8u(x)
+            | OperatorCode::I32Load16s(x)
+            | OperatorCode::I32Load16u(x)
+            | OperatorCode::I64Load8s(x)
+            | OperatorCode::I64Load8u(x)
+            | OperatorCode::I64Load16s(x)
+            | OperatorCode::I64Load16u(x)
+            | OperatorCode::I64Load32s(x)
+            | OperatorCode::I64Load32u(x)
+            | OperatorCode::I32Store(x)
+            | OperatorCode::I64Store(x)
+            | OperatorCode::F32Store(x)
+            | OperatorCode::F64Store(x)
+            | OperatorCode::I32Store8(x)
+            | OperatorCode::I32Store16(x)
+            | OperatorCode::I64Store8(x)
+            | OperatorCode::I64Store16(x)
+            | OperatorCode::I64Store32(x)
+            | OperatorCode::V128Load(x)
+            | OperatorCode::V128Store(x)
+            | OperatorCode::MemoryAtomicNotify(x)
+            | OperatorCode::MemoryAtomicWait32(x)
+            | OperatorCode::MemoryAtomicWait64(x)
+            | OperatorCode::I32AtomicLoad(x)
+            | OperatorCode::I64AtomicLoad(x)
+            | OperatorCode::I32AtomicStore(x)
+            | OperatorCode::I64AtomicStore(x)
+            | OperatorCode::I32AtomicRmwAdd(x)
+            | OperatorCode::I64AtomicRmwAdd(x)
+            | OperatorCode::I32AtomicRmwSub(x)
+            | OperatorCode::I64AtomicRmwSub(x)
+            | OperatorCode::I32AtomicRmwAnd(x)
+            | OperatorCode::I64AtomicRmwAnd(x)
+            | OperatorCode::I32AtomicRmwOr(x)
+            | OperatorCode::I64AtomicRmwOr(x)
+            | OperatorCode::I32AtomicRmwXor(x)
+            | OperatorCode::I64AtomicRmwXor(x)
+            | OperatorCode::I32AtomicRmwXchg(x)
+            | OperatorCode::I64AtomicRmwXchg(x)
+            | OperatorCode::I32AtomicRmwCmpxchg(x)
+            | OperatorCode::I64AtomicRmwCmpxchg(x) => x.encode(bytes),
+            // Reserved.
+            OperatorCode::AtomicFence => encode_uint8(0, bytes),
+            OperatorCode::V128Const(x) => bytes.extend_from_slice(&x.to_le_bytes()),
+            OperatorCode::I8x16ExtractLanes(x)
+            | OperatorCode::I8x16ExtractLaneu(x)
+            | OperatorCode::I8x16ReplaceLane(x)
+            | OperatorCode::I16x8ExtractLanes(x)
+            | OperatorCode::I16x8ExtractLaneu(x)
+            | OperatorCode::I16x8ReplaceLane(x)
+            | OperatorCode::I32x4ExtractLane(x)
+            | OperatorCode::I32x4ReplaceLane(x)
+            | OperatorCode::I64x2ExtractLane(x)
+            | OperatorCode::I64x2ReplaceLane(x)
+            | OperatorCode::F32x4ExtractLane(x)
+            | OperatorCode::F32x4ReplaceLane(x)
+            | OperatorCode::F64x2ExtractLane(x)
+            | OperatorCode::F64x2ReplaceLane(x) => encode_uint8(*x, bytes),
+            // Reserved memory index.
+            OperatorCode::CurrentMemory | OperatorCode::GrowMemory => encode_uint8(0, bytes),
+            OperatorCode::I32Const(x) => encode_varint32(*x, bytes),
+            OperatorCode::I64Const(x) => encode_varint64(*x, bytes),
+            OperatorCode::F32Const(x) => bytes.write_f32::<LittleEndian>(*x).unwrap(),
+            OperatorCode::F64Const(x) => bytes.write_f64::<LittleEndian>(*x).unwrap(),
+            _ => {}
+        }
+    }
+}
+
+impl BinaryEncode for TypeSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(1, bytes, |bytes| encode_vec(&self.0, bytes));
+    }
+}
+
+impl BinaryEncode for ImportSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(2, bytes, |bytes| encode_vec(&self.0, bytes));
+    }
+}
+
+impl BinaryEncode for FunctionSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(3, bytes, |bytes| {
+            encode_index(self.0.len(), bytes);
+            for x in &self.0 {
+                encode_index(*x, bytes);
+            }
+        });
+    }
+}
+
+impl BinaryEncode for TableSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(4, bytes, |bytes| encode_vec(&self.0, bytes));
+    }
+}
+
+impl BinaryEncode for MemorySection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(5, bytes, |bytes| encode_vec(&self.0, bytes));
+    }
+}
+
+impl BinaryEncode for GlobalSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(6, bytes, |bytes| encode_vec(&self.0, bytes));
+    }
+}
+
+impl BinaryEncode for ExportSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(7, bytes, |bytes| encode_vec(&self.0, bytes));
+    }
+}
+
+impl BinaryEncode for StartSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(8, bytes, |bytes| encode_index(self.0, bytes));
+    }
+}
+
+impl BinaryEncode for ElementSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(9, bytes, |bytes| encode_vec(&self.0, bytes));
+    }
+}
+
+impl BinaryEncode for CodeSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(10, bytes, |bytes| encode_vec(&self.0, bytes));
+    }
+}
+
+impl BinaryEncode for DataSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(11, bytes, |bytes| encode_vec(&self.0, bytes));
+    }
+}
+
+// A name map: indices in increasing order, each with a name.
+fn encode_name_map(xs: &[(usize, String)], bytes: &mut Vec<u8>) {
+    encode_index(xs.len(), bytes);
+    for (i, x) in xs {
+        encode_index(*i, bytes);
+        encode_string(x, bytes);
+    }
+}
+
+// A custom section with subsections 0 (the module name), 1 (function names) and 2 (local names
+// of each function), left out when empty.
+impl BinaryEncode for NameSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(0, bytes, |bytes| {
+            encode_string("name", bytes);
+            if let Some(x) = &self.module {
+                encode_uint8(0, bytes);
+                encode_sized(bytes, |bytes| encode_string(x, bytes));
+            }
+            if !self.functions.is_empty() {
+                encode_uint8(1, bytes);
+                encode_sized(bytes, |bytes| encode_name_map(&self.functions, bytes));
+            }
+            if !self.locals.is_empty() {
+                encode_uint8(2, bytes);
+                encode_sized(bytes, |bytes| {
+                    encode_index(self.locals.len(), bytes);
+                    for (i, xs) in &self.locals {
+                        encode_index(*i, bytes);
+                        encode_name_map(xs, bytes);
+                    }
+                });
+            }
+        });
+    }
+}
+
+impl BinaryEncode for CustomSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(0, bytes, |bytes| {
+            encode_string(&self.name, bytes);
+            bytes.extend_from_slice(&self.payload);
+        });
+    }
+}
+
+impl BinaryEncode for WasmASTRoot {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.write_to(bytes).unwrap();
+    }
+}
+
+// Encodes a section whose items are encoded one at a time, twice: once for the size of the
+// section, which comes first, and once to write them.
+fn write_items<T: BinaryEncode, W: Write>(
+    id: u8,
+    xs: &[T],
+    w: &mut W,
+    buf: &mut Vec<u8>,
+) -> io::Result<()> {
+    buf.clear();
+    encode_index(xs.len(), buf);
+    let mut size = buf.len();
+    for x in xs {
+        buf.clear();
+        x.encode(buf);
+        size += buf.len();
+    }
+    buf.clear();
+    encode_uint8(id, buf);
+    encode_index(size, buf);
+    encode_index(xs.len(), buf);
+    w.write_all(buf)?;
+    for x in xs {
+        buf.clear();
+        x.encode(buf);
+        w.write_all(buf)?;
+    }
+    Ok(())
+}
+
+impl WasmASTRoot {
+    // Writes a complete `.wasm` file section by section, so that no more than a section, or a
+    // function body or data segment of the code and data sections, is held in memory. Sections
+    // have to appear in this order, each at most once. Custom sections go last.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        fn section<T: BinaryEncode, W: Write>(
+            x: &Option<T>,
+            empty: impl Fn(&T) -> bool,
+            w: &mut W,
+            buf: &mut Vec<u8>,
+        ) -> io::Result<()> {
+            if let Some(x) = x.as_ref().filter(|x| !empty(x)) {
+                buf.clear();
+                x.encode(buf);
+                w.write_all(buf)?;
+            }
+            Ok(())
+        }
+        let buf = &mut Vec::new();
+        w.write_all(b"\0asm")?;
+        w.write_all(&1u32.to_le_bytes())?;
+        section(&self.type_section, |x| x.0.is_empty(), w, buf)?;
+        section(&self.import_section, |x| x.0.is_empty(), w, buf)?;
+        section(&self.function_section, |x| x.0.is_empty(), w, buf)?;
+        section(&self.table_section, |x| x.0.is_empty(), w, buf)?;
+        section(&self.memory_section, |x| x.0.is_empty(), w, buf)?;
+        section(&self.global_section, |x| x.0.is_empty(), w, buf)?;
+        section(&self.export_section, |x| x.0.is_empty(), w, buf)?;
+        section(&self.start_section, |_| false, w, buf)?;
+        section(&self.element_section, |x| x.0.is_empty(), w, buf)?;
+        buf.clear();
+        self.encode_data_count(buf);
+        w.write_all(buf)?;
+        if let Some(x) = self.code_section.as_ref().filter(|x| !x.0.is_empty()) {
+            write_items(10, &x.0, w, buf)?;
+        }
+        if let Some(x) = self.data_section.as_ref().filter(|x| !x.0.is_empty()) {
+            write_items(11, &x.0, w, buf)?;
+        }
+        section(&self.name_section, |_| false, w, buf)?;
+        if let Some(x) = &self.source_mapping_url {
+            buf.clear();
+            encode_section(0, buf, |bytes| {
+                encode_string("sourceMappingURL", bytes);
+                encode_string(x, bytes);
+            });
+            w.write_all(buf)?;
+        }
+        for x in &self.custom_sections {
+            buf.clear();
+            x.encode(buf);
+            w.write_all(buf)?;
+        }
+        Ok(())
+    }
+
+    // The data count section, which `memory.init` and `data.drop` need before the code section.
+    // It is left out for modules without passive segments, which cannot use them.
+    fn encode_data_count(&self, bytes: &mut Vec<u8>) {
+        let datas = self.data_section.as_ref().map_or(&[][..], |x| &x.0);
+        if datas.iter().any(|x| x.offset.is_none()) {
+            encode_section(12, bytes, |bytes| encode_index(datas.len(), bytes));
+        }
+    }
+
+    // A complete `.wasm` file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.encode(&mut bytes);
+        bytes
+    }
+
+    // The offset in `to_bytes` of every instruction of every function body, which is what source
+    // maps of wasm refer to.
+    pub fn code_offsets(&self) -> Vec<Vec<usize>> {
+        let bodies = match &self.code_section {
+            Some(x) if !x.0.is_empty() => &x.0,
+            _ => return Vec::new(),
+        };
+        let leb = |x: usize| {
+            let mut bytes = Vec::new();
+            encode_index(x, &mut bytes);
+            bytes.len()
+        };
+        let before = WasmASTRoot {
+            code_section: None,
+            data_section: None,
+            name_section: None,
+            source_mapping_url: None,
+            custom_sections: Vec::new(),
+            ..self.clone()
+        };
+        // Each body is its size, its locals and then its instructions.
+        let sizes = bodies
+            .iter()
+            .map(|x| {
+                let mut bytes = Vec::new();
+                x.encode(&mut bytes);
+                bytes.len()
+            })
+            .collect::<Vec<_>>();
+        let payload = leb(bodies.len()) + sizes.iter().sum::<usize>();
+        let mut data_count = Vec::new();
+        self.encode_data_count(&mut data_count);
+        let mut pos =
+            before.to_bytes().len() + data_count.len() + 1 + leb(payload) + leb(bodies.len());
+        let mut offsets = Vec::new();
+        for (body, size) in bodies.iter().zip(sizes) {
+            let end = pos + size;
+            let mut bytes = Vec::new();
+            encode_vec(&body.locals, &mut bytes);
+            pos += leb(size - leb(size)) + bytes.len();
+            let mut body_offsets = Vec::new();
+            for x in &body.codes {
+                body_offsets.push(pos);
+                bytes.clear();
+                x.encode(&mut bytes);
+                pos += bytes.len();
+            }
+            offsets.push(body_offsets);
+            pos = end;
+        }
+        offsets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode<T: BinaryEncode>(x: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        x.encode(&mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn section_test() {
+        assert_eq!(
+            encode(&TypeSection(vec![FuncType {
+                params: vec![ValueType::I32, ValueType::I64],
+                results: vec![ValueType::F64],
+            }])),
+            vec![0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7e, 0x01, 0x7c]
+        );
+        assert_eq!(
+            encode(&ImportSection(vec![ImportEntry {
+                module: "env".to_string(),
+                field: "f".to_string(),
+                kind: ExternalKindImport::Function(1),
+            }])),
+            vec![0x02, 0x09, 0x01, 0x03, b'e', b'n', b'v', 0x01, b'f', 0x00, 0x01]
+        );
+        assert_eq!(
+            encode(&MemorySection(vec![MemoryType(ResizableLimits {
+                initial: 1,
+                maximum: Some(2),
+                shared: false,
+            })])),
+            vec![0x05, 0x04, 0x01, 0x01, 0x01, 0x02]
+        );
+        assert_eq!(
+            encode(&GlobalSection(vec![GlobalVariable(
+                GlobalType {
+                    content_type: ValueType::I32,
+                    mutability: true,
+                },
+                InitExpr::I32(-1),
+            )])),
+            vec![0x06, 0x06, 0x01, 0x7f, 0x01, 0x41, 0x7f, 0x0b]
+        );
+        assert_eq!(
+            encode(&ExportSection(vec![ExportEntry {
+                field: "main".to_string(),
+                kind: ExternalKind::Function,
+                index: 0,
+            }])),
+            vec![0x07, 0x08, 0x01, 0x04, b'm', b'a', b'i', b'n', 0x00, 0x00]
+        );
+        assert_eq!(encode(&StartSection(3)), vec![0x08, 0x01, 0x03]);
+        assert_eq!(
+            encode(&ElementSection(vec![ElemSegment {
+                offset: InitExpr::I32(0),
+                elems: vec![0, 2],
+            }])),
+            vec![0x09, 0x08, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x02, 0x00, 0x02]
+        );
+        assert_eq!(
+            encode(&CodeSection(vec![FunctionBody {
+                locals: vec![LocalEntry {
+                    count: 2,
+                    typ: ValueType::I32,
+                }],
+                codes: vec![
+                    OperatorCode::GetLocal(0),
+                    OperatorCode::I32Load(MemoryImmediate {
+                        flags: 2,
+                        offset: 8
+                    }),
+                    OperatorCode::BrTable {
+                        index: 0,
+                        params: vec![1, 2],
+                    },
+                    OperatorCode::F32Const(1.0),
+                    OperatorCode::F64PromoteF32,
+                ],
+            }])),
+            vec![
+                0x0a, 0x16, 0x01, 0x14, 0x01, 0x02, 0x7f, 0x20, 0x00, 0x28, 0x02, 0x08, 0x0e, 0x02,
+                0x01, 0x02, 0x00, 0x43, 0x00, 0x00, 0x80, 0x3f, 0xbb, 0x0b,
+            ]
+        );
+        assert_eq!(
+            encode(&TableSection(vec![TableType {
+                element_type: ElemType::ExternRef,
+                limits: ResizableLimits {
+                    initial: 2,
+                    maximum: None,
+                    shared: false,
+                },
+            }])),
+            vec![0x04, 0x04, 0x01, 0x6f, 0x00, 0x02]
+        );
+        assert_eq!(
+            encode(&CodeSection(vec![FunctionBody {
+                locals: vec![LocalEntry {
+                    count: 1,
+                    typ: ValueType::ExternRef,
+                }],
+                codes: vec![
+                    OperatorCode::I32Const(1),
+                    OperatorCode::TableGet(0),
+                    OperatorCode::RefIsNull,
+                    OperatorCode::Drop,
+                    OperatorCode::I32Const(0),
+                    OperatorCode::RefNull(ElemType::ExternRef),
+                    OperatorCode::TableSet(0),
+                    OperatorCode::RefFunc(1),
+                    OperatorCode::Drop,
+                ],
+            }])),
+            vec![
+                0x0a, 0x15, 0x01, 0x13, 0x01, 0x01, 0x6f, 0x41, 0x01, 0x25, 0x00, 0xd1, 0x1a, 0x41,
+                0x00, 0xd0, 0x6f, 0x26, 0x00, 0xd2, 0x01, 0x1a, 0x0b,
+            ]
+        );
+        assert_eq!(
+            encode(&DataSection(vec![DataSegment {
+                offset: Some(InitExpr::I32(16)),
+                data: b"hi".to_vec(),
+            }])),
+            vec![0x0b, 0x08, 0x01, 0x00, 0x41, 0x10, 0x0b, 0x02, b'h', b'i']
+        );
+        assert_eq!(
+            encode(&NameSection {
+                module: Some("m".to_string()),
+                functions: vec![(1, "f".to_string())],
+                locals: vec![(1, vec![(0, "x".to_string())])],
+            }),
+            vec![
+                0x00, 0x17, 0x04, b'n', b'a', b'm', b'e', 0x00, 0x02, 0x01, b'm', 0x01, 0x04, 0x01,
+                0x01, 0x01, b'f', 0x02, 0x06, 0x01, 0x01, 0x01, 0x00, 0x01, b'x',
+            ]
+        );
+    }
+
+    #[test]
+    fn to_bytes_test() {
+        assert_eq!(
+            WasmASTRoot::default().to_bytes(),
+            vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]
+        );
+        let root = WasmASTRoot {
+            type_section: Some(TypeSection(vec![FuncType {
+                params: vec![],
+                results: vec![ValueType::I32],
+            }])),
+            function_section: Some(FunctionSection(vec![0])),
+            export_section: Some(ExportSection(vec![ExportEntry {
+                field: "f".to_string(),
+                kind: ExternalKind::Function,
+                index: 0,
+            }])),
+            code_section: Some(CodeSection(vec![FunctionBody {
+                locals: vec![],
+                codes: vec![OperatorCode::I32Const(42)],
+            }])),
+            data_section: Some(DataSection(vec![])),
+            ..WasmASTRoot::default()
+        };
+        assert_eq!(
+            root.to_bytes(),
+            vec![
+                0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+                0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // type
+                0x03, 0x02, 0x01, 0x00, // function
+                0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // export
+                0x0a, 0x06, 0x01, 0x04, 0x00, 0x41, 0x2a, 0x0b, // code
+            ]
+        );
+        assert_eq!(root.code_offsets(), vec![vec![31]]);
+
+        let root = WasmASTRoot {
+            source_mapping_url: Some("a.map".to_string()),
+            ..root
+        };
+        assert!(root.to_bytes().ends_with(&[
+            0x00, 0x17, 0x10, b's', b'o', b'u', b'r', b'c', b'e', b'M', b'a', b'p', b'p', b'i',
+            b'n', b'g', b'U', b'R', b'L', 0x05, b'a', b'.', b'm', b'a', b'p',
+        ]));
+    }
+
+    #[test]
+    fn write_to_test() {
+        // Keeps what is written and the size of the largest write.
+        struct Writes(Vec<u8>, usize);
+        impl Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.extend_from_slice(buf);
+                self.1 = self.1.max(buf.len());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let code = CodeSection(vec![
+            FunctionBody {
+                locals: vec![],
+                codes: (0..1000)
+                    .map(|i| match i % 2 {
+                        0 => OperatorCode::I32Const(1),
+                        _ => OperatorCode::Drop,
+                    })
+                    .collect(),
+            };
+            10
+        ]);
+        let data = DataSection(vec![
+            DataSegment {
+                offset: Some(InitExpr::I32(0)),
+                data: vec![7; 3000],
+            };
+            2
+        ]);
+        let root = WasmASTRoot {
+            type_section: Some(TypeSection(vec![FuncType {
+                params: vec![],
+                results: vec![],
+            }])),
+            function_section: Some(FunctionSection(vec![0; 10])),
+            memory_section: Some(MemorySection(vec![MemoryType(ResizableLimits {
+                initial: 1,
+                maximum: None,
+                shared: false,
+            })])),
+            code_section: Some(code.clone()),
+            data_section: Some(data.clone()),
+            ..WasmASTRoot::default()
+        };
+        let mut writes = Writes(Vec::new(), 0);
+        root.write_to(&mut writes).unwrap();
+        let mut expected = root.to_bytes()[..8].to_vec();
+        for x in [
+            encode(root.type_section.as_ref().unwrap()),
+            encode(root.function_section.as_ref().unwrap()),
+            encode(root.memory_section.as_ref().unwrap()),
+            encode(&code),
+            encode(&data),
+        ] {
+            expected.extend(x);
+        }
+        assert_eq!(writes.0, expected);
+        assert!(writes.1 < 4000 && expected.len() > 20000);
+
+        let mut full = [0; 100];
+        assert_eq!(
+            root.write_to(&mut &mut full[..]).unwrap_err().kind(),
+            io::ErrorKind::WriteZero
+        );
+    }
+
+    #[test]
+    fn leb128_test() {
+        let mut bytes = Vec::new();
+        encode_varuint32(624_485, &mut bytes);
+        encode_varint32(-123_456, &mut bytes);
+        encode_varint64(-1, &mut bytes);
+        assert_eq!(bytes, vec![0xe5, 0x8e, 0x26, 0xc0, 0xbb, 0x78, 0x7f]);
+
+        for &x in &[0, 1, 127, 128, 16_383, 16_384, u32::MAX] {
+            let mut bytes = Vec::new();
+            encode_varuint32(x, &mut bytes);
+            assert_eq!(decode_varuint32(&mut &bytes[..]), Ok(x));
+        }
+        for &x in &[0, 63, 64, -64, -65, i32::MIN, i32::MAX] {
+            let mut bytes = Vec::new();
+            encode_varint32(x, &mut bytes);
+            assert_eq!(decode_varint32(&mut &bytes[..]), Ok(x));
+        }
+        for &x in &[0, -1, i64::from(i32::MIN) - 1, i64::MIN, i64::MAX] {
+            let mut bytes = Vec::new();
+            encode_varint64(x, &mut bytes);
+            let mut rest = &bytes[..];
+            assert_eq!(decode_varint64(&mut rest), Ok(x));
+            assert!(rest.is_empty());
+        }
+
+        let mut bytes = Vec::new();
+        encode_varint64(i64::from(u32::MAX) + 1, &mut bytes);
+        assert_eq!(
+            decode_varuint32(&mut &bytes[..]),
+            Err(DecodeError::Overflow)
+        );
+        assert_eq!(decode_varint32(&mut &bytes[..]), Err(DecodeError::Overflow));
+        assert_eq!(
+            decode_varuint32(&mut &[0x80][..]),
+            Err(DecodeError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn float_test() {
+        for &bits in &[0x7fa0_0001u32, 0xffc0_0000, 0x8000_0000, 0x3dcc_cccd] {
+            let bytes = encode(&OperatorCode::F32Const(f32::from_bits(bits)));
+            let x = decode_f32(&mut &bytes[1..]).unwrap();
+            assert_eq!(x.to_bits(), bits);
+            assert_eq!(encode(&OperatorCode::F32Const(x)), bytes);
+        }
+        let bits = 0xfff0_0000_0000_0002u64;
+        let bytes = encode(&InitExpr::F64(f64::from_bits(bits)));
+        let mut rest = &bytes[1..];
+        assert_eq!(decode_f64(&mut rest).map(f64::to_bits), Ok(bits));
+        assert_eq!(rest, [0x0b]);
+        assert_eq!(decode_f64(&mut rest), Err(DecodeError::UnexpectedEof));
+    }
+
+    #[test]
+    fn custom_section_test() {
+        let custom_sections = vec![
+            CustomSection {
+                name: "build".to_string(),
+                payload: b"v1".to_vec(),
+            },
+            CustomSection {
+                name: "hash".to_string(),
+                payload: vec![0xff; 200],
+            },
+        ];
+        let root = WasmASTRoot {
+            type_section: Some(TypeSection(vec![FuncType {
+                params: vec![],
+                results: vec![],
+            }])),
+            name_section: Some(NameSection {
+                module: Some("m".to_string()),
+                ..NameSection::default()
+            }),
+            source_mapping_url: Some("a.map".to_string()),
+            custom_sections: custom_sections.clone(),
+            ..WasmASTRoot::default()
+        };
+        let bytes = root.to_bytes();
+        // After the sections `WasmASTRoot` has fields for.
+        let build = [0x00, 0x08, 0x05, b'b', b'u', b'i', b'l', b'd', b'v', b'1'];
+        let at = |x: &[u8]| bytes.windows(x.len()).position(|y| y == x).unwrap();
+        assert!(at(b"a.map") < at(&build));
+        assert_eq!(decode_custom_sections(&bytes), Ok(custom_sections));
+        assert_eq!(
+            decode_custom_sections(&WasmASTRoot::default().to_bytes()),
+            Ok(vec![])
+        );
+        assert_eq!(
+            decode_custom_sections(b"\0asm\x02\0\0\0"),
+            Err(DecodeError::InvalidHeader)
+        );
+        assert_eq!(
+            decode_custom_sections(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEof)
+        );
+    }
+}
@@ -0,0 +1,226 @@
+// Text forms of floats that read back to the same bits. Numbers are written as the shortest
+// decimal that rounds to them, infinities as `inf`, and NaNs as `nan` when their payload is the
+// canonical one or as `nan:0x..` with their payload otherwise, each with a `-` when the sign bit
+// is set. Hexadecimal floats are read too, rounded once to the precision of the type, and
+// underscores between digits are ignored.
+
+#[derive(Clone, Copy)]
+struct Format {
+    mantissa: u32,
+    exponent: u32,
+}
+
+const F32: Format = Format {
+    mantissa: 23,
+    exponent: 8,
+};
+
+const F64: Format = Format {
+    mantissa: 52,
+    exponent: 11,
+};
+
+impl Format {
+    fn sign(self) -> u64 {
+        1 << (self.mantissa + self.exponent)
+    }
+
+    fn mantissa_mask(self) -> u64 {
+        (1 << self.mantissa) - 1
+    }
+
+    fn max_exponent(self) -> u64 {
+        (1 << self.exponent) - 1
+    }
+
+    fn bias(self) -> i64 {
+        (1 << (self.exponent - 1)) - 1
+    }
+}
+
+pub fn format_f32(x: f32) -> String {
+    format(x.to_bits().into(), F32, || format!("{:?}", x))
+}
+
+pub fn format_f64(x: f64) -> String {
+    format(x.to_bits(), F64, || format!("{:?}", x))
+}
+
+fn format(bits: u64, f: Format, number: impl FnOnce() -> String) -> String {
+    let payload = bits & f.mantissa_mask();
+    if (bits >> f.mantissa) & f.max_exponent() != f.max_exponent() || payload == 0 {
+        return number();
+    }
+    let sign = if bits & f.sign() != 0 { "-" } else { "" };
+    if payload == 1 << (f.mantissa - 1) {
+        format!("{}nan", sign)
+    } else {
+        format!("{}nan:{:#x}", sign, payload)
+    }
+}
+
+pub fn parse_f32(s: &str) -> Option<f32> {
+    let bits = parse(s, F32, |s| {
+        s.parse::<f32>().ok().map(|x| x.to_bits().into())
+    })?;
+    Some(f32::from_bits(bits as u32))
+}
+
+pub fn parse_f64(s: &str) -> Option<f64> {
+    parse(s, F64, |s| s.parse::<f64>().ok().map(f64::to_bits)).map(f64::from_bits)
+}
+
+fn parse(s: &str, f: Format, decimal: impl FnOnce(&str) -> Option<u64>) -> Option<u64> {
+    let s = s.replace('_', "");
+    let (negative, body) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s.strip_prefix('+').unwrap_or(&s)),
+    };
+    let infinity = f.max_exponent() << f.mantissa;
+    let bits = if body == "inf" {
+        infinity
+    } else if body == "nan" {
+        infinity | 1 << (f.mantissa - 1)
+    } else if let Some(payload) = body.strip_prefix("nan:0x") {
+        let payload = u64::from_str_radix(payload, 16).ok()?;
+        if payload == 0 || payload > f.mantissa_mask() {
+            return None;
+        }
+        infinity | payload
+    } else if let Some(h) = body.strip_prefix("0x") {
+        hex(h, f)?
+    } else if body.starts_with(|c: char| c.is_ascii_digit()) {
+        decimal(body)?
+    } else {
+        return None;
+    };
+    Some(if negative { bits | f.sign() } else { bits })
+}
+
+// The bits of a positive hexadecimal float, rounded to the nearest with ties to even, or `None`
+// when it is too large for the type.
+fn hex(s: &str, f: Format) -> Option<u64> {
+    let (mantissa, exp) = match s.find(['p', 'P']) {
+        Some(i) => (&s[..i], s[i + 1..].parse::<i32>().ok()?),
+        None => (s, 0),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, ""),
+    };
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+    // The value is `m * 2^e`, and `sticky` whether digits too small for `m` were not zero.
+    let (mut m, mut e, mut sticky) = (0u64, i64::from(exp), false);
+    for c in int.chars() {
+        let d = u64::from(c.to_digit(16)?);
+        if m >> 60 == 0 {
+            m = m * 16 + d;
+        } else {
+            e += 4;
+            sticky |= d != 0;
+        }
+    }
+    for c in frac.chars() {
+        let d = u64::from(c.to_digit(16)?);
+        if m >> 60 == 0 {
+            m = m * 16 + d;
+            e -= 4;
+        } else {
+            sticky |= d != 0;
+        }
+    }
+    if m == 0 {
+        return Some(0);
+    }
+    // The result is `q * 2^qexp`, where `q` has one bit more than the mantissa for normal
+    // numbers and fewer for subnormal ones.
+    let bits = i64::from(f.mantissa);
+    let top = 63 - i64::from(m.leading_zeros());
+    let mut qexp = (top + e).max(1 - f.bias()) - bits;
+    let shift = qexp - e;
+    let mut q = if shift <= 0 {
+        m << -shift
+    } else if shift >= 128 {
+        0
+    } else {
+        let m = u128::from(m);
+        let q = m >> shift;
+        let rest = m & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        let up = rest > half || (rest == half && (sticky || q & 1 == 1));
+        (q + up as u128) as u64
+    };
+    if q >> (f.mantissa + 1) != 0 {
+        q >>= 1;
+        qexp += 1;
+    }
+    if q >> f.mantissa == 0 {
+        return Some(q);
+    }
+    let biased = qexp + bits + f.bias();
+    if biased >= f.max_exponent() as i64 {
+        return None;
+    }
+    Some((biased as u64) << f.mantissa | (q & f.mantissa_mask()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn float_test() {
+        for &x in &[
+            0.1,
+            -0.0,
+            1.0 / 3.0,
+            f32::MIN_POSITIVE,
+            1e-45,
+            f32::MAX,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NAN,
+            -f32::NAN,
+            f32::from_bits(0x7f80_0001),
+            f32::from_bits(0xffa0_0000),
+        ] {
+            let s = format_f32(x);
+            assert_eq!(parse_f32(&s).map(f32::to_bits), Some(x.to_bits()), "{}", s);
+        }
+        for &x in &[
+            0.1,
+            -0.0,
+            1e300,
+            5e-324,
+            f64::NAN,
+            f64::from_bits(0x7ff0_0000_0000_0001),
+        ] {
+            let s = format_f64(x);
+            assert_eq!(parse_f64(&s).map(f64::to_bits), Some(x.to_bits()), "{}", s);
+        }
+        assert_eq!(format_f32(0.1), "0.1");
+        assert_eq!(format_f32(-f32::NAN), "-nan");
+        assert_eq!(format_f32(f32::from_bits(0x7f80_0001)), "nan:0x1");
+        assert_eq!(format_f64(f64::NEG_INFINITY), "-inf");
+
+        assert_eq!(parse_f32("0x1p-149"), Some(f32::from_bits(1)));
+        assert_eq!(parse_f32("0x1.fffffep127"), Some(f32::MAX));
+        assert_eq!(parse_f32("0x1.ffffffp127"), None);
+        assert_eq!(parse_f32("0x1.8p1"), Some(3.0));
+        assert_eq!(parse_f64("-0x.8"), Some(-0.5));
+        assert_eq!(parse_f64("1_000.5"), Some(1000.5));
+        // Just over halfway between two floats: rounding to `f64` first would land on the
+        // halfway point and then round down to 1.
+        assert_eq!(
+            parse_f32("0x1.000001000000001p0"),
+            Some(f32::from_bits(0x3f80_0001))
+        );
+        assert_eq!(parse_f32("0x1.000001p0"), Some(1.0));
+        assert_eq!(parse_f32("0x1.000003p0"), Some(f32::from_bits(0x3f80_0002)));
+        assert_eq!(parse_f32("nan:0x0"), None);
+        assert_eq!(parse_f32("nan:0x800000"), None);
+        assert_eq!(parse_f32("bogus"), None);
+    }
+}
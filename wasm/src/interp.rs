@@ -0,0 +1,1720 @@
+use crate::ast::*;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+// Runs modules directly. They are not validated first: an instruction that does not find the
+// values it needs traps with `Trap::Invalid`. Only functions can be imported, as closures of the
+// host, and modules have at most one memory and one table.
+
+const PAGE_SIZE: usize = 65536;
+const MAX_PAGES: usize = 65536;
+// Calls nest at most this deep, as `Trap::StackExhausted` says once exceeded.
+const MAX_FRAMES: usize = 10000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    V128(u128),
+    // A function of the module, or null.
+    FuncRef(Option<usize>),
+    // A handle to an object of the host, which modules can only pass around, or null.
+    ExternRef(Option<usize>),
+}
+
+impl Value {
+    pub fn zero(t: &ValueType) -> Value {
+        match t {
+            ValueType::I32 => Value::I32(0),
+            ValueType::I64 => Value::I64(0),
+            ValueType::F32 => Value::F32(0.0),
+            ValueType::F64 => Value::F64(0.0),
+            ValueType::V128 => Value::V128(0),
+            ValueType::FuncRef => Value::FuncRef(None),
+            ValueType::ExternRef => Value::ExternRef(None),
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::I32(_) => ValueType::I32,
+            Value::I64(_) => ValueType::I64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+            Value::V128(_) => ValueType::V128,
+            Value::FuncRef(_) => ValueType::FuncRef,
+            Value::ExternRef(_) => ValueType::ExternRef,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::I32(x) => write!(f, "{}", x),
+            Value::I64(x) => write!(f, "{}", x),
+            Value::F32(x) => write!(f, "{}", x),
+            Value::F64(x) => write!(f, "{}", x),
+            Value::V128(x) => write!(f, "{:#034x}", x),
+            Value::FuncRef(Some(x)) => write!(f, "ref.func {}", x),
+            Value::ExternRef(Some(x)) => write!(f, "ref.extern {}", x),
+            Value::FuncRef(None) | Value::ExternRef(None) => write!(f, "ref.null"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trap {
+    Unreachable,
+    DivisionByZero,
+    IntegerOverflow,
+    InvalidConversion,
+    OutOfBounds,
+    UnalignedAtomic,
+    StackExhausted,
+    UndefinedElement,
+    SignatureMismatch,
+    // The module is not valid.
+    Invalid(String),
+    UnknownImport { module: String, field: String },
+    UnknownExport(String),
+    // Raised by a host function.
+    Host(String),
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trap::Unreachable => write!(f, "unreachable executed"),
+            Trap::DivisionByZero => write!(f, "integer divide by zero"),
+            Trap::IntegerOverflow => write!(f, "integer overflow"),
+            Trap::InvalidConversion => write!(f, "invalid conversion to integer"),
+            Trap::OutOfBounds => write!(f, "out of bounds memory access"),
+            Trap::UnalignedAtomic => write!(f, "unaligned atomic memory access"),
+            Trap::StackExhausted => write!(f, "call stack exhausted"),
+            Trap::UndefinedElement => write!(f, "undefined element"),
+            Trap::SignatureMismatch => write!(f, "indirect call signature mismatch"),
+            Trap::Invalid(message) => write!(f, "invalid module: {}", message),
+            Trap::UnknownImport { module, field } => {
+                write!(f, "unknown import `{}.{}`", module, field)
+            }
+            Trap::UnknownExport(name) => write!(f, "unknown export `{}`", name),
+            Trap::Host(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl error::Error for Trap {}
+
+fn invalid<T>(message: &str) -> Result<T, Trap> {
+    Err(Trap::Invalid(message.to_string()))
+}
+
+// Gets the memory and the arguments, and returns the results of the function.
+pub type HostFunc = Box<dyn FnMut(&mut [u8], &[Value]) -> Result<Vec<Value>, Trap>>;
+
+#[derive(Default)]
+pub struct Imports {
+    funcs: HashMap<(String, String), HostFunc>,
+}
+
+impl Imports {
+    pub fn new() -> Imports {
+        Imports::default()
+    }
+
+    pub fn func(
+        &mut self,
+        module: &str,
+        field: &str,
+        f: impl FnMut(&mut [u8], &[Value]) -> Result<Vec<Value>, Trap> + 'static,
+    ) -> &mut Imports {
+        self.funcs
+            .insert((module.to_string(), field.to_string()), Box::new(f));
+        self
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Label {
+    // The number of values a branch to the label keeps, the height of the stack below them and
+    // where execution continues.
+    arity: usize,
+    height: usize,
+    cont: usize,
+    is_loop: bool,
+}
+
+struct Frame {
+    // The index of the body.
+    body: usize,
+    pc: usize,
+    locals: Vec<Value>,
+    labels: Vec<Label>,
+    height: usize,
+    arity: usize,
+}
+
+pub struct Instance {
+    root: WasmASTRoot,
+    hosts: Vec<HostFunc>,
+    // The type of every function, imports included.
+    funcs: Vec<FuncType>,
+    // For each instruction of each body that opens a block, the `else` or `end` that follows,
+    // and for each `else`, its `end`.
+    pairs: Vec<Vec<usize>>,
+    pub memory: Vec<u8>,
+    max_pages: usize,
+    shared: bool,
+    pub globals: Vec<Value>,
+    table: Vec<Value>,
+    // The data segments `data.drop` has dropped, along with the active ones once copied.
+    dropped: Vec<bool>,
+}
+
+fn pairs(codes: &[OperatorCode]) -> Result<Vec<usize>, Trap> {
+    let mut pairs = vec![0; codes.len()];
+    let mut open = Vec::new();
+    for (i, op) in codes.iter().enumerate() {
+        match op {
+            OperatorCode::Block(_) | OperatorCode::Loop(_) | OperatorCode::If(_) => open.push(i),
+            OperatorCode::Else => match open.pop() {
+                Some(j) if matches!(codes[j], OperatorCode::If(_)) => {
+                    pairs[j] = i;
+                    open.push(i);
+                }
+                _ => return invalid("`else` without `if`"),
+            },
+            OperatorCode::End => match open.pop() {
+                Some(j) => pairs[j] = i,
+                None => return invalid("unbalanced `end`"),
+            },
+            _ => {}
+        }
+    }
+    if !open.is_empty() {
+        return invalid("unterminated block");
+    }
+    Ok(pairs)
+}
+
+fn init_expr(x: &InitExpr, globals: &[Value]) -> Result<Value, Trap> {
+    Ok(match x {
+        InitExpr::I32(x) => Value::I32(*x),
+        InitExpr::I64(x) => Value::I64(*x),
+        InitExpr::F32(x) => Value::F32(*x),
+        InitExpr::F64(x) => Value::F64(*x),
+        InitExpr::Global(i) => match globals.get(*i) {
+            Some(x) => *x,
+            None => return invalid("unknown global"),
+        },
+    })
+}
+
+fn offset(x: &InitExpr, globals: &[Value]) -> Result<usize, Trap> {
+    match init_expr(x, globals)? {
+        Value::I32(x) => Ok(x as u32 as usize),
+        _ => invalid("offset is not an i32"),
+    }
+}
+
+fn pop(stack: &mut Vec<Value>) -> Result<Value, Trap> {
+    match stack.pop() {
+        Some(x) => Ok(x),
+        None => invalid("stack underflow"),
+    }
+}
+
+// The address of an atomic access of `size` bytes, which has to be aligned.
+fn atomic_address(
+    memory: &[u8],
+    stack: &mut Vec<Value>,
+    imm: &MemoryImmediate,
+    size: usize,
+) -> Result<usize, Trap> {
+    let base = pop_i32(stack)?;
+    let addr = address(memory, base, imm, size)?;
+    if addr % size != 0 {
+        return Err(Trap::UnalignedAtomic);
+    }
+    Ok(addr)
+}
+
+// The size of what an atomic instruction accesses, which is also its alignment.
+fn atomic_size(op: &OperatorCode) -> usize {
+    1 << op.natural_alignment().unwrap_or(0)
+}
+
+fn read(memory: &[u8], addr: usize, size: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes[..size].copy_from_slice(&memory[addr..addr + size]);
+    u64::from_le_bytes(bytes)
+}
+
+fn write(memory: &mut [u8], addr: usize, size: usize, x: u64) {
+    memory[addr..addr + size].copy_from_slice(&x.to_le_bytes()[..size]);
+}
+
+// An integer operand 4 or 8 bytes wide, zero-extended.
+fn pop_int(stack: &mut Vec<Value>, size: usize) -> Result<u64, Trap> {
+    if size == 4 {
+        Ok(pop_i32(stack)? as u32 as u64)
+    } else {
+        Ok(pop_i64(stack)? as u64)
+    }
+}
+
+fn int(size: usize, x: u64) -> Value {
+    if size == 4 {
+        Value::I32(x as i32)
+    } else {
+        Value::I64(x as i64)
+    }
+}
+
+// The `n` bytes from `start` of something `len` bytes long, as `memory.copy` and the like check
+// them.
+fn range(len: usize, start: i32, n: i32) -> Result<std::ops::Range<usize>, Trap> {
+    let (start, n) = (start as u32 as usize, n as u32 as usize);
+    if start + n > len {
+        return Err(Trap::OutOfBounds);
+    }
+    Ok(start..start + n)
+}
+
+fn null(t: &ElemType) -> Value {
+    match t {
+        ElemType::AnyFunc => Value::FuncRef(None),
+        ElemType::ExternRef => Value::ExternRef(None),
+    }
+}
+
+fn pop_i32(stack: &mut Vec<Value>) -> Result<i32, Trap> {
+    match pop(stack)? {
+        Value::I32(x) => Ok(x),
+        _ => invalid("expected i32"),
+    }
+}
+
+fn pop_i64(stack: &mut Vec<Value>) -> Result<i64, Trap> {
+    match pop(stack)? {
+        Value::I64(x) => Ok(x),
+        _ => invalid("expected i64"),
+    }
+}
+
+fn pop_f32(stack: &mut Vec<Value>) -> Result<f32, Trap> {
+    match pop(stack)? {
+        Value::F32(x) => Ok(x),
+        _ => invalid("expected f32"),
+    }
+}
+
+fn pop_f64(stack: &mut Vec<Value>) -> Result<f64, Trap> {
+    match pop(stack)? {
+        Value::F64(x) => Ok(x),
+        _ => invalid("expected f64"),
+    }
+}
+
+fn pop_v128(stack: &mut Vec<Value>) -> Result<u128, Trap> {
+    match pop(stack)? {
+        Value::V128(x) => Ok(x),
+        _ => invalid("expected v128"),
+    }
+}
+
+// The lanes of a vector, `bits` wide each, and back.
+fn split(x: u128, bits: u32) -> Vec<u64> {
+    (0..128 / bits)
+        .map(|i| (x >> (i * bits)) as u64 & (u64::MAX >> (64 - bits)))
+        .collect()
+}
+
+fn join(lanes: impl IntoIterator<Item = u64>, bits: u32) -> u128 {
+    lanes.into_iter().zip(0..).fold(0, |v, (x, i)| {
+        v | (x as u128 & ((1 << bits) - 1)) << (i * bits)
+    })
+}
+
+fn splat(x: u64, bits: u32) -> u128 {
+    join(std::iter::repeat_n(x, (128 / bits) as usize), bits)
+}
+
+// Lanes of all ones for true and of zeros for false, as comparisons give.
+fn mask(b: bool) -> u64 {
+    if b {
+        u64::MAX
+    } else {
+        0
+    }
+}
+
+fn lane(stack: &mut Vec<Value>, bits: u32, i: u8) -> Result<u64, Trap> {
+    match split(pop_v128(stack)?, bits).get(i as usize) {
+        Some(x) => Ok(*x),
+        None => invalid("unknown lane"),
+    }
+}
+
+fn replace_lane(stack: &mut Vec<Value>, bits: u32, i: u8, x: u64) -> Result<(), Trap> {
+    let mut lanes = split(pop_v128(stack)?, bits);
+    match lanes.get_mut(i as usize) {
+        Some(lane) => *lane = x,
+        None => return invalid("unknown lane"),
+    }
+    stack.push(Value::V128(join(lanes, bits)));
+    Ok(())
+}
+
+fn f32_lane(x: u64) -> f32 {
+    f32::from_bits(x as u32)
+}
+
+// The number of parameters and results of a block.
+fn block_arity(root: &WasmASTRoot, t: &BlockType) -> Result<(usize, usize), Trap> {
+    match t {
+        BlockType::Empty => Ok((0, 0)),
+        BlockType::Value(_) => Ok((0, 1)),
+        BlockType::Func(i) => match root.type_section.as_ref().and_then(|x| x.0.get(*i)) {
+            Some(t) => Ok((t.params.len(), t.results.len())),
+            None => invalid("unknown type"),
+        },
+    }
+}
+
+// Keeps the top `arity` values of the stack and drops those down to `height`.
+fn unwind(stack: &mut Vec<Value>, height: usize, arity: usize) -> Result<(), Trap> {
+    if stack.len() < height + arity {
+        return invalid("stack underflow");
+    }
+    let keep = stack.split_off(stack.len() - arity);
+    stack.truncate(height);
+    stack.extend(keep);
+    Ok(())
+}
+
+fn branch(frame: &mut Frame, stack: &mut Vec<Value>, depth: usize) -> Result<(), Trap> {
+    let i = match frame.labels.len().checked_sub(depth + 1) {
+        Some(i) => i,
+        None => return invalid("unknown label"),
+    };
+    let label = frame.labels[i];
+    unwind(stack, label.height, label.arity)?;
+    frame.labels.truncate(if label.is_loop { i + 1 } else { i });
+    frame.pc = label.cont;
+    Ok(())
+}
+
+fn address(memory: &[u8], base: i32, imm: &MemoryImmediate, size: usize) -> Result<usize, Trap> {
+    let addr = base as u32 as usize + imm.offset as usize;
+    if addr + size > memory.len() {
+        return Err(Trap::OutOfBounds);
+    }
+    Ok(addr)
+}
+
+fn load<const N: usize>(
+    memory: &[u8],
+    stack: &mut Vec<Value>,
+    imm: &MemoryImmediate,
+) -> Result<[u8; N], Trap> {
+    let base = pop_i32(stack)?;
+    let addr = address(memory, base, imm, N)?;
+    let mut bytes = [0; N];
+    bytes.copy_from_slice(&memory[addr..addr + N]);
+    Ok(bytes)
+}
+
+fn store(
+    memory: &mut [u8],
+    stack: &mut Vec<Value>,
+    imm: &MemoryImmediate,
+    bytes: &[u8],
+) -> Result<(), Trap> {
+    let base = pop_i32(stack)?;
+    let addr = address(memory, base, imm, bytes.len())?;
+    memory[addr..addr + bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
+
+// The integer part of `x` if it is within [min, max).
+fn truncate(x: f64, min: f64, max: f64) -> Result<f64, Trap> {
+    if x.is_nan() {
+        return Err(Trap::InvalidConversion);
+    }
+    let x = x.trunc();
+    if x < min || x >= max {
+        return Err(Trap::IntegerOverflow);
+    }
+    Ok(x)
+}
+
+// `min` and `max` of wasm propagate NaN and order -0 before 0.
+fn fmin(x: f64, y: f64) -> f64 {
+    if x.is_nan() || y.is_nan() {
+        f64::NAN
+    } else if x == y {
+        if x.is_sign_negative() {
+            x
+        } else {
+            y
+        }
+    } else {
+        x.min(y)
+    }
+}
+
+fn fmax(x: f64, y: f64) -> f64 {
+    if x.is_nan() || y.is_nan() {
+        f64::NAN
+    } else if x == y {
+        if x.is_sign_positive() {
+            x
+        } else {
+            y
+        }
+    } else {
+        x.max(y)
+    }
+}
+
+impl Instance {
+    // Instantiates the module and runs its start function.
+    pub fn new(root: WasmASTRoot, mut imports: Imports) -> Result<Instance, Trap> {
+        let types = root.type_section.as_ref().map_or(&[][..], |x| &x.0[..]);
+        let typ = |t: usize| match types.get(t) {
+            Some(x) => Ok(x.clone()),
+            None => invalid("unknown type"),
+        };
+        let mut hosts = Vec::new();
+        let mut funcs = Vec::new();
+        for entry in root.import_section.iter().flat_map(|x| &x.0) {
+            let unknown = || Trap::UnknownImport {
+                module: entry.module.clone(),
+                field: entry.field.clone(),
+            };
+            match &entry.kind {
+                ExternalKindImport::Function(t) => {
+                    let key = (entry.module.clone(), entry.field.clone());
+                    hosts.push(imports.funcs.remove(&key).ok_or_else(unknown)?);
+                    funcs.push(typ(*t)?);
+                }
+                _ => return Err(unknown()),
+            }
+        }
+        for t in root.function_section.iter().flat_map(|x| &x.0) {
+            funcs.push(typ(*t)?);
+        }
+        let pairs = root
+            .code_section
+            .iter()
+            .flat_map(|x| &x.0)
+            .map(|x| pairs(&x.codes))
+            .collect::<Result<Vec<_>, _>>()?;
+        let limits = root.memory_section.as_ref().and_then(|x| x.0.first());
+        let memory = vec![0; limits.map_or(0, |x| x.0.initial as usize * PAGE_SIZE)];
+        let max_pages = limits
+            .and_then(|x| x.0.maximum)
+            .map_or(MAX_PAGES, |x| x as usize);
+        let shared = limits.is_some_and(|x| x.0.shared);
+        let mut globals = Vec::new();
+        for x in root.global_section.iter().flat_map(|x| &x.0) {
+            let value = init_expr(&x.1, &globals)?;
+            globals.push(value);
+        }
+        let table = match root.table_section.as_ref().and_then(|x| x.0.first()) {
+            Some(x) => vec![null(&x.element_type); x.limits.initial as usize],
+            None => Vec::new(),
+        };
+        let mut instance = Instance {
+            root,
+            hosts,
+            funcs,
+            pairs,
+            memory,
+            max_pages,
+            shared,
+            globals,
+            table,
+            dropped: Vec::new(),
+        };
+        for x in instance.root.element_section.iter().flat_map(|x| &x.0) {
+            let start = offset(&x.offset, &instance.globals)?;
+            match instance.table.get_mut(start..start + x.elems.len()) {
+                Some(slots) => {
+                    for (slot, f) in slots.iter_mut().zip(&x.elems) {
+                        *slot = Value::FuncRef(Some(*f));
+                    }
+                }
+                None => return Err(Trap::UndefinedElement),
+            }
+        }
+        for x in instance.root.data_section.iter().flat_map(|x| &x.0) {
+            instance.dropped.push(x.offset.is_some());
+            if let Some(at) = &x.offset {
+                let start = offset(at, &instance.globals)?;
+                match instance.memory.get_mut(start..start + x.data.len()) {
+                    Some(bytes) => bytes.copy_from_slice(&x.data),
+                    None => return Err(Trap::OutOfBounds),
+                }
+            }
+        }
+        if let Some(start) = instance.root.start_section.clone() {
+            instance.call(start.0, &[])?;
+        }
+        Ok(instance)
+    }
+
+    // Calls the function exported as `name`.
+    pub fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, Trap> {
+        let func = self
+            .root
+            .export_section
+            .iter()
+            .flat_map(|x| &x.0)
+            .find(|x| x.field == name && x.kind == ExternalKind::Function)
+            .map(|x| x.index)
+            .ok_or_else(|| Trap::UnknownExport(name.to_string()))?;
+        self.call(func, args)
+    }
+
+    pub fn call(&mut self, func: usize, args: &[Value]) -> Result<Vec<Value>, Trap> {
+        let params = match self.funcs.get(func) {
+            Some(x) => &x.params,
+            None => return invalid("unknown function"),
+        };
+        if params.len() != args.len() || params.iter().zip(args).any(|(t, x)| *t != x.value_type())
+        {
+            return invalid("arguments do not match the parameters");
+        }
+        let mut stack = args.to_vec();
+        let mut frames = Vec::new();
+        self.enter(func, &mut stack, &mut frames)?;
+        self.execute(&mut stack, &mut frames)?;
+        Ok(stack)
+    }
+
+    // Calls a host function right away, or pushes the frame of a function of the module.
+    fn enter(
+        &mut self,
+        func: usize,
+        stack: &mut Vec<Value>,
+        frames: &mut Vec<Frame>,
+    ) -> Result<(), Trap> {
+        let t = match self.funcs.get(func) {
+            Some(x) => x,
+            None => return invalid("unknown function"),
+        };
+        let (params, arity) = (t.params.len(), t.results.len());
+        if stack.len() < params {
+            return invalid("stack underflow");
+        }
+        let args = stack.split_off(stack.len() - params);
+        if func < self.hosts.len() {
+            let result = (self.hosts[func])(&mut self.memory, &args)?;
+            stack.extend(result);
+            return Ok(());
+        }
+        if frames.len() == MAX_FRAMES {
+            return Err(Trap::StackExhausted);
+        }
+        let body = func - self.hosts.len();
+        let code = &self.root.code_section.as_ref().unwrap().0[body];
+        let mut locals = args;
+        for x in &code.locals {
+            locals.extend(std::iter::repeat_n(Value::zero(&x.typ), x.count));
+        }
+        frames.push(Frame {
+            body,
+            pc: 0,
+            locals,
+            labels: vec![Label {
+                arity,
+                height: stack.len(),
+                cont: code.codes.len(),
+                is_loop: false,
+            }],
+            height: stack.len(),
+            arity,
+        });
+        Ok(())
+    }
+
+    fn execute(&mut self, stack: &mut Vec<Value>, frames: &mut Vec<Frame>) -> Result<(), Trap> {
+        use OperatorCode::*;
+        macro_rules! unary {
+            ($pop:ident, |$x:ident| $e:expr) => {{
+                let $x = $pop(stack)?;
+                stack.push($e);
+            }};
+        }
+        macro_rules! binary {
+            ($pop:ident, |$x:ident, $y:ident| $e:expr) => {{
+                let $y = $pop(stack)?;
+                let $x = $pop(stack)?;
+                stack.push($e);
+            }};
+        }
+        macro_rules! lanes {
+            ($bits:expr, |$x:ident, $y:ident| $e:expr) => {{
+                let ys = split(pop_v128(stack)?, $bits);
+                let xs = split(pop_v128(stack)?, $bits);
+                let lanes = xs.into_iter().zip(ys).map(|($x, $y)| $e);
+                stack.push(Value::V128(join(lanes, $bits)));
+            }};
+        }
+        macro_rules! load {
+            ($m:expr, $t:ty, $v:ident) => {{
+                let x = <$t>::from_le_bytes(load(&self.memory, stack, $m)?);
+                stack.push(Value::$v(x.into()));
+            }};
+        }
+        while let Some(frame) = frames.last_mut() {
+            let codes = &self.root.code_section.as_ref().unwrap().0[frame.body].codes;
+            let pairs = &self.pairs[frame.body];
+            let pc = frame.pc;
+            let op = match codes.get(pc) {
+                Some(op) => op,
+                None => {
+                    unwind(stack, frame.height, frame.arity)?;
+                    frames.pop();
+                    continue;
+                }
+            };
+            frame.pc += 1;
+            let memory = &mut self.memory;
+            match op {
+                Unreachable => return Err(Trap::Unreachable),
+                Nop => {}
+                Block(t) | Loop(t) => {
+                    let is_loop = matches!(op, Loop(_));
+                    let (params, results) = block_arity(&self.root, t)?;
+                    if stack.len() < params {
+                        return invalid("stack underflow");
+                    }
+                    frame.labels.push(Label {
+                        arity: if is_loop { params } else { results },
+                        height: stack.len() - params,
+                        cont: if is_loop { pc + 1 } else { pairs[pc] + 1 },
+                        is_loop,
+                    });
+                }
+                If(t) => {
+                    let c = pop_i32(stack)?;
+                    let (params, results) = block_arity(&self.root, t)?;
+                    if stack.len() < params {
+                        return invalid("stack underflow");
+                    }
+                    let other = pairs[pc];
+                    let end = match codes[other] {
+                        Else => pairs[other],
+                        _ => other,
+                    };
+                    frame.labels.push(Label {
+                        arity: results,
+                        height: stack.len() - params,
+                        cont: end + 1,
+                        is_loop: false,
+                    });
+                    if c == 0 {
+                        // Into the `else` branch, or to the `end` that pops the label.
+                        frame.pc = if other == end { end } else { other + 1 };
+                    }
+                }
+                Else => {
+                    let label = frame.labels.pop().unwrap();
+                    frame.pc = label.cont;
+                }
+                End => {
+                    frame.labels.pop();
+                }
+                Br(depth) => branch(frame, stack, *depth)?,
+                BrIf(depth) => {
+                    if pop_i32(stack)? != 0 {
+                        branch(frame, stack, *depth)?;
+                    }
+                }
+                BrTable { index, params } => {
+                    let i = pop_i32(stack)? as u32 as usize;
+                    branch(frame, stack, *params.get(i).unwrap_or(index))?;
+                }
+                Return => {
+                    let depth = frame.labels.len() - 1;
+                    branch(frame, stack, depth)?;
+                }
+                Call(f) => {
+                    let f = *f;
+                    self.enter(f, stack, frames)?;
+                }
+                CallIndirect(t) => {
+                    let i = pop_i32(stack)? as u32 as usize;
+                    let f = match self.table.get(i) {
+                        Some(Value::FuncRef(Some(f))) => *f,
+                        _ => return Err(Trap::UndefinedElement),
+                    };
+                    let expected = self.root.type_section.as_ref().and_then(|x| x.0.get(*t));
+                    if expected != self.funcs.get(f) {
+                        return Err(Trap::SignatureMismatch);
+                    }
+                    self.enter(f, stack, frames)?;
+                }
+                Drop => {
+                    pop(stack)?;
+                }
+                Select => {
+                    let c = pop_i32(stack)?;
+                    let y = pop(stack)?;
+                    let x = pop(stack)?;
+                    stack.push(if c != 0 { x } else { y });
+                }
+                GetLocal(i) => match frame.locals.get(*i) {
+                    Some(x) => stack.push(*x),
+                    None => return invalid("unknown local"),
+                },
+                SetLocal(i) | TeeLocal(i) => {
+                    let x = pop(stack)?;
+                    match frame.locals.get_mut(*i) {
+                        Some(local) => *local = x,
+                        None => return invalid("unknown local"),
+                    }
+                    if matches!(op, TeeLocal(_)) {
+                        stack.push(x);
+                    }
+                }
+                TableGet(0) => {
+                    let i = pop_i32(stack)? as u32 as usize;
+                    match self.table.get(i) {
+                        Some(x) => stack.push(*x),
+                        None => return Err(Trap::OutOfBounds),
+                    }
+                }
+                TableSet(0) => {
+                    let x = pop(stack)?;
+                    let i = pop_i32(stack)? as u32 as usize;
+                    match self.table.get_mut(i) {
+                        Some(slot) if slot.value_type() == x.value_type() => *slot = x,
+                        Some(_) => return invalid("the value does not match the table"),
+                        None => return Err(Trap::OutOfBounds),
+                    }
+                }
+                TableGet(_) | TableSet(_) => return invalid("unknown table"),
+                RefNull(t) => stack.push(null(t)),
+                RefIsNull => {
+                    let x = match pop(stack)? {
+                        Value::FuncRef(x) | Value::ExternRef(x) => x,
+                        _ => return invalid("expected a reference"),
+                    };
+                    stack.push(Value::I32(x.is_none() as i32));
+                }
+                RefFunc(i) => stack.push(Value::FuncRef(Some(*i))),
+                GetGlobal(i) => match self.globals.get(*i) {
+                    Some(x) => stack.push(*x),
+                    None => return invalid("unknown global"),
+                },
+                SetGlobal(i) => {
+                    let x = pop(stack)?;
+                    match self.globals.get_mut(*i) {
+                        Some(global) => *global = x,
+                        None => return invalid("unknown global"),
+                    }
+                }
+                I32Load(m) => load!(m, i32, I32),
+                I64Load(m) => load!(m, i64, I64),
+                F32Load(m) => load!(m, f32, F32),
+                F64Load(m) => load!(m, f64, F64),
+                I32Load8s(m) => load!(m, i8, I32),
+                I32Load8u(m) => load!(m, u8, I32),
+                I32Load16s(m) => load!(m, i16, I32),
+                I32Load16u(m) => load!(m, u16, I32),
+                I64Load8s(m) => load!(m, i8, I64),
+                I64Load8u(m) => load!(m, u8, I64),
+                I64Load16s(m) => load!(m, i16, I64),
+                I64Load16u(m) => load!(m, u16, I64),
+                I64Load32s(m) => load!(m, i32, I64),
+                I64Load32u(m) => load!(m, u32, I64),
+                I32Store(m) => {
+                    let x = pop_i32(stack)?;
+                    store(memory, stack, m, &x.to_le_bytes())?;
+                }
+                I64Store(m) => {
+                    let x = pop_i64(stack)?;
+                    store(memory, stack, m, &x.to_le_bytes())?;
+                }
+                F32Store(m) => {
+                    let x = pop_f32(stack)?;
+                    store(memory, stack, m, &x.to_le_bytes())?;
+                }
+                F64Store(m) => {
+                    let x = pop_f64(stack)?;
+                    store(memory, stack, m, &x.to_le_bytes())?;
+                }
+                I32Store8(m) => {
+                    let x = pop_i32(stack)?;
+                    store(memory, stack, m, &x.to_le_bytes()[..1])?;
+                }
+                I32Store16(m) => {
+                    let x = pop_i32(stack)?;
+                    store(memory, stack, m, &x.to_le_bytes()[..2])?;
+                }
+                I64Store8(m) => {
+                    let x = pop_i64(stack)?;
+                    store(memory, stack, m, &x.to_le_bytes()[..1])?;
+                }
+                I64Store16(m) => {
+                    let x = pop_i64(stack)?;
+                    store(memory, stack, m, &x.to_le_bytes()[..2])?;
+                }
+                I64Store32(m) => {
+                    let x = pop_i64(stack)?;
+                    store(memory, stack, m, &x.to_le_bytes()[..4])?;
+                }
+                CurrentMemory => stack.push(Value::I32((memory.len() / PAGE_SIZE) as i32)),
+                GrowMemory => {
+                    let n = pop_i32(stack)? as u32 as usize;
+                    let pages = memory.len() / PAGE_SIZE;
+                    if pages + n > self.max_pages {
+                        stack.push(Value::I32(-1));
+                    } else {
+                        memory.resize((pages + n) * PAGE_SIZE, 0);
+                        stack.push(Value::I32(pages as i32));
+                    }
+                }
+                MemoryInit(i) => {
+                    let n = pop_i32(stack)?;
+                    let src = pop_i32(stack)?;
+                    let dst = pop_i32(stack)?;
+                    let data = match self.root.data_section.as_ref().and_then(|x| x.0.get(*i)) {
+                        Some(_) if self.dropped[*i] => &[][..],
+                        Some(x) => &x.data[..],
+                        None => return invalid("unknown data segment"),
+                    };
+                    let src = range(data.len(), src, n)?;
+                    let dst = range(memory.len(), dst, n)?;
+                    memory[dst].copy_from_slice(&data[src]);
+                }
+                DataDrop(i) => match self.dropped.get_mut(*i) {
+                    Some(dropped) => *dropped = true,
+                    None => return invalid("unknown data segment"),
+                },
+                MemoryCopy => {
+                    let n = pop_i32(stack)?;
+                    let src = pop_i32(stack)?;
+                    let dst = pop_i32(stack)?;
+                    let src = range(memory.len(), src, n)?;
+                    let dst = range(memory.len(), dst, n)?;
+                    memory.copy_within(src, dst.start);
+                }
+                MemoryFill => {
+                    let n = pop_i32(stack)?;
+                    let x = pop_i32(stack)?;
+                    let dst = pop_i32(stack)?;
+                    let dst = range(memory.len(), dst, n)?;
+                    memory[dst].fill(x as u8);
+                }
+                I32Const(x) => stack.push(Value::I32(*x)),
+                I64Const(x) => stack.push(Value::I64(*x)),
+                F32Const(x) => stack.push(Value::F32(*x)),
+                F64Const(x) => stack.push(Value::F64(*x)),
+
+                I32Eqz => unary!(pop_i32, |x| Value::I32((x == 0) as i32)),
+                I32Eq => binary!(pop_i32, |x, y| Value::I32((x == y) as i32)),
+                I32Ne => binary!(pop_i32, |x, y| Value::I32((x != y) as i32)),
+                I32Lts => binary!(pop_i32, |x, y| Value::I32((x < y) as i32)),
+                I32Ltu => binary!(pop_i32, |x, y| Value::I32(((x as u32) < y as u32) as i32)),
+                I32Gts => binary!(pop_i32, |x, y| Value::I32((x > y) as i32)),
+                I32Gtu => binary!(pop_i32, |x, y| Value::I32((x as u32 > y as u32) as i32)),
+                I32Les => binary!(pop_i32, |x, y| Value::I32((x <= y) as i32)),
+                I32Leu => binary!(pop_i32, |x, y| Value::I32((x as u32 <= y as u32) as i32)),
+                I32Ges => binary!(pop_i32, |x, y| Value::I32((x >= y) as i32)),
+                I32Geu => binary!(pop_i32, |x, y| Value::I32((x as u32 >= y as u32) as i32)),
+                I64Eqz => unary!(pop_i64, |x| Value::I32((x == 0) as i32)),
+                I64Eq => binary!(pop_i64, |x, y| Value::I32((x == y) as i32)),
+                I64Ne => binary!(pop_i64, |x, y| Value::I32((x != y) as i32)),
+                I64Lts => binary!(pop_i64, |x, y| Value::I32((x < y) as i32)),
+                I64Ltu => binary!(pop_i64, |x, y| Value::I32(((x as u64) < y as u64) as i32)),
+                I64Gts => binary!(pop_i64, |x, y| Value::I32((x > y) as i32)),
+                I64Gtu => binary!(pop_i64, |x, y| Value::I32((x as u64 > y as u64) as i32)),
+                I64Les => binary!(pop_i64, |x, y| Value::I32((x <= y) as i32)),
+                I64Leu => binary!(pop_i64, |x, y| Value::I32((x as u64 <= y as u64) as i32)),
+                I64Ges => binary!(pop_i64, |x, y| Value::I32((x >= y) as i32)),
+                I64Geu => binary!(pop_i64, |x, y| Value::I32((x as u64 >= y as u64) as i32)),
+                F32Eq => binary!(pop_f32, |x, y| Value::I32((x == y) as i32)),
+                F32Ne => binary!(pop_f32, |x, y| Value::I32((x != y) as i32)),
+                F32Lt => binary!(pop_f32, |x, y| Value::I32((x < y) as i32)),
+                F32Gt => binary!(pop_f32, |x, y| Value::I32((x > y) as i32)),
+                F32Le => binary!(pop_f32, |x, y| Value::I32((x <= y) as i32)),
+                F32Ge => binary!(pop_f32, |x, y| Value::I32((x >= y) as i32)),
+                F64Eq => binary!(pop_f64, |x, y| Value::I32((x == y) as i32)),
+                F64Ne => binary!(pop_f64, |x, y| Value::I32((x != y) as i32)),
+                F64Lt => binary!(pop_f64, |x, y| Value::I32((x < y) as i32)),
+                F64Gt => binary!(pop_f64, |x, y| Value::I32((x > y) as i32)),
+                F64Le => binary!(pop_f64, |x, y| Value::I32((x <= y) as i32)),
+                F64Ge => binary!(pop_f64, |x, y| Value::I32((x >= y) as i32)),
+
+                I32Clz => unary!(pop_i32, |x| Value::I32(x.leading_zeros() as i32)),
+                I32Ctz => unary!(pop_i32, |x| Value::I32(x.trailing_zeros() as i32)),
+                I32Popcnt => unary!(pop_i32, |x| Value::I32(x.count_ones() as i32)),
+                I32Add => binary!(pop_i32, |x, y| Value::I32(x.wrapping_add(y))),
+                I32Sub => binary!(pop_i32, |x, y| Value::I32(x.wrapping_sub(y))),
+                I32Mul => binary!(pop_i32, |x, y| Value::I32(x.wrapping_mul(y))),
+                I32Divs => binary!(pop_i32, |x, y| match (x, y) {
+                    (_, 0) => return Err(Trap::DivisionByZero),
+                    (i32::MIN, -1) => return Err(Trap::IntegerOverflow),
+                    _ => Value::I32(x / y),
+                }),
+                I32Divu => binary!(pop_i32, |x, y| match (x as u32).checked_div(y as u32) {
+                    Some(z) => Value::I32(z as i32),
+                    None => return Err(Trap::DivisionByZero),
+                }),
+                I32Rems => binary!(pop_i32, |x, y| match y {
+                    0 => return Err(Trap::DivisionByZero),
+                    _ => Value::I32(x.wrapping_rem(y)),
+                }),
+                I32Remu => binary!(pop_i32, |x, y| match (x as u32).checked_rem(y as u32) {
+                    Some(z) => Value::I32(z as i32),
+                    None => return Err(Trap::DivisionByZero),
+                }),
+                I32And => binary!(pop_i32, |x, y| Value::I32(x & y)),
+                I32Or => binary!(pop_i32, |x, y| Value::I32(x | y)),
+                I32Xor => binary!(pop_i32, |x, y| Value::I32(x ^ y)),
+                I32Shl => binary!(pop_i32, |x, y| Value::I32(x.wrapping_shl(y as u32))),
+                I32Shrs => binary!(pop_i32, |x, y| Value::I32(x.wrapping_shr(y as u32))),
+                I32Shru => binary!(pop_i32, |x, y| Value::I32(
+                    (x as u32).wrapping_shr(y as u32) as i32
+                )),
+                I32Rotl => binary!(pop_i32, |x, y| Value::I32(x.rotate_left(y as u32 % 32))),
+                I32Rotr => binary!(pop_i32, |x, y| Value::I32(x.rotate_right(y as u32 % 32))),
+                I64Clz => unary!(pop_i64, |x| Value::I64(x.leading_zeros().into())),
+                I64Ctz => unary!(pop_i64, |x| Value::I64(x.trailing_zeros().into())),
+                I64Popcnt => unary!(pop_i64, |x| Value::I64(x.count_ones().into())),
+                I64Add => binary!(pop_i64, |x, y| Value::I64(x.wrapping_add(y))),
+                I64Sub => binary!(pop_i64, |x, y| Value::I64(x.wrapping_sub(y))),
+                I64Mul => binary!(pop_i64, |x, y| Value::I64(x.wrapping_mul(y))),
+                I64Divs => binary!(pop_i64, |x, y| match (x, y) {
+                    (_, 0) => return Err(Trap::DivisionByZero),
+                    (i64::MIN, -1) => return Err(Trap::IntegerOverflow),
+                    _ => Value::I64(x / y),
+                }),
+                I64Divu => binary!(pop_i64, |x, y| match (x as u64).checked_div(y as u64) {
+                    Some(z) => Value::I64(z as i64),
+                    None => return Err(Trap::DivisionByZero),
+                }),
+                I64Rems => binary!(pop_i64, |x, y| match y {
+                    0 => return Err(Trap::DivisionByZero),
+                    _ => Value::I64(x.wrapping_rem(y)),
+                }),
+                I64Remu => binary!(pop_i64, |x, y| match (x as u64).checked_rem(y as u64) {
+                    Some(z) => Value::I64(z as i64),
+                    None => return Err(Trap::DivisionByZero),
+                }),
+                I64And => binary!(pop_i64, |x, y| Value::I64(x & y)),
+                I64Or => binary!(pop_i64, |x, y| Value::I64(x | y)),
+                I64Xor => binary!(pop_i64, |x, y| Value::I64(x ^ y)),
+                I64Shl => binary!(pop_i64, |x, y| Value::I64(x.wrapping_shl(y as u32))),
+                I64Shrs => binary!(pop_i64, |x, y| Value::I64(x.wrapping_shr(y as u32))),
+                I64Shru => binary!(pop_i64, |x, y| Value::I64(
+                    (x as u64).wrapping_shr(y as u32) as i64
+                )),
+                I64Rotl => binary!(pop_i64, |x, y| Value::I64(
+                    x.rotate_left((y as u64 % 64) as u32)
+                )),
+                I64Rotr => binary!(pop_i64, |x, y| Value::I64(
+                    x.rotate_right((y as u64 % 64) as u32)
+                )),
+
+                F32Abs => unary!(pop_f32, |x| Value::F32(x.abs())),
+                F32Neg => unary!(pop_f32, |x| Value::F32(-x)),
+                F32Ceil => unary!(pop_f32, |x| Value::F32(x.ceil())),
+                F32Floor => unary!(pop_f32, |x| Value::F32(x.floor())),
+                F32Trunc => unary!(pop_f32, |x| Value::F32(x.trunc())),
+                F32Nearest => unary!(pop_f32, |x| Value::F32(x.round_ties_even())),
+                F32Sqrt => unary!(pop_f32, |x| Value::F32(x.sqrt())),
+                F32Add => binary!(pop_f32, |x, y| Value::F32(x + y)),
+                F32Sub => binary!(pop_f32, |x, y| Value::F32(x - y)),
+                F32Mul => binary!(pop_f32, |x, y| Value::F32(x * y)),
+                F32Div => binary!(pop_f32, |x, y| Value::F32(x / y)),
+                F32Min => binary!(pop_f32, |x, y| Value::F32(fmin(x.into(), y.into()) as f32)),
+                F32Max => binary!(pop_f32, |x, y| Value::F32(fmax(x.into(), y.into()) as f32)),
+                F32Copysign => binary!(pop_f32, |x, y| Value::F32(x.copysign(y))),
+                F64Abs => unary!(pop_f64, |x| Value::F64(x.abs())),
+                F64Neg => unary!(pop_f64, |x| Value::F64(-x)),
+                F64Ceil => unary!(pop_f64, |x| Value::F64(x.ceil())),
+                F64Floor => unary!(pop_f64, |x| Value::F64(x.floor())),
+                F64Trunc => unary!(pop_f64, |x| Value::F64(x.trunc())),
+                F64Nearest => unary!(pop_f64, |x| Value::F64(x.round_ties_even())),
+                F64Sqrt => unary!(pop_f64, |x| Value::F64(x.sqrt())),
+                F64Add => binary!(pop_f64, |x, y| Value::F64(x + y)),
+                F64Sub => binary!(pop_f64, |x, y| Value::F64(x - y)),
+                F64Mul => binary!(pop_f64, |x, y| Value::F64(x * y)),
+                F64Div => binary!(pop_f64, |x, y| Value::F64(x / y)),
+                F64Min => binary!(pop_f64, |x, y| Value::F64(fmin(x, y))),
+                F64Max => binary!(pop_f64, |x, y| Value::F64(fmax(x, y))),
+                F64Copysign => binary!(pop_f64, |x, y| Value::F64(x.copysign(y))),
+
+                I32WrapI64 => unary!(pop_i64, |x| Value::I32(x as i32)),
+                I32TruncsF32 => {
+                    unary!(pop_f32, |x| Value::I32(
+                        truncate(x.into(), -2147483648.0, 2147483648.0)? as i32
+                    ))
+                }
+                I32TrancuF32 => {
+                    unary!(pop_f32, |x| Value::I32(
+                        truncate(x.into(), 0.0, 4294967296.0)? as u32 as i32
+                    ))
+                }
+                I32TrancsF64 => {
+                    unary!(pop_f64, |x| Value::I32(
+                        truncate(x, -2147483648.0, 2147483648.0)? as i32
+                    ))
+                }
+                I32TrancuF64 => unary!(pop_f64, |x| Value::I32(
+                    truncate(x, 0.0, 4294967296.0)? as u32 as i32
+                )),
+                I64ExtendsI32 => unary!(pop_i32, |x| Value::I64(x.into())),
+                I64ExtenduI32 => unary!(pop_i32, |x| Value::I64((x as u32).into())),
+                I64TruncsF32 => unary!(pop_f32, |x| Value::I64(truncate(
+                    x.into(),
+                    -9223372036854775808.0,
+                    9223372036854775808.0
+                )? as i64)),
+                I64TrancuF32 => {
+                    unary!(pop_f32, |x| Value::I64(
+                        truncate(x.into(), 0.0, 18446744073709551616.0)? as u64 as i64
+                    ))
+                }
+                I64TrancsF64 => unary!(pop_f64, |x| Value::I64(truncate(
+                    x,
+                    -9223372036854775808.0,
+                    9223372036854775808.0
+                )? as i64)),
+                I64TrancuF64 => {
+                    unary!(pop_f64, |x| Value::I64(
+                        truncate(x, 0.0, 18446744073709551616.0)? as u64 as i64
+                    ))
+                }
+                F32ConvertsI32 => unary!(pop_i32, |x| Value::F32(x as f32)),
+                F32ConvertuI32 => unary!(pop_i32, |x| Value::F32(x as u32 as f32)),
+                F32ConvertsI64 => unary!(pop_i64, |x| Value::F32(x as f32)),
+                F32ConvertuI64 => unary!(pop_i64, |x| Value::F32(x as u64 as f32)),
+                F32DemoteF64 => unary!(pop_f64, |x| Value::F32(x as f32)),
+                F64ConvertsI32 => unary!(pop_i32, |x| Value::F64(x.into())),
+                F64ConvertuI32 => unary!(pop_i32, |x| Value::F64((x as u32).into())),
+                F64ConvertsI64 => unary!(pop_i64, |x| Value::F64(x as f64)),
+                F64ConvertuI64 => unary!(pop_i64, |x| Value::F64(x as u64 as f64)),
+                F64PromoteF32 => unary!(pop_f32, |x| Value::F64(x.into())),
+                I32ReinterpretF32 => unary!(pop_f32, |x| Value::I32(x.to_bits() as i32)),
+                I64ReinterpretF64 => unary!(pop_f64, |x| Value::I64(x.to_bits() as i64)),
+                F32ReinterpretI32 => unary!(pop_i32, |x| Value::F32(f32::from_bits(x as u32))),
+                F64ReinterpretI64 => unary!(pop_i64, |x| Value::F64(f64::from_bits(x as u64))),
+
+                V128Load(m) => load!(m, u128, V128),
+                V128Store(m) => {
+                    let x = pop_v128(stack)?;
+                    store(memory, stack, m, &x.to_le_bytes())?;
+                }
+                V128Const(x) => stack.push(Value::V128(*x)),
+                I8x16Splat => unary!(pop_i32, |x| Value::V128(splat(x as u64, 8))),
+                I16x8Splat => unary!(pop_i32, |x| Value::V128(splat(x as u64, 16))),
+                I32x4Splat => unary!(pop_i32, |x| Value::V128(splat(x as u64, 32))),
+                I64x2Splat => unary!(pop_i64, |x| Value::V128(splat(x as u64, 64))),
+                F32x4Splat => unary!(pop_f32, |x| Value::V128(splat(x.to_bits().into(), 32))),
+                F64x2Splat => unary!(pop_f64, |x| Value::V128(splat(x.to_bits(), 64))),
+                I8x16ExtractLanes(i) => {
+                    let x = lane(stack, 8, *i)?;
+                    stack.push(Value::I32((x as i8).into()));
+                }
+                I8x16ExtractLaneu(i) => {
+                    let x = lane(stack, 8, *i)?;
+                    stack.push(Value::I32(x as i32));
+                }
+                I16x8ExtractLanes(i) => {
+                    let x = lane(stack, 16, *i)?;
+                    stack.push(Value::I32((x as i16).into()));
+                }
+                I16x8ExtractLaneu(i) => {
+                    let x = lane(stack, 16, *i)?;
+                    stack.push(Value::I32(x as i32));
+                }
+                I32x4ExtractLane(i) => {
+                    let x = lane(stack, 32, *i)?;
+                    stack.push(Value::I32(x as i32));
+                }
+                I64x2ExtractLane(i) => {
+                    let x = lane(stack, 64, *i)?;
+                    stack.push(Value::I64(x as i64));
+                }
+                F32x4ExtractLane(i) => {
+                    let x = lane(stack, 32, *i)?;
+                    stack.push(Value::F32(f32_lane(x)));
+                }
+                F64x2ExtractLane(i) => {
+                    let x = lane(stack, 64, *i)?;
+                    stack.push(Value::F64(f64::from_bits(x)));
+                }
+                I8x16ReplaceLane(i) | I16x8ReplaceLane(i) | I32x4ReplaceLane(i) => {
+                    let bits = match op {
+                        I8x16ReplaceLane(_) => 8,
+                        I16x8ReplaceLane(_) => 16,
+                        _ => 32,
+                    };
+                    let x = pop_i32(stack)?;
+                    replace_lane(stack, bits, *i, x as u64)?;
+                }
+                I64x2ReplaceLane(i) => {
+                    let x = pop_i64(stack)?;
+                    replace_lane(stack, 64, *i, x as u64)?;
+                }
+                F32x4ReplaceLane(i) => {
+                    let x = pop_f32(stack)?;
+                    replace_lane(stack, 32, *i, x.to_bits().into())?;
+                }
+                F64x2ReplaceLane(i) => {
+                    let x = pop_f64(stack)?;
+                    replace_lane(stack, 64, *i, x.to_bits())?;
+                }
+                I8x16Eq => lanes!(8, |x, y| mask(x == y)),
+                I8x16Ne => lanes!(8, |x, y| mask(x != y)),
+                I16x8Eq => lanes!(16, |x, y| mask(x == y)),
+                I16x8Ne => lanes!(16, |x, y| mask(x != y)),
+                I32x4Eq => lanes!(32, |x, y| mask(x == y)),
+                I32x4Ne => lanes!(32, |x, y| mask(x != y)),
+                F32x4Eq => lanes!(32, |x, y| mask(f32_lane(x) == f32_lane(y))),
+                F32x4Ne => lanes!(32, |x, y| mask(f32_lane(x) != f32_lane(y))),
+                F64x2Eq => lanes!(64, |x, y| mask(f64::from_bits(x) == f64::from_bits(y))),
+                F64x2Ne => lanes!(64, |x, y| mask(f64::from_bits(x) != f64::from_bits(y))),
+                V128Not => unary!(pop_v128, |x| Value::V128(!x)),
+                V128And => binary!(pop_v128, |x, y| Value::V128(x & y)),
+                V128AndNot => binary!(pop_v128, |x, y| Value::V128(x & !y)),
+                V128Or => binary!(pop_v128, |x, y| Value::V128(x | y)),
+                V128Xor => binary!(pop_v128, |x, y| Value::V128(x ^ y)),
+                V128Bitselect => {
+                    let c = pop_v128(stack)?;
+                    binary!(pop_v128, |x, y| Value::V128(x & c | y & !c))
+                }
+                V128AnyTrue => unary!(pop_v128, |x| Value::I32((x != 0) as i32)),
+                I8x16AllTrue | I16x8AllTrue | I32x4AllTrue | I64x2AllTrue => {
+                    let bits = match op {
+                        I8x16AllTrue => 8,
+                        I16x8AllTrue => 16,
+                        I32x4AllTrue => 32,
+                        _ => 64,
+                    };
+                    let x = pop_v128(stack)?;
+                    let all = split(x, bits).iter().all(|&x| x != 0);
+                    stack.push(Value::I32(all as i32));
+                }
+                // The top bit of each lane.
+                I8x16Bitmask | I16x8Bitmask | I32x4Bitmask | I64x2Bitmask => {
+                    let bits = match op {
+                        I8x16Bitmask => 8,
+                        I16x8Bitmask => 16,
+                        I32x4Bitmask => 32,
+                        _ => 64,
+                    };
+                    let x = pop_v128(stack)?;
+                    let mask = split(x, bits)
+                        .iter()
+                        .zip(0..)
+                        .fold(0, |m, (x, i)| m | ((x >> (bits - 1)) as i32) << i);
+                    stack.push(Value::I32(mask));
+                }
+                I8x16Add => lanes!(8, |x, y| x.wrapping_add(y)),
+                I8x16Sub => lanes!(8, |x, y| x.wrapping_sub(y)),
+                I16x8Add => lanes!(16, |x, y| x.wrapping_add(y)),
+                I16x8Sub => lanes!(16, |x, y| x.wrapping_sub(y)),
+                I16x8Mul => lanes!(16, |x, y| x.wrapping_mul(y)),
+                I32x4Add => lanes!(32, |x, y| x.wrapping_add(y)),
+                I32x4Sub => lanes!(32, |x, y| x.wrapping_sub(y)),
+                I32x4Mul => lanes!(32, |x, y| x.wrapping_mul(y)),
+                I64x2Add => lanes!(64, |x, y| x.wrapping_add(y)),
+                I64x2Sub => lanes!(64, |x, y| x.wrapping_sub(y)),
+                I64x2Mul => lanes!(64, |x, y| x.wrapping_mul(y)),
+                F32x4Add => lanes!(32, |x, y| (f32_lane(x) + f32_lane(y)).to_bits().into()),
+                F32x4Sub => lanes!(32, |x, y| (f32_lane(x) - f32_lane(y)).to_bits().into()),
+                F32x4Mul => lanes!(32, |x, y| (f32_lane(x) * f32_lane(y)).to_bits().into()),
+                F32x4Div => lanes!(32, |x, y| (f32_lane(x) / f32_lane(y)).to_bits().into()),
+                F64x2Add => lanes!(64, |x, y| (f64::from_bits(x) + f64::from_bits(y)).to_bits()),
+                F64x2Sub => lanes!(64, |x, y| (f64::from_bits(x) - f64::from_bits(y)).to_bits()),
+                F64x2Mul => lanes!(64, |x, y| (f64::from_bits(x) * f64::from_bits(y)).to_bits()),
+                F64x2Div => lanes!(64, |x, y| (f64::from_bits(x) / f64::from_bits(y)).to_bits()),
+
+                // There is only one thread, so nothing ever waits to be notified.
+                MemoryAtomicNotify(m) => {
+                    pop_i32(stack)?;
+                    atomic_address(memory, stack, m, 4)?;
+                    stack.push(Value::I32(0));
+                }
+                // 1 when the value is not the expected one and 2 once the timeout is over.
+                MemoryAtomicWait32(m) | MemoryAtomicWait64(m) => {
+                    let size = atomic_size(op);
+                    let timeout = pop_i64(stack)?;
+                    let expected = pop_int(stack, size)?;
+                    let addr = atomic_address(memory, stack, m, size)?;
+                    if !self.shared {
+                        return invalid("waiting on memory that is not shared");
+                    }
+                    if read(memory, addr, size) != expected {
+                        stack.push(Value::I32(1));
+                    } else if timeout >= 0 {
+                        stack.push(Value::I32(2));
+                    } else {
+                        return invalid("waiting forever with no other thread to notify");
+                    }
+                }
+                AtomicFence => {}
+                I32AtomicLoad(m) | I64AtomicLoad(m) => {
+                    let size = atomic_size(op);
+                    let addr = atomic_address(memory, stack, m, size)?;
+                    stack.push(int(size, read(memory, addr, size)));
+                }
+                I32AtomicStore(m) | I64AtomicStore(m) => {
+                    let size = atomic_size(op);
+                    let x = pop_int(stack, size)?;
+                    let addr = atomic_address(memory, stack, m, size)?;
+                    write(memory, addr, size, x);
+                }
+                I32AtomicRmwCmpxchg(m) | I64AtomicRmwCmpxchg(m) => {
+                    let size = atomic_size(op);
+                    let replacement = pop_int(stack, size)?;
+                    let expected = pop_int(stack, size)?;
+                    let addr = atomic_address(memory, stack, m, size)?;
+                    let old = read(memory, addr, size);
+                    if old == expected {
+                        write(memory, addr, size, replacement);
+                    }
+                    stack.push(int(size, old));
+                }
+                I32AtomicRmwAdd(m) | I32AtomicRmwSub(m) | I32AtomicRmwAnd(m)
+                | I32AtomicRmwOr(m) | I32AtomicRmwXor(m) | I32AtomicRmwXchg(m)
+                | I64AtomicRmwAdd(m) | I64AtomicRmwSub(m) | I64AtomicRmwAnd(m)
+                | I64AtomicRmwOr(m) | I64AtomicRmwXor(m) | I64AtomicRmwXchg(m) => {
+                    let size = atomic_size(op);
+                    let x = pop_int(stack, size)?;
+                    let addr = atomic_address(memory, stack, m, size)?;
+                    let old = read(memory, addr, size);
+                    let new = match op {
+                        I32AtomicRmwAdd(_) | I64AtomicRmwAdd(_) => old.wrapping_add(x),
+                        I32AtomicRmwSub(_) | I64AtomicRmwSub(_) => old.wrapping_sub(x),
+                        I32AtomicRmwAnd(_) | I64AtomicRmwAnd(_) => old & x,
+                        I32AtomicRmwOr(_) | I64AtomicRmwOr(_) => old | x,
+                        I32AtomicRmwXor(_) | I64AtomicRmwXor(_) => old ^ x,
+                        _ => x,
+                    };
+                    write(memory, addr, size, new);
+                    stack.push(int(size, old));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wat::print_module;
+    use crate::wat_parser::parse_module;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn interp_test() {
+        let src = r#"
+(module
+  (import "env" "log" (func $log (param i32)))
+  (memory 1 2)
+  (table 2 anyfunc)
+  (global $calls (mut i32) (i32.const 0))
+  (elem (i32.const 0) $fact $sum)
+  (data (i32.const 8) "hi")
+  (type $unary (func (param i64) (result i64)))
+  (func $fact (export "fact") (param $n i64) (result i64)
+    (global.set $calls (i32.add (global.get $calls) (i32.const 1)))
+    (if (result i64) (i64.le_s (local.get $n) (i64.const 1))
+      (then (i64.const 1))
+      (else (i64.mul (local.get $n) (call $fact (i64.sub (local.get $n) (i64.const 1)))))))
+  (func $sum (export "sum") (param $n i64) (result i64)
+    (local $acc i64)
+    (block $done
+      (loop $next
+        (br_if $done (i64.eqz (local.get $n)))
+        (local.set $acc (i64.add (local.get $acc) (local.get $n)))
+        (local.set $n (i64.sub (local.get $n) (i64.const 1)))
+        (br $next)))
+    (local.get $acc))
+  (func (export "apply") (param i32 i64) (result i64)
+    (call_indirect (type $unary) (local.get 1) (local.get 0)))
+  (func (export "pick") (param i32) (result i32)
+    (block (block (block (br_table 0 1 2 (local.get 0)))
+      (return (i32.const 10)))
+      (return (i32.const 20)))
+    (i32.const 30))
+  (func (export "memory") (result i32)
+    (call $log (i32.load16_u (i32.const 8)))
+    (i32.store8 (i32.const 10) (i32.const 0x21))
+    (drop (memory.grow (i32.const 1)))
+    (i32.add (memory.size) (memory.grow (i32.const 1))))
+  (func (export "div") (param i32 i32) (result i32)
+    (i32.div_s (local.get 0) (local.get 1)))
+  (func (export "trunc") (param f64) (result i32)
+    (i32.trunc_f64_s (local.get 0)))
+  (func $loop (export "loop") (call $loop)))
+"#;
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut imports = Imports::new();
+        {
+            let log = log.clone();
+            imports.func("env", "log", move |memory, args| {
+                log.borrow_mut().push((args[0], memory[10]));
+                Ok(Vec::new())
+            });
+        }
+        let mut instance = Instance::new(parse_module(src).unwrap(), imports).unwrap();
+
+        assert_eq!(
+            instance.invoke("fact", &[Value::I64(20)]),
+            Ok(vec![Value::I64(2432902008176640000)])
+        );
+        assert_eq!(instance.globals, vec![Value::I32(20)]);
+        assert_eq!(
+            instance.invoke("sum", &[Value::I64(100)]),
+            Ok(vec![Value::I64(5050)])
+        );
+        assert_eq!(
+            instance.invoke("apply", &[Value::I32(1), Value::I64(4)]),
+            Ok(vec![Value::I64(10)])
+        );
+        assert_eq!(
+            instance.invoke("apply", &[Value::I32(2), Value::I64(4)]),
+            Err(Trap::UndefinedElement)
+        );
+        let picks = (0..4)
+            .map(|i| instance.invoke("pick", &[Value::I32(i)]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            picks,
+            vec![
+                Ok(vec![Value::I32(10)]),
+                Ok(vec![Value::I32(20)]),
+                Ok(vec![Value::I32(30)]),
+                Ok(vec![Value::I32(30)]),
+            ]
+        );
+
+        // The second `memory.grow` goes past the maximum of 2 pages.
+        assert_eq!(instance.invoke("memory", &[]), Ok(vec![Value::I32(1)]));
+        assert_eq!(*log.borrow(), vec![(Value::I32(0x6968), 0)]);
+        assert_eq!(&instance.memory[8..11], b"hi!");
+
+        assert_eq!(
+            instance.invoke("div", &[Value::I32(-7), Value::I32(2)]),
+            Ok(vec![Value::I32(-3)])
+        );
+        assert_eq!(
+            instance.invoke("div", &[Value::I32(1), Value::I32(0)]),
+            Err(Trap::DivisionByZero)
+        );
+        assert_eq!(
+            instance.invoke("div", &[Value::I32(i32::MIN), Value::I32(-1)]),
+            Err(Trap::IntegerOverflow)
+        );
+        assert_eq!(
+            instance.invoke("trunc", &[Value::F64(-2147483648.9)]),
+            Ok(vec![Value::I32(i32::MIN)])
+        );
+        assert_eq!(
+            instance.invoke("trunc", &[Value::F64(f64::NAN)]),
+            Err(Trap::InvalidConversion)
+        );
+        assert_eq!(instance.invoke("loop", &[]), Err(Trap::StackExhausted));
+        assert_eq!(
+            instance.invoke("nothing", &[]),
+            Err(Trap::UnknownExport("nothing".to_string()))
+        );
+
+        assert_eq!(
+            Instance::new(parse_module(src).unwrap(), Imports::new()).err(),
+            Some(Trap::UnknownImport {
+                module: "env".to_string(),
+                field: "log".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn multi_value_test() {
+        let src = r#"
+(module
+  (func $divmod (export "divmod") (param i32 i32) (result i32 i32)
+    (i32.div_u (local.get 0) (local.get 1))
+    (i32.rem_u (local.get 0) (local.get 1)))
+  (func (export "swap_sub") (param i32 i32) (result i32)
+    (local.get 0)
+    (local.get 1)
+    (block (param i32 i32) (result i32 i32)
+      (local.set 1)
+      (local.set 0)
+      (local.get 1)
+      (local.get 0))
+    (i32.sub))
+  (func (export "sum") (param i32) (result i32)
+    (i32.const 0)
+    (local.get 0)
+    (loop $next (param i32 i32) (result i32)
+      (local.set 0)
+      (local.get 0)
+      (i32.add)
+      (local.get 0)
+      (i32.const 1)
+      (i32.sub)
+      (local.tee 0)
+      (br_if $next (i32.ne (local.get 0) (i32.const 0)))
+      (drop))
+    (call $divmod (i32.const 1))
+    (drop)))
+"#;
+        let root = parse_module(src).unwrap();
+        let bytes = root.to_bytes();
+        // `block` and `loop` with the types of `divmod` and `swap_sub`.
+        assert!(bytes.windows(2).any(|x| x == [0x02, 0x00]));
+        assert!(bytes.windows(2).any(|x| x == [0x03, 0x01]));
+        let mut instance = Instance::new(root, Imports::new()).unwrap();
+        assert_eq!(
+            instance.invoke("divmod", &[Value::I32(17), Value::I32(5)]),
+            Ok(vec![Value::I32(3), Value::I32(2)])
+        );
+        assert_eq!(
+            instance.invoke("swap_sub", &[Value::I32(10), Value::I32(3)]),
+            Ok(vec![Value::I32(-7)])
+        );
+        assert_eq!(
+            instance.invoke("sum", &[Value::I32(10)]),
+            Ok(vec![Value::I32(55)])
+        );
+    }
+
+    #[test]
+    fn reference_types_test() {
+        let src = r#"
+(module
+  (table $handles 2 externref)
+  ;; `ref.func` only refers to functions that are declared elsewhere, such as in an export.
+  (func $seven (export "f") (result i32) (i32.const 7))
+  (func (export "keep") (param i32 externref)
+    (table.set $handles (local.get 0) (local.get 1)))
+  (func (export "get") (param i32) (result externref)
+    (table.get (local.get 0)))
+  (func (export "is_null") (param externref) (result i32)
+    (ref.is_null (local.get 0)))
+  (func (export "null") (result funcref)
+    (ref.null func))
+  (func (export "seven") (result funcref)
+    (ref.func $seven)))
+"#;
+        let root = parse_module(src).unwrap();
+        assert_eq!(parse_module(&print_module(&root)), Ok(root.clone()));
+        let mut instance = Instance::new(root, Imports::new()).unwrap();
+        let handle = Value::ExternRef(Some(42));
+        assert_eq!(
+            instance.invoke("keep", &[Value::I32(1), handle]),
+            Ok(vec![])
+        );
+        assert_eq!(instance.invoke("get", &[Value::I32(1)]), Ok(vec![handle]));
+        assert_eq!(
+            instance.invoke("get", &[Value::I32(0)]),
+            Ok(vec![Value::ExternRef(None)])
+        );
+        assert_eq!(
+            instance.invoke("get", &[Value::I32(2)]),
+            Err(Trap::OutOfBounds)
+        );
+        assert_eq!(
+            instance.invoke("is_null", &[handle]),
+            Ok(vec![Value::I32(0)])
+        );
+        assert_eq!(
+            instance.invoke("is_null", &[Value::ExternRef(None)]),
+            Ok(vec![Value::I32(1)])
+        );
+        assert_eq!(instance.invoke("null", &[]), Ok(vec![Value::FuncRef(None)]));
+        assert_eq!(
+            instance.invoke("seven", &[]),
+            Ok(vec![Value::FuncRef(Some(0))])
+        );
+    }
+
+    #[test]
+    fn bulk_memory_test() {
+        let src = r#"
+(module
+  (memory 1)
+  (data (i32.const 0) "abc")
+  (data $greeting "hello")
+  (func (export "init") (param i32 i32 i32)
+    (memory.init $greeting (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "drop")
+    (data.drop $greeting))
+  (func (export "copy") (param i32 i32 i32)
+    (memory.copy (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "fill") (param i32 i32 i32)
+    (memory.fill (local.get 0) (local.get 1) (local.get 2))))
+"#;
+        let root = parse_module(src).unwrap();
+        assert_eq!(root.data_section.as_ref().unwrap().0[1].offset, None);
+        assert_eq!(parse_module(&print_module(&root)), Ok(root.clone()));
+        // The data count section, between the export and code sections.
+        let bytes = root.to_bytes();
+        assert!(bytes.windows(3).any(|x| x == [0x0c, 0x01, 0x02]));
+
+        let mut instance = Instance::new(root, Imports::new()).unwrap();
+        let mut run = |name: &str, x: i32, y: i32, n: i32| {
+            let args = [Value::I32(x), Value::I32(y), Value::I32(n)];
+            instance.invoke(name, &args).map(|_| ())
+        };
+        assert_eq!(run("init", 4, 1, 4), Ok(()));
+        assert_eq!(run("copy", 8, 0, 7), Ok(()));
+        assert_eq!(run("fill", 1, 0x7a, 2), Ok(()));
+        assert_eq!(run("init", 0, 3, 3), Err(Trap::OutOfBounds));
+        assert_eq!(run("copy", 65535, 0, 2), Err(Trap::OutOfBounds));
+        assert_eq!(run("init", 0, 0, 0), Ok(()));
+        let memory = instance.memory[..15].to_vec();
+        assert_eq!(memory, b"azz\0elloabc\0ell");
+        instance.invoke("drop", &[]).unwrap();
+        let mut run = |x: i32, y: i32, n: i32| {
+            let args = [Value::I32(x), Value::I32(y), Value::I32(n)];
+            instance.invoke("init", &args)
+        };
+        assert_eq!(run(0, 0, 0), Ok(vec![]));
+        assert_eq!(run(0, 0, 1), Err(Trap::OutOfBounds));
+    }
+
+    #[test]
+    fn simd_test() {
+        let src = r#"
+(module
+  (memory 1)
+  (data (i32.const 0) "0123456789abcdef0123456789abcdeX")
+  ;; Whether the 16 bytes at both addresses are the same.
+  (func (export "same") (param i32 i32) (result i32)
+    (i8x16.all_true (i8x16.eq (v128.load (local.get 0)) (v128.load (local.get 1)))))
+  ;; The index of the first byte that differs, or 16.
+  (func (export "mismatch") (param i32 i32) (result i32)
+    (i32.ctz (i32.or
+      (i8x16.bitmask (v128.not (i8x16.eq (v128.load (local.get 0)) (v128.load (local.get 1)))))
+      (i32.const 0x10000))))
+  (func (export "sum") (result i32)
+    (local $v v128)
+    (local.set $v (i32x4.mul
+      (i32x4.add (v128.const i32x4 1 2 3 4) (i32x4.splat (i32.const 10)))
+      (v128.const i8x16 2 0 0 0 2 0 0 0 2 0 0 0 -1 -1 -1 -1)))
+    (i32.add
+      (i32.add (i32x4.extract_lane 0 (local.get $v)) (i32x4.extract_lane 1 (local.get $v)))
+      (i32.add (i32x4.extract_lane 2 (local.get $v)) (i32x4.extract_lane 3 (local.get $v)))))
+  (func (export "lanes") (result f64)
+    (f64x2.extract_lane 1 (f64x2.replace_lane 1
+      (f64x2.mul (v128.const f64x2 1.5 2.5) (f64x2.splat (f64.const 2)))
+      (f64.add
+        (f64x2.extract_lane 0 (f64x2.splat (f64.const 0.25)))
+        (f64.convert_i32_s (i8x16.extract_lane_s 15 (v128.const i8x16 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 -3)))))))
+  (func (export "store") (param v128)
+    (v128.store offset=32 (i32.const 0) (local.get 0))))
+"#;
+        let root = parse_module(src).unwrap();
+        assert_eq!(parse_module(&print_module(&root)), Ok(root.clone()));
+        // `i32x4.add` after the prefix, as a LEB128 opcode, and `i32x4.extract_lane 3`.
+        let bytes = root.to_bytes();
+        assert!(bytes.windows(3).any(|x| x == [0xfd, 0xae, 0x01]));
+        assert!(bytes.windows(3).any(|x| x == [0xfd, 0x1b, 0x03]));
+
+        let mut instance = Instance::new(root, Imports::new()).unwrap();
+        let mut run = |name: &str, args: &[Value]| instance.invoke(name, args).unwrap();
+        assert_eq!(
+            run("same", &[Value::I32(0), Value::I32(0)]),
+            [Value::I32(1)]
+        );
+        assert_eq!(
+            run("same", &[Value::I32(0), Value::I32(16)]),
+            [Value::I32(0)]
+        );
+        assert_eq!(
+            run("mismatch", &[Value::I32(0), Value::I32(16)]),
+            [Value::I32(15)]
+        );
+        assert_eq!(
+            run("mismatch", &[Value::I32(1), Value::I32(1)]),
+            [Value::I32(16)]
+        );
+        assert_eq!(run("sum", &[]), [Value::I32(22 + 24 + 26 - 14)]);
+        assert_eq!(run("lanes", &[]), [Value::F64(-2.75)]);
+        let x = 0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10;
+        assert_eq!(run("store", &[Value::V128(x)]), []);
+        assert_eq!(instance.memory[32..48], x.to_le_bytes());
+    }
+
+    #[test]
+    fn atomics_test() {
+        let src = r#"
+(module
+  (memory 1 1 shared)
+  ;; A counter at 8, which `add` bumps and returns the old value of.
+  (func (export "add") (param i32) (result i32)
+    (i32.atomic.rmw.add (i32.const 8) (local.get 0)))
+  (func (export "swap") (param i64 i64) (result i64)
+    (i64.atomic.rmw.cmpxchg offset=16 (i32.const 0) (local.get 0) (local.get 1)))
+  (func (export "load") (param i32) (result i32)
+    (atomic.fence)
+    (i32.atomic.load (local.get 0)))
+  (func (export "wait") (param i32) (result i32)
+    (memory.atomic.wait32 (i32.const 8) (local.get 0) (i64.const 0)))
+  (func (export "notify") (result i32)
+    (memory.atomic.notify (i32.const 8) (i32.const 1))))
+"#;
+        let root = parse_module(src).unwrap();
+        assert_eq!(parse_module(&print_module(&root)), Ok(root.clone()));
+        // The limits of a shared memory with a maximum, and `i32.atomic.rmw.add` with its
+        // alignment.
+        let bytes = root.to_bytes();
+        assert!(bytes.windows(3).any(|x| x == [0x03, 0x01, 0x01]));
+        assert!(bytes.windows(3).any(|x| x == [0xfe, 0x1e, 0x02]));
+
+        let mut instance = Instance::new(root, Imports::new()).unwrap();
+        assert_eq!(
+            instance.invoke("add", &[Value::I32(5)]),
+            Ok(vec![Value::I32(0)])
+        );
+        assert_eq!(
+            instance.invoke("add", &[Value::I32(-1)]),
+            Ok(vec![Value::I32(5)])
+        );
+        assert_eq!(
+            instance.invoke("load", &[Value::I32(8)]),
+            Ok(vec![Value::I32(4)])
+        );
+        assert_eq!(
+            instance.invoke("load", &[Value::I32(6)]),
+            Err(Trap::UnalignedAtomic)
+        );
+        assert_eq!(
+            instance.invoke("swap", &[Value::I64(1), Value::I64(2)]),
+            Ok(vec![Value::I64(0)])
+        );
+        assert_eq!(
+            instance.invoke("swap", &[Value::I64(0), Value::I64(3)]),
+            Ok(vec![Value::I64(0)])
+        );
+        assert_eq!(instance.memory[16], 3);
+        assert_eq!(
+            instance.invoke("wait", &[Value::I32(5)]),
+            Ok(vec![Value::I32(1)])
+        );
+        assert_eq!(
+            instance.invoke("wait", &[Value::I32(4)]),
+            Ok(vec![Value::I32(2)])
+        );
+        assert_eq!(instance.invoke("notify", &[]), Ok(vec![Value::I32(0)]));
+    }
+}
@@ -1,2 +1,9 @@
 pub mod ast;
-pub mod encode;
\ No newline at end of file
+pub mod builder;
+pub mod encode;
+pub mod float;
+pub mod interp;
+pub mod link;
+pub mod optimize;
+pub mod wat;
+pub mod wat_parser;
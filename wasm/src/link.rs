@@ -0,0 +1,505 @@
+use crate::ast::*;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+// Merges modules into one. Imports from another of the modules, by its name, are resolved to
+// what it exports; the other imports stay, once each. Functions and globals are numbered with
+// the remaining imports first and then the definitions of each module in turn, and types are
+// shared. All modules use the same memory: the data of each is moved past that of the ones
+// before it, together with the addresses its relocations point out. At most one module may have
+// a table.
+
+// A module to link.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Object {
+    // What other modules import it as.
+    pub name: String,
+    pub root: WasmASTRoot,
+    // The `i32.const` instructions holding addresses of data, by function body and instruction,
+    // and the globals initialized to such addresses.
+    pub code_relocations: Vec<(usize, usize)>,
+    pub global_relocations: Vec<usize>,
+}
+
+impl Object {
+    pub fn new(name: String, root: WasmASTRoot) -> Object {
+        Object {
+            name,
+            root,
+            code_relocations: Vec::new(),
+            global_relocations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkError {
+    // The module imported from does not export the field.
+    Unresolved { module: String, field: String },
+    // Imports that lead back to themselves without reaching a definition.
+    Cycle { module: String, field: String },
+    DuplicateExport(String),
+    MultipleTables,
+    MultipleStarts,
+    // A relocation of something that is not a constant address, or a data segment that is not
+    // at a constant address.
+    Relocation(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::Unresolved { module, field } => {
+                write!(f, "`{}` does not export `{}`", module, field)
+            }
+            LinkError::Cycle { module, field } => {
+                write!(f, "import cycle through `{}.{}`", module, field)
+            }
+            LinkError::DuplicateExport(name) => write!(f, "`{}` is exported twice", name),
+            LinkError::MultipleTables => write!(f, "more than one module has a table"),
+            LinkError::MultipleStarts => write!(f, "more than one module has a start function"),
+            LinkError::Relocation(module) => write!(f, "invalid relocation in `{}`", module),
+        }
+    }
+}
+
+impl error::Error for LinkError {}
+
+// Where an index of a module ends up: an import of the output or a definition of a module.
+#[derive(Clone, Copy)]
+enum Target {
+    Import(usize),
+    Defined(usize),
+}
+
+fn imports<'a>(
+    root: &'a WasmASTRoot,
+    f: impl Fn(&ExternalKindImport) -> bool + 'a,
+) -> impl Iterator<Item = &'a ImportEntry> + 'a {
+    root.import_section
+        .iter()
+        .flat_map(|x| &x.0)
+        .filter(move |x| f(&x.kind))
+}
+
+fn is_func(x: &ExternalKindImport) -> bool {
+    matches!(x, ExternalKindImport::Function(_))
+}
+
+fn is_global(x: &ExternalKindImport) -> bool {
+    matches!(x, ExternalKindImport::Global(_))
+}
+
+struct Linker<'a> {
+    objects: &'a [Object],
+    modules: HashMap<&'a str, usize>,
+    types: Vec<FuncType>,
+    type_maps: Vec<Vec<usize>>,
+    imports: Vec<ImportEntry>,
+    // The first function and global each module defines in the output, counted without imports.
+    funcs: Vec<usize>,
+    globals: Vec<usize>,
+}
+
+impl<'a> Linker<'a> {
+    fn import(&mut self, entry: &ImportEntry, kind: ExternalKindImport) -> usize {
+        let entry = ImportEntry {
+            kind,
+            ..entry.clone()
+        };
+        match self.imports.iter().position(|x| *x == entry) {
+            Some(i) => i,
+            None => {
+                self.imports.push(entry);
+                self.imports.len() - 1
+            }
+        }
+    }
+
+    // The target of index `i` of kind `kind` in module `k`, following imports between modules.
+    fn resolve(
+        &mut self,
+        k: usize,
+        i: usize,
+        kind: ExternalKind,
+        seen: &mut Vec<(usize, usize)>,
+    ) -> Result<Target, LinkError> {
+        let object = &self.objects[k];
+        let filter = match kind {
+            ExternalKind::Function => is_func,
+            _ => is_global,
+        };
+        let entry = match imports(&object.root, filter).nth(i) {
+            Some(x) => x,
+            None => {
+                let count = imports(&object.root, filter).count();
+                let base = match kind {
+                    ExternalKind::Function => self.funcs[k],
+                    _ => self.globals[k],
+                };
+                return Ok(Target::Defined(base + i - count));
+            }
+        };
+        let j = match self.modules.get(entry.module.as_str()) {
+            Some(&j) => j,
+            None => {
+                let import = match &entry.kind {
+                    ExternalKindImport::Function(t) => {
+                        ExternalKindImport::Function(self.type_maps[k][*t])
+                    }
+                    x => x.clone(),
+                };
+                return Ok(Target::Import(self.import(entry, import)));
+            }
+        };
+        if seen.contains(&(k, i)) {
+            return Err(LinkError::Cycle {
+                module: entry.module.clone(),
+                field: entry.field.clone(),
+            });
+        }
+        seen.push((k, i));
+        let export = self.objects[j]
+            .root
+            .export_section
+            .iter()
+            .flat_map(|x| &x.0)
+            .find(|x| x.field == entry.field && x.kind == kind)
+            .ok_or_else(|| LinkError::Unresolved {
+                module: entry.module.clone(),
+                field: entry.field.clone(),
+            })?;
+        self.resolve(j, export.index, kind, seen)
+    }
+
+    fn map(&mut self, k: usize, kind: ExternalKind) -> Result<Vec<Target>, LinkError> {
+        let root = &self.objects[k].root;
+        let count = match kind {
+            ExternalKind::Function => {
+                imports(root, is_func).count()
+                    + root.function_section.as_ref().map_or(0, |x| x.0.len())
+            }
+            _ => {
+                imports(root, is_global).count()
+                    + root.global_section.as_ref().map_or(0, |x| x.0.len())
+            }
+        };
+        (0..count)
+            .map(|i| self.resolve(k, i, kind.clone(), &mut Vec::new()))
+            .collect()
+    }
+}
+
+fn relocate(x: &mut i32, base: u32, name: &str) -> Result<(), LinkError> {
+    *x = x
+        .checked_add(base as i32)
+        .ok_or_else(|| LinkError::Relocation(name.to_string()))?;
+    Ok(())
+}
+
+pub fn link(objects: &[Object]) -> Result<WasmASTRoot, LinkError> {
+    let mut linker = Linker {
+        objects,
+        modules: objects
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.name.as_str(), i))
+            .collect(),
+        types: Vec::new(),
+        type_maps: Vec::new(),
+        imports: Vec::new(),
+        funcs: Vec::new(),
+        globals: Vec::new(),
+    };
+    let (mut funcs, mut globals) = (0, 0);
+    for object in objects {
+        let mut map = Vec::new();
+        for t in object.root.type_section.iter().flat_map(|x| &x.0) {
+            map.push(match linker.types.iter().position(|x| x == t) {
+                Some(i) => i,
+                None => {
+                    linker.types.push(t.clone());
+                    linker.types.len() - 1
+                }
+            });
+        }
+        linker.type_maps.push(map);
+        linker.funcs.push(funcs);
+        linker.globals.push(globals);
+        funcs += object
+            .root
+            .function_section
+            .as_ref()
+            .map_or(0, |x| x.0.len());
+        globals += object.root.global_section.as_ref().map_or(0, |x| x.0.len());
+    }
+    let mut func_maps = Vec::new();
+    let mut global_maps = Vec::new();
+    for k in 0..objects.len() {
+        func_maps.push(linker.map(k, ExternalKind::Function)?);
+        global_maps.push(linker.map(k, ExternalKind::Global)?);
+    }
+    // Memories and tables are shared, so only those from elsewhere are imported.
+    for object in objects {
+        for x in imports(&object.root, |x| !is_func(x) && !is_global(x)) {
+            if !linker.modules.contains_key(x.module.as_str()) {
+                linker.import(x, x.kind.clone());
+            }
+        }
+    }
+    // Only now is the number of imports known, which definitions are numbered after. Imports of
+    // all kinds are in `linker.imports`, but each kind is numbered on its own.
+    let number = |targets: Vec<Target>, kind: fn(&ExternalKindImport) -> bool| {
+        let imports = linker.imports.iter().filter(|x| kind(&x.kind)).count();
+        targets
+            .into_iter()
+            .map(|x| match x {
+                Target::Import(i) => linker.imports[..i].iter().filter(|x| kind(&x.kind)).count(),
+                Target::Defined(i) => imports + i,
+            })
+            .collect::<Vec<_>>()
+    };
+    let func_maps = func_maps
+        .into_iter()
+        .map(|x| number(x, is_func))
+        .collect::<Vec<_>>();
+    let global_maps = global_maps
+        .into_iter()
+        .map(|x| number(x, is_global))
+        .collect::<Vec<_>>();
+
+    let mut root = WasmASTRoot {
+        type_section: Some(TypeSection(linker.types.clone())),
+        import_section: Some(ImportSection(linker.imports.clone())),
+        ..WasmASTRoot::default()
+    };
+    let mut signatures = Vec::new();
+    let mut bodies = Vec::new();
+    let mut global_vars = Vec::new();
+    let mut exports: Vec<ExportEntry> = Vec::new();
+    let mut datas = Vec::new();
+    let mut memory: Option<ResizableLimits> = None;
+    let mut names = NameSection::default();
+    // The end of the data laid out so far.
+    let mut end = 0u32;
+    for (k, object) in objects.iter().enumerate() {
+        let (types, funcs, globals) = (&linker.type_maps[k], &func_maps[k], &global_maps[k]);
+        let name = &object.name;
+        let src = &object.root;
+        let base = (end + 7) & !7;
+
+        let mut module_datas = src.data_section.clone().map_or(Vec::new(), |x| x.0);
+        for x in &mut module_datas {
+            match &mut x.offset {
+                Some(InitExpr::I32(offset)) => {
+                    relocate(offset, base, name)?;
+                    end = end.max(*offset as u32 + x.data.len() as u32);
+                }
+                // Passive segments are placed by `memory.init`.
+                None => {}
+                _ => return Err(LinkError::Relocation(name.clone())),
+            }
+        }
+        let data_base = datas.len();
+        datas.extend(module_datas);
+
+        signatures.extend(
+            src.function_section
+                .iter()
+                .flat_map(|x| &x.0)
+                .map(|t| types[*t]),
+        );
+        let mut module_bodies = src.code_section.clone().map_or(Vec::new(), |x| x.0);
+        for &(body, i) in &object.code_relocations {
+            match module_bodies.get_mut(body).and_then(|x| x.codes.get_mut(i)) {
+                Some(OperatorCode::I32Const(x)) => relocate(x, base, name)?,
+                _ => return Err(LinkError::Relocation(name.clone())),
+            }
+        }
+        for op in module_bodies.iter_mut().flat_map(|x| &mut x.codes) {
+            match op {
+                OperatorCode::Call(i) | OperatorCode::RefFunc(i) => *i = funcs[*i],
+                OperatorCode::CallIndirect(t)
+                | OperatorCode::Block(BlockType::Func(t))
+                | OperatorCode::Loop(BlockType::Func(t))
+                | OperatorCode::If(BlockType::Func(t)) => *t = types[*t],
+                OperatorCode::GetGlobal(i) | OperatorCode::SetGlobal(i) => *i = globals[*i],
+                OperatorCode::MemoryInit(i) | OperatorCode::DataDrop(i) => *i += data_base,
+                _ => {}
+            }
+        }
+        bodies.extend(module_bodies);
+
+        let mut module_globals = src.global_section.clone().map_or(Vec::new(), |x| x.0);
+        for &i in &object.global_relocations {
+            match module_globals.get_mut(i).map(|x| &mut x.1) {
+                Some(InitExpr::I32(x)) => relocate(x, base, name)?,
+                _ => return Err(LinkError::Relocation(name.clone())),
+            }
+        }
+        for x in &mut module_globals {
+            if let InitExpr::Global(i) = &mut x.1 {
+                *i = globals[*i];
+            }
+        }
+        global_vars.extend(module_globals);
+
+        for x in src.memory_section.iter().flat_map(|x| &x.0) {
+            memory = Some(match memory {
+                Some(m) => ResizableLimits {
+                    initial: m.initial.max(x.0.initial),
+                    maximum: m.maximum.zip(x.0.maximum).map(|(a, b)| a.max(b)),
+                    shared: m.shared || x.0.shared,
+                },
+                None => x.0.clone(),
+            });
+        }
+        if let Some(tables) = &src.table_section {
+            if root.table_section.is_some() {
+                return Err(LinkError::MultipleTables);
+            }
+            root.table_section = Some(tables.clone());
+        }
+        if let Some(elems) = &src.element_section {
+            let mut elems = elems.clone();
+            for x in elems.0.iter_mut().flat_map(|x| &mut x.elems) {
+                *x = funcs[*x];
+            }
+            root.element_section = Some(elems);
+        }
+        if let Some(start) = &src.start_section {
+            if root.start_section.is_some() {
+                return Err(LinkError::MultipleStarts);
+            }
+            root.start_section = Some(StartSection(funcs[start.0]));
+        }
+        for x in src.export_section.iter().flat_map(|x| &x.0) {
+            let index = match x.kind {
+                ExternalKind::Function => funcs[x.index],
+                ExternalKind::Global => globals[x.index],
+                ExternalKind::Table | ExternalKind::Memory => x.index,
+            };
+            let export = ExportEntry { index, ..x.clone() };
+            match exports.iter().find(|y| y.field == x.field) {
+                // Every module exports the one memory.
+                Some(y) if *y == export && x.kind == ExternalKind::Memory => {}
+                Some(_) => return Err(LinkError::DuplicateExport(x.field.clone())),
+                None => exports.push(export),
+            }
+        }
+        if let Some(x) = &src.name_section {
+            names
+                .functions
+                .extend(x.functions.iter().map(|(i, x)| (funcs[*i], x.clone())));
+            names
+                .locals
+                .extend(x.locals.iter().map(|(i, x)| (funcs[*i], x.clone())));
+        }
+        root.custom_sections
+            .extend(src.custom_sections.iter().cloned());
+    }
+    if let Some(mut limits) = memory {
+        let pages = (end as usize).div_ceil(65536) as i32;
+        limits.initial = limits.initial.max(pages);
+        root.memory_section = Some(MemorySection(vec![MemoryType(limits)]));
+    }
+    root.function_section = Some(FunctionSection(signatures));
+    root.global_section = Some(GlobalSection(global_vars));
+    root.export_section = Some(ExportSection(exports));
+    root.code_section = Some(CodeSection(bodies));
+    root.data_section = Some(DataSection(datas));
+    if !names.functions.is_empty() || !names.locals.is_empty() {
+        names.functions.sort_by_key(|x| x.0);
+        names.locals.sort_by_key(|x| x.0);
+        root.name_section = Some(names);
+    }
+    Ok(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wat::print_module;
+    use crate::wat_parser::parse_module;
+
+    #[test]
+    fn link_test() {
+        let a = parse_module(
+            r#"(module
+  (func (export "add") (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    i32.add)
+  (memory 1)
+  (export "memory" (memory 0))
+  (data (i32.const 0) "abc"))"#,
+        )
+        .unwrap();
+        let b = parse_module(
+            r#"(module
+  (import "env" "log" (func (param i32)))
+  (import "a" "add" (func (param i32 i32) (result i32)))
+  (global (mut i32) (i32.const 4))
+  (func (export "main")
+    i32.const 1
+    i32.const 2
+    call 1
+    call 0
+    i32.const 4
+    global.set 0)
+  (memory 1)
+  (export "memory" (memory 0))
+  (data (i32.const 4) "d"))"#,
+        )
+        .unwrap();
+        let b = Object {
+            code_relocations: vec![(0, 4)],
+            global_relocations: vec![0],
+            ..Object::new("b".to_string(), b)
+        };
+        let root = link(&[Object::new("a".to_string(), a.clone()), b.clone()]).unwrap();
+        assert_eq!(
+            print_module(&root),
+            r#"(module
+  (type (;0;) (func (param i32 i32) (result i32)))
+  (type (;1;) (func (param i32)))
+  (type (;2;) (func))
+  (import "env" "log" (func (;0;) (type 1)))
+  (func (;1;) (type 0) (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    i32.add)
+  (func (;2;) (type 2)
+    i32.const 1
+    i32.const 2
+    call 1
+    call 0
+    i32.const 12
+    global.set 0)
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) (i32.const 12))
+  (export "add" (func 1))
+  (export "memory" (memory 0))
+  (export "main" (func 2))
+  (data (;0;) (i32.const 0) "abc")
+  (data (;1;) (i32.const 12) "d"))
+"#
+        );
+
+        assert_eq!(
+            link(&[Object::new("a".to_string(), WasmASTRoot::default()), b]),
+            Err(LinkError::Unresolved {
+                module: "a".to_string(),
+                field: "add".to_string(),
+            })
+        );
+        assert_eq!(
+            link(&[
+                Object::new("a".to_string(), a.clone()),
+                Object::new("c".to_string(), a)
+            ]),
+            Err(LinkError::DuplicateExport("add".to_string()))
+        );
+    }
+}
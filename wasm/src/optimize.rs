@@ -0,0 +1,751 @@
+use crate::ast::*;
+use std::fmt;
+
+// Passes over function bodies that keep their behaviour but make them smaller:
+//
+// * Operations on constants are folded, unless they would trap.
+// * Code after `unreachable`, `return` and unconditional branches is removed up to the end of
+//   its block.
+// * Locals that are never read are no longer written, and locals that are neither read nor
+//   written are removed.
+// * `local.set` followed by `local.get` of the same local becomes `local.tee`, and constants
+//   and locals that are dropped right away go away.
+//
+// Instructions move, so spans recorded for source maps no longer apply afterwards.
+
+// The size of the module before and after `optimize`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub before: usize,
+    pub after: usize,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let saved = self.before.saturating_sub(self.after);
+        write!(
+            f,
+            "{} -> {} bytes ({:.1}% smaller)",
+            self.before,
+            self.after,
+            saved as f64 * 100.0 / self.before.max(1) as f64
+        )
+    }
+}
+
+pub fn optimize(root: &mut WasmASTRoot) -> Report {
+    let before = root.to_bytes().len();
+    let params = root
+        .function_section
+        .iter()
+        .flat_map(|x| &x.0)
+        .map(|&t| {
+            root.type_section
+                .as_ref()
+                .map_or(0, |types| types.0[t].params.len())
+        })
+        .collect::<Vec<_>>();
+    for (body, params) in root
+        .code_section
+        .iter_mut()
+        .flat_map(|x| &mut x.0)
+        .zip(params)
+    {
+        fold_constants(&mut body.codes);
+        remove_dead_code(&mut body.codes);
+        remove_unused_locals(body, params);
+        peephole(&mut body.codes);
+    }
+    Report {
+        before,
+        after: root.to_bytes().len(),
+    }
+}
+
+fn fold_unary(op: &OperatorCode, x: &OperatorCode) -> Option<OperatorCode> {
+    use OperatorCode::*;
+    let folded = match (op, x) {
+        (I32Eqz, I32Const(x)) => I32Const((*x == 0) as i32),
+        (I32Clz, I32Const(x)) => I32Const(x.leading_zeros() as i32),
+        (I32Ctz, I32Const(x)) => I32Const(x.trailing_zeros() as i32),
+        (I32Popcnt, I32Const(x)) => I32Const(x.count_ones() as i32),
+        (I64Eqz, I64Const(x)) => I32Const((*x == 0) as i32),
+        (I64Clz, I64Const(x)) => I64Const(x.leading_zeros().into()),
+        (I64Ctz, I64Const(x)) => I64Const(x.trailing_zeros().into()),
+        (I64Popcnt, I64Const(x)) => I64Const(x.count_ones().into()),
+        (F32Neg, F32Const(x)) => F32Const(-x),
+        (F32Abs, F32Const(x)) => F32Const(x.abs()),
+        (F64Neg, F64Const(x)) => F64Const(-x),
+        (F64Abs, F64Const(x)) => F64Const(x.abs()),
+        (I32WrapI64, I64Const(x)) => I32Const(*x as i32),
+        (I64ExtendsI32, I32Const(x)) => I64Const((*x).into()),
+        (I64ExtenduI32, I32Const(x)) => I64Const((*x as u32).into()),
+        (F32ConvertsI32, I32Const(x)) => F32Const(*x as f32),
+        (F32ConvertsI64, I64Const(x)) => F32Const(*x as f32),
+        (F64ConvertsI32, I32Const(x)) => F64Const((*x).into()),
+        (F64ConvertsI64, I64Const(x)) => F64Const(*x as f64),
+        (F32DemoteF64, F64Const(x)) => F32Const(*x as f32),
+        (F64PromoteF32, F32Const(x)) => F64Const((*x).into()),
+        _ => return None,
+    };
+    Some(folded)
+}
+
+// Divisions by zero and of the minimum by -1 trap, so they are left to run.
+fn fold_binary(op: &OperatorCode, x: &OperatorCode, y: &OperatorCode) -> Option<OperatorCode> {
+    use OperatorCode::*;
+    let folded = match (x, y) {
+        (I32Const(x), I32Const(y)) => {
+            let (x, y) = (*x, *y);
+            let (ux, uy) = (x as u32, y as u32);
+            match op {
+                I32Add => I32Const(x.wrapping_add(y)),
+                I32Sub => I32Const(x.wrapping_sub(y)),
+                I32Mul => I32Const(x.wrapping_mul(y)),
+                I32Divs => I32Const(x.checked_div(y)?),
+                I32Divu => I32Const(ux.checked_div(uy)? as i32),
+                I32Rems if y != 0 => I32Const(x.wrapping_rem(y)),
+                I32Remu => I32Const(ux.checked_rem(uy)? as i32),
+                I32And => I32Const(x & y),
+                I32Or => I32Const(x | y),
+                I32Xor => I32Const(x ^ y),
+                I32Shl => I32Const(x.wrapping_shl(uy)),
+                I32Shrs => I32Const(x.wrapping_shr(uy)),
+                I32Shru => I32Const(ux.wrapping_shr(uy) as i32),
+                I32Rotl => I32Const(x.rotate_left(uy % 32)),
+                I32Rotr => I32Const(x.rotate_right(uy % 32)),
+                I32Eq => I32Const((x == y) as i32),
+                I32Ne => I32Const((x != y) as i32),
+                I32Lts => I32Const((x < y) as i32),
+                I32Ltu => I32Const((ux < uy) as i32),
+                I32Gts => I32Const((x > y) as i32),
+                I32Gtu => I32Const((ux > uy) as i32),
+                I32Les => I32Const((x <= y) as i32),
+                I32Leu => I32Const((ux <= uy) as i32),
+                I32Ges => I32Const((x >= y) as i32),
+                I32Geu => I32Const((ux >= uy) as i32),
+                _ => return None,
+            }
+        }
+        (I64Const(x), I64Const(y)) => {
+            let (x, y) = (*x, *y);
+            let (ux, uy) = (x as u64, y as u64);
+            match op {
+                I64Add => I64Const(x.wrapping_add(y)),
+                I64Sub => I64Const(x.wrapping_sub(y)),
+                I64Mul => I64Const(x.wrapping_mul(y)),
+                I64Divs => I64Const(x.checked_div(y)?),
+                I64Divu => I64Const(ux.checked_div(uy)? as i64),
+                I64Rems if y != 0 => I64Const(x.wrapping_rem(y)),
+                I64Remu => I64Const(ux.checked_rem(uy)? as i64),
+                I64And => I64Const(x & y),
+                I64Or => I64Const(x | y),
+                I64Xor => I64Const(x ^ y),
+                I64Shl => I64Const(x.wrapping_shl(uy as u32)),
+                I64Shrs => I64Const(x.wrapping_shr(uy as u32)),
+                I64Shru => I64Const(ux.wrapping_shr(uy as u32) as i64),
+                I64Rotl => I64Const(x.rotate_left((uy % 64) as u32)),
+                I64Rotr => I64Const(x.rotate_right((uy % 64) as u32)),
+                I64Eq => I32Const((x == y) as i32),
+                I64Ne => I32Const((x != y) as i32),
+                I64Lts => I32Const((x < y) as i32),
+                I64Ltu => I32Const((ux < uy) as i32),
+                I64Gts => I32Const((x > y) as i32),
+                I64Gtu => I32Const((ux > uy) as i32),
+                I64Les => I32Const((x <= y) as i32),
+                I64Leu => I32Const((ux <= uy) as i32),
+                I64Ges => I32Const((x >= y) as i32),
+                I64Geu => I32Const((ux >= uy) as i32),
+                _ => return None,
+            }
+        }
+        (F32Const(x), F32Const(y)) => {
+            let (x, y) = (*x, *y);
+            match op {
+                F32Add => F32Const(x + y),
+                F32Sub => F32Const(x - y),
+                F32Mul => F32Const(x * y),
+                F32Div => F32Const(x / y),
+                F32Eq => I32Const((x == y) as i32),
+                F32Ne => I32Const((x != y) as i32),
+                F32Lt => I32Const((x < y) as i32),
+                F32Gt => I32Const((x > y) as i32),
+                F32Le => I32Const((x <= y) as i32),
+                F32Ge => I32Const((x >= y) as i32),
+                _ => return None,
+            }
+        }
+        (F64Const(x), F64Const(y)) => {
+            let (x, y) = (*x, *y);
+            match op {
+                F64Add => F64Const(x + y),
+                F64Sub => F64Const(x - y),
+                F64Mul => F64Const(x * y),
+                F64Div => F64Const(x / y),
+                F64Eq => I32Const((x == y) as i32),
+                F64Ne => I32Const((x != y) as i32),
+                F64Lt => I32Const((x < y) as i32),
+                F64Gt => I32Const((x > y) as i32),
+                F64Le => I32Const((x <= y) as i32),
+                F64Ge => I32Const((x >= y) as i32),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some(folded)
+}
+
+fn is_const(x: &OperatorCode) -> bool {
+    matches!(
+        x,
+        OperatorCode::I32Const(_)
+            | OperatorCode::I64Const(_)
+            | OperatorCode::F32Const(_)
+            | OperatorCode::F64Const(_)
+    )
+}
+
+// The constants an operation takes are the instructions right before it, so folding only has to
+// look at the end of what has been output so far.
+fn fold_constants(codes: &mut Vec<OperatorCode>) {
+    let mut out: Vec<OperatorCode> = Vec::with_capacity(codes.len());
+    for op in codes.drain(..) {
+        let n = out.len();
+        let folded = match &out[..] {
+            [.., x, y] if is_const(x) && is_const(y) => fold_binary(&op, x, y).map(|z| (2, z)),
+            _ => None,
+        }
+        .or_else(|| match out.last() {
+            Some(x) if is_const(x) => fold_unary(&op, x).map(|z| (1, z)),
+            _ => None,
+        });
+        match folded {
+            Some((args, z)) => {
+                out.truncate(n - args);
+                out.push(z);
+            }
+            None => out.push(op),
+        }
+    }
+    *codes = out;
+}
+
+fn remove_dead_code(codes: &mut Vec<OperatorCode>) {
+    let mut out = Vec::with_capacity(codes.len());
+    // The depth of the blocks opened in dead code, while in dead code.
+    let mut dead: Option<usize> = None;
+    for op in codes.drain(..) {
+        match (&mut dead, &op) {
+            (None, _) => {
+                dead = match &op {
+                    OperatorCode::Unreachable
+                    | OperatorCode::Return
+                    | OperatorCode::Br(_)
+                    | OperatorCode::BrTable { .. } => Some(0),
+                    _ => None,
+                };
+                out.push(op);
+            }
+            (Some(depth), OperatorCode::Block(_) | OperatorCode::Loop(_) | OperatorCode::If(_)) => {
+                *depth += 1;
+            }
+            (Some(0), OperatorCode::Else | OperatorCode::End) => {
+                dead = None;
+                out.push(op);
+            }
+            (Some(depth), OperatorCode::End) => *depth -= 1,
+            (Some(_), _) => {}
+        }
+    }
+    *codes = out;
+}
+
+fn local_index(op: &mut OperatorCode) -> Option<&mut usize> {
+    match op {
+        OperatorCode::GetLocal(i) | OperatorCode::SetLocal(i) | OperatorCode::TeeLocal(i) => {
+            Some(i)
+        }
+        _ => None,
+    }
+}
+
+fn remove_unused_locals(body: &mut FunctionBody, params: usize) {
+    let types = body
+        .locals
+        .iter()
+        .flat_map(|x| std::iter::repeat_n(x.typ.clone(), x.count))
+        .collect::<Vec<_>>();
+    let mut read = vec![false; params + types.len()];
+    for op in &body.codes {
+        if let OperatorCode::GetLocal(i) = op {
+            read[*i] = true;
+        }
+    }
+    let codes = std::mem::take(&mut body.codes);
+    for op in codes {
+        match op {
+            OperatorCode::SetLocal(i) if i >= params && !read[i] => {
+                body.codes.push(OperatorCode::Drop)
+            }
+            OperatorCode::TeeLocal(i) if i >= params && !read[i] => {}
+            op => body.codes.push(op),
+        }
+    }
+    // Parameters keep their indices; the locals that are left are numbered after them.
+    let mut indices = (0..params).map(Some).collect::<Vec<_>>();
+    let mut next = params;
+    for used in &read[params..] {
+        indices.push(if *used { Some(next) } else { None });
+        next += *used as usize;
+    }
+    for op in &mut body.codes {
+        if let Some(i) = local_index(op) {
+            *i = indices[*i].unwrap();
+        }
+    }
+    body.locals = Vec::new();
+    for (typ, _) in types.into_iter().zip(&read[params..]).filter(|x| *x.1) {
+        match body.locals.last_mut() {
+            Some(last) if last.typ == typ => last.count += 1,
+            _ => body.locals.push(LocalEntry { count: 1, typ }),
+        }
+    }
+}
+
+fn peephole(codes: &mut Vec<OperatorCode>) {
+    let mut out: Vec<OperatorCode> = Vec::with_capacity(codes.len());
+    for op in codes.drain(..) {
+        match (out.last(), &op) {
+            (Some(OperatorCode::SetLocal(i)), OperatorCode::GetLocal(j)) if i == j => {
+                *out.last_mut().unwrap() = OperatorCode::TeeLocal(*i);
+            }
+            (Some(x), OperatorCode::Drop)
+                if is_const(x) || matches!(x, OperatorCode::GetLocal(_)) =>
+            {
+                out.pop();
+            }
+            (Some(OperatorCode::TeeLocal(i)), OperatorCode::Drop) => {
+                *out.last_mut().unwrap() = OperatorCode::SetLocal(*i);
+            }
+            _ => out.push(op),
+        }
+    }
+    *codes = out;
+}
+
+// Passes over the rest of the module that make its encoding smaller, each reported by name with
+// the size before and after it:
+//
+// * "types": identical function types become one.
+// * "imports": identical imports of functions and globals become one.
+// * "locals": adjacent local declarations of the same type become one.
+// * "data": active data segments that follow each other in memory become one.
+// * "sections": sections with nothing in them are left out.
+//
+// The encoder already writes every LEB128 number in as few bytes as it takes.
+pub fn shrink_module(root: &mut WasmASTRoot) -> Vec<(&'static str, Report)> {
+    let passes: [(_, fn(&mut WasmASTRoot)); 5] = [
+        ("types", dedup_types),
+        ("imports", dedup_imports),
+        ("locals", merge_locals),
+        ("data", merge_data),
+        ("sections", drop_empty_sections),
+    ];
+    let mut size = root.to_bytes().len();
+    passes
+        .iter()
+        .map(|(name, pass)| {
+            pass(root);
+            let before = std::mem::replace(&mut size, root.to_bytes().len());
+            (
+                *name,
+                Report {
+                    before,
+                    after: size,
+                },
+            )
+        })
+        .collect()
+}
+
+// The index each item gets once the duplicates of earlier ones are removed, and the items that
+// are left.
+fn dedup<T: PartialEq>(xs: Vec<T>, same: impl Fn(&T, &T) -> bool) -> (Vec<usize>, Vec<T>) {
+    let mut indices = Vec::new();
+    let mut kept: Vec<T> = Vec::new();
+    for x in xs {
+        match kept.iter().position(|y| same(&x, y)) {
+            Some(i) => indices.push(i),
+            None => {
+                indices.push(kept.len());
+                kept.push(x);
+            }
+        }
+    }
+    (indices, kept)
+}
+
+fn dedup_types(root: &mut WasmASTRoot) {
+    let types = match &mut root.type_section {
+        Some(x) => std::mem::take(&mut x.0),
+        None => return,
+    };
+    let (indices, types) = dedup(types, |x, y| x == y);
+    root.type_section = Some(TypeSection(types));
+    for t in root.function_section.iter_mut().flat_map(|x| &mut x.0) {
+        *t = indices[*t];
+    }
+    for x in root.import_section.iter_mut().flat_map(|x| &mut x.0) {
+        if let ExternalKindImport::Function(t) = &mut x.kind {
+            *t = indices[*t];
+        }
+    }
+    for op in root
+        .code_section
+        .iter_mut()
+        .flat_map(|x| &mut x.0)
+        .flat_map(|x| &mut x.codes)
+    {
+        match op {
+            OperatorCode::CallIndirect(t)
+            | OperatorCode::Block(BlockType::Func(t))
+            | OperatorCode::Loop(BlockType::Func(t))
+            | OperatorCode::If(BlockType::Func(t)) => *t = indices[*t],
+            _ => {}
+        }
+    }
+}
+
+// Imports of tables and memories are left alone, as a module has at most one of each.
+fn dedup_imports(root: &mut WasmASTRoot) {
+    let imports = match &mut root.import_section {
+        Some(x) => std::mem::take(&mut x.0),
+        None => return,
+    };
+    let kind = |x: &ImportEntry| match x.kind {
+        ExternalKindImport::Function(_) => ExternalKind::Function,
+        ExternalKindImport::Table(_) => ExternalKind::Table,
+        ExternalKindImport::Memory(_) => ExternalKind::Memory,
+        ExternalKindImport::Global(_) => ExternalKind::Global,
+    };
+    let kinds = imports.iter().map(kind).collect::<Vec<_>>();
+    let (indices, imports) = dedup(imports, |x, y| {
+        x == y && matches!(kind(x), ExternalKind::Function | ExternalKind::Global)
+    });
+    // Imports come first in their index space, so the definitions move down by the number of
+    // imports removed.
+    let renumber = |k: ExternalKind| {
+        let map = (0..kinds.len())
+            .filter(|&i| kinds[i] == k)
+            .map(|i| {
+                imports[..indices[i]]
+                    .iter()
+                    .filter(|x| kind(x) == k)
+                    .count()
+            })
+            .collect::<Vec<_>>();
+        let removed = map.len() - imports.iter().filter(|x| kind(x) == k).count();
+        move |i: usize| map.get(i).copied().unwrap_or_else(|| i - removed)
+    };
+    let funcs = renumber(ExternalKind::Function);
+    let globals = renumber(ExternalKind::Global);
+    root.import_section = Some(ImportSection(imports));
+
+    let init = |x: &mut InitExpr| {
+        if let InitExpr::Global(i) = x {
+            *i = globals(*i);
+        }
+    };
+    for x in root.global_section.iter_mut().flat_map(|x| &mut x.0) {
+        init(&mut x.1);
+    }
+    for x in root.element_section.iter_mut().flat_map(|x| &mut x.0) {
+        init(&mut x.offset);
+        for f in &mut x.elems {
+            *f = funcs(*f);
+        }
+    }
+    for x in root.data_section.iter_mut().flat_map(|x| &mut x.0) {
+        if let Some(offset) = &mut x.offset {
+            init(offset);
+        }
+    }
+    for x in root.export_section.iter_mut().flat_map(|x| &mut x.0) {
+        match x.kind {
+            ExternalKind::Function => x.index = funcs(x.index),
+            ExternalKind::Global => x.index = globals(x.index),
+            ExternalKind::Table | ExternalKind::Memory => {}
+        }
+    }
+    if let Some(x) = &mut root.start_section {
+        x.0 = funcs(x.0);
+    }
+    for op in root
+        .code_section
+        .iter_mut()
+        .flat_map(|x| &mut x.0)
+        .flat_map(|x| &mut x.codes)
+    {
+        match op {
+            OperatorCode::Call(i) | OperatorCode::RefFunc(i) => *i = funcs(*i),
+            OperatorCode::GetGlobal(i) | OperatorCode::SetGlobal(i) => *i = globals(*i),
+            _ => {}
+        }
+    }
+    // The names of removed imports go with them. Name maps stay in increasing order.
+    if let Some(names) = &mut root.name_section {
+        for x in &mut names.functions {
+            x.0 = funcs(x.0);
+        }
+        names.functions.dedup_by_key(|x| x.0);
+        for x in &mut names.locals {
+            x.0 = funcs(x.0);
+        }
+        names.locals.dedup_by_key(|x| x.0);
+    }
+}
+
+fn merge_locals(root: &mut WasmASTRoot) {
+    for body in root.code_section.iter_mut().flat_map(|x| &mut x.0) {
+        let mut locals: Vec<LocalEntry> = Vec::new();
+        for x in body.locals.drain(..).filter(|x| x.count > 0) {
+            match locals.last_mut() {
+                Some(last) if last.typ == x.typ => last.count += x.count,
+                _ => locals.push(x),
+            }
+        }
+        body.locals = locals;
+    }
+}
+
+fn merge_data(root: &mut WasmASTRoot) {
+    let datas = match &mut root.data_section {
+        Some(x) => std::mem::take(&mut x.0),
+        None => return,
+    };
+    let mut indices = Vec::new();
+    let mut merged: Vec<DataSegment> = Vec::new();
+    for x in datas {
+        if let Some(last) = merged.last_mut() {
+            if let (Some(InitExpr::I32(a)), Some(InitExpr::I32(b))) = (&last.offset, &x.offset) {
+                if i64::from(*a) + last.data.len() as i64 == i64::from(*b) {
+                    last.data.extend(x.data);
+                    indices.push(merged.len() - 1);
+                    continue;
+                }
+            }
+        }
+        indices.push(merged.len());
+        merged.push(x);
+    }
+    root.data_section = Some(DataSection(merged));
+    for op in root
+        .code_section
+        .iter_mut()
+        .flat_map(|x| &mut x.0)
+        .flat_map(|x| &mut x.codes)
+    {
+        if let OperatorCode::MemoryInit(i) | OperatorCode::DataDrop(i) = op {
+            *i = indices[*i];
+        }
+    }
+}
+
+fn drop_empty_sections(root: &mut WasmASTRoot) {
+    fn drop<T>(x: &mut Option<T>, empty: impl Fn(&T) -> bool) {
+        if x.as_ref().is_some_and(empty) {
+            *x = None;
+        }
+    }
+    drop(&mut root.type_section, |x| x.0.is_empty());
+    drop(&mut root.import_section, |x| x.0.is_empty());
+    drop(&mut root.function_section, |x| x.0.is_empty());
+    drop(&mut root.table_section, |x| x.0.is_empty());
+    drop(&mut root.memory_section, |x| x.0.is_empty());
+    drop(&mut root.global_section, |x| x.0.is_empty());
+    drop(&mut root.export_section, |x| x.0.is_empty());
+    drop(&mut root.element_section, |x| x.0.is_empty());
+    drop(&mut root.code_section, |x| x.0.is_empty());
+    drop(&mut root.data_section, |x| x.0.is_empty());
+    drop(&mut root.name_section, |x| {
+        x.module.is_none() && x.functions.is_empty() && x.locals.is_empty()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wat_parser::parse_module;
+    use OperatorCode::*;
+
+    #[test]
+    fn optimize_test() {
+        let mut root = WasmASTRoot {
+            type_section: Some(TypeSection(vec![FuncType {
+                params: vec![ValueType::I32],
+                results: vec![ValueType::I32],
+            }])),
+            function_section: Some(FunctionSection(vec![0])),
+            code_section: Some(CodeSection(vec![FunctionBody {
+                locals: vec![LocalEntry {
+                    count: 3,
+                    typ: ValueType::I32,
+                }],
+                codes: vec![
+                    I32Const(2),
+                    I32Const(3),
+                    I32Mul,
+                    I32Const(1),
+                    I32Add,
+                    SetLocal(3),
+                    GetLocal(3),
+                    I32Const(5),
+                    SetLocal(1),
+                    GetLocal(0),
+                    I32Add,
+                    I32Const(1),
+                    I32Const(0),
+                    I32Divs,
+                    Drop,
+                    Return,
+                    I32Const(9),
+                    Block(BlockType::Empty),
+                    Nop,
+                    End,
+                ],
+            }])),
+            ..WasmASTRoot::default()
+        };
+        let report = optimize(&mut root);
+        assert_eq!(
+            root.code_section.unwrap().0,
+            vec![FunctionBody {
+                locals: vec![LocalEntry {
+                    count: 1,
+                    typ: ValueType::I32,
+                }],
+                codes: vec![
+                    I32Const(7),
+                    TeeLocal(1),
+                    GetLocal(0),
+                    I32Add,
+                    I32Const(1),
+                    I32Const(0),
+                    I32Divs,
+                    Drop,
+                    Return,
+                ],
+            }]
+        );
+        assert!(report.after < report.before);
+        assert_eq!(
+            Report {
+                before: 200,
+                after: 150
+            }
+            .to_string(),
+            "200 -> 150 bytes (25.0% smaller)"
+        );
+    }
+
+    #[test]
+    fn shrink_module_test() {
+        let src = r#"
+(module
+  (type (func (param i32) (result i32)))
+  (type (func (param i32) (result i32)))
+  (import "env" "f" (func $f (type 0)))
+  (import "env" "base" (global $base i32))
+  (import "env" "f" (func $g (type 1)))
+  (import "env" "base" (global $base2 i32))
+  (memory 1)
+  (data (i32.const 8) "ab")
+  (data (i32.const 10) "cd")
+  (data (i32.const 16) "ef")
+  (func (export "h") (param i32) (result i32)
+    (local i32 i32)
+    (local.set 1 (call $g (local.get 0)))
+    (i32.add (local.get 1) (global.get $base2))))
+"#;
+        let mut root = parse_module(src).unwrap();
+        root.export_section.as_mut().unwrap().0.push(ExportEntry {
+            field: "g".to_string(),
+            kind: ExternalKind::Function,
+            index: 1,
+        });
+        root.code_section.as_mut().unwrap().0[0].locals = vec![
+            LocalEntry {
+                count: 1,
+                typ: ValueType::I32,
+            },
+            LocalEntry {
+                count: 0,
+                typ: ValueType::F64,
+            },
+            LocalEntry {
+                count: 1,
+                typ: ValueType::I32,
+            },
+        ];
+        root.element_section = Some(ElementSection(vec![]));
+        root.name_section = Some(NameSection {
+            functions: vec![
+                (0, "f".to_string()),
+                (1, "g".to_string()),
+                (2, "h".to_string()),
+            ],
+            ..NameSection::default()
+        });
+        let reports = shrink_module(&mut root);
+        assert_eq!(
+            reports.iter().map(|x| x.0).collect::<Vec<_>>(),
+            ["types", "imports", "locals", "data", "sections"]
+        );
+        assert!(reports[..4].iter().all(|x| x.1.after < x.1.before));
+        assert_eq!(reports[4].1.after, reports[4].1.before);
+        for (before, after) in reports.iter().zip(&reports[1..]) {
+            assert_eq!(before.1.after, after.1.before);
+        }
+
+        assert_eq!(root.type_section.as_ref().unwrap().0.len(), 1);
+        assert_eq!(root.import_section.as_ref().unwrap().0.len(), 2);
+        assert_eq!(
+            root.code_section.as_ref().unwrap().0[0].locals,
+            vec![LocalEntry {
+                count: 2,
+                typ: ValueType::I32,
+            }]
+        );
+        assert_eq!(
+            root.data_section.as_ref().unwrap().0,
+            vec![
+                DataSegment {
+                    offset: Some(InitExpr::I32(8)),
+                    data: b"abcd".to_vec(),
+                },
+                DataSegment {
+                    offset: Some(InitExpr::I32(16)),
+                    data: b"ef".to_vec(),
+                },
+            ]
+        );
+        assert_eq!(
+            root.export_section.as_ref().unwrap().0[1],
+            ExportEntry {
+                field: "g".to_string(),
+                kind: ExternalKind::Function,
+                index: 0,
+            }
+        );
+        assert_eq!(
+            root.name_section.as_ref().unwrap().functions,
+            vec![(0, "f".to_string()), (1, "h".to_string())]
+        );
+        assert_eq!(root.element_section, None);
+
+        let codes = &root.code_section.as_ref().unwrap().0[0].codes;
+        assert!(codes.contains(&Call(0)));
+        assert!(codes.contains(&GetGlobal(0)));
+    }
+}
@@ -0,0 +1,780 @@
+use crate::ast::*;
+use crate::float::{format_f32, format_f64};
+use std::fmt::Write;
+
+// Prints modules in the WebAssembly text format, laid out like `wasm2wat` does: one
+// instruction per line with blocks indented, and index comments such as `(;0;)` on
+// definitions.
+
+pub fn value_type(x: &ValueType) -> &'static str {
+    match x {
+        ValueType::I32 => "i32",
+        ValueType::I64 => "i64",
+        ValueType::F32 => "f32",
+        ValueType::F64 => "f64",
+        ValueType::V128 => "v128",
+        ValueType::FuncRef => "funcref",
+        ValueType::ExternRef => "externref",
+    }
+}
+
+pub fn elem_type(x: &ElemType) -> &'static str {
+    match x {
+        ElemType::AnyFunc => "funcref",
+        ElemType::ExternRef => "externref",
+    }
+}
+
+fn func_type(x: &FuncType) -> String {
+    let mut s = String::new();
+    if !x.params.is_empty() {
+        let params = x.params.iter().map(value_type).collect::<Vec<_>>();
+        write!(s, " (param {})", params.join(" ")).unwrap();
+    }
+    if !x.results.is_empty() {
+        let results = x.results.iter().map(value_type).collect::<Vec<_>>();
+        write!(s, " (result {})", results.join(" ")).unwrap();
+    }
+    s
+}
+
+fn limits(x: &ResizableLimits) -> String {
+    let mut s = x.initial.to_string();
+    if let Some(maximum) = x.maximum {
+        write!(s, " {}", maximum).unwrap();
+    }
+    if x.shared {
+        s.push_str(" shared");
+    }
+    s
+}
+
+fn global_type(x: &GlobalType) -> String {
+    if x.mutability {
+        format!("(mut {})", value_type(&x.content_type))
+    } else {
+        value_type(&x.content_type).to_string()
+    }
+}
+
+fn init_expr(x: &InitExpr) -> String {
+    match x {
+        InitExpr::I32(x) => format!("(i32.const {})", x),
+        InitExpr::I64(x) => format!("(i64.const {})", x),
+        InitExpr::F32(x) => format!("(f32.const {})", format_f32(*x)),
+        InitExpr::F64(x) => format!("(f64.const {})", format_f64(*x)),
+        InitExpr::Global(x) => format!("(global.get {})", x),
+    }
+}
+
+pub fn string_literal(x: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for &b in x {
+        match b {
+            b'"' | b'\\' => write!(s, "\\{}", b as char).unwrap(),
+            0x20..=0x7e => s.push(b as char),
+            _ => write!(s, "\\{:02x}", b).unwrap(),
+        }
+    }
+    s.push('"');
+    s
+}
+
+impl OperatorCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OperatorCode::Unreachable => "unreachable",
+            OperatorCode::Nop => "nop",
+            OperatorCode::Block(_) => "block",
+            OperatorCode::Loop(_) => "loop",
+            OperatorCode::If(_) => "if",
+            OperatorCode::Else => "else",
+            OperatorCode::End => "end",
+            OperatorCode::Br(_) => "br",
+            OperatorCode::BrIf(_) => "br_if",
+            OperatorCode::BrTable { .. } => "br_table",
+            OperatorCode::Return => "return",
+            OperatorCode::Call(_) => "call",
+            OperatorCode::CallIndirect(_) => "call_indirect",
+            OperatorCode::Drop => "drop",
+            OperatorCode::Select => "select",
+            OperatorCode::GetLocal(_) => "local.get",
+            OperatorCode::SetLocal(_) => "local.set",
+            OperatorCode::TeeLocal(_) => "local.tee",
+            OperatorCode::GetGlobal(_) => "global.get",
+            OperatorCode::SetGlobal(_) => "global.set",
+            OperatorCode::TableGet(_) => "table.get",
+            OperatorCode::TableSet(_) => "table.set",
+            OperatorCode::I32Load(_) => "i32.load",
+            OperatorCode::I64Load(_) => "i64.load",
+            OperatorCode::F32Load(_) => "f32.load",
+            OperatorCode::F64Load(_) => "f64.load",
+            OperatorCode::I32Load8s(_) => "i32.load8_s",
+            OperatorCode::I32Load8u(_) => "i32.load8_u",
+            OperatorCode::I32Load16s(_) => "i32.load16_s",
+            OperatorCode::I32Load16u(_) => "i32.load16_u",
+            OperatorCode::I64Load8s(_) => "i64.load8_s",
+            OperatorCode::I64Load8u(_) => "i64.load8_u",
+            OperatorCode::I64Load16s(_) => "i64.load16_s",
+            OperatorCode::I64Load16u(_) => "i64.load16_u",
+            OperatorCode::I64Load32s(_) => "i64.load32_s",
+            OperatorCode::I64Load32u(_) => "i64.load32_u",
+            OperatorCode::I32Store(_) => "i32.store",
+            OperatorCode::I64Store(_) => "i64.store",
+            OperatorCode::F32Store(_) => "f32.store",
+            OperatorCode::F64Store(_) => "f64.store",
+            OperatorCode::I32Store8(_) => "i32.store8",
+            OperatorCode::I32Store16(_) => "i32.store16",
+            OperatorCode::I64Store8(_) => "i64.store8",
+            OperatorCode::I64Store16(_) => "i64.store16",
+            OperatorCode::I64Store32(_) => "i64.store32",
+            OperatorCode::CurrentMemory => "memory.size",
+            OperatorCode::GrowMemory => "memory.grow",
+            OperatorCode::I32Const(_) => "i32.const",
+            OperatorCode::I64Const(_) => "i64.const",
+            OperatorCode::F32Const(_) => "f32.const",
+            OperatorCode::F64Const(_) => "f64.const",
+            OperatorCode::I32Eqz => "i32.eqz",
+            OperatorCode::I32Eq => "i32.eq",
+            OperatorCode::I32Ne => "i32.ne",
+            OperatorCode::I32Lts => "i32.lt_s",
+            OperatorCode::I32Ltu => "i32.lt_u",
+            OperatorCode::I32Gts => "i32.gt_s",
+            OperatorCode::I32Gtu => "i32.gt_u",
+            OperatorCode::I32Les => "i32.le_s",
+            OperatorCode::I32Leu => "i32.le_u",
+            OperatorCode::I32Ges => "i32.ge_s",
+            OperatorCode::I32Geu => "i32.ge_u",
+            OperatorCode::I64Eqz => "i64.eqz",
+            OperatorCode::I64Eq => "i64.eq",
+            OperatorCode::I64Ne => "i64.ne",
+            OperatorCode::I64Lts => "i64.lt_s",
+            OperatorCode::I64Ltu => "i64.lt_u",
+            OperatorCode::I64Gts => "i64.gt_s",
+            OperatorCode::I64Gtu => "i64.gt_u",
+            OperatorCode::I64Les => "i64.le_s",
+            OperatorCode::I64Leu => "i64.le_u",
+            OperatorCode::I64Ges => "i64.ge_s",
+            OperatorCode::I64Geu => "i64.ge_u",
+            OperatorCode::F32Eq => "f32.eq",
+            OperatorCode::F32Ne => "f32.ne",
+            OperatorCode::F32Lt => "f32.lt",
+            OperatorCode::F32Gt => "f32.gt",
+            OperatorCode::F32Le => "f32.le",
+            OperatorCode::F32Ge => "f32.ge",
+            OperatorCode::F64Eq => "f64.eq",
+            OperatorCode::F64Ne => "f64.ne",
+            OperatorCode::F64Lt => "f64.lt",
+            OperatorCode::F64Gt => "f64.gt",
+            OperatorCode::F64Le => "f64.le",
+            OperatorCode::F64Ge => "f64.ge",
+            OperatorCode::I32Clz => "i32.clz",
+            OperatorCode::I32Ctz => "i32.ctz",
+            OperatorCode::I32Popcnt => "i32.popcnt",
+            OperatorCode::I32Add => "i32.add",
+            OperatorCode::I32Sub => "i32.sub",
+            OperatorCode::I32Mul => "i32.mul",
+            OperatorCode::I32Divs => "i32.div_s",
+            OperatorCode::I32Divu => "i32.div_u",
+            OperatorCode::I32Rems => "i32.rem_s",
+            OperatorCode::I32Remu => "i32.rem_u",
+            OperatorCode::I32And => "i32.and",
+            OperatorCode::I32Or => "i32.or",
+            OperatorCode::I32Xor => "i32.xor",
+            OperatorCode::I32Shl => "i32.shl",
+            OperatorCode::I32Shrs => "i32.shr_s",
+            OperatorCode::I32Shru => "i32.shr_u",
+            OperatorCode::I32Rotl => "i32.rotl",
+            OperatorCode::I32Rotr => "i32.rotr",
+            OperatorCode::I64Clz => "i64.clz",
+            OperatorCode::I64Ctz => "i64.ctz",
+            OperatorCode::I64Popcnt => "i64.popcnt",
+            OperatorCode::I64Add => "i64.add",
+            OperatorCode::I64Sub => "i64.sub",
+            OperatorCode::I64Mul => "i64.mul",
+            OperatorCode::I64Divs => "i64.div_s",
+            OperatorCode::I64Divu => "i64.div_u",
+            OperatorCode::I64Rems => "i64.rem_s",
+            OperatorCode::I64Remu => "i64.rem_u",
+            OperatorCode::I64And => "i64.and",
+            OperatorCode::I64Or => "i64.or",
+            OperatorCode::I64Xor => "i64.xor",
+            OperatorCode::I64Shl => "i64.shl",
+            OperatorCode::I64Shrs => "i64.shr_s",
+            OperatorCode::I64Shru => "i64.shr_u",
+            OperatorCode::I64Rotl => "i64.rotl",
+            OperatorCode::I64Rotr => "i64.rotr",
+            OperatorCode::F32Abs => "f32.abs",
+            OperatorCode::F32Neg => "f32.neg",
+            OperatorCode::F32Ceil => "f32.ceil",
+            OperatorCode::F32Floor => "f32.floor",
+            OperatorCode::F32Trunc => "f32.trunc",
+            OperatorCode::F32Nearest => "f32.nearest",
+            OperatorCode::F32Sqrt => "f32.sqrt",
+            OperatorCode::F32Add => "f32.add",
+            OperatorCode::F32Sub => "f32.sub",
+            OperatorCode::F32Mul => "f32.mul",
+            OperatorCode::F32Div => "f32.div",
+            OperatorCode::F32Min => "f32.min",
+            OperatorCode::F32Max => "f32.max",
+            OperatorCode::F32Copysign => "f32.copysign",
+            OperatorCode::F64Abs => "f64.abs",
+            OperatorCode::F64Neg => "f64.neg",
+            OperatorCode::F64Ceil => "f64.ceil",
+            OperatorCode::F64Floor => "f64.floor",
+            OperatorCode::F64Trunc => "f64.trunc",
+            OperatorCode::F64Nearest => "f64.nearest",
+            OperatorCode::F64Sqrt => "f64.sqrt",
+            OperatorCode::F64Add => "f64.add",
+            OperatorCode::F64Sub => "f64.sub",
+            OperatorCode::F64Mul => "f64.mul",
+            OperatorCode::F64Div => "f64.div",
+            OperatorCode::F64Min => "f64.min",
+            OperatorCode::F64Max => "f64.max",
+            OperatorCode::F64Copysign => "f64.copysign",
+            OperatorCode::I32WrapI64 => "i32.wrap_i64",
+            OperatorCode::I32TruncsF32 => "i32.trunc_f32_s",
+            OperatorCode::I32TrancuF32 => "i32.trunc_f32_u",
+            OperatorCode::I32TrancsF64 => "i32.trunc_f64_s",
+            OperatorCode::I32TrancuF64 => "i32.trunc_f64_u",
+            OperatorCode::I64ExtendsI32 => "i64.extend_i32_s",
+            OperatorCode::I64ExtenduI32 => "i64.extend_i32_u",
+            OperatorCode::I64TruncsF32 => "i64.trunc_f32_s",
+            OperatorCode::I64TrancuF32 => "i64.trunc_f32_u",
+            OperatorCode::I64TrancsF64 => "i64.trunc_f64_s",
+            OperatorCode::I64TrancuF64 => "i64.trunc_f64_u",
+            OperatorCode::F32ConvertsI32 => "f32.convert_i32_s",
+            OperatorCode::F32ConvertuI32 => "f32.convert_i32_u",
+            OperatorCode::F32ConvertsI64 => "f32.convert_i64_s",
+            OperatorCode::F32ConvertuI64 => "f32.convert_i64_u",
+            OperatorCode::F32DemoteF64 => "f32.demote_f64",
+            OperatorCode::F64ConvertsI32 => "f64.convert_i32_s",
+            OperatorCode::F64ConvertuI32 => "f64.convert_i32_u",
+            OperatorCode::F64ConvertsI64 => "f64.convert_i64_s",
+            OperatorCode::F64ConvertuI64 => "f64.convert_i64_u",
+            OperatorCode::F64PromoteF32 => "f64.promote_f32",
+            OperatorCode::I32ReinterpretF32 => "i32.reinterpret_f32",
+            OperatorCode::I64ReinterpretF64 => "i64.reinterpret_f64",
+            OperatorCode::F32ReinterpretI32 => "f32.reinterpret_i32",
+            OperatorCode::F64ReinterpretI64 => "f64.reinterpret_i64",
+            OperatorCode::RefNull(_) => "ref.null",
+            OperatorCode::RefIsNull => "ref.is_null",
+            OperatorCode::RefFunc(_) => "ref.func",
+            OperatorCode::MemoryInit(_) => "memory.init",
+            OperatorCode::DataDrop(_) => "data.drop",
+            OperatorCode::MemoryCopy => "memory.copy",
+            OperatorCode::MemoryFill => "memory.fill",
+            OperatorCode::V128Load(_) => "v128.load",
+            OperatorCode::V128Store(_) => "v128.store",
+            OperatorCode::V128Const(_) => "v128.const",
+            OperatorCode::I8x16Splat => "i8x16.splat",
+            OperatorCode::I16x8Splat => "i16x8.splat",
+            OperatorCode::I32x4Splat => "i32x4.splat",
+            OperatorCode::I64x2Splat => "i64x2.splat",
+            OperatorCode::F32x4Splat => "f32x4.splat",
+            OperatorCode::F64x2Splat => "f64x2.splat",
+            OperatorCode::I8x16ExtractLanes(_) => "i8x16.extract_lane_s",
+            OperatorCode::I8x16ExtractLaneu(_) => "i8x16.extract_lane_u",
+            OperatorCode::I8x16ReplaceLane(_) => "i8x16.replace_lane",
+            OperatorCode::I16x8ExtractLanes(_) => "i16x8.extract_lane_s",
+            OperatorCode::I16x8ExtractLaneu(_) => "i16x8.extract_lane_u",
+            OperatorCode::I16x8ReplaceLane(_) => "i16x8.replace_lane",
+            OperatorCode::I32x4ExtractLane(_) => "i32x4.extract_lane",
+            OperatorCode::I32x4ReplaceLane(_) => "i32x4.replace_lane",
+            OperatorCode::I64x2ExtractLane(_) => "i64x2.extract_lane",
+            OperatorCode::I64x2ReplaceLane(_) => "i64x2.replace_lane",
+            OperatorCode::F32x4ExtractLane(_) => "f32x4.extract_lane",
+            OperatorCode::F32x4ReplaceLane(_) => "f32x4.replace_lane",
+            OperatorCode::F64x2ExtractLane(_) => "f64x2.extract_lane",
+            OperatorCode::F64x2ReplaceLane(_) => "f64x2.replace_lane",
+            OperatorCode::I8x16Eq => "i8x16.eq",
+            OperatorCode::I8x16Ne => "i8x16.ne",
+            OperatorCode::I16x8Eq => "i16x8.eq",
+            OperatorCode::I16x8Ne => "i16x8.ne",
+            OperatorCode::I32x4Eq => "i32x4.eq",
+            OperatorCode::I32x4Ne => "i32x4.ne",
+            OperatorCode::F32x4Eq => "f32x4.eq",
+            OperatorCode::F32x4Ne => "f32x4.ne",
+            OperatorCode::F64x2Eq => "f64x2.eq",
+            OperatorCode::F64x2Ne => "f64x2.ne",
+            OperatorCode::V128Not => "v128.not",
+            OperatorCode::V128And => "v128.and",
+            OperatorCode::V128AndNot => "v128.andnot",
+            OperatorCode::V128Or => "v128.or",
+            OperatorCode::V128Xor => "v128.xor",
+            OperatorCode::V128Bitselect => "v128.bitselect",
+            OperatorCode::V128AnyTrue => "v128.any_true",
+            OperatorCode::I8x16AllTrue => "i8x16.all_true",
+            OperatorCode::I8x16Bitmask => "i8x16.bitmask",
+            OperatorCode::I8x16Add => "i8x16.add",
+            OperatorCode::I8x16Sub => "i8x16.sub",
+            OperatorCode::I16x8AllTrue => "i16x8.all_true",
+            OperatorCode::I16x8Bitmask => "i16x8.bitmask",
+            OperatorCode::I16x8Add => "i16x8.add",
+            OperatorCode::I16x8Sub => "i16x8.sub",
+            OperatorCode::I16x8Mul => "i16x8.mul",
+            OperatorCode::I32x4AllTrue => "i32x4.all_true",
+            OperatorCode::I32x4Bitmask => "i32x4.bitmask",
+            OperatorCode::I32x4Add => "i32x4.add",
+            OperatorCode::I32x4Sub => "i32x4.sub",
+            OperatorCode::I32x4Mul => "i32x4.mul",
+            OperatorCode::I64x2AllTrue => "i64x2.all_true",
+            OperatorCode::I64x2Bitmask => "i64x2.bitmask",
+            OperatorCode::I64x2Add => "i64x2.add",
+            OperatorCode::I64x2Sub => "i64x2.sub",
+            OperatorCode::I64x2Mul => "i64x2.mul",
+            OperatorCode::F32x4Add => "f32x4.add",
+            OperatorCode::F32x4Sub => "f32x4.sub",
+            OperatorCode::F32x4Mul => "f32x4.mul",
+            OperatorCode::F32x4Div => "f32x4.div",
+            OperatorCode::F64x2Add => "f64x2.add",
+            OperatorCode::F64x2Sub => "f64x2.sub",
+            OperatorCode::F64x2Mul => "f64x2.mul",
+            OperatorCode::F64x2Div => "f64x2.div",
+            OperatorCode::AtomicFence => "atomic.fence",
+            OperatorCode::MemoryAtomicNotify(_) => "memory.atomic.notify",
+            OperatorCode::MemoryAtomicWait32(_) => "memory.atomic.wait32",
+            OperatorCode::MemoryAtomicWait64(_) => "memory.atomic.wait64",
+            OperatorCode::I32AtomicLoad(_) => "i32.atomic.load",
+            OperatorCode::I64AtomicLoad(_) => "i64.atomic.load",
+            OperatorCode::I32AtomicStore(_) => "i32.atomic.store",
+            OperatorCode::I64AtomicStore(_) => "i64.atomic.store",
+            OperatorCode::I32AtomicRmwAdd(_) => "i32.atomic.rmw.add",
+            OperatorCode::I64AtomicRmwAdd(_) => "i64.atomic.rmw.add",
+            OperatorCode::I32AtomicRmwSub(_) => "i32.atomic.rmw.sub",
+            OperatorCode::I64AtomicRmwSub(_) => "i64.atomic.rmw.sub",
+            OperatorCode::I32AtomicRmwAnd(_) => "i32.atomic.rmw.and",
+            OperatorCode::I64AtomicRmwAnd(_) => "i64.atomic.rmw.and",
+            OperatorCode::I32AtomicRmwOr(_) => "i32.atomic.rmw.or",
+            OperatorCode::I64AtomicRmwOr(_) => "i64.atomic.rmw.or",
+            OperatorCode::I32AtomicRmwXor(_) => "i32.atomic.rmw.xor",
+            OperatorCode::I64AtomicRmwXor(_) => "i64.atomic.rmw.xor",
+            OperatorCode::I32AtomicRmwXchg(_) => "i32.atomic.rmw.xchg",
+            OperatorCode::I64AtomicRmwXchg(_) => "i64.atomic.rmw.xchg",
+            OperatorCode::I32AtomicRmwCmpxchg(_) => "i32.atomic.rmw.cmpxchg",
+            OperatorCode::I64AtomicRmwCmpxchg(_) => "i64.atomic.rmw.cmpxchg",
+        }
+    }
+
+    // log2 of the alignment a memory access has when `align=` is omitted.
+    pub fn natural_alignment(&self) -> Option<u32> {
+        match self {
+            OperatorCode::I32Load8s(_)
+            | OperatorCode::I32Load8u(_)
+            | OperatorCode::I64Load8s(_)
+            | OperatorCode::I64Load8u(_)
+            | OperatorCode::I32Store8(_)
+            | OperatorCode::I64Store8(_) => Some(0),
+            OperatorCode::I32Load16s(_)
+            | OperatorCode::I32Load16u(_)
+            | OperatorCode::I64Load16s(_)
+            | OperatorCode::I64Load16u(_)
+            | OperatorCode::I32Store16(_)
+            | OperatorCode::I64Store16(_) => Some(1),
+            OperatorCode::I32Load(_)
+            | OperatorCode::F32Load(_)
+            | OperatorCode::I64Load32s(_)
+            | OperatorCode::I64Load32u(_)
+            | OperatorCode::I32Store(_)
+            | OperatorCode::F32Store(_)
+            | OperatorCode::I64Store32(_)
+            | OperatorCode::MemoryAtomicNotify(_)
+            | OperatorCode::MemoryAtomicWait32(_)
+            | OperatorCode::I32AtomicLoad(_)
+            | OperatorCode::I32AtomicStore(_)
+            | OperatorCode::I32AtomicRmwAdd(_)
+            | OperatorCode::I32AtomicRmwSub(_)
+            | OperatorCode::I32AtomicRmwAnd(_)
+            | OperatorCode::I32AtomicRmwOr(_)
+            | OperatorCode::I32AtomicRmwXor(_)
+            | OperatorCode::I32AtomicRmwXchg(_)
+            | OperatorCode::I32AtomicRmwCmpxchg(_) => Some(2),
+            OperatorCode::I64Load(_)
+            | OperatorCode::F64Load(_)
+            | OperatorCode::I64Store(_)
+            | OperatorCode::F64Store(_)
+            | OperatorCode::MemoryAtomicWait64(_)
+            | OperatorCode::I64AtomicLoad(_)
+            | OperatorCode::I64AtomicStore(_)
+            | OperatorCode::I64AtomicRmwAdd(_)
+            | OperatorCode::I64AtomicRmwSub(_)
+            | OperatorCode::I64AtomicRmwAnd(_)
+            | OperatorCode::I64AtomicRmwOr(_)
+            | OperatorCode::I64AtomicRmwXor(_)
+            | OperatorCode::I64AtomicRmwXchg(_)
+            | OperatorCode::I64AtomicRmwCmpxchg(_) => Some(3),
+            OperatorCode::V128Load(_) | OperatorCode::V128Store(_) => Some(4),
+            _ => None,
+        }
+    }
+
+    fn memory_immediate(&self) -> Option<&MemoryImmediate> {
+        match self {
+            OperatorCode::I32Load(x)
+            | OperatorCode::I64Load(x)
+            | OperatorCode::F32Load(x)
+            | OperatorCode::F64Load(x)
+            | OperatorCode::I32Load8s(x)
+            | OperatorCode::I32Load8u(x)
+            | OperatorCode::I32Load16s(x)
+            | OperatorCode::I32Load16u(x)
+            | OperatorCode::I64Load8s(x)
+            | OperatorCode::I64Load8u(x)
+            | OperatorCode::I64Load16s(x)
+            | OperatorCode::I64Load16u(x)
+            | OperatorCode::I64Load32s(x)
+            | OperatorCode::I64Load32u(x)
+            | OperatorCode::I32Store(x)
+            | OperatorCode::I64Store(x)
+            | OperatorCode::F32Store(x)
+            | OperatorCode::F64Store(x)
+            | OperatorCode::I32Store8(x)
+            | OperatorCode::I32Store16(x)
+            | OperatorCode::I64Store8(x)
+            | OperatorCode::I64Store16(x)
+            | OperatorCode::I64Store32(x)
+            | OperatorCode::V128Load(x)
+            | OperatorCode::V128Store(x)
+            | OperatorCode::MemoryAtomicNotify(x)
+            | OperatorCode::MemoryAtomicWait32(x)
+            | OperatorCode::MemoryAtomicWait64(x)
+            | OperatorCode::I32AtomicLoad(x)
+            | OperatorCode::I64AtomicLoad(x)
+            | OperatorCode::I32AtomicStore(x)
+            | OperatorCode::I64AtomicStore(x)
+            | OperatorCode::I32AtomicRmwAdd(x)
+            | OperatorCode::I64AtomicRmwAdd(x)
+            | OperatorCode::I32AtomicRmwSub(x)
+            | OperatorCode::I64AtomicRmwSub(x)
+            | OperatorCode::I32AtomicRmwAnd(x)
+            | OperatorCode::I64AtomicRmwAnd(x)
+            | OperatorCode::I32AtomicRmwOr(x)
+            | OperatorCode::I64AtomicRmwOr(x)
+            | OperatorCode::I32AtomicRmwXor(x)
+            | OperatorCode::I64AtomicRmwXor(x)
+            | OperatorCode::I32AtomicRmwXchg(x)
+            | OperatorCode::I64AtomicRmwXchg(x)
+            | OperatorCode::I32AtomicRmwCmpxchg(x)
+            | OperatorCode::I64AtomicRmwCmpxchg(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+// A single instruction without the block structure around it, e.g. `i32.load offset=4`.
+pub fn print_instruction(x: &OperatorCode) -> String {
+    let mut s = x.name().to_string();
+    match x {
+        OperatorCode::Block(t) | OperatorCode::Loop(t) | OperatorCode::If(t) => match t {
+            BlockType::Empty => {}
+            BlockType::Value(t) => write!(s, " (result {})", value_type(t)).unwrap(),
+            BlockType::Func(x) => write!(s, " (type {})", x).unwrap(),
+        },
+        OperatorCode::Br(x)
+        | OperatorCode::BrIf(x)
+        | OperatorCode::Call(x)
+        | OperatorCode::GetLocal(x)
+        | OperatorCode::SetLocal(x)
+        | OperatorCode::TeeLocal(x)
+        | OperatorCode::GetGlobal(x)
+        | OperatorCode::SetGlobal(x)
+        | OperatorCode::TableGet(x)
+        | OperatorCode::TableSet(x)
+        | OperatorCode::RefFunc(x)
+        | OperatorCode::MemoryInit(x)
+        | OperatorCode::DataDrop(x) => write!(s, " {}", x).unwrap(),
+        OperatorCode::I8x16ExtractLanes(x)
+        | OperatorCode::I8x16ExtractLaneu(x)
+        | OperatorCode::I8x16ReplaceLane(x)
+        | OperatorCode::I16x8ExtractLanes(x)
+        | OperatorCode::I16x8ExtractLaneu(x)
+        | OperatorCode::I16x8ReplaceLane(x)
+        | OperatorCode::I32x4ExtractLane(x)
+        | OperatorCode::I32x4ReplaceLane(x)
+        | OperatorCode::I64x2ExtractLane(x)
+        | OperatorCode::I64x2ReplaceLane(x)
+        | OperatorCode::F32x4ExtractLane(x)
+        | OperatorCode::F32x4ReplaceLane(x)
+        | OperatorCode::F64x2ExtractLane(x)
+        | OperatorCode::F64x2ReplaceLane(x) => write!(s, " {}", x).unwrap(),
+        // As four 32-bit lanes, like `wasm2wat`.
+        OperatorCode::V128Const(x) => {
+            s.push_str(" i32x4");
+            for i in 0..4 {
+                write!(s, " {:#010x}", (x >> (32 * i)) as u32).unwrap();
+            }
+        }
+        // The heap type, which is the reference type without `ref`.
+        OperatorCode::RefNull(t) => write!(s, " {}", elem_type(t).trim_end_matches("ref")).unwrap(),
+        OperatorCode::BrTable { index, params } => {
+            for x in params {
+                write!(s, " {}", x).unwrap();
+            }
+            write!(s, " {}", index).unwrap();
+        }
+        OperatorCode::CallIndirect(x) => write!(s, " (type {})", x).unwrap(),
+        OperatorCode::I32Const(x) => write!(s, " {}", x).unwrap(),
+        OperatorCode::I64Const(x) => write!(s, " {}", x).unwrap(),
+        OperatorCode::F32Const(x) => write!(s, " {}", format_f32(*x)).unwrap(),
+        OperatorCode::F64Const(x) => write!(s, " {}", format_f64(*x)).unwrap(),
+        x => {
+            if let (Some(m), Some(natural)) = (x.memory_immediate(), x.natural_alignment()) {
+                if m.offset != 0 {
+                    write!(s, " offset={}", m.offset).unwrap();
+                }
+                if m.flags != natural {
+                    write!(s, " align={}", 1u64 << m.flags).unwrap();
+                }
+            }
+        }
+    }
+    s
+}
+
+fn print_body(out: &mut String, codes: &[OperatorCode]) {
+    let mut depth = 2;
+    for x in codes {
+        if let OperatorCode::Else | OperatorCode::End = x {
+            depth -= 1;
+        }
+        write!(out, "\n{}{}", "  ".repeat(depth), print_instruction(x)).unwrap();
+        if let OperatorCode::Block(_)
+        | OperatorCode::Loop(_)
+        | OperatorCode::If(_)
+        | OperatorCode::Else = x
+        {
+            depth += 1;
+        }
+    }
+}
+
+pub fn print_module(root: &WasmASTRoot) -> String {
+    let types = root.type_section.as_ref().map_or(&[][..], |x| &x.0);
+    let imports = root.import_section.as_ref().map_or(&[][..], |x| &x.0);
+    let mut out = String::from("(module");
+    for (i, x) in types.iter().enumerate() {
+        write!(out, "\n  (type (;{};) (func{}))", i, func_type(x)).unwrap();
+    }
+
+    // Imports come first in each index space.
+    let (mut funcs, mut tables, mut memories, mut globals) = (0, 0, 0, 0);
+    for x in imports {
+        let desc = match &x.kind {
+            ExternalKindImport::Function(t) => {
+                funcs += 1;
+                format!("(func (;{};) (type {}))", funcs - 1, t)
+            }
+            ExternalKindImport::Table(t) => {
+                tables += 1;
+                format!(
+                    "(table (;{};) {} {})",
+                    tables - 1,
+                    limits(&t.limits),
+                    elem_type(&t.element_type)
+                )
+            }
+            ExternalKindImport::Memory(t) => {
+                memories += 1;
+                format!("(memory (;{};) {})", memories - 1, limits(&t.0))
+            }
+            ExternalKindImport::Global(t) => {
+                globals += 1;
+                format!("(global (;{};) {})", globals - 1, global_type(t))
+            }
+        };
+        write!(
+            out,
+            "\n  (import {} {} {})",
+            string_literal(x.module.as_bytes()),
+            string_literal(x.field.as_bytes()),
+            desc
+        )
+        .unwrap();
+    }
+
+    let signatures = root.function_section.as_ref().map_or(&[][..], |x| &x.0);
+    let bodies = root.code_section.as_ref().map_or(&[][..], |x| &x.0);
+    for (i, (t, body)) in signatures.iter().zip(bodies).enumerate() {
+        write!(out, "\n  (func (;{};) (type {})", funcs + i, t).unwrap();
+        if let Some(t) = types.get(*t) {
+            out.push_str(&func_type(t));
+        }
+        let locals = body
+            .locals
+            .iter()
+            .flat_map(|x| std::iter::repeat_n(value_type(&x.typ), x.count))
+            .collect::<Vec<_>>();
+        if !locals.is_empty() {
+            write!(out, "\n    (local {})", locals.join(" ")).unwrap();
+        }
+        print_body(&mut out, &body.codes);
+        out.push(')');
+    }
+    for (i, x) in root.table_section.iter().flat_map(|x| &x.0).enumerate() {
+        write!(
+            out,
+            "\n  (table (;{};) {} {})",
+            tables + i,
+            limits(&x.limits),
+            elem_type(&x.element_type)
+        )
+        .unwrap();
+    }
+    for (i, x) in root.memory_section.iter().flat_map(|x| &x.0).enumerate() {
+        write!(out, "\n  (memory (;{};) {})", memories + i, limits(&x.0)).unwrap();
+    }
+    for (i, x) in root.global_section.iter().flat_map(|x| &x.0).enumerate() {
+        write!(
+            out,
+            "\n  (global (;{};) {} {})",
+            globals + i,
+            global_type(&x.0),
+            init_expr(&x.1)
+        )
+        .unwrap();
+    }
+    for x in root.export_section.iter().flat_map(|x| &x.0) {
+        let kind = match x.kind {
+            ExternalKind::Function => "func",
+            ExternalKind::Table => "table",
+            ExternalKind::Memory => "memory",
+            ExternalKind::Global => "global",
+        };
+        write!(
+            out,
+            "\n  (export {} ({} {}))",
+            string_literal(x.field.as_bytes()),
+            kind,
+            x.index
+        )
+        .unwrap();
+    }
+    if let Some(x) = &root.start_section {
+        write!(out, "\n  (start {})", x.0).unwrap();
+    }
+    for (i, x) in root.element_section.iter().flat_map(|x| &x.0).enumerate() {
+        write!(out, "\n  (elem (;{};) {} func", i, init_expr(&x.offset)).unwrap();
+        for x in &x.elems {
+            write!(out, " {}", x).unwrap();
+        }
+        out.push(')');
+    }
+    for (i, x) in root.data_section.iter().flat_map(|x| &x.0).enumerate() {
+        write!(out, "\n  (data (;{};)", i).unwrap();
+        if let Some(offset) = &x.offset {
+            write!(out, " {}", init_expr(offset)).unwrap();
+        }
+        write!(out, " {})", string_literal(&x.data)).unwrap();
+    }
+    out.push_str(")\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn print_module_test() {
+        let root = WasmASTRoot {
+            type_section: Some(TypeSection(vec![
+                FuncType {
+                    params: vec![ValueType::I32],
+                    results: vec![],
+                },
+                FuncType {
+                    params: vec![ValueType::I32, ValueType::F64],
+                    results: vec![ValueType::I32],
+                },
+            ])),
+            import_section: Some(ImportSection(vec![ImportEntry {
+                module: "console".to_string(),
+                field: "log".to_string(),
+                kind: ExternalKindImport::Function(0),
+            }])),
+            function_section: Some(FunctionSection(vec![1])),
+            code_section: Some(CodeSection(vec![FunctionBody {
+                locals: vec![
+                    LocalEntry {
+                        count: 2,
+                        typ: ValueType::I32,
+                    },
+                    LocalEntry {
+                        count: 1,
+                        typ: ValueType::F32,
+                    },
+                ],
+                codes: vec![
+                    OperatorCode::Block(BlockType::Value(ValueType::I32)),
+                    OperatorCode::GetLocal(0),
+                    OperatorCode::If(BlockType::Empty),
+                    OperatorCode::I32Const(-1),
+                    OperatorCode::Call(0),
+                    OperatorCode::Else,
+                    OperatorCode::F32Const(f32::NAN),
+                    OperatorCode::SetLocal(4),
+                    OperatorCode::End,
+                    OperatorCode::GetLocal(0),
+                    OperatorCode::I32Load(MemoryImmediate {
+                        flags: 0,
+                        offset: 4,
+                    }),
+                    OperatorCode::BrTable {
+                        index: 0,
+                        params: vec![0, 1],
+                    },
+                    OperatorCode::End,
+                ],
+            }])),
+            memory_section: Some(MemorySection(vec![MemoryType(ResizableLimits {
+                initial: 1,
+                maximum: None,
+                shared: false,
+            })])),
+            global_section: Some(GlobalSection(vec![GlobalVariable(
+                GlobalType {
+                    content_type: ValueType::F64,
+                    mutability: true,
+                },
+                InitExpr::F64(0.5),
+            )])),
+            export_section: Some(ExportSection(vec![ExportEntry {
+                field: "f".to_string(),
+                kind: ExternalKind::Function,
+                index: 1,
+            }])),
+            data_section: Some(DataSection(vec![DataSegment {
+                offset: Some(InitExpr::I32(16)),
+                data: b"a\"\n".to_vec(),
+            }])),
+            ..WasmASTRoot::default()
+        };
+        assert_eq!(
+            print_module(&root),
+            r#"(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func (param i32 f64) (result i32)))
+  (import "console" "log" (func (;0;) (type 0)))
+  (func (;1;) (type 1) (param i32 f64) (result i32)
+    (local i32 i32 f32)
+    block (result i32)
+      local.get 0
+      if
+        i32.const -1
+        call 0
+      else
+        f32.const nan
+        local.set 4
+      end
+      local.get 0
+      i32.load offset=4 align=1
+      br_table 0 1 0
+    end)
+  (memory (;0;) 1)
+  (global (;0;) (mut f64) (f64.const 0.5))
+  (export "f" (func 1))
+  (data (;0;) (i32.const 16) "a\"\0a"))
+"#
+        );
+    }
+}
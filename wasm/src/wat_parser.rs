@@ -0,0 +1,1587 @@
+use crate::ast::*;
+use crate::float::{parse_f32, parse_f64};
+use parser::{
+    or,
+    parser::{
+        any_one, eof, expect, fail, parser_func, token, tokens, val, Either, Parser, ParserError,
+    },
+    stream::Stream,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+// Reads modules in the WebAssembly text format. Besides what `wat::print_module` writes, it
+// accepts `$name` identifiers, inline `(export ..)`, inline signatures, folded instructions
+// such as `(i32.add (local.get 0) (i32.const 1))` and the pre-standard mnemonics like
+// `get_local`. The source is first read into S-expressions and then lowered field by field.
+
+type Result<T> = std::result::Result<T, ParserError<char>>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum SExprKind {
+    Atom(String),
+    Str(Vec<u8>),
+    List(Vec<SExpr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SExpr {
+    kind: SExprKind,
+    pos: usize,
+    len: usize,
+}
+
+fn string(s: &str) -> impl Parser<Input = char, Output = ()> {
+    tokens(s.chars().collect()).with(val(()))
+}
+
+fn line_comment() -> impl Parser<Input = char, Output = ()> {
+    string(";;")
+        .attempt()
+        .with(expect(|&x| x != '\n').many())
+        .with(val(()))
+}
+
+fn block_comment() -> impl Parser<Input = char, Output = ()> {
+    parser_func(|st| {
+        string("(;")
+            .attempt()
+            .with(
+                parser_func(|st| match (st.peak(), st.peak_index(1)) {
+                    (Some('('), Some(';')) => block_comment().parse(st),
+                    (Some(';'), Some(')')) => fail().parse(st),
+                    _ => any_one().with(val(())).parse(st),
+                })
+                .many(),
+            )
+            .with(string(";)"))
+            .parse(st)
+    })
+}
+
+fn skip() -> impl Parser<Input = char, Output = ()> {
+    or!(
+        expect(|&x: &char| x.is_whitespace()).with(val(())),
+        line_comment(),
+        block_comment()
+    )
+    .many()
+    .with(val(()))
+}
+
+fn hex_digit() -> impl Parser<Input = char, Output = u32> {
+    expect(|x: &char| x.is_ascii_hexdigit()).map(|x| x.to_digit(16).unwrap())
+}
+
+fn string_char() -> impl Parser<Input = char, Output = Vec<u8>> {
+    let escape = or!(
+        token('t').val(vec![b'\t']),
+        token('n').val(vec![b'\n']),
+        token('r').val(vec![b'\r']),
+        token('"').val(vec![b'"']),
+        token('\'').val(vec![b'\'']),
+        token('\\').val(vec![b'\\']),
+        token('u')
+            .with(token('{'))
+            .with(hex_digit().many1())
+            .skip(token('}'))
+            .then(|xs| {
+                let c = xs
+                    .into_iter()
+                    .try_fold(0u32, |acc, x| acc.checked_mul(16).map(|acc| acc + x))
+                    .and_then(std::char::from_u32);
+                match c {
+                    Some(c) => Either::Left(val(c.to_string().into_bytes())),
+                    None => Either::Right(fail()),
+                }
+            }),
+        hex_digit()
+            .and(hex_digit())
+            .map(|(x, y)| vec![(x * 16 + y) as u8])
+    );
+    or!(
+        token('\\').with(escape),
+        expect(|&x: &char| x != '"' && x != '\\').map(|x| x.to_string().into_bytes())
+    )
+}
+
+fn string_literal() -> impl Parser<Input = char, Output = Vec<u8>> {
+    token('"')
+        .with(string_char().many())
+        .skip(token('"'))
+        .map(|xs| xs.concat())
+}
+
+fn atom() -> impl Parser<Input = char, Output = String> {
+    expect(|&x: &char| !x.is_whitespace() && !"()\";".contains(x))
+        .many1()
+        .map(|x| x.into_iter().collect())
+}
+
+fn sexpr() -> impl Parser<Input = char, Output = SExpr> {
+    parser_func(|st| {
+        let pos = st.pos();
+        let kind = or!(
+            token('(')
+                .with(skip())
+                .with(sexpr().skip(skip()).many())
+                .skip(token(')'))
+                .map(SExprKind::List),
+            string_literal().map(SExprKind::Str),
+            atom().map(SExprKind::Atom)
+        )
+        .parse(st)?;
+        Ok(SExpr {
+            kind,
+            pos,
+            len: st.pos() - pos,
+        })
+    })
+}
+
+fn error(x: &SExpr, message: String) -> ParserError<char> {
+    ParserError::with_message(x.pos, x.len, message)
+}
+
+fn head(x: &SExpr) -> Option<&str> {
+    match &x.kind {
+        SExprKind::List(xs) => match xs.first().map(|x| &x.kind) {
+            Some(SExprKind::Atom(s)) => Some(s),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn describe(x: &SExpr) -> String {
+    match &x.kind {
+        SExprKind::Atom(s) => format!("`{}`", s),
+        SExprKind::Str(_) => "string".to_string(),
+        SExprKind::List(_) => match head(x) {
+            Some(s) => format!("`({} ...)`", s),
+            None => "list".to_string(),
+        },
+    }
+}
+
+// The items of a list after its keyword.
+struct Cursor<'a> {
+    parent: &'a SExpr,
+    xs: &'a [SExpr],
+    i: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(x: &'a SExpr) -> Cursor<'a> {
+        let xs = match &x.kind {
+            SExprKind::List(xs) => &xs[..],
+            _ => &[],
+        };
+        Cursor {
+            parent: x,
+            xs,
+            i: 1.min(xs.len()),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a SExpr> {
+        self.xs.get(self.i)
+    }
+
+    fn next(&mut self, what: &str) -> Result<&'a SExpr> {
+        match self.peek() {
+            Some(x) => {
+                self.i += 1;
+                Ok(x)
+            }
+            None => Err(ParserError::with_message(
+                self.parent.pos + self.parent.len - 1,
+                1,
+                format!("expected {}", what),
+            )),
+        }
+    }
+
+    fn peek_atom(&self) -> Option<&'a str> {
+        match self.peek().map(|x| &x.kind) {
+            Some(SExprKind::Atom(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn peek_list(&self, keyword: &str) -> Option<&'a SExpr> {
+        self.peek().filter(|x| head(x) == Some(keyword))
+    }
+
+    fn id(&mut self) -> Option<&'a str> {
+        let s = self.peek_atom().filter(|s| s.starts_with('$'))?;
+        self.i += 1;
+        Some(s)
+    }
+
+    fn end(&self) -> Result<()> {
+        match self.peek() {
+            Some(x) => Err(error(x, format!("unexpected {}", describe(x)))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn atom_str(x: &SExpr) -> Option<&str> {
+    match &x.kind {
+        SExprKind::Atom(s) => Some(s),
+        _ => None,
+    }
+}
+
+fn is_index(x: &SExpr) -> bool {
+    atom_str(x).is_some_and(|s| s.starts_with('$') || s.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+fn nat(s: &str) -> Option<u64> {
+    let s = s.replace('_', "");
+    match s.strip_prefix("0x") {
+        Some(h) if !h.is_empty() && h.chars().all(|c| c.is_ascii_hexdigit()) => {
+            u64::from_str_radix(h, 16).ok()
+        }
+        None if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn uint(x: &SExpr) -> Result<u32> {
+    let n = atom_str(x)
+        .and_then(nat)
+        .ok_or_else(|| error(x, format!("expected an integer, found {}", describe(x))))?;
+    u32::try_from(n).map_err(|_| error(x, "integer constant out of range".to_string()))
+}
+
+// Integers of `bits` bits may be written signed or unsigned; the result wraps like the
+// corresponding `iN.const` does.
+fn int(x: &SExpr, bits: u32) -> Result<i64> {
+    let s = atom_str(x).unwrap_or("");
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let n =
+        nat(s).ok_or_else(|| error(x, format!("expected an integer, found {}", describe(x))))?;
+    let max = if negative {
+        1u64 << (bits - 1)
+    } else {
+        u64::MAX >> (64 - bits)
+    };
+    if n > max {
+        return Err(error(x, "integer constant out of range".to_string()));
+    }
+    Ok(if negative {
+        (n as i64).wrapping_neg()
+    } else {
+        n as i64
+    })
+}
+
+trait Float: Sized {
+    fn parse(s: &str) -> Option<Self>;
+}
+
+impl Float for f32 {
+    fn parse(s: &str) -> Option<f32> {
+        parse_f32(s)
+    }
+}
+
+impl Float for f64 {
+    fn parse(s: &str) -> Option<f64> {
+        parse_f64(s)
+    }
+}
+
+fn float<T: Float>(x: &SExpr) -> Result<T> {
+    atom_str(x)
+        .and_then(T::parse)
+        .ok_or_else(|| error(x, format!("expected a float, found {}", describe(x))))
+}
+
+// The operand of `v128.const`: a shape such as `i32x4` and then its lanes.
+fn v128(c: &mut Cursor) -> Result<u128> {
+    let x = c.next("a shape")?;
+    let (bits, float_lanes) = match atom_str(x) {
+        Some("i8x16") => (8, false),
+        Some("i16x8") => (16, false),
+        Some("i32x4") => (32, false),
+        Some("i64x2") => (64, false),
+        Some("f32x4") => (32, true),
+        Some("f64x2") => (64, true),
+        _ => {
+            return Err(error(
+                x,
+                format!("expected a shape such as `i32x4`, found {}", describe(x)),
+            ))
+        }
+    };
+    let mut v = 0;
+    for i in 0..128 / bits {
+        let y = c.next("a lane")?;
+        let lane = match (float_lanes, bits) {
+            (true, 32) => float::<f32>(y)?.to_bits().into(),
+            (true, _) => float::<f64>(y)?.to_bits(),
+            _ => int(y, bits)? as u64,
+        };
+        v |= (lane as u128 & ((1 << bits) - 1)) << (i * bits);
+    }
+    Ok(v)
+}
+
+fn value_type(x: &SExpr) -> Result<ValueType> {
+    match atom_str(x) {
+        Some("i32") => Ok(ValueType::I32),
+        Some("i64") => Ok(ValueType::I64),
+        Some("f32") => Ok(ValueType::F32),
+        Some("f64") => Ok(ValueType::F64),
+        Some("v128") => Ok(ValueType::V128),
+        Some("funcref") => Ok(ValueType::FuncRef),
+        Some("externref") => Ok(ValueType::ExternRef),
+        _ => Err(error(
+            x,
+            format!("expected a value type, found {}", describe(x)),
+        )),
+    }
+}
+
+fn global_type(x: &SExpr) -> Result<GlobalType> {
+    if head(x) == Some("mut") {
+        let mut c = Cursor::new(x);
+        let content_type = value_type(c.next("a value type")?)?;
+        c.end()?;
+        Ok(GlobalType {
+            content_type,
+            mutability: true,
+        })
+    } else {
+        Ok(GlobalType {
+            content_type: value_type(x)?,
+            mutability: false,
+        })
+    }
+}
+
+fn limits(c: &mut Cursor) -> Result<ResizableLimits> {
+    let initial = uint(c.next("the initial size")?)? as i32;
+    let maximum = match c.peek() {
+        Some(x) if is_index(x) => {
+            c.i += 1;
+            Some(uint(x)? as i32)
+        }
+        _ => None,
+    };
+    let shared = c.peek().and_then(atom_str) == Some("shared");
+    if shared {
+        c.i += 1;
+    }
+    Ok(ResizableLimits {
+        initial,
+        maximum,
+        shared,
+    })
+}
+
+fn elem_type(c: &mut Cursor) -> Result<ElemType> {
+    let x = c.next("a reference type")?;
+    match atom_str(x) {
+        Some("funcref") | Some("anyfunc") => Ok(ElemType::AnyFunc),
+        Some("externref") => Ok(ElemType::ExternRef),
+        _ => Err(error(
+            x,
+            format!("expected a reference type, found {}", describe(x)),
+        )),
+    }
+}
+
+// The heap type of `ref.null`.
+fn heap_type(c: &mut Cursor) -> Result<ElemType> {
+    let x = c.next("a heap type")?;
+    match atom_str(x) {
+        Some("func") => Ok(ElemType::AnyFunc),
+        Some("extern") => Ok(ElemType::ExternRef),
+        _ => Err(error(
+            x,
+            format!("expected `func` or `extern`, found {}", describe(x)),
+        )),
+    }
+}
+
+fn results(c: &mut Cursor) -> Result<Vec<ValueType>> {
+    let mut results = Vec::new();
+    while let Some(x) = c.peek_list("result") {
+        c.i += 1;
+        let mut r = Cursor::new(x);
+        while let Some(y) = r.peek() {
+            r.i += 1;
+            results.push(value_type(y)?);
+        }
+    }
+    Ok(results)
+}
+
+fn name(x: &SExpr) -> Result<String> {
+    match &x.kind {
+        SExprKind::Str(s) => String::from_utf8(s.clone())
+            .map_err(|_| error(x, "malformed UTF-8 encoding".to_string())),
+        _ => Err(error(
+            x,
+            format!("expected a string, found {}", describe(x)),
+        )),
+    }
+}
+
+// The signature of a function: `(param ..)*` followed by `(result ..)*`, with the names of
+// the parameters that have one.
+fn signature(c: &mut Cursor) -> Result<(FuncType, Vec<Option<String>>, bool)> {
+    let start = c.i;
+    let mut params = Vec::new();
+    let mut names = Vec::new();
+    while let Some(x) = c.peek_list("param") {
+        c.i += 1;
+        let mut p = Cursor::new(x);
+        if let Some(id) = p.id() {
+            params.push(value_type(p.next("a value type")?)?);
+            names.push(Some(id.to_string()));
+            p.end()?;
+        } else {
+            while let Some(y) = p.peek() {
+                p.i += 1;
+                params.push(value_type(y)?);
+                names.push(None);
+            }
+        }
+    }
+    let results = results(c)?;
+    Ok((FuncType { params, results }, names, c.i != start))
+}
+
+const NULLARY: &[OperatorCode] = &[
+    OperatorCode::Unreachable,
+    OperatorCode::Nop,
+    OperatorCode::Return,
+    OperatorCode::Drop,
+    OperatorCode::Select,
+    OperatorCode::CurrentMemory,
+    OperatorCode::GrowMemory,
+    OperatorCode::I32Eqz,
+    OperatorCode::I32Eq,
+    OperatorCode::I32Ne,
+    OperatorCode::I32Lts,
+    OperatorCode::I32Ltu,
+    OperatorCode::I32Gts,
+    OperatorCode::I32Gtu,
+    OperatorCode::I32Les,
+    OperatorCode::I32Leu,
+    OperatorCode::I32Ges,
+    OperatorCode::I32Geu,
+    OperatorCode::I64Eqz,
+    OperatorCode::I64Eq,
+    OperatorCode::I64Ne,
+    OperatorCode::I64Lts,
+    OperatorCode::I64Ltu,
+    OperatorCode::I64Gts,
+    OperatorCode::I64Gtu,
+    OperatorCode::I64Les,
+    OperatorCode::I64Leu,
+    OperatorCode::I64Ges,
+    OperatorCode::I64Geu,
+    OperatorCode::F32Eq,
+    OperatorCode::F32Ne,
+    OperatorCode::F32Lt,
+    OperatorCode::F32Gt,
+    OperatorCode::F32Le,
+    OperatorCode::F32Ge,
+    OperatorCode::F64Eq,
+    OperatorCode::F64Ne,
+    OperatorCode::F64Lt,
+    OperatorCode::F64Gt,
+    OperatorCode::F64Le,
+    OperatorCode::F64Ge,
+    OperatorCode::I32Clz,
+    OperatorCode::I32Ctz,
+    OperatorCode::I32Popcnt,
+    OperatorCode::I32Add,
+    OperatorCode::I32Sub,
+    OperatorCode::I32Mul,
+    OperatorCode::I32Divs,
+    OperatorCode::I32Divu,
+    OperatorCode::I32Rems,
+    OperatorCode::I32Remu,
+    OperatorCode::I32And,
+    OperatorCode::I32Or,
+    OperatorCode::I32Xor,
+    OperatorCode::I32Shl,
+    OperatorCode::I32Shrs,
+    OperatorCode::I32Shru,
+    OperatorCode::I32Rotl,
+    OperatorCode::I32Rotr,
+    OperatorCode::I64Clz,
+    OperatorCode::I64Ctz,
+    OperatorCode::I64Popcnt,
+    OperatorCode::I64Add,
+    OperatorCode::I64Sub,
+    OperatorCode::I64Mul,
+    OperatorCode::I64Divs,
+    OperatorCode::I64Divu,
+    OperatorCode::I64Rems,
+    OperatorCode::I64Remu,
+    OperatorCode::I64And,
+    OperatorCode::I64Or,
+    OperatorCode::I64Xor,
+    OperatorCode::I64Shl,
+    OperatorCode::I64Shrs,
+    OperatorCode::I64Shru,
+    OperatorCode::I64Rotl,
+    OperatorCode::I64Rotr,
+    OperatorCode::F32Abs,
+    OperatorCode::F32Neg,
+    OperatorCode::F32Ceil,
+    OperatorCode::F32Floor,
+    OperatorCode::F32Trunc,
+    OperatorCode::F32Nearest,
+    OperatorCode::F32Sqrt,
+    OperatorCode::F32Add,
+    OperatorCode::F32Sub,
+    OperatorCode::F32Mul,
+    OperatorCode::F32Div,
+    OperatorCode::F32Min,
+    OperatorCode::F32Max,
+    OperatorCode::F32Copysign,
+    OperatorCode::F64Abs,
+    OperatorCode::F64Neg,
+    OperatorCode::F64Ceil,
+    OperatorCode::F64Floor,
+    OperatorCode::F64Trunc,
+    OperatorCode::F64Nearest,
+    OperatorCode::F64Sqrt,
+    OperatorCode::F64Add,
+    OperatorCode::F64Sub,
+    OperatorCode::F64Mul,
+    OperatorCode::F64Div,
+    OperatorCode::F64Min,
+    OperatorCode::F64Max,
+    OperatorCode::F64Copysign,
+    OperatorCode::I32WrapI64,
+    OperatorCode::I32TruncsF32,
+    OperatorCode::I32TrancuF32,
+    OperatorCode::I32TrancsF64,
+    OperatorCode::I32TrancuF64,
+    OperatorCode::I64ExtendsI32,
+    OperatorCode::I64ExtenduI32,
+    OperatorCode::I64TruncsF32,
+    OperatorCode::I64TrancuF32,
+    OperatorCode::I64TrancsF64,
+    OperatorCode::I64TrancuF64,
+    OperatorCode::F32ConvertsI32,
+    OperatorCode::F32ConvertuI32,
+    OperatorCode::F32ConvertsI64,
+    OperatorCode::F32ConvertuI64,
+    OperatorCode::F32DemoteF64,
+    OperatorCode::F64ConvertsI32,
+    OperatorCode::F64ConvertuI32,
+    OperatorCode::F64ConvertsI64,
+    OperatorCode::F64ConvertuI64,
+    OperatorCode::F64PromoteF32,
+    OperatorCode::I32ReinterpretF32,
+    OperatorCode::I64ReinterpretF64,
+    OperatorCode::F32ReinterpretI32,
+    OperatorCode::F64ReinterpretI64,
+    OperatorCode::RefIsNull,
+    OperatorCode::MemoryCopy,
+    OperatorCode::MemoryFill,
+    OperatorCode::AtomicFence,
+    OperatorCode::I8x16Splat,
+    OperatorCode::I16x8Splat,
+    OperatorCode::I32x4Splat,
+    OperatorCode::I64x2Splat,
+    OperatorCode::F32x4Splat,
+    OperatorCode::F64x2Splat,
+    OperatorCode::I8x16Eq,
+    OperatorCode::I8x16Ne,
+    OperatorCode::I16x8Eq,
+    OperatorCode::I16x8Ne,
+    OperatorCode::I32x4Eq,
+    OperatorCode::I32x4Ne,
+    OperatorCode::F32x4Eq,
+    OperatorCode::F32x4Ne,
+    OperatorCode::F64x2Eq,
+    OperatorCode::F64x2Ne,
+    OperatorCode::V128Not,
+    OperatorCode::V128And,
+    OperatorCode::V128AndNot,
+    OperatorCode::V128Or,
+    OperatorCode::V128Xor,
+    OperatorCode::V128Bitselect,
+    OperatorCode::V128AnyTrue,
+    OperatorCode::I8x16AllTrue,
+    OperatorCode::I8x16Bitmask,
+    OperatorCode::I8x16Add,
+    OperatorCode::I8x16Sub,
+    OperatorCode::I16x8AllTrue,
+    OperatorCode::I16x8Bitmask,
+    OperatorCode::I16x8Add,
+    OperatorCode::I16x8Sub,
+    OperatorCode::I16x8Mul,
+    OperatorCode::I32x4AllTrue,
+    OperatorCode::I32x4Bitmask,
+    OperatorCode::I32x4Add,
+    OperatorCode::I32x4Sub,
+    OperatorCode::I32x4Mul,
+    OperatorCode::I64x2AllTrue,
+    OperatorCode::I64x2Bitmask,
+    OperatorCode::I64x2Add,
+    OperatorCode::I64x2Sub,
+    OperatorCode::I64x2Mul,
+    OperatorCode::F32x4Add,
+    OperatorCode::F32x4Sub,
+    OperatorCode::F32x4Mul,
+    OperatorCode::F32x4Div,
+    OperatorCode::F64x2Add,
+    OperatorCode::F64x2Sub,
+    OperatorCode::F64x2Mul,
+    OperatorCode::F64x2Div,
+];
+
+const LANE: &[fn(u8) -> OperatorCode] = &[
+    OperatorCode::I8x16ExtractLanes,
+    OperatorCode::I8x16ExtractLaneu,
+    OperatorCode::I8x16ReplaceLane,
+    OperatorCode::I16x8ExtractLanes,
+    OperatorCode::I16x8ExtractLaneu,
+    OperatorCode::I16x8ReplaceLane,
+    OperatorCode::I32x4ExtractLane,
+    OperatorCode::I32x4ReplaceLane,
+    OperatorCode::I64x2ExtractLane,
+    OperatorCode::I64x2ReplaceLane,
+    OperatorCode::F32x4ExtractLane,
+    OperatorCode::F32x4ReplaceLane,
+    OperatorCode::F64x2ExtractLane,
+    OperatorCode::F64x2ReplaceLane,
+];
+
+const MEMORY: &[fn(MemoryImmediate) -> OperatorCode] = &[
+    OperatorCode::I32Load,
+    OperatorCode::I64Load,
+    OperatorCode::F32Load,
+    OperatorCode::F64Load,
+    OperatorCode::I32Load8s,
+    OperatorCode::I32Load8u,
+    OperatorCode::I32Load16s,
+    OperatorCode::I32Load16u,
+    OperatorCode::I64Load8s,
+    OperatorCode::I64Load8u,
+    OperatorCode::I64Load16s,
+    OperatorCode::I64Load16u,
+    OperatorCode::I64Load32s,
+    OperatorCode::I64Load32u,
+    OperatorCode::I32Store,
+    OperatorCode::I64Store,
+    OperatorCode::F32Store,
+    OperatorCode::F64Store,
+    OperatorCode::I32Store8,
+    OperatorCode::I32Store16,
+    OperatorCode::I64Store8,
+    OperatorCode::I64Store16,
+    OperatorCode::I64Store32,
+    OperatorCode::V128Load,
+    OperatorCode::V128Store,
+    OperatorCode::MemoryAtomicNotify,
+    OperatorCode::MemoryAtomicWait32,
+    OperatorCode::MemoryAtomicWait64,
+    OperatorCode::I32AtomicLoad,
+    OperatorCode::I64AtomicLoad,
+    OperatorCode::I32AtomicStore,
+    OperatorCode::I64AtomicStore,
+    OperatorCode::I32AtomicRmwAdd,
+    OperatorCode::I64AtomicRmwAdd,
+    OperatorCode::I32AtomicRmwSub,
+    OperatorCode::I64AtomicRmwSub,
+    OperatorCode::I32AtomicRmwAnd,
+    OperatorCode::I64AtomicRmwAnd,
+    OperatorCode::I32AtomicRmwOr,
+    OperatorCode::I64AtomicRmwOr,
+    OperatorCode::I32AtomicRmwXor,
+    OperatorCode::I64AtomicRmwXor,
+    OperatorCode::I32AtomicRmwXchg,
+    OperatorCode::I64AtomicRmwXchg,
+    OperatorCode::I32AtomicRmwCmpxchg,
+    OperatorCode::I64AtomicRmwCmpxchg,
+];
+
+// Loads and stores take `offset=N` and `align=N`, with the natural alignment by default.
+fn memory_op(f: fn(MemoryImmediate) -> OperatorCode, c: &mut Cursor) -> Result<OperatorCode> {
+    let natural = f(MemoryImmediate {
+        flags: 0,
+        offset: 0,
+    })
+    .natural_alignment()
+    .unwrap();
+    let mut imm = MemoryImmediate {
+        flags: natural,
+        offset: 0,
+    };
+    while let Some(s) = c.peek_atom() {
+        let x = c.peek().unwrap();
+        if let Some(n) = s.strip_prefix("offset=") {
+            imm.offset = nat(n)
+                .and_then(|n| u32::try_from(n).ok())
+                .ok_or_else(|| error(x, format!("invalid offset {}", describe(x))))?;
+        } else if let Some(n) = s.strip_prefix("align=") {
+            imm.flags = nat(n)
+                .filter(|n| n.is_power_of_two())
+                .map(|n| n.trailing_zeros())
+                .ok_or_else(|| error(x, "alignment must be a power of two".to_string()))?;
+        } else {
+            break;
+        }
+        c.i += 1;
+    }
+    Ok(f(imm))
+}
+
+#[derive(Clone, Copy)]
+enum Space {
+    Type,
+    Func,
+    Table,
+    Memory,
+    Global,
+    Data,
+}
+
+impl Space {
+    fn name(self) -> &'static str {
+        match self {
+            Space::Type => "type",
+            Space::Func => "function",
+            Space::Table => "table",
+            Space::Memory => "memory",
+            Space::Global => "global",
+            Space::Data => "data segment",
+        }
+    }
+}
+
+// The locals and enclosing block labels of the function being read.
+#[derive(Default)]
+struct Body {
+    locals: HashMap<String, usize>,
+    labels: Vec<Option<String>>,
+}
+
+impl Body {
+    fn local(&self, x: &SExpr) -> Result<usize> {
+        match atom_str(x).filter(|s| s.starts_with('$')) {
+            Some(s) => self
+                .locals
+                .get(s)
+                .copied()
+                .ok_or_else(|| error(x, format!("unknown local `{}`", s))),
+            None => Ok(uint(x)? as usize),
+        }
+    }
+
+    fn label(&self, x: &SExpr) -> Result<usize> {
+        match atom_str(x).filter(|s| s.starts_with('$')) {
+            Some(s) => self
+                .labels
+                .iter()
+                .rev()
+                .position(|l| l.as_deref() == Some(s))
+                .ok_or_else(|| error(x, format!("unknown label `{}`", s))),
+            None => Ok(uint(x)? as usize),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Module {
+    names: [HashMap<String, usize>; 6],
+    // The number of imported and defined items in each index space, filled in before any
+    // body is read so that functions can refer to later ones.
+    imported: [usize; 6],
+    defined: [usize; 6],
+    types: Vec<FuncType>,
+    imports: Vec<ImportEntry>,
+    funcs: Vec<usize>,
+    tables: Vec<TableType>,
+    memories: Vec<MemoryType>,
+    globals: Vec<GlobalVariable>,
+    exports: Vec<ExportEntry>,
+    start: Option<usize>,
+    elems: Vec<ElemSegment>,
+    codes: Vec<FunctionBody>,
+    datas: Vec<DataSegment>,
+}
+
+impl Module {
+    fn declare(&mut self, x: &SExpr, space: Space, id: Option<&str>, import: bool) -> Result<()> {
+        let count = self.imported[space as usize] + self.defined[space as usize];
+        if import {
+            self.imported[space as usize] += 1;
+        } else {
+            self.defined[space as usize] += 1;
+        }
+        match id {
+            Some(id)
+                if self.names[space as usize]
+                    .insert(id.to_string(), count)
+                    .is_some() =>
+            {
+                Err(error(x, format!("duplicate {} `{}`", space.name(), id)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn index(&self, x: &SExpr, space: Space) -> Result<usize> {
+        match atom_str(x).filter(|s| s.starts_with('$')) {
+            Some(s) => self.names[space as usize]
+                .get(s)
+                .copied()
+                .ok_or_else(|| error(x, format!("unknown {} `{}`", space.name(), s))),
+            None => Ok(uint(x)? as usize),
+        }
+    }
+
+    // `(type x)` and/or an inline signature. Without `(type x)` the first type with the same
+    // signature is used, and a new one is appended when there is none.
+    fn type_use(&mut self, c: &mut Cursor) -> Result<(usize, Vec<Option<String>>)> {
+        let explicit = match c.peek_list("type") {
+            Some(x) => {
+                c.i += 1;
+                let mut t = Cursor::new(x);
+                let index = self.index(t.next("a type")?, Space::Type)?;
+                t.end()?;
+                if index >= self.types.len() {
+                    return Err(error(x, format!("unknown type {}", index)));
+                }
+                Some((index, x))
+            }
+            None => None,
+        };
+        let (sig, names, inline) = signature(c)?;
+        match explicit {
+            Some((index, x)) => {
+                if inline && self.types[index] != sig {
+                    return Err(error(
+                        x,
+                        format!("inline signature does not match type {}", index),
+                    ));
+                }
+                Ok((index, names))
+            }
+            None => {
+                let index = match self.types.iter().position(|t| t == &sig) {
+                    Some(index) => index,
+                    None => {
+                        self.types.push(sig);
+                        self.types.len() - 1
+                    }
+                };
+                Ok((index, names))
+            }
+        }
+    }
+
+    // A block type is a function type when it has `(type x)`, parameters or several results.
+    fn block_type(&mut self, c: &mut Cursor) -> Result<BlockType> {
+        if c.peek_list("type").is_none() {
+            let start = c.i;
+            let (sig, _, _) = signature(c)?;
+            if sig.params.is_empty() && sig.results.len() <= 1 {
+                return Ok(BlockType::from(sig.results.into_iter().next()));
+            }
+            c.i = start;
+        }
+        Ok(BlockType::Func(self.type_use(c)?.0))
+    }
+
+    fn inline_exports(&mut self, c: &mut Cursor, kind: ExternalKind, index: usize) -> Result<()> {
+        while let Some(x) = c.peek_list("export") {
+            c.i += 1;
+            let mut e = Cursor::new(x);
+            let field = name(e.next("a name")?)?;
+            e.end()?;
+            self.exports.push(ExportEntry {
+                field,
+                kind: kind.clone(),
+                index,
+            });
+        }
+        if let Some(x) = c.peek_list("import") {
+            return Err(error(x, "inline imports are not supported".to_string()));
+        }
+        Ok(())
+    }
+
+    fn init_expr(&self, x: &SExpr) -> Result<InitExpr> {
+        let mut c = Cursor::new(x);
+        let expr = match head(x) {
+            Some("i32.const") => InitExpr::I32(int(c.next("an integer")?, 32)? as i32),
+            Some("i64.const") => InitExpr::I64(int(c.next("an integer")?, 64)?),
+            Some("f32.const") => InitExpr::F32(float(c.next("a float")?)?),
+            Some("f64.const") => InitExpr::F64(float(c.next("a float")?)?),
+            Some("global.get") | Some("get_global") => {
+                InitExpr::Global(self.index(c.next("a global")?, Space::Global)?)
+            }
+            _ => {
+                return Err(error(
+                    x,
+                    format!("expected a constant expression, found {}", describe(x)),
+                ))
+            }
+        };
+        c.end()?;
+        Ok(expr)
+    }
+
+    // `(offset expr)` or just `expr`.
+    fn offset(&self, c: &mut Cursor) -> Result<InitExpr> {
+        let x = c.next("an offset")?;
+        if head(x) == Some("offset") {
+            let mut o = Cursor::new(x);
+            let expr = self.init_expr(o.next("a constant expression")?)?;
+            o.end()?;
+            Ok(expr)
+        } else {
+            self.init_expr(x)
+        }
+    }
+
+    fn instrs(
+        &mut self,
+        body: &mut Body,
+        c: &mut Cursor,
+        out: &mut Vec<OperatorCode>,
+    ) -> Result<()> {
+        while c.peek().is_some() {
+            self.instr(body, c, out)?;
+        }
+        Ok(())
+    }
+
+    fn instr(
+        &mut self,
+        body: &mut Body,
+        c: &mut Cursor,
+        out: &mut Vec<OperatorCode>,
+    ) -> Result<()> {
+        let x = c.next("an instruction")?;
+        let s = match &x.kind {
+            SExprKind::List(_) => return self.folded(body, x, out),
+            SExprKind::Atom(s) => s.as_str(),
+            SExprKind::Str(_) => {
+                return Err(error(
+                    x,
+                    "expected an instruction, found string".to_string(),
+                ))
+            }
+        };
+        match s {
+            "block" | "loop" | "if" => {
+                let label = c.id().map(str::to_string);
+                let t = self.block_type(c)?;
+                out.push(match s {
+                    "block" => OperatorCode::Block(t),
+                    "loop" => OperatorCode::Loop(t),
+                    _ => OperatorCode::If(t),
+                });
+                body.labels.push(label);
+            }
+            "else" | "end" => {
+                c.id();
+                if body.labels.is_empty() {
+                    return Err(error(x, format!("`{}` outside of a block", s)));
+                }
+                if s == "end" {
+                    body.labels.pop();
+                    out.push(OperatorCode::End);
+                } else {
+                    out.push(OperatorCode::Else);
+                }
+            }
+            _ => {
+                let op = self.plain(body, x, c)?;
+                out.push(op);
+            }
+        }
+        Ok(())
+    }
+
+    // `(op immediates.. operands..)`, where the operands are folded instructions evaluated
+    // before `op`, and the structured forms `(block ..)`, `(loop ..)` and
+    // `(if .. (then ..) (else ..))`.
+    fn folded(&mut self, body: &mut Body, x: &SExpr, out: &mut Vec<OperatorCode>) -> Result<()> {
+        let keyword = match head(x) {
+            Some(s) => s,
+            None => {
+                return Err(error(
+                    x,
+                    format!("expected an instruction, found {}", describe(x)),
+                ))
+            }
+        };
+        let mut c = Cursor::new(x);
+        match keyword {
+            "block" | "loop" => {
+                let label = c.id().map(str::to_string);
+                let t = self.block_type(&mut c)?;
+                out.push(if keyword == "block" {
+                    OperatorCode::Block(t)
+                } else {
+                    OperatorCode::Loop(t)
+                });
+                body.labels.push(label);
+                self.instrs(body, &mut c, out)?;
+                body.labels.pop();
+                out.push(OperatorCode::End);
+            }
+            "if" => {
+                let label = c.id().map(str::to_string);
+                let t = self.block_type(&mut c)?;
+                while c.peek().is_some() && c.peek_list("then").is_none() {
+                    let y = c.next("a condition")?;
+                    self.folded(body, y, out)?;
+                }
+                let then = c.next("`(then ..)`")?;
+                if head(then) != Some("then") {
+                    return Err(error(
+                        then,
+                        format!("expected `(then ..)`, found {}", describe(then)),
+                    ));
+                }
+                out.push(OperatorCode::If(t));
+                body.labels.push(label);
+                self.instrs(body, &mut Cursor::new(then), out)?;
+                if let Some(y) = c.peek_list("else") {
+                    c.i += 1;
+                    out.push(OperatorCode::Else);
+                    self.instrs(body, &mut Cursor::new(y), out)?;
+                }
+                c.end()?;
+                body.labels.pop();
+                out.push(OperatorCode::End);
+            }
+            _ => {
+                let op = self.plain(body, &c.xs[0], &mut c)?;
+                while let Some(y) = c.peek() {
+                    c.i += 1;
+                    self.folded(body, y, out)?;
+                }
+                out.push(op);
+            }
+        }
+        Ok(())
+    }
+
+    // The table an instruction names, which is the first one when it leaves it out.
+    fn table(&self, c: &mut Cursor) -> Result<usize> {
+        match c.peek().filter(|y| is_index(y)) {
+            Some(y) => {
+                c.i += 1;
+                self.index(y, Space::Table)
+            }
+            None => Ok(0),
+        }
+    }
+
+    // An instruction other than the block structure, reading its immediates from `c`.
+    fn plain(&mut self, body: &Body, x: &SExpr, c: &mut Cursor) -> Result<OperatorCode> {
+        let s = atom_str(x).unwrap_or("");
+        Ok(match s {
+            "br" => OperatorCode::Br(body.label(c.next("a label")?)?),
+            "br_if" => OperatorCode::BrIf(body.label(c.next("a label")?)?),
+            "br_table" => {
+                let mut params = vec![body.label(c.next("a label")?)?];
+                while let Some(y) = c.peek().filter(|y| is_index(y)) {
+                    c.i += 1;
+                    params.push(body.label(y)?);
+                }
+                let index = params.pop().unwrap();
+                OperatorCode::BrTable { index, params }
+            }
+            "call" => OperatorCode::Call(self.index(c.next("a function")?, Space::Func)?),
+            "call_indirect" => {
+                if let Some(y) = c.peek().filter(|y| is_index(y)) {
+                    c.i += 1;
+                    self.index(y, Space::Table)?;
+                }
+                OperatorCode::CallIndirect(self.type_use(c)?.0)
+            }
+            "local.get" | "get_local" => OperatorCode::GetLocal(body.local(c.next("a local")?)?),
+            "local.set" | "set_local" => OperatorCode::SetLocal(body.local(c.next("a local")?)?),
+            "local.tee" | "tee_local" => OperatorCode::TeeLocal(body.local(c.next("a local")?)?),
+            "global.get" | "get_global" => {
+                OperatorCode::GetGlobal(self.index(c.next("a global")?, Space::Global)?)
+            }
+            "global.set" | "set_global" => {
+                OperatorCode::SetGlobal(self.index(c.next("a global")?, Space::Global)?)
+            }
+            "table.get" => OperatorCode::TableGet(self.table(c)?),
+            "table.set" => OperatorCode::TableSet(self.table(c)?),
+            "ref.null" => OperatorCode::RefNull(heap_type(c)?),
+            "memory.init" => {
+                OperatorCode::MemoryInit(self.index(c.next("a data segment")?, Space::Data)?)
+            }
+            "data.drop" => {
+                OperatorCode::DataDrop(self.index(c.next("a data segment")?, Space::Data)?)
+            }
+            "ref.func" => OperatorCode::RefFunc(self.index(c.next("a function")?, Space::Func)?),
+            "i32.const" => OperatorCode::I32Const(int(c.next("an integer")?, 32)? as i32),
+            "i64.const" => OperatorCode::I64Const(int(c.next("an integer")?, 64)?),
+            "f32.const" => OperatorCode::F32Const(float(c.next("a float")?)?),
+            "f64.const" => OperatorCode::F64Const(float(c.next("a float")?)?),
+            "v128.const" => OperatorCode::V128Const(v128(c)?),
+            "current_memory" => OperatorCode::CurrentMemory,
+            "grow_memory" => OperatorCode::GrowMemory,
+            _ => {
+                let default = MemoryImmediate {
+                    flags: 0,
+                    offset: 0,
+                };
+                if let Some(&f) = MEMORY.iter().find(|f| f(default.clone()).name() == s) {
+                    memory_op(f, c)?
+                } else if let Some(op) = NULLARY.iter().find(|op| op.name() == s) {
+                    op.clone()
+                } else if let Some(&f) = LANE.iter().find(|f| f(0).name() == s) {
+                    let y = c.next("a lane index")?;
+                    let lane = u8::try_from(uint(y)?)
+                        .map_err(|_| error(y, "lane index out of range".to_string()))?;
+                    f(lane)
+                } else {
+                    return Err(error(x, format!("unknown instruction {}", describe(x))));
+                }
+            }
+        })
+    }
+
+    // Assigns indices to everything named before reading any field, imports first as they
+    // precede definitions in every index space.
+    fn declare_all(&mut self, fields: &[SExpr]) -> Result<()> {
+        for x in fields {
+            let mut c = Cursor::new(x);
+            match head(x) {
+                Some("type") => {
+                    let id = c.id();
+                    self.declare(x, Space::Type, id, false)?;
+                    let f = c.next("`(func ..)`")?;
+                    if head(f) != Some("func") {
+                        return Err(error(
+                            f,
+                            format!("expected `(func ..)`, found {}", describe(f)),
+                        ));
+                    }
+                    let mut f = Cursor::new(f);
+                    let (sig, _, _) = signature(&mut f)?;
+                    f.end()?;
+                    c.end()?;
+                    self.types.push(sig);
+                }
+                Some("import") => {
+                    c.next("a module name")?;
+                    c.next("a field name")?;
+                    let desc = c.next("an import description")?;
+                    let space = match head(desc) {
+                        Some("func") => Space::Func,
+                        Some("table") => Space::Table,
+                        Some("memory") => Space::Memory,
+                        Some("global") => Space::Global,
+                        _ => {
+                            return Err(error(
+                                desc,
+                                format!("expected an import description, found {}", describe(desc)),
+                            ))
+                        }
+                    };
+                    if self.defined[space as usize] != 0 {
+                        return Err(error(
+                            x,
+                            format!("import after {} definition", space.name()),
+                        ));
+                    }
+                    self.declare(x, space, Cursor::new(desc).id(), true)?;
+                }
+                Some("func") => self.declare(x, Space::Func, c.id(), false)?,
+                Some("table") => self.declare(x, Space::Table, c.id(), false)?,
+                Some("memory") => self.declare(x, Space::Memory, c.id(), false)?,
+                Some("global") => self.declare(x, Space::Global, c.id(), false)?,
+                Some("data") => self.declare(x, Space::Data, c.id(), false)?,
+                Some("export") | Some("start") | Some("elem") => {}
+                _ => return Err(error(x, format!("unknown module field {}", describe(x)))),
+            }
+        }
+        Ok(())
+    }
+
+    fn field(&mut self, x: &SExpr) -> Result<()> {
+        let mut c = Cursor::new(x);
+        match head(x) {
+            Some("import") => {
+                let module = name(c.next("a module name")?)?;
+                let field = name(c.next("a field name")?)?;
+                let desc = c.next("an import description")?;
+                let mut d = Cursor::new(desc);
+                d.id();
+                let kind = match head(desc) {
+                    Some("func") => ExternalKindImport::Function(self.type_use(&mut d)?.0),
+                    Some("table") => {
+                        let limits = limits(&mut d)?;
+                        ExternalKindImport::Table(TableType {
+                            element_type: elem_type(&mut d)?,
+                            limits,
+                        })
+                    }
+                    Some("memory") => ExternalKindImport::Memory(MemoryType(limits(&mut d)?)),
+                    _ => ExternalKindImport::Global(global_type(d.next("a global type")?)?),
+                };
+                d.end()?;
+                self.imports.push(ImportEntry {
+                    module,
+                    field,
+                    kind,
+                });
+            }
+            Some("func") => {
+                c.id();
+                let index = self.imported[Space::Func as usize] + self.funcs.len();
+                self.inline_exports(&mut c, ExternalKind::Function, index)?;
+                let (t, names) = self.type_use(&mut c)?;
+                let mut body = Body::default();
+                let declare = |body: &mut Body, id: Option<String>, i: usize| match id {
+                    Some(id) if body.locals.insert(id.clone(), i).is_some() => {
+                        Err(error(x, format!("duplicate local `{}`", id)))
+                    }
+                    _ => Ok(()),
+                };
+                for (i, id) in names.into_iter().enumerate() {
+                    declare(&mut body, id, i)?;
+                }
+                let mut n = self.types[t].params.len();
+                let mut locals: Vec<LocalEntry> = Vec::new();
+                while let Some(y) = c.peek_list("local") {
+                    c.i += 1;
+                    let mut l = Cursor::new(y);
+                    let mut types = Vec::new();
+                    if let Some(id) = l.id() {
+                        declare(&mut body, Some(id.to_string()), n)?;
+                        types.push(value_type(l.next("a value type")?)?);
+                        l.end()?;
+                    }
+                    while let Some(z) = l.peek() {
+                        l.i += 1;
+                        types.push(value_type(z)?);
+                    }
+                    for typ in types {
+                        n += 1;
+                        match locals.last_mut() {
+                            Some(last) if last.typ == typ => last.count += 1,
+                            _ => locals.push(LocalEntry { count: 1, typ }),
+                        }
+                    }
+                }
+                let mut codes = Vec::new();
+                self.instrs(&mut body, &mut c, &mut codes)?;
+                if !body.labels.is_empty() {
+                    return Err(error(x, "unclosed block".to_string()));
+                }
+                self.funcs.push(t);
+                self.codes.push(FunctionBody { locals, codes });
+            }
+            Some("table") => {
+                c.id();
+                let index = self.imported[Space::Table as usize] + self.tables.len();
+                self.inline_exports(&mut c, ExternalKind::Table, index)?;
+                let limits = limits(&mut c)?;
+                self.tables.push(TableType {
+                    element_type: elem_type(&mut c)?,
+                    limits,
+                });
+            }
+            Some("memory") => {
+                c.id();
+                let index = self.imported[Space::Memory as usize] + self.memories.len();
+                self.inline_exports(&mut c, ExternalKind::Memory, index)?;
+                self.memories.push(MemoryType(limits(&mut c)?));
+            }
+            Some("global") => {
+                c.id();
+                let index = self.imported[Space::Global as usize] + self.globals.len();
+                self.inline_exports(&mut c, ExternalKind::Global, index)?;
+                let t = global_type(c.next("a global type")?)?;
+                let init = self.init_expr(c.next("an initializer")?)?;
+                self.globals.push(GlobalVariable(t, init));
+            }
+            Some("export") => {
+                let field = name(c.next("a name")?)?;
+                let desc = c.next("an export description")?;
+                let (kind, space) = match head(desc) {
+                    Some("func") => (ExternalKind::Function, Space::Func),
+                    Some("table") => (ExternalKind::Table, Space::Table),
+                    Some("memory") => (ExternalKind::Memory, Space::Memory),
+                    Some("global") => (ExternalKind::Global, Space::Global),
+                    _ => {
+                        return Err(error(
+                            desc,
+                            format!("expected an export description, found {}", describe(desc)),
+                        ))
+                    }
+                };
+                let mut d = Cursor::new(desc);
+                let index = self.index(d.next("an index")?, space)?;
+                d.end()?;
+                self.exports.push(ExportEntry { field, kind, index });
+            }
+            Some("start") => {
+                let index = self.index(c.next("a function")?, Space::Func)?;
+                if self.start.replace(index).is_some() {
+                    return Err(error(x, "multiple start functions".to_string()));
+                }
+            }
+            Some("elem") => {
+                c.id();
+                if c.peek_list("table").is_some() {
+                    c.i += 1;
+                }
+                let offset = self.offset(&mut c)?;
+                if c.peek_atom() == Some("func") {
+                    c.i += 1;
+                }
+                let mut elems = Vec::new();
+                while let Some(y) = c.peek() {
+                    c.i += 1;
+                    elems.push(self.index(y, Space::Func)?);
+                }
+                self.elems.push(ElemSegment { offset, elems });
+            }
+            Some("data") => {
+                c.id();
+                // Passive segments have nothing but strings.
+                let offset = if c.peek_list("memory").is_some() {
+                    c.i += 1;
+                    Some(self.offset(&mut c)?)
+                } else if c
+                    .peek()
+                    .is_some_and(|y| !matches!(y.kind, SExprKind::Str(_)))
+                {
+                    Some(self.offset(&mut c)?)
+                } else {
+                    None
+                };
+                let mut data = Vec::new();
+                while let Some(y) = c.peek() {
+                    c.i += 1;
+                    match &y.kind {
+                        SExprKind::Str(s) => data.extend_from_slice(s),
+                        _ => {
+                            return Err(error(
+                                y,
+                                format!("expected a string, found {}", describe(y)),
+                            ))
+                        }
+                    }
+                }
+                self.datas.push(DataSegment { offset, data });
+            }
+            // Types are read by `declare_all`.
+            _ => return Ok(()),
+        }
+        c.end()
+    }
+
+    fn finish(self) -> WasmASTRoot {
+        fn section<T, S>(xs: Vec<T>, f: fn(Vec<T>) -> S) -> Option<S> {
+            if xs.is_empty() {
+                None
+            } else {
+                Some(f(xs))
+            }
+        }
+        WasmASTRoot {
+            type_section: section(self.types, TypeSection),
+            import_section: section(self.imports, ImportSection),
+            function_section: section(self.funcs, FunctionSection),
+            table_section: section(self.tables, TableSection),
+            memory_section: section(self.memories, MemorySection),
+            global_section: section(self.globals, GlobalSection),
+            export_section: section(self.exports, ExportSection),
+            start_section: self.start.map(StartSection),
+            element_section: section(self.elems, ElementSection),
+            code_section: section(self.codes, CodeSection),
+            data_section: section(self.datas, DataSection),
+            name_section: None,
+            source_mapping_url: None,
+            custom_sections: Vec::new(),
+        }
+    }
+}
+
+pub fn parse_module(src: &str) -> Result<WasmASTRoot> {
+    let mut st = Stream::new(src.chars().collect());
+    let x = skip()
+        .with(sexpr())
+        .skip(skip())
+        .skip(eof())
+        .parse(&mut st)?;
+    if head(&x) != Some("module") {
+        return Err(error(
+            &x,
+            format!("expected `(module ..)`, found {}", describe(&x)),
+        ));
+    }
+    let mut c = Cursor::new(&x);
+    c.id();
+    let fields = &c.xs[c.i..];
+    let mut module = Module::default();
+    module.declare_all(fields)?;
+    for x in fields {
+        module.field(x)?;
+    }
+    Ok(module.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wat::print_module;
+
+    #[test]
+    fn parse_module_test() {
+        let src = r#"
+(module
+  ;; Hand-written, with names and folded instructions.
+  (import "env" "log" (func $log (param i32)))
+  (memory (export "memory") 1 2)
+  (global $count (mut i32) (i32.const 0))
+  (func $inc (export "inc") (param $by i32) (result i32)
+    (local $old i32) (local f64 f64)
+    (local.set $old (global.get $count))
+    (block $done
+      (br_if $done (i32.eqz (local.get $by)))
+      (global.set $count (i32.add (local.get $old) (local.get $by))))
+    (if (result i32) (i32.gt_u (global.get $count) (i32.const 0xffff_ffff))
+      (then (call $log (global.get $count)) (i32.const -1))
+      (else (i64.store offset=8 align=4 (i32.const 0) (i64.const 1)) (global.get $count))))
+  (; unused ;)
+  (data (i32.const 4) "hi" "\00\u{3042}"))
+"#;
+        let root = parse_module(src).unwrap();
+        assert_eq!(
+            root.type_section,
+            Some(TypeSection(vec![
+                FuncType {
+                    params: vec![ValueType::I32],
+                    results: vec![],
+                },
+                FuncType {
+                    params: vec![ValueType::I32],
+                    results: vec![ValueType::I32],
+                },
+            ]))
+        );
+        assert_eq!(root.function_section, Some(FunctionSection(vec![1])));
+        assert_eq!(
+            root.export_section,
+            Some(ExportSection(vec![
+                ExportEntry {
+                    field: "memory".to_string(),
+                    kind: ExternalKind::Memory,
+                    index: 0,
+                },
+                ExportEntry {
+                    field: "inc".to_string(),
+                    kind: ExternalKind::Function,
+                    index: 1,
+                },
+            ]))
+        );
+        let body = &root.code_section.unwrap().0[0];
+        assert_eq!(
+            body.locals,
+            vec![
+                LocalEntry {
+                    count: 1,
+                    typ: ValueType::I32,
+                },
+                LocalEntry {
+                    count: 2,
+                    typ: ValueType::F64,
+                },
+            ]
+        );
+        assert_eq!(
+            body.codes,
+            vec![
+                OperatorCode::GetGlobal(0),
+                OperatorCode::SetLocal(1),
+                OperatorCode::Block(BlockType::Empty),
+                OperatorCode::GetLocal(0),
+                OperatorCode::I32Eqz,
+                OperatorCode::BrIf(0),
+                OperatorCode::GetLocal(1),
+                OperatorCode::GetLocal(0),
+                OperatorCode::I32Add,
+                OperatorCode::SetGlobal(0),
+                OperatorCode::End,
+                OperatorCode::GetGlobal(0),
+                OperatorCode::I32Const(-1),
+                OperatorCode::I32Gtu,
+                OperatorCode::If(BlockType::Value(ValueType::I32)),
+                OperatorCode::GetGlobal(0),
+                OperatorCode::Call(0),
+                OperatorCode::I32Const(-1),
+                OperatorCode::Else,
+                OperatorCode::I32Const(0),
+                OperatorCode::I64Const(1),
+                OperatorCode::I64Store(MemoryImmediate {
+                    flags: 2,
+                    offset: 8,
+                }),
+                OperatorCode::GetGlobal(0),
+                OperatorCode::End,
+            ]
+        );
+        assert_eq!(
+            root.data_section.unwrap().0[0].data,
+            b"hi\0\xe3\x81\x82".to_vec()
+        );
+
+        // What `print_module` writes reads back to the same module.
+        let wat = print_module(&parse_module(src).unwrap());
+        assert_eq!(print_module(&parse_module(&wat).unwrap()), wat);
+
+        let flat = r#"(module
+  (type (;0;) (func (param i32 f64) (result i32)))
+  (func (;0;) (type 0) (param i32 f64) (result i32)
+    (local i32 i32 f32)
+    block (result i32)
+      local.get 0
+      if
+        f32.const -nan
+        local.set 4
+        f64.const nan:0x1
+        f64.const 5e-324
+        f64.ne
+        local.set 2
+      end
+      local.get 0
+      i32.load offset=4 align=1
+      br_table 0 1 0
+    end)
+  (table (;0;) 1 funcref)
+  (elem (;0;) (i32.const 0) func 0 0))
+"#;
+        assert_eq!(print_module(&parse_module(flat).unwrap()), flat);
+
+        let err = parse_module("(module (func i32.bogus))").unwrap_err();
+        assert_eq!(err.span(), (14, 9));
+        assert_eq!(err.message(), Some("unknown instruction `i32.bogus`"));
+        let err = parse_module("(module (func br $nowhere))").unwrap_err();
+        assert_eq!(err.message(), Some("unknown label `$nowhere`"));
+    }
+}